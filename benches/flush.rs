@@ -0,0 +1,53 @@
+//! Serialization cost of the flush path.
+//!
+//! Compare the default serializer with the hand-rolled one:
+//! `cargo bench` and `cargo bench --features fast-serialize`
+use criterion::{criterion_group, criterion_main, Criterion};
+use lambda_helpers_metrics::sink::NullSink;
+use lambda_helpers_metrics::{MetricUnit, Metrics, MetricsBuilder};
+
+fn build_metrics() -> Metrics {
+    MetricsBuilder::new("bench")
+        .dimension("service", "dummy_service")
+        .dimension("stage", "prod")
+        .sink(NullSink)
+        .build()
+        .unwrap()
+}
+
+fn flush(c: &mut Criterion) {
+    let names: Vec<String> = (0..100).map(|i| format!("metric_{i}")).collect();
+
+    let mut metrics = build_metrics();
+    for (i, name) in (0u32..).zip(&names) {
+        metrics.add_metric(name, MetricUnit::Milliseconds, f64::from(i) * 1.5);
+    }
+    c.bench_function("serialize 100 metrics", |b| {
+        b.iter(|| metrics.to_json_bytes().unwrap());
+    });
+    metrics.set_dry_run(true);
+    drop(metrics);
+
+    let mut metrics = build_metrics();
+    c.bench_function("flush 100 metrics", |b| {
+        b.iter(|| {
+            for (i, name) in (0u32..).zip(&names) {
+                metrics.add_metric(name, MetricUnit::Milliseconds, f64::from(i) * 1.5);
+            }
+            metrics.flush_metrics();
+        });
+    });
+
+    let mut metrics = build_metrics();
+    c.bench_function("flush 100 samples", |b| {
+        b.iter(|| {
+            for i in 0..100u32 {
+                metrics.add_sample("latency", MetricUnit::Milliseconds, f64::from(i) * 1.5);
+            }
+            metrics.flush_metrics();
+        });
+    });
+}
+
+criterion_group!(benches, flush);
+criterion_main!(benches);
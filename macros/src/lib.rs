@@ -0,0 +1,130 @@
+//! Attribute macros of `lambda_helpers_metrics`, re-exported by its `macros` feature.
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, parse_quote, Error, FnArg, ItemFn, Pat};
+
+/// Wraps an async Lambda handler taking a `LambdaEvent` as its first argument: the metrics
+/// of the invocation are created with `Metrics::for_invocation`, set as the current context
+/// of the handler's future, and flushed with the standard invocation metrics once it returns.
+/// See `lambda_helpers_metrics::handler::instrument`.
+#[proc_macro_attribute]
+pub fn lambda_metrics(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        let attr = proc_macro2::TokenStream::from(attr);
+        return Error::new(attr.span(), "#[lambda_metrics] takes no arguments")
+            .to_compile_error()
+            .into();
+    }
+    let mut function = parse_macro_input!(item as ItemFn);
+    match expand(&mut function) {
+        Ok(()) => quote!(#function).into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn expand(function: &mut ItemFn) -> Result<(), Error> {
+    if function.sig.asyncness.is_none() {
+        return Err(Error::new(
+            function.sig.fn_token.span(),
+            "#[lambda_metrics] requires an async handler",
+        ));
+    }
+    let event = match function.sig.inputs.first() {
+        Some(FnArg::Typed(argument)) => match &*argument.pat {
+            Pat::Ident(pattern) => pattern.ident.clone(),
+            pattern => {
+                return Err(Error::new(
+                    pattern.span(),
+                    "#[lambda_metrics] requires the event argument to be a plain identifier",
+                ))
+            }
+        },
+        _ => {
+            return Err(Error::new(
+                function.sig.inputs.span(),
+                "#[lambda_metrics] requires a `LambdaEvent` as the first argument",
+            ))
+        }
+    };
+    let body = &function.block;
+    function.block = parse_quote!({
+        let __invocation = #event.context.clone();
+        let __metrics = ::lambda_helpers_metrics::Metrics::for_invocation(&#event);
+        ::lambda_helpers_metrics::handler::instrument(&__invocation, __metrics, async move #body)
+            .await
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use quote::ToTokens;
+
+    use super::*;
+
+    fn expand_error(mut function: ItemFn) -> String {
+        expand(&mut function).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn should_wrap_body_with_instrument() {
+        let mut function: ItemFn = parse_quote! {
+            async fn handler(event: LambdaEvent<Value>) -> Result<Value, Error> {
+                Ok(event.payload)
+            }
+        };
+
+        expand(&mut function).unwrap();
+
+        let body = function.block.to_token_stream().to_string();
+        assert!(body.contains("Metrics :: for_invocation (& event)"));
+        assert!(body.contains("handler :: instrument (& __invocation , __metrics , async move"));
+        assert!(body.contains("Ok (event . payload)"));
+        assert_eq!(function.sig.ident, "handler");
+    }
+
+    #[test]
+    fn should_reject_sync_handler() {
+        let function = parse_quote! {
+            fn handler(event: LambdaEvent<Value>) -> Result<Value, Error> {
+                Ok(event.payload)
+            }
+        };
+
+        assert_eq!(
+            expand_error(function),
+            "#[lambda_metrics] requires an async handler"
+        );
+    }
+
+    #[test]
+    fn should_reject_handler_without_plain_event_argument() {
+        let destructured = parse_quote! {
+            async fn handler(LambdaEvent { payload, .. }: LambdaEvent<Value>) -> Result<Value, Error> {
+                Ok(payload)
+            }
+        };
+        let receiver = parse_quote! {
+            async fn handler(&self) -> Result<(), Error> {
+                Ok(())
+            }
+        };
+        let no_arguments = parse_quote! {
+            async fn handler() -> Result<(), Error> {
+                Ok(())
+            }
+        };
+
+        assert_eq!(
+            expand_error(destructured),
+            "#[lambda_metrics] requires the event argument to be a plain identifier"
+        );
+        for function in [receiver, no_arguments] {
+            assert_eq!(
+                expand_error(function),
+                "#[lambda_metrics] requires a `LambdaEvent` as the first argument"
+            );
+        }
+    }
+}
@@ -0,0 +1,717 @@
+//! Companion aggregator, emitting EMF out of the invocation's critical path.
+//!
+//! An [`Aggregator`] listens on a local TCP socket, either on a thread of the function itself
+//! (an internal extension, [`Aggregator::spawn`]) or in an external extension binary
+//! ([`Aggregator::run`]). `Metrics` objects send it the values of each flush as compact deltas
+//! with an [`AggregatorSink`], which is a fast local write. The aggregator merges the values of
+//! the same metric and dimensions into samples, and emits the batches to its own sink:
+//!
+//! ```no_run
+//! use std::time::Duration;
+//!
+//! use lambda_helpers_metrics::aggregator::Aggregator;
+//! use lambda_helpers_metrics::{MetricUnit, Metrics};
+//!
+//! let aggregator = Aggregator::bind("127.0.0.1:25899")
+//!     .unwrap()
+//!     .interval(Duration::from_secs(5))
+//!     .spawn()
+//!     .unwrap();
+//!
+//! let mut metrics = Metrics::builder("custom_lambdas")
+//!     .sink(aggregator.client_sink())
+//!     .build()
+//!     .unwrap();
+//! metrics.add_metric("orders", MetricUnit::Count, 1.0);
+//! metrics.flush_metrics();
+//! ```
+//!
+//! In Lambda (`AWS_LAMBDA_RUNTIME_API` is set), the aggregator registers with the Extensions
+//! API and emits on the `INVOKE` events, at most once per interval, so nothing waits on a timer
+//! while the execution environment is frozen. An external extension also registers for
+//! `SHUTDOWN` and emits what is left before it exits. Internal extensions can't receive
+//! `SHUTDOWN`: call [`AggregatorHandle::flush`] before the environment shuts down, e.g. from
+//! [`crate::shutdown`]. Outside Lambda, the aggregator emits on a timer instead.
+//!
+//! Properties are per invocation and are not aggregated. The aggregated payloads are emitted
+//! with the timestamp of their flush. At most [`DEFAULT_MAX_CONNECTIONS`] clients are served
+//! at once, see [`Aggregator::max_connections`].
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::sink::{AgentSink, MetricsSink, StdoutSink};
+use crate::{emf, mode, platform, Dimensions, MetricUnit, Metrics, MetricsError};
+
+/// The shortest time between two emissions of an [`Aggregator`] by default, one second,
+/// see [`Aggregator::interval`].
+pub const DEFAULT_AGGREGATION_INTERVAL: Duration = Duration::from_secs(1);
+/// The default number of clients served at once.
+pub const DEFAULT_MAX_CONNECTIONS: usize = 16;
+/// The name an internal extension registers with, see [`Aggregator::extension_name`].
+pub const DEFAULT_EXTENSION_NAME: &str = "lambda-helpers-metrics-aggregator";
+const RUNTIME_API_ENV: &str = "AWS_LAMBDA_RUNTIME_API";
+
+/// The values of a metric sent by an [`AggregatorSink`].
+#[derive(Debug, Serialize, Deserialize)]
+struct Delta {
+    #[serde(rename = "n")]
+    namespace: String,
+    #[serde(rename = "d")]
+    dimensions: Vec<(String, String)>,
+    #[serde(rename = "m")]
+    name: String,
+    #[serde(rename = "u")]
+    unit: MetricUnit,
+    #[serde(rename = "v")]
+    values: Vec<f64>,
+}
+
+/// Aggregated metrics per namespace, shared by the connection and flush threads.
+#[derive(Debug)]
+struct State {
+    namespaces: HashMap<String, Metrics>,
+    flushed_at: Instant,
+}
+
+/// A listener aggregating the payloads of `Metrics` objects, see [`crate::aggregator`].
+#[derive(Debug)]
+pub struct Aggregator {
+    listener: TcpListener,
+    sink: Arc<dyn MetricsSink>,
+    interval: Duration,
+    max_connections: usize,
+    runtime_api: Option<String>,
+    extension_name: Option<String>,
+}
+
+impl Aggregator {
+    /// Listens on the given address, e.g. `127.0.0.1:25899`, or port 0 for any free port.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the address can't be bound
+    pub fn bind(address: impl ToSocketAddrs) -> Result<Self, MetricsError> {
+        Ok(Self {
+            listener: TcpListener::bind(address)?,
+            sink: Arc::new(StdoutSink),
+            interval: DEFAULT_AGGREGATION_INTERVAL,
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            runtime_api: std::env::var(RUNTIME_API_ENV).ok(),
+            extension_name: None,
+        })
+    }
+
+    /// Sets the sink the aggregated payloads are written to, stdout by default.
+    #[must_use]
+    pub fn sink(mut self, sink: impl MetricsSink + 'static) -> Self {
+        self.sink = Arc::new(sink);
+        self
+    }
+
+    /// Sets how often the aggregated payloads are emitted at most, every second by default.
+    #[must_use]
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Sets how many clients are served at once, [`DEFAULT_MAX_CONNECTIONS`] by default.
+    /// Further connections are closed and reported.
+    #[must_use]
+    pub fn max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = max_connections.max(1);
+        self
+    }
+
+    /// Sets the name registered with the Extensions API. An external extension must be
+    /// registered with the name of its file in `/opt/extensions`, which is the default of
+    /// [`Aggregator::run`]; [`Aggregator::spawn`] uses [`DEFAULT_EXTENSION_NAME`].
+    #[must_use]
+    pub fn extension_name(mut self, name: &str) -> Self {
+        self.extension_name = Some(name.to_string());
+        self
+    }
+
+    /// Returns the address the aggregator listens on.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the address of the listener can't be read
+    pub fn local_addr(&self) -> Result<SocketAddr, MetricsError> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Starts listening and emitting on background threads, for the lifetime of the process.
+    /// In Lambda, registers as an internal extension, which must happen before
+    /// `lambda_runtime::run`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the address of the listener can't be read, if the extension
+    /// can't be registered, or if the threads can't be spawned, e.g. on WASI
+    pub fn spawn(self) -> Result<AggregatorHandle, MetricsError> {
+        let extension = match &self.runtime_api {
+            Some(api) => {
+                let name = self
+                    .extension_name
+                    .as_deref()
+                    .unwrap_or(DEFAULT_EXTENSION_NAME);
+                Some(Extension::register(api, name, &["INVOKE"])?)
+            }
+            None => None,
+        };
+        let (handle, listener) = self.start()?;
+        let accepting = handle.clone();
+        platform::spawn("metrics-aggregator", move || accepting.accept(&listener))?;
+        let flushing = handle.clone();
+        match extension {
+            Some(extension) => platform::spawn("metrics-aggregator-flush", move || {
+                if let Err(err) = flushing.serve(&extension) {
+                    mode::report(&err);
+                }
+            })?,
+            None => platform::spawn("metrics-aggregator-flush", move || {
+                flushing.flush_on_timer();
+            })?,
+        }
+        Ok(handle)
+    }
+
+    /// Listens and emits on the current thread, e.g. in an external extension binary.
+    /// In Lambda, registers as an external extension and returns once the `SHUTDOWN` event
+    /// is received and the aggregated payloads are emitted. Outside Lambda, only returns if
+    /// the listener fails.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the address of the listener can't be read, if the Extensions
+    /// API fails, or if the threads can't be spawned, e.g. on WASI
+    pub fn run(self) -> Result<(), MetricsError> {
+        let Some(api) = self.runtime_api.clone() else {
+            let (handle, listener) = self.start()?;
+            let flushing = handle.clone();
+            platform::spawn("metrics-aggregator-flush", move || {
+                flushing.flush_on_timer()
+            })?;
+            handle.accept(&listener);
+            return Ok(());
+        };
+        let name = self.extension_name.clone().unwrap_or_else(executable_name);
+        let extension = Extension::register(&api, &name, &["INVOKE", "SHUTDOWN"])?;
+        let (handle, listener) = self.start()?;
+        let accepting = handle.clone();
+        platform::spawn("metrics-aggregator", move || accepting.accept(&listener))?;
+        handle.serve(&extension)
+    }
+
+    fn start(self) -> Result<(AggregatorHandle, TcpListener), MetricsError> {
+        let handle = AggregatorHandle {
+            address: self.listener.local_addr()?,
+            sink: self.sink,
+            interval: self.interval,
+            max_connections: self.max_connections,
+            state: Arc::new(Mutex::new(State {
+                namespaces: HashMap::new(),
+                flushed_at: Instant::now(),
+            })),
+            received: Arc::default(),
+            connections: Arc::default(),
+        };
+        Ok((handle, self.listener))
+    }
+}
+
+/// The file name of the current executable, the name of an external extension.
+fn executable_name() -> String {
+    std::env::current_exe()
+        .ok()
+        .and_then(|path| Some(path.file_name()?.to_string_lossy().into_owned()))
+        .unwrap_or_else(|| DEFAULT_EXTENSION_NAME.to_string())
+}
+
+/// Handle of a running [`Aggregator`]. Clones share the aggregated state.
+#[derive(Debug, Clone)]
+pub struct AggregatorHandle {
+    address: SocketAddr,
+    sink: Arc<dyn MetricsSink>,
+    interval: Duration,
+    max_connections: usize,
+    state: Arc<Mutex<State>>,
+    received: Arc<AtomicU64>,
+    connections: Arc<AtomicUsize>,
+}
+
+impl AggregatorHandle {
+    /// Returns the address the aggregator listens on.
+    #[must_use]
+    pub fn local_addr(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Returns a sink sending the values of each payload to the aggregator.
+    #[must_use]
+    pub fn client_sink(&self) -> AggregatorSink {
+        AggregatorSink::new(self.address)
+    }
+
+    /// Returns the number of payloads received so far.
+    #[must_use]
+    pub fn payloads_received(&self) -> u64 {
+        self.received.load(Ordering::Relaxed)
+    }
+
+    /// Emits the aggregated payloads now.
+    pub fn flush(&self) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.flushed_at = Instant::now();
+        for metrics in state.namespaces.values_mut() {
+            metrics.flush_metrics();
+        }
+    }
+
+    /// Emits the aggregated payloads if the interval elapsed since the last flush.
+    fn flush_if_due(&self) {
+        let flushed_at = self
+            .state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .flushed_at;
+        if flushed_at.elapsed() >= self.interval {
+            self.flush();
+        }
+    }
+
+    fn flush_on_timer(&self) {
+        loop {
+            std::thread::sleep(self.interval);
+            self.flush();
+        }
+    }
+
+    /// Emits on the events of the Extensions API until `SHUTDOWN`.
+    fn serve(&self, extension: &Extension) -> Result<(), MetricsError> {
+        loop {
+            if extension.next_event()? == "SHUTDOWN" {
+                self.flush();
+                return Ok(());
+            }
+            self.flush_if_due();
+        }
+    }
+
+    fn accept(&self, listener: &TcpListener) {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    mode::report(&err.into());
+                    continue;
+                }
+            };
+            let Some(connection) = self.open_connection() else {
+                mode::report(&MetricsError::Io(std::io::Error::other(format!(
+                    "closing metrics connection, {} clients are served already",
+                    self.max_connections
+                ))));
+                continue;
+            };
+            let handle = self.clone();
+            let spawned = platform::spawn("metrics-aggregator-connection", move || {
+                handle.read(stream);
+                drop(connection);
+            });
+            if let Err(err) = spawned {
+                mode::report(&err.into());
+            }
+        }
+    }
+
+    /// Counts a new connection, unless the limit is reached.
+    fn open_connection(&self) -> Option<Connection> {
+        self.connections
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+                (count < self.max_connections).then_some(count + 1)
+            })
+            .ok()
+            .map(|_| Connection(Arc::clone(&self.connections)))
+    }
+
+    fn read(&self, stream: TcpStream) {
+        for line in BufReader::new(stream).lines() {
+            let result = line
+                .map_err(MetricsError::from)
+                .and_then(|line| self.ingest(&line));
+            if let Err(err) = result {
+                mode::report(&err);
+            }
+        }
+    }
+
+    /// Merges the deltas of a single line into the aggregated state. Lines holding an EMF
+    /// payload, e.g. from an `AgentSink`, are accepted too.
+    fn ingest(&self, line: &str) -> Result<(), MetricsError> {
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(());
+        }
+        let deltas = if line.starts_with('[') {
+            serde_json::from_str::<Vec<Delta>>(line)
+                .map_err(|err| MetricsError::Serialization(err.to_string()))?
+        } else {
+            deltas(line)?
+        };
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        for delta in deltas {
+            let metrics = match state.namespaces.entry(delta.namespace) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    let metrics = self.namespace_metrics(entry.key())?;
+                    entry.insert(metrics)
+                }
+            };
+            let mut dimensions = Dimensions::default();
+            for (key, value) in &delta.dimensions {
+                dimensions.insert(key, value);
+            }
+            for value in delta.values {
+                metrics.add_sample_with_dimensions(
+                    &delta.name,
+                    delta.unit,
+                    value,
+                    dimensions.clone(),
+                );
+            }
+        }
+        self.received.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// The metrics of a namespace, re-emitting the values only: the environment, the process
+    /// defaults and the runtime information are already in the dimensions of the deltas.
+    fn namespace_metrics(&self, namespace: &str) -> Result<Metrics, MetricsError> {
+        Metrics::builder(namespace)
+            .shared_sink(Arc::clone(&self.sink))
+            .initialization_type(crate::runtime_info::AttachAs::Off)
+            .without_process_defaults()
+            .build()
+    }
+}
+
+/// Releases a connection slot when the client is served.
+#[derive(Debug)]
+struct Connection(Arc<AtomicUsize>);
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// The deltas of the metrics of an EMF payload.
+fn deltas(payload: &str) -> Result<Vec<Delta>, MetricsError> {
+    Ok(emf::parse(payload)?
+        .into_iter()
+        .map(|metric| Delta {
+            namespace: metric.namespace,
+            dimensions: metric
+                .dimensions
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            name: metric.name,
+            unit: metric.unit,
+            values: metric.values,
+        })
+        .collect())
+}
+
+/// Sends the values of each payload to an [`Aggregator`], as a single line of compact deltas:
+/// the namespace, dimensions, name, unit and values of each metric, without the properties
+/// and the metadata of the payload. Created by [`AggregatorHandle::client_sink`], or with
+/// [`AggregatorSink::new`] in the function when the aggregator is an external extension.
+#[derive(Debug)]
+pub struct AggregatorSink {
+    connection: AgentSink,
+}
+
+impl AggregatorSink {
+    /// Creates a sink for the aggregator listening on `address`.
+    #[allow(clippy::missing_panics_doc)]
+    #[must_use]
+    pub fn new(address: SocketAddr) -> Self {
+        Self {
+            // UNWRAP: the endpoint always has the tcp:// scheme
+            connection: AgentSink::new(&format!("tcp://{address}")).unwrap(),
+        }
+    }
+}
+
+impl MetricsSink for AggregatorSink {
+    fn emit(&self, payload: &str) -> Result<(), MetricsError> {
+        let line = serde_json::to_string(&deltas(payload)?)
+            .map_err(|err| MetricsError::Serialization(err.to_string()))?;
+        self.connection.emit(&line)
+    }
+}
+
+/// A registration with the Lambda Extensions API.
+#[derive(Debug)]
+struct Extension {
+    api: String,
+    id: String,
+}
+
+impl Extension {
+    /// Registers the extension for the given events.
+    fn register(api: &str, name: &str, events: &[&str]) -> Result<Self, MetricsError> {
+        let body = serde_json::json!({ "events": events }).to_string();
+        let (headers, _) = runtime_api_request(
+            api,
+            "POST",
+            "/2020-01-01/extension/register",
+            &[("Lambda-Extension-Name", name)],
+            &body,
+        )?;
+        let id = headers
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(key, _)| {
+                key.trim()
+                    .eq_ignore_ascii_case("Lambda-Extension-Identifier")
+            })
+            .map(|(_, value)| value.trim().to_string())
+            .ok_or_else(|| {
+                MetricsError::Configuration("extension registered without an identifier".into())
+            })?;
+        Ok(Self {
+            api: api.to_string(),
+            id,
+        })
+    }
+
+    /// Waits for the next event, returning its type, e.g. `INVOKE` or `SHUTDOWN`.
+    fn next_event(&self) -> Result<String, MetricsError> {
+        let (_, body) = runtime_api_request(
+            &self.api,
+            "GET",
+            "/2020-01-01/extension/event/next",
+            &[("Lambda-Extension-Identifier", &self.id)],
+            "",
+        )?;
+        let event: serde_json::Value = serde_json::from_str(&body)
+            .map_err(|err| MetricsError::Serialization(err.to_string()))?;
+        Ok(event["eventType"].as_str().unwrap_or_default().to_string())
+    }
+}
+
+/// Sends a request to the Extensions API, returning the headers and the body of a successful
+/// response. `next` blocks until the next event, so there is no read timeout.
+fn runtime_api_request(
+    api: &str,
+    method: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: &str,
+) -> Result<(String, String), MetricsError> {
+    let mut stream = TcpStream::connect(api)?;
+    // HTTP/1.0 keeps the response free of chunked encoding
+    let mut request = format!("{method} {path} HTTP/1.0\r\nHost: {api}\r\n");
+    for (key, value) in headers {
+        request.push_str(&format!("{key}: {value}\r\n"));
+    }
+    request.push_str(&format!("Content-Length: {}\r\n\r\n{body}", body.len()));
+    stream.write_all(request.as_bytes())?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| MetricsError::Configuration("malformed Extensions API response".into()))?;
+    let status = head.split_whitespace().nth(1).unwrap_or_default();
+    if status != "200" {
+        return Err(MetricsError::Configuration(format!(
+            "Extensions API returned status {status} for {path}"
+        )));
+    }
+    Ok((head.to_string(), body.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::{self, Receiver};
+    use std::thread::JoinHandle;
+
+    use super::*;
+    use crate::sink::RecordingSink;
+
+    fn wait_for_payloads(aggregator: &AggregatorHandle, count: u64) {
+        let started = Instant::now();
+        while aggregator.payloads_received() < count && started.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn should_aggregate_payloads_of_metrics_objects() {
+        let sink = RecordingSink::default();
+        let aggregator = Aggregator::bind("127.0.0.1:0")
+            .unwrap()
+            .sink(sink.clone())
+            .interval(Duration::from_secs(3600))
+            .spawn()
+            .unwrap();
+
+        let mut metrics = Metrics::builder("test")
+            .dimension("service", "orders")
+            .sink(aggregator.client_sink())
+            .build()
+            .unwrap();
+        for value in [1.0, 2.0] {
+            metrics.add_metric("orders", MetricUnit::Count, value);
+            metrics.add_property("request_id", "req-1");
+            metrics.flush_metrics();
+        }
+        wait_for_payloads(&aggregator, 2);
+        aggregator.flush();
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads.len(), 1);
+        assert_eq!(payloads[0]["service"], "orders");
+        assert_eq!(payloads[0]["orders"], serde_json::json!([1.0, 2.0]));
+        assert_eq!(
+            payloads[0]["_aws"]["CloudWatchMetrics"][0]["Namespace"],
+            "test"
+        );
+        assert!(payloads[0].get("request_id").is_none());
+        assert!(payloads[0].get("InitializationType").is_none());
+    }
+
+    #[test]
+    fn should_send_values_without_properties_as_deltas() {
+        let line = serde_json::to_string(
+            &deltas(
+                r#"{"_aws":{"Timestamp":1,"CloudWatchMetrics":[{"Namespace":"test","Dimensions":[["service"]],"Metrics":[{"Name":"orders","Unit":"Count"}]}]},"service":"orders","request_id":"req-1","orders":[1.0,2.0]}"#,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            line,
+            r#"[{"n":"test","d":[["service","orders"]],"m":"orders","u":"Count","v":[1.0,2.0]}]"#
+        );
+    }
+
+    #[test]
+    fn should_close_connections_over_the_limit() {
+        let aggregator = Aggregator::bind("127.0.0.1:0")
+            .unwrap()
+            .sink(RecordingSink::default())
+            .interval(Duration::from_secs(3600))
+            .max_connections(1)
+            .spawn()
+            .unwrap();
+
+        let _served = TcpStream::connect(aggregator.local_addr()).unwrap();
+        let started = Instant::now();
+        while aggregator.connections.load(Ordering::Acquire) < 1
+            && started.elapsed() < Duration::from_secs(5)
+        {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let mut rejected = TcpStream::connect(aggregator.local_addr()).unwrap();
+        rejected
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        assert_eq!(rejected.read(&mut [0; 1]).unwrap(), 0);
+        assert_eq!(aggregator.connections.load(Ordering::Acquire), 1);
+    }
+
+    /// Serves the Extensions API, answering each `next` with an event from `events`
+    /// until `SHUTDOWN`. Returns the received requests.
+    fn fake_runtime_api(events: Receiver<&'static str>) -> (String, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let serving = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = String::new();
+                let mut reader = BufReader::new(&stream);
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if let Some(value) = line.strip_prefix("Content-Length: ") {
+                        length = value.trim().parse().unwrap();
+                    }
+                    request.push_str(&line);
+                    if line == "\r\n" {
+                        break;
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                request.push_str(&String::from_utf8(body).unwrap());
+                let (headers, body) = if request.contains("/register") {
+                    ("Lambda-Extension-Identifier: ext-1\r\n", "{}".to_string())
+                } else {
+                    let event = events.recv().unwrap();
+                    ("", format!(r#"{{"eventType":"{event}"}}"#))
+                };
+                requests.push(request);
+                let response = format!("HTTP/1.0 200 OK\r\n{headers}\r\n{body}");
+                stream.write_all(response.as_bytes()).unwrap();
+                if body.contains("SHUTDOWN") {
+                    return requests;
+                }
+            }
+            requests
+        });
+        (address, serving)
+    }
+
+    #[test]
+    fn should_flush_on_shutdown_event() {
+        let (events, receiver) = mpsc::channel();
+        let (api, serving) = fake_runtime_api(receiver);
+        let sink = RecordingSink::default();
+        let aggregator = Aggregator::bind("127.0.0.1:0")
+            .unwrap()
+            .sink(sink.clone())
+            .interval(Duration::from_secs(3600));
+
+        let extension = Extension::register(&api, "aggregator", &["INVOKE", "SHUTDOWN"]).unwrap();
+        let (handle, listener) = aggregator.start().unwrap();
+        let accepting = handle.clone();
+        std::thread::spawn(move || accepting.accept(&listener));
+        let mut metrics = Metrics::builder("test")
+            .sink(handle.client_sink())
+            .build()
+            .unwrap();
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+        metrics.flush_metrics();
+        wait_for_payloads(&handle, 1);
+
+        events.send("INVOKE").unwrap();
+        events.send("SHUTDOWN").unwrap();
+        handle.serve(&extension).unwrap();
+        let requests = serving.join().unwrap();
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads.len(), 1);
+        assert_eq!(payloads[0]["orders"], 1.0);
+        assert!(requests[0].starts_with("POST /2020-01-01/extension/register"));
+        assert!(requests[0].contains("Lambda-Extension-Name: aggregator"));
+        assert!(requests[0].ends_with(r#"{"events":["INVOKE","SHUTDOWN"]}"#));
+        assert_eq!(requests.len(), 3);
+        assert!(requests[2].contains("Lambda-Extension-Identifier: ext-1"));
+    }
+}
@@ -0,0 +1,229 @@
+//! Heap allocations counted by a wrapping global allocator, recorded at flush.
+//!
+//! Available with the `alloc-metrics` feature. The binary installs [`CountingAllocator`] as
+//! its global allocator, and `Metrics` objects built with
+//! `MetricsBuilder::allocation_stats(true)` record at each flush:
+//!
+//! - `heap_allocations`: the number of allocations since the previous flush, since the start
+//!   of the process at the first one;
+//! - `heap_allocated_bytes`: the bytes allocated since the previous flush;
+//! - `heap_in_use_bytes`: the bytes allocated and not freed yet.
+//!
+//! ```ignore
+//! use lambda_helpers_metrics::alloc_stats::CountingAllocator;
+//!
+//! #[global_allocator]
+//! static ALLOCATOR: CountingAllocator = CountingAllocator::system();
+//!
+//! let metrics = Metrics::builder("custom_lambdas")
+//!     .allocation_stats(true)
+//!     .build()?;
+//! ```
+//!
+//! The counts are the ones of the whole process, so with a flush at the end of each invocation
+//! they are the allocations of the invocation. Nothing is recorded when the wrapper isn't
+//! installed. Counting costs two relaxed atomic additions per allocation.
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::catalog::codegen::CatalogMetric;
+use crate::{MetricUnit, Metrics};
+
+/// Allocations since the previous flush.
+pub const ALLOCATIONS_METRIC: &str = "heap_allocations";
+/// Bytes allocated since the previous flush.
+pub const ALLOCATED_BYTES_METRIC: &str = "heap_allocated_bytes";
+/// Bytes allocated and not freed yet.
+pub const IN_USE_BYTES_METRIC: &str = "heap_in_use_bytes";
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static ALLOCATED_BYTES: AtomicU64 = AtomicU64::new(0);
+static FREED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// A global allocator counting the allocations of another one, see [`crate::alloc_stats`].
+#[derive(Debug, Default)]
+pub struct CountingAllocator<A = System> {
+    inner: A,
+}
+
+impl CountingAllocator<System> {
+    /// Counts the allocations of the system allocator.
+    #[must_use]
+    pub const fn system() -> Self {
+        Self { inner: System }
+    }
+}
+
+impl<A> CountingAllocator<A> {
+    /// Counts the allocations of `inner`, e.g. another allocator crate.
+    #[must_use]
+    pub const fn new(inner: A) -> Self {
+        Self { inner }
+    }
+}
+
+fn count_allocation(size: usize) {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    ALLOCATED_BYTES.fetch_add(size as u64, Ordering::Relaxed);
+}
+
+fn count_free(size: usize) {
+    FREED_BYTES.fetch_add(size as u64, Ordering::Relaxed);
+}
+
+// SAFETY: every call is forwarded to the inner allocator with the same arguments,
+// counting doesn't allocate
+unsafe impl<A: GlobalAlloc> GlobalAlloc for CountingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc(layout) };
+        if !ptr.is_null() {
+            count_allocation(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc_zeroed(layout) };
+        if !ptr.is_null() {
+            count_allocation(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.inner.dealloc(ptr, layout) };
+        count_free(layout.size());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { self.inner.realloc(ptr, layout, new_size) };
+        if !new_ptr.is_null() {
+            // counted like a new allocation replacing the previous one
+            count_allocation(new_size);
+            count_free(layout.size());
+        }
+        new_ptr
+    }
+}
+
+/// Totals counted by [`CountingAllocator`] since the start of the process.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AllocationCounts {
+    pub allocations: u64,
+    pub allocated_bytes: u64,
+    pub freed_bytes: u64,
+}
+
+impl AllocationCounts {
+    /// Returns the current totals, all zero when the wrapper isn't installed.
+    #[must_use]
+    pub fn current() -> Self {
+        Self {
+            allocations: ALLOCATIONS.load(Ordering::Relaxed),
+            allocated_bytes: ALLOCATED_BYTES.load(Ordering::Relaxed),
+            freed_bytes: FREED_BYTES.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns the bytes allocated and not freed yet.
+    #[must_use]
+    pub fn in_use_bytes(&self) -> u64 {
+        self.allocated_bytes.saturating_sub(self.freed_bytes)
+    }
+}
+
+/// The metrics recorded with `MetricsBuilder::allocation_stats`, see [`Metrics::catalog`].
+pub(crate) fn catalog_metrics() -> Vec<CatalogMetric> {
+    [
+        (
+            ALLOCATIONS_METRIC,
+            MetricUnit::Count,
+            "Heap allocations since the previous flush.",
+        ),
+        (
+            ALLOCATED_BYTES_METRIC,
+            MetricUnit::Bytes,
+            "Bytes allocated on the heap since the previous flush.",
+        ),
+        (
+            IN_USE_BYTES_METRIC,
+            MetricUnit::Bytes,
+            "Bytes allocated on the heap and not freed yet.",
+        ),
+    ]
+    .into_iter()
+    .map(|(name, unit, description)| CatalogMetric::library(name, unit, description))
+    .collect()
+}
+
+impl Metrics {
+    /// Adds the allocations since the previous flush to the buffer, if enabled, see
+    /// [`Metrics::measures_at_flush`].
+    pub(crate) fn buffer_allocation_stats(&mut self) {
+        if !self.measures_at_flush() {
+            return;
+        }
+        let Some(previous) = &mut self.allocation_stats else {
+            return;
+        };
+        let counts = AllocationCounts::current();
+        if counts.allocations == 0 {
+            return;
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let values = [
+            (
+                ALLOCATIONS_METRIC,
+                MetricUnit::Count,
+                counts.allocations.saturating_sub(previous.allocations) as f64,
+            ),
+            (
+                ALLOCATED_BYTES_METRIC,
+                MetricUnit::Bytes,
+                counts
+                    .allocated_bytes
+                    .saturating_sub(previous.allocated_bytes) as f64,
+            ),
+            (
+                IN_USE_BYTES_METRIC,
+                MetricUnit::Bytes,
+                counts.in_use_bytes() as f64,
+            ),
+        ];
+        *previous = counts;
+        for (name, unit, value) in values {
+            self.push_internal_metric(name, unit, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::RecordingSink;
+
+    #[test]
+    fn should_record_counted_allocations_at_flush() {
+        let allocator = CountingAllocator::system();
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .allocation_stats(true)
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            let ptr = allocator.realloc(ptr, layout, 128);
+            allocator.dealloc(ptr, Layout::from_size_align(128, 8).unwrap());
+        }
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+        metrics.flush_metrics();
+
+        let payload = &sink.payloads()[0];
+        assert_eq!(payload[ALLOCATIONS_METRIC], 2.0);
+        assert_eq!(payload[ALLOCATED_BYTES_METRIC], 192.0);
+        assert_eq!(payload[IN_USE_BYTES_METRIC], 0.0);
+    }
+}
@@ -0,0 +1,101 @@
+//! Dimension extraction for API Gateway proxy events.
+//!
+//! Available with the `events` feature.
+//!
+//! ```ignore
+//! async fn handler(event: LambdaEvent<ApiGatewayProxyRequest>) -> Result<ApiGatewayProxyResponse, Error> {
+//!     let mut metrics = Metrics::new("custom_lambdas", "service", "dummy_service");
+//!     metrics.add_api_gateway_dimensions(&event.payload)?;
+//!     // ...
+//! }
+//! ```
+//!
+//! Dimensions, low cardinality:
+//! - `api_stage`, `api_resource` (the resource path template, e.g. `/orders/{id}`), `http_method`
+//!
+//! Properties, searchable in `CloudWatch Logs Insights` without creating metric series:
+//! - `api_id`, `api_request_id`, `http_path`
+use aws_lambda_events::apigw::ApiGatewayProxyRequest;
+
+use crate::{Metrics, MetricsError};
+
+pub const STAGE_DIMENSION: &str = "api_stage";
+pub const RESOURCE_DIMENSION: &str = "api_resource";
+pub const HTTP_METHOD_DIMENSION: &str = "http_method";
+pub const API_ID_PROPERTY: &str = "api_id";
+pub const REQUEST_ID_PROPERTY: &str = "api_request_id";
+pub const PATH_PROPERTY: &str = "http_path";
+
+impl Metrics {
+    /// Adds stage, resource path template and HTTP method of an API Gateway proxy request as
+    /// dimensions, and API ID, request ID and the actual path as properties.
+    /// Values missing from the event are skipped.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the dimension limit is reached and the policy is `Reject`
+    pub fn add_api_gateway_dimensions(
+        &mut self,
+        request: &ApiGatewayProxyRequest,
+    ) -> Result<(), MetricsError> {
+        let context = &request.request_context;
+        if let Some(stage) = &context.stage {
+            self.try_add_dimension(STAGE_DIMENSION, stage)?;
+        }
+        if let Some(resource) = request.resource.as_ref().or(context.resource_path.as_ref()) {
+            self.try_add_dimension(RESOURCE_DIMENSION, resource)?;
+        }
+        self.try_add_dimension(HTTP_METHOD_DIMENSION, request.http_method.as_str())?;
+
+        if let Some(api_id) = &context.apiid {
+            self.add_property(API_ID_PROPERTY, api_id);
+        }
+        if let Some(request_id) = &context.request_id {
+            self.add_property(REQUEST_ID_PROPERTY, request_id);
+        }
+        if let Some(path) = &request.path {
+            self.add_property(PATH_PROPERTY, path);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use aws_lambda_events::http::Method;
+
+    use super::*;
+
+    #[test]
+    fn should_extract_dimensions_and_properties() {
+        let mut request = ApiGatewayProxyRequest::default();
+        request.resource = Some("/orders/{id}".to_string());
+        request.path = Some("/orders/42".to_string());
+        request.http_method = Method::POST;
+        request.request_context.stage = Some("prod".to_string());
+        request.request_context.apiid = Some("abc123".to_string());
+        request.request_context.request_id = Some("req-1".to_string());
+
+        let mut metrics = Metrics::new("test", "service", "orders");
+        metrics.add_api_gateway_dimensions(&request).unwrap();
+
+        assert_eq!(metrics.dimension(STAGE_DIMENSION), Some("prod"));
+        assert_eq!(metrics.dimension(RESOURCE_DIMENSION), Some("/orders/{id}"));
+        assert_eq!(metrics.dimension(HTTP_METHOD_DIMENSION), Some("POST"));
+        assert_eq!(metrics.property(API_ID_PROPERTY), Some("abc123"));
+        assert_eq!(metrics.property(REQUEST_ID_PROPERTY), Some("req-1"));
+        assert_eq!(metrics.property(PATH_PROPERTY), Some("/orders/42"));
+    }
+
+    #[test]
+    fn should_skip_missing_values() {
+        let request = ApiGatewayProxyRequest::default();
+
+        let mut metrics = Metrics::new("test", "service", "orders");
+        metrics.add_api_gateway_dimensions(&request).unwrap();
+
+        assert_eq!(metrics.dimension(STAGE_DIMENSION), None);
+        assert_eq!(metrics.dimension(HTTP_METHOD_DIMENSION), Some("GET"));
+        assert_eq!(metrics.property(API_ID_PROPERTY), None);
+    }
+}
@@ -0,0 +1,319 @@
+//! Metric groups switched on and off at runtime with AWS `AppConfig` feature flags.
+//!
+//! Expensive or experimental metrics are recorded in named groups, emitted only if the
+//! flag of the group is enabled. The flags document is fetched from the `AppConfig` Lambda
+//! extension and cached, so metrics can be switched without a deploy:
+//!
+//! ```no_run
+//! use std::time::Duration;
+//!
+//! use lambda_helpers_metrics::appconfig::MetricFlags;
+//! use lambda_helpers_metrics::{MetricUnit, Metrics};
+//!
+//! let flags = MetricFlags::appconfig("orders", "prod", "metric-flags").ttl(Duration::from_secs(30));
+//! let mut metrics = Metrics::builder("custom_lambdas")
+//!     .metric_flags(&flags)
+//!     .build()
+//!     .unwrap();
+//!
+//! metrics.add_metric_in_group("experimental", "queue_depth", MetricUnit::Count, 12.0);
+//! ```
+//!
+//! The document uses the `AppConfig` feature flags format, e.g.
+//! `{ "experimental": { "enabled": true }, "cache": { "enabled": false } }`.
+//! Groups missing from the document, or all groups if it can't be fetched, are enabled by
+//! default (see [`MetricFlags::default_enabled`]). A fetch failure keeps the last document,
+//! and is reported like other errors which are not returned, through the `on_error` callback
+//! of the `Metrics` object. The document is fetched outside of the cache lock, so other
+//! threads keep reading the previous one meanwhile.
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use crate::{mode, MetricUnit, Metrics, MetricsError};
+
+/// The environment variable with the HTTP port of the `AppConfig` extension.
+pub const EXTENSION_PORT_ENV: &str = "AWS_APPCONFIG_EXTENSION_HTTP_PORT";
+/// The port of the `AppConfig` extension if [`EXTENSION_PORT_ENV`] is not set.
+pub const DEFAULT_EXTENSION_PORT: u16 = 2772;
+/// How long the flags document is cached by default, see [`MetricFlags::ttl`].
+pub const DEFAULT_TTL: Duration = Duration::from_secs(45);
+/// Timeout of connecting to, and of reading from, the extension.
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+type Fetch = dyn Fn() -> Result<String, MetricsError> + Send + Sync;
+
+#[derive(Debug, Deserialize)]
+struct Flag {
+    enabled: bool,
+}
+
+#[derive(Debug, Default)]
+struct Cache {
+    fetched_at: Option<Instant>,
+    groups: HashMap<String, bool>,
+}
+
+/// Flags of metric groups, fetched lazily and cached for a TTL. Clones share the cache.
+#[derive(Clone)]
+pub struct MetricFlags {
+    fetch: Arc<Fetch>,
+    ttl: Duration,
+    default_enabled: bool,
+    cache: Arc<Mutex<Cache>>,
+}
+
+impl fmt::Debug for MetricFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MetricFlags")
+            .field("ttl", &self.ttl)
+            .field("default_enabled", &self.default_enabled)
+            .finish_non_exhaustive()
+    }
+}
+
+impl MetricFlags {
+    /// Fetches the flags document with the given function, e.g. from another configuration store.
+    #[must_use]
+    pub fn new(fetch: impl Fn() -> Result<String, MetricsError> + Send + Sync + 'static) -> Self {
+        Self {
+            fetch: Arc::new(fetch),
+            ttl: DEFAULT_TTL,
+            default_enabled: true,
+            cache: Arc::default(),
+        }
+    }
+
+    /// Fetches the flags document from the `AppConfig` Lambda extension, listening on
+    /// the port from `AWS_APPCONFIG_EXTENSION_HTTP_PORT` (2772 by default).
+    #[must_use]
+    pub fn appconfig(application: &str, environment: &str, profile: &str) -> Self {
+        let port = std::env::var(EXTENSION_PORT_ENV)
+            .ok()
+            .and_then(|port| port.trim().parse().ok())
+            .unwrap_or(DEFAULT_EXTENSION_PORT);
+        let path = format!(
+            "/applications/{application}/environments/{environment}/configurations/{profile}"
+        );
+        Self::new(move || http_get(SocketAddr::from(([127, 0, 0, 1], port)), &path))
+    }
+
+    /// Sets how long the document is cached, 45 seconds by default.
+    #[must_use]
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Sets whether groups missing from the document are enabled, `true` by default.
+    #[must_use]
+    pub fn default_enabled(mut self, enabled: bool) -> Self {
+        self.default_enabled = enabled;
+        self
+    }
+
+    /// Returns `true` if the group is enabled, fetching the document if the cached one expired.
+    /// Fetch failures are reported like other errors which are not returned.
+    #[must_use]
+    pub fn is_enabled(&self, group: &str) -> bool {
+        let (enabled, err) = self.lookup(group);
+        if let Some(err) = err {
+            mode::report(&err);
+        }
+        enabled
+    }
+
+    /// Returns whether the group is enabled, and the error of the fetch, if one failed.
+    pub(crate) fn lookup(&self, group: &str) -> (bool, Option<MetricsError>) {
+        let err = self.refresh_if_expired().err();
+        let cache = self.cache.lock().unwrap_or_else(PoisonError::into_inner);
+        let enabled = cache
+            .groups
+            .get(group)
+            .copied()
+            .unwrap_or(self.default_enabled);
+        (enabled, err)
+    }
+
+    /// Fetches the document without holding the lock, if the cached one expired.
+    fn refresh_if_expired(&self) -> Result<(), MetricsError> {
+        {
+            let mut cache = self.cache.lock().unwrap_or_else(PoisonError::into_inner);
+            if cache
+                .fetched_at
+                .is_some_and(|fetched_at| fetched_at.elapsed() < self.ttl)
+            {
+                return Ok(());
+            }
+            // failures are retried once the TTL expires again, not on every metric, and
+            // other threads keep the previous document while this one fetches
+            cache.fetched_at = Some(Instant::now());
+        }
+        let groups = (self.fetch)().and_then(|document| parse(&document))?;
+        self.cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .groups = groups;
+        Ok(())
+    }
+}
+
+fn parse(document: &str) -> Result<HashMap<String, bool>, MetricsError> {
+    let flags: HashMap<String, Flag> = serde_json::from_str(document)
+        .map_err(|err| MetricsError::Configuration(format!("invalid metric flags: {err}")))?;
+    Ok(flags
+        .into_iter()
+        .map(|(group, flag)| (group, flag.enabled))
+        .collect())
+}
+
+fn http_get(address: SocketAddr, path: &str) -> Result<String, MetricsError> {
+    let mut stream = TcpStream::connect_timeout(&address, REQUEST_TIMEOUT)?;
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    // HTTP/1.0 keeps the response free of chunked encoding
+    let request = format!("GET {path} HTTP/1.0\r\nHost: localhost\r\n\r\n");
+    stream.write_all(request.as_bytes())?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| MetricsError::Configuration("malformed AppConfig response".to_string()))?;
+    let status = head.split_whitespace().nth(1).unwrap_or_default();
+    if status != "200" {
+        return Err(MetricsError::Configuration(format!(
+            "AppConfig extension returned status {status}"
+        )));
+    }
+    Ok(body.to_string())
+}
+
+impl Metrics {
+    /// Returns `true` if the metric group is enabled, or if no flags are set.
+    /// See [`crate::appconfig`].
+    #[must_use]
+    pub fn is_group_enabled(&self, group: &str) -> bool {
+        let Some(flags) = &self.metric_flags else {
+            return true;
+        };
+        let (enabled, err) = flags.lookup(group);
+        if let Some(err) = err {
+            self.report_error(&err);
+        }
+        enabled
+    }
+
+    /// Adds the metric if its group is enabled, see [`crate::appconfig`].
+    pub fn add_metric_in_group(&mut self, group: &str, name: &str, unit: MetricUnit, value: f64) {
+        if self.is_group_enabled(group) {
+            self.add_metric(name, unit, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[test]
+    fn should_cache_flags_for_ttl() {
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&fetches);
+        let flags = MetricFlags::new(move || {
+            counter.fetch_add(1, Ordering::Relaxed);
+            Ok(
+                r#"{ "cache": { "enabled": false }, "experimental": { "enabled": true } }"#
+                    .to_string(),
+            )
+        });
+
+        assert!(!flags.is_enabled("cache"));
+        assert!(flags.is_enabled("experimental"));
+        assert!(flags.is_enabled("unknown"));
+        assert!(!flags.clone().default_enabled(false).is_enabled("unknown"));
+        assert_eq!(fetches.load(Ordering::Relaxed), 1);
+
+        let expired = flags.ttl(Duration::ZERO);
+        assert!(!expired.is_enabled("cache"));
+        assert_eq!(fetches.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn should_fall_back_to_default_when_fetch_fails() {
+        let flags = MetricFlags::new(|| Err(MetricsError::Configuration("down".to_string())));
+
+        assert!(flags.is_enabled("cache"));
+        assert!(!flags.default_enabled(false).is_enabled("cache"));
+    }
+
+    #[test]
+    fn should_pass_fetch_failures_to_error_callback() {
+        let reported = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&reported);
+        let flags = MetricFlags::new(|| Err(MetricsError::Configuration("down".to_string())));
+        let metrics = Metrics::builder("test")
+            .metric_flags(&flags)
+            .on_error(move |_| {
+                counter.fetch_add(1, Ordering::Relaxed);
+            })
+            .sink(crate::sink::NullSink)
+            .build()
+            .unwrap();
+
+        assert!(metrics.is_group_enabled("cache"));
+        assert!(metrics.is_group_enabled("cache"));
+        assert_eq!(reported.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn should_skip_metrics_of_disabled_groups() {
+        let flags = MetricFlags::new(|| Ok(r#"{ "cache": { "enabled": false } }"#.to_string()));
+        let mut metrics = Metrics::builder("test")
+            .metric_flags(&flags)
+            .build()
+            .unwrap();
+
+        metrics.add_metric_in_group("cache", "cache_hit", MetricUnit::Count, 1.0);
+        metrics.add_metric_in_group("core", "orders", MetricUnit::Count, 1.0);
+
+        assert!(!metrics.contains("cache_hit"));
+        assert!(metrics.contains("orders"));
+    }
+
+    #[test]
+    fn should_fetch_document_over_http() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let read = stream.read(&mut buffer).unwrap();
+                request.extend_from_slice(&buffer[..read]);
+            }
+            stream
+                .write_all(b"HTTP/1.0 200 OK\r\nContent-Type: application/json\r\n\r\n{}")
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let body = http_get(
+            SocketAddr::from(([127, 0, 0, 1], port)),
+            "/applications/a/environments/e/configurations/p",
+        );
+
+        assert_eq!(body.unwrap(), "{}");
+        assert!(server
+            .join()
+            .unwrap()
+            .starts_with("GET /applications/a/environments/e/configurations/p HTTP/1.0"));
+    }
+}
@@ -0,0 +1,392 @@
+//! Sink archiving payloads to S3, for long-term retention of the raw metrics.
+//!
+//! [`ArchiveSink`] accumulates payloads and writes them as newline-delimited batches under an
+//! S3 prefix, partitioned by date and function:
+//! `<prefix>/date=2024-06-01/function=orders/20240601T120000.000Z-<id>.ndjson`.
+//! A batch is written once it holds `max_payloads` payloads or `max_bytes` bytes, or when a
+//! payload arrives more than `max_age` after the first one of the batch:
+//!
+//! ```no_run
+//! use lambda_helpers_metrics::archive::ArchiveSink;
+//! use lambda_helpers_metrics::{MetricUnit, Metrics};
+//!
+//! # async fn example(client: aws_sdk_s3::Client) {
+//! let archive = ArchiveSink::new(client, "metrics-archive", "raw").flush_on_shutdown();
+//! let mut metrics = Metrics::builder("custom_lambdas")
+//!     .async_sink(archive.clone())
+//!     .build()
+//!     .unwrap();
+//! metrics.add_metric("orders", MetricUnit::Count, 1.0);
+//! metrics.flush_async().await;
+//!
+//! // e.g. before the execution environment shuts down
+//! archive.flush().await.unwrap();
+//! # }
+//! ```
+//!
+//! The last batch is written only by a later payload or [`ArchiveSink::flush`]. To write it
+//! when the execution environment shuts down, register the sink with
+//! [`ArchiveSink::flush_on_shutdown`], see [`crate::shutdown`].
+//!
+//! With the `parquet` feature, batches can be written as Parquet files instead, with
+//! [`ArchiveFormat::Parquet`], so they can be queried with Athena without parsing JSON lines.
+//!
+//! With the `gzip` feature, newline-delimited batches can be compressed with
+//! [`ArchiveSink::gzip`], raw EMF JSON usually shrinks tenfold.
+//!
+//! Clones share the batch. A batch which can't be written is lost, and the failure is
+//! reported like any failed emission. Available with the `archive` feature.
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use aws_sdk_s3::error::DisplayErrorContext;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::Client;
+use chrono::{DateTime, Utc};
+
+use crate::clock::{Clock, SystemClock};
+use crate::sink::{AsyncMetricsSink, EmitFuture};
+use crate::MetricsError;
+
+#[cfg(feature = "parquet")]
+mod columnar;
+
+const DEFAULT_MAX_PAYLOADS: usize = 1000;
+const DEFAULT_MAX_BYTES: usize = 5 * 1024 * 1024;
+const DEFAULT_MAX_AGE: Duration = Duration::from_secs(60);
+
+/// The format of the archived batches.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    /// The payloads as they are, one per line, in `.ndjson` objects.
+    #[default]
+    Ndjson,
+    /// One row per metric value, with the columns `timestamp` (milliseconds since the epoch),
+    /// `namespace`, `name`, `unit`, `value` and `dimensions` (a JSON object), in snappy-compressed
+    /// `.parquet` objects. Properties are not kept. Available with the `parquet` feature.
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+impl ArchiveFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Ndjson => "ndjson",
+            #[cfg(feature = "parquet")]
+            Self::Parquet => "parquet",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Self::Ndjson => "application/x-ndjson",
+            #[cfg(feature = "parquet")]
+            Self::Parquet => "application/vnd.apache.parquet",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Batch {
+    lines: Vec<String>,
+    bytes: usize,
+    started: Option<Instant>,
+}
+
+/// Writes batches of payloads to S3, see [`crate::archive`].
+#[derive(Clone)]
+pub struct ArchiveSink {
+    client: Client,
+    bucket: String,
+    prefix: String,
+    function_name: String,
+    max_payloads: usize,
+    max_bytes: usize,
+    max_age: Duration,
+    format: ArchiveFormat,
+    #[cfg(feature = "gzip")]
+    gzip_level: Option<u32>,
+    clock: Arc<dyn Clock>,
+    batch: Arc<Mutex<Batch>>,
+}
+
+impl fmt::Debug for ArchiveSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ArchiveSink")
+            .field("bucket", &self.bucket)
+            .field("prefix", &self.prefix)
+            .field("function_name", &self.function_name)
+            .field("max_payloads", &self.max_payloads)
+            .field("max_bytes", &self.max_bytes)
+            .field("max_age", &self.max_age)
+            .field("format", &self.format)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ArchiveSink {
+    /// Creates a sink writing under the prefix of the bucket, partitioned by the function
+    /// name from `AWS_LAMBDA_FUNCTION_NAME`.
+    #[must_use]
+    pub fn new(client: Client, bucket: &str, prefix: &str) -> Self {
+        Self {
+            client,
+            bucket: bucket.to_string(),
+            prefix: prefix.trim_matches('/').to_string(),
+            function_name: std::env::var("AWS_LAMBDA_FUNCTION_NAME")
+                .unwrap_or_else(|_| "unknown".to_string()),
+            max_payloads: DEFAULT_MAX_PAYLOADS,
+            max_bytes: DEFAULT_MAX_BYTES,
+            max_age: DEFAULT_MAX_AGE,
+            format: ArchiveFormat::default(),
+            #[cfg(feature = "gzip")]
+            gzip_level: None,
+            clock: Arc::new(SystemClock),
+            batch: Arc::default(),
+        }
+    }
+
+    /// Sets the function name of the partition.
+    #[must_use]
+    pub fn function_name(mut self, name: &str) -> Self {
+        self.function_name = name.to_string();
+        self
+    }
+
+    /// Sets the number of payloads of a full batch, 1000 by default.
+    #[must_use]
+    pub fn max_payloads(mut self, max: usize) -> Self {
+        self.max_payloads = max.max(1);
+        self
+    }
+
+    /// Sets the size of a full batch, 5 MiB by default.
+    #[must_use]
+    pub fn max_bytes(mut self, max: usize) -> Self {
+        self.max_bytes = max;
+        self
+    }
+
+    /// Sets the age after which a batch is written with the next payload, 60 seconds by default.
+    #[must_use]
+    pub fn max_age(mut self, max: Duration) -> Self {
+        self.max_age = max;
+        self
+    }
+
+    /// Sets the format of the batches, newline-delimited JSON by default.
+    #[must_use]
+    pub fn format(mut self, format: ArchiveFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Compresses newline-delimited batches with gzip at the level, from 0 (none) to 9 (best),
+    /// into `.ndjson.gz` objects. Parquet files are compressed already and are left as they are.
+    /// Available with the `gzip` feature.
+    #[cfg(feature = "gzip")]
+    #[must_use]
+    pub fn gzip(mut self, level: u32) -> Self {
+        self.gzip_level = Some(level);
+        self
+    }
+
+    /// Returns the gzip level of newline-delimited batches, if compressed.
+    fn ndjson_gzip_level(&self) -> Option<u32> {
+        #[cfg(feature = "gzip")]
+        if self.format == ArchiveFormat::Ndjson {
+            return self.gzip_level;
+        }
+        None
+    }
+
+    /// Replaces the clock dating the objects and measuring the age of batches, e.g. in tests.
+    #[must_use]
+    pub fn clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Registers the sink to be flushed by [`crate::shutdown::flush_pending_async`], e.g. on
+    /// `SIGTERM` with [`crate::shutdown::flush_on_shutdown`], so the last batch isn't lost.
+    #[must_use]
+    pub fn flush_on_shutdown(self) -> Self {
+        let sink = self.clone();
+        crate::shutdown::register_sink(move || {
+            let sink = sink.clone();
+            Box::pin(async move { sink.flush().await })
+        });
+        self
+    }
+
+    /// Writes the pending payloads, if any.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the batch can't be written
+    pub async fn flush(&self) -> Result<(), MetricsError> {
+        let lines = std::mem::take(&mut *self.lock()).lines;
+        self.write(lines).await
+    }
+
+    /// Returns the number of payloads waiting for the next batch.
+    #[must_use]
+    pub fn pending(&self) -> usize {
+        self.lock().lines.len()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Batch> {
+        self.batch.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Adds the payload to the batch, returning the batch if it's due to be written.
+    fn push(&self, payload: String) -> Option<Vec<String>> {
+        let now = self.clock.instant();
+        let mut batch = self.lock();
+        let started = *batch.started.get_or_insert(now);
+        batch.bytes += payload.len() + 1;
+        batch.lines.push(payload);
+        let due = batch.lines.len() >= self.max_payloads
+            || batch.bytes >= self.max_bytes
+            || now.duration_since(started) >= self.max_age;
+        due.then(|| std::mem::take(&mut *batch).lines)
+    }
+
+    /// Returns the key of a batch written at the given time.
+    fn object_key(&self, now: DateTime<Utc>) -> String {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_i64(now.timestamp_nanos_opt().unwrap_or_default());
+        let partition = format!(
+            "date={}/function={}/{}-{:016x}.{}{}",
+            now.format("%Y-%m-%d"),
+            self.function_name,
+            now.format("%Y%m%dT%H%M%S%.3fZ"),
+            hasher.finish(),
+            self.format.extension(),
+            if self.ndjson_gzip_level().is_some() {
+                ".gz"
+            } else {
+                ""
+            }
+        );
+        if self.prefix.is_empty() {
+            partition
+        } else {
+            format!("{}/{partition}", self.prefix)
+        }
+    }
+
+    async fn write(&self, lines: Vec<String>) -> Result<(), MetricsError> {
+        if lines.is_empty() {
+            return Ok(());
+        }
+        let now = self.clock.now();
+        let body = match self.format {
+            ArchiveFormat::Ndjson => {
+                let mut body = lines.join("\n");
+                body.push('\n');
+                body.into_bytes()
+            }
+            #[cfg(feature = "parquet")]
+            ArchiveFormat::Parquet => columnar::encode(&lines, now.timestamp_millis())?,
+        };
+        #[cfg(feature = "gzip")]
+        let body = match self.ndjson_gzip_level() {
+            Some(level) => crate::compression::gzip(&body, level)?,
+            None => body,
+        };
+        // without `Content-Encoding`, so downloads keep the compressed file Athena expects
+        let content_type = if self.ndjson_gzip_level().is_some() {
+            "application/gzip"
+        } else {
+            self.format.content_type()
+        };
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(self.object_key(now))
+            .content_type(content_type)
+            .body(ByteStream::from(body))
+            .send()
+            .await
+            .map_err(|err| {
+                MetricsError::Io(std::io::Error::other(DisplayErrorContext(err).to_string()))
+            })?;
+        Ok(())
+    }
+}
+
+impl AsyncMetricsSink for ArchiveSink {
+    fn emit(&self, payload: String) -> EmitFuture<'_> {
+        Box::pin(async move {
+            match self.push(payload) {
+                Some(lines) => self.write(lines).await,
+                None => Ok(()),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::clock::ManualClock;
+
+    fn sink(clock: &ManualClock) -> ArchiveSink {
+        let config = aws_sdk_s3::Config::builder()
+            .behavior_version(aws_sdk_s3::config::BehaviorVersion::latest())
+            .region(aws_sdk_s3::config::Region::new("eu-west-1"))
+            .build();
+        ArchiveSink::new(Client::from_conf(config), "bucket", "/raw/")
+            .function_name("orders")
+            .clock(clock.clone())
+    }
+
+    #[test]
+    fn should_release_batch_when_full_or_old() {
+        let clock = ManualClock::new(Utc.timestamp_millis_opt(0).unwrap());
+        let sink = sink(&clock).max_payloads(2);
+
+        assert_eq!(sink.push("a".to_string()), None);
+        assert_eq!(
+            sink.push("b".to_string()),
+            Some(vec!["a".to_string(), "b".to_string()])
+        );
+        assert_eq!(sink.push("c".to_string()), None);
+        clock.advance(DEFAULT_MAX_AGE);
+        assert_eq!(
+            sink.clone()
+                .max_payloads(10)
+                .push("d".to_string())
+                .unwrap()
+                .len(),
+            2
+        );
+        assert_eq!(sink.pending(), 0);
+    }
+
+    #[test]
+    fn should_partition_keys_by_date_and_function() {
+        let clock = ManualClock::new(Utc.timestamp_millis_opt(1_717_243_200_123).unwrap());
+        let key = sink(&clock).object_key(clock.now());
+
+        assert!(key.starts_with("raw/date=2024-06-01/function=orders/20240601T120000.123Z-"));
+        assert!(key.ends_with(".ndjson"));
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn should_name_compressed_batches() {
+        let clock = ManualClock::new(Utc.timestamp_millis_opt(0).unwrap());
+
+        assert!(sink(&clock)
+            .gzip(6)
+            .object_key(clock.now())
+            .ends_with(".ndjson.gz"));
+    }
+}
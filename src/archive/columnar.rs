@@ -0,0 +1,125 @@
+//! Parquet encoding of archived batches.
+use std::sync::Arc;
+
+use parquet::basic::Compression;
+use parquet::data_type::{ByteArray, ByteArrayType, DoubleType, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+
+use crate::{emf, MetricsError};
+
+/// One row per metric value. The dimensions are a JSON object, which Athena reads with
+/// `json_extract_scalar(dimensions, '$.service')`.
+const SCHEMA: &str = "message metric {
+    required int64 timestamp (TIMESTAMP(MILLIS, true));
+    required binary namespace (STRING);
+    required binary name (STRING);
+    required binary unit (STRING);
+    required double value;
+    required binary dimensions (STRING);
+}";
+
+#[derive(Debug, Default)]
+struct Columns {
+    timestamps: Vec<i64>,
+    namespaces: Vec<ByteArray>,
+    names: Vec<ByteArray>,
+    units: Vec<ByteArray>,
+    values: Vec<f64>,
+    dimensions: Vec<ByteArray>,
+}
+
+/// Encodes the metrics of the payloads as a Parquet file with a single row group.
+/// Metrics without a timestamp are dated `now`, in milliseconds since the epoch.
+pub(super) fn encode(lines: &[String], now: i64) -> Result<Vec<u8>, MetricsError> {
+    let mut columns = Columns::default();
+    for line in lines {
+        for metric in emf::parse(line)? {
+            let dimensions = serde_json::to_string(&metric.dimensions)
+                .map_err(|err| MetricsError::Serialization(err.to_string()))?;
+            for value in metric.values {
+                columns.timestamps.push(metric.timestamp.unwrap_or(now));
+                columns.namespaces.push(metric.namespace.as_str().into());
+                columns.names.push(metric.name.as_str().into());
+                columns.units.push(metric.unit.as_str().into());
+                columns.values.push(value);
+                columns.dimensions.push(dimensions.as_str().into());
+            }
+        }
+    }
+    write(&columns).map_err(|err| MetricsError::Serialization(err.to_string()))
+}
+
+fn write(columns: &Columns) -> parquet::errors::Result<Vec<u8>> {
+    let schema = Arc::new(parse_message_type(SCHEMA)?);
+    let properties = Arc::new(
+        WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build(),
+    );
+    let mut file = Vec::new();
+    let mut writer = SerializedFileWriter::new(&mut file, schema, properties)?;
+    let mut row_group = writer.next_row_group()?;
+    let mut index = 0;
+    while let Some(mut column) = row_group.next_column()? {
+        match index {
+            0 => {
+                column
+                    .typed::<Int64Type>()
+                    .write_batch(&columns.timestamps, None, None)?;
+            }
+            4 => {
+                column
+                    .typed::<DoubleType>()
+                    .write_batch(&columns.values, None, None)?;
+            }
+            _ => {
+                let values = match index {
+                    1 => &columns.namespaces,
+                    2 => &columns.names,
+                    3 => &columns.units,
+                    _ => &columns.dimensions,
+                };
+                column
+                    .typed::<ByteArrayType>()
+                    .write_batch(values, None, None)?;
+            }
+        }
+        column.close()?;
+        index += 1;
+    }
+    row_group.close()?;
+    writer.close()?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::record::RowAccessor;
+
+    use super::*;
+
+    #[test]
+    fn should_write_a_row_per_value() {
+        let payload = r#"{"_aws":{"Timestamp":1000,"CloudWatchMetrics":[{"Namespace":"shop",
+            "Dimensions":[["service"]],"Metrics":[{"Name":"latency","Unit":"Milliseconds"}]}]},
+            "service":"orders","latency":[1.5,2.5]}"#;
+
+        let file = encode(&[payload.to_string()], 0).unwrap();
+
+        let reader = SerializedFileReader::new(bytes::Bytes::from(file)).unwrap();
+        let rows: Vec<_> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1].get_timestamp_millis(0).unwrap(), 1000);
+        assert_eq!(rows[1].get_string(2).unwrap(), "latency");
+        assert_eq!(rows[1].get_string(3).unwrap(), "Milliseconds");
+        assert_eq!(rows[1].get_double(4).unwrap(), 2.5);
+        assert_eq!(rows[1].get_string(5).unwrap(), r#"{"service":"orders"}"#);
+    }
+}
@@ -0,0 +1,299 @@
+//! AWS SDK interceptor recording per-call latency, retries and errors.
+//!
+//! Available with the `aws-sdk` feature.
+//!
+//! ```ignore
+//! use lambda_helpers_metrics::aws_sdk::MetricsInterceptor;
+//!
+//! let config = aws_config::load_from_env().await;
+//! let dynamo_config = aws_sdk_dynamodb::config::Builder::from(&config)
+//!     .interceptor(MetricsInterceptor::new())
+//!     .build();
+//! let client = aws_sdk_dynamodb::Client::from_conf(dynamo_config);
+//! ```
+//!
+//! Every call records, with `aws_service` and `aws_operation` dimensions:
+//! - `aws_sdk_call_duration` in milliseconds, including retries
+//! - `aws_sdk_call_retries`, the number of attempts after the first one
+//! - `aws_sdk_call_errors` with an additional `error_class` dimension, for failed calls
+use std::time::{Duration, Instant};
+
+use aws_smithy_runtime_api::box_error::BoxError;
+use aws_smithy_runtime_api::client::interceptors::context::{
+    BeforeSerializationInterceptorContextRef, BeforeTransmitInterceptorContextRef,
+    FinalizerInterceptorContextRef,
+};
+use aws_smithy_runtime_api::client::interceptors::Intercept;
+use aws_smithy_runtime_api::client::orchestrator::{Metadata, OrchestratorError};
+use aws_smithy_runtime_api::client::runtime_components::RuntimeComponents;
+use aws_smithy_types::config_bag::{ConfigBag, Storable, StoreReplace};
+
+use crate::context::{self, MetricsHandle};
+use crate::{Dimensions, MetricUnit, Metrics};
+
+pub const CALL_DURATION_METRIC: &str = "aws_sdk_call_duration";
+pub const CALL_RETRIES_METRIC: &str = "aws_sdk_call_retries";
+pub const CALL_ERRORS_METRIC: &str = "aws_sdk_call_errors";
+
+/// Classification of a failed SDK call, used as the `error_class` dimension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// The call timed out.
+    Timeout,
+    /// The request could not be sent, e.g. DNS or connection failure.
+    Connector,
+    /// The service returned a throttling response (HTTP 429).
+    Throttling,
+    /// The service rejected the request (HTTP 4xx).
+    Client,
+    /// The service failed (HTTP 5xx).
+    Server,
+    /// The response could not be parsed.
+    Response,
+    /// Anything else, e.g. an interceptor error.
+    Other,
+}
+
+impl ErrorClass {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorClass::Timeout => "timeout",
+            ErrorClass::Connector => "connector",
+            ErrorClass::Throttling => "throttling",
+            ErrorClass::Client => "client",
+            ErrorClass::Server => "server",
+            ErrorClass::Response => "response",
+            ErrorClass::Other => "other",
+        }
+    }
+
+    fn classify<E>(error: &OrchestratorError<E>, status: Option<u16>) -> Self {
+        if error.is_timeout_error() {
+            ErrorClass::Timeout
+        } else if error.is_connector_error() {
+            ErrorClass::Connector
+        } else if error.is_response_error() {
+            ErrorClass::Response
+        } else if error.is_operation_error() {
+            Self::from_status(status)
+        } else {
+            ErrorClass::Other
+        }
+    }
+
+    fn from_status(status: Option<u16>) -> Self {
+        match status {
+            Some(429) => ErrorClass::Throttling,
+            Some(400..=499) => ErrorClass::Client,
+            Some(500..=599) => ErrorClass::Server,
+            _ => ErrorClass::Other,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct CallStart(Instant);
+
+impl Storable for CallStart {
+    type Storer = StoreReplace<Self>;
+}
+
+#[derive(Debug, Clone)]
+struct Attempts(u32);
+
+impl Storable for Attempts {
+    type Storer = StoreReplace<Self>;
+}
+
+/// SDK interceptor recording call metrics.
+///
+/// By default metrics are recorded into the [current context](crate::context);
+/// calls made while no context is set are not recorded.
+#[derive(Debug, Default, Clone)]
+pub struct MetricsInterceptor {
+    handle: Option<MetricsHandle>,
+}
+
+impl MetricsInterceptor {
+    /// Creates an interceptor recording into the current context.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an interceptor recording into the given metrics.
+    #[must_use]
+    pub fn with_handle(handle: MetricsHandle) -> Self {
+        Self {
+            handle: Some(handle),
+        }
+    }
+
+    fn record(&self, f: impl FnOnce(&mut Metrics)) {
+        match &self.handle {
+            Some(handle) => handle.with(f),
+            None => {
+                context::with_current(f);
+            }
+        }
+    }
+}
+
+impl Intercept for MetricsInterceptor {
+    fn name(&self) -> &'static str {
+        "LambdaHelpersMetricsInterceptor"
+    }
+
+    fn read_before_execution(
+        &self,
+        _context: &BeforeSerializationInterceptorContextRef<'_>,
+        cfg: &mut ConfigBag,
+    ) -> Result<(), BoxError> {
+        cfg.interceptor_state().store_put(CallStart(Instant::now()));
+        cfg.interceptor_state().store_put(Attempts(0));
+        Ok(())
+    }
+
+    fn read_before_attempt(
+        &self,
+        _context: &BeforeTransmitInterceptorContextRef<'_>,
+        _runtime_components: &RuntimeComponents,
+        cfg: &mut ConfigBag,
+    ) -> Result<(), BoxError> {
+        let attempts = cfg.load::<Attempts>().map_or(0, |attempts| attempts.0);
+        cfg.interceptor_state().store_put(Attempts(attempts + 1));
+        Ok(())
+    }
+
+    fn read_after_execution(
+        &self,
+        context: &FinalizerInterceptorContextRef<'_>,
+        _runtime_components: &RuntimeComponents,
+        cfg: &mut ConfigBag,
+    ) -> Result<(), BoxError> {
+        let (service, operation) = cfg
+            .load::<Metadata>()
+            .map_or(("unknown", "unknown"), |metadata| {
+                (metadata.service(), metadata.name())
+            });
+        let call = SdkCall {
+            service,
+            operation,
+            duration: cfg
+                .load::<CallStart>()
+                .map_or(Duration::ZERO, |start| start.0.elapsed()),
+            attempts: cfg.load::<Attempts>().map_or(1, |attempts| attempts.0),
+            error: match context.output_or_error() {
+                Some(Err(error)) => Some(ErrorClass::classify(
+                    error,
+                    context
+                        .response()
+                        .map(|response| response.status().as_u16()),
+                )),
+                _ => None,
+            },
+        };
+        self.record(|metrics| call.record(metrics));
+        Ok(())
+    }
+}
+
+/// Outcome of a single SDK call.
+#[derive(Debug)]
+struct SdkCall<'a> {
+    service: &'a str,
+    operation: &'a str,
+    duration: Duration,
+    attempts: u32,
+    error: Option<ErrorClass>,
+}
+
+impl SdkCall<'_> {
+    fn record(&self, metrics: &mut Metrics) {
+        let mut dimensions = Dimensions::default();
+        dimensions.insert("aws_service", self.service);
+        dimensions.insert("aws_operation", self.operation);
+
+        metrics.add_sample_with_dimensions(
+            CALL_DURATION_METRIC,
+            MetricUnit::Milliseconds,
+            self.duration.as_secs_f64() * 1000.0,
+            dimensions.clone(),
+        );
+        metrics.increment_with_dimensions(
+            CALL_RETRIES_METRIC,
+            f64::from(self.attempts.saturating_sub(1)),
+            dimensions.clone(),
+        );
+        if let Some(error) = self.error {
+            dimensions.insert("error_class", error.as_str());
+            metrics.increment_with_dimensions(CALL_ERRORS_METRIC, 1.0, dimensions);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use aws_smithy_runtime_api::client::interceptors::context::{Input, InterceptorContext};
+    use aws_smithy_runtime_api::client::runtime_components::RuntimeComponentsBuilder;
+    use aws_smithy_types::config_bag::Layer;
+
+    use super::*;
+    use crate::sink::NullSink;
+
+    #[test]
+    fn should_record_call_metrics() {
+        let mut metrics = Metrics::builder("test").build().unwrap();
+        SdkCall {
+            service: "DynamoDB",
+            operation: "PutItem",
+            duration: Duration::from_millis(25),
+            attempts: 3,
+            error: Some(ErrorClass::Throttling),
+        }
+        .record(&mut metrics);
+
+        assert_eq!(metrics.value_of(CALL_DURATION_METRIC), Some(25.0));
+        assert_eq!(metrics.value_of(CALL_RETRIES_METRIC), Some(2.0));
+        assert_eq!(metrics.value_of(CALL_ERRORS_METRIC), Some(1.0));
+    }
+
+    #[test]
+    fn should_classify_by_status() {
+        assert_eq!(ErrorClass::from_status(Some(429)), ErrorClass::Throttling);
+        assert_eq!(ErrorClass::from_status(Some(404)), ErrorClass::Client);
+        assert_eq!(ErrorClass::from_status(Some(503)), ErrorClass::Server);
+        assert_eq!(ErrorClass::from_status(None), ErrorClass::Other);
+    }
+
+    #[test]
+    fn should_record_calls_through_interceptor() {
+        let handle = MetricsHandle::new(Metrics::builder("test").sink(NullSink).build().unwrap());
+        let interceptor = MetricsInterceptor::with_handle(handle.clone());
+        let components = RuntimeComponentsBuilder::for_tests().build().unwrap();
+        let mut layer = Layer::new("operation");
+        layer.store_put(Metadata::new("PutItem", "DynamoDB"));
+        let mut cfg = ConfigBag::of_layers(vec![layer]);
+        let mut context = InterceptorContext::new(Input::doesnt_matter());
+
+        interceptor
+            .read_before_execution(&(&context).into(), &mut cfg)
+            .unwrap();
+        for _ in 0..2 {
+            interceptor
+                .read_before_attempt(&(&context).into(), &components, &mut cfg)
+                .unwrap();
+        }
+        context.set_output_or_error(Err(OrchestratorError::timeout("timed out".into())));
+        interceptor
+            .read_after_execution(&(&context).into(), &components, &mut cfg)
+            .unwrap();
+
+        handle.with(|metrics| {
+            assert!(metrics.value_of(CALL_DURATION_METRIC).is_some());
+            assert_eq!(metrics.value_of(CALL_RETRIES_METRIC), Some(1.0));
+            assert_eq!(metrics.value_of(CALL_ERRORS_METRIC), Some(1.0));
+        });
+    }
+}
@@ -0,0 +1,320 @@
+//! Batch processing wrappers for stream event sources.
+//!
+//! Available with the `events` feature.
+//!
+//! ```ignore
+//! use lambda_helpers_metrics::batch::process_stream_batch;
+//!
+//! async fn handler(event: LambdaEvent<KinesisEvent>) -> Result<KinesisEventResponse, Error> {
+//!     let mut metrics = Metrics::new("custom_lambdas", "service", "dummy_service");
+//!     let response = process_stream_batch(&mut metrics, event.payload.records, |record| async move {
+//!         process(record).await
+//!     })
+//!     .await;
+//!     Ok(response)
+//! }
+//! ```
+//!
+//! The wrapper records, and flushes in one payload:
+//! - `batch_records_received`
+//! - `BatchItemsFailed`, `BatchItemsSucceeded` and `BatchItemsFailureRate` (percent),
+//!   see [`Metrics::record_batch_item_failures`]
+//! - `stream_iterator_age` in milliseconds, the age of the oldest record in the batch
+//! - `batch_record_duration` in milliseconds, one sample per record
+//!
+//! Failed records are reported in the partial batch response, which requires
+//! `ReportBatchItemFailures` to be enabled on the event source mapping.
+//!
+//! Handlers building the partial batch response themselves, e.g. for SQS, can record the same
+//! item metrics from the response:
+//!
+//! ```ignore
+//! let response: SqsBatchResponse = process(event.payload.records).await;
+//! metrics.record_batch_response(received, &response);
+//! ```
+use std::collections::HashSet;
+use std::future::Future;
+use std::time::Instant;
+
+use aws_lambda_events::dynamodb::EventRecord;
+use aws_lambda_events::kinesis::KinesisEventRecord;
+use aws_lambda_events::sqs::SqsBatchResponse;
+use aws_lambda_events::streams::{
+    DynamoDbBatchItemFailure, DynamoDbEventResponse, KinesisEventResponse,
+};
+use chrono::{DateTime, Utc};
+
+use crate::{MetricUnit, Metrics};
+
+pub const RECORDS_RECEIVED_METRIC: &str = "batch_records_received";
+pub const ITERATOR_AGE_METRIC: &str = "stream_iterator_age";
+pub const RECORD_DURATION_METRIC: &str = "batch_record_duration";
+pub const ITEMS_FAILED_METRIC: &str = "BatchItemsFailed";
+pub const ITEMS_SUCCEEDED_METRIC: &str = "BatchItemsSucceeded";
+pub const ITEMS_FAILURE_RATE_METRIC: &str = "BatchItemsFailureRate";
+
+/// A partial batch response, listing the items which failed.
+pub trait PartialBatchResponse {
+    /// The identifiers of the failed items.
+    fn failed_item_ids(&self) -> Vec<&str>;
+}
+
+impl PartialBatchResponse for SqsBatchResponse {
+    fn failed_item_ids(&self) -> Vec<&str> {
+        self.batch_item_failures
+            .iter()
+            .map(|failure| failure.item_identifier.as_str())
+            .collect()
+    }
+}
+
+impl PartialBatchResponse for KinesisEventResponse {
+    fn failed_item_ids(&self) -> Vec<&str> {
+        self.batch_item_failures
+            .iter()
+            .filter_map(|failure| failure.item_identifier.as_deref())
+            .collect()
+    }
+}
+
+impl PartialBatchResponse for DynamoDbEventResponse {
+    fn failed_item_ids(&self) -> Vec<&str> {
+        self.batch_item_failures
+            .iter()
+            .filter_map(|failure| failure.item_identifier.as_deref())
+            .collect()
+    }
+}
+
+impl Metrics {
+    /// Records `BatchItemsFailed`, `BatchItemsSucceeded` and `BatchItemsFailureRate` for a batch
+    /// of `total` items, given the identifiers reported with `ReportBatchItemFailures`.
+    /// Identifiers reported twice are counted once.
+    pub fn record_batch_item_failures<S: AsRef<str>>(
+        &mut self,
+        total: usize,
+        failed_item_ids: &[S],
+    ) {
+        let failed = failed_item_ids
+            .iter()
+            .map(AsRef::as_ref)
+            .collect::<HashSet<_>>()
+            .len();
+        self.record_batch_items(total, failed);
+    }
+
+    fn record_batch_items(&mut self, total: usize, failed: usize) {
+        let failed = failed.min(total);
+        #[allow(clippy::cast_precision_loss)]
+        let (total, failed) = (total as f64, failed as f64);
+        self.increment(ITEMS_FAILED_METRIC, failed);
+        self.increment(ITEMS_SUCCEEDED_METRIC, total - failed);
+        if total > 0.0 {
+            self.add_sample(
+                ITEMS_FAILURE_RATE_METRIC,
+                MetricUnit::Percent,
+                failed / total * 100.0,
+            );
+        }
+    }
+
+    /// Records the item metrics of a partial batch response, see
+    /// [`Metrics::record_batch_item_failures`].
+    pub fn record_batch_response(&mut self, total: usize, response: &impl PartialBatchResponse) {
+        self.record_batch_item_failures(total, &response.failed_item_ids());
+    }
+}
+
+/// A record delivered by a stream event source.
+pub trait StreamRecord {
+    /// The partial batch response type of the event source.
+    type Response: Default;
+
+    /// The identifier reported back to Lambda when processing of the record fails.
+    fn item_identifier(&self) -> String;
+
+    /// When the record was written to the stream.
+    fn arrival_time(&self) -> DateTime<Utc>;
+
+    /// Adds a failed record to the response.
+    fn add_failure(response: &mut Self::Response, item_identifier: String);
+}
+
+impl StreamRecord for KinesisEventRecord {
+    type Response = KinesisEventResponse;
+
+    fn item_identifier(&self) -> String {
+        self.kinesis.sequence_number.clone()
+    }
+
+    fn arrival_time(&self) -> DateTime<Utc> {
+        self.kinesis.approximate_arrival_timestamp.0
+    }
+
+    fn add_failure(response: &mut Self::Response, item_identifier: String) {
+        response.add_failure(item_identifier);
+    }
+}
+
+impl StreamRecord for EventRecord {
+    type Response = DynamoDbEventResponse;
+
+    fn item_identifier(&self) -> String {
+        self.change
+            .sequence_number
+            .clone()
+            .unwrap_or_else(|| self.event_id.clone())
+    }
+
+    fn arrival_time(&self) -> DateTime<Utc> {
+        self.change.approximate_creation_date_time
+    }
+
+    fn add_failure(response: &mut Self::Response, item_identifier: String) {
+        let mut failure = DynamoDbBatchItemFailure::default();
+        failure.item_identifier = Some(item_identifier);
+        response.batch_item_failures.push(failure);
+    }
+}
+
+/// Processes the records one by one, records batch metrics, flushes them and returns the
+/// partial batch response listing failed records.
+pub async fn process_stream_batch<R, F, Fut, E>(
+    metrics: &mut Metrics,
+    records: Vec<R>,
+    mut handler: F,
+) -> R::Response
+where
+    R: StreamRecord,
+    F: FnMut(R) -> Fut,
+    Fut: Future<Output = Result<(), E>>,
+{
+    let mut response = R::Response::default();
+    let now = Utc::now();
+    let total = records.len();
+    #[allow(clippy::cast_precision_loss)]
+    let received = total as f64;
+    let oldest = records.iter().map(StreamRecord::arrival_time).min();
+    let mut failed = 0;
+
+    for record in records {
+        let item_identifier = record.item_identifier();
+        let start = Instant::now();
+        let result = handler(record).await;
+        metrics.add_sample(
+            RECORD_DURATION_METRIC,
+            MetricUnit::Milliseconds,
+            start.elapsed().as_secs_f64() * 1000.0,
+        );
+        if result.is_err() {
+            failed += 1;
+            R::add_failure(&mut response, item_identifier);
+        }
+    }
+
+    metrics.increment(RECORDS_RECEIVED_METRIC, received);
+    metrics.record_batch_items(total, failed);
+    if let Some(oldest) = oldest {
+        #[allow(clippy::cast_precision_loss)]
+        let age = (now - oldest).num_milliseconds().max(0) as f64;
+        metrics.add_metric(ITERATOR_AGE_METRIC, MetricUnit::Milliseconds, age);
+    }
+    metrics.flush_metrics();
+    response
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::pin::pin;
+    use std::task::{Context, Poll, Waker};
+
+    use super::*;
+    use crate::sink::RecordingSink;
+
+    /// Polls a future which never waits, e.g. one built only from ready futures.
+    pub(crate) fn block_on_ready<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        match future
+            .as_mut()
+            .poll(&mut Context::from_waker(Waker::noop()))
+        {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("future is not ready"),
+        }
+    }
+
+    fn kinesis_record(sequence_number: &str, age_ms: i64) -> KinesisEventRecord {
+        let mut record: KinesisEventRecord = serde_json::from_value(serde_json::json!({
+            "kinesis": {
+                "approximateArrivalTimestamp": 0,
+                "data": "",
+                "partitionKey": "key",
+                "sequenceNumber": sequence_number,
+            }
+        }))
+        .unwrap();
+        record.kinesis.approximate_arrival_timestamp.0 =
+            Utc::now() - chrono::Duration::milliseconds(age_ms);
+        record
+    }
+
+    #[test]
+    fn should_record_batch_metrics_and_report_failures() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test").sink(sink.clone()).build().unwrap();
+        let records = vec![
+            kinesis_record("1", 5_000),
+            kinesis_record("2", 1_000),
+            kinesis_record("3", 1_000),
+        ];
+
+        let response = block_on_ready(process_stream_batch(
+            &mut metrics,
+            records,
+            |record| async move {
+                if record.kinesis.sequence_number == "2" {
+                    Err("poison")
+                } else {
+                    Ok(())
+                }
+            },
+        ));
+
+        assert_eq!(response.batch_item_failures.len(), 1);
+        assert_eq!(
+            response.batch_item_failures[0].item_identifier.as_deref(),
+            Some("2")
+        );
+        let payloads = sink.payloads();
+        assert_eq!(payloads.len(), 1);
+        assert_eq!(payloads[0][RECORDS_RECEIVED_METRIC], 3.0);
+        assert_eq!(
+            payloads[0][RECORD_DURATION_METRIC]
+                .as_array()
+                .unwrap()
+                .len(),
+            3
+        );
+        assert!(payloads[0][ITERATOR_AGE_METRIC].as_f64().unwrap() >= 5_000.0);
+        assert_eq!(payloads[0][ITEMS_FAILED_METRIC], 1.0);
+        assert_eq!(payloads[0][ITEMS_SUCCEEDED_METRIC], 2.0);
+    }
+
+    #[test]
+    fn should_record_sqs_batch_response() {
+        let mut metrics = Metrics::builder("test")
+            .sink(crate::sink::NullSink)
+            .build()
+            .unwrap();
+        let mut response = SqsBatchResponse::default();
+        response.add_failure("msg-1");
+        response.add_failure("msg-1");
+        response.add_failure("msg-2");
+
+        metrics.record_batch_response(8, &response);
+
+        assert_eq!(metrics.value_of(ITEMS_FAILED_METRIC), Some(2.0));
+        assert_eq!(metrics.value_of(ITEMS_SUCCEEDED_METRIC), Some(6.0));
+        assert_eq!(metrics.value_of(ITEMS_FAILURE_RATE_METRIC), Some(25.0));
+    }
+}
@@ -0,0 +1,76 @@
+//! Validates and pretty-prints the EMF lines of log files, or of stdin without arguments.
+//!
+//! ```text
+//! emf-inspect [--errors-only] [FILE]...
+//! ```
+//!
+//! Exits with status 1 if any EMF line is invalid.
+use std::io::{BufRead, BufReader};
+use std::process::ExitCode;
+
+use lambda_helpers_metrics::inspect;
+
+fn main() -> ExitCode {
+    let mut errors_only = false;
+    let mut paths = Vec::new();
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--errors-only" => errors_only = true,
+            "-h" | "--help" => {
+                println!("usage: emf-inspect [--errors-only] [FILE]...");
+                return ExitCode::SUCCESS;
+            }
+            _ => paths.push(arg),
+        }
+    }
+    let mut invalid = 0;
+    if paths.is_empty() {
+        invalid += inspect_lines("<stdin>", std::io::stdin().lock(), errors_only);
+    }
+    for path in &paths {
+        match std::fs::File::open(path) {
+            Ok(file) => invalid += inspect_lines(path, BufReader::new(file), errors_only),
+            Err(err) => {
+                eprintln!("{path}: {err}");
+                invalid += 1;
+            }
+        }
+    }
+    if invalid > 0 {
+        eprintln!("{invalid} invalid EMF line(s)");
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+/// Prints the EMF lines of the reader and returns the number of invalid ones.
+fn inspect_lines(source: &str, reader: impl BufRead, errors_only: bool) -> usize {
+    let mut invalid = 0;
+    for (index, line) in reader.lines().enumerate() {
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => {
+                eprintln!("{source}: {err}");
+                return invalid + 1;
+            }
+        };
+        let Some(payload) = inspect::extract_payload(&line) else {
+            continue;
+        };
+        let problems = inspect::validate(payload);
+        if problems.is_empty() {
+            if !errors_only {
+                println!("{source}:{}: valid", index + 1);
+                print!("{}", inspect::pretty_print(payload).unwrap_or_default());
+            }
+            continue;
+        }
+        invalid += 1;
+        println!("{source}:{}: invalid", index + 1);
+        for problem in problems {
+            println!("  - {problem}");
+        }
+    }
+    invalid
+}
@@ -0,0 +1,150 @@
+//! Recording from borrowed strings, without allocating per metric.
+//!
+//! [`BorrowedMetrics`] keeps metric names and dimensions as `&'a str`, e.g. slices of the
+//! incoming event, and defers all allocation to the moment they are moved into [`Metrics`],
+//! right before the flush:
+//!
+//! ```
+//! use lambda_helpers_metrics::borrowed::BorrowedMetrics;
+//! use lambda_helpers_metrics::{MetricUnit, Metrics};
+//!
+//! let mut metrics = Metrics::new("custom_lambdas", "service", "dummy_service");
+//! let event = String::from("checkout");
+//!
+//! let mut borrowed = BorrowedMetrics::with_capacity(2);
+//! let dimensions = [("operation", event.as_str())];
+//! borrowed.add_metric_with_dimensions("latency", MetricUnit::Milliseconds, 120.0, &dimensions);
+//! borrowed.increment("requests", 1.0);
+//!
+//! metrics.flush_borrowed(borrowed);
+//! ```
+use crate::{MetricUnit, Metrics};
+
+#[derive(Debug, Clone, Copy)]
+struct BorrowedMetric<'a> {
+    name: &'a str,
+    unit: MetricUnit,
+    value: f64,
+    dimensions: &'a [(&'a str, &'a str)],
+}
+
+/// Metrics recorded from borrowed strings, moved into [`Metrics`] with
+/// [`Metrics::record_borrowed`] or [`Metrics::flush_borrowed`].
+#[derive(Debug, Clone, Default)]
+pub struct BorrowedMetrics<'a> {
+    entries: Vec<BorrowedMetric<'a>>,
+}
+
+impl<'a> BorrowedMetrics<'a> {
+    /// Creates an empty recorder.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an empty recorder with room for `capacity` metrics, so recording doesn't allocate.
+    #[must_use]
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            entries: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Records a metric, see [`Metrics::add_metric`].
+    pub fn add_metric(&mut self, name: &'a str, unit: MetricUnit, value: f64) {
+        self.add_metric_with_dimensions(name, unit, value, &[]);
+    }
+
+    /// Records a metric with dimensions which apply only to this metric,
+    /// see [`Metrics::add_metric_with_dimensions`].
+    pub fn add_metric_with_dimensions(
+        &mut self,
+        name: &'a str,
+        unit: MetricUnit,
+        value: f64,
+        dimensions: &'a [(&'a str, &'a str)],
+    ) {
+        self.entries.push(BorrowedMetric {
+            name,
+            unit,
+            value,
+            dimensions,
+        });
+    }
+
+    /// Records a count, see [`Metrics::increment`].
+    pub fn increment(&mut self, name: &'a str, by: f64) {
+        self.add_metric(name, MetricUnit::Count, by);
+    }
+
+    /// Returns the number of recorded metrics.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if no metrics are recorded.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl Metrics {
+    /// Moves metrics recorded from borrowed strings into the buffer, see [`crate::borrowed`].
+    pub fn record_borrowed(&mut self, borrowed: BorrowedMetrics<'_>) {
+        for metric in borrowed.entries {
+            if metric.dimensions.is_empty() {
+                self.add_metric(metric.name, metric.unit, metric.value);
+            } else {
+                self.add_metric_with_dimensions(
+                    metric.name,
+                    metric.unit,
+                    metric.value,
+                    metric.dimensions,
+                );
+            }
+        }
+    }
+
+    /// Moves metrics recorded from borrowed strings into the buffer and flushes it,
+    /// see [`Metrics::flush_metrics`].
+    pub fn flush_borrowed(&mut self, borrowed: BorrowedMetrics<'_>) {
+        self.record_borrowed(borrowed);
+        self.flush_metrics();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::RecordingSink;
+
+    #[test]
+    fn should_record_borrowed_metrics_at_flush() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test").sink(sink.clone()).build().unwrap();
+        let event = String::from(r#"{"operation":"checkout"}"#);
+        let operation = &event[14..22];
+
+        let mut borrowed = BorrowedMetrics::with_capacity(2);
+        let dimensions = [("operation", operation)];
+        borrowed.add_metric_with_dimensions(
+            "latency",
+            MetricUnit::Milliseconds,
+            120.0,
+            &dimensions,
+        );
+        borrowed.increment("requests", 1.0);
+        assert_eq!(borrowed.len(), 2);
+        assert!(metrics.is_empty());
+
+        metrics.flush_borrowed(borrowed);
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads.len(), 2);
+        assert_eq!(payloads[0]["operation"], "checkout");
+        assert_eq!(payloads[0]["latency"], 120.0);
+        assert_eq!(payloads[1]["requests"], 1.0);
+    }
+}
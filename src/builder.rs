@@ -0,0 +1,919 @@
+use std::sync::Arc;
+
+use crate::appconfig::MetricFlags;
+use crate::catalog::codegen::{CatalogMetric, MetricCatalog};
+use crate::clock::{Clock, SystemClock};
+use crate::config::{MetricsConfig, SinkConfig};
+use crate::contributors::{self, ContributorRule};
+use crate::error::ErrorCallback;
+use crate::exporters::Exporters;
+use crate::filter::MetricFilter;
+use crate::format::EmbeddedMetricsContext;
+use crate::preset::DimensionPresets;
+use crate::provider::DimensionProvider;
+use crate::rename::{RenameMode, Renames};
+use crate::routing::{NamespaceRouting, SinkRoute, SinkRoutes};
+use crate::runtime_info::{self, AttachAs, ARCHITECTURE_KEY, INITIALIZATION_TYPE_KEY, REGION_KEY};
+use crate::selectors::{EventField, Selector, Target};
+use crate::self_metrics::{self, LibraryStats};
+#[cfg(feature = "async")]
+use crate::sink::AsyncMetricsSink;
+use crate::sink::{AgentSink, MetricsSink, NullSink, PrettySink, StdoutSink};
+use crate::spill::Spill;
+use crate::stage::StageDimension;
+use crate::staleness::{Staleness, StalenessWatchdog};
+use crate::warmup::WarmupDetector;
+use crate::{
+    DimensionLengthPolicy, DimensionOverflowPolicy, DimensionSet, Dimensions, Environment, Limits,
+    LogFields, MetricOverflowPolicy, MetricSchema, Metrics, MetricsError, Namespace, OutputFormat,
+    OutputSchema, Properties, TenantContext, TimestampAgePolicy,
+};
+
+/// Builder for [`Metrics`], for cases where the defaults of [`Metrics::new`] are not enough.
+///
+/// ```
+/// use lambda_helpers_metrics::sink::StdoutSink;
+/// use lambda_helpers_metrics::MetricsBuilder;
+///
+/// let metrics = MetricsBuilder::new("custom_lambdas")
+///     .dimension("service", "dummy_service")
+///     .dimension("application", "customer_service")
+///     .sink(StdoutSink)
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Debug)]
+pub struct MetricsBuilder {
+    namespace: String,
+    dimensions: Vec<(String, String)>,
+    dimension_set: Option<DimensionSet>,
+    dimension_templates: Vec<(String, String)>,
+    sink: Option<Arc<dyn MetricsSink>>,
+    #[cfg(feature = "async")]
+    async_sink: Option<Arc<dyn AsyncMetricsSink>>,
+    environment: Option<Environment>,
+    dry_run: bool,
+    dimension_overflow: DimensionOverflowPolicy,
+    limits: Limits,
+    dimension_length: DimensionLengthPolicy,
+    metric_overflow: MetricOverflowPolicy,
+    timestamp_age: TimestampAgePolicy,
+    sandbox_id: bool,
+    tenant_context: TenantContext,
+    namespace_routing: NamespaceRouting,
+    sink_routes: SinkRoutes,
+    renames: Renames,
+    metric_filter: MetricFilter,
+    providers: Vec<Arc<dyn DimensionProvider>>,
+    #[cfg(feature = "async")]
+    resolvers: Vec<crate::resolver::AsyncResolver>,
+    #[cfg(feature = "tracing")]
+    span_fields: Vec<(String, AttachAs)>,
+    output_format: OutputFormat,
+    output_schema: Option<OutputSchema>,
+    storage_resolution: u64,
+    log_group_name: Option<String>,
+    log_stream_name: Option<String>,
+    on_error: Option<ErrorCallback>,
+    schema: MetricSchema,
+    catalog: Vec<CatalogMetric>,
+    rollups: Vec<Vec<String>>,
+    dimension_presets: DimensionPresets,
+    dimension_preset: Option<String>,
+    disabled: bool,
+    stage: Option<StageDimension>,
+    log_fields: Option<LogFields>,
+    initialization_type: AttachAs,
+    architecture: AttachAs,
+    region: AttachAs,
+    metric_flags: Option<MetricFlags>,
+    clock: Arc<dyn Clock>,
+    spill: Option<Spill>,
+    infer_units: bool,
+    container_counters: bool,
+    process_stats: bool,
+    #[cfg(feature = "alloc-metrics")]
+    allocation_stats: bool,
+    near_timeout_threshold: Option<std::time::Duration>,
+    staleness_watchdog: Option<StalenessWatchdog>,
+    /// Selectors, names and targets of the event fields, parsed in `build`.
+    event_fields: Vec<(String, String, Target)>,
+    contributor_rules: Vec<ContributorRule>,
+    overhead_metrics: bool,
+    warmup_detector: Option<WarmupDetector>,
+    #[cfg(feature = "tokio-metrics")]
+    tokio_stats: Option<crate::tokio_stats::TokioStats>,
+    /// Whether the [process defaults](crate::defaults) are added in `build`.
+    process_defaults: bool,
+}
+
+impl MetricsBuilder {
+    /// Creates a builder for metrics published under the given namespace.
+    #[must_use]
+    pub fn new(namespace: &str) -> Self {
+        Self {
+            namespace: namespace.to_string(),
+            dimensions: Vec::new(),
+            dimension_set: None,
+            dimension_templates: Vec::new(),
+            sink: None,
+            #[cfg(feature = "async")]
+            async_sink: None,
+            environment: None,
+            dry_run: false,
+            dimension_overflow: DimensionOverflowPolicy::default(),
+            limits: Limits::default(),
+            dimension_length: DimensionLengthPolicy::default(),
+            metric_overflow: MetricOverflowPolicy::default(),
+            timestamp_age: TimestampAgePolicy::default(),
+            sandbox_id: false,
+            tenant_context: TenantContext::default(),
+            namespace_routing: NamespaceRouting::default(),
+            sink_routes: SinkRoutes::default(),
+            renames: Renames::default(),
+            metric_filter: MetricFilter::default(),
+            providers: Vec::new(),
+            #[cfg(feature = "async")]
+            resolvers: Vec::new(),
+            #[cfg(feature = "tracing")]
+            span_fields: Vec::new(),
+            output_format: OutputFormat::default(),
+            output_schema: None,
+            storage_resolution: 60,
+            log_group_name: None,
+            log_stream_name: None,
+            on_error: None,
+            schema: MetricSchema::default(),
+            catalog: Vec::new(),
+            rollups: Vec::new(),
+            dimension_presets: DimensionPresets::default(),
+            dimension_preset: None,
+            disabled: false,
+            stage: None,
+            log_fields: None,
+            initialization_type: AttachAs::Property,
+            architecture: AttachAs::Off,
+            region: AttachAs::Off,
+            metric_flags: None,
+            clock: Arc::new(SystemClock),
+            spill: None,
+            infer_units: false,
+            container_counters: false,
+            process_stats: false,
+            #[cfg(feature = "alloc-metrics")]
+            allocation_stats: false,
+            near_timeout_threshold: None,
+            staleness_watchdog: None,
+            event_fields: Vec::new(),
+            contributor_rules: Vec::new(),
+            overhead_metrics: false,
+            warmup_detector: None,
+            #[cfg(feature = "tokio-metrics")]
+            tokio_stats: None,
+            process_defaults: true,
+        }
+    }
+
+    /// Adds a dimension. Limits are validated in `build`.
+    #[must_use]
+    pub fn dimension(mut self, key: &str, value: &str) -> Self {
+        self.dimensions.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Adds a dimension whose value is resolved from the properties at each flush,
+    /// see [`crate::template`].
+    #[must_use]
+    pub fn dimension_template(mut self, key: &str, template: &str) -> Self {
+        self.dimension_templates
+            .push((key.to_string(), template.to_string()));
+        self
+    }
+
+    /// Starts from a shared set of dimensions, referenced instead of copied.
+    /// Dimensions added with [`MetricsBuilder::dimension`] are added on top of the set.
+    #[must_use]
+    pub fn dimension_set(mut self, set: &DimensionSet) -> Self {
+        self.dimension_set = Some(set.clone());
+        self
+    }
+
+    /// Sets the sink payloads are written to, overriding environment detection.
+    #[must_use]
+    pub fn sink(self, sink: impl MetricsSink + 'static) -> Self {
+        self.shared_sink(Arc::new(sink))
+    }
+
+    /// Sets a sink shared with other `Metrics` objects.
+    #[must_use]
+    pub fn shared_sink(mut self, sink: Arc<dyn MetricsSink>) -> Self {
+        self.sink = Some(sink);
+        self
+    }
+
+    /// Sets the async sink used by [`Metrics::flush_async`]. `flush_metrics`,
+    /// also called on drop, keeps writing to the synchronous sink.
+    #[cfg(feature = "async")]
+    #[must_use]
+    pub fn async_sink(mut self, sink: impl AsyncMetricsSink + 'static) -> Self {
+        self.async_sink = Some(Arc::new(sink));
+        self
+    }
+
+    /// Uses the default sink of the given environment instead of detecting it.
+    #[must_use]
+    pub fn environment(mut self, environment: Environment) -> Self {
+        self.environment = Some(environment);
+        self
+    }
+
+    /// Enables dry-run mode: payloads are serialized but printed to stderr instead of the sink.
+    /// See [`Metrics::set_dry_run`].
+    #[must_use]
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Sets what happens when a dimension is added after the limit is reached.
+    #[must_use]
+    pub fn dimension_overflow(mut self, policy: DimensionOverflowPolicy) -> Self {
+        self.dimension_overflow = policy;
+        self
+    }
+
+    /// Sets what happens when a dimension name or value is too long.
+    #[must_use]
+    pub fn dimension_length(mut self, policy: DimensionLengthPolicy) -> Self {
+        self.dimension_length = policy;
+        self
+    }
+
+    /// Sets what happens when a metric is added after the limit is reached.
+    #[must_use]
+    pub fn metric_overflow(mut self, policy: MetricOverflowPolicy) -> Self {
+        self.metric_overflow = policy;
+        self
+    }
+
+    /// Lowers the limits of the payloads, see [`Limits`]. They are validated in `build`.
+    #[must_use]
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Sets what happens to metrics with a timestamp `CloudWatch` would drop.
+    #[must_use]
+    pub fn timestamp_age(mut self, policy: TimestampAgePolicy) -> Self {
+        self.timestamp_age = policy;
+        self
+    }
+
+    /// Attaches the ID of the execution environment as the `sandbox_id` property.
+    /// See [`crate::cold_start::sandbox_id`].
+    #[must_use]
+    pub fn sandbox_id(mut self, enabled: bool) -> Self {
+        self.sandbox_id = enabled;
+        self
+    }
+
+    /// Infers the unit of [`Metrics::add_metric_value`] from the suffix of the metric name,
+    /// see [`crate::value::unit_from_suffix`]. Disabled by default.
+    #[must_use]
+    pub fn infer_units(mut self, enabled: bool) -> Self {
+        self.infer_units = enabled;
+        self
+    }
+
+    /// Emits the totals of the counters declared with [`static_counter!`](crate::static_counter)
+    /// at each flush, see [`crate::counters`]. Disabled by default.
+    #[must_use]
+    pub fn container_counters(mut self, enabled: bool) -> Self {
+        self.container_counters = enabled;
+        self
+    }
+
+    /// Records the CPU time since the previous flush and the open file descriptors of the
+    /// process at each flush, see [`crate::process_stats`]. Disabled by default.
+    #[must_use]
+    pub fn process_stats(mut self, enabled: bool) -> Self {
+        self.process_stats = enabled;
+        self
+    }
+
+    /// Records the heap allocations since the previous flush at each flush, counted by
+    /// [`crate::alloc_stats::CountingAllocator`]. Disabled by default.
+    /// Available with the `alloc-metrics` feature.
+    #[cfg(feature = "alloc-metrics")]
+    #[must_use]
+    pub fn allocation_stats(mut self, enabled: bool) -> Self {
+        self.allocation_stats = enabled;
+        self
+    }
+
+    /// Records the time left until the deadline at each flush, and whether it is below the
+    /// threshold, see [`crate::deadline`]. Disabled by default.
+    #[must_use]
+    pub fn near_timeout_threshold(mut self, threshold: std::time::Duration) -> Self {
+        self.near_timeout_threshold = Some(threshold);
+        self
+    }
+
+    /// Adds the value selected from the event by a JSONPath selector, e.g. `$.detail.orderType`,
+    /// as a dimension, see [`crate::selectors`]. The selector is validated in `build`. Distinct
+    /// values are capped, so values like order IDs belong in [`MetricsBuilder::event_property`].
+    #[must_use]
+    pub fn event_dimension(mut self, selector: &str, name: &str) -> Self {
+        self.event_fields
+            .push((selector.to_string(), name.to_string(), Target::Dimension));
+        self
+    }
+
+    /// Adds the value selected from the event as a property, see
+    /// [`MetricsBuilder::event_dimension`].
+    #[must_use]
+    pub fn event_property(mut self, selector: &str, name: &str) -> Self {
+        self.event_fields
+            .push((selector.to_string(), name.to_string(), Target::Property));
+        self
+    }
+
+    /// Declares a Contributor Insights rule, whose contributions are written as log lines
+    /// alongside the metrics, see [`crate::contributors`]. The rule is validated in `build`.
+    #[must_use]
+    pub fn contributor_rule(mut self, rule: ContributorRule) -> Self {
+        self.contributor_rules.push(rule);
+        self
+    }
+
+    /// Flushes or reports metrics kept in the buffer for too long without a flush,
+    /// see [`crate::staleness`]. Disabled by default.
+    #[must_use]
+    pub fn staleness_watchdog(mut self, watchdog: StalenessWatchdog) -> Self {
+        self.staleness_watchdog = Some(watchdog);
+        self
+    }
+
+    /// Records the size and serialization time of the payloads and the number of flushes per
+    /// invocation, see [`crate::self_metrics`]. Disabled by default.
+    #[must_use]
+    pub fn overhead_metrics(mut self, enabled: bool) -> Self {
+        self.overhead_metrics = enabled;
+        self
+    }
+
+    /// Sets the dimensions identifying a tenant, see [`Metrics::for_tenant`].
+    #[must_use]
+    pub fn tenant_context(mut self, context: TenantContext) -> Self {
+        self.tenant_context = context;
+        self
+    }
+
+    /// Sets a function selecting the namespace of each payload at flush, based on its dimensions
+    /// (shared and per-metric ones merged). Returning `None` keeps the default namespace.
+    /// Metrics with different dimensions are already emitted in separate payloads, so e.g.
+    /// each tenant can be routed to its own namespace.
+    #[must_use]
+    pub fn namespace_router(
+        mut self,
+        router: impl Fn(&Dimensions) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.namespace_routing.set_router(router);
+        self
+    }
+
+    /// Routes metrics whose names start with `prefix` (e.g. `db_` or `db_*`) to `namespace`.
+    /// Rules are checked in the order they were added and take precedence over
+    /// [`MetricsBuilder::namespace_router`].
+    #[must_use]
+    pub fn namespace_for_prefix(mut self, prefix: &str, namespace: &str) -> Self {
+        self.namespace_routing.add_prefix(prefix, namespace);
+        self
+    }
+
+    /// Writes the metrics matching `route` to `sink` instead of the sink of the object,
+    /// e.g. security metrics to a dedicated log group. Routes are checked at flush in the order
+    /// they were added, and metrics of different routes are emitted in separate payloads.
+    ///
+    /// ```
+    /// use lambda_helpers_metrics::sink::{AgentSink, StdoutSink};
+    /// use lambda_helpers_metrics::{Metrics, SinkRoute};
+    ///
+    /// let metrics = Metrics::builder("custom_lambdas")
+    ///     .route_to_sink(SinkRoute::MetricPrefix("security_".into()), AgentSink::from_env())
+    ///     .sink(StdoutSink)
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    #[must_use]
+    pub fn route_to_sink(mut self, route: SinkRoute, sink: impl MetricsSink + 'static) -> Self {
+        self.sink_routes.add(route, Arc::new(sink));
+        self
+    }
+
+    /// Emits the metric `old` as `new` from now on, see [`crate::rename`]. The rename map is
+    /// applied at flush, after derived metrics are computed.
+    #[must_use]
+    pub fn rename_metric(mut self, old: &str, new: &str, mode: RenameMode) -> Self {
+        self.renames.add(old, new, mode);
+        self
+    }
+
+    /// Adds a provider contributing dimensions and properties at every flush,
+    /// see [`crate::provider`].
+    #[must_use]
+    pub fn dimension_provider(mut self, provider: impl DimensionProvider + 'static) -> Self {
+        self.providers.push(Arc::new(provider));
+        self
+    }
+
+    /// Adds a resolver awaited by [`Metrics::flush_async`], see [`crate::resolver`].
+    /// Available with the `async` feature.
+    #[cfg(feature = "async")]
+    #[must_use]
+    pub fn async_resolver(mut self, resolver: crate::resolver::AsyncResolver) -> Self {
+        self.resolvers.push(resolver);
+        self
+    }
+
+    /// Attaches the field of the current `tracing` span, or of its parents, to the metrics
+    /// recorded inside it, see [`crate::span_fields`]. [`AttachAs::Off`] is ignored.
+    #[cfg(feature = "tracing")]
+    #[must_use]
+    pub fn span_field(mut self, field: &str, attach: AttachAs) -> Self {
+        if attach != AttachAs::Off {
+            self.span_fields.push((field.to_string(), attach));
+        }
+        self
+    }
+
+    /// Sets the detector of keep-warm pings used by [`Metrics::detect_warmup`],
+    /// see [`crate::warmup`].
+    #[must_use]
+    pub fn warmup_detector(mut self, detector: WarmupDetector) -> Self {
+        self.warmup_detector = Some(detector);
+        self
+    }
+
+    /// Records the statistics of a tokio runtime at flush, see [`crate::tokio_stats`].
+    /// Available with the `tokio-metrics` feature.
+    #[cfg(feature = "tokio-metrics")]
+    #[must_use]
+    pub fn tokio_stats(mut self, stats: crate::tokio_stats::TokioStats) -> Self {
+        self.tokio_stats = Some(stats);
+        self
+    }
+
+    /// Records only metrics whose names match one of the include patterns, e.g. `orders_*`.
+    /// Patterns are globs, `*` matching any characters and `?` a single one. Filtered metrics
+    /// are skipped when added, without being counted as dropped.
+    #[must_use]
+    pub fn include_metrics(mut self, pattern: &str) -> Self {
+        self.metric_filter.include(pattern);
+        self
+    }
+
+    /// Skips metrics whose names match the pattern, e.g. noisy `debug_*` metrics in production,
+    /// even if they match an include pattern. See [`MetricsBuilder::include_metrics`].
+    #[must_use]
+    pub fn exclude_metrics(mut self, pattern: &str) -> Self {
+        self.metric_filter.exclude(pattern);
+        self
+    }
+
+    /// Sets the shape of the emitted payloads. With [`OutputFormat::AwsEmbeddedMetrics`]
+    /// the default dimensions and properties of the official libraries are added in `build`,
+    /// before the dimensions set on this builder.
+    #[must_use]
+    pub fn output_format(mut self, format: OutputFormat) -> Self {
+        self.output_format = format;
+        self
+    }
+
+    /// Pins the layout of the emitted payloads, see [`OutputSchema`]. Not pinned by default.
+    /// Options changing the pinned layout are rejected in `build`.
+    #[must_use]
+    pub fn output_schema(mut self, schema: OutputSchema) -> Self {
+        self.output_schema = Some(schema);
+        self
+    }
+
+    /// Sets the storage resolution of all metrics: 1 for high resolution, 60 (default) for standard.
+    /// The value is validated in `build`.
+    #[must_use]
+    pub fn storage_resolution(mut self, seconds: u64) -> Self {
+        self.storage_resolution = seconds;
+        self
+    }
+
+    /// Sets the `LogGroupName` of the payloads, used by the `CloudWatch` agent
+    /// (e.g. in ECS or Fargate) to pick the log group. Lambda ignores it.
+    #[must_use]
+    pub fn log_group_name(mut self, name: &str) -> Self {
+        self.log_group_name = Some(name.to_string());
+        self
+    }
+
+    /// Sets the `LogStreamName` of the payloads, used by the `CloudWatch` agent. Lambda ignores it.
+    #[must_use]
+    pub fn log_stream_name(mut self, name: &str) -> Self {
+        self.log_stream_name = Some(name.to_string());
+        self
+    }
+
+    /// Adds `level`, `message` and `logger` fields to every payload, see [`LogFields`].
+    /// In Lambda with the JSON log format, [`LogFields::lambda_json`] is used if none are set.
+    #[must_use]
+    pub fn log_fields(mut self, fields: LogFields) -> Self {
+        self.log_fields = Some(fields);
+        self
+    }
+
+    /// Adds the stage read from an environment variable as a dimension, see [`crate::stage`].
+    /// Dimensions added with [`MetricsBuilder::dimension`] override it.
+    #[must_use]
+    pub fn stage_dimension(mut self, stage: StageDimension) -> Self {
+        self.stage = Some(stage);
+        self
+    }
+
+    /// Sets how the initialization type (`on-demand`, `provisioned-concurrency` or `snap-start`)
+    /// is attached, a property by default. See [`crate::runtime_info`].
+    #[must_use]
+    pub fn initialization_type(mut self, attach: AttachAs) -> Self {
+        self.initialization_type = attach;
+        self
+    }
+
+    /// Attaches the CPU architecture (`arm64` or `x86_64`), not attached by default.
+    /// See [`crate::runtime_info`].
+    #[must_use]
+    pub fn architecture(mut self, attach: AttachAs) -> Self {
+        self.architecture = attach;
+        self
+    }
+
+    /// Attaches the region from `AWS_REGION`, not attached by default.
+    /// See [`crate::runtime_info`].
+    #[must_use]
+    pub fn region(mut self, attach: AttachAs) -> Self {
+        self.region = attach;
+        self
+    }
+
+    /// Sets the flags switching metric groups on and off, see [`crate::appconfig`].
+    #[must_use]
+    pub fn metric_flags(mut self, flags: &MetricFlags) -> Self {
+        self.metric_flags = Some(flags.clone());
+        self
+    }
+
+    /// Sets the clock payload timestamps and timers are taken from, see [`crate::clock`].
+    #[must_use]
+    pub fn clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Periodically persists the buffer to a file and replays the metrics a crashed process
+    /// left there, see [`crate::spill`].
+    #[must_use]
+    pub fn spill(mut self, spill: Spill) -> Self {
+        self.spill = Some(spill);
+        self
+    }
+
+    /// Sets the schema of metrics recorded with [`Metrics::record`], see [`crate::schema`].
+    #[must_use]
+    pub fn schema(mut self, schema: &MetricSchema) -> Self {
+        self.schema = schema.clone();
+        self
+    }
+
+    /// Emits the metrics under the subsets of dimensions too, see [`crate::rollup`].
+    #[must_use]
+    pub fn rollups(mut self, rollups: &[&[&str]]) -> Self {
+        self.rollups = rollups
+            .iter()
+            .map(|set| set.iter().map(ToString::to_string).collect())
+            .collect();
+        self
+    }
+
+    /// Defines a named preset of the dimensions used to aggregate the metrics,
+    /// see [`crate::preset`].
+    #[must_use]
+    pub fn define_dimension_preset(mut self, name: &str, keys: &[&str]) -> Self {
+        self.dimension_presets.define(name, keys);
+        self
+    }
+
+    /// Selects a preset of dimensions, which must be defined when the object is built.
+    #[must_use]
+    pub fn dimension_preset(mut self, name: &str) -> Self {
+        self.dimension_preset = Some(name.to_string());
+        self
+    }
+
+    /// Adds the metrics of a catalog, e.g. loaded with [`MetricCatalog::from_file`], to the
+    /// one returned by [`Metrics::catalog`]. Nothing is validated or emitted with it.
+    #[must_use]
+    pub fn metric_catalog(mut self, catalog: &MetricCatalog) -> Self {
+        self.catalog.extend(catalog.metrics.iter().cloned());
+        self
+    }
+
+    /// Sets a function called whenever a flush fails, because a payload couldn't be
+    /// serialized or written to the sink, e.g. to report the failure through the alerting
+    /// of the application. The error is still printed to stderr and counted
+    /// (see [`crate::self_metrics`]).
+    ///
+    /// ```
+    /// use lambda_helpers_metrics::Metrics;
+    ///
+    /// let metrics = Metrics::builder("custom_lambdas")
+    ///     .on_error(|err| eprintln!("metrics lost: {err}"))
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    #[must_use]
+    pub fn on_error(mut self, callback: impl Fn(&MetricsError) + Send + Sync + 'static) -> Self {
+        self.on_error = Some(ErrorCallback(Arc::new(callback)));
+        self
+    }
+
+    /// Applies the values set in the configuration, see [`crate::config`].
+    #[must_use]
+    pub fn config(mut self, config: &MetricsConfig) -> Self {
+        if let Some(namespace) = &config.namespace {
+            self.namespace.clone_from(namespace);
+        }
+        for (key, value) in &config.dimensions {
+            self = self.dimension(key, value);
+        }
+        if let Some(resolution) = config.storage_resolution {
+            self.storage_resolution = resolution;
+        }
+        match &config.sink {
+            Some(SinkConfig::Stdout) => self = self.sink(StdoutSink),
+            Some(SinkConfig::Pretty) => self = self.sink(PrettySink),
+            Some(SinkConfig::Agent(Some(endpoint))) => {
+                // endpoints are validated when the configuration is parsed
+                let sink = AgentSink::new(endpoint).unwrap_or_else(|_| AgentSink::from_env());
+                self = self.sink(sink);
+            }
+            Some(SinkConfig::Agent(None)) => self = self.sink(AgentSink::from_env()),
+            None => {}
+        }
+        if let Some(policy) = config.dimension_overflow {
+            self.dimension_overflow = policy;
+        }
+        if let Some(policy) = config.dimension_length {
+            self.dimension_length = policy;
+        }
+        if let Some(policy) = config.metric_overflow {
+            self.metric_overflow = policy;
+        }
+        if let Some(policy) = config.timestamp_age {
+            self.timestamp_age = policy;
+        }
+        if let Some(limits) = config.limits {
+            self.limits = limits;
+        }
+        if let Some(schema) = config.output_schema {
+            self.output_schema = Some(schema);
+        }
+        if let Some(name) = &config.log_group_name {
+            self.log_group_name = Some(name.clone());
+        }
+        if let Some(name) = &config.log_stream_name {
+            self.log_stream_name = Some(name.clone());
+        }
+        self.dimension_presets.extend(&config.dimension_presets);
+        if let Some(name) = &config.dimension_preset {
+            self.dimension_preset = Some(name.clone());
+        }
+        if let Some(stage) = &config.stage {
+            self.stage = Some(stage.clone());
+        }
+        for (name, selector) in &config.event_dimensions {
+            self = self.event_dimension(selector, name);
+        }
+        for (name, selector) in &config.event_properties {
+            self = self.event_property(selector, name);
+        }
+        for pattern in &config.include_metrics {
+            self = self.include_metrics(pattern);
+        }
+        for pattern in &config.exclude_metrics {
+            self = self.exclude_metrics(pattern);
+        }
+        if config.disabled {
+            self.disabled = true;
+        }
+        self
+    }
+
+    /// Leaves out the [process defaults](crate::defaults), e.g. for metrics re-emitting the
+    /// payloads of other `Metrics` objects, which already carry them.
+    #[must_use]
+    pub(crate) fn without_process_defaults(mut self) -> Self {
+        self.process_defaults = false;
+        self
+    }
+
+    /// Builds the `Metrics` object.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if too many dimensions were added, if the storage resolution is not 1 or 60,
+    /// if the [`Limits`] exceed the ones of `CloudWatch`,
+    /// if the selected dimension preset is not defined, if an event selector or a contributor rule
+    /// is invalid, or if an option changes the layout of the pinned [`OutputSchema`].
+    pub fn build(self) -> Result<Metrics, MetricsError> {
+        if !matches!(self.storage_resolution, 1 | 60) {
+            return Err(MetricsError::Configuration(format!(
+                "invalid storage resolution: {}, expected 1 or 60",
+                self.storage_resolution
+            )));
+        }
+        self.limits.validate()?;
+        let mut dimension_presets = self.dimension_presets;
+        dimension_presets.select(self.dimension_preset.as_deref())?;
+        let event_fields = self
+            .event_fields
+            .iter()
+            .map(|(selector, name, target)| {
+                Ok(EventField {
+                    selector: Selector::parse(selector)?,
+                    name: name.clone(),
+                    target: *target,
+                    values: Vec::new(),
+                })
+            })
+            .collect::<Result<Vec<_>, MetricsError>>()?;
+        contributors::validate_rules(&self.contributor_rules)?;
+        if let Some(schema) = self.output_schema {
+            if self.output_format != OutputFormat::Standard {
+                schema.allow("the output format")?;
+            }
+            if self
+                .log_fields
+                .as_ref()
+                .is_some_and(|fields| fields.timestamp)
+            {
+                schema.allow("the timestamp log field")?;
+            }
+            if !self.rollups.is_empty() {
+                schema.allow("rollups")?;
+            }
+            if !self.contributor_rules.is_empty() {
+                schema.allow("contributor rules")?;
+            }
+        }
+        let environment = self.environment.unwrap_or_else(Environment::detect);
+        // a disabled object discards every payload, whatever sink was set after the configuration
+        let sink = match self.sink {
+            _ if self.disabled => Arc::new(NullSink),
+            Some(sink) => sink,
+            None => environment.default_sink(),
+        };
+        let log_fields = self.log_fields.or_else(|| {
+            LogFields::detect(environment, self.output_schema, |key| {
+                std::env::var(key).ok()
+            })
+        });
+        let mut metrics = Metrics {
+            namespace: Namespace(self.namespace),
+            dimensions: self.dimension_set.unwrap_or_default().0,
+            properties: Properties::default(),
+            entries: Vec::new(),
+            sink,
+            #[cfg(feature = "async")]
+            async_sink: self.async_sink.filter(|_| !self.disabled),
+            dry_run: self.dry_run,
+            dimension_overflow: self.dimension_overflow,
+            dimension_length: self.dimension_length,
+            metric_overflow: self.metric_overflow,
+            timestamp_age: self.timestamp_age,
+            limits: self.limits,
+            tenant_context: self.tenant_context,
+            buffered_tenants: Vec::new(),
+            namespace_routing: self.namespace_routing,
+            sink_routes: self.sink_routes,
+            renames: self.renames,
+            metric_filter: self.metric_filter,
+            providers: self.providers,
+            #[cfg(feature = "async")]
+            resolvers: self.resolvers,
+            #[cfg(feature = "tracing")]
+            span_fields: self.span_fields,
+            output_format: self.output_format,
+            output_schema: self.output_schema,
+            log_timestamp: log_fields.as_ref().is_some_and(|fields| fields.timestamp),
+            storage_resolution: self.storage_resolution,
+            scoped_dimensions: Dimensions::default(),
+            log_group_name: self.log_group_name,
+            log_stream_name: self.log_stream_name,
+            library_stats: LibraryStats::default(),
+            on_error: self.on_error,
+            schema: self.schema,
+            catalog: self.catalog,
+            rollups: self.rollups,
+            dimension_presets,
+            lazy_entries: Vec::new(),
+            derived: Vec::new(),
+            disabled: self.disabled,
+            metric_flags: self.metric_flags,
+            clock: self.clock,
+            spill: self.spill,
+            exporters: Exporters::default(),
+            infer_units: self.infer_units,
+            error_types: Vec::new(),
+            container_counters: self.container_counters,
+            process_stats: self
+                .process_stats
+                .then(crate::process_stats::CpuTimes::default),
+            #[cfg(feature = "alloc-metrics")]
+            allocation_stats: self
+                .allocation_stats
+                .then(crate::alloc_stats::AllocationCounts::default),
+            overhead: self.overhead_metrics.then(self_metrics::Overhead::default),
+            deadline: None,
+            near_timeout_threshold: self.near_timeout_threshold,
+            warmup_detector: self.warmup_detector,
+            staleness: self.staleness_watchdog.map(Staleness::new),
+            event_fields,
+            contributor_rules: self.contributor_rules,
+            contributions: Default::default(),
+            dimension_templates: self.dimension_templates,
+            #[cfg(feature = "tokio-metrics")]
+            tokio_stats: self.tokio_stats,
+            warmup: false,
+        };
+        if self.output_format == OutputFormat::AwsEmbeddedMetrics {
+            let context = EmbeddedMetricsContext::detect(environment);
+            for (key, value) in &context.dimensions {
+                metrics.try_add_dimension(key, value)?;
+            }
+            for (key, value) in &context.properties {
+                metrics.add_property(key, value);
+            }
+        }
+        let stage = self
+            .stage
+            .as_ref()
+            .and_then(|stage| stage.resolve_from(|key| std::env::var(key).ok()));
+        if let Some((key, value)) = stage {
+            metrics.try_add_dimension(&key, &value)?;
+        }
+        if let Some(value) = runtime_info::initialization_type() {
+            metrics.attach(self.initialization_type, INITIALIZATION_TYPE_KEY, &value)?;
+        }
+        metrics.attach(
+            self.architecture,
+            ARCHITECTURE_KEY,
+            runtime_info::architecture(),
+        )?;
+        if let Some(value) = runtime_info::region() {
+            metrics.attach(self.region, REGION_KEY, &value)?;
+        }
+        for (key, value) in &self.dimensions {
+            metrics.try_add_dimension(key, value)?;
+        }
+        if let Some(fields) = &log_fields {
+            for (key, value) in fields.properties() {
+                metrics.add_property(key, value);
+            }
+        }
+        if self.sandbox_id {
+            metrics.add_sandbox_id();
+        }
+        // added last, so the defaults never take the place of the fields of the builder
+        let defaults = crate::defaults::current().filter(|_| self.process_defaults);
+        if let Some(defaults) = defaults {
+            for (key, value) in &defaults.dimensions {
+                if metrics.dimension(key).is_some() {
+                    continue;
+                }
+                // a full set is not made room for, whatever the overflow policy
+                let added = if metrics.dimensions_remaining() == 0 {
+                    Err(MetricsError::TooManyDimensions)
+                } else {
+                    metrics.try_add_dimension(key, value)
+                };
+                if let Err(err) = added {
+                    metrics.report_error(&err);
+                }
+            }
+            for (key, value) in &defaults.properties {
+                if metrics.json_property(key).is_none() {
+                    metrics.add_json_property(key, value.clone());
+                }
+            }
+        }
+        metrics.start_spill();
+        Ok(metrics)
+    }
+}
@@ -0,0 +1,247 @@
+//! Metric names and units defined once.
+//!
+//! [`define_metrics!`](crate::define_metrics) declares each metric as a typed constant, so a
+//! misspelled name is a compile error and a metric is always recorded with the same unit:
+//!
+//! ```
+//! use lambda_helpers_metrics::{define_metrics, Metrics};
+//!
+//! define_metrics! {
+//!     ORDERS_PROCESSED: Count,
+//!     CHECKOUT_LATENCY: Milliseconds,
+//!     pub PAYLOAD_SIZE = "payload.size": bytes,
+//! }
+//!
+//! let mut metrics = Metrics::new("custom_lambdas", "service", "dummy_service");
+//! metrics.add_defined(ORDERS_PROCESSED, 1.0);
+//! metrics.add_defined(CHECKOUT_LATENCY, 120.0);
+//! assert_eq!(metrics.value_of("orders_processed"), Some(1.0));
+//! assert_eq!(PAYLOAD_SIZE.name(), "payload.size");
+//! ```
+//!
+//! The metric name is the name of the constant in lowercase, unless given as a string literal.
+//! Units are written like in [`emit!`](crate::emit).
+//!
+//! The definitions can also be generated from a catalog file in a build script, see [`codegen`].
+//!
+//! [`Metrics::catalog`] lists everything a `Metrics` object can emit: the metrics of the
+//! catalogs passed to [`MetricsBuilder::metric_catalog`](crate::MetricsBuilder::metric_catalog),
+//! the declarations of its [schema](crate::schema) with their descriptions and owners,
+//! its derived metrics and the metrics of the library, such as `InitDuration` or the statistics
+//! enabled on the builder, e.g. for observability reviews:
+//!
+//! ```
+//! use lambda_helpers_metrics::{MetricSchema, MetricUnit, Metrics};
+//!
+//! let mut schema = MetricSchema::new();
+//! let orders = schema.register("orders", MetricUnit::Count).unwrap();
+//! schema.describe(orders, "Orders accepted by the checkout.");
+//! schema.set_owner(orders, "checkout-team");
+//!
+//! let metrics = Metrics::builder("custom_lambdas")
+//!     .schema(&schema)
+//!     .build()
+//!     .unwrap();
+//! let markdown = metrics.catalog().to_markdown();
+//! assert!(markdown.contains("| `orders` | Count |  | checkout-team |"));
+//! ```
+use crate::{cold_start, counters, deadline, process_stats, self_metrics, MetricUnit, Metrics};
+use codegen::{CatalogMetric, MetricCatalog};
+
+pub mod codegen;
+
+/// A metric name with its unit, usually declared with [`define_metrics!`](crate::define_metrics).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DefinedMetric {
+    name: &'static str,
+    unit: MetricUnit,
+}
+
+impl DefinedMetric {
+    /// Defines a metric.
+    #[must_use]
+    pub const fn new(name: &'static str, unit: MetricUnit) -> Self {
+        Self { name, unit }
+    }
+
+    /// Returns the name of the metric.
+    #[must_use]
+    pub const fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns the unit of the metric.
+    #[must_use]
+    pub const fn unit(&self) -> MetricUnit {
+        self.unit
+    }
+}
+
+impl Metrics {
+    /// Records a value of a defined metric, see [`crate::catalog`].
+    /// Follows the same flushing rules as `add_metric`.
+    pub fn add_defined(&mut self, metric: DefinedMetric, value: f64) {
+        self.add_metric(metric.name, metric.unit, value);
+    }
+
+    /// Returns the catalog of the metrics this object can emit, see [`crate::catalog`].
+    /// A name listed more than once keeps its first entry.
+    #[must_use]
+    pub fn catalog(&self) -> MetricCatalog {
+        let mut metrics: Vec<CatalogMetric> = Vec::new();
+        let entries = self
+            .catalog
+            .iter()
+            .cloned()
+            .chain(self.schema.catalog_metrics())
+            .chain(self.derived.iter().map(|derived| derived.catalog_metric()))
+            .chain(self.library_catalog());
+        for entry in entries {
+            if !metrics.iter().any(|metric| metric.name == entry.name) {
+                metrics.push(entry);
+            }
+        }
+        MetricCatalog { metrics }
+    }
+
+    /// Returns the metrics the library can record for this object: the ones it always records,
+    /// the ones of the statistics enabled on the builder, and the ones of its recording methods,
+    /// e.g. `InitDuration`.
+    fn library_catalog(&self) -> Vec<CatalogMetric> {
+        let mut metrics = self_metrics::catalog_metrics(self.overhead.is_some());
+        metrics.extend(cold_start::catalog_metrics());
+        #[cfg(feature = "lambda")]
+        metrics.extend(crate::invocation::catalog_metrics());
+        metrics.extend(deadline::catalog_metrics());
+        if self.container_counters {
+            metrics.extend(counters::catalog_metrics());
+        }
+        if self.process_stats.is_some() {
+            metrics.extend(process_stats::catalog_metrics());
+        }
+        #[cfg(feature = "alloc-metrics")]
+        if self.allocation_stats.is_some() {
+            metrics.extend(crate::alloc_stats::catalog_metrics());
+        }
+        #[cfg(feature = "tokio-metrics")]
+        if let Some(stats) = &self.tokio_stats {
+            metrics.extend(stats.catalog_metrics());
+        }
+        metrics
+    }
+}
+
+/// Converts an ASCII identifier to lowercase at compile time.
+#[doc(hidden)]
+#[must_use]
+pub const fn __lowercase<const N: usize>(name: &str) -> [u8; N] {
+    let bytes = name.as_bytes();
+    let mut lowercase = [0; N];
+    let mut i = 0;
+    while i < N {
+        lowercase[i] = bytes[i].to_ascii_lowercase();
+        i += 1;
+    }
+    lowercase
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sink::NullSink;
+    use crate::{MetricUnit, Metrics};
+
+    crate::define_metrics! {
+        ORDERS_PROCESSED: Count,
+        LATENCY = "checkout.latency": ms,
+    }
+
+    #[test]
+    fn should_record_defined_metrics() {
+        let mut metrics = Metrics::builder("test").sink(NullSink).build().unwrap();
+
+        metrics.add_defined(ORDERS_PROCESSED, 2.0);
+        metrics.add_defined(LATENCY, 120.0);
+
+        assert_eq!(ORDERS_PROCESSED.name(), "orders_processed");
+        assert_eq!(metrics.value_of("orders_processed"), Some(2.0));
+        assert_eq!(
+            metrics.unit_of("checkout.latency"),
+            Some(MetricUnit::Milliseconds)
+        );
+    }
+
+    #[test]
+    fn should_list_everything_the_object_can_emit() {
+        let mut schema = crate::MetricSchema::new();
+        let orders = schema.register("orders", MetricUnit::Count).unwrap();
+        schema.describe(orders, "Orders accepted.");
+        schema.set_owner(orders, "checkout");
+        schema.register("refunds", MetricUnit::Count).unwrap();
+        let catalog = super::MetricCatalog::from_json(
+            r#"{"metrics": [{ "name": "refunds", "unit": "Count", "owner": "payments" }]}"#,
+        )
+        .unwrap();
+        let mut metrics = Metrics::builder("test")
+            .sink(NullSink)
+            .schema(&schema)
+            .metric_catalog(&catalog)
+            .build()
+            .unwrap();
+        metrics.add_success_rate("success_rate", "ok", "failed");
+
+        let catalog = metrics.catalog();
+
+        let names = catalog
+            .metrics
+            .iter()
+            .map(|metric| metric.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names[..6],
+            [
+                "refunds",
+                "orders",
+                "success_rate",
+                crate::self_metrics::DROPPED_METRIC,
+                crate::self_metrics::ERRORS_METRIC,
+                crate::self_metrics::SINK_DROPPED_METRIC,
+            ]
+        );
+        assert!(names.contains(&crate::cold_start::INIT_DURATION_METRIC));
+        assert!(!names.contains(&crate::process_stats::OPEN_FDS_METRIC));
+        assert_eq!(catalog.metrics[0].owner.as_deref(), Some("payments"));
+        assert_eq!(catalog.metrics[1].owner.as_deref(), Some("checkout"));
+        assert_eq!(
+            catalog.metrics[1].description.as_deref(),
+            Some("Orders accepted.")
+        );
+        assert_eq!(catalog.metrics[2].unit, MetricUnit::Percent);
+    }
+
+    #[test]
+    fn should_list_statistics_enabled_on_builder() {
+        crate::static_counter!("catalog_listed").increment(1);
+        let metrics = Metrics::builder("test")
+            .sink(NullSink)
+            .process_stats(true)
+            .container_counters(true)
+            .build()
+            .unwrap();
+
+        let catalog = metrics.catalog();
+
+        let names = catalog
+            .metrics
+            .iter()
+            .map(|metric| metric.name.as_str())
+            .collect::<Vec<_>>();
+        for name in [
+            crate::process_stats::CPU_USER_TIME_METRIC,
+            crate::process_stats::OPEN_FDS_METRIC,
+            crate::deadline::NEAR_TIMEOUT_METRIC,
+            "catalog_listed_container_total",
+        ] {
+            assert!(names.contains(&name), "{name}");
+        }
+    }
+}
@@ -0,0 +1,451 @@
+//! Generation of the metric definitions from a catalog file, in a build script.
+//!
+//! The catalog lists the metrics with their units, descriptions, owners and dimensions:
+//!
+//! ```json
+//! {
+//!     "metrics": [
+//!         { "name": "orders_processed", "unit": "Count", "description": "Orders accepted by the checkout.", "owner": "checkout-team" },
+//!         { "name": "checkout.latency", "unit": "Milliseconds", "dimensions": ["payment_method"] }
+//!     ]
+//! }
+//! ```
+//!
+//! or in YAML:
+//!
+//! ```yaml
+//! metrics:
+//!   - name: orders_processed
+//!     unit: Count
+//!     description: Orders accepted by the checkout.
+//!     owner: checkout-team
+//!   - name: checkout.latency
+//!     unit: Milliseconds
+//!     dimensions: [payment_method]
+//! ```
+//!
+//! `generate` writes `metrics.rs` into `OUT_DIR`, with a
+//! [`DefinedMetric`](crate::catalog::DefinedMetric) constant and a `record_<name>` function per
+//! metric, taking the values of its dimensions, `metric_catalog.json`, the validated
+//! catalog with the units as `CloudWatch` strings, e.g. to build dashboards from, and
+//! `metric_catalog.md`, the same catalog as a Markdown table for reviews:
+//!
+//! ```no_run
+//! // in `main` of build.rs, with lambda_helpers_metrics in the build-dependencies
+//! // and its `codegen` feature enabled
+//! # #[cfg(feature = "codegen")]
+//! lambda_helpers_metrics::catalog::codegen::generate("metrics.json").unwrap();
+//! ```
+//!
+//! ```ignore
+//! // src/main.rs
+//! include!(concat!(env!("OUT_DIR"), "/metrics.rs"));
+//!
+//! record_checkout_latency(&mut metrics, 120.0, "card");
+//! metrics.add_defined(ORDERS_PROCESSED, 1.0);
+//! ```
+//!
+//! Catalogs can also be written in YAML, with the same keys, see [`MetricCatalog::from_yaml`].
+//! Catalogs in TOML require the `toml` feature.
+//!
+//! The types and the loading of catalogs are always available, e.g. for
+//! [`MetricsBuilder::metric_catalog`](crate::MetricsBuilder::metric_catalog), while the
+//! generation of the code, meant for build scripts, requires the `codegen` feature.
+use std::fmt::Write as _;
+use std::path::Path;
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::{MetricUnit, MetricsError};
+
+mod yaml;
+
+/// Keywords which can't name the parameters of the generated functions.
+const KEYWORDS: [&str; 38] = [
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "self", "Self", "static", "struct", "super", "trait", "true", "type",
+    "unsafe", "use", "where", "while",
+];
+
+/// The metrics of a catalog file, see [`crate::catalog::codegen`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MetricCatalog {
+    pub metrics: Vec<CatalogMetric>,
+}
+
+/// A metric of a [`MetricCatalog`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CatalogMetric {
+    pub name: String,
+    #[serde(deserialize_with = "parse_unit")]
+    pub unit: MetricUnit,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// The team or person to ask about the metric.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dimensions: Vec<String>,
+}
+
+fn parse_unit<'de, D: Deserializer<'de>>(deserializer: D) -> Result<MetricUnit, D::Error> {
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(serde::de::Error::custom)
+}
+
+impl CatalogMetric {
+    /// Describes a metric recorded by the library.
+    pub(crate) fn library(name: impl Into<String>, unit: MetricUnit, description: &str) -> Self {
+        Self {
+            name: name.into(),
+            unit,
+            description: Some(description.to_string()),
+            owner: None,
+            dimensions: Vec::new(),
+        }
+    }
+}
+
+fn invalid(err: impl std::fmt::Display) -> MetricsError {
+    MetricsError::Configuration(err.to_string())
+}
+
+impl MetricCatalog {
+    /// Loads and validates a catalog from a JSON string.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the JSON is malformed or the catalog is invalid, see
+    /// [`MetricCatalog::validate`]
+    pub fn from_json(json: &str) -> Result<Self, MetricsError> {
+        let catalog: Self = serde_json::from_str(json).map_err(invalid)?;
+        catalog.validate()?;
+        Ok(catalog)
+    }
+
+    /// Loads and validates a catalog from a TOML string.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the TOML is malformed or the catalog is invalid, see
+    /// [`MetricCatalog::validate`]
+    #[cfg(feature = "toml")]
+    pub fn from_toml(toml: &str) -> Result<Self, MetricsError> {
+        let catalog: Self = toml::from_str(toml).map_err(invalid)?;
+        catalog.validate()?;
+        Ok(catalog)
+    }
+
+    /// Loads and validates a catalog from a YAML string, in the subset of YAML made of block
+    /// mappings and sequences, flow sequences like `[a, b]`, quoted, plain and block scalars,
+    /// and comments. Anchors, tags and flow mappings are rejected.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the YAML is malformed or unsupported, or the catalog is invalid, see
+    /// [`MetricCatalog::validate`]
+    pub fn from_yaml(yaml: &str) -> Result<Self, MetricsError> {
+        let catalog: Self = serde_json::from_value(yaml::parse(yaml)?).map_err(invalid)?;
+        catalog.validate()?;
+        Ok(catalog)
+    }
+
+    /// Loads and validates a catalog from a file, TOML if the extension is `.toml`, YAML if it
+    /// is `.yaml` or `.yml`, JSON otherwise.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file can't be read or the catalog is invalid
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, MetricsError> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)?;
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        if matches!(extension, Some("yaml" | "yml")) {
+            return Self::from_yaml(&content);
+        }
+        if extension == Some("toml") {
+            #[cfg(feature = "toml")]
+            return Self::from_toml(&content);
+            #[cfg(not(feature = "toml"))]
+            return Err(MetricsError::Configuration(
+                "TOML catalogs require the `toml` feature".to_string(),
+            ));
+        }
+        Self::from_json(&content)
+    }
+
+    /// Checks that the names are not empty and that neither two metrics nor two dimensions of
+    /// a metric map to the same identifier in the generated code.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` describing the first problem found
+    pub fn validate(&self) -> Result<(), MetricsError> {
+        let mut identifiers = Vec::new();
+        for metric in &self.metrics {
+            let identifier = identifier(&metric.name);
+            if identifier.is_empty() {
+                return Err(MetricsError::InvalidMetric(
+                    "metric name is empty".to_string(),
+                ));
+            }
+            if identifiers.contains(&identifier) {
+                return Err(MetricsError::InvalidMetric(format!(
+                    "metric {} is declared twice or clashes with another name",
+                    metric.name
+                )));
+            }
+            identifiers.push(identifier);
+            let mut parameters = Vec::new();
+            for dimension in &metric.dimensions {
+                let parameter = identifier_of_dimension(dimension);
+                if dimension.is_empty() || parameters.contains(&parameter) {
+                    return Err(MetricsError::InvalidDimension(format!(
+                        "dimension {dimension:?} of {} is empty or declared twice",
+                        metric.name
+                    )));
+                }
+                parameters.push(parameter);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the Rust source of the metric definitions, see [`crate::catalog::codegen`].
+    /// Available with the `codegen` feature.
+    #[cfg(feature = "codegen")]
+    #[must_use]
+    pub fn to_rust(&self) -> String {
+        let mut source = String::from(
+            "// Generated from the metric catalog by lambda_helpers_metrics, do not edit.\n",
+        );
+        for metric in &self.metrics {
+            let identifier = identifier(&metric.name);
+            let unit = format!("::lambda_helpers_metrics::MetricUnit::{:?}", metric.unit);
+            let mut doc = String::new();
+            for line in metric.description.iter().flat_map(|text| text.lines()) {
+                let _ = writeln!(doc, "/// {line}");
+            }
+            let _ = write!(
+                source,
+                "\n{doc}pub const {}: ::lambda_helpers_metrics::catalog::DefinedMetric =\n    \
+                 ::lambda_helpers_metrics::catalog::DefinedMetric::new({:?}, {unit});\n",
+                identifier.to_ascii_uppercase(),
+                metric.name,
+            );
+            let parameters: String = metric
+                .dimensions
+                .iter()
+                .map(|dimension| format!(", {}: &str", identifier_of_dimension(dimension)))
+                .collect();
+            let dimensions: Vec<String> = metric
+                .dimensions
+                .iter()
+                .map(|dimension| format!("({dimension:?}, {})", identifier_of_dimension(dimension)))
+                .collect();
+            let _ = write!(
+                source,
+                "\n{doc}pub fn record_{identifier}(\n    \
+                 metrics: &mut ::lambda_helpers_metrics::Metrics,\n    \
+                 value: f64{parameters},\n) {{\n    \
+                 metrics.add_metric_with_dimensions({:?}, {unit}, value, &[{}]);\n}}\n",
+                metric.name,
+                dimensions.join(", "),
+            );
+        }
+        source
+    }
+
+    /// Returns the catalog as JSON, with the units as `CloudWatch` strings.
+    #[must_use]
+    pub fn to_json(&self) -> String {
+        // UNWRAP: the catalog only holds strings and units
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    /// Returns the catalog as a Markdown table, one row per metric.
+    #[must_use]
+    pub fn to_markdown(&self) -> String {
+        let mut markdown = String::from(
+            "| Metric | Unit | Dimensions | Owner | Description |\n\
+             | --- | --- | --- | --- | --- |\n",
+        );
+        for metric in &self.metrics {
+            let dimensions = metric
+                .dimensions
+                .iter()
+                .map(|dimension| format!("`{}`", markdown_cell(dimension)))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let _ = writeln!(
+                markdown,
+                "| `{}` | {} | {dimensions} | {} | {} |",
+                markdown_cell(&metric.name),
+                metric.unit.as_str(),
+                markdown_cell(metric.owner.as_deref().unwrap_or_default()),
+                markdown_cell(metric.description.as_deref().unwrap_or_default()),
+            );
+        }
+        markdown
+    }
+}
+
+/// Escapes the characters which would break a row of a Markdown table.
+fn markdown_cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+/// Generates `metrics.rs`, `metric_catalog.json` and `metric_catalog.md` into `OUT_DIR` from the catalog file,
+/// see [`crate::catalog::codegen`]. Meant to be called from a build script, which is rerun
+/// when the catalog changes. Available with the `codegen` feature.
+///
+/// # Errors
+///
+/// Will return `Err` if `OUT_DIR` is not set, or the catalog can't be read or is invalid
+#[cfg(feature = "codegen")]
+pub fn generate(catalog: impl AsRef<Path>) -> Result<(), MetricsError> {
+    let out_dir = std::env::var("OUT_DIR")
+        .map_err(|_| invalid("OUT_DIR is not set, generate is meant for build scripts"))?;
+    println!("cargo:rerun-if-changed={}", catalog.as_ref().display());
+    generate_into(catalog, out_dir)
+}
+
+/// Generates `metrics.rs`, `metric_catalog.json` and `metric_catalog.md` into the directory,
+/// see [`generate`]. Available with the `codegen` feature.
+///
+/// # Errors
+///
+/// Will return `Err` if the catalog can't be read or is invalid, or the files can't be written
+#[cfg(feature = "codegen")]
+pub fn generate_into(
+    catalog: impl AsRef<Path>,
+    out_dir: impl AsRef<Path>,
+) -> Result<(), MetricsError> {
+    let catalog = MetricCatalog::from_file(catalog)?;
+    let out_dir = out_dir.as_ref();
+    std::fs::write(out_dir.join("metrics.rs"), catalog.to_rust())?;
+    std::fs::write(out_dir.join("metric_catalog.json"), catalog.to_json())?;
+    std::fs::write(out_dir.join("metric_catalog.md"), catalog.to_markdown())?;
+    Ok(())
+}
+
+/// Maps a name to a lowercase identifier, e.g. `checkout.latency` to `checkout_latency`.
+fn identifier(name: &str) -> String {
+    let identifier: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if identifier.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{identifier}")
+    } else {
+        identifier
+    }
+}
+
+fn identifier_of_dimension(dimension: &str) -> String {
+    let identifier = identifier(dimension);
+    if KEYWORDS.contains(&identifier.as_str()) {
+        format!("{identifier}_")
+    } else {
+        identifier
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CATALOG: &str = r#"{"metrics": [
+        { "name": "orders_processed", "unit": "Count", "description": "Orders accepted.", "owner": "checkout" },
+        { "name": "checkout.latency", "unit": "milliseconds", "dimensions": ["type"] }
+    ]}"#;
+
+    #[cfg(feature = "codegen")]
+    #[test]
+    fn should_generate_definitions_and_functions() {
+        let source = MetricCatalog::from_json(CATALOG).unwrap().to_rust();
+
+        assert!(source.contains(
+            "/// Orders accepted.\npub const ORDERS_PROCESSED: \
+             ::lambda_helpers_metrics::catalog::DefinedMetric =\n    \
+             ::lambda_helpers_metrics::catalog::DefinedMetric::new(\"orders_processed\", \
+             ::lambda_helpers_metrics::MetricUnit::Count);"
+        ));
+        assert!(source.contains("pub fn record_checkout_latency(\n"));
+        assert!(source.contains("    value: f64, type_: &str,\n"));
+        assert!(source.contains(
+            "metrics.add_metric_with_dimensions(\"checkout.latency\", \
+             ::lambda_helpers_metrics::MetricUnit::Milliseconds, value, &[(\"type\", type_)]);"
+        ));
+    }
+
+    #[test]
+    fn should_load_yaml_catalogs() {
+        let yaml = "metrics:\n\
+                    - name: orders_processed\n  unit: Count\n  owner: checkout\n\
+                    - name: checkout.latency\n  unit: milliseconds\n  dimensions: [type]\n";
+
+        assert_eq!(
+            MetricCatalog::from_yaml(yaml).unwrap().metrics,
+            MetricCatalog::from_json(CATALOG)
+                .unwrap()
+                .metrics
+                .into_iter()
+                .map(|metric| CatalogMetric {
+                    description: None,
+                    ..metric
+                })
+                .collect::<Vec<_>>()
+        );
+        assert!(MetricCatalog::from_yaml("metrics:\n- name: a\n  unit: Weeks\n").is_err());
+    }
+
+    #[test]
+    fn should_reject_invalid_catalogs() {
+        for catalog in [
+            r#"{"metrics": [{ "name": "latency", "unit": "Weeks" }]}"#,
+            r#"{"metrics": [{ "name": "a.b", "unit": "Count" }, { "name": "a_b", "unit": "Count" }]}"#,
+            r#"{"metrics": [{ "name": "a", "unit": "Count", "dimensions": ["x", "x"] }]}"#,
+        ] {
+            assert!(MetricCatalog::from_json(catalog).is_err(), "{catalog}");
+        }
+    }
+
+    #[test]
+    fn should_write_machine_readable_catalog() {
+        let catalog = MetricCatalog::from_json(CATALOG).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&catalog.to_json()).unwrap();
+
+        assert_eq!(json["metrics"][1]["unit"], "Milliseconds");
+        assert!(json["metrics"][0].get("dimensions").is_none());
+    }
+
+    #[test]
+    fn should_write_markdown_catalog() {
+        let mut catalog = MetricCatalog::from_json(CATALOG).unwrap();
+        catalog.metrics[1].description = Some("p99 | p50".to_string());
+
+        let markdown = catalog.to_markdown();
+
+        let rows = markdown.lines().collect::<Vec<_>>();
+        assert_eq!(rows.len(), 4);
+        assert_eq!(
+            rows[2],
+            "| `orders_processed` | Count |  | checkout | Orders accepted. |"
+        );
+        assert_eq!(
+            rows[3],
+            "| `checkout.latency` | Milliseconds | `type` |  | p99 \\| p50 |"
+        );
+    }
+}
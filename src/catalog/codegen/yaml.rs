@@ -0,0 +1,381 @@
+//! Parser of the subset of YAML used by catalog files.
+//!
+//! Supported are block mappings and sequences, flow sequences of scalars (`[a, "b"]`), plain,
+//! single- and double-quoted scalars, literal (`|`) and folded (`>`) block scalars, and
+//! comments. Plain scalars are strings, except `~` and `null`. Flow mappings, anchors, tags
+//! and multiple documents are rejected.
+use serde_json::{Map, Value};
+
+use super::invalid;
+use crate::MetricsError;
+
+/// Parses the document into a JSON value, to be deserialized like a JSON catalog.
+pub(super) fn parse(yaml: &str) -> Result<Value, MetricsError> {
+    let mut parser = Parser {
+        lines: yaml.lines().collect(),
+        position: 0,
+    };
+    if parser.peek()?.is_some_and(|line| line.text == "---") {
+        parser.position += 1;
+    }
+    let value = parser.node(0)?;
+    match parser.peek()? {
+        Some(line) => Err(line.error("unexpected content")),
+        None => Ok(value),
+    }
+}
+
+/// A significant line, without its indentation and comment.
+#[derive(Debug, Clone, Copy)]
+struct Line<'a> {
+    number: usize,
+    indent: usize,
+    text: &'a str,
+}
+
+impl Line<'_> {
+    fn error(&self, message: &str) -> MetricsError {
+        invalid(format!("invalid YAML at line {}: {message}", self.number))
+    }
+}
+
+struct Parser<'a> {
+    lines: Vec<&'a str>,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    /// Returns the next significant line, skipping blank and comment lines, without consuming it.
+    fn peek(&mut self) -> Result<Option<Line<'a>>, MetricsError> {
+        while let Some(raw) = self.lines.get(self.position) {
+            let line = Line {
+                number: self.position + 1,
+                indent: raw.len() - raw.trim_start_matches(' ').len(),
+                text: strip_comment(raw).trim(),
+            };
+            if !line.text.is_empty() {
+                if raw[line.indent..].starts_with('\t') {
+                    return Err(line.error("tabs can't indent"));
+                }
+                return Ok(Some(line));
+            }
+            self.position += 1;
+        }
+        Ok(None)
+    }
+
+    /// Parses the node whose lines are indented by at least `min_indent`, `null` if there are none.
+    fn node(&mut self, min_indent: usize) -> Result<Value, MetricsError> {
+        match self.peek()? {
+            Some(line) if line.indent >= min_indent => {
+                if is_sequence_item(line.text) {
+                    self.sequence(line.indent)
+                } else {
+                    self.mapping(line.indent, None)
+                }
+            }
+            _ => Ok(Value::Null),
+        }
+    }
+
+    fn sequence(&mut self, indent: usize) -> Result<Value, MetricsError> {
+        let mut items = Vec::new();
+        while let Some(line) = self.peek()? {
+            if line.indent < indent || !is_sequence_item(line.text) {
+                break;
+            }
+            if line.indent > indent {
+                return Err(line.error("unexpected indentation"));
+            }
+            self.position += 1;
+            let rest = line.text[1..].trim_start();
+            let item = if rest.is_empty() {
+                self.node(indent + 1)?
+            } else if is_mapping_entry(rest) {
+                let column = indent + line.text.len() - rest.len();
+                self.mapping(column, Some(Line { text: rest, ..line }))?
+            } else {
+                self.scalar(rest, line)?
+            };
+            items.push(item);
+        }
+        Ok(Value::Array(items))
+    }
+
+    /// Parses the mapping at the indentation, whose first entry may be on the line of a
+    /// sequence item.
+    fn mapping(&mut self, indent: usize, first: Option<Line<'a>>) -> Result<Value, MetricsError> {
+        let mut entries = Map::new();
+        let mut next = first;
+        loop {
+            let line = match next.take() {
+                Some(line) => line,
+                None => match self.peek()? {
+                    Some(line) if line.indent >= indent && !is_sequence_item(line.text) => {
+                        if line.indent > indent {
+                            return Err(line.error("unexpected indentation"));
+                        }
+                        self.position += 1;
+                        line
+                    }
+                    _ => break,
+                },
+            };
+            let Some(colon) = find_colon(line.text) else {
+                return Err(line.error("expected `key: value`"));
+            };
+            let key = match self.scalar(line.text[..colon].trim(), line)? {
+                Value::String(key) => key,
+                _ => return Err(line.error("keys must be strings")),
+            };
+            let rest = line.text[colon + 1..].trim_start();
+            let value = if !rest.is_empty() {
+                self.scalar(rest, Line { indent, ..line })?
+            } else {
+                match self.peek()? {
+                    // a sequence may be indented like the key it belongs to
+                    Some(next) if next.indent == indent && is_sequence_item(next.text) => {
+                        self.sequence(indent)?
+                    }
+                    _ => self.node(indent + 1)?,
+                }
+            };
+            if entries.insert(key.clone(), value).is_some() {
+                return Err(line.error(&format!("duplicate key {key:?}")));
+            }
+        }
+        Ok(Value::Object(entries))
+    }
+
+    /// Parses the scalar or flow sequence starting the line, reading the following lines of a
+    /// block scalar.
+    fn scalar(&mut self, text: &str, line: Line<'_>) -> Result<Value, MetricsError> {
+        if let Some(header) = text.strip_prefix(['|', '>']) {
+            let strip = match header {
+                "" => false,
+                "-" => true,
+                _ => return Err(line.error("unsupported block scalar header")),
+            };
+            return Ok(Value::String(self.block_scalar(
+                text.starts_with('>'),
+                strip,
+                line.indent,
+            )));
+        }
+        if let Some(items) = text.strip_prefix('[') {
+            let Some(items) = items.strip_suffix(']') else {
+                return Err(line.error("unterminated flow sequence"));
+            };
+            if items.trim().is_empty() {
+                return Ok(Value::Array(Vec::new()));
+            }
+            return split_flow_items(items)
+                .into_iter()
+                .map(|item| match item.trim() {
+                    item if item.starts_with(['[', '{']) => {
+                        Err(line.error("nested flow collections are not supported"))
+                    }
+                    item => quoted_or_plain(item).ok_or_else(|| line.error("malformed scalar")),
+                })
+                .collect::<Result<_, _>>()
+                .map(Value::Array);
+        }
+        if text.starts_with(['{', '&', '*', '!', '%', '@', '`']) {
+            return Err(line.error("unsupported YAML syntax"));
+        }
+        quoted_or_plain(text).ok_or_else(|| line.error("malformed scalar"))
+    }
+
+    /// Reads the lines of a block scalar, more indented than its parent.
+    fn block_scalar(&mut self, folded: bool, strip: bool, parent_indent: usize) -> String {
+        let mut block: Vec<&str> = Vec::new();
+        let mut indent = None;
+        while let Some(raw) = self.lines.get(self.position) {
+            if raw.trim().is_empty() {
+                block.push("");
+            } else {
+                let line_indent = raw.len() - raw.trim_start_matches(' ').len();
+                let block_indent = *indent.get_or_insert(line_indent);
+                if line_indent <= parent_indent || line_indent < block_indent {
+                    break;
+                }
+                block.push(&raw[block_indent..]);
+            }
+            self.position += 1;
+        }
+        // trailing blank lines belong to what follows
+        while block.last() == Some(&"") {
+            block.pop();
+            self.position -= 1;
+        }
+        let mut text = if folded {
+            let mut text = String::new();
+            for line in &block {
+                if line.is_empty() {
+                    text.push('\n');
+                } else {
+                    if !text.is_empty() && !text.ends_with('\n') {
+                        text.push(' ');
+                    }
+                    text.push_str(line);
+                }
+            }
+            text
+        } else {
+            block.join("\n")
+        };
+        if !strip && !text.is_empty() {
+            text.push('\n');
+        }
+        text
+    }
+}
+
+fn is_sequence_item(text: &str) -> bool {
+    text == "-" || text.starts_with("- ")
+}
+
+fn is_mapping_entry(text: &str) -> bool {
+    !text.starts_with('[') && find_colon(text).is_some()
+}
+
+/// Returns the position of the colon separating a key from its value, outside quotes.
+fn find_colon(text: &str) -> Option<usize> {
+    let bytes = text.as_bytes();
+    outside_quotes(text).find(|&index| {
+        bytes[index] == b':' && bytes.get(index + 1).is_none_or(|&next| next == b' ')
+    })
+}
+
+/// Removes the comment ending the line, a `#` outside quotes at the start or after a space.
+fn strip_comment(line: &str) -> &str {
+    let bytes = line.as_bytes();
+    match outside_quotes(line)
+        .find(|&index| bytes[index] == b'#' && (index == 0 || bytes[index - 1] == b' '))
+    {
+        Some(index) => &line[..index],
+        None => line,
+    }
+}
+
+/// Splits the items of a flow sequence on the commas outside quotes.
+fn split_flow_items(items: &str) -> Vec<&str> {
+    let bytes = items.as_bytes();
+    let mut parts = Vec::new();
+    let mut start = 0;
+    for index in outside_quotes(items).filter(|&index| bytes[index] == b',') {
+        parts.push(&items[start..index]);
+        start = index + 1;
+    }
+    parts.push(&items[start..]);
+    parts
+}
+
+/// Returns the byte positions of the text which are outside quoted scalars.
+fn outside_quotes(text: &str) -> impl Iterator<Item = usize> + '_ {
+    let mut quote = None;
+    let mut escaped = false;
+    text.bytes().enumerate().filter_map(move |(index, byte)| {
+        match quote {
+            Some(b'"') if escaped => escaped = false,
+            Some(b'"') if byte == b'\\' => escaped = true,
+            Some(open) if byte == open => quote = None,
+            Some(_) => {}
+            None if byte == b'"' || byte == b'\'' => quote = Some(byte),
+            None => return Some(index),
+        }
+        None
+    })
+}
+
+/// Parses a quoted scalar, or a plain one as a string, `None` if a quoted scalar is malformed.
+fn quoted_or_plain(text: &str) -> Option<Value> {
+    if text.starts_with('"') {
+        return serde_json::from_str::<String>(text).ok().map(Value::String);
+    }
+    if let Some(quoted) = text.strip_prefix('\'') {
+        let inner = quoted.strip_suffix('\'')?;
+        if inner.replace("''", "").contains('\'') {
+            return None;
+        }
+        return Some(Value::String(inner.replace("''", "'")));
+    }
+    match text {
+        "~" | "null" => Some(Value::Null),
+        _ => Some(Value::String(text.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn should_parse_catalog_subset() {
+        let yaml = "\
+---
+# metrics of the checkout
+metrics:
+- name: orders_processed  # accepted orders
+  unit: Count
+  description: >
+    Orders accepted
+    by the checkout.
+  owner: 'checkout-team'
+- name: \"checkout.latency\"
+  unit: Milliseconds
+  dimensions: [payment_method, \"region, zone\"]
+  description: |-
+    p99: #1
+    p50
+-
+  name: refunds
+  unit: Count
+  dimensions:
+    - reason
+  owner: ~
+";
+
+        assert_eq!(
+            parse(yaml).unwrap(),
+            json!({"metrics": [
+                {
+                    "name": "orders_processed",
+                    "unit": "Count",
+                    "description": "Orders accepted by the checkout.\n",
+                    "owner": "checkout-team",
+                },
+                {
+                    "name": "checkout.latency",
+                    "unit": "Milliseconds",
+                    "dimensions": ["payment_method", "region, zone"],
+                    "description": "p99: #1\np50",
+                },
+                {
+                    "name": "refunds",
+                    "unit": "Count",
+                    "dimensions": ["reason"],
+                    "owner": null,
+                },
+            ]})
+        );
+    }
+
+    #[test]
+    fn should_reject_unsupported_or_malformed_yaml() {
+        for yaml in [
+            "metrics: {name: a}",
+            "metrics:\n  - name: &a x",
+            "metrics:\n\t- name: a",
+            "metrics: [a, b",
+            "name: a\nname: b",
+            "name: a\n  unit: Count",
+            "just text",
+            "a: 1\n---\nb: 2",
+        ] {
+            assert!(parse(yaml).is_err(), "{yaml}");
+        }
+    }
+}
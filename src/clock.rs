@@ -0,0 +1,123 @@
+//! Time access of the library, replaceable in tests.
+//!
+//! Payload timestamps are taken from the wall clock of a [`Clock`], and timers
+//! ([`Timer::time`](crate::handle::Timer::time)) measure durations with its monotonic clock.
+//! A [`ManualClock`] only moves when advanced, so tests can pin timestamps, compare full
+//! payloads, or simulate long invocations without sleeping:
+//!
+//! ```
+//! use std::time::Duration;
+//!
+//! use chrono::{TimeZone, Utc};
+//! use lambda_helpers_metrics::clock::ManualClock;
+//! use lambda_helpers_metrics::Metrics;
+//!
+//! let clock = ManualClock::new(Utc.timestamp_millis_opt(1_700_000_000_000).unwrap());
+//! let mut metrics = Metrics::builder("custom_lambdas")
+//!     .clock(clock.clone())
+//!     .build()
+//!     .unwrap();
+//!
+//! metrics.timer("query_ms").time(|| clock.advance(Duration::from_secs(3)));
+//! assert_eq!(metrics.value_of("query_ms"), Some(3000.0));
+//! ```
+use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+
+/// Source of wall-clock and monotonic time.
+pub trait Clock: fmt::Debug + Send + Sync {
+    /// Returns the wall-clock time, used for payload timestamps.
+    fn now(&self) -> DateTime<Utc>;
+
+    /// Returns the monotonic time, used to measure durations.
+    fn instant(&self) -> Instant;
+}
+
+/// The system clock, the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock which only moves when advanced. Clones share the time.
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    start: DateTime<Utc>,
+    start_instant: Instant,
+    elapsed: Arc<Mutex<Duration>>,
+}
+
+impl ManualClock {
+    /// Creates a clock stopped at `start`.
+    #[must_use]
+    pub fn new(start: DateTime<Utc>) -> Self {
+        Self {
+            start,
+            start_instant: Instant::now(),
+            elapsed: Arc::default(),
+        }
+    }
+
+    /// Moves the clock forward.
+    pub fn advance(&self, by: Duration) {
+        *self.elapsed.lock().unwrap_or_else(PoisonError::into_inner) += by;
+    }
+
+    fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.start + self.elapsed()
+    }
+
+    fn instant(&self) -> Instant {
+        self.start_instant + self.elapsed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::sink::RecordingSink;
+    use crate::{MetricUnit, Metrics};
+
+    #[test]
+    fn should_take_timestamps_from_clock() {
+        let sink = RecordingSink::default();
+        let clock = ManualClock::new(Utc.timestamp_millis_opt(1_700_000_000_000).unwrap());
+        let mut metrics = Metrics::builder("test")
+            .clock(clock.clone())
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+
+        for _ in 0..2 {
+            metrics.add_metric("orders", MetricUnit::Count, 1.0);
+            metrics.flush_metrics();
+        }
+        clock.advance(Duration::from_millis(1500));
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+        metrics.flush_metrics();
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads[0]["_aws"]["Timestamp"], 1_700_000_000_000_i64);
+        assert_eq!(payloads[0], payloads[1]);
+        assert_eq!(payloads[2]["_aws"]["Timestamp"], 1_700_000_001_500_i64);
+    }
+}
@@ -0,0 +1,244 @@
+//! Cold start measurements.
+//!
+//! The process start is captured in a crate-level static by [`mark_process_start`], which
+//! should be the first statement of `main`; without it, the init duration is not recorded:
+//!
+//! ```
+//! use lambda_helpers_metrics::{cold_start, Metrics};
+//!
+//! cold_start::mark_process_start();
+//! // expensive initialization: SDK clients, configuration, caches...
+//!
+//! // in the handler
+//! let mut metrics = Metrics::new("custom_lambdas", "service", "dummy_service");
+//! metrics.record_init_duration();
+//! ```
+//!
+//! Each execution environment (sandbox) also gets a random ID, generated once per process.
+//! Attached as the `sandbox_id` property, it allows analyzing warm reuse and container-specific
+//! errors in `CloudWatch Logs Insights` without creating a metric series per sandbox.
+//!
+//! With SnapStart, the process statics are captured in the snapshot, so the first invocation
+//! after a restore is not a true cold start. Restores are detected from
+//! `AWS_LAMBDA_INITIALIZATION_TYPE`, or marked by [`mark_restored`]. With the `lambda`
+//! feature, [`RestoreHook`] calls it from the runtime restore hooks:
+//!
+//! ```ignore
+//! Runtime::new(service_fn(handler))
+//!     .register_snapstart_resource(Arc::new(cold_start::RestoreHook))
+//!     .run()
+//!     .await
+//! ```
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LazyLock, OnceLock};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::catalog::codegen::CatalogMetric;
+use crate::{MetricUnit, Metrics};
+
+pub const INIT_DURATION_METRIC: &str = "InitDuration";
+pub const SANDBOX_ID_PROPERTY: &str = "sandbox_id";
+pub const INITIALIZATION_TYPE_VAR: &str = "AWS_LAMBDA_INITIALIZATION_TYPE";
+pub const SNAP_START_INITIALIZATION: &str = "snap-start";
+
+static PROCESS_START: OnceLock<Instant> = OnceLock::new();
+static RESTORE_START: OnceLock<Instant> = OnceLock::new();
+static INIT_DURATION_RECORDED: AtomicBool = AtomicBool::new(false);
+#[cfg_attr(not(feature = "lambda"), allow(dead_code))]
+static COLD_START_TAKEN: AtomicBool = AtomicBool::new(false);
+static SANDBOX_ID: LazyLock<String> = LazyLock::new(generate_sandbox_id);
+
+/// Captures the process start, if not captured yet.
+pub fn mark_process_start() {
+    let _ = PROCESS_START.set(Instant::now());
+}
+
+/// Marks the process as restored from a SnapStart snapshot, now. The first invocation
+/// is then reported as a restore start, and `InitDuration` is measured from the restore.
+pub fn mark_restored() {
+    let _ = RESTORE_START.set(Instant::now());
+    COLD_START_TAKEN.store(false, Ordering::Relaxed);
+    INIT_DURATION_RECORDED.store(false, Ordering::Relaxed);
+}
+
+/// How the execution environment was started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartKind {
+    /// The function was initialized in this process.
+    Cold,
+    /// The process was restored from a SnapStart snapshot.
+    Restore,
+}
+
+impl StartKind {
+    /// Detects the start kind from [`mark_restored`] and the process environment variables.
+    #[must_use]
+    pub fn detect() -> Self {
+        Self::detect_from(RESTORE_START.get().is_some(), |key| std::env::var(key).ok())
+    }
+
+    pub(crate) fn detect_from(restored: bool, var: impl Fn(&str) -> Option<String>) -> Self {
+        if restored || var(INITIALIZATION_TYPE_VAR).as_deref() == Some(SNAP_START_INITIALIZATION) {
+            StartKind::Restore
+        } else {
+            StartKind::Cold
+        }
+    }
+}
+
+/// Returns the start kind the first time it is called in the process (or after a restore),
+/// i.e. during the first invocation, and `None` afterwards.
+#[cfg_attr(not(feature = "lambda"), allow(dead_code))]
+pub(crate) fn take_start() -> Option<StartKind> {
+    (!COLD_START_TAKEN.swap(true, Ordering::Relaxed)).then(StartKind::detect)
+}
+
+/// Calls [`mark_restored`] after the runtime restores the snapshot.
+///
+/// Available with the `lambda` feature.
+#[cfg(feature = "lambda")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RestoreHook;
+
+#[cfg(feature = "lambda")]
+impl lambda_runtime::SnapStartResource for RestoreHook {
+    fn after_restore(&self) -> lambda_runtime::BoxFuture<'_, Result<(), lambda_runtime::Error>> {
+        mark_restored();
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// The metric recorded by [`Metrics::record_init_duration`], see [`Metrics::catalog`].
+pub(crate) fn catalog_metrics() -> Vec<CatalogMetric> {
+    vec![CatalogMetric::library(
+        INIT_DURATION_METRIC,
+        MetricUnit::Milliseconds,
+        "Time between the process start, or the SnapStart restore, and the first invocation.",
+    )]
+}
+
+/// Returns the ID of the current execution environment, a random UUID (v4) stable for the
+/// lifetime of the process.
+#[must_use]
+pub fn sandbox_id() -> &'static str {
+    &SANDBOX_ID
+}
+
+fn generate_sandbox_id() -> String {
+    // RandomState is seeded randomly per process, mixing in the pid and time on top of it
+    // keeps sandboxes restored from the same snapshot apart
+    let random = |salt: u64| {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(salt);
+        hasher.write_u32(crate::platform::process_id());
+        if let Ok(now) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+            hasher.write_u128(now.as_nanos());
+        }
+        hasher.finish()
+    };
+    let high = random(0);
+    let low = random(1);
+    format!(
+        "{:08x}-{:04x}-4{:03x}-{:04x}-{:012x}",
+        high >> 32,
+        (high >> 16) & 0xffff,
+        high & 0x0fff,
+        (low >> 48) & 0x3fff | 0x8000,
+        low & 0xffff_ffff_ffff
+    )
+}
+
+/// Returns the elapsed time since `start` the first time it is called with the given flag.
+fn elapsed_once(recorded: &AtomicBool, start: Instant) -> Option<Duration> {
+    if recorded.swap(true, Ordering::Relaxed) {
+        None
+    } else {
+        Some(start.elapsed())
+    }
+}
+
+impl Metrics {
+    /// Records `InitDuration`, the time between the process start marked with
+    /// [`mark_process_start`] and now, in milliseconds. Only the first call in the process
+    /// records the metric, so it can be called at the start of every invocation. Returns `true`
+    /// if the metric was recorded; nothing is recorded if the process start wasn't marked.
+    ///
+    /// After a SnapStart restore, the duration is measured from [`mark_restored`], and nothing is
+    /// recorded if it wasn't called: the process start predates the snapshot.
+    pub fn record_init_duration(&mut self) -> bool {
+        let start = match (
+            RESTORE_START.get(),
+            StartKind::detect(),
+            PROCESS_START.get(),
+        ) {
+            (Some(restore), _, _) => *restore,
+            (None, StartKind::Cold, Some(process)) => *process,
+            (None, _, _) => return false,
+        };
+        match elapsed_once(&INIT_DURATION_RECORDED, start) {
+            Some(duration) => {
+                self.add(INIT_DURATION_METRIC, duration);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Adds the ID of the current execution environment as the `sandbox_id` property.
+    pub fn add_sandbox_id(&mut self) {
+        self.add_property(SANDBOX_ID_PROPERTY, sandbox_id());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_return_elapsed_only_once() {
+        let recorded = AtomicBool::new(false);
+        let start = Instant::now();
+
+        assert!(elapsed_once(&recorded, start).is_some());
+        assert!(elapsed_once(&recorded, start).is_none());
+    }
+
+    #[test]
+    fn should_detect_restore_start() {
+        use crate::environment::tests::vars;
+
+        assert_eq!(StartKind::detect_from(false, vars(&[])), StartKind::Cold);
+        assert_eq!(
+            StartKind::detect_from(false, vars(&[(INITIALIZATION_TYPE_VAR, "on-demand")])),
+            StartKind::Cold
+        );
+        assert_eq!(
+            StartKind::detect_from(false, vars(&[(INITIALIZATION_TYPE_VAR, "snap-start")])),
+            StartKind::Restore
+        );
+        assert_eq!(StartKind::detect_from(true, vars(&[])), StartKind::Restore);
+    }
+
+    #[test]
+    fn should_keep_sandbox_id_stable() {
+        let id = sandbox_id();
+
+        assert_eq!(id, sandbox_id());
+        assert_eq!(id.len(), 36);
+        assert_eq!(&id[14..15], "4");
+        assert!(id
+            .chars()
+            .all(|c| c == '-' || c.is_ascii_hexdigit() && !c.is_ascii_uppercase()));
+        assert_ne!(generate_sandbox_id(), generate_sandbox_id());
+    }
+
+    #[test]
+    fn should_add_sandbox_id_property() {
+        let mut metrics = Metrics::new("test", "service", "dummy_service");
+        metrics.add_sandbox_id();
+
+        assert_eq!(metrics.property(SANDBOX_ID_PROPERTY), Some(sandbox_id()));
+    }
+}
@@ -0,0 +1,140 @@
+//! An object-safe trait for recording metrics, for dependency injection.
+//!
+//! Application code can depend on `&mut dyn MetricsCollector` instead of [`Metrics`], and be
+//! unit-tested with a [`RecordingCollector`] or a [`NoopCollector`] without producing output:
+//!
+//! ```
+//! use lambda_helpers_metrics::collector::{MetricsCollector, RecordingCollector};
+//! use lambda_helpers_metrics::MetricUnit;
+//!
+//! fn place_order(metrics: &mut dyn MetricsCollector) {
+//!     metrics.add_dimension("payment", "card");
+//!     metrics.add_metric("orders", MetricUnit::Count, 1.0);
+//! }
+//!
+//! let mut collector = RecordingCollector::default();
+//! place_order(&mut collector);
+//! assert_eq!(collector.metrics, vec![("orders".to_string(), MetricUnit::Count, 1.0)]);
+//! assert_eq!(collector.dimensions, vec![("payment".to_string(), "card".to_string())]);
+//! ```
+use crate::{mode, MetricUnit, Metrics};
+
+/// Records metrics, dimensions and properties, implemented by [`Metrics`].
+pub trait MetricsCollector: Send {
+    /// Adds a metric, see [`Metrics::add_metric`].
+    fn add_metric(&mut self, name: &str, unit: MetricUnit, value: f64);
+
+    /// Adds a dimension, see [`Metrics::try_add_dimension`]. Errors are printed to stderr.
+    fn add_dimension(&mut self, key: &str, value: &str);
+
+    /// Adds a property, see [`Metrics::add_property`].
+    fn add_property(&mut self, key: &str, value: &str);
+
+    /// Flushes the buffered metrics, see [`Metrics::flush_metrics`].
+    fn flush(&mut self);
+}
+
+impl MetricsCollector for Metrics {
+    fn add_metric(&mut self, name: &str, unit: MetricUnit, value: f64) {
+        Metrics::add_metric(self, name, unit, value);
+    }
+
+    fn add_dimension(&mut self, key: &str, value: &str) {
+        if let Err(err) = self.try_add_dimension(key, value) {
+            mode::report(&err);
+        }
+    }
+
+    fn add_property(&mut self, key: &str, value: &str) {
+        Metrics::add_property(self, key, value);
+    }
+
+    fn flush(&mut self) {
+        self.flush_metrics();
+    }
+}
+
+/// Discards everything.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopCollector;
+
+impl MetricsCollector for NoopCollector {
+    fn add_metric(&mut self, _name: &str, _unit: MetricUnit, _value: f64) {}
+
+    fn add_dimension(&mut self, _key: &str, _value: &str) {}
+
+    fn add_property(&mut self, _key: &str, _value: &str) {}
+
+    fn flush(&mut self) {}
+}
+
+/// Keeps every call in order, for assertions in unit tests.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RecordingCollector {
+    pub metrics: Vec<(String, MetricUnit, f64)>,
+    pub dimensions: Vec<(String, String)>,
+    pub properties: Vec<(String, String)>,
+    /// Number of calls to [`MetricsCollector::flush`].
+    pub flushes: usize,
+}
+
+impl RecordingCollector {
+    /// Returns the values recorded for the metric.
+    #[must_use]
+    pub fn values_of(&self, name: &str) -> Vec<f64> {
+        self.metrics
+            .iter()
+            .filter(|(metric, _, _)| metric == name)
+            .map(|(_, _, value)| *value)
+            .collect()
+    }
+}
+
+impl MetricsCollector for RecordingCollector {
+    fn add_metric(&mut self, name: &str, unit: MetricUnit, value: f64) {
+        self.metrics.push((name.to_string(), unit, value));
+    }
+
+    fn add_dimension(&mut self, key: &str, value: &str) {
+        self.dimensions.push((key.to_string(), value.to_string()));
+    }
+
+    fn add_property(&mut self, key: &str, value: &str) {
+        self.properties.push((key.to_string(), value.to_string()));
+    }
+
+    fn flush(&mut self) {
+        self.flushes += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::RecordingSink;
+
+    fn checkout(metrics: &mut dyn MetricsCollector) {
+        metrics.add_dimension("payment", "card");
+        metrics.add_property("order_id", "o-1");
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+        metrics.flush();
+    }
+
+    #[test]
+    fn should_record_through_trait_object() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test").sink(sink.clone()).build().unwrap();
+        let mut recording = RecordingCollector::default();
+
+        checkout(&mut metrics);
+        checkout(&mut recording);
+        checkout(&mut NoopCollector);
+
+        let payload = &sink.payloads()[0];
+        assert_eq!(payload["payment"], "card");
+        assert_eq!(payload["order_id"], "o-1");
+        assert_eq!(payload["orders"], 1.0);
+        assert_eq!(recording.values_of("orders"), vec![1.0]);
+        assert_eq!(recording.flushes, 1);
+    }
+}
@@ -0,0 +1,40 @@
+//! Gzip compression of the batches of the batch-oriented sinks.
+use std::io::Write;
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use crate::MetricsError;
+
+/// The highest gzip level, higher levels are clamped to it.
+const MAX_LEVEL: u32 = 9;
+
+/// Compresses the bytes with gzip at the level, from 0 (none) to 9 (best).
+pub(crate) fn gzip(bytes: &[u8], level: u32) -> Result<Vec<u8>, MetricsError> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::new(level.min(MAX_LEVEL)));
+    encoder.write_all(bytes)?;
+    Ok(encoder.finish()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+
+    use super::*;
+
+    #[test]
+    fn should_round_trip_payloads() {
+        let payload = r#"{"_aws":{"Timestamp":0},"orders":1}"#.repeat(100);
+
+        let compressed = gzip(payload.as_bytes(), 6).unwrap();
+        let mut decompressed = String::new();
+        GzDecoder::new(compressed.as_slice())
+            .read_to_string(&mut decompressed)
+            .unwrap();
+
+        assert!(compressed.len() < payload.len() / 10);
+        assert_eq!(decompressed, payload);
+    }
+}
@@ -0,0 +1,438 @@
+//! Configuration loaded from environment variables and/or a file.
+//!
+//! ```no_run
+//! use lambda_helpers_metrics::config::MetricsConfig;
+//! use lambda_helpers_metrics::Metrics;
+//!
+//! let config = MetricsConfig::from_file("metrics.json")
+//!     .unwrap()
+//!     .with_env();
+//! let metrics = Metrics::builder("custom_lambdas")
+//!     .config(&config)
+//!     .build()
+//!     .unwrap();
+//! ```
+//!
+//! A JSON file looks like this (TOML files with the same keys require the `toml` feature):
+//!
+//! ```json
+//! {
+//!     "namespace": "custom_lambdas",
+//!     "dimensions": { "service": "dummy_service" },
+//!     "storage_resolution": 60,
+//!     "sink": "stdout",
+//!     "dimension_overflow": "drop_oldest",
+//!     "dimension_length": "truncate",
+//!     "metric_overflow": "split_at_flush",
+//!     "timestamp_age": "clamp",
+//!     "limits": { "max_metrics": 50, "max_dimensions": 10 },
+//!     "output_schema": "v1",
+//!     "log_group_name": "dummy_service-metrics",
+//!     "stage": { "env_var": "APP_STAGE", "mapping": { "prod-us-east-1": "prod" } },
+//!     "exclude_metrics": ["debug_*"],
+//!     "event_dimensions": { "order_type": "$.detail.orderType" },
+//!     "disabled": false
+//! }
+//! ```
+//!
+//! Environment variables override the values from the file:
+//! `AWS_EMF_NAMESPACE`, `AWS_EMF_DIMENSIONS` (`key=value,key=value`), `AWS_EMF_STORAGE_RESOLUTION`,
+//! `AWS_EMF_SINK`, `AWS_EMF_DIMENSION_OVERFLOW`, `AWS_EMF_METRIC_OVERFLOW`, `AWS_EMF_DIMENSION_PRESET`,
+//! `AWS_EMF_LOG_GROUP_NAME`, `AWS_EMF_LOG_STREAM_NAME`, `AWS_EMF_INCLUDE_METRICS` and
+//! `AWS_EMF_EXCLUDE_METRICS` (comma-separated patterns) and `AWS_EMF_DISABLE_METRIC_EXTRACTION`.
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::de::IntoDeserializer;
+use serde::Deserialize;
+
+use crate::sink::AgentSink;
+use crate::stage::StageDimension;
+use crate::{
+    DimensionLengthPolicy, DimensionOverflowPolicy, Limits, MetricOverflowPolicy, MetricsError,
+    OutputSchema, TimestampAgePolicy,
+};
+
+/// Where payloads are written, see [`crate::sink`].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum SinkConfig {
+    /// `stdout`: one line per payload on stdout.
+    Stdout,
+    /// `pretty`: pretty-printed JSON on stdout.
+    Pretty,
+    /// `agent` for the endpoint from `AWS_EMF_AGENT_ENDPOINT`,
+    /// or an explicit `tcp://` / `udp://` endpoint.
+    Agent(Option<String>),
+}
+
+impl FromStr for SinkConfig {
+    type Err = MetricsError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "stdout" => Ok(SinkConfig::Stdout),
+            "pretty" => Ok(SinkConfig::Pretty),
+            "agent" => Ok(SinkConfig::Agent(None)),
+            _ => {
+                AgentSink::new(value.trim())?;
+                Ok(SinkConfig::Agent(Some(value.trim().to_string())))
+            }
+        }
+    }
+}
+
+impl TryFrom<String> for SinkConfig {
+    type Error = MetricsError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// Configuration applied with [`crate::MetricsBuilder::config`]. Missing values keep the
+/// defaults of the builder.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// Overrides the namespace passed to the builder.
+    pub namespace: Option<String>,
+    /// Dimensions added to every payload.
+    pub dimensions: BTreeMap<String, String>,
+    /// 1 for high resolution metrics, 60 for standard resolution.
+    pub storage_resolution: Option<u64>,
+    pub sink: Option<SinkConfig>,
+    pub dimension_overflow: Option<DimensionOverflowPolicy>,
+    pub dimension_length: Option<DimensionLengthPolicy>,
+    pub metric_overflow: Option<MetricOverflowPolicy>,
+    pub timestamp_age: Option<TimestampAgePolicy>,
+    /// Lowered limits of the payloads, e.g. `{ "max_metrics": 50 }`, see [`crate::Limits`].
+    pub limits: Option<Limits>,
+    /// Pinned version of the payload layout, e.g. `"v1"`, see [`crate::OutputSchema`].
+    pub output_schema: Option<OutputSchema>,
+    /// `LogGroupName` of the payloads, used by the `CloudWatch` agent.
+    pub log_group_name: Option<String>,
+    /// `LogStreamName` of the payloads, used by the `CloudWatch` agent.
+    pub log_stream_name: Option<String>,
+    /// Discards all payloads, e.g. in tests or to switch metrics off without a redeploy.
+    pub disabled: bool,
+    /// Named sets of the dimensions used to aggregate the metrics, see [`crate::preset`].
+    pub dimension_presets: BTreeMap<String, Vec<String>>,
+    /// The selected one of the `dimension_presets`.
+    pub dimension_preset: Option<String>,
+    /// Stage dimension read from an environment variable, see [`crate::stage`].
+    pub stage: Option<StageDimension>,
+    /// Dimensions selected from the event by name, e.g. `"order_type": "$.detail.orderType"`,
+    /// see [`crate::selectors`].
+    pub event_dimensions: BTreeMap<String, String>,
+    /// Properties selected from the event by name, see [`crate::selectors`].
+    pub event_properties: BTreeMap<String, String>,
+    /// Glob patterns of the metric names recorded, see [`crate::MetricsBuilder::include_metrics`].
+    pub include_metrics: Vec<String>,
+    /// Glob patterns of the metric names skipped, see [`crate::MetricsBuilder::exclude_metrics`].
+    pub exclude_metrics: Vec<String>,
+}
+
+fn invalid(err: impl std::fmt::Display) -> MetricsError {
+    MetricsError::Configuration(err.to_string())
+}
+
+impl MetricsConfig {
+    /// Loads the configuration from environment variables only.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if a variable holds an invalid value
+    pub fn from_env() -> Result<Self, MetricsError> {
+        Self::default().apply_env(|key| std::env::var(key).ok())
+    }
+
+    /// Loads the configuration from a JSON string.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the JSON is malformed or holds unknown keys or invalid values
+    pub fn from_json(json: &str) -> Result<Self, MetricsError> {
+        serde_json::from_str(json).map_err(invalid)
+    }
+
+    /// Loads the configuration from a TOML string.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the TOML is malformed or holds unknown keys or invalid values
+    #[cfg(feature = "toml")]
+    pub fn from_toml(toml: &str) -> Result<Self, MetricsError> {
+        toml::from_str(toml).map_err(invalid)
+    }
+
+    /// Loads the configuration from a file, TOML if the extension is `.toml`, JSON otherwise.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file can't be read or parsed, or if it is a TOML file
+    /// and the `toml` feature is disabled
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, MetricsError> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)?;
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "toml")
+        {
+            #[cfg(feature = "toml")]
+            return Self::from_toml(&content);
+            #[cfg(not(feature = "toml"))]
+            return Err(MetricsError::Configuration(
+                "TOML configuration requires the `toml` feature".to_string(),
+            ));
+        }
+        Self::from_json(&content)
+    }
+
+    /// Overrides values with the ones set in environment variables.
+    /// Invalid values are printed to stderr and ignored.
+    #[must_use]
+    pub fn with_env(self) -> Self {
+        let fallback = self.clone();
+        self.apply_env(|key| std::env::var(key).ok())
+            .unwrap_or_else(|err| {
+                eprintln!("{err}");
+                fallback
+            })
+    }
+
+    pub(crate) fn apply_env(
+        mut self,
+        var: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, MetricsError> {
+        let var = |key: &str| var(key).filter(|value| !value.trim().is_empty());
+        if let Some(namespace) = var("AWS_EMF_NAMESPACE") {
+            self.namespace = Some(namespace);
+        }
+        if let Some(dimensions) = var("AWS_EMF_DIMENSIONS") {
+            for pair in dimensions.split(',') {
+                let (key, value) = pair
+                    .split_once('=')
+                    .ok_or_else(|| invalid(format!("malformed dimension: {pair}")))?;
+                self.dimensions
+                    .insert(key.trim().to_string(), value.trim().to_string());
+            }
+        }
+        if let Some(resolution) = var("AWS_EMF_STORAGE_RESOLUTION") {
+            self.storage_resolution = Some(resolution.trim().parse().map_err(invalid)?);
+        }
+        if let Some(sink) = var("AWS_EMF_SINK") {
+            self.sink = Some(sink.parse()?);
+        }
+        if let Some(policy) = var("AWS_EMF_DIMENSION_OVERFLOW") {
+            self.dimension_overflow = Some(parse_policy(&policy)?);
+        }
+        if let Some(policy) = var("AWS_EMF_METRIC_OVERFLOW") {
+            self.metric_overflow = Some(parse_policy(&policy)?);
+        }
+        if let Some(name) = var("AWS_EMF_DIMENSION_PRESET") {
+            self.dimension_preset = Some(name.trim().to_string());
+        }
+        if let Some(name) = var("AWS_EMF_LOG_GROUP_NAME") {
+            self.log_group_name = Some(name);
+        }
+        if let Some(name) = var("AWS_EMF_LOG_STREAM_NAME") {
+            self.log_stream_name = Some(name);
+        }
+        if let Some(patterns) = var("AWS_EMF_INCLUDE_METRICS") {
+            self.include_metrics = split_patterns(&patterns);
+        }
+        if let Some(patterns) = var("AWS_EMF_EXCLUDE_METRICS") {
+            self.exclude_metrics = split_patterns(&patterns);
+        }
+        if let Some(disabled) = var("AWS_EMF_DISABLE_METRIC_EXTRACTION") {
+            self.disabled = disabled.trim().eq_ignore_ascii_case("true");
+        }
+        Ok(self)
+    }
+}
+
+fn split_patterns(patterns: &str) -> Vec<String> {
+    patterns
+        .split(',')
+        .map(str::trim)
+        .filter(|pattern| !pattern.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn parse_policy<'de, T: Deserialize<'de>>(value: &'de str) -> Result<T, MetricsError> {
+    T::deserialize(value.trim().into_deserializer())
+        .map_err(|err: serde::de::value::Error| invalid(err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::environment::tests::vars;
+    use crate::sink::RecordingSink;
+    use crate::{MetricUnit, Metrics};
+
+    #[test]
+    fn should_load_config_from_json() {
+        let config = MetricsConfig::from_json(
+            r#"{
+                "namespace": "custom_lambdas",
+                "dimensions": { "service": "dummy_service" },
+                "sink": "udp://127.0.0.1:25888",
+                "metric_overflow": "split_at_flush",
+                "stage": { "env_var": "APP_STAGE", "mapping": { "prod-us-east-1": "prod" } }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.namespace.as_deref(), Some("custom_lambdas"));
+        assert_eq!(config.dimensions["service"], "dummy_service");
+        assert_eq!(
+            config.sink,
+            Some(SinkConfig::Agent(Some("udp://127.0.0.1:25888".to_string())))
+        );
+        assert_eq!(
+            config.metric_overflow,
+            Some(MetricOverflowPolicy::SplitAtFlush)
+        );
+        assert_eq!(
+            config.stage,
+            Some(StageDimension::new("APP_STAGE").map("prod-us-east-1", "prod"))
+        );
+        assert!(!config.disabled);
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn should_load_config_from_toml() {
+        let config = MetricsConfig::from_toml(
+            r#"
+            namespace = "custom_lambdas"
+            disabled = true
+
+            [dimensions]
+            service = "dummy_service"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.namespace.as_deref(), Some("custom_lambdas"));
+        assert_eq!(config.dimensions["service"], "dummy_service");
+        assert!(config.disabled);
+    }
+
+    #[test]
+    fn should_reject_invalid_json_config() {
+        assert!(MetricsConfig::from_json(r#"{ "sink": "kafka" }"#).is_err());
+        assert!(MetricsConfig::from_json(r#"{ "namespaces": "typo" }"#).is_err());
+    }
+
+    #[test]
+    fn should_override_config_from_env() {
+        let config = MetricsConfig::from_json(r#"{ "namespace": "from_file" }"#)
+            .unwrap()
+            .apply_env(vars(&[
+                ("AWS_EMF_NAMESPACE", "from_env"),
+                ("AWS_EMF_DIMENSIONS", "service=orders, stage=prod"),
+                ("AWS_EMF_DIMENSION_OVERFLOW", "demote_to_property"),
+                ("AWS_EMF_LOG_GROUP_NAME", "orders-metrics"),
+                ("AWS_EMF_EXCLUDE_METRICS", "debug_*, trace_*"),
+                ("AWS_EMF_DISABLE_METRIC_EXTRACTION", "true"),
+            ]))
+            .unwrap();
+
+        assert_eq!(config.namespace.as_deref(), Some("from_env"));
+        assert_eq!(config.dimensions["stage"], "prod");
+        assert_eq!(
+            config.dimension_overflow,
+            Some(DimensionOverflowPolicy::DemoteToProperty)
+        );
+        assert_eq!(config.log_group_name.as_deref(), Some("orders-metrics"));
+        assert_eq!(config.exclude_metrics, ["debug_*", "trace_*"]);
+        assert!(config.disabled);
+        assert!(MetricsConfig::default()
+            .apply_env(vars(&[("AWS_EMF_METRIC_OVERFLOW", "sometimes")]))
+            .is_err());
+    }
+
+    #[test]
+    fn should_apply_config_to_builder() {
+        let sink = RecordingSink::default();
+        let config = MetricsConfig {
+            namespace: Some("configured".to_string()),
+            dimensions: BTreeMap::from([("service".to_string(), "orders".to_string())]),
+            storage_resolution: Some(1),
+            ..MetricsConfig::default()
+        };
+        let mut metrics = Metrics::builder("default")
+            .sink(sink.clone())
+            .config(&config)
+            .build()
+            .unwrap();
+
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+        metrics.flush_metrics();
+
+        let payload = &sink.payloads()[0];
+        let directive = &payload["_aws"]["CloudWatchMetrics"][0];
+        assert_eq!(directive["Namespace"], "configured");
+        assert_eq!(directive["Metrics"][0]["StorageResolution"], 1);
+        assert_eq!(payload["service"], "orders");
+    }
+
+    #[test]
+    fn should_reject_invalid_storage_resolution() {
+        let config = MetricsConfig {
+            storage_resolution: Some(30),
+            ..MetricsConfig::default()
+        };
+
+        assert!(Metrics::builder("test").config(&config).build().is_err());
+    }
+
+    #[test]
+    fn should_apply_limits_from_config() {
+        let config = MetricsConfig::from_json(r#"{ "limits": { "max_metrics": 2 } }"#).unwrap();
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .sink(sink.clone())
+            .config(&config)
+            .build()
+            .unwrap();
+
+        for name in ["a", "b", "c"] {
+            metrics.add_metric(name, MetricUnit::Count, 1.0);
+        }
+        metrics.flush_metrics();
+
+        assert_eq!(metrics.metrics_remaining(), 2);
+        assert_eq!(metrics.dimensions_remaining(), crate::MAX_DIMENSIONS);
+        assert_eq!(sink.payloads().len(), 2);
+        let too_high = MetricsConfig::from_json(r#"{ "limits": { "max_dimensions": 31 } }"#);
+        assert!(Metrics::builder("test")
+            .config(&too_high.unwrap())
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn should_stay_disabled_when_sink_is_set_after_config() {
+        let config = MetricsConfig {
+            disabled: true,
+            ..MetricsConfig::default()
+        };
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .config(&config)
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+        metrics.flush_metrics();
+
+        assert!(sink.payloads().is_empty());
+    }
+}
@@ -0,0 +1,174 @@
+//! Ambient metrics context.
+//!
+//! Integrations which cannot receive `&mut Metrics` explicitly (e.g. the AWS SDK interceptor)
+//! record into the current context of the process. Lambda processes one invocation at a time,
+//! so the handler sets the context at the start of the invocation and flushes it at the end.
+//!
+//! Where invocations run concurrently, e.g. with response streaming, [`scope`] sets the
+//! context of a single future instead: it takes precedence over the context of the process
+//! while the future is polled.
+//!
+//! ```
+//! use lambda_helpers_metrics::context::{self, MetricsHandle};
+//! use lambda_helpers_metrics::{MetricUnit, Metrics};
+//!
+//! let handle = MetricsHandle::new(Metrics::new("custom_lambdas", "service", "dummy_service"));
+//! context::set_current(handle.clone());
+//!
+//! // somewhere deep in the call stack
+//! context::with_current(|metrics| metrics.add_metric("cache_miss", MetricUnit::Count, 1.0));
+//!
+//! context::take_current();
+//! handle.flush();
+//! ```
+use std::cell::RefCell;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::task::{Context, Poll};
+
+use crate::Metrics;
+
+/// A cheaply cloneable, thread-safe handle to a `Metrics` object.
+#[derive(Debug, Clone)]
+pub struct MetricsHandle(Arc<Mutex<Metrics>>);
+
+impl MetricsHandle {
+    /// Wraps the metrics into a shared handle.
+    #[must_use]
+    pub fn new(metrics: Metrics) -> Self {
+        Self(Arc::new(Mutex::new(metrics)))
+    }
+
+    /// Runs the closure with exclusive access to the metrics.
+    pub fn with<R>(&self, f: impl FnOnce(&mut Metrics) -> R) -> R {
+        f(&mut self.lock())
+    }
+
+    /// Flushes the buffered metrics, see [`Metrics::flush_metrics`].
+    pub fn flush(&self) {
+        self.lock().flush_metrics();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Metrics> {
+        // a panic while recording must not disable metrics for the rest of the process
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl From<Metrics> for MetricsHandle {
+    fn from(metrics: Metrics) -> Self {
+        Self::new(metrics)
+    }
+}
+
+static CURRENT: Mutex<Option<MetricsHandle>> = Mutex::new(None);
+
+thread_local! {
+    /// The context of the future being polled on this thread, see [`scope`].
+    static SCOPED: RefCell<Option<MetricsHandle>> = const { RefCell::new(None) };
+}
+
+fn current_slot() -> MutexGuard<'static, Option<MetricsHandle>> {
+    CURRENT.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Sets the current context, returning the previous one.
+pub fn set_current(handle: MetricsHandle) -> Option<MetricsHandle> {
+    current_slot().replace(handle)
+}
+
+/// Removes the current context and returns it.
+pub fn take_current() -> Option<MetricsHandle> {
+    current_slot().take()
+}
+
+/// Returns the current context, if set: the context of the enclosing [`scope`], or else the
+/// context of the process.
+#[must_use]
+pub fn current() -> Option<MetricsHandle> {
+    SCOPED
+        .with(|scoped| scoped.borrow().clone())
+        .or_else(|| current_slot().clone())
+}
+
+/// Runs the future with `handle` as the current context, without changing the context of the
+/// process or of other futures.
+pub fn scope<F: Future>(handle: MetricsHandle, future: F) -> Scoped<F> {
+    Scoped {
+        handle,
+        future: Box::pin(future),
+    }
+}
+
+/// A future running with its own context, see [`scope`].
+#[derive(Debug)]
+pub struct Scoped<F> {
+    handle: MetricsHandle,
+    future: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for Scoped<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        /// Restores the enclosing context, also if the future panics.
+        struct Restore(Option<MetricsHandle>);
+
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                let previous = self.0.take();
+                SCOPED.with(|scoped| *scoped.borrow_mut() = previous);
+            }
+        }
+
+        let previous = SCOPED.with(|scoped| scoped.replace(Some(self.handle.clone())));
+        let _restore = Restore(previous);
+        self.future.as_mut().poll(cx)
+    }
+}
+
+/// Runs the closure with the current context. Returns `None` if no context is set.
+pub fn with_current<R>(f: impl FnOnce(&mut Metrics) -> R) -> Option<R> {
+    current().map(|handle| handle.with(f))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_keep_scoped_contexts_apart_across_polls() {
+        /// Records on each poll, pending on the first one.
+        async fn record(name: &'static str) {
+            let mut yielded = false;
+            std::future::poll_fn(|cx| {
+                with_current(|metrics| metrics.increment(name, 1.0));
+                if yielded {
+                    Poll::Ready(())
+                } else {
+                    yielded = true;
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                }
+            })
+            .await;
+        }
+
+        let first = MetricsHandle::new(Metrics::builder("test").build().unwrap());
+        let second = MetricsHandle::new(Metrics::builder("test").build().unwrap());
+        let mut first_call = std::pin::pin!(scope(first.clone(), record("first")));
+        let mut second_call = std::pin::pin!(scope(second.clone(), record("second")));
+        let mut cx = Context::from_waker(std::task::Waker::noop());
+
+        assert!(first_call.as_mut().poll(&mut cx).is_pending());
+        assert!(second_call.as_mut().poll(&mut cx).is_pending());
+        assert!(first_call.as_mut().poll(&mut cx).is_ready());
+        assert!(second_call.as_mut().poll(&mut cx).is_ready());
+
+        assert_eq!(first.with(|metrics| metrics.value_of("first")), Some(2.0));
+        assert_eq!(first.with(|metrics| metrics.value_of("second")), None);
+        assert_eq!(second.with(|metrics| metrics.value_of("second")), Some(2.0));
+        assert!(SCOPED.with(|scoped| scoped.borrow().is_none()));
+    }
+}
@@ -0,0 +1,322 @@
+//! Log lines for `CloudWatch` Contributor Insights, written alongside the EMF payloads.
+//!
+//! Keys with a high cardinality, e.g. tenants or endpoints, are too costly as dimensions, but
+//! Contributor Insights can rank them from structured logs. Rules declared on the builder fix the
+//! key fields, and [`Metrics::add_contribution`] adds to the count of a combination of keys:
+//!
+//! ```
+//! use lambda_helpers_metrics::contributors::ContributorRule;
+//! use lambda_helpers_metrics::Metrics;
+//!
+//! let mut metrics = Metrics::builder("custom_lambdas")
+//!     .contributor_rule(ContributorRule::new("top_tenants", &["tenant_id", "endpoint"]))
+//!     .build()
+//!     .unwrap();
+//! metrics.add_contribution("top_tenants", &["tenant-a", "/orders"], 1.0);
+//! ```
+//!
+//! Contributions of the same keys are summed until the flush, which writes one JSON line per
+//! combination after the EMF payloads, to the sinks writing to logs, e.g. `StdoutSink` or
+//! `AgentSink` (see [`MetricsSink::emit_log_line`](crate::sink::MetricsSink::emit_log_line)):
+//!
+//! ```json
+//! {"contributor_count":1.0,"contributor_rule":"top_tenants","endpoint":"/orders","tenant_id":"tenant-a"}
+//! ```
+//!
+//! [`ContributorRule::definition`] returns the matching rule body for `PutInsightRule`,
+//! summing `contributor_count` over the key fields of the lines of the rule.
+use std::collections::HashMap;
+
+use serde_json::{json, Map, Value};
+
+#[cfg(feature = "async")]
+use crate::sink::AsyncMetricsSink;
+use crate::{Metrics, MetricsError};
+
+/// Field holding the name of the rule in each line.
+pub const RULE_FIELD: &str = "contributor_rule";
+/// Field holding the summed count in each line.
+pub const COUNT_FIELD: &str = "contributor_count";
+/// The maximum number of keys of a Contributor Insights rule.
+pub const MAX_KEYS: usize = 4;
+
+/// A Contributor Insights rule, see [`crate::contributors`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContributorRule {
+    name: String,
+    keys: Vec<String>,
+}
+
+impl ContributorRule {
+    /// Creates a rule ranking the combinations of the `keys` fields, validated in `build`.
+    #[must_use]
+    pub fn new(name: &str, keys: &[&str]) -> Self {
+        Self {
+            name: name.to_string(),
+            keys: keys.iter().map(ToString::to_string).collect(),
+        }
+    }
+
+    /// Returns the name of the rule.
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the key fields of the rule.
+    #[must_use]
+    pub fn keys(&self) -> &[String] {
+        &self.keys
+    }
+
+    /// Returns the rule body for `PutInsightRule`, matching the lines of this rule in
+    /// `log_groups`.
+    #[must_use]
+    pub fn definition(&self, log_groups: &[&str]) -> Value {
+        json!({
+            "Schema": { "Name": "CloudWatchLogRule", "Version": 1 },
+            "LogGroupNames": log_groups,
+            "LogFormat": "JSON",
+            "Contribution": {
+                "Keys": self.keys.iter().map(|key| format!("$.{key}")).collect::<Vec<_>>(),
+                "ValueOf": format!("$.{COUNT_FIELD}"),
+                "Filters": [{ "Match": format!("$.{RULE_FIELD}"), "In": [self.name] }],
+            },
+            "AggregateOn": "Sum",
+        })
+    }
+
+    fn validate(&self) -> Result<(), MetricsError> {
+        let invalid = |reason: &str| {
+            MetricsError::Configuration(format!("invalid contributor rule {}: {reason}", self.name))
+        };
+        if self.name.is_empty() {
+            return Err(invalid("empty name"));
+        }
+        if self.keys.is_empty() || self.keys.len() > MAX_KEYS {
+            return Err(invalid(&format!("expected 1 to {MAX_KEYS} keys")));
+        }
+        for (index, key) in self.keys.iter().enumerate() {
+            if key.is_empty() || key == RULE_FIELD || key == COUNT_FIELD {
+                return Err(invalid(&format!("invalid key: {key}")));
+            }
+            if self.keys[..index].contains(key) {
+                return Err(invalid(&format!("duplicate key: {key}")));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Validates the rules set on the builder.
+pub(crate) fn validate_rules(rules: &[ContributorRule]) -> Result<(), MetricsError> {
+    for (index, rule) in rules.iter().enumerate() {
+        rule.validate()?;
+        if rules[..index].iter().any(|other| other.name == rule.name) {
+            return Err(MetricsError::Configuration(format!(
+                "duplicate contributor rule: {}",
+                rule.name
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// The counts of the combinations of keys of each rule, by index of the rule, until the flush.
+pub(crate) type Contributions = HashMap<(usize, Vec<String>), f64>;
+
+impl Metrics {
+    /// Adds `count` to the combination of `keys` of the rule, given in the order of the rule,
+    /// see [`crate::contributors`]. Contributions to an unknown rule, with a different number
+    /// of keys or a non-finite count, are dropped and reported like invalid metrics.
+    pub fn add_contribution(&mut self, rule: &str, keys: &[&str], count: f64) {
+        let Some(index) = self
+            .contributor_rules
+            .iter()
+            .position(|candidate| candidate.name == rule)
+        else {
+            self.record_dropped(&MetricsError::InvalidMetric(format!(
+                "unknown contributor rule: {rule}"
+            )));
+            return;
+        };
+        let expected = self.contributor_rules[index].keys.len();
+        if keys.len() != expected {
+            self.record_dropped(&MetricsError::InvalidMetric(format!(
+                "contributor rule {rule} expects {expected} keys, got {}",
+                keys.len()
+            )));
+            return;
+        }
+        if !count.is_finite() {
+            self.record_dropped(&MetricsError::InvalidMetric(format!(
+                "non-finite contribution to {rule}: {count}"
+            )));
+            return;
+        }
+        let keys = keys.iter().map(ToString::to_string).collect();
+        *self.contributions.entry((index, keys)).or_default() += count;
+    }
+
+    /// Returns a JSON line for each buffered contribution, ordered by rule and keys.
+    fn contribution_lines(&self) -> Vec<String> {
+        let mut contributions = self.contributions.iter().collect::<Vec<_>>();
+        contributions.sort_unstable_by_key(|(key, _)| *key);
+        contributions
+            .into_iter()
+            .map(|((rule, keys), count)| {
+                let rule = &self.contributor_rules[*rule];
+                let mut line = Map::new();
+                line.insert(RULE_FIELD.to_string(), Value::from(rule.name.as_str()));
+                for (key, value) in rule.keys.iter().zip(keys) {
+                    line.insert(key.clone(), Value::from(value.as_str()));
+                }
+                line.insert(COUNT_FIELD.to_string(), Value::from(*count));
+                Value::Object(line).to_string()
+            })
+            .collect()
+    }
+
+    /// Writes the buffered contributions to the sink and the registered sinks,
+    /// returning the first error.
+    pub(crate) fn emit_contributions(&mut self) -> Result<(), MetricsError> {
+        let mut first_error = None;
+        for line in self.contribution_lines() {
+            let result = if self.dry_run {
+                eprintln!("Dry run, contributions not emitted: {line}");
+                Ok(())
+            } else {
+                let emitted = self.sink.emit_log_line(&line);
+                emitted.and(self.exporters.emit_log_line(&line))
+            };
+            if let Err(err) = result {
+                self.record_failed(&err, 0);
+                first_error.get_or_insert(err);
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    }
+
+    /// Writes the buffered contributions to the async sink and the registered sinks,
+    /// returning the first error.
+    #[cfg(feature = "async")]
+    pub(crate) async fn emit_contributions_async(
+        &mut self,
+        sink: &dyn AsyncMetricsSink,
+    ) -> Result<(), MetricsError> {
+        if self.dry_run {
+            return self.emit_contributions();
+        }
+        let mut first_error = None;
+        for line in self.contribution_lines() {
+            let exported = self.exporters.emit_log_line(&line);
+            if let Err(err) = sink.emit_log_line(line).await.and(exported) {
+                self.record_failed(&err, 0);
+                first_error.get_or_insert(err);
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::{MetricsSink, RecordingSink};
+    use crate::MetricUnit;
+
+    #[test]
+    fn should_emit_summed_contributions_after_metrics() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .contributor_rule(ContributorRule::new(
+                "top_tenants",
+                &["tenant_id", "endpoint"],
+            ))
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+        metrics.add_contribution("top_tenants", &["a", "/orders"], 1.0);
+        metrics.add_contribution("top_tenants", &["b", "/orders"], 1.0);
+        metrics.add_contribution("top_tenants", &["a", "/orders"], 2.0);
+        metrics.add_contribution("top_tenants", &["a"], 1.0);
+        metrics.add_contribution("unknown", &["a"], 1.0);
+        metrics.flush_metrics();
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads.len(), 3);
+        assert_eq!(payloads[0]["orders"], 1.0);
+        assert_eq!(payloads[0]["MetricsLibraryDropped"], 2.0);
+        assert_eq!(
+            payloads[1],
+            json!({ RULE_FIELD: "top_tenants", "tenant_id": "a", "endpoint": "/orders", COUNT_FIELD: 3.0 })
+        );
+        assert_eq!(payloads[2]["tenant_id"], "b");
+    }
+
+    #[test]
+    fn should_not_write_contributions_to_sinks_without_logs() {
+        #[derive(Debug, Default, Clone)]
+        struct EmfOnlySink(RecordingSink);
+
+        impl MetricsSink for EmfOnlySink {
+            fn emit(&self, payload: &str) -> Result<(), MetricsError> {
+                MetricsSink::emit(&self.0, payload)
+            }
+        }
+
+        let sink = EmfOnlySink::default();
+        let mut metrics = Metrics::builder("test")
+            .contributor_rule(ContributorRule::new("top_tenants", &["tenant_id"]))
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+        metrics.add_contribution("top_tenants", &["a"], 1.0);
+        metrics.try_flush_metrics().unwrap();
+
+        let payloads = sink.0.payloads();
+        assert_eq!(payloads.len(), 1);
+        assert!(payloads[0]
+            .get(crate::self_metrics::ERRORS_METRIC)
+            .is_none());
+    }
+
+    #[test]
+    fn should_describe_rule_for_put_insight_rule() {
+        let rule = ContributorRule::new("top_tenants", &["tenant_id"]);
+
+        let definition = rule.definition(&["/aws/lambda/orders"]);
+
+        assert_eq!(definition["LogGroupNames"], json!(["/aws/lambda/orders"]));
+        assert_eq!(definition["Contribution"]["Keys"], json!(["$.tenant_id"]));
+        assert_eq!(definition["Contribution"]["ValueOf"], "$.contributor_count");
+        assert_eq!(
+            definition["Contribution"]["Filters"],
+            json!([{ "Match": "$.contributor_rule", "In": ["top_tenants"] }])
+        );
+    }
+
+    #[test]
+    fn should_reject_invalid_rules_in_build() {
+        let rules = [
+            ContributorRule::new("top", &[]),
+            ContributorRule::new("top", &["a", "b", "c", "d", "e"]),
+            ContributorRule::new("top", &["a", "a"]),
+            ContributorRule::new("top", &[COUNT_FIELD]),
+            ContributorRule::new("", &["a"]),
+        ];
+        for rule in rules {
+            let result = Metrics::builder("test").contributor_rule(rule).build();
+            assert!(matches!(result, Err(MetricsError::Configuration(_))));
+        }
+        let result = Metrics::builder("test")
+            .contributor_rule(ContributorRule::new("top", &["a"]))
+            .contributor_rule(ContributorRule::new("top", &["b"]))
+            .build();
+        assert!(matches!(result, Err(MetricsError::Configuration(_))));
+    }
+}
@@ -0,0 +1,200 @@
+//! Standard metric names and units shared across teams.
+//!
+//! Metrics recorded under the same names and units can be compared, aggregated and alarmed on
+//! the same way across functions owned by different teams. The constants are
+//! [`DefinedMetric`]s, recorded with [`Metrics::add_defined`], and the `record_*` functions
+//! record the common cases:
+//!
+//! ```
+//! use std::time::{Duration, Instant};
+//!
+//! use lambda_helpers_metrics::{conventions, Metrics};
+//!
+//! let mut metrics = Metrics::new("custom_lambdas", "service", "dummy_service");
+//! let started = Instant::now();
+//!
+//! conventions::record_dependency_call(&mut metrics, "dynamodb", Duration::from_millis(8), true);
+//! conventions::record_handler_duration(&mut metrics, started.elapsed());
+//! metrics.add_defined(conventions::MESSAGES_PROCESSED, 10.0);
+//! ```
+//!
+//! Durations are recorded as samples in milliseconds, so percentiles are computed across all
+//! of them. The conventions can be listed next to the metrics of an application with
+//! [`catalog`].
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use crate::catalog::codegen::{CatalogMetric, MetricCatalog};
+use crate::catalog::DefinedMetric;
+use crate::{Dimensions, MetricUnit, Metrics};
+
+/// Time spent in the handler.
+pub const HANDLER_DURATION: DefinedMetric =
+    DefinedMetric::new("handler_duration", MetricUnit::Milliseconds);
+/// Invocations of the handler which failed.
+pub const ERRORS: DefinedMetric = DefinedMetric::new("handler_errors", MetricUnit::Count);
+/// Time between a message being sent to a queue or stream and its processing.
+pub const QUEUE_LAG: DefinedMetric = DefinedMetric::new("queue_lag", MetricUnit::Milliseconds);
+/// Messages of a queue or stream processed.
+pub const MESSAGES_PROCESSED: DefinedMetric =
+    DefinedMetric::new("messages_processed", MetricUnit::Count);
+/// Time spent calling a downstream dependency, with the [`DEPENDENCY_DIMENSION`].
+pub const DEPENDENCY_DURATION: DefinedMetric =
+    DefinedMetric::new("dependency_duration", MetricUnit::Milliseconds);
+/// Failed calls to a downstream dependency, with the [`DEPENDENCY_DIMENSION`].
+pub const DEPENDENCY_ERRORS: DefinedMetric =
+    DefinedMetric::new("dependency_errors", MetricUnit::Count);
+/// Size of a request or response payload.
+pub const PAYLOAD_SIZE: DefinedMetric = DefinedMetric::new("payload_size", MetricUnit::Bytes);
+
+/// Dimension naming the dependency of [`DEPENDENCY_DURATION`] and [`DEPENDENCY_ERRORS`].
+pub const DEPENDENCY_DIMENSION: &str = "dependency";
+
+/// Records the time spent in the handler as a sample.
+pub fn record_handler_duration(metrics: &mut Metrics, elapsed: Duration) {
+    record_duration(metrics, HANDLER_DURATION, elapsed, Dimensions::default());
+}
+
+/// Counts a failed invocation.
+pub fn record_error(metrics: &mut Metrics) {
+    metrics.increment(ERRORS.name(), 1.0);
+}
+
+/// Records the lag of a message sent at `sent_at` as a sample, measured with the clock of the
+/// `Metrics` object. Messages sent "in the future" because of clock skew have a lag of 0.
+pub fn record_queue_lag(metrics: &mut Metrics, sent_at: DateTime<Utc>) {
+    let lag = (metrics.clock.now() - sent_at).to_std().unwrap_or_default();
+    record_duration(metrics, QUEUE_LAG, lag, Dimensions::default());
+}
+
+/// Records the duration of a call to a dependency as a sample, and counts it in
+/// [`DEPENDENCY_ERRORS`] if it failed. Both carry the [`DEPENDENCY_DIMENSION`].
+pub fn record_dependency_call(
+    metrics: &mut Metrics,
+    dependency: &str,
+    elapsed: Duration,
+    success: bool,
+) {
+    let mut dimensions = Dimensions::default();
+    dimensions.insert(DEPENDENCY_DIMENSION, dependency);
+    record_duration(metrics, DEPENDENCY_DURATION, elapsed, dimensions.clone());
+    if !success {
+        metrics.add_sample_with_dimensions(
+            DEPENDENCY_ERRORS.name(),
+            DEPENDENCY_ERRORS.unit(),
+            1.0,
+            dimensions,
+        );
+    }
+}
+
+/// Records the size of a payload as a sample.
+pub fn record_payload_size(metrics: &mut Metrics, bytes: usize) {
+    #[allow(clippy::cast_precision_loss)]
+    metrics.add_sample(PAYLOAD_SIZE.name(), PAYLOAD_SIZE.unit(), bytes as f64);
+}
+
+fn record_duration(
+    metrics: &mut Metrics,
+    metric: DefinedMetric,
+    elapsed: Duration,
+    dimensions: Dimensions,
+) {
+    let millis = elapsed.as_secs_f64() * 1000.0;
+    metrics.add_sample_with_dimensions(metric.name(), metric.unit(), millis, dimensions);
+}
+
+/// Returns the conventions as a catalog, e.g. to be merged with the one of an application with
+/// [`MetricsBuilder::metric_catalog`](crate::MetricsBuilder::metric_catalog).
+#[must_use]
+pub fn catalog() -> MetricCatalog {
+    let metrics = [
+        (HANDLER_DURATION, "Time spent in the handler.", &[][..]),
+        (ERRORS, "Invocations of the handler which failed.", &[]),
+        (
+            QUEUE_LAG,
+            "Time between a message being sent and its processing.",
+            &[],
+        ),
+        (
+            MESSAGES_PROCESSED,
+            "Messages of a queue or stream processed.",
+            &[],
+        ),
+        (
+            DEPENDENCY_DURATION,
+            "Time spent calling a dependency.",
+            &[DEPENDENCY_DIMENSION],
+        ),
+        (
+            DEPENDENCY_ERRORS,
+            "Failed calls to a dependency.",
+            &[DEPENDENCY_DIMENSION],
+        ),
+        (PAYLOAD_SIZE, "Size of a request or response payload.", &[]),
+    ];
+    MetricCatalog {
+        metrics: metrics
+            .into_iter()
+            .map(|(metric, description, dimensions)| CatalogMetric {
+                name: metric.name().to_string(),
+                unit: metric.unit(),
+                description: Some(description.to_string()),
+                owner: None,
+                dimensions: dimensions.iter().map(ToString::to_string).collect(),
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::sink::RecordingSink;
+
+    #[test]
+    fn should_record_conventional_metrics() {
+        let now = Utc.timestamp_millis_opt(1_700_000_000_000).unwrap();
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .sink(sink.clone())
+            .clock(ManualClock::new(now))
+            .build()
+            .unwrap();
+
+        record_handler_duration(&mut metrics, Duration::from_millis(120));
+        record_handler_duration(&mut metrics, Duration::from_micros(1500));
+        record_error(&mut metrics);
+        record_queue_lag(&mut metrics, now - chrono::Duration::seconds(2));
+        record_queue_lag(&mut metrics, now + chrono::Duration::seconds(2));
+        record_payload_size(&mut metrics, 512);
+        record_dependency_call(&mut metrics, "dynamodb", Duration::from_millis(8), false);
+        metrics.flush_metrics();
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads.len(), 2);
+        assert_eq!(
+            payloads[0]["handler_duration"],
+            serde_json::json!([120.0, 1.5])
+        );
+        assert_eq!(payloads[0]["handler_errors"], 1.0);
+        assert_eq!(payloads[0]["queue_lag"], serde_json::json!([2000.0, 0.0]));
+        assert_eq!(payloads[0]["payload_size"], 512.0);
+        assert_eq!(payloads[1]["dependency"], "dynamodb");
+        assert_eq!(payloads[1]["dependency_duration"], 8.0);
+        assert_eq!(payloads[1]["dependency_errors"], 1.0);
+    }
+
+    #[test]
+    fn should_list_conventions_in_a_valid_catalog() {
+        let catalog = catalog();
+
+        assert!(catalog.validate().is_ok());
+        assert_eq!(catalog.metrics.len(), 7);
+        assert_eq!(catalog.metrics[4].dimensions, [DEPENDENCY_DIMENSION]);
+    }
+}
@@ -0,0 +1,169 @@
+//! Estimated cost of invocations.
+//!
+//! A [`CostEstimator`] combines the billed duration, the configured memory size and a
+//! [`PriceTable`] into the `EstimatedCostMicroUSD` metric, optionally dimensioned by business
+//! operation, so cost-per-feature dashboards can be built directly from the metrics.
+//!
+//! ```
+//! use std::time::Duration;
+//! use lambda_helpers_metrics::cost::{CostEstimator, PriceTable};
+//! use lambda_helpers_metrics::Metrics;
+//!
+//! let estimator = CostEstimator::new(1024).prices(PriceTable::ARM64);
+//!
+//! let mut metrics = Metrics::new("custom_lambdas", "service", "dummy_service");
+//! estimator.record_for_operation(&mut metrics, Duration::from_millis(120), "checkout");
+//! ```
+//!
+//! The estimate covers the compute and request charges only, not ephemeral storage,
+//! data transfer or free tier.
+use std::time::Duration;
+
+use crate::{runtime_info, MetricUnit, Metrics};
+
+pub const ESTIMATED_COST_METRIC: &str = "EstimatedCostMicroUSD";
+pub const OPERATION_DIMENSION: &str = "operation";
+pub const MEMORY_SIZE_ENV: &str = "AWS_LAMBDA_FUNCTION_MEMORY_SIZE";
+
+/// Prices in USD, e.g. of a region which differs from the defaults.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PriceTable {
+    /// Price of one GB-second of compute.
+    pub gb_second: f64,
+    /// Price of one request.
+    pub request: f64,
+}
+
+impl PriceTable {
+    /// Default prices of `x86_64` functions (us-east-1, first pricing tier).
+    pub const X86_64: PriceTable = PriceTable {
+        gb_second: 0.000_016_666_7,
+        request: 0.000_000_2,
+    };
+
+    /// Default prices of `arm64` functions (us-east-1, first pricing tier).
+    pub const ARM64: PriceTable = PriceTable {
+        gb_second: 0.000_013_333_4,
+        request: 0.000_000_2,
+    };
+
+    /// Returns the default prices of the architecture, `arm64` or `x86_64`.
+    #[must_use]
+    pub fn for_architecture(architecture: &str) -> Self {
+        match architecture {
+            "arm64" => Self::ARM64,
+            _ => Self::X86_64,
+        }
+    }
+}
+
+impl Default for PriceTable {
+    /// The default prices of the architecture the binary was compiled for.
+    fn default() -> Self {
+        Self::for_architecture(runtime_info::architecture())
+    }
+}
+
+/// Records the estimated cost of invocations.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CostEstimator {
+    memory_mb: u32,
+    prices: PriceTable,
+}
+
+impl CostEstimator {
+    /// Creates an estimator for a function with the given memory size in MB.
+    #[must_use]
+    pub fn new(memory_mb: u32) -> Self {
+        Self {
+            memory_mb,
+            prices: PriceTable::default(),
+        }
+    }
+
+    /// Creates an estimator for the memory size from `AWS_LAMBDA_FUNCTION_MEMORY_SIZE`,
+    /// if it is set.
+    #[must_use]
+    pub fn from_env() -> Option<Self> {
+        std::env::var(MEMORY_SIZE_ENV)
+            .ok()
+            .and_then(|memory| memory.trim().parse().ok())
+            .map(Self::new)
+    }
+
+    /// Sets the prices, the defaults of the current architecture otherwise.
+    #[must_use]
+    pub fn prices(mut self, prices: PriceTable) -> Self {
+        self.prices = prices;
+        self
+    }
+
+    /// Returns the estimated cost of an invocation in micro USD.
+    /// Lambda bills the duration rounded up to the millisecond.
+    #[must_use]
+    pub fn estimate_micro_usd(&self, duration: Duration) -> f64 {
+        let billed_ms = duration.as_nanos().div_ceil(1_000_000);
+        #[allow(clippy::cast_precision_loss)]
+        let gb_seconds = billed_ms as f64 / 1000.0 * f64::from(self.memory_mb) / 1024.0;
+        (gb_seconds * self.prices.gb_second + self.prices.request) * 1_000_000.0
+    }
+
+    /// Records the estimated cost of an invocation.
+    pub fn record(&self, metrics: &mut Metrics, duration: Duration) {
+        metrics.add_sample(
+            ESTIMATED_COST_METRIC,
+            MetricUnit::None,
+            self.estimate_micro_usd(duration),
+        );
+    }
+
+    /// Records the estimated cost of an invocation with the `operation` dimension.
+    pub fn record_for_operation(&self, metrics: &mut Metrics, duration: Duration, operation: &str) {
+        metrics.add_metric_with_dimensions(
+            ESTIMATED_COST_METRIC,
+            MetricUnit::None,
+            self.estimate_micro_usd(duration),
+            &[(OPERATION_DIMENSION, operation)],
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_estimate_billed_cost() {
+        let estimator = CostEstimator::new(1024).prices(PriceTable {
+            gb_second: 0.00001,
+            request: 0.0000002,
+        });
+
+        // 1 GB for 1 second, plus the request
+        let cost = estimator.estimate_micro_usd(Duration::from_millis(1000));
+        assert!((cost - 10.2).abs() < 1e-9);
+        // rounded up to 2 ms
+        let cost = estimator.estimate_micro_usd(Duration::from_micros(1001));
+        assert!((cost - 0.22).abs() < 1e-9);
+    }
+
+    #[test]
+    fn should_pick_prices_by_architecture() {
+        assert_eq!(PriceTable::for_architecture("arm64"), PriceTable::ARM64);
+        assert_eq!(PriceTable::for_architecture("x86_64"), PriceTable::X86_64);
+    }
+
+    #[test]
+    fn should_record_cost_per_operation() {
+        let sink = crate::sink::RecordingSink::default();
+        let mut metrics = Metrics::builder("test").sink(sink.clone()).build().unwrap();
+        let estimator = CostEstimator::new(512);
+
+        estimator.record_for_operation(&mut metrics, Duration::from_millis(100), "checkout");
+        metrics.flush_metrics();
+
+        let payload = &sink.payloads()[0];
+        assert_eq!(payload[OPERATION_DIMENSION], "checkout");
+        assert!(payload[ESTIMATED_COST_METRIC].as_f64().unwrap() > 0.0);
+    }
+}
@@ -0,0 +1,147 @@
+//! Monotonic counters living as long as the execution environment.
+//!
+//! A counter declared with [`static_counter!`](crate::static_counter) survives across warm
+//! invocations and is incremented with a single atomic operation. `Metrics` objects built with
+//! [`MetricsBuilder::container_counters`](crate::MetricsBuilder::container_counters) emit the
+//! totals at each flush as `<name>_container_total`, which helps to debug how containers are
+//! reused:
+//!
+//! ```
+//! use lambda_helpers_metrics::{static_counter, Metrics};
+//!
+//! let mut metrics = Metrics::builder("custom_lambdas")
+//!     .container_counters(true)
+//!     .build()
+//!     .unwrap();
+//!
+//! static_counter!("total_processed").increment(1);
+//! metrics.flush_metrics();
+//! ```
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, Once, PoisonError};
+
+use crate::catalog::codegen::CatalogMetric;
+use crate::{MetricUnit, Metrics};
+
+/// Suffix of the metrics holding the totals of the counters.
+pub const CONTAINER_TOTAL_SUFFIX: &str = "_container_total";
+
+/// Counters incremented at least once, in order of first use.
+static COUNTERS: Mutex<Vec<&'static ContainerCounter>> = Mutex::new(Vec::new());
+
+/// A counter for the lifetime of the execution environment, see [`crate::counters`].
+#[derive(Debug)]
+pub struct ContainerCounter {
+    name: &'static str,
+    value: AtomicU64,
+    registered: Once,
+}
+
+impl ContainerCounter {
+    /// Creates a counter, usually with [`static_counter!`](crate::static_counter).
+    #[must_use]
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            value: AtomicU64::new(0),
+            registered: Once::new(),
+        }
+    }
+
+    /// Adds to the counter.
+    pub fn increment(&'static self, by: u64) {
+        self.registered.call_once(|| {
+            COUNTERS
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .push(self);
+        });
+        self.value.fetch_add(by, Ordering::Relaxed);
+    }
+
+    /// Returns the total since the execution environment started.
+    #[must_use]
+    pub fn get(&self) -> u64 {
+        self.value.load(Ordering::Relaxed)
+    }
+
+    /// Returns the name of the counter.
+    #[must_use]
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+/// Declares a [`ContainerCounter`](crate::counters::ContainerCounter) in a `static`
+/// and returns a reference to it, see [`crate::counters`].
+#[macro_export]
+macro_rules! static_counter {
+    ($name:expr) => {{
+        static COUNTER: $crate::counters::ContainerCounter =
+            $crate::counters::ContainerCounter::new($name);
+        &COUNTER
+    }};
+}
+
+/// The totals of the counters incremented so far, see [`Metrics::catalog`]. Counters which
+/// were never incremented are unknown until then.
+pub(crate) fn catalog_metrics() -> Vec<CatalogMetric> {
+    let counters = COUNTERS.lock().unwrap_or_else(PoisonError::into_inner);
+    counters
+        .iter()
+        .map(|counter| {
+            CatalogMetric::library(
+                format!("{}{CONTAINER_TOTAL_SUFFIX}", counter.name),
+                MetricUnit::Count,
+                "Total of the counter over the lifetime of the execution environment.",
+            )
+        })
+        .collect()
+}
+
+impl Metrics {
+    /// Moves the totals of the counters into the buffer, if enabled.
+    /// Like the metrics of the library, they don't carry scoped or per-metric dimensions.
+    pub(crate) fn buffer_container_counters(&mut self) {
+        if !self.container_counters {
+            return;
+        }
+        let counters = COUNTERS.lock().unwrap_or_else(PoisonError::into_inner);
+        for counter in counters.iter() {
+            #[allow(clippy::cast_precision_loss)]
+            let total = counter.get() as f64;
+            self.push_internal_metric(
+                format!("{}{CONTAINER_TOTAL_SUFFIX}", counter.name),
+                MetricUnit::Count,
+                total,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sink::RecordingSink;
+    use crate::Metrics;
+
+    #[test]
+    fn should_emit_totals_across_flushes() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .container_counters(true)
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+        let counter = static_counter!("counters_test_processed");
+
+        counter.increment(2);
+        metrics.flush_metrics();
+        counter.increment(3);
+        metrics.flush_metrics();
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads[0]["counters_test_processed_container_total"], 2.0);
+        assert_eq!(payloads[1]["counters_test_processed_container_total"], 5.0);
+        assert_eq!(counter.get(), 5);
+    }
+}
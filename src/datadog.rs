@@ -0,0 +1,192 @@
+//! Sink forwarding metrics to the Datadog Lambda extension.
+//!
+//! The extension listens for `DogStatsD` on `127.0.0.1:8125`. Each metric value is sent as a
+//! distribution, named `<namespace>.<metric>`, with the dimensions mapped to tags, so teams
+//! split across `CloudWatch` and Datadog can instrument once:
+//!
+//! ```
+//! use lambda_helpers_metrics::datadog::DatadogSink;
+//! use lambda_helpers_metrics::{MetricUnit, Metrics};
+//!
+//! let mut metrics = Metrics::builder("custom_lambdas")
+//!     .dimension("service", "orders")
+//!     .sink(DatadogSink::new().tag("env", "prod"))
+//!     .build()
+//!     .unwrap();
+//! metrics.add_metric("latency", MetricUnit::Milliseconds, 120.0);
+//! ```
+//!
+//! Available with the `datadog` feature. Properties are not forwarded. To keep the EMF logs
+//! as well, combine it with another sink.
+use std::net::UdpSocket;
+
+use crate::sink::MetricsSink;
+use crate::{emf, MetricsError};
+
+pub const DEFAULT_DOGSTATSD_ADDRESS: &str = "127.0.0.1:8125";
+/// The largest datagram the extension reads, longer payloads are split.
+const MAX_DATAGRAM_LEN: usize = 8192;
+
+/// Sends payloads to the Datadog Lambda extension as `DogStatsD` distributions.
+#[derive(Debug, Clone)]
+pub struct DatadogSink {
+    address: String,
+    prefix_namespace: bool,
+    tags: Vec<String>,
+}
+
+impl Default for DatadogSink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DatadogSink {
+    /// Creates a sink for the extension on `127.0.0.1:8125`.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::with_address(DEFAULT_DOGSTATSD_ADDRESS)
+    }
+
+    /// Creates a sink for a `DogStatsD` server on the given address.
+    #[must_use]
+    pub fn with_address(address: &str) -> Self {
+        Self {
+            address: address.to_string(),
+            prefix_namespace: true,
+            tags: Vec::new(),
+        }
+    }
+
+    /// Sets whether metric names are prefixed with the namespace, `true` by default.
+    #[must_use]
+    pub fn prefix_namespace(mut self, prefix: bool) -> Self {
+        self.prefix_namespace = prefix;
+        self
+    }
+
+    /// Adds a tag sent with every metric, e.g. `env:prod`.
+    #[must_use]
+    pub fn tag(mut self, key: &str, value: &str) -> Self {
+        self.tags.push(tag(key, value));
+        self
+    }
+
+    /// Formats the metrics of an EMF payload as `DogStatsD` lines.
+    fn lines(&self, payload: &str) -> Result<Vec<String>, MetricsError> {
+        let mut lines = Vec::new();
+        for metric in emf::parse(payload)? {
+            let name = if self.prefix_namespace {
+                sanitize_name(&format!("{}.{}", metric.namespace, metric.name))
+            } else {
+                sanitize_name(&metric.name)
+            };
+            let tags: Vec<String> = self
+                .tags
+                .iter()
+                .cloned()
+                .chain(metric.dimensions.iter().map(|(key, value)| tag(key, value)))
+                .collect();
+            let tags = if tags.is_empty() {
+                String::new()
+            } else {
+                format!("|#{}", tags.join(","))
+            };
+            for value in metric.values.iter().filter(|value| value.is_finite()) {
+                lines.push(format!("{name}:{value}|d{tags}"));
+            }
+        }
+        Ok(lines)
+    }
+}
+
+impl MetricsSink for DatadogSink {
+    fn emit(&self, payload: &str) -> Result<(), MetricsError> {
+        let lines = self.lines(payload)?;
+        if lines.is_empty() {
+            return Ok(());
+        }
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        let mut datagram = String::new();
+        for line in lines {
+            if !datagram.is_empty() && datagram.len() + line.len() + 1 > MAX_DATAGRAM_LEN {
+                socket.send_to(datagram.as_bytes(), &self.address)?;
+                datagram.clear();
+            }
+            if !datagram.is_empty() {
+                datagram.push('\n');
+            }
+            datagram.push_str(&line);
+        }
+        socket.send_to(datagram.as_bytes(), &self.address)?;
+        Ok(())
+    }
+}
+
+/// Metric names may only contain ASCII alphanumerics, underscores and periods.
+fn sanitize_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Tags can't contain the separators of the `DogStatsD` format.
+fn tag(key: &str, value: &str) -> String {
+    format!("{key}:{value}").replace([',', '|', '#', '\n'], "_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MetricUnit, Metrics};
+
+    #[test]
+    fn should_format_distributions_with_tags() {
+        let sink = DatadogSink::new().tag("env", "prod");
+        let payload = r#"{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"custom lambdas",
+            "Dimensions":[["service"]],"Metrics":[{"Name":"latency","Unit":"Milliseconds"}]}]},
+            "service":"orders|eu","latency":[1.5,2]}"#;
+
+        assert_eq!(
+            sink.lines(payload).unwrap(),
+            vec![
+                "custom_lambdas.latency:1.5|d|#env:prod,service:orders_eu",
+                "custom_lambdas.latency:2|d|#env:prod,service:orders_eu",
+            ]
+        );
+        assert_eq!(
+            DatadogSink::new()
+                .prefix_namespace(false)
+                .lines(payload)
+                .unwrap()[0],
+            "latency:1.5|d|#service:orders_eu"
+        );
+    }
+
+    #[test]
+    fn should_send_metrics_to_dogstatsd() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = server.local_addr().unwrap().to_string();
+        let mut metrics = Metrics::builder("test")
+            .dimension("service", "orders")
+            .sink(DatadogSink::with_address(&address))
+            .build()
+            .unwrap();
+
+        metrics.add_metric("orders", MetricUnit::Count, 3.0);
+        metrics.flush_metrics();
+
+        let mut buffer = [0; MAX_DATAGRAM_LEN];
+        let read = server.recv(&mut buffer).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&buffer[..read]).unwrap(),
+            "test.orders:3|d|#service:orders"
+        );
+    }
+}
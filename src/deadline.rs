@@ -0,0 +1,125 @@
+//! Metrics of the time left before the invocation times out.
+//!
+//! A timeout is only visible once it happened, as an error of the invocation. Recording the
+//! remaining time, e.g. at the end of the handler, shows how close invocations get to it:
+//!
+//! ```
+//! use std::time::Duration;
+//!
+//! use lambda_helpers_metrics::Metrics;
+//!
+//! let mut metrics = Metrics::builder("custom_lambdas")
+//!     .near_timeout_threshold(Duration::from_secs(2))
+//!     .build()
+//!     .unwrap();
+//! metrics.set_deadline(chrono::Utc::now() + Duration::from_secs(10));
+//! metrics.record_remaining_time();
+//! ```
+//!
+//! - `RemainingTimeMs`: the time left until the deadline, see [`Metrics::set_deadline`];
+//! - `NearTimeout`: 1 when the time left is below the threshold, 0 otherwise, to alarm on
+//!   the rate of invocations close to a timeout.
+//!
+//! With a threshold set on the builder, both are also recorded at each flush with other
+//! metrics. Nothing is recorded without a deadline, which `Metrics::for_invocation` sets from
+//! the Lambda context with the `lambda` feature.
+use std::time::Duration;
+
+use crate::catalog::codegen::CatalogMetric;
+use crate::{MetricUnit, Metrics};
+
+/// Time left until the deadline, in milliseconds.
+pub const REMAINING_TIME_METRIC: &str = "RemainingTimeMs";
+/// 1 when the time left is below the threshold, 0 otherwise.
+pub const NEAR_TIMEOUT_METRIC: &str = "NearTimeout";
+/// Threshold of [`Metrics::record_remaining_time`] when none is set on the builder.
+pub const DEFAULT_NEAR_TIMEOUT_THRESHOLD: Duration = Duration::from_secs(1);
+
+/// The metrics recorded by [`Metrics::record_remaining_time`], see [`Metrics::catalog`].
+pub(crate) fn catalog_metrics() -> Vec<CatalogMetric> {
+    vec![
+        CatalogMetric::library(
+            REMAINING_TIME_METRIC,
+            MetricUnit::Milliseconds,
+            "Time left until the deadline of the invocation.",
+        ),
+        CatalogMetric::library(
+            NEAR_TIMEOUT_METRIC,
+            MetricUnit::Count,
+            "1 when the time left is below the threshold, 0 otherwise.",
+        ),
+    ]
+}
+
+impl Metrics {
+    /// Records the time left until the deadline and whether it is below the threshold,
+    /// see [`crate::deadline`]. Nothing is recorded if no deadline is set.
+    pub fn record_remaining_time(&mut self) {
+        for (name, unit, value) in self.remaining_time_values().into_iter().flatten() {
+            self.add_metric(name, unit, value);
+        }
+    }
+
+    /// Records the time left at flush if a threshold is set on the builder, see
+    /// [`Metrics::measures_at_flush`].
+    pub(crate) fn buffer_remaining_time(&mut self) {
+        if self.near_timeout_threshold.is_none() || !self.measures_at_flush() {
+            return;
+        }
+        for (name, unit, value) in self.remaining_time_values().into_iter().flatten() {
+            self.push_internal_metric(name, unit, value);
+        }
+    }
+
+    fn remaining_time_values(&self) -> Option<[(&'static str, MetricUnit, f64); 2]> {
+        let remaining = self.remaining_time()?;
+        let threshold = self
+            .near_timeout_threshold
+            .unwrap_or(DEFAULT_NEAR_TIMEOUT_THRESHOLD);
+        let near_timeout = if remaining < threshold { 1.0 } else { 0.0 };
+        Some([
+            (
+                REMAINING_TIME_METRIC,
+                MetricUnit::Milliseconds,
+                remaining.as_secs_f64() * 1000.0,
+            ),
+            (NEAR_TIMEOUT_METRIC, MetricUnit::Count, near_timeout),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+    use crate::clock::{Clock, ManualClock};
+    use crate::sink::RecordingSink;
+
+    #[test]
+    fn should_record_remaining_time_at_flush() {
+        let clock = ManualClock::new(Utc::now());
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .clock(clock.clone())
+            .near_timeout_threshold(Duration::from_secs(2))
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+        metrics.flush_metrics();
+        metrics.set_deadline(clock.now() + Duration::from_secs(3));
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+        metrics.flush_metrics();
+        clock.advance(Duration::from_millis(1500));
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+        metrics.flush_metrics();
+
+        let payloads = sink.payloads();
+        assert!(payloads[0].get(REMAINING_TIME_METRIC).is_none());
+        assert_eq!(payloads[1][NEAR_TIMEOUT_METRIC], 0.0);
+        assert_eq!(payloads[2][NEAR_TIMEOUT_METRIC], 1.0);
+        assert!(payloads[2][REMAINING_TIME_METRIC].as_f64().unwrap() <= 1500.0);
+    }
+}
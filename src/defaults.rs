@@ -0,0 +1,72 @@
+//! Process-wide default dimensions and properties.
+//!
+//! Shared libraries often create their own `Metrics` objects, which would not carry the
+//! identity of the service. Defaults set once at init with [`init`](crate::init) are added to
+//! every `Metrics` object built afterwards in the process:
+//!
+//! ```
+//! use lambda_helpers_metrics::defaults::Defaults;
+//! use lambda_helpers_metrics::Metrics;
+//!
+//! lambda_helpers_metrics::init(
+//!     Defaults::new()
+//!         .dimension("service", "checkout")
+//!         .property("team", "payments"),
+//! );
+//!
+//! // e.g. in a shared library
+//! let metrics = Metrics::builder("custom_lambdas").build().unwrap();
+//! assert_eq!(metrics.dimension("service"), Some("checkout"));
+//! ```
+//!
+//! Dimensions and properties set on the builder take precedence over the defaults.
+//! `Metrics` objects built before `init` are not changed. Default dimensions which can't be
+//! added, e.g. because of the [`DimensionLengthPolicy`](crate::DimensionLengthPolicy) or because
+//! the limit of dimensions is reached, are reported and skipped, so they don't fail the `build`
+//! of every `Metrics` object.
+use std::sync::{Arc, PoisonError, RwLock};
+
+/// Dimensions and properties added to every `Metrics` object, see [`crate::defaults`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Defaults {
+    pub(crate) dimensions: Vec<(String, String)>,
+    pub(crate) properties: Vec<(String, serde_json::Value)>,
+}
+
+impl Defaults {
+    /// Creates empty defaults.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a default dimension.
+    #[must_use]
+    pub fn dimension(mut self, key: &str, value: &str) -> Self {
+        self.dimensions.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Adds a default property.
+    #[must_use]
+    pub fn property(mut self, key: &str, value: impl Into<serde_json::Value>) -> Self {
+        self.properties.push((key.to_string(), value.into()));
+        self
+    }
+}
+
+static DEFAULTS: RwLock<Option<Arc<Defaults>>> = RwLock::new(None);
+
+/// Sets the defaults of the `Metrics` objects built afterwards in the process,
+/// replacing the ones set before, see [`crate::defaults`].
+pub fn init(defaults: Defaults) {
+    *DEFAULTS.write().unwrap_or_else(PoisonError::into_inner) = Some(Arc::new(defaults));
+}
+
+/// Returns the defaults set with [`init`], if any.
+pub(crate) fn current() -> Option<Arc<Defaults>> {
+    DEFAULTS
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+}
@@ -0,0 +1,84 @@
+//! Per-invocation deltas of cumulative counters.
+//!
+//! A [`DeltaTracker`] remembers the previous value of cumulative counters, e.g. the total
+//! number of rows in a table, for the lifetime of the execution environment, and records the
+//! change since the previous invocation. It can be a `static`:
+//!
+//! ```
+//! use lambda_helpers_metrics::delta::DeltaTracker;
+//! use lambda_helpers_metrics::{MetricUnit, Metrics};
+//!
+//! static TRACKER: DeltaTracker = DeltaTracker::new();
+//!
+//! let mut metrics = Metrics::new("custom_lambdas", "service", "dummy_service");
+//! // the first invocation only remembers the value
+//! TRACKER.record(&mut metrics, "new_rows", MetricUnit::Count, 1000.0);
+//! TRACKER.record(&mut metrics, "new_rows", MetricUnit::Count, 1042.0);
+//! assert_eq!(metrics.value_of("new_rows"), Some(42.0));
+//! ```
+use std::sync::{Mutex, PoisonError};
+
+use crate::{MetricUnit, Metrics};
+
+/// Previous values of cumulative counters, by metric name.
+#[derive(Debug, Default)]
+pub struct DeltaTracker {
+    previous: Mutex<Vec<(String, f64)>>,
+}
+
+impl DeltaTracker {
+    /// Creates a tracker without previous values.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            previous: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Remembers the current value of the counter and returns the change since the previous
+    /// one, or `None` for the first value. The change is negative if the counter decreased.
+    pub fn delta(&self, name: &str, current: f64) -> Option<f64> {
+        let mut previous = self.previous.lock().unwrap_or_else(PoisonError::into_inner);
+        match previous.iter_mut().find(|(key, _)| key == name) {
+            Some((_, value)) => Some(current - std::mem::replace(value, current)),
+            None => {
+                previous.push((name.to_string(), current));
+                None
+            }
+        }
+    }
+
+    /// Records the change of the counter since the previous invocation, see [`DeltaTracker::delta`].
+    /// Nothing is recorded for the first value.
+    pub fn record(&self, metrics: &mut Metrics, name: &str, unit: MetricUnit, current: f64) {
+        if let Some(delta) = self.delta(name, current) {
+            metrics.add_metric(name, unit, delta);
+        }
+    }
+
+    /// Forgets the previous values, e.g. after restoring a snapshot.
+    pub fn reset(&self) {
+        self.previous
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_return_change_since_previous_value() {
+        let tracker = DeltaTracker::new();
+
+        assert_eq!(tracker.delta("rows", 10.0), None);
+        assert_eq!(tracker.delta("rows", 15.0), Some(5.0));
+        assert_eq!(tracker.delta("rows", 12.0), Some(-3.0));
+        assert_eq!(tracker.delta("other", 1.0), None);
+
+        tracker.reset();
+        assert_eq!(tracker.delta("rows", 20.0), None);
+    }
+}
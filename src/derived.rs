@@ -0,0 +1,168 @@
+//! Metrics derived from other buffered metrics at flush time.
+//!
+//! Simple ratios, like a success rate, can be emitted as metrics of their own instead of
+//! being computed with `CloudWatch` metric math on every dashboard and alarm.
+//!
+//! ```
+//! use lambda_helpers_metrics::{MetricUnit, Metrics};
+//!
+//! let mut metrics = Metrics::new("custom_lambdas", "service", "dummy_service");
+//! metrics.add_success_rate("success_rate", "success", "failure");
+//! metrics.add_derived_metric("cache_hit_ratio", MetricUnit::None, |inputs| {
+//!     let lookups = inputs.sum("cache_hit") + inputs.sum("cache_miss");
+//!     (lookups > 0.0).then(|| inputs.sum("cache_hit") / lookups)
+//! });
+//!
+//! metrics.increment("success", 3.0);
+//! metrics.increment("failure", 1.0);
+//! metrics.flush_metrics(); // success_rate = 75
+//! ```
+use std::fmt;
+use std::sync::Arc;
+
+use crate::catalog::codegen::CatalogMetric;
+use crate::{mode, Dimensions, Metric, MetricUnit, Metrics};
+
+type Derive = dyn Fn(&DerivedInputs<'_>) -> Option<f64> + Send + Sync;
+
+/// The metrics buffered at flush, passed to the functions of derived metrics.
+#[derive(Debug)]
+pub struct DerivedInputs<'a>(&'a [Metric]);
+
+impl DerivedInputs<'_> {
+    /// Returns the sum of all values of the metric, without per-metric dimensions,
+    /// or 0 if the metric is not buffered.
+    #[must_use]
+    pub fn sum(&self, name: &str) -> f64 {
+        self.0
+            .iter()
+            .filter(|metric| metric.name == name && metric.dimensions.is_empty())
+            .flat_map(|metric| &metric.values)
+            .sum()
+    }
+
+    /// Returns `true` if the metric is buffered without per-metric dimensions.
+    #[must_use]
+    pub fn contains(&self, name: &str) -> bool {
+        self.0
+            .iter()
+            .any(|metric| metric.name == name && metric.dimensions.is_empty())
+    }
+}
+
+/// A metric computed at every flush, see [`Metrics::add_derived_metric`].
+#[derive(Clone)]
+pub(crate) struct DerivedMetric {
+    name: String,
+    unit: MetricUnit,
+    derive: Arc<Derive>,
+}
+
+impl fmt::Debug for DerivedMetric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DerivedMetric")
+            .field("name", &self.name)
+            .field("unit", &self.unit)
+            .finish_non_exhaustive()
+    }
+}
+
+impl DerivedMetric {
+    pub(crate) fn catalog_metric(&self) -> CatalogMetric {
+        CatalogMetric {
+            name: self.name.clone(),
+            unit: self.unit,
+            description: Some("Derived at flush from the buffered metrics.".to_string()),
+            owner: None,
+            dimensions: Vec::new(),
+        }
+    }
+}
+
+impl Metrics {
+    /// Declares a metric computed at every flush from the buffered metrics.
+    /// The metric is emitted only if `derive` returns a value, e.g. not when
+    /// a denominator is 0. Unlike the buffered metrics, the declaration is kept after a flush.
+    pub fn add_derived_metric(
+        &mut self,
+        name: &str,
+        unit: MetricUnit,
+        derive: impl Fn(&DerivedInputs<'_>) -> Option<f64> + Send + Sync + 'static,
+    ) {
+        self.derived.push(DerivedMetric {
+            name: name.to_string(),
+            unit,
+            derive: Arc::new(derive),
+        });
+    }
+
+    /// Declares a `Percent` metric: `success / (success + failure) * 100`,
+    /// emitted only if any of the two metrics is recorded.
+    pub fn add_success_rate(&mut self, name: &str, success: &str, failure: &str) {
+        let (success, failure) = (success.to_string(), failure.to_string());
+        self.add_derived_metric(name, MetricUnit::Percent, move |inputs| {
+            let total = inputs.sum(&success) + inputs.sum(&failure);
+            (total > 0.0).then(|| inputs.sum(&success) / total * 100.0)
+        });
+    }
+
+    /// Computes the derived metrics and adds them to the buffer.
+    pub(crate) fn evaluate_derived_metrics(&mut self) {
+        if self.entries.is_empty() {
+            return;
+        }
+        let inputs = DerivedInputs(&self.entries);
+        let derived = self
+            .derived
+            .iter()
+            .filter_map(|derived| {
+                (derived.derive)(&inputs).map(|value| Metric {
+                    name: derived.name.clone(),
+                    unit: derived.unit,
+                    values: vec![value],
+                    dimensions: Dimensions::default(),
+                    storage_resolution: None,
+                    timestamp: None,
+                    properties: Vec::new(),
+                })
+            })
+            .collect::<Vec<_>>();
+        for metric in derived {
+            match mode::validate(&metric) {
+                Ok(()) => self.entries.push(metric),
+                Err(err) => self.record_dropped(&err),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sink::RecordingSink;
+    use crate::Metrics;
+
+    #[test]
+    fn should_emit_success_rate_at_each_flush() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test").sink(sink.clone()).build().unwrap();
+        metrics.add_success_rate("success_rate", "success", "failure");
+
+        metrics.increment("success", 3.0);
+        metrics.increment("failure", 1.0);
+        metrics.flush_metrics();
+        metrics.increment("failure", 1.0);
+        metrics.flush_metrics();
+        metrics.add_metric("other", crate::MetricUnit::Count, 1.0);
+        metrics.flush_metrics();
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads.len(), 3);
+        assert_eq!(payloads[0]["success_rate"], 75.0);
+        assert_eq!(
+            payloads[0]["_aws"]["CloudWatchMetrics"][0]["Metrics"][2]["Unit"],
+            "Percent"
+        );
+        assert_eq!(payloads[1]["success_rate"], 0.0);
+        assert!(payloads[2].get("success_rate").is_none());
+    }
+}
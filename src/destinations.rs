@@ -0,0 +1,74 @@
+//! Outcome metrics of asynchronously invoked functions using Lambda Destinations.
+//!
+//! The platform routes an asynchronous invocation to the success destination when the handler
+//! returns `Ok`, and to the failure destination when it fails after the retries are exhausted.
+//! Recording the outcome of every invocation gives visibility into the routing from the
+//! function side:
+//! - `DestinationSuccess`, 1 if the invocation is routed to the success destination, 0 otherwise
+//! - `DestinationFailure`, 1 if the invocation failed, 0 otherwise. The last failed attempt
+//!   is routed to the failure destination, earlier ones are retried.
+//!
+//! ```
+//! use lambda_helpers_metrics::Metrics;
+//!
+//! # fn process() -> Result<(), std::io::Error> { Ok(()) }
+//! let mut metrics = Metrics::new("custom_lambdas", "service", "dummy_service");
+//! let result = process();
+//! metrics.record_destination_result(&result);
+//! ```
+use crate::Metrics;
+
+pub const DESTINATION_SUCCESS_METRIC: &str = "DestinationSuccess";
+pub const DESTINATION_FAILURE_METRIC: &str = "DestinationFailure";
+
+/// The destination an invocation is routed to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DestinationOutcome {
+    /// The success destination.
+    Success,
+    /// The failure destination, once the retries are exhausted.
+    Failure,
+}
+
+impl<T, E> From<&Result<T, E>> for DestinationOutcome {
+    fn from(result: &Result<T, E>) -> Self {
+        match result {
+            Ok(_) => DestinationOutcome::Success,
+            Err(_) => DestinationOutcome::Failure,
+        }
+    }
+}
+
+impl Metrics {
+    /// Records the outcome path of an asynchronous invocation.
+    pub fn record_destination_outcome(&mut self, outcome: DestinationOutcome) {
+        let success = outcome == DestinationOutcome::Success;
+        self.increment(DESTINATION_SUCCESS_METRIC, if success { 1.0 } else { 0.0 });
+        self.increment(DESTINATION_FAILURE_METRIC, if success { 0.0 } else { 1.0 });
+    }
+
+    /// Records the outcome path of an asynchronous invocation from the result of the handler.
+    pub fn record_destination_result<T, E>(&mut self, result: &Result<T, E>) {
+        self.record_destination_outcome(result.into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_record_destination_outcomes() {
+        let mut metrics = Metrics::builder("test")
+            .sink(crate::sink::NullSink)
+            .build()
+            .unwrap();
+
+        metrics.record_destination_result(&Ok::<_, ()>(()));
+        metrics.record_destination_result(&Err::<(), _>("failed"));
+        metrics.record_destination_outcome(DestinationOutcome::Success);
+
+        assert_eq!(metrics.value_of(DESTINATION_SUCCESS_METRIC), Some(2.0));
+        assert_eq!(metrics.value_of(DESTINATION_FAILURE_METRIC), Some(1.0));
+    }
+}
@@ -0,0 +1,99 @@
+use std::sync::Arc;
+
+use crate::{Dimensions, MetricsError, MAX_DIMENSIONS};
+
+/// An immutable set of dimensions shared between `Metrics` objects without copying,
+/// e.g. when a short-lived `Metrics` object is created per item of a batch.
+///
+/// ```
+/// use lambda_helpers_metrics::{DimensionSet, Metrics};
+///
+/// let shared = DimensionSet::new(&[("service", "orders"), ("stage", "prod")]).unwrap();
+/// for item in ["a", "b"] {
+///     let metrics = Metrics::builder("custom_lambdas")
+///         .dimension_set(&shared)
+///         .build()
+///         .unwrap();
+///     // ...
+/// }
+/// ```
+///
+/// A `Metrics` object built from the set references it until one of its dimensions is changed,
+/// e.g. with [`crate::Metrics::try_add_dimension`], which copies the set first.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DimensionSet(pub(crate) Arc<Dimensions>);
+
+impl DimensionSet {
+    /// Creates a set from key-value pairs. A key repeated later replaces the earlier value.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the set holds more than 30 dimensions
+    pub fn new(dimensions: &[(&str, &str)]) -> Result<Self, MetricsError> {
+        let mut set = Dimensions::default();
+        for (key, value) in dimensions {
+            set.insert(key, value);
+        }
+        if set.len() > MAX_DIMENSIONS {
+            return Err(MetricsError::TooManyDimensions);
+        }
+        Ok(Self(Arc::new(set)))
+    }
+
+    /// Returns the value of the dimension.
+    #[must_use]
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key)
+    }
+
+    /// Returns the number of dimensions.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns `true` if there are no dimensions.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Metrics;
+
+    #[test]
+    fn should_share_dimensions_until_changed() {
+        let shared = DimensionSet::new(&[("service", "orders")]).unwrap();
+        let mut first = Metrics::builder("test")
+            .dimension_set(&shared)
+            .dry_run(true)
+            .build()
+            .unwrap();
+        let second = Metrics::builder("test")
+            .dimension_set(&shared)
+            .dry_run(true)
+            .build()
+            .unwrap();
+
+        assert!(Arc::ptr_eq(&first.dimensions, &shared.0));
+        assert!(Arc::ptr_eq(&second.dimensions, &shared.0));
+
+        first.try_add_dimension("stage", "prod").unwrap();
+
+        assert!(!Arc::ptr_eq(&first.dimensions, &shared.0));
+        assert_eq!(first.dimension("stage"), Some("prod"));
+        assert_eq!(second.dimension("stage"), None);
+        assert_eq!(shared.len(), 1);
+    }
+
+    #[test]
+    fn should_reject_too_many_dimensions() {
+        let keys: Vec<String> = (0..31).map(|i| format!("key{i}")).collect();
+        let pairs: Vec<(&str, &str)> = keys.iter().map(|key| (key.as_str(), "v")).collect();
+
+        assert!(DimensionSet::new(&pairs).is_err());
+    }
+}
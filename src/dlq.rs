@@ -0,0 +1,64 @@
+//! Dead-letter queue send metrics.
+//!
+//! Messages routed to a dead-letter queue on purpose, or failing terminally, are counted as
+//! `DlqSends` with a `reason` dimension (and optionally a `queue` one), so poison-message
+//! trends are visible without a separate logging pipeline. Reasons should be a small set of
+//! values, e.g. `validation`, `max_retries` or `unknown_type`: details specific to a message
+//! belong in properties.
+//!
+//! ```
+//! use lambda_helpers_metrics::Metrics;
+//!
+//! let mut metrics = Metrics::new("custom_lambdas", "service", "dummy_service");
+//! metrics.record_dlq_send("validation");
+//! metrics.record_dlq_send_to("orders-dlq", "max_retries");
+//! metrics.add_property("dlq_message_id", "msg-1");
+//! ```
+use crate::{Dimensions, Metrics};
+
+pub const DLQ_SENDS_METRIC: &str = "DlqSends";
+pub const REASON_DIMENSION: &str = "reason";
+pub const QUEUE_DIMENSION: &str = "queue";
+
+impl Metrics {
+    /// Counts a message sent to a dead-letter queue, with the `reason` dimension.
+    pub fn record_dlq_send(&mut self, reason: &str) {
+        let mut dimensions = Dimensions::default();
+        dimensions.insert(REASON_DIMENSION, reason);
+        self.increment_with_dimensions(DLQ_SENDS_METRIC, 1.0, dimensions);
+    }
+
+    /// Counts a message sent to the given dead-letter queue, with the `queue`
+    /// and `reason` dimensions.
+    pub fn record_dlq_send_to(&mut self, queue: &str, reason: &str) {
+        let mut dimensions = Dimensions::default();
+        dimensions.insert(QUEUE_DIMENSION, queue);
+        dimensions.insert(REASON_DIMENSION, reason);
+        self.increment_with_dimensions(DLQ_SENDS_METRIC, 1.0, dimensions);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::RecordingSink;
+
+    #[test]
+    fn should_count_dlq_sends_by_reason() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test").sink(sink.clone()).build().unwrap();
+
+        metrics.record_dlq_send("validation");
+        metrics.record_dlq_send("validation");
+        metrics.record_dlq_send_to("orders-dlq", "max_retries");
+        metrics.flush_metrics();
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads.len(), 2);
+        assert_eq!(payloads[0][REASON_DIMENSION], "validation");
+        assert_eq!(payloads[0][DLQ_SENDS_METRIC], 2.0);
+        assert_eq!(payloads[1][QUEUE_DIMENSION], "orders-dlq");
+        assert_eq!(payloads[1][REASON_DIMENSION], "max_retries");
+        assert_eq!(payloads[1][DLQ_SENDS_METRIC], 1.0);
+    }
+}
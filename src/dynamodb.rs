@@ -0,0 +1,92 @@
+//! DynamoDB consumed-capacity metrics.
+//!
+//! Available with the `dynamodb` feature. Requests must set `ReturnConsumedCapacity`
+//! to `TOTAL` or `INDEXES` for DynamoDB to report the consumed capacity.
+//!
+//! ```ignore
+//! let output = client
+//!     .put_item()
+//!     .table_name("orders")
+//!     .set_item(Some(item))
+//!     .return_consumed_capacity(ReturnConsumedCapacity::Total)
+//!     .send()
+//!     .await?;
+//!
+//! if let Some(capacity) = output.consumed_capacity() {
+//!     metrics.record_consumed_capacity("checkout", capacity);
+//! }
+//! ```
+use aws_sdk_dynamodb::types::ConsumedCapacity;
+
+use crate::{Dimensions, Metrics};
+
+/// Total capacity units consumed, reported for both `TOTAL` and `INDEXES` modes.
+pub const CONSUMED_CAPACITY_METRIC: &str = "dynamodb_consumed_capacity_units";
+/// Read capacity units consumed, reported only in the `INDEXES` mode.
+pub const CONSUMED_RCU_METRIC: &str = "dynamodb_consumed_rcu";
+/// Write capacity units consumed, reported only in the `INDEXES` mode.
+pub const CONSUMED_WCU_METRIC: &str = "dynamodb_consumed_wcu";
+
+impl Metrics {
+    /// Records the capacity consumed by a DynamoDB call, dimensioned by `table_name` and the
+    /// business `operation`. Multiple calls within one payload are summed up.
+    pub fn record_consumed_capacity(&mut self, operation: &str, capacity: &ConsumedCapacity) {
+        let mut dimensions = Dimensions::default();
+        dimensions.insert("table_name", capacity.table_name().unwrap_or("unknown"));
+        dimensions.insert("operation", operation);
+
+        let values = [
+            (CONSUMED_CAPACITY_METRIC, capacity.capacity_units()),
+            (CONSUMED_RCU_METRIC, capacity.read_capacity_units()),
+            (CONSUMED_WCU_METRIC, capacity.write_capacity_units()),
+        ];
+        for (name, value) in values {
+            if let Some(value) = value {
+                self.increment_with_dimensions(name, value, dimensions.clone());
+            }
+        }
+    }
+
+    /// Records the capacity consumed by a batch or transactional call, which reports
+    /// one `ConsumedCapacity` per table.
+    pub fn record_consumed_capacities(&mut self, operation: &str, capacities: &[ConsumedCapacity]) {
+        for capacity in capacities {
+            self.record_consumed_capacity(operation, capacity);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::RecordingSink;
+
+    #[test]
+    fn should_record_capacity_per_table_and_operation() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test").sink(sink.clone()).build().unwrap();
+        let orders = ConsumedCapacity::builder()
+            .table_name("orders")
+            .capacity_units(2.0)
+            .write_capacity_units(2.0)
+            .build();
+        let customers = ConsumedCapacity::builder()
+            .table_name("customers")
+            .capacity_units(0.5)
+            .build();
+
+        metrics.record_consumed_capacities("checkout", &[orders.clone(), customers]);
+        metrics.record_consumed_capacity("checkout", &orders);
+        metrics.flush_metrics();
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads.len(), 2);
+        assert_eq!(payloads[0]["table_name"], "orders");
+        assert_eq!(payloads[0]["operation"], "checkout");
+        assert_eq!(payloads[0][CONSUMED_CAPACITY_METRIC], 4.0);
+        assert_eq!(payloads[0][CONSUMED_WCU_METRIC], 4.0);
+        assert!(payloads[0].get(CONSUMED_RCU_METRIC).is_none());
+        assert_eq!(payloads[1]["table_name"], "customers");
+        assert_eq!(payloads[1][CONSUMED_CAPACITY_METRIC], 0.5);
+    }
+}
@@ -0,0 +1,88 @@
+//! Reading EMF payloads back, for the components which re-emit them elsewhere.
+use serde_json::Value;
+
+use crate::{Dimensions, MetricUnit, MetricsError};
+
+/// A metric of a parsed EMF payload, with the values of all its samples.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct EmfMetric {
+    pub(crate) namespace: String,
+    pub(crate) dimensions: Dimensions,
+    pub(crate) name: String,
+    pub(crate) unit: MetricUnit,
+    pub(crate) values: Vec<f64>,
+    pub(crate) storage_resolution: Option<u64>,
+    /// Milliseconds since the epoch, shared by all metrics of the payload.
+    pub(crate) timestamp: Option<i64>,
+}
+
+/// Parses the metrics of a single EMF payload. Properties are skipped.
+pub(crate) fn parse(payload: &str) -> Result<Vec<EmfMetric>, MetricsError> {
+    let payload: Value = serde_json::from_str(payload)
+        .map_err(|err| MetricsError::Serialization(err.to_string()))?;
+    let directives = payload["_aws"]["CloudWatchMetrics"]
+        .as_array()
+        .ok_or_else(|| MetricsError::Serialization("not an EMF payload".to_string()))?;
+    let timestamp = payload["_aws"]["Timestamp"].as_i64();
+    let mut metrics = Vec::new();
+    for directive in directives {
+        let namespace = directive["Namespace"].as_str().unwrap_or_default();
+        let mut dimensions = Dimensions::default();
+        for key in directive["Dimensions"][0].as_array().into_iter().flatten() {
+            let key = key.as_str().unwrap_or_default();
+            if let Some(value) = payload[key].as_str() {
+                dimensions.insert(key, value);
+            }
+        }
+        for definition in directive["Metrics"].as_array().into_iter().flatten() {
+            let Some(name) = definition["Name"].as_str() else {
+                continue;
+            };
+            // the unit is omitted for `None` in the compact format
+            let unit = definition["Unit"]
+                .as_str()
+                .and_then(|unit| unit.parse().ok())
+                .unwrap_or(MetricUnit::None);
+            let values = match &payload[name] {
+                Value::Array(values) => values.iter().filter_map(Value::as_f64).collect(),
+                value => value.as_f64().into_iter().collect(),
+            };
+            metrics.push(EmfMetric {
+                namespace: namespace.to_string(),
+                dimensions: dimensions.clone(),
+                name: name.to_string(),
+                unit,
+                values,
+                storage_resolution: definition["StorageResolution"].as_u64(),
+                timestamp,
+            });
+        }
+    }
+    Ok(metrics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_parse_metrics_of_payload() {
+        let payload = r#"{"_aws":{"Timestamp":0,"CloudWatchMetrics":[{"Namespace":"test",
+            "Dimensions":[["service"]],"Metrics":[{"Name":"latency","Unit":"Milliseconds"},
+            {"Name":"ratio","StorageResolution":1}]}]},"service":"orders","request_id":"req-1",
+            "latency":[1.5,2.0],"ratio":0.5}"#;
+
+        let metrics = parse(payload).unwrap();
+
+        assert_eq!(metrics.len(), 2);
+        assert_eq!(metrics[0].namespace, "test");
+        assert_eq!(metrics[0].dimensions.get("service"), Some("orders"));
+        assert_eq!(metrics[0].unit, MetricUnit::Milliseconds);
+        assert_eq!(metrics[0].values, vec![1.5, 2.0]);
+        assert_eq!(metrics[1].unit, MetricUnit::None);
+        assert_eq!(metrics[1].values, vec![0.5]);
+        assert_eq!(metrics[1].storage_resolution, Some(1));
+        assert_eq!(metrics[1].timestamp, Some(0));
+        assert!(parse(r#"{"message":"not metrics"}"#).is_err());
+    }
+}
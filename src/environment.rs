@@ -0,0 +1,74 @@
+use std::sync::Arc;
+
+use crate::sink::{AgentSink, MetricsSink, PrettySink, StdoutSink};
+
+/// The execution environment, used to pick a default sink.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Environment {
+    /// AWS Lambda, detected by `AWS_LAMBDA_FUNCTION_NAME`.
+    Lambda,
+    /// Amazon ECS (including Fargate), detected by `ECS_CONTAINER_METADATA_URI(_V4)`.
+    Ecs,
+    /// Anything else, e.g. a developer machine.
+    Local,
+}
+
+impl Environment {
+    /// Detects the environment from the process environment variables.
+    #[must_use]
+    pub fn detect() -> Self {
+        Self::detect_from(|key| std::env::var(key).ok())
+    }
+
+    pub(crate) fn detect_from(var: impl Fn(&str) -> Option<String>) -> Self {
+        let is_set = |key: &str| var(key).is_some_and(|value| !value.is_empty());
+        if is_set("AWS_LAMBDA_FUNCTION_NAME") {
+            Environment::Lambda
+        } else if is_set("ECS_CONTAINER_METADATA_URI_V4") || is_set("ECS_CONTAINER_METADATA_URI") {
+            Environment::Ecs
+        } else {
+            Environment::Local
+        }
+    }
+
+    /// Returns the sink which does the right thing in this environment.
+    #[must_use]
+    pub fn default_sink(self) -> Arc<dyn MetricsSink> {
+        match self {
+            Environment::Lambda => Arc::new(StdoutSink),
+            Environment::Ecs => Arc::new(AgentSink::from_env()),
+            Environment::Local => Arc::new(PrettySink),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn vars(pairs: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let pairs: Vec<(String, String)> = pairs
+            .iter()
+            .map(|(key, value)| ((*key).to_string(), (*value).to_string()))
+            .collect();
+        move |key| {
+            pairs
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value.clone())
+        }
+    }
+
+    #[test]
+    fn should_detect_environment() {
+        assert_eq!(
+            Environment::detect_from(vars(&[("AWS_LAMBDA_FUNCTION_NAME", "fn")])),
+            Environment::Lambda
+        );
+        assert_eq!(
+            Environment::detect_from(vars(&[("ECS_CONTAINER_METADATA_URI_V4", "http://x")])),
+            Environment::Ecs
+        );
+        assert_eq!(Environment::detect_from(vars(&[])), Environment::Local);
+    }
+}
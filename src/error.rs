@@ -0,0 +1,64 @@
+use std::fmt;
+use std::sync::Arc;
+
+/// Errors returned by the metrics API and by sinks.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum MetricsError {
+    /// The limit of dimensions per payload was reached.
+    TooManyDimensions,
+    /// The limit of metrics per payload was reached.
+    TooManyMetrics,
+    /// The payload could not be serialized.
+    Serialization(String),
+    /// The payload could not be written to the sink.
+    Io(std::io::Error),
+    /// Invalid configuration, e.g. a malformed agent endpoint.
+    Configuration(String),
+    /// The metric was rejected in strict mode, e.g. because of a non-finite value.
+    InvalidMetric(String),
+    /// The dimension name or value is too long for `CloudWatch`.
+    InvalidDimension(String),
+    /// Metrics were kept in the buffer for too long without a flush, see [`crate::staleness`].
+    StaleBuffer(String),
+}
+
+impl fmt::Display for MetricsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MetricsError::TooManyDimensions => f.write_str("Too many dimensions"),
+            MetricsError::TooManyMetrics => f.write_str("Too many metrics"),
+            MetricsError::Serialization(err) => write!(f, "Error when serializing metrics: {err}"),
+            MetricsError::Io(err) => write!(f, "Error when writing metrics: {err}"),
+            MetricsError::Configuration(err) => write!(f, "Invalid metrics configuration: {err}"),
+            MetricsError::InvalidMetric(err) => write!(f, "Invalid metric: {err}"),
+            MetricsError::InvalidDimension(err) => write!(f, "Invalid dimension: {err}"),
+            MetricsError::StaleBuffer(err) => write!(f, "Metrics not flushed: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for MetricsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MetricsError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for MetricsError {
+    fn from(err: std::io::Error) -> Self {
+        MetricsError::Io(err)
+    }
+}
+
+/// Callback set with [`crate::MetricsBuilder::on_error`].
+#[derive(Clone)]
+pub(crate) struct ErrorCallback(pub(crate) Arc<dyn Fn(&MetricsError) + Send + Sync>);
+
+impl fmt::Debug for ErrorCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ErrorCallback")
+    }
+}
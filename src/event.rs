@@ -0,0 +1,177 @@
+use crate::format::{LEVEL_FIELD, MESSAGE_FIELD};
+use crate::{mode, LogLevel, Metric, MetricUnit, Metrics, MetricsError, PayloadFields};
+
+/// Field holding the name of the event emitted with [`Metrics::emit_event`].
+pub const EVENT_FIELD: &str = "event";
+
+impl Metrics {
+    /// Emits a single payload which is both a structured log event and its metrics, e.g. an error
+    /// log and the count of errors, so the log and the metric can never diverge. The payload holds
+    /// the shared dimensions and properties, the `event`, `level` and `message` fields, and only
+    /// the given metrics: the buffer is not flushed nor changed. Values given for the same name
+    /// are emitted together as an array; the same name with different units, or more than 100
+    /// distinct names, fail the event.
+    ///
+    /// Errors are printed to stderr and counted, like the ones of `flush_metrics`.
+    ///
+    /// ```
+    /// # use lambda_helpers_metrics::{LogLevel, MetricUnit, Metrics};
+    /// let mut metrics = Metrics::new("custom_lambdas", "service", "dummy_service");
+    /// metrics.emit_event(
+    ///     "order_failed",
+    ///     LogLevel::Error,
+    ///     "payment declined",
+    ///     &[("order_failed", MetricUnit::Count, 1.0)],
+    /// );
+    /// ```
+    pub fn emit_event(
+        &mut self,
+        event: &str,
+        level: LogLevel,
+        message: &str,
+        metrics: &[(&str, MetricUnit, f64)],
+    ) {
+        if let Err(err) = self.try_emit_event(event, level, message, metrics) {
+            self.record_failed(&err, metrics.len());
+            mode::fail_in_strict_mode(&err);
+        }
+    }
+
+    fn try_emit_event(
+        &mut self,
+        event: &str,
+        level: LogLevel,
+        message: &str,
+        metrics: &[(&str, MetricUnit, f64)],
+    ) -> Result<(), MetricsError> {
+        let mut entries: Vec<Metric> = Vec::new();
+        for (name, unit, value) in metrics {
+            let metric = self.new_metric(name, *unit, *value);
+            mode::validate(&metric)?;
+            match entries.iter_mut().find(|entry| entry.name == metric.name) {
+                Some(entry) if entry.unit == metric.unit => entry.values.extend(metric.values),
+                Some(_) => {
+                    return Err(MetricsError::InvalidMetric(format!(
+                        "{name} is given with different units"
+                    )))
+                }
+                None => entries.push(metric),
+            }
+        }
+        if entries.len() > self.limits.max_metrics {
+            return Err(MetricsError::TooManyMetrics);
+        }
+
+        let mut properties = self.properties.clone();
+        for (key, value) in [
+            (EVENT_FIELD, event),
+            (LEVEL_FIELD, level.as_str()),
+            (MESSAGE_FIELD, message),
+        ] {
+            properties.0.insert(key.to_string(), value.into());
+        }
+        let mut payload = Vec::new();
+        self.write_payload(
+            PayloadFields::new(&self.dimensions, &properties),
+            &entries.iter().collect::<Vec<_>>(),
+            &mut payload,
+        )?;
+
+        let payload = String::from_utf8(payload)
+            .map_err(|err| MetricsError::Serialization(err.to_string()))?;
+        self.emit(&payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::RecordingSink;
+    use crate::MAX_METRICS;
+
+    #[test]
+    fn should_emit_event_with_its_metrics() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .dimension("service", "orders")
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+        metrics.add_metric("requests", MetricUnit::Count, 1.0);
+
+        metrics.emit_event(
+            "order_failed",
+            LogLevel::Error,
+            "payment declined",
+            &[("order_failed", MetricUnit::Count, 1.0)],
+        );
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads.len(), 1);
+        assert_eq!(payloads[0][EVENT_FIELD], "order_failed");
+        assert_eq!(payloads[0][LEVEL_FIELD], "ERROR");
+        assert_eq!(payloads[0][MESSAGE_FIELD], "payment declined");
+        assert_eq!(payloads[0]["service"], "orders");
+        assert_eq!(payloads[0]["order_failed"], 1.0);
+        assert!(payloads[0].get("requests").is_none());
+        assert!(metrics.contains("requests"));
+        assert!(metrics.property(EVENT_FIELD).is_none());
+    }
+
+    #[test]
+    fn should_merge_repeated_names_of_event() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test").sink(sink.clone()).build().unwrap();
+
+        metrics.emit_event(
+            "retried",
+            LogLevel::Warn,
+            "retried twice",
+            &[
+                ("attempt_ms", MetricUnit::Milliseconds, 10.0),
+                ("attempt_ms", MetricUnit::Milliseconds, 20.0),
+            ],
+        );
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads[0]["attempt_ms"], serde_json::json!([10.0, 20.0]));
+        assert_eq!(
+            payloads[0]["_aws"]["CloudWatchMetrics"][0]["Metrics"]
+                .as_array()
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn should_reject_events_over_the_metrics_limit() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test").sink(sink.clone()).build().unwrap();
+        let names = (0..=MAX_METRICS)
+            .map(|index| format!("metric_{index}"))
+            .collect::<Vec<_>>();
+        let values = names
+            .iter()
+            .map(|name| (name.as_str(), MetricUnit::Count, 1.0))
+            .collect::<Vec<_>>();
+
+        assert!(matches!(
+            metrics.try_emit_event("too_many", LogLevel::Info, "", &values),
+            Err(MetricsError::TooManyMetrics)
+        ));
+        assert!(matches!(
+            metrics.try_emit_event(
+                "mixed",
+                LogLevel::Info,
+                "",
+                &[
+                    ("latency", MetricUnit::Milliseconds, 1.0),
+                    ("latency", MetricUnit::Seconds, 1.0),
+                ],
+            ),
+            Err(MetricsError::InvalidMetric(_))
+        ));
+        assert!(sink.payloads().is_empty());
+    }
+}
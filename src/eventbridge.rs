@@ -0,0 +1,113 @@
+//! EventBridge `PutEvents` metrics.
+//!
+//! Available with the `eventbridge` feature.
+//!
+//! ```ignore
+//! let result = client.put_events().set_entries(Some(entries.clone())).send().await;
+//! match &result {
+//!     Ok(output) => metrics.record_put_events(&entries, output),
+//!     Err(_) => metrics.record_put_events_error(&entries),
+//! }
+//! ```
+//!
+//! Metrics are dimensioned by `event_bus` and `detail_type`:
+//! - `eventbridge_events_attempted`
+//! - `eventbridge_events_succeeded`
+//! - `eventbridge_events_failed`
+use aws_sdk_eventbridge::operation::put_events::PutEventsOutput;
+use aws_sdk_eventbridge::types::PutEventsRequestEntry;
+
+use crate::{Dimensions, Metrics};
+
+pub const EVENTS_ATTEMPTED_METRIC: &str = "eventbridge_events_attempted";
+pub const EVENTS_SUCCEEDED_METRIC: &str = "eventbridge_events_succeeded";
+pub const EVENTS_FAILED_METRIC: &str = "eventbridge_events_failed";
+
+fn entry_dimensions(entry: &PutEventsRequestEntry) -> Dimensions {
+    let mut dimensions = Dimensions::default();
+    dimensions.insert("event_bus", entry.event_bus_name().unwrap_or("default"));
+    dimensions.insert("detail_type", entry.detail_type().unwrap_or("unknown"));
+    dimensions
+}
+
+impl Metrics {
+    /// Records the outcome of a `PutEvents` call. Result entries are matched with the request
+    /// entries by position, as documented by EventBridge; entries without a result count as failed.
+    pub fn record_put_events(
+        &mut self,
+        entries: &[PutEventsRequestEntry],
+        output: &PutEventsOutput,
+    ) {
+        for (index, entry) in entries.iter().enumerate() {
+            let failed = output
+                .entries()
+                .get(index)
+                .is_none_or(|result| result.error_code().is_some());
+            self.record_put_event(entry, failed);
+        }
+    }
+
+    /// Records a `PutEvents` call which failed as a whole, e.g. because of throttling.
+    pub fn record_put_events_error(&mut self, entries: &[PutEventsRequestEntry]) {
+        for entry in entries {
+            self.record_put_event(entry, true);
+        }
+    }
+
+    fn record_put_event(&mut self, entry: &PutEventsRequestEntry, failed: bool) {
+        let dimensions = entry_dimensions(entry);
+        let (failed, succeeded) = if failed { (1.0, 0.0) } else { (0.0, 1.0) };
+        self.increment_with_dimensions(EVENTS_ATTEMPTED_METRIC, 1.0, dimensions.clone());
+        self.increment_with_dimensions(EVENTS_SUCCEEDED_METRIC, succeeded, dimensions.clone());
+        self.increment_with_dimensions(EVENTS_FAILED_METRIC, failed, dimensions);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use aws_sdk_eventbridge::types::PutEventsResultEntry;
+
+    use super::*;
+    use crate::sink::RecordingSink;
+
+    fn entry(detail_type: &str) -> PutEventsRequestEntry {
+        PutEventsRequestEntry::builder()
+            .event_bus_name("orders")
+            .detail_type(detail_type)
+            .build()
+    }
+
+    #[test]
+    fn should_record_events_per_bus_and_detail_type() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test").sink(sink.clone()).build().unwrap();
+        let entries = vec![
+            entry("OrderPlaced"),
+            entry("OrderPlaced"),
+            entry("OrderShipped"),
+        ];
+        let output = PutEventsOutput::builder()
+            .failed_entry_count(1)
+            .entries(PutEventsResultEntry::builder().event_id("1").build())
+            .entries(
+                PutEventsResultEntry::builder()
+                    .error_code("InternalFailure")
+                    .build(),
+            )
+            .entries(PutEventsResultEntry::builder().event_id("3").build())
+            .build();
+
+        metrics.record_put_events(&entries, &output);
+        metrics.flush_metrics();
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads.len(), 2);
+        assert_eq!(payloads[0]["event_bus"], "orders");
+        assert_eq!(payloads[0]["detail_type"], "OrderPlaced");
+        assert_eq!(payloads[0][EVENTS_ATTEMPTED_METRIC], 2.0);
+        assert_eq!(payloads[0][EVENTS_SUCCEEDED_METRIC], 1.0);
+        assert_eq!(payloads[0][EVENTS_FAILED_METRIC], 1.0);
+        assert_eq!(payloads[1]["detail_type"], "OrderShipped");
+        assert_eq!(payloads[1][EVENTS_SUCCEEDED_METRIC], 1.0);
+    }
+}
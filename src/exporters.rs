@@ -0,0 +1,115 @@
+//! Additional sinks attached and detached at runtime.
+//!
+//! Every payload written to the sink of the `Metrics` object is also written to the registered
+//! sinks, so third-party crates can ship their own exporters:
+//!
+//! ```
+//! use lambda_helpers_metrics::sink::NullSink;
+//! use lambda_helpers_metrics::{MetricUnit, Metrics};
+//!
+//! let mut metrics = Metrics::new("custom_lambdas", "service", "dummy_service");
+//! let exporter = metrics.register_sink(Box::new(NullSink));
+//!
+//! metrics.add_metric("orders", MetricUnit::Count, 1.0);
+//! metrics.flush_metrics();
+//!
+//! let detached = metrics.unregister_sink(exporter);
+//! assert!(detached.is_some());
+//! ```
+//!
+//! All sinks are attempted even if one of them fails, the first error is reported.
+use crate::sink::MetricsSink;
+use crate::{Metrics, MetricsError};
+
+/// Identifies a sink registered with [`Metrics::register_sink`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SinkId(u64);
+
+/// The registered sinks, in registration order.
+#[derive(Debug, Default)]
+pub(crate) struct Exporters {
+    next_id: u64,
+    sinks: Vec<(SinkId, Box<dyn MetricsSink>)>,
+}
+
+impl Exporters {
+    /// Writes the payload to all registered sinks.
+    pub(crate) fn emit(&self, payload: &str) -> Result<(), MetricsError> {
+        let mut first_error = None;
+        for (_, sink) in &self.sinks {
+            if let Err(err) = sink.emit(payload) {
+                first_error.get_or_insert(err);
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    }
+
+    /// Writes a log line which is not an EMF payload to all registered sinks.
+    pub(crate) fn emit_log_line(&self, line: &str) -> Result<(), MetricsError> {
+        let mut first_error = None;
+        for (_, sink) in &self.sinks {
+            if let Err(err) = sink.emit_log_line(line) {
+                first_error.get_or_insert(err);
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    }
+}
+
+impl Metrics {
+    /// Attaches a sink receiving every payload written to the sink of this object,
+    /// see [`crate::exporters`].
+    pub fn register_sink(&mut self, sink: Box<dyn MetricsSink>) -> SinkId {
+        let id = SinkId(self.exporters.next_id);
+        self.exporters.next_id += 1;
+        self.exporters.sinks.push((id, sink));
+        id
+    }
+
+    /// Detaches a registered sink, returning it if it was registered.
+    pub fn unregister_sink(&mut self, id: SinkId) -> Option<Box<dyn MetricsSink>> {
+        let index = self
+            .exporters
+            .sinks
+            .iter()
+            .position(|(registered, _)| *registered == id)?;
+        Some(self.exporters.sinks.remove(index).1)
+    }
+
+    /// Returns the number of registered sinks.
+    #[must_use]
+    pub fn registered_sinks(&self) -> usize {
+        self.exporters.sinks.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::RecordingSink;
+    use crate::MetricUnit;
+
+    #[test]
+    fn should_write_payloads_to_registered_sinks() {
+        let primary = RecordingSink::default();
+        let exporter = RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .sink(primary.clone())
+            .build()
+            .unwrap();
+        let id = metrics.register_sink(Box::new(exporter.clone()));
+
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+        metrics.flush_metrics();
+        assert!(metrics.unregister_sink(id).is_some());
+        assert!(metrics.unregister_sink(id).is_none());
+        metrics.add_metric("orders", MetricUnit::Count, 2.0);
+        metrics.flush_metrics();
+
+        assert_eq!(primary.payloads().len(), 2);
+        let exported = exporter.payloads();
+        assert_eq!(exported.len(), 1);
+        assert_eq!(exported[0], primary.payloads()[0]);
+        assert_eq!(metrics.registered_sinks(), 0);
+    }
+}
@@ -0,0 +1,163 @@
+//! Minimal payloads emitted when a payload can't be serialized.
+//!
+//! Without a fallback, a serialization error loses every metric of the payload. The fallback
+//! payload is written by hand with only what can't fail: the namespace, the dimensions, and
+//! the finite values of the metrics, without properties. It carries the
+//! `SerializationFallback` count, so the fallback is visible in `CloudWatch`. The original error
+//! is still a failure of the flush: it is returned by `try_flush_metrics`, passed to the
+//! `on_error` callback and counted as `MetricsLibraryErrors`.
+//!
+//! Payloads fail to serialize when they exceed the 256 KB limit of `CloudWatch` Logs events,
+//! e.g. because of large properties, which the fallback payload leaves out.
+use crate::{Metric, MetricUnit, Metrics, MAX_METRICS};
+
+/// Count of payloads replaced by a fallback payload.
+pub const SERIALIZATION_FALLBACK_METRIC: &str = "SerializationFallback";
+
+impl Metrics {
+    /// Returns the fallback payload of a chunk, see [`crate::fallback`].
+    pub(crate) fn fallback_payload(&self, entries: &[&Metric]) -> String {
+        let dimensions = self.payload_dimensions(entries);
+        let namespace = self.payload_namespace(entries, &dimensions);
+        let mut metrics = entries
+            .iter()
+            .filter_map(|metric| {
+                let values = metric
+                    .values
+                    .iter()
+                    .copied()
+                    .filter(|value| value.is_finite())
+                    .collect::<Vec<_>>();
+                (!values.is_empty()).then_some((metric.name.as_str(), metric.unit, values))
+            })
+            .take(MAX_METRICS - 1)
+            .collect::<Vec<_>>();
+        metrics.push((SERIALIZATION_FALLBACK_METRIC, MetricUnit::Count, vec![1.0]));
+
+        let mut out = String::new();
+        out.push_str(r#"{"_aws":{"Timestamp":"#);
+        out.push_str(&self.payload_timestamp(entries).to_string());
+        out.push_str(r#","CloudWatchMetrics":[{"Namespace":"#);
+        out.push_str(&json_str(&namespace));
+        out.push_str(r#","Dimensions":[["#);
+        let keys = dimensions
+            .iter()
+            .map(|(key, _)| json_str(key))
+            .collect::<Vec<_>>();
+        out.push_str(&keys.join(","));
+        out.push_str(r#"]],"Metrics":["#);
+        let definitions = metrics
+            .iter()
+            .map(|(name, unit, _)| {
+                format!(
+                    r#"{{"Name":{},"Unit":{}}}"#,
+                    json_str(name),
+                    json_str(unit.as_str())
+                )
+            })
+            .collect::<Vec<_>>();
+        out.push_str(&definitions.join(","));
+        out.push_str("]}]}");
+        for (key, value) in dimensions.iter() {
+            out.push(',');
+            out.push_str(&json_str(key));
+            out.push(':');
+            out.push_str(&json_str(value));
+        }
+        for (name, _, values) in &metrics {
+            out.push(',');
+            out.push_str(&json_str(name));
+            out.push(':');
+            let values = values
+                .iter()
+                .map(|value| serde_json::Value::from(*value).to_string())
+                .collect::<Vec<_>>();
+            match values.as_slice() {
+                [value] => out.push_str(value),
+                values => {
+                    out.push('[');
+                    out.push_str(&values.join(","));
+                    out.push(']');
+                }
+            }
+        }
+        out.push('}');
+        out
+    }
+}
+
+/// Returns the string as a JSON string, quoted and escaped.
+fn json_str(value: &str) -> String {
+    serde_json::Value::from(value).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_keep_finite_values_and_dimensions() {
+        let mut metrics = Metrics::builder("test")
+            .dimension("service", "check\"out")
+            .sink(crate::sink::NullSink)
+            .build()
+            .unwrap();
+        metrics.add_metric("orders", MetricUnit::Count, 2.0);
+        metrics.add_sample("latency", MetricUnit::Milliseconds, 12.5);
+        metrics.add_sample("latency", MetricUnit::Milliseconds, f64::NAN);
+        metrics.entries.push(Metric {
+            name: "broken".to_string(),
+            unit: MetricUnit::None,
+            values: vec![f64::INFINITY],
+            dimensions: crate::Dimensions::default(),
+            storage_resolution: None,
+            timestamp: None,
+            properties: Vec::new(),
+        });
+
+        let chunks = metrics.payload_chunks();
+        let payload: serde_json::Value =
+            serde_json::from_str(&metrics.fallback_payload(&chunks[0])).unwrap();
+        metrics.clear_buffer();
+
+        let directive = &payload["_aws"]["CloudWatchMetrics"][0];
+        assert_eq!(directive["Namespace"], "test");
+        assert_eq!(directive["Dimensions"], serde_json::json!([["service"]]));
+        assert_eq!(directive["Metrics"].as_array().unwrap().len(), 3);
+        assert_eq!(payload["service"], "check\"out");
+        assert_eq!(payload["orders"], 2.0);
+        assert_eq!(payload["latency"], 12.5);
+        assert!(payload.get("broken").is_none());
+        assert_eq!(payload[SERIALIZATION_FALLBACK_METRIC], 1.0);
+    }
+
+    #[test]
+    fn should_emit_fallback_and_report_error_of_oversized_payload() {
+        let sink = crate::sink::RecordingSink::default();
+        let errors = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = errors.clone();
+        let mut metrics = Metrics::builder("test")
+            .on_error(move |err| recorded.lock().unwrap().push(err.to_string()))
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+
+        metrics.add_property("request", &"x".repeat(300 * 1024));
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+        let result = metrics.try_flush_metrics();
+        metrics.add_property("request", "trimmed");
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+        metrics.flush_metrics();
+
+        assert!(matches!(result, Err(crate::MetricsError::Serialization(_))));
+        assert_eq!(errors.lock().unwrap().len(), 1);
+        let payloads = sink.payloads();
+        assert_eq!(payloads[0]["orders"], 1.0);
+        assert_eq!(payloads[0][SERIALIZATION_FALLBACK_METRIC], 1.0);
+        assert!(payloads[0].get("request").is_none());
+        assert_eq!(payloads[1][crate::self_metrics::ERRORS_METRIC], 1.0);
+        assert!(payloads[1]
+            .get(crate::self_metrics::DROPPED_METRIC)
+            .is_none());
+    }
+}
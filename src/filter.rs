@@ -0,0 +1,87 @@
+//! Include and exclude patterns of metric names, see [`crate::MetricsBuilder::include_metrics`].
+
+/// Metric names recorded, checked when a metric is added.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MetricFilter {
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+
+impl MetricFilter {
+    pub(crate) fn include(&mut self, pattern: &str) {
+        self.include.push(pattern.to_string());
+    }
+
+    pub(crate) fn exclude(&mut self, pattern: &str) {
+        self.exclude.push(pattern.to_string());
+    }
+
+    /// Returns `true` if the name matches no exclude pattern, and an include pattern
+    /// if there are any. Exclude patterns take precedence.
+    pub(crate) fn allows(&self, name: &str) -> bool {
+        !self.exclude.iter().any(|pattern| glob_match(pattern, name))
+            && (self.include.is_empty()
+                || self.include.iter().any(|pattern| glob_match(pattern, name)))
+    }
+}
+
+/// Matches a glob pattern, where `*` stands for any sequence of characters and `?` for any
+/// single character.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // position of the last `*` and the name position it was tried at, to backtrack
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MetricUnit, Metrics};
+
+    #[test]
+    fn should_match_glob_patterns() {
+        assert!(glob_match("debug_*", "debug_cache"));
+        assert!(glob_match("*_ms", "latency_ms"));
+        assert!(glob_match("db_*_count", "db_query_row_count"));
+        assert!(glob_match("v?", "v2"));
+        assert!(!glob_match("debug_*", "cache_debug"));
+        assert!(!glob_match("v?", "v10"));
+    }
+
+    #[test]
+    fn should_skip_filtered_metrics() {
+        let mut metrics = Metrics::builder("test")
+            .include_metrics("orders_*")
+            .include_metrics("debug_*")
+            .exclude_metrics("debug_*")
+            .build()
+            .unwrap();
+
+        metrics.add_metric("orders_created", MetricUnit::Count, 1.0);
+        metrics.add_metric("debug_cache", MetricUnit::Count, 1.0);
+        metrics.add_metric("latency", MetricUnit::Milliseconds, 1.0);
+
+        assert_eq!(metrics.value_of("orders_created"), Some(1.0));
+        assert_eq!(metrics.value_of("debug_cache"), None);
+        assert_eq!(metrics.value_of("latency"), None);
+        assert_eq!(metrics.metrics_remaining(), 99);
+    }
+}
@@ -0,0 +1,550 @@
+use chrono::{DateTime, SecondsFormat};
+use serde::Deserialize;
+
+use crate::{Environment, Metric, Metrics, MetricsError};
+
+/// The shape of the emitted payloads.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// The format of this crate.
+    #[default]
+    Standard,
+    /// Payloads shaped like the ones of the official `aws-embedded-metrics` libraries
+    /// (Node, Python, Java), so services mixing languages produce uniform metrics:
+    /// - default dimensions `LogGroup`, `ServiceName` and `ServiceType`
+    /// - properties describing the environment, e.g. `executionEnvironment` or `functionVersion`
+    /// - `StorageResolution` omitted for standard resolution metrics
+    AwsEmbeddedMetrics,
+    /// The smallest valid payload, for functions emitting many payloads where log bytes
+    /// are a real cost: `StorageResolution` is omitted for standard resolution metrics,
+    /// `Unit` for metrics without a unit (`None` is the default in EMF), and properties holding
+    /// an empty array or object. `Count` is kept, as omitting it would change the unit of the
+    /// metric in `CloudWatch`, and so is the empty dimension set of metrics without dimensions,
+    /// as `CloudWatch` creates a series per dimension set.
+    Compact,
+}
+
+/// Version of the payload layout, pinned with [`crate::MetricsBuilder::output_schema`] so
+/// upgrading the crate doesn't change the payloads parsed by log queries or subscription
+/// filters. A layout only changes under a new version, and `build` rejects the options which
+/// change the layout of the pinned version. Without a pinned version, payloads follow the
+/// layout of the options in use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum OutputSchema {
+    /// A single JSON object per line with:
+    /// - `_aws` holding `Timestamp` in milliseconds, then `CloudWatchMetrics`, a single
+    ///   directive with `Namespace`, a single dimension set in `Dimensions` and `Metrics`
+    ///   (`Name`, `Unit`, `StorageResolution`), then `LogGroupName` and `LogStreamName` when set
+    /// - the dimensions, then the properties, then the metric values at the top level, a value
+    ///   being a number for a single value and an array for several
+    ///
+    /// Rejects an [`OutputFormat`] other than `Standard`, the `timestamp` field of
+    /// [`LogFields`], rollups and Contributor Insights rules.
+    V1,
+    /// The layout of `V1`, with the changes of the options rejected by `V1`: `Unit` and
+    /// `StorageResolution` left out by the output format, a `timestamp` field after the
+    /// properties, the rollups as additional dimension sets, and Contributor Insights lines
+    /// after the payloads.
+    V2,
+}
+
+impl OutputSchema {
+    /// The most recent version.
+    pub const LATEST: OutputSchema = OutputSchema::V2;
+
+    /// Returns `true` if the version allows the options which change the layout of `V1`.
+    pub(crate) fn allows_layout_options(self) -> bool {
+        match self {
+            OutputSchema::V1 => false,
+            OutputSchema::V2 => true,
+        }
+    }
+
+    /// Returns an error if the option, which changes the layout, is not part of the version.
+    pub(crate) fn allow(self, option: &str) -> Result<(), MetricsError> {
+        if self.allows_layout_options() {
+            return Ok(());
+        }
+        Err(MetricsError::Configuration(format!(
+            "{option} changes the payload layout pinned by OutputSchema::{self:?}, \
+             select OutputSchema::V2"
+        )))
+    }
+}
+
+pub const LEVEL_FIELD: &str = "level";
+pub const MESSAGE_FIELD: &str = "message";
+pub const LOGGER_FIELD: &str = "logger";
+pub const TIMESTAMP_FIELD: &str = "timestamp";
+/// Environment variable set by Lambda to `JSON` when the function uses the JSON log format.
+pub const LOG_FORMAT_VAR: &str = "AWS_LAMBDA_LOG_FORMAT";
+/// Environment variable set by Lambda with the application log level of the JSON log format.
+pub const LOG_LEVEL_VAR: &str = "AWS_LAMBDA_LOG_LEVEL";
+
+/// Level of the `level` field of payloads, see [`LogFields`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    #[default]
+    Info,
+    Warn,
+    Error,
+    Fatal,
+}
+
+impl LogLevel {
+    /// Returns the level as written in the payload, e.g. `INFO`,
+    /// as expected by the JSON log-level filtering of Lambda.
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LogLevel::Trace => "TRACE",
+            LogLevel::Debug => "DEBUG",
+            LogLevel::Info => "INFO",
+            LogLevel::Warn => "WARN",
+            LogLevel::Error => "ERROR",
+            LogLevel::Fatal => "FATAL",
+        }
+    }
+
+    /// Parses a level, ignoring the case, e.g. the value of `AWS_LAMBDA_LOG_LEVEL`.
+    #[must_use]
+    pub fn parse(level: &str) -> Option<Self> {
+        [
+            LogLevel::Trace,
+            LogLevel::Debug,
+            LogLevel::Info,
+            LogLevel::Warn,
+            LogLevel::Error,
+            LogLevel::Fatal,
+        ]
+        .into_iter()
+        .find(|candidate| candidate.as_str().eq_ignore_ascii_case(level.trim()))
+    }
+}
+
+/// `level`, `message` and `logger` fields added to every payload, so EMF lines read like
+/// the other structured logs of the function in `CloudWatch Logs` (e.g. in Live Tail)
+/// and pass JSON log-level filtering instead of appearing as opaque blobs.
+///
+/// ```
+/// use lambda_helpers_metrics::{LogFields, Metrics};
+///
+/// let metrics = Metrics::builder("custom_lambdas")
+///     .log_fields(LogFields::default().message("request metrics").logger("orders"))
+///     .build()
+///     .unwrap();
+/// ```
+///
+/// Functions using the JSON log format of Lambda (Advanced Logging Controls) filter the lines by
+/// their `level` against the application log level, and lines without one are not recognised as
+/// structured logs. [`LogFields::lambda_json`] shapes the payloads like the JSON logs of Lambda,
+/// with a `timestamp` field and a level which passes the filter, while `_aws` stays at the top
+/// level of the line, where `CloudWatch` extracts the metrics from. It is used by default in
+/// Lambda when the JSON log format is enabled, unless fields are set with
+/// [`MetricsBuilder::log_fields`](crate::MetricsBuilder::log_fields).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogFields {
+    pub(crate) level: LogLevel,
+    pub(crate) message: String,
+    pub(crate) logger: String,
+    pub(crate) timestamp: bool,
+}
+
+impl Default for LogFields {
+    fn default() -> Self {
+        Self {
+            level: LogLevel::Info,
+            message: "metrics".to_string(),
+            logger: env!("CARGO_PKG_NAME").to_string(),
+            timestamp: false,
+        }
+    }
+}
+
+impl LogFields {
+    /// Fields of the JSON log format of Lambda: a `timestamp` field, and the `level` set to the
+    /// application log level from `AWS_LAMBDA_LOG_LEVEL` when it is above `INFO`, so the
+    /// payloads are not filtered out.
+    #[must_use]
+    pub fn lambda_json() -> Self {
+        Self::lambda_json_from(|key| std::env::var(key).ok())
+    }
+
+    pub(crate) fn lambda_json_from(var: impl Fn(&str) -> Option<String>) -> Self {
+        let level = var(LOG_LEVEL_VAR)
+            .and_then(|level| LogLevel::parse(&level))
+            .map_or(LogLevel::Info, |level| level.max(LogLevel::Info));
+        Self::default().level(level).timestamp(true)
+    }
+
+    /// Returns `true` if Lambda is configured with the JSON log format, see [`LOG_FORMAT_VAR`].
+    #[must_use]
+    pub fn lambda_json_enabled() -> bool {
+        json_log_format(|key| std::env::var(key).ok())
+    }
+
+    /// The fields used when none are set on the builder: [`LogFields::lambda_json`] in Lambda
+    /// with the JSON log format, without the `timestamp` field if the pinned schema rejects it.
+    pub(crate) fn detect(
+        environment: Environment,
+        schema: Option<OutputSchema>,
+        var: impl Fn(&str) -> Option<String>,
+    ) -> Option<Self> {
+        if environment != Environment::Lambda || !json_log_format(&var) {
+            return None;
+        }
+        let timestamp = schema.is_none_or(OutputSchema::allows_layout_options);
+        Some(Self::lambda_json_from(var).timestamp(timestamp))
+    }
+
+    /// Sets the `level` field, `INFO` by default.
+    #[must_use]
+    pub fn level(mut self, level: LogLevel) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Sets the `message` field, `metrics` by default.
+    #[must_use]
+    pub fn message(mut self, message: &str) -> Self {
+        self.message = message.to_string();
+        self
+    }
+
+    /// Sets the `logger` field, the name of this crate by default.
+    #[must_use]
+    pub fn logger(mut self, logger: &str) -> Self {
+        self.logger = logger.to_string();
+        self
+    }
+
+    /// Adds a `timestamp` field with the time of the payload in RFC 3339, e.g.
+    /// `2024-06-01T12:00:00.123Z`, like the JSON logs of Lambda. Disabled by default.
+    #[must_use]
+    pub fn timestamp(mut self, enabled: bool) -> Self {
+        self.timestamp = enabled;
+        self
+    }
+
+    pub(crate) fn properties(&self) -> [(&'static str, &str); 3] {
+        [
+            (LEVEL_FIELD, self.level.as_str()),
+            (MESSAGE_FIELD, &self.message),
+            (LOGGER_FIELD, &self.logger),
+        ]
+    }
+}
+
+fn json_log_format(var: impl Fn(&str) -> Option<String>) -> bool {
+    var(LOG_FORMAT_VAR).is_some_and(|format| format.eq_ignore_ascii_case("JSON"))
+}
+
+impl Metrics {
+    /// The `timestamp` field of the payload, if enabled with [`LogFields::timestamp`].
+    pub(crate) fn log_timestamp(&self, entries: &[&Metric]) -> Option<String> {
+        if !self.log_timestamp {
+            return None;
+        }
+        DateTime::from_timestamp_millis(self.payload_timestamp(entries))
+            .map(|timestamp| timestamp.to_rfc3339_opts(SecondsFormat::Millis, true))
+    }
+}
+
+/// Default dimensions and properties of the `aws-embedded-metrics` libraries.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct EmbeddedMetricsContext {
+    pub(crate) dimensions: Vec<(String, String)>,
+    pub(crate) properties: Vec<(String, String)>,
+}
+
+impl EmbeddedMetricsContext {
+    pub(crate) fn detect(environment: Environment) -> Self {
+        Self::detect_from(environment, |key| std::env::var(key).ok())
+    }
+
+    pub(crate) fn detect_from(
+        environment: Environment,
+        var: impl Fn(&str) -> Option<String>,
+    ) -> Self {
+        let var = |key: &str| var(key).filter(|value| !value.is_empty());
+        let mut context = Self::default();
+        let (default_name, default_type) = match environment {
+            Environment::Lambda => (var("AWS_LAMBDA_FUNCTION_NAME"), "AWS::Lambda::Function"),
+            Environment::Ecs => (None, "AWS::ECS::Container"),
+            Environment::Local => (None, "Unknown"),
+        };
+        let service_name = var("AWS_EMF_SERVICE_NAME")
+            .or(default_name)
+            .unwrap_or_else(|| "Unknown".to_string());
+        let service_type = var("AWS_EMF_SERVICE_TYPE").unwrap_or_else(|| default_type.to_string());
+        let log_group = var("AWS_EMF_LOG_GROUP_NAME")
+            .or_else(|| var("AWS_LAMBDA_LOG_GROUP_NAME"))
+            .unwrap_or_else(|| format!("{service_name}-metrics"));
+        context.dimensions = vec![
+            ("LogGroup".to_string(), log_group),
+            ("ServiceName".to_string(), service_name),
+            ("ServiceType".to_string(), service_type),
+        ];
+
+        if environment == Environment::Lambda {
+            let properties = [
+                ("executionEnvironment", "AWS_EXECUTION_ENV"),
+                ("memorySize", "AWS_LAMBDA_FUNCTION_MEMORY_SIZE"),
+                ("functionVersion", "AWS_LAMBDA_FUNCTION_VERSION"),
+                ("logStreamId", "AWS_LAMBDA_LOG_STREAM_NAME"),
+            ];
+            for (property, key) in properties {
+                if let Some(value) = var(key) {
+                    context.properties.push((property.to_string(), value));
+                }
+            }
+            if let Some(trace_id) = var("_X_AMZN_TRACE_ID").filter(|id| id.contains("Sampled=1")) {
+                context.properties.push(("traceId".to_string(), trace_id));
+            }
+        }
+        context
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::environment::tests::vars;
+    use crate::sink::RecordingSink;
+    use crate::{MetricUnit, Metrics};
+
+    #[test]
+    fn should_detect_lambda_context() {
+        let context = EmbeddedMetricsContext::detect_from(
+            Environment::Lambda,
+            vars(&[
+                ("AWS_LAMBDA_FUNCTION_NAME", "orders"),
+                ("AWS_LAMBDA_LOG_GROUP_NAME", "/aws/lambda/orders"),
+                ("AWS_LAMBDA_FUNCTION_VERSION", "$LATEST"),
+                ("_X_AMZN_TRACE_ID", "Root=1-abc;Sampled=0"),
+            ]),
+        );
+
+        assert_eq!(
+            context.dimensions,
+            [
+                ("LogGroup".to_string(), "/aws/lambda/orders".to_string()),
+                ("ServiceName".to_string(), "orders".to_string()),
+                (
+                    "ServiceType".to_string(),
+                    "AWS::Lambda::Function".to_string()
+                ),
+            ]
+        );
+        assert_eq!(
+            context.properties,
+            [("functionVersion".to_string(), "$LATEST".to_string())]
+        );
+    }
+
+    #[test]
+    fn should_fall_back_to_unknown_service() {
+        let context = EmbeddedMetricsContext::detect_from(Environment::Local, vars(&[]));
+
+        assert_eq!(context.dimensions[0].1, "Unknown-metrics");
+        assert_eq!(context.dimensions[2].1, "Unknown");
+        assert!(context.properties.is_empty());
+    }
+
+    #[test]
+    fn should_shape_payload_like_aws_embedded_metrics() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .environment(Environment::Local)
+            .output_format(OutputFormat::AwsEmbeddedMetrics)
+            .dimension("operation", "checkout")
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+        metrics.flush_metrics();
+
+        let payload = &sink.payloads()[0];
+        let directive = &payload["_aws"]["CloudWatchMetrics"][0];
+        assert_eq!(
+            directive["Dimensions"][0],
+            serde_json::json!(["LogGroup", "ServiceName", "ServiceType", "operation"])
+        );
+        assert!(directive["Metrics"][0].get("StorageResolution").is_none());
+        assert_eq!(payload["ServiceType"], "Unknown");
+    }
+
+    #[test]
+    fn should_omit_default_fields_in_compact_format() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .output_format(OutputFormat::Compact)
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+
+        metrics.add_json_property("tags", serde_json::json!([]));
+        metrics.add_json_property("order", serde_json::json!({}));
+        metrics.add_json_property("items", serde_json::json!([1]));
+        metrics.add_metric("ratio", MetricUnit::None, 0.5);
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+        metrics.flush_metrics();
+
+        let payload = &sink.payloads()[0];
+        assert!(payload.get("tags").is_none());
+        assert!(payload.get("order").is_none());
+        assert_eq!(payload["items"], serde_json::json!([1]));
+        assert_eq!(
+            payload["_aws"]["CloudWatchMetrics"][0]["Dimensions"],
+            serde_json::json!([[]])
+        );
+        let definitions = &payload["_aws"]["CloudWatchMetrics"][0]["Metrics"];
+        assert_eq!(definitions[0], serde_json::json!({ "Name": "ratio" }));
+        assert_eq!(
+            definitions[1],
+            serde_json::json!({ "Name": "orders", "Unit": "Count" })
+        );
+    }
+
+    #[test]
+    fn should_add_log_fields() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .log_fields(LogFields::default().level(LogLevel::Warn).logger("orders"))
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+        metrics.flush_metrics();
+
+        let payload = &sink.payloads()[0];
+        assert_eq!(payload[LEVEL_FIELD], "WARN");
+        assert_eq!(payload[MESSAGE_FIELD], "metrics");
+        assert_eq!(payload[LOGGER_FIELD], "orders");
+    }
+
+    #[test]
+    fn should_default_to_lambda_json_fields_with_json_log_format() {
+        let json = vars(&[(LOG_FORMAT_VAR, "JSON"), (LOG_LEVEL_VAR, "WARN")]);
+
+        let fields = LogFields::detect(Environment::Lambda, None, &json).unwrap();
+        assert_eq!(fields.level, LogLevel::Warn);
+        assert!(fields.timestamp);
+        let pinned = LogFields::detect(Environment::Lambda, Some(OutputSchema::V1), &json);
+        assert!(!pinned.unwrap().timestamp);
+        assert!(LogFields::detect(Environment::Local, None, &json).is_none());
+        assert!(
+            LogFields::detect(Environment::Lambda, None, vars(&[(LOG_FORMAT_VAR, "Text")]))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn should_shape_payload_like_lambda_json_logs() {
+        let fields = LogFields::lambda_json_from(vars(&[(LOG_LEVEL_VAR, "warn")]));
+        let sink = RecordingSink::default();
+        let clock = crate::clock::ManualClock::new(
+            DateTime::from_timestamp_millis(1_717_243_200_123).unwrap(),
+        );
+        let mut metrics = Metrics::builder("test")
+            .log_fields(fields)
+            .clock(clock)
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+        metrics.flush_metrics();
+
+        let payload = &sink.payloads()[0];
+        assert_eq!(payload[LEVEL_FIELD], "WARN");
+        assert_eq!(payload[TIMESTAMP_FIELD], "2024-06-01T12:00:00.123Z");
+        assert_eq!(payload["_aws"]["Timestamp"], 1_717_243_200_123_i64);
+        assert_eq!(
+            LogFields::lambda_json_from(vars(&[(LOG_LEVEL_VAR, "debug")])).level,
+            LogLevel::Info
+        );
+    }
+
+    #[test]
+    fn should_reject_layout_options_outside_pinned_schema() {
+        let pinned = |schema| Metrics::builder("test").output_schema(schema);
+
+        assert!(pinned(OutputSchema::V1)
+            .output_format(OutputFormat::Compact)
+            .build()
+            .is_err());
+        assert!(pinned(OutputSchema::V1)
+            .log_fields(LogFields::default().timestamp(true))
+            .build()
+            .is_err());
+        assert!(pinned(OutputSchema::V1)
+            .rollups(&[&["service"]])
+            .build()
+            .is_err());
+        assert!(pinned(OutputSchema::V1)
+            .log_fields(LogFields::default())
+            .build()
+            .is_ok());
+        assert!(pinned(OutputSchema::V2)
+            .output_format(OutputFormat::Compact)
+            .rollups(&[&["service"]])
+            .build()
+            .is_ok());
+        assert!(Metrics::builder("test")
+            .output_format(OutputFormat::Compact)
+            .build()
+            .is_ok());
+
+        let mut metrics = pinned(OutputSchema::V1).dry_run(true).build().unwrap();
+        metrics.enable_rollups(&[&["service"]]);
+        assert!(metrics.rollups.is_empty());
+    }
+
+    #[test]
+    fn should_pin_v1_payload_layout() {
+        let start = DateTime::from_timestamp_millis(1_700_000_000_000).unwrap();
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .output_schema(OutputSchema::V1)
+            .clock(crate::clock::ManualClock::new(start))
+            .dimension("service", "orders")
+            .log_group_name("orders-metrics")
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+        metrics.add_metric("placed", MetricUnit::Count, 2.0);
+        metrics.add_sample("latency", MetricUnit::Milliseconds, 12.5);
+        metrics.add_sample("latency", MetricUnit::Milliseconds, 7.0);
+        metrics.add_property("order_id", "7051cd10");
+        metrics.flush_metrics();
+
+        assert_eq!(
+            sink.payloads()[0],
+            serde_json::json!({
+                "_aws": {
+                    "Timestamp": 1_700_000_000_000_i64,
+                    "CloudWatchMetrics": [{
+                        "Namespace": "test",
+                        "Dimensions": [["service"]],
+                        "Metrics": [
+                            { "Name": "placed", "Unit": "Count", "StorageResolution": 60 },
+                            { "Name": "latency", "Unit": "Milliseconds", "StorageResolution": 60 },
+                        ],
+                    }],
+                    "LogGroupName": "orders-metrics",
+                },
+                "service": "orders",
+                "order_id": "7051cd10",
+                "placed": 2.0,
+                "latency": [12.5, 7.0],
+            })
+        );
+    }
+}
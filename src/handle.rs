@@ -0,0 +1,197 @@
+//! Typed metric handles.
+//!
+//! A handle binds a metric name to the semantics of the metric, so only the operations
+//! which make sense for it are available: a counter can only be incremented, a gauge
+//! only set, and a timer only records durations.
+//!
+//! ```
+//! use std::time::Duration;
+//! use lambda_helpers_metrics::{MetricUnit, Metrics};
+//!
+//! let mut metrics = Metrics::new("custom_lambdas", "service", "dummy_service");
+//!
+//! metrics.counter("orders").inc();
+//! metrics.gauge("queue_depth").set(42.0);
+//! metrics.gauge("cache_size").with_unit(MetricUnit::Bytes).set(1024.0);
+//! metrics.timer("db_ms").record(Duration::from_millis(12));
+//! let rows = metrics.timer("query_ms").time(|| 3);
+//! ```
+use std::time::Duration;
+
+use crate::{Dimensions, MetricUnit, Metrics};
+
+/// A `Count` metric which is only incremented, see [`Metrics::counter`].
+#[derive(Debug)]
+pub struct Counter<'a> {
+    metrics: &'a mut Metrics,
+    name: &'a str,
+}
+
+impl Counter<'_> {
+    /// Increments the counter by one.
+    pub fn inc(&mut self) {
+        self.inc_by(1.0);
+    }
+
+    /// Increments the counter by `by`.
+    pub fn inc_by(&mut self, by: f64) {
+        self.metrics.increment(self.name, by);
+    }
+}
+
+/// A metric holding the last value set, see [`Metrics::gauge`].
+#[derive(Debug)]
+pub struct Gauge<'a> {
+    metrics: &'a mut Metrics,
+    name: &'a str,
+    unit: MetricUnit,
+}
+
+impl Gauge<'_> {
+    /// Sets the unit of the gauge, `None` by default.
+    #[must_use]
+    pub fn with_unit(mut self, unit: MetricUnit) -> Self {
+        self.unit = unit;
+        self
+    }
+
+    /// Sets the value, replacing the value buffered since the last flush.
+    pub fn set(&mut self, value: f64) {
+        self.metrics
+            .set_gauge_with_dimensions(self.name, self.unit, value, Dimensions::default());
+    }
+}
+
+/// A `Milliseconds` metric recording durations as samples, see [`Metrics::timer`].
+#[derive(Debug)]
+pub struct Timer<'a> {
+    metrics: &'a mut Metrics,
+    name: &'a str,
+}
+
+impl Timer<'_> {
+    /// Records a duration.
+    pub fn record(&mut self, duration: Duration) {
+        self.record_ms(duration.as_secs_f64() * 1000.0);
+    }
+
+    /// Records a duration given in milliseconds.
+    pub fn record_ms(&mut self, millis: f64) {
+        self.metrics
+            .add_sample(self.name, MetricUnit::Milliseconds, millis);
+    }
+
+    /// Runs `f` and records how long it took.
+    pub fn time<T>(&mut self, f: impl FnOnce() -> T) -> T {
+        let start = self.metrics.clock.instant();
+        let result = f();
+        let elapsed = self
+            .metrics
+            .clock
+            .instant()
+            .saturating_duration_since(start);
+        self.record(elapsed);
+        result
+    }
+}
+
+impl Metrics {
+    /// Returns a handle to the counter with the given name.
+    pub fn counter<'a>(&'a mut self, name: &'a str) -> Counter<'a> {
+        Counter {
+            metrics: self,
+            name,
+        }
+    }
+
+    /// Returns a handle to the gauge with the given name.
+    pub fn gauge<'a>(&'a mut self, name: &'a str) -> Gauge<'a> {
+        Gauge {
+            metrics: self,
+            name,
+            unit: MetricUnit::None,
+        }
+    }
+
+    /// Returns a handle to the timer with the given name.
+    pub fn timer<'a>(&'a mut self, name: &'a str) -> Timer<'a> {
+        Timer {
+            metrics: self,
+            name,
+        }
+    }
+
+    pub(crate) fn set_gauge_with_dimensions(
+        &mut self,
+        name: &str,
+        unit: MetricUnit,
+        value: f64,
+        dimensions: Dimensions,
+    ) {
+        let metric = self.new_metric_with_dimensions(name, unit, value, &dimensions);
+        let existing = self.entries.iter_mut().find(|entry| {
+            entry.name == name
+                && entry.dimensions == metric.dimensions
+                && entry.properties == metric.properties
+        });
+        match existing {
+            Some(entry) => {
+                entry.unit = unit;
+                entry.values = vec![value];
+            }
+            None => self.push_or_drop(metric),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metrics() -> Metrics {
+        Metrics::builder("test")
+            .sink(crate::sink::NullSink)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn should_accumulate_counter() {
+        let mut metrics = metrics();
+
+        metrics.counter("orders").inc();
+        metrics.counter("orders").inc_by(2.0);
+
+        assert_eq!(metrics.values_of("orders"), Some([3.0].as_slice()));
+        assert_eq!(metrics.unit_of("orders"), Some(MetricUnit::Count));
+    }
+
+    #[test]
+    fn should_keep_last_gauge_value() {
+        let mut metrics = metrics();
+
+        metrics.gauge("queue_depth").set(5.0);
+        let mut gauge = metrics.gauge("queue_depth").with_unit(MetricUnit::Count);
+        gauge.set(7.0);
+        gauge.set(3.0);
+
+        assert_eq!(metrics.len(), 1);
+        assert_eq!(metrics.values_of("queue_depth"), Some([3.0].as_slice()));
+        assert_eq!(metrics.unit_of("queue_depth"), Some(MetricUnit::Count));
+    }
+
+    #[test]
+    fn should_record_timer_samples() {
+        let mut metrics = metrics();
+
+        metrics.timer("db_ms").record(Duration::from_millis(12));
+        metrics.timer("db_ms").record_ms(3.5);
+        assert_eq!(metrics.timer("db_ms").time(|| "rows"), "rows");
+
+        assert_eq!(metrics.len(), 1);
+        let values = metrics.values_of("db_ms").unwrap();
+        assert_eq!(&values[..2], &[12.0, 3.5]);
+        assert_eq!(values.len(), 3);
+        assert_eq!(metrics.unit_of("db_ms"), Some(MetricUnit::Milliseconds));
+    }
+}
@@ -0,0 +1,303 @@
+//! Handler wrapper managing the lifecycle of `Metrics`.
+//!
+//! Available with the `lambda` feature.
+//!
+//! ```ignore
+//! use lambda_helpers_metrics::config::MetricsConfig;
+//! use lambda_helpers_metrics::handler::run_with_metrics;
+//! use lambda_helpers_metrics::{MetricUnit, Metrics};
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<(), Error> {
+//!     let config = MetricsConfig::from_env()?;
+//!     run_with_metrics(
+//!         move || Metrics::builder("custom_lambdas").config(&config).build(),
+//!         async |event: LambdaEvent<Value>, metrics: &mut Metrics| {
+//!             metrics.add_metric("orders", MetricUnit::Count, 1.0);
+//!             Ok(event.payload)
+//!         },
+//!     )
+//!     .await
+//! }
+//! ```
+//!
+//! A `Metrics` object is created for each invocation and flushed once the handler returns,
+//! whether it succeeded or not. Handlers returning a streaming response can be wrapped with
+//! [`with_metrics`] and passed to `lambda_runtime::run`.
+//!
+//! With the `macros` feature, a handler can instead be annotated with `#[lambda_metrics]`,
+//! which records into the [current context](crate::context) through [`instrument`]:
+//!
+//! ```ignore
+//! use lambda_helpers_metrics::{context, lambda_metrics};
+//!
+//! #[lambda_metrics]
+//! async fn handler(event: LambdaEvent<Value>) -> Result<Value, Error> {
+//!     context::with_current(|metrics| metrics.increment("orders", 1.0));
+//!     Ok(event.payload)
+//! }
+//! ```
+use std::future::Future;
+use std::rc::Rc;
+
+use lambda_runtime::{service_fn, Context, Error, LambdaEvent, Service};
+use serde::{Deserialize, Serialize};
+
+use crate::context::{self, MetricsHandle};
+use crate::{mode, Metrics, MetricsError};
+
+/// Wraps the handler into a service which can be passed to `lambda_runtime::run`,
+/// see the [module documentation](self).
+pub fn with_metrics<A, R, N, F>(
+    new_metrics: N,
+    handler: F,
+) -> impl Service<LambdaEvent<A>, Response = R, Error = Error>
+where
+    N: Fn() -> Result<Metrics, MetricsError> + 'static,
+    F: AsyncFn(LambdaEvent<A>, &mut Metrics) -> Result<R, Error> + 'static,
+{
+    let new_metrics = Rc::new(new_metrics);
+    let handler = Rc::new(handler);
+    service_fn(move |event: LambdaEvent<A>| {
+        let new_metrics = Rc::clone(&new_metrics);
+        let handler = Rc::clone(&handler);
+        async move {
+            let mut metrics = new_metrics()?;
+            let result = handler(event, &mut metrics).await;
+            metrics.flush_metrics();
+            result
+        }
+    })
+}
+
+/// Wraps the handler so the serialized sizes of the event and of the successful response are
+/// recorded, see [`crate::payload_size`]:
+///
+/// ```ignore
+/// run_with_metrics(new_metrics, with_payload_sizes(handler)).await
+/// ```
+pub fn with_payload_sizes<A, R, F>(
+    handler: F,
+) -> impl AsyncFn(LambdaEvent<A>, &mut Metrics) -> Result<R, Error>
+where
+    F: AsyncFn(LambdaEvent<A>, &mut Metrics) -> Result<R, Error>,
+    A: Serialize,
+    R: Serialize,
+{
+    async move |event: LambdaEvent<A>, metrics: &mut Metrics| {
+        metrics.record_event_size(&event.payload);
+        let result = handler(event, metrics).await;
+        if let Ok(response) = &result {
+            metrics.record_response_size(response);
+        }
+        result
+    }
+}
+
+/// Wraps the handler so the dimensions and properties selected from the event are added
+/// before it runs, see [`crate::selectors`]:
+///
+/// ```ignore
+/// let new_metrics = || {
+///     Metrics::builder("custom_lambdas")
+///         .event_dimension("$.detail.orderType", "order_type")
+///         .build()
+/// };
+/// run_with_metrics(new_metrics, with_event_fields(handler)).await
+/// ```
+pub fn with_event_fields<A, R, F>(
+    handler: F,
+) -> impl AsyncFn(LambdaEvent<A>, &mut Metrics) -> Result<R, Error>
+where
+    F: AsyncFn(LambdaEvent<A>, &mut Metrics) -> Result<R, Error>,
+    A: Serialize,
+{
+    async move |event: LambdaEvent<A>, metrics: &mut Metrics| {
+        metrics.extract_event_fields(&event.payload);
+        handler(event, metrics).await
+    }
+}
+
+/// Runs the Lambda runtime with the handler, see [`with_metrics`].
+///
+/// # Errors
+///
+/// Will return `Err` if the runtime fails, see `lambda_runtime::run`
+pub async fn run_with_metrics<A, R, N, F>(new_metrics: N, handler: F) -> Result<(), Error>
+where
+    N: Fn() -> Result<Metrics, MetricsError> + 'static,
+    F: AsyncFn(LambdaEvent<A>, &mut Metrics) -> Result<R, Error> + 'static,
+    A: for<'de> Deserialize<'de>,
+    R: Serialize,
+{
+    lambda_runtime::run(with_metrics(new_metrics, handler)).await
+}
+
+/// Runs the handler with `metrics` as its [scoped](context::scope) context, then records the
+/// standard invocation metrics (see [`Metrics::emit_standard_metrics`]) and flushes. Used by
+/// `#[lambda_metrics]`. If the metrics couldn't be created, the error is reported and the
+/// handler runs without them.
+pub async fn instrument<R, E>(
+    invocation: &Context,
+    metrics: Result<Metrics, MetricsError>,
+    handler: impl Future<Output = Result<R, E>>,
+) -> Result<R, E> {
+    let metrics = match metrics {
+        Ok(metrics) => metrics,
+        Err(err) => {
+            mode::report(&err);
+            return handler.await;
+        }
+    };
+    let handle = MetricsHandle::new(metrics);
+    let start = handle.with(|metrics| metrics.clock.instant());
+    let result = context::scope(handle.clone(), handler).await;
+    handle.with(|metrics| {
+        let duration = metrics.clock.instant().saturating_duration_since(start);
+        metrics.emit_standard_metrics(invocation, &result, duration);
+    });
+    handle.flush();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::pin::pin;
+    use std::task::{Context, Waker};
+
+    use serde_json::Value;
+
+    use super::*;
+    use crate::sink::RecordingSink;
+    use crate::MetricUnit;
+
+    #[test]
+    fn should_flush_after_each_invocation() {
+        let sink = RecordingSink::default();
+        let factory_sink = sink.clone();
+        let mut service = with_metrics(
+            move || Metrics::builder("test").sink(factory_sink.clone()).build(),
+            async |event: LambdaEvent<bool>, metrics: &mut Metrics| {
+                metrics.add_metric("orders", MetricUnit::Count, 1.0);
+                if event.payload {
+                    Ok("done")
+                } else {
+                    Err(Error::from("failed"))
+                }
+            },
+        );
+
+        for payload in [true, false] {
+            let event = LambdaEvent::new(payload, lambda_runtime::Context::default());
+            let mut call = pin!(service.call(event));
+            let mut context = Context::from_waker(Waker::noop());
+            let result = std::future::Future::poll(call.as_mut(), &mut context);
+            assert!(result.is_ready());
+        }
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads.len(), 2);
+        assert_eq!(payloads[1]["orders"], 1.0);
+    }
+
+    #[test]
+    fn should_add_event_fields_before_handler() {
+        let sink = RecordingSink::default();
+        let factory_sink = sink.clone();
+        let mut service = with_metrics(
+            move || {
+                Metrics::builder("test")
+                    .event_dimension("$.kind", "kind")
+                    .sink(factory_sink.clone())
+                    .build()
+            },
+            with_event_fields(async |_: LambdaEvent<Value>, metrics: &mut Metrics| {
+                metrics.add_metric("orders", MetricUnit::Count, 1.0);
+                Ok(())
+            }),
+        );
+
+        let event = LambdaEvent::new(
+            serde_json::json!({ "kind": "refund" }),
+            lambda_runtime::Context::default(),
+        );
+        let mut call = pin!(service.call(event));
+        let mut context = Context::from_waker(Waker::noop());
+        let result = std::future::Future::poll(call.as_mut(), &mut context);
+
+        assert!(result.is_ready());
+        assert_eq!(sink.payloads()[0]["kind"], "refund");
+    }
+
+    #[test]
+    fn should_record_payload_sizes() {
+        let sink = RecordingSink::default();
+        let factory_sink = sink.clone();
+        let mut service = with_metrics(
+            move || Metrics::builder("test").sink(factory_sink.clone()).build(),
+            with_payload_sizes(async |event: LambdaEvent<String>, _: &mut Metrics| {
+                Ok(event.payload.repeat(2))
+            }),
+        );
+
+        let event = LambdaEvent::new("abc".to_string(), lambda_runtime::Context::default());
+        let mut call = pin!(service.call(event));
+        let mut context = Context::from_waker(Waker::noop());
+        let result = std::future::Future::poll(call.as_mut(), &mut context);
+
+        assert!(result.is_ready());
+        let payload = &sink.payloads()[0];
+        assert_eq!(payload[crate::payload_size::EVENT_SIZE_METRIC], 5.0);
+        assert_eq!(payload[crate::payload_size::RESPONSE_SIZE_METRIC], 8.0);
+    }
+
+    #[test]
+    fn should_time_instrumented_handler_with_clock() {
+        let sink = RecordingSink::default();
+        let clock = crate::clock::ManualClock::new(chrono::Utc::now());
+        let metrics = Metrics::builder("test")
+            .clock(clock.clone())
+            .sink(sink.clone())
+            .build();
+        let invocation = lambda_runtime::Context::default();
+
+        let mut call = pin!(instrument(&invocation, metrics, async {
+            clock.advance(std::time::Duration::from_millis(250));
+            Ok::<_, Error>(())
+        }));
+        let mut context = Context::from_waker(Waker::noop());
+        let result = std::future::Future::poll(call.as_mut(), &mut context);
+
+        assert!(result.is_ready());
+        assert_eq!(
+            sink.payloads()[0][crate::invocation::DURATION_METRIC],
+            250.0
+        );
+    }
+
+    #[cfg(feature = "macros")]
+    #[test]
+    fn should_instrument_annotated_handler() {
+        use crate::lambda_metrics;
+
+        #[lambda_metrics]
+        async fn handler(event: LambdaEvent<RecordingSink>) -> Result<&'static str, Error> {
+            context::with_current(|metrics| {
+                metrics.register_sink(Box::new(event.payload.clone()));
+                metrics.increment("orders", 1.0);
+            });
+            Ok("done")
+        }
+
+        let sink = RecordingSink::default();
+        let event = LambdaEvent::new(sink.clone(), lambda_runtime::Context::default());
+        let mut call = pin!(handler(event));
+        let mut context = Context::from_waker(Waker::noop());
+        let result = std::future::Future::poll(call.as_mut(), &mut context);
+
+        assert!(matches!(result, std::task::Poll::Ready(Ok("done"))));
+        let payload = &sink.payloads()[0];
+        assert_eq!(payload["orders"], 1.0);
+        assert_eq!(payload[crate::invocation::INVOCATIONS_METRIC], 1.0);
+    }
+}
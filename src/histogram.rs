@@ -0,0 +1,138 @@
+//! Histograms as bucket counters.
+//!
+//! `CloudWatch` computes percentiles from the recorded samples, a [`Histogram`] instead counts
+//! observations per bucket, so the distribution stays visible e.g. as a share of requests
+//! faster than a threshold. Buckets are cumulative: `latency_lt_500ms` counts all observations
+//! below 500, including the ones below 100, and `latency_overflow` counts the observations
+//! at or above the last boundary.
+//!
+//! ```
+//! use lambda_helpers_metrics::histogram::Histogram;
+//! use lambda_helpers_metrics::Metrics;
+//!
+//! let latency = Histogram::new("latency", &[100.0, 500.0]).unwrap().suffix("ms");
+//!
+//! let mut metrics = Metrics::new("custom_lambdas", "service", "dummy_service");
+//! latency.observe(&mut metrics, 250.0);
+//!
+//! assert_eq!(metrics.value_of("latency_lt_100ms"), Some(0.0));
+//! assert_eq!(metrics.value_of("latency_lt_500ms"), Some(1.0));
+//! assert_eq!(metrics.value_of("latency_overflow"), Some(0.0));
+//! ```
+use crate::{Metrics, MetricsError};
+
+/// Bucket boundaries of a histogram, with the names of the bucket metrics.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    name: String,
+    bounds: Vec<f64>,
+    suffix: String,
+    /// Names of the buckets, in the order of `bounds`, followed by the overflow bucket.
+    bucket_names: Vec<String>,
+}
+
+impl Histogram {
+    /// Creates a histogram with the given upper boundaries (exclusive) of the buckets.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if there are no boundaries, or they are not finite and strictly increasing
+    pub fn new(name: &str, bounds: &[f64]) -> Result<Self, MetricsError> {
+        if bounds.is_empty() {
+            return Err(MetricsError::InvalidMetric(format!(
+                "histogram {name} has no buckets"
+            )));
+        }
+        let increasing = bounds.windows(2).all(|pair| pair[0] < pair[1]);
+        if !increasing || bounds.iter().any(|bound| !bound.is_finite()) {
+            return Err(MetricsError::InvalidMetric(format!(
+                "bucket boundaries of histogram {name} must be finite and increasing"
+            )));
+        }
+        let mut histogram = Self {
+            name: name.to_string(),
+            bounds: bounds.to_vec(),
+            suffix: String::new(),
+            bucket_names: Vec::new(),
+        };
+        histogram.name_buckets();
+        Ok(histogram)
+    }
+
+    /// Sets the suffix of the bucket names, usually the unit, e.g. `ms` for `latency_lt_100ms`.
+    #[must_use]
+    pub fn suffix(mut self, suffix: &str) -> Self {
+        self.suffix = suffix.to_string();
+        self.name_buckets();
+        self
+    }
+
+    fn name_buckets(&mut self) {
+        self.bucket_names = self
+            .bounds
+            .iter()
+            .map(|bound| format!("{}_lt_{bound}{}", self.name, self.suffix))
+            .chain(std::iter::once(format!("{}_overflow", self.name)))
+            .collect();
+    }
+
+    /// Returns the names of the bucket metrics, the overflow bucket last.
+    #[must_use]
+    pub fn bucket_names(&self) -> &[String] {
+        &self.bucket_names
+    }
+
+    /// Records an observation: the count of every bucket whose boundary is above the value
+    /// is incremented, the other buckets are recorded with 0, so all buckets are always
+    /// present in the payload.
+    pub fn observe(&self, metrics: &mut Metrics, value: f64) {
+        let overflow = self.bounds.iter().all(|bound| value >= *bound);
+        for (bound, name) in self.bounds.iter().zip(&self.bucket_names) {
+            metrics.increment(name, if value < *bound { 1.0 } else { 0.0 });
+        }
+        // UNWRAP: the overflow bucket is always the last name
+        let overflow_name = self.bucket_names.last().unwrap();
+        metrics.increment(overflow_name, if overflow { 1.0 } else { 0.0 });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_reject_invalid_bounds() {
+        assert!(Histogram::new("latency", &[]).is_err());
+        assert!(Histogram::new("latency", &[500.0, 100.0]).is_err());
+        assert!(Histogram::new("latency", &[100.0, f64::INFINITY]).is_err());
+    }
+
+    #[test]
+    fn should_count_observations_in_cumulative_buckets() {
+        let histogram = Histogram::new("latency", &[100.0, 500.0, 0.5e4])
+            .unwrap()
+            .suffix("ms");
+        let mut metrics = Metrics::builder("test")
+            .sink(crate::sink::NullSink)
+            .build()
+            .unwrap();
+
+        for value in [50.0, 100.0, 250.0, 7000.0] {
+            histogram.observe(&mut metrics, value);
+        }
+
+        assert_eq!(
+            histogram.bucket_names(),
+            [
+                "latency_lt_100ms",
+                "latency_lt_500ms",
+                "latency_lt_5000ms",
+                "latency_overflow"
+            ]
+        );
+        assert_eq!(metrics.value_of("latency_lt_100ms"), Some(1.0));
+        assert_eq!(metrics.value_of("latency_lt_500ms"), Some(3.0));
+        assert_eq!(metrics.value_of("latency_lt_5000ms"), Some(3.0));
+        assert_eq!(metrics.value_of("latency_overflow"), Some(1.0));
+    }
+}
@@ -0,0 +1,158 @@
+//! Outbound HTTP metrics for `reqwest` clients.
+//!
+//! Available with the `reqwest` feature.
+//!
+//! ```ignore
+//! use lambda_helpers_metrics::http_client::HttpMetricsMiddleware;
+//!
+//! let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
+//!     .with(HttpMetricsMiddleware::new())
+//!     .build();
+//! ```
+//!
+//! Every request records, with an `http_target` dimension (the host, or a fixed label):
+//! - `http_client_requests`, the number of requests
+//! - `http_client_duration` in milliseconds
+//! - `http_client_responses` with an additional `status_class` dimension (`2xx`, `4xx`, `5xx`, ...
+//!   or `error` if no response was received)
+use std::time::{Duration, Instant};
+
+use http::Extensions;
+use reqwest_middleware::reqwest::{Request, Response};
+use reqwest_middleware::{Middleware, Next};
+
+use crate::context::{self, MetricsHandle};
+use crate::{Dimensions, MetricUnit, Metrics};
+
+pub const REQUESTS_METRIC: &str = "http_client_requests";
+pub const DURATION_METRIC: &str = "http_client_duration";
+pub const RESPONSES_METRIC: &str = "http_client_responses";
+
+/// `reqwest-middleware` middleware recording outbound request metrics.
+///
+/// By default metrics are recorded into the [current context](crate::context),
+/// dimensioned by the host of the request URL.
+#[derive(Debug, Default, Clone)]
+pub struct HttpMetricsMiddleware {
+    handle: Option<MetricsHandle>,
+    label: Option<String>,
+}
+
+impl HttpMetricsMiddleware {
+    /// Creates a middleware recording into the current context, dimensioned by host.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records into the given metrics instead of the current context.
+    #[must_use]
+    pub fn with_handle(mut self, handle: MetricsHandle) -> Self {
+        self.handle = Some(handle);
+        self
+    }
+
+    /// Uses a fixed label (e.g. `payments_api`) instead of the host as the `http_target` dimension.
+    #[must_use]
+    pub fn with_label(mut self, label: &str) -> Self {
+        self.label = Some(label.to_string());
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for HttpMetricsMiddleware {
+    async fn handle(
+        &self,
+        req: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> reqwest_middleware::Result<Response> {
+        let target = match &self.label {
+            Some(label) => label.clone(),
+            None => req.url().host_str().unwrap_or("unknown").to_string(),
+        };
+        let start = Instant::now();
+        let result = next.run(req, extensions).await;
+        let request = OutboundRequest {
+            target: &target,
+            duration: start.elapsed(),
+            status: result
+                .as_ref()
+                .ok()
+                .map(|response| response.status().as_u16()),
+        };
+        match &self.handle {
+            Some(handle) => handle.with(|metrics| request.record(metrics)),
+            None => {
+                context::with_current(|metrics| request.record(metrics));
+            }
+        }
+        result
+    }
+}
+
+#[derive(Debug)]
+struct OutboundRequest<'a> {
+    target: &'a str,
+    duration: Duration,
+    status: Option<u16>,
+}
+
+impl OutboundRequest<'_> {
+    fn status_class(&self) -> String {
+        match self.status {
+            Some(status) => format!("{}xx", status / 100),
+            None => "error".to_string(),
+        }
+    }
+
+    fn record(&self, metrics: &mut Metrics) {
+        let mut dimensions = Dimensions::default();
+        dimensions.insert("http_target", self.target);
+
+        metrics.increment_with_dimensions(REQUESTS_METRIC, 1.0, dimensions.clone());
+        metrics.add_sample_with_dimensions(
+            DURATION_METRIC,
+            MetricUnit::Milliseconds,
+            self.duration.as_secs_f64() * 1000.0,
+            dimensions.clone(),
+        );
+        dimensions.insert("status_class", &self.status_class());
+        metrics.increment_with_dimensions(RESPONSES_METRIC, 1.0, dimensions);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::RecordingSink;
+
+    #[test]
+    fn should_record_request_metrics_by_status_class() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test").sink(sink.clone()).build().unwrap();
+        for status in [Some(200), Some(201), None] {
+            OutboundRequest {
+                target: "api.example.com",
+                duration: Duration::from_millis(10),
+                status,
+            }
+            .record(&mut metrics);
+        }
+        metrics.flush_metrics();
+
+        let payloads = sink.payloads();
+        let requests: f64 = payloads
+            .iter()
+            .filter_map(|payload| payload[REQUESTS_METRIC].as_f64())
+            .sum();
+        assert_eq!(requests, 3.0);
+        assert!(payloads
+            .iter()
+            .any(|payload| payload["status_class"] == "2xx" && payload[RESPONSES_METRIC] == 2.0));
+        assert!(payloads
+            .iter()
+            .any(|payload| payload["status_class"] == "error" && payload[RESPONSES_METRIC] == 1.0));
+    }
+}
@@ -0,0 +1,385 @@
+//! Sink posting payloads to an HTTP endpoint, e.g. an internal metrics gateway.
+//!
+//! Each payload is sent as the body of a `POST` request, as it is or wrapped in a template
+//! where `{payload}` is replaced by the payload. Requests are authenticated by an [`HttpAuth`]:
+//! a [`BearerToken`], [`StaticHeaders`], [`SigV4Auth`] for endpoints behind IAM, or a custom
+//! implementation.
+//!
+//! ```no_run
+//! use lambda_helpers_metrics::http_push::{HttpPushSink, SigV4Auth};
+//! use lambda_helpers_metrics::{MetricUnit, Metrics};
+//!
+//! # async fn example() {
+//! let sink = HttpPushSink::new("https://metrics.internal/ingest")
+//!     .auth(SigV4Auth::new("execute-api", "eu-west-1"))
+//!     .template(r#"{"source":"lambda","emf":{payload}}"#);
+//! let mut metrics = Metrics::builder("custom_lambdas")
+//!     .async_sink(sink)
+//!     .build()
+//!     .unwrap();
+//! metrics.add_metric("orders", MetricUnit::Count, 1.0);
+//! metrics.flush_async().await;
+//! # }
+//! ```
+//!
+//! With the `gzip` feature, bodies can be compressed with [`HttpPushSink::gzip`].
+//!
+//! Available with the `http-push` feature. Responses with an error status fail the flush.
+use std::fmt;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use aws_credential_types::Credentials;
+use aws_sigv4::http_request::{sign, SignableBody, SignableRequest, SigningSettings};
+use aws_sigv4::sign::v4;
+use aws_smithy_runtime_api::client::identity::Identity;
+
+use crate::sink::{AsyncMetricsSink, EmitFuture};
+use crate::{template, MetricsError};
+
+/// The placeholder of the payload in a template.
+pub const PAYLOAD_PLACEHOLDER: &str = "{payload}";
+
+type Headers = Vec<(String, String)>;
+
+/// Authenticates the requests of an [`HttpPushSink`].
+pub trait HttpAuth: fmt::Debug + Send + Sync {
+    /// Returns the headers to add to a request, given its method, URL, headers and body.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the request can't be authenticated, which fails the emission
+    fn headers(
+        &self,
+        method: &str,
+        url: &str,
+        headers: &[(String, String)],
+        body: &[u8],
+    ) -> Result<Vec<(String, String)>, MetricsError>;
+}
+
+/// Sends `Authorization: Bearer <token>`.
+#[derive(Clone)]
+pub struct BearerToken(String);
+
+impl fmt::Debug for BearerToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("BearerToken(..)")
+    }
+}
+
+impl BearerToken {
+    /// Sends the token with every request.
+    #[must_use]
+    pub fn new(token: &str) -> Self {
+        Self(token.to_string())
+    }
+}
+
+impl HttpAuth for BearerToken {
+    fn headers(
+        &self,
+        _method: &str,
+        _url: &str,
+        _headers: &[(String, String)],
+        _body: &[u8],
+    ) -> Result<Vec<(String, String)>, MetricsError> {
+        Ok(vec![(
+            "Authorization".to_string(),
+            format!("Bearer {}", self.0),
+        )])
+    }
+}
+
+/// Sends a fixed set of headers, e.g. an API key.
+#[derive(Debug, Clone, Default)]
+pub struct StaticHeaders(Vec<(String, String)>);
+
+impl StaticHeaders {
+    /// Creates an empty set of headers.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a header.
+    #[must_use]
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.0.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+impl HttpAuth for StaticHeaders {
+    fn headers(
+        &self,
+        _method: &str,
+        _url: &str,
+        _headers: &[(String, String)],
+        _body: &[u8],
+    ) -> Result<Vec<(String, String)>, MetricsError> {
+        Ok(self.0.clone())
+    }
+}
+
+/// Signs requests with AWS Signature Version 4, e.g. for API Gateway endpoints with IAM
+/// authorization. By default the credentials are read from `AWS_ACCESS_KEY_ID`,
+/// `AWS_SECRET_ACCESS_KEY` and `AWS_SESSION_TOKEN` for each request, as set by Lambda.
+#[derive(Debug, Clone)]
+pub struct SigV4Auth {
+    service: String,
+    region: String,
+    credentials: Option<Credentials>,
+}
+
+impl SigV4Auth {
+    /// Signs for the service, e.g. `execute-api`, in the region.
+    #[must_use]
+    pub fn new(service: &str, region: &str) -> Self {
+        Self {
+            service: service.to_string(),
+            region: region.to_string(),
+            credentials: None,
+        }
+    }
+
+    /// Signs with fixed credentials instead of the ones of the environment.
+    #[must_use]
+    pub fn credentials(
+        mut self,
+        access_key_id: &str,
+        secret_access_key: &str,
+        session_token: Option<&str>,
+    ) -> Self {
+        self.credentials = Some(Credentials::new(
+            access_key_id,
+            secret_access_key,
+            session_token.map(str::to_string),
+            None,
+            "lambda_helpers_metrics",
+        ));
+        self
+    }
+
+    fn current_credentials(&self) -> Result<Credentials, MetricsError> {
+        if let Some(credentials) = &self.credentials {
+            return Ok(credentials.clone());
+        }
+        let var = |key: &str| {
+            std::env::var(key)
+                .map_err(|_| MetricsError::Configuration(format!("{key} is not set for SigV4")))
+        };
+        Ok(Credentials::new(
+            var("AWS_ACCESS_KEY_ID")?,
+            var("AWS_SECRET_ACCESS_KEY")?,
+            std::env::var("AWS_SESSION_TOKEN").ok(),
+            None,
+            "environment",
+        ))
+    }
+
+    fn sign(
+        &self,
+        method: &str,
+        url: &str,
+        headers: &[(String, String)],
+        body: &[u8],
+        time: SystemTime,
+    ) -> Result<Vec<(String, String)>, MetricsError> {
+        let identity: Identity = self.current_credentials()?.into();
+        let params = v4::SigningParams::builder()
+            .identity(&identity)
+            .region(&self.region)
+            .name(&self.service)
+            .time(time)
+            .settings(SigningSettings::default())
+            .build()
+            .map_err(signing_error)?;
+        let request = SignableRequest::new(
+            method,
+            url,
+            headers
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str())),
+            SignableBody::Bytes(body),
+        )
+        .map_err(signing_error)?;
+        let (instructions, _) = sign(request, &params.into())
+            .map_err(signing_error)?
+            .into_parts();
+        Ok(instructions
+            .headers()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect())
+    }
+}
+
+fn signing_error(err: impl fmt::Display) -> MetricsError {
+    MetricsError::Configuration(format!("can't sign the request: {err}"))
+}
+
+impl HttpAuth for SigV4Auth {
+    fn headers(
+        &self,
+        method: &str,
+        url: &str,
+        headers: &[(String, String)],
+        body: &[u8],
+    ) -> Result<Vec<(String, String)>, MetricsError> {
+        self.sign(method, url, headers, body, SystemTime::now())
+    }
+}
+
+/// Posts payloads to an HTTP endpoint, see [`crate::http_push`].
+#[derive(Debug, Clone)]
+pub struct HttpPushSink {
+    client: reqwest::Client,
+    url: String,
+    auth: Option<Arc<dyn HttpAuth>>,
+    template: Option<String>,
+    content_type: String,
+    #[cfg(feature = "gzip")]
+    gzip_level: Option<u32>,
+}
+
+impl HttpPushSink {
+    /// Creates a sink posting the payloads as they are, as `application/json`, without authentication.
+    #[must_use]
+    pub fn new(url: &str) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url: url.to_string(),
+            auth: None,
+            template: None,
+            content_type: "application/json".to_string(),
+            #[cfg(feature = "gzip")]
+            gzip_level: None,
+        }
+    }
+
+    /// Sets how requests are authenticated.
+    #[must_use]
+    pub fn auth(mut self, auth: impl HttpAuth + 'static) -> Self {
+        self.auth = Some(Arc::new(auth));
+        self
+    }
+
+    /// Wraps each payload in the template, where `{payload}` is replaced by the payload.
+    #[must_use]
+    pub fn template(mut self, template: &str) -> Self {
+        self.template = Some(template.to_string());
+        self
+    }
+
+    /// Sets the `Content-Type` of the requests, `application/json` by default.
+    #[must_use]
+    pub fn content_type(mut self, content_type: &str) -> Self {
+        self.content_type = content_type.to_string();
+        self
+    }
+
+    /// Compresses the bodies with gzip at the level, from 0 (none) to 9 (best), sent with
+    /// `Content-Encoding: gzip`. Available with the `gzip` feature.
+    #[cfg(feature = "gzip")]
+    #[must_use]
+    pub fn gzip(mut self, level: u32) -> Self {
+        self.gzip_level = Some(level);
+        self
+    }
+
+    /// Returns the body and the headers of the request posting the payload.
+    fn request(&self, payload: &str) -> Result<(Vec<u8>, Headers), MetricsError> {
+        let body = match &self.template {
+            Some(template) => {
+                template::resolve(template, |key| (key == "payload").then_some(payload))
+                    .into_owned()
+            }
+            None => payload.to_string(),
+        }
+        .into_bytes();
+        let mut headers = vec![("content-type".to_string(), self.content_type.clone())];
+        #[cfg(feature = "gzip")]
+        let body = match self.gzip_level {
+            Some(level) => {
+                headers.push(("content-encoding".to_string(), "gzip".to_string()));
+                crate::compression::gzip(&body, level)?
+            }
+            None => body,
+        };
+        if let Some(auth) = &self.auth {
+            let signed = auth.headers("POST", &self.url, &headers, &body)?;
+            headers.extend(signed);
+        }
+        Ok((body, headers))
+    }
+}
+
+impl AsyncMetricsSink for HttpPushSink {
+    fn emit(&self, payload: String) -> EmitFuture<'_> {
+        Box::pin(async move {
+            let (body, headers) = self.request(&payload)?;
+            let mut request = self.client.post(&self.url).body(body);
+            for (name, value) in headers {
+                request = request.header(name, value);
+            }
+            let response = request.send().await.map_err(io_error)?;
+            response.error_for_status().map_err(io_error)?;
+            Ok(())
+        })
+    }
+}
+
+fn io_error(err: reqwest::Error) -> MetricsError {
+    MetricsError::Io(std::io::Error::other(err))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn should_wrap_payload_in_template_with_auth_headers() {
+        let sink = HttpPushSink::new("https://metrics.internal/ingest")
+            .auth(BearerToken::new("secret"))
+            .template(r#"{"source":"lambda","emf":{payload}}"#);
+
+        let (body, headers) = sink.request(r#"{"orders":1}"#).unwrap();
+
+        assert_eq!(body, br#"{"source":"lambda","emf":{"orders":1}}"#);
+        assert_eq!(
+            headers,
+            vec![
+                ("content-type".to_string(), "application/json".to_string()),
+                ("Authorization".to_string(), "Bearer secret".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_sign_requests_with_sigv4() {
+        let auth = SigV4Auth::new("execute-api", "eu-west-1").credentials(
+            "AKIDEXAMPLE",
+            "secret",
+            Some("token"),
+        );
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_717_243_200);
+
+        let headers = auth
+            .sign("POST", "https://metrics.internal/ingest", &[], b"{}", time)
+            .unwrap();
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.as_str())
+        };
+
+        assert!(header("authorization").unwrap().starts_with(
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20240601/eu-west-1/execute-api/aws4_request"
+        ));
+        assert_eq!(header("x-amz-date"), Some("20240601T120000Z"));
+        assert_eq!(header("x-amz-security-token"), Some("token"));
+    }
+}
@@ -0,0 +1,250 @@
+//! Validation and pretty-printing of EMF log lines.
+//!
+//! Helps to debug why `CloudWatch` doesn't extract metrics from a log: [`validate`] lists the
+//! violations of the [EMF specification](https://docs.aws.amazon.com/AmazonCloudWatch/latest/monitoring/CloudWatch_Embedded_Metric_Format_Specification.html)
+//! and [`pretty_print`] shows the metrics `CloudWatch` would extract.
+//!
+//! ```
+//! use lambda_helpers_metrics::inspect;
+//!
+//! let line = r#"2024-06-01T12:00:00.000Z {"_aws":{"Timestamp":1717243200000,"CloudWatchMetrics":[{"Namespace":"shop","Dimensions":[["service"]],"Metrics":[{"Name":"latency","Unit":"Milliseconds"}]}]},"service":"orders","latency":12.5}"#;
+//!
+//! let payload = inspect::extract_payload(line).unwrap();
+//! assert!(inspect::validate(payload).is_empty());
+//! assert!(inspect::pretty_print(payload).unwrap().contains("latency = 12.5 Milliseconds"));
+//! ```
+//!
+//! The `emf-inspect` binary, installed with `cargo install lambda_helpers_metrics --features cli`,
+//! runs both on every EMF line of log files or of stdin.
+use std::fmt::Write as _;
+
+use serde_json::{Map, Value};
+
+use crate::{
+    emf, MetricUnit, MetricsError, MAX_DIMENSIONS, MAX_EVENT_BYTES, MAX_METRICS,
+    MAX_VALUES_PER_METRIC,
+};
+
+/// The longest namespace, metric or dimension name.
+const MAX_NAME_LEN: usize = 255;
+
+/// Returns the JSON object of a log line which looks like an EMF payload, skipping a prefix
+/// like the timestamp and request ID of Lambda logs or `CloudWatch` Logs exports.
+#[must_use]
+pub fn extract_payload(line: &str) -> Option<&str> {
+    let start = line.find('{')?;
+    let payload = line[start..].trim_end();
+    payload.contains("\"_aws\"").then_some(payload)
+}
+
+/// Returns the violations of the EMF specification, empty if `CloudWatch` can extract all
+/// metrics of the payload.
+#[must_use]
+pub fn validate(payload: &str) -> Vec<String> {
+    let mut problems = Vec::new();
+    if payload.len() > MAX_EVENT_BYTES {
+        problems.push(format!(
+            "payload is {} bytes, log events are limited to {MAX_EVENT_BYTES}",
+            payload.len()
+        ));
+    }
+    let root = match serde_json::from_str::<Value>(payload) {
+        Ok(Value::Object(root)) => root,
+        Ok(_) => return vec!["payload is not a JSON object".to_string()],
+        Err(err) => return vec![format!("payload is not valid JSON: {err}")],
+    };
+    let Some(metadata) = root.get("_aws").and_then(Value::as_object) else {
+        problems.push("_aws is missing or not an object".to_string());
+        return problems;
+    };
+    if !metadata.get("Timestamp").is_some_and(Value::is_i64) {
+        problems.push("_aws.Timestamp is missing or not an integer".to_string());
+    }
+    let Some(directives) = metadata.get("CloudWatchMetrics").and_then(Value::as_array) else {
+        problems.push("_aws.CloudWatchMetrics is missing or not an array".to_string());
+        return problems;
+    };
+    for (index, directive) in directives.iter().enumerate() {
+        let at = format!("CloudWatchMetrics[{index}]");
+        validate_directive(&root, directive, &at, &mut problems);
+    }
+    problems
+}
+
+fn validate_directive(
+    root: &Map<String, Value>,
+    directive: &Value,
+    at: &str,
+    problems: &mut Vec<String>,
+) {
+    match directive.get("Namespace").and_then(Value::as_str) {
+        Some(namespace) => check_name(namespace, &format!("{at}.Namespace"), problems),
+        None => problems.push(format!("{at}.Namespace is missing or not a string")),
+    }
+    match directive.get("Dimensions").and_then(Value::as_array) {
+        Some(sets) => {
+            for (index, set) in sets.iter().enumerate() {
+                let at = format!("{at}.Dimensions[{index}]");
+                let Some(keys) = set.as_array() else {
+                    problems.push(format!("{at} is not an array"));
+                    continue;
+                };
+                if keys.len() > MAX_DIMENSIONS {
+                    problems.push(format!(
+                        "{at} has {} dimensions, at most {MAX_DIMENSIONS} are allowed",
+                        keys.len()
+                    ));
+                }
+                for key in keys {
+                    match key.as_str() {
+                        Some(key) if root.get(key).is_some_and(Value::is_string) => {}
+                        Some(key) => problems.push(format!(
+                            "dimension {key} of {at} is missing or not a string"
+                        )),
+                        None => problems.push(format!("{at} holds a key which is not a string")),
+                    }
+                }
+            }
+        }
+        None => problems.push(format!("{at}.Dimensions is missing or not an array")),
+    }
+    let Some(definitions) = directive.get("Metrics").and_then(Value::as_array) else {
+        problems.push(format!("{at}.Metrics is missing or not an array"));
+        return;
+    };
+    if definitions.len() > MAX_METRICS {
+        problems.push(format!(
+            "{at}.Metrics has {} metrics, at most {MAX_METRICS} are allowed",
+            definitions.len()
+        ));
+    }
+    for (index, definition) in definitions.iter().enumerate() {
+        let at = format!("{at}.Metrics[{index}]");
+        let Some(name) = definition.get("Name").and_then(Value::as_str) else {
+            problems.push(format!("{at}.Name is missing or not a string"));
+            continue;
+        };
+        check_name(name, &format!("{at}.Name"), problems);
+        if let Some(unit) = definition.get("Unit") {
+            if unit
+                .as_str()
+                .is_none_or(|unit| unit.parse::<MetricUnit>().is_err())
+            {
+                problems.push(format!("{at}.Unit {unit} is not a CloudWatch unit"));
+            }
+        }
+        if let Some(resolution) = definition.get("StorageResolution") {
+            if !matches!(resolution.as_u64(), Some(1 | 60)) {
+                problems.push(format!(
+                    "{at}.StorageResolution {resolution} is not 1 or 60"
+                ));
+            }
+        }
+        match root.get(name) {
+            Some(Value::Number(_)) => {}
+            Some(Value::Array(values)) if values.iter().all(Value::is_number) => {
+                if values.len() > MAX_VALUES_PER_METRIC {
+                    problems.push(format!(
+                        "metric {name} has {} values, at most {MAX_VALUES_PER_METRIC} are allowed",
+                        values.len()
+                    ));
+                }
+            }
+            Some(_) => problems.push(format!(
+                "metric {name} is not a number or an array of numbers"
+            )),
+            None => problems.push(format!("metric {name} has no value")),
+        }
+    }
+}
+
+fn check_name(name: &str, at: &str, problems: &mut Vec<String>) {
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        problems.push(format!("{at} must be 1 to {MAX_NAME_LEN} characters long"));
+    }
+}
+
+/// Formats the metrics of a payload, one line per metric, under its namespace and dimensions.
+///
+/// # Errors
+///
+/// Will return `Err` if the payload is not JSON or has no `_aws.CloudWatchMetrics`
+pub fn pretty_print(payload: &str) -> Result<String, MetricsError> {
+    let mut output = String::new();
+    let mut group = None;
+    for metric in emf::parse(payload)? {
+        let dimensions: Vec<String> = metric
+            .dimensions
+            .iter()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect();
+        let heading = format!("{} [{}]", metric.namespace, dimensions.join(", "));
+        if group.as_ref() != Some(&heading) {
+            let _ = writeln!(output, "{heading}");
+            group = Some(heading);
+        }
+        let values: Vec<String> = metric.values.iter().map(f64::to_string).collect();
+        let _ = writeln!(
+            output,
+            "  {} = {} {}",
+            metric.name,
+            values.join(", "),
+            metric.unit
+        );
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_accept_payloads_of_library() {
+        let sink = crate::sink::RecordingSink::default();
+        let mut metrics = crate::Metrics::builder("test")
+            .dimension("service", "orders")
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+        metrics.add_sample("latency", MetricUnit::Milliseconds, 1.5);
+        metrics.add_sample("latency", MetricUnit::Milliseconds, 2.0);
+        metrics.flush_metrics();
+
+        let payload = sink.payloads()[0].to_string();
+        assert_eq!(validate(&payload), Vec::<String>::new());
+        assert_eq!(
+            pretty_print(&payload).unwrap(),
+            "test [service=orders]\n  latency = 1.5, 2 Milliseconds\n"
+        );
+    }
+
+    #[test]
+    fn should_list_violations() {
+        let payload = r#"{"_aws":{"Timestamp":"now","CloudWatchMetrics":[{"Namespace":"",
+            "Dimensions":[["service"]],"Metrics":[{"Name":"latency","Unit":"Weeks",
+            "StorageResolution":5},{"Name":"errors"}]}]},"latency":"12"}"#;
+
+        assert_eq!(
+            validate(payload),
+            vec![
+                "_aws.Timestamp is missing or not an integer",
+                "CloudWatchMetrics[0].Namespace must be 1 to 255 characters long",
+                "dimension service of CloudWatchMetrics[0].Dimensions[0] is missing or not a string",
+                "CloudWatchMetrics[0].Metrics[0].Unit \"Weeks\" is not a CloudWatch unit",
+                "CloudWatchMetrics[0].Metrics[0].StorageResolution 5 is not 1 or 60",
+                "metric latency is not a number or an array of numbers",
+                "metric errors has no value",
+            ]
+        );
+        assert_eq!(validate("[]"), vec!["payload is not a JSON object"]);
+    }
+
+    #[test]
+    fn should_extract_payload_from_log_line() {
+        let line = "2024-06-01T12:00:00.000Z\treq-1\tINFO\t{\"_aws\":{}}\n";
+
+        assert_eq!(extract_payload(line), Some("{\"_aws\":{}}"));
+        assert_eq!(extract_payload("START RequestId: req-1 {\"a\":1}"), None);
+    }
+}
@@ -0,0 +1,304 @@
+//! Standard Lambda health metrics under the user's namespace.
+//!
+//! Available with the `lambda` feature.
+//!
+//! ```ignore
+//! async fn handler(event: LambdaEvent<Value>) -> Result<Value, Error> {
+//!     let mut metrics = Metrics::new("custom_lambdas", "service", "dummy_service");
+//!     let start = Instant::now();
+//!     let result = process(event.payload).await;
+//!     metrics.emit_standard_metrics(&event.context, &result, start.elapsed());
+//!     result
+//! }
+//! ```
+//!
+//! The metrics mirror the built-in `AWS/Lambda` ones, so alarms can be built on versions
+//! carrying the custom dimensions:
+//! - `Invocations`
+//! - `Errors`, 0 for successful invocations
+//! - `Duration` in milliseconds
+//! - `ColdStart`, 1 for the first invocation of the execution environment, 0 afterwards
+//! - `RestoreStart`, with SnapStart only: 1 for the first invocation after a restore,
+//!   which is not counted as a cold start, 0 afterwards
+//!
+//! [`Metrics::for_invocation`] sets up the metrics of an invocation in one call:
+//!
+//! ```ignore
+//! async fn handler(event: LambdaEvent<Value>) -> Result<Value, Error> {
+//!     let mut metrics = Metrics::for_invocation(&event)?;
+//!     if metrics.remaining_time() > Some(Duration::from_secs(1)) {
+//!         warm_cache(&mut metrics).await;
+//!     }
+//!     Ok(event.payload)
+//! }
+//! ```
+use std::time::Duration;
+
+use chrono::{TimeZone, Utc};
+use lambda_runtime::{Context, LambdaEvent};
+
+use crate::catalog::codegen::CatalogMetric;
+use crate::cold_start::{self, StartKind};
+use crate::config::MetricsConfig;
+use crate::{MetricUnit, Metrics, MetricsError};
+
+pub const INVOCATIONS_METRIC: &str = "Invocations";
+pub const ERRORS_METRIC: &str = "Errors";
+pub const DURATION_METRIC: &str = "Duration";
+pub const COLD_START_METRIC: &str = "ColdStart";
+pub const RESTORE_START_METRIC: &str = "RestoreStart";
+pub const REQUEST_ID_PROPERTY: &str = "request_id";
+pub const FUNCTION_VERSION_PROPERTY: &str = "function_version";
+pub const FUNCTION_ARN_PROPERTY: &str = "invoked_function_arn";
+pub const DEADLINE_PROPERTY: &str = "deadline_ms";
+pub const TRACE_ID_PROPERTY: &str = "xray_trace_id";
+pub const TENANT_ID_PROPERTY: &str = "tenant_id";
+/// Prefix of the properties holding the client context fields, e.g. `client_app_title`.
+pub const CLIENT_PROPERTY_PREFIX: &str = "client_";
+pub const FUNCTION_NAME_DIMENSION: &str = "function_name";
+
+/// The metrics recorded by [`Metrics::emit_standard_metrics`], see [`Metrics::catalog`].
+pub(crate) fn catalog_metrics() -> Vec<CatalogMetric> {
+    [
+        (
+            INVOCATIONS_METRIC,
+            MetricUnit::Count,
+            "Invocations of the handler.",
+        ),
+        (
+            ERRORS_METRIC,
+            MetricUnit::Count,
+            "Invocations which failed, 0 otherwise.",
+        ),
+        (
+            DURATION_METRIC,
+            MetricUnit::Milliseconds,
+            "Time spent in the handler.",
+        ),
+        (
+            COLD_START_METRIC,
+            MetricUnit::Count,
+            "1 for the first invocation of the execution environment, 0 afterwards.",
+        ),
+        (
+            RESTORE_START_METRIC,
+            MetricUnit::Count,
+            "1 for the first invocation after a SnapStart restore, 0 afterwards.",
+        ),
+    ]
+    .into_iter()
+    .map(|(name, unit, description)| CatalogMetric::library(name, unit, description))
+    .collect()
+}
+
+impl Metrics {
+    /// Creates the metrics of an invocation: configured from the `AWS_EMF_*` environment
+    /// variables (see [`crate::config`]), in the namespace from `AWS_EMF_NAMESPACE` or named
+    /// after the function, with the function name as the `function_name` dimension,
+    /// the invocation context attached as properties (see [`Metrics::attach_context`])
+    /// and the deadline of the invocation set (see [`Metrics::remaining_time`]).
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if an environment variable holds an invalid value
+    pub fn for_invocation<T>(event: &LambdaEvent<T>) -> Result<Self, MetricsError> {
+        Self::for_context(&event.context, MetricsConfig::from_env()?)
+    }
+
+    fn for_context(context: &Context, config: MetricsConfig) -> Result<Self, MetricsError> {
+        let function_name = &context.env_config.function_name;
+        let mut metrics = Metrics::builder(function_name)
+            .config(&config)
+            .dimension(FUNCTION_NAME_DIMENSION, function_name)
+            .build()?;
+        metrics.attach_context(context);
+        let deadline = i64::try_from(context.deadline)
+            .ok()
+            .filter(|deadline| *deadline > 0)
+            .and_then(|deadline| Utc.timestamp_millis_opt(deadline).single());
+        if let Some(deadline) = deadline {
+            metrics.set_deadline(deadline);
+        }
+        Ok(metrics)
+    }
+
+    /// Records the standard health metrics of an invocation, and its request ID and function
+    /// version as properties. The metrics are flushed together with the rest of the buffer.
+    pub fn emit_standard_metrics<T, E>(
+        &mut self,
+        context: &Context,
+        outcome: &Result<T, E>,
+        duration: Duration,
+    ) {
+        let start = cold_start::take_start();
+        self.increment(INVOCATIONS_METRIC, 1.0);
+        self.increment(ERRORS_METRIC, if outcome.is_err() { 1.0 } else { 0.0 });
+        self.add(DURATION_METRIC, duration);
+        let cold_start = start == Some(StartKind::Cold);
+        self.increment(COLD_START_METRIC, if cold_start { 1.0 } else { 0.0 });
+        if StartKind::detect() == StartKind::Restore {
+            let restore = start == Some(StartKind::Restore);
+            self.increment(RESTORE_START_METRIC, if restore { 1.0 } else { 0.0 });
+        }
+
+        self.add_property(REQUEST_ID_PROPERTY, &context.request_id);
+        if !context.env_config.version.is_empty() {
+            self.add_property(FUNCTION_VERSION_PROPERTY, &context.env_config.version);
+        }
+    }
+
+    /// Adds the fields of the invocation context as properties: request ID, invoked function
+    /// ARN, deadline (milliseconds since the epoch), X-Ray trace ID and tenant ID, and the
+    /// client context of invocations from the AWS mobile SDK, prefixed with `client_`
+    /// (e.g. `client_app_title`, or `client_custom_<key>` for custom fields).
+    /// Empty fields are skipped. The invocation is counted by the staleness watchdog,
+    /// see [`Metrics::mark_invocation`].
+    pub fn attach_context(&mut self, context: &Context) {
+        self.reset_flush_count();
+        self.mark_invocation();
+        let mut properties = vec![
+            (REQUEST_ID_PROPERTY.to_string(), context.request_id.clone()),
+            (
+                FUNCTION_ARN_PROPERTY.to_string(),
+                context.invoked_function_arn.clone(),
+            ),
+        ];
+        if context.deadline > 0 {
+            properties.push((DEADLINE_PROPERTY.to_string(), context.deadline.to_string()));
+        }
+        for (key, value) in [
+            (TRACE_ID_PROPERTY, &context.xray_trace_id),
+            (TENANT_ID_PROPERTY, &context.tenant_id),
+        ] {
+            if let Some(value) = value {
+                properties.push((key.to_string(), value.clone()));
+            }
+        }
+        if let Some(client_context) = &context.client_context {
+            let client = &client_context.client;
+            for (key, value) in [
+                ("installation_id", &client.installation_id),
+                ("app_title", &client.app_title),
+                ("app_version_name", &client.app_version_name),
+                ("app_version_code", &client.app_version_code),
+                ("app_package_name", &client.app_package_name),
+            ] {
+                properties.push((format!("{CLIENT_PROPERTY_PREFIX}{key}"), value.clone()));
+            }
+            for (section, fields) in [
+                ("custom", &client_context.custom),
+                ("environment", &client_context.environment),
+            ] {
+                for (key, value) in fields {
+                    properties.push((
+                        format!("{CLIENT_PROPERTY_PREFIX}{section}_{key}"),
+                        value.clone(),
+                    ));
+                }
+            }
+        }
+        for (key, value) in properties {
+            if !value.is_empty() {
+                self.add_property(&key, &value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::RecordingSink;
+
+    #[test]
+    fn should_record_standard_metrics() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test").sink(sink.clone()).build().unwrap();
+        let mut context = Context::default();
+        context.request_id = "req-1".to_string();
+
+        let outcome: Result<(), &str> = Err("boom");
+        metrics.emit_standard_metrics(&context, &outcome, Duration::from_millis(120));
+        metrics.flush_metrics();
+
+        let payload = &sink.payloads()[0];
+        assert_eq!(payload[INVOCATIONS_METRIC], 1.0);
+        assert_eq!(payload[ERRORS_METRIC], 1.0);
+        assert_eq!(payload[DURATION_METRIC], 120.0);
+        assert!(payload[COLD_START_METRIC].is_number());
+        assert_eq!(payload[REQUEST_ID_PROPERTY], "req-1");
+        assert!(payload.get(FUNCTION_VERSION_PROPERTY).is_none());
+    }
+
+    #[test]
+    fn should_create_metrics_for_invocation() {
+        let mut context = Context::default();
+        context.request_id = "req-1".to_string();
+        context.env_config = std::sync::Arc::new(lambda_runtime::Config {
+            function_name: "orders".to_string(),
+            ..lambda_runtime::Config::default()
+        });
+        context.deadline = u64::try_from(Utc::now().timestamp_millis() + 60_000).unwrap();
+        let sink = RecordingSink::default();
+
+        let mut metrics = Metrics::for_context(&context, MetricsConfig::default()).unwrap();
+        let remaining = metrics.remaining_time().unwrap();
+        assert!(remaining > Duration::from_secs(50) && remaining <= Duration::from_secs(60));
+        metrics.register_sink(Box::new(sink.clone()));
+        metrics.increment("orders", 1.0);
+        metrics.flush_metrics();
+
+        let payload = &sink.payloads()[0];
+        assert_eq!(
+            payload["_aws"]["CloudWatchMetrics"][0]["Namespace"],
+            "orders"
+        );
+        assert_eq!(payload[FUNCTION_NAME_DIMENSION], "orders");
+        assert_eq!(payload[REQUEST_ID_PROPERTY], "req-1");
+
+        let config = MetricsConfig {
+            namespace: Some("custom_lambdas".to_string()),
+            ..MetricsConfig::default()
+        };
+        let metrics = Metrics::for_context(&Context::default(), config).unwrap();
+        assert_eq!(metrics.namespace.0, "custom_lambdas");
+        assert_eq!(metrics.remaining_time(), None);
+    }
+
+    #[test]
+    fn should_attach_context_as_properties() {
+        let mut metrics = Metrics::new("test", "service", "dummy_service");
+        let mut context = Context::default();
+        context.request_id = "req-1".to_string();
+        context.invoked_function_arn = "arn:aws:lambda:eu-west-1:123:function:orders".to_string();
+        context.deadline = 1_700_000_000_000;
+        context.xray_trace_id = Some("Root=1-abc".to_string());
+        // the client context type isn't exported by lambda_runtime
+        let client_context = serde_json::from_value(serde_json::json!({
+            "client": {
+                "installationId": "",
+                "appTitle": "shop",
+                "appVersionName": "",
+                "appVersionCode": "",
+                "appPackageName": "",
+            },
+            "custom": { "tier": "gold" },
+        }))
+        .unwrap();
+        context.client_context = Some(client_context);
+
+        metrics.attach_context(&context);
+
+        assert_eq!(metrics.property(REQUEST_ID_PROPERTY), Some("req-1"));
+        assert_eq!(
+            metrics.property(FUNCTION_ARN_PROPERTY),
+            Some("arn:aws:lambda:eu-west-1:123:function:orders")
+        );
+        assert_eq!(metrics.property(DEADLINE_PROPERTY), Some("1700000000000"));
+        assert_eq!(metrics.property(TRACE_ID_PROPERTY), Some("Root=1-abc"));
+        assert_eq!(metrics.property(TENANT_ID_PROPERTY), None);
+        assert_eq!(metrics.property("client_app_title"), Some("shop"));
+        assert_eq!(metrics.property("client_installation_id"), None);
+        assert_eq!(metrics.property("client_custom_tier"), Some("gold"));
+    }
+}
@@ -0,0 +1,213 @@
+//! Batch processing wrapper for Amazon MSK and self-managed Kafka event sources.
+//!
+//! Available with the `kafka` feature.
+//!
+//! ```ignore
+//! use lambda_helpers_metrics::kafka::process_kafka_batch;
+//!
+//! async fn handler(event: LambdaEvent<KafkaEvent>) -> Result<(), Error> {
+//!     let mut metrics = Metrics::new("custom_lambdas", "service", "dummy_service");
+//!     process_kafka_batch(&mut metrics, event.payload, |record| async move {
+//!         process(record).await
+//!     })
+//!     .await?;
+//!     Ok(())
+//! }
+//! ```
+//!
+//! The wrapper records, and flushes in one payload:
+//! - `kafka_records_received`, `kafka_records_processed` and `kafka_records_failed`
+//! - `kafka_consumer_lag` in milliseconds, the age of the oldest record in the batch from
+//!   its Kafka timestamp
+//! - `kafka_record_duration` in milliseconds, one sample per record
+//! - the `kafka_records_by_partition` property, the number of records of each topic-partition,
+//!   e.g. `{"orders-0": 12, "orders-1": 3}`; a dimension per topic-partition would split the
+//!   batch into one payload per partition
+//!
+//! Kafka event sources have no partial batch response: the whole batch is retried when the
+//! invocation fails. Processing stops at the first failed record and its error is returned:
+//! the remaining records of every partition, not only of the failed record's one, are left
+//! unprocessed, and are processed again with the retried batch.
+use std::collections::BTreeMap;
+use std::future::Future;
+
+use aws_lambda_events::kafka::{KafkaEvent, KafkaRecord};
+
+use crate::{MetricUnit, Metrics};
+
+pub const RECORDS_RECEIVED_METRIC: &str = "kafka_records_received";
+pub const RECORDS_PROCESSED_METRIC: &str = "kafka_records_processed";
+pub const RECORDS_FAILED_METRIC: &str = "kafka_records_failed";
+pub const CONSUMER_LAG_METRIC: &str = "kafka_consumer_lag";
+pub const RECORD_DURATION_METRIC: &str = "kafka_record_duration";
+pub const RECORDS_BY_PARTITION_PROPERTY: &str = "kafka_records_by_partition";
+
+/// Processes the records one by one, in order within each topic-partition, records batch
+/// metrics and flushes them. Returns the error of the first record which failed, after which no
+/// record of any partition is processed.
+///
+/// # Errors
+///
+/// Will return the error of the handler for the first record which failed
+pub async fn process_kafka_batch<F, Fut, E>(
+    metrics: &mut Metrics,
+    event: KafkaEvent,
+    mut handler: F,
+) -> Result<(), E>
+where
+    F: FnMut(KafkaRecord) -> Fut,
+    Fut: Future<Output = Result<(), E>>,
+{
+    let now = metrics.clock.now();
+    // sorted by topic-partition, so batches are processed in a stable order
+    let partitions = event.records.into_iter().collect::<BTreeMap<_, _>>();
+    let received = partitions.values().map(Vec::len).sum::<usize>();
+    let oldest = partitions
+        .values()
+        .flatten()
+        .map(|record| record.timestamp.0)
+        .min();
+    let by_partition = partitions
+        .iter()
+        .map(|(partition, records)| (partition.clone(), serde_json::Value::from(records.len())))
+        .collect::<serde_json::Map<_, _>>();
+    let mut processed = 0;
+    let mut result = Ok(());
+
+    for record in partitions.into_values().flatten() {
+        let start = metrics.clock.instant();
+        let outcome = handler(record).await;
+        let elapsed = metrics.clock.instant().saturating_duration_since(start);
+        metrics.add_sample(
+            RECORD_DURATION_METRIC,
+            MetricUnit::Milliseconds,
+            elapsed.as_secs_f64() * 1000.0,
+        );
+        if let Err(err) = outcome {
+            result = Err(err);
+            break;
+        }
+        processed += 1;
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    {
+        metrics.increment(RECORDS_RECEIVED_METRIC, received as f64);
+        metrics.increment(RECORDS_PROCESSED_METRIC, processed as f64);
+        metrics.increment(RECORDS_FAILED_METRIC, f64::from(u8::from(result.is_err())));
+    }
+    if let Some(oldest) = oldest {
+        #[allow(clippy::cast_precision_loss)]
+        let lag = (now - oldest).num_milliseconds().max(0) as f64;
+        metrics.add_metric(CONSUMER_LAG_METRIC, MetricUnit::Milliseconds, lag);
+    }
+    metrics.add_json_property(RECORDS_BY_PARTITION_PROPERTY, by_partition);
+    metrics.flush_metrics();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::Utc;
+
+    use super::*;
+    use crate::batch::tests::block_on_ready;
+    use crate::clock::ManualClock;
+    use crate::sink::RecordingSink;
+
+    fn kafka_event(records: &[(&str, i64, i64)]) -> KafkaEvent {
+        let mut partitions = serde_json::Map::new();
+        for (partition, offset, age_ms) in records {
+            let timestamp = Utc::now().timestamp_millis() - age_ms;
+            let record = serde_json::json!({
+                "topic": partition.rsplit_once('-').unwrap().0,
+                "partition": 0,
+                "offset": offset,
+                "timestamp": timestamp,
+                "timestampType": "CREATE_TIME",
+                "key": null,
+                "value": "",
+                "headers": [],
+            });
+            partitions
+                .entry(partition.to_string())
+                .or_insert_with(|| serde_json::json!([]))
+                .as_array_mut()
+                .unwrap()
+                .push(record);
+        }
+        serde_json::from_value(serde_json::json!({
+            "eventSource": "aws:kafka",
+            "records": partitions,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn should_record_batch_metrics_and_stop_at_first_failure() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test").sink(sink.clone()).build().unwrap();
+        let event = kafka_event(&[
+            ("orders-0", 1, 5_000),
+            ("orders-0", 2, 1_000),
+            ("orders-1", 7, 1_000),
+            ("orders-1", 8, 1_000),
+        ]);
+
+        let result = block_on_ready(process_kafka_batch(
+            &mut metrics,
+            event,
+            |record| async move {
+                if record.offset == 7 {
+                    Err("poison")
+                } else {
+                    Ok(())
+                }
+            },
+        ));
+
+        assert_eq!(result, Err("poison"));
+        let payloads = sink.payloads();
+        assert_eq!(payloads.len(), 1);
+        assert_eq!(payloads[0][RECORDS_RECEIVED_METRIC], 4.0);
+        assert_eq!(payloads[0][RECORDS_PROCESSED_METRIC], 2.0);
+        assert_eq!(payloads[0][RECORDS_FAILED_METRIC], 1.0);
+        assert_eq!(
+            payloads[0][RECORD_DURATION_METRIC]
+                .as_array()
+                .unwrap()
+                .len(),
+            3
+        );
+        assert!(payloads[0][CONSUMER_LAG_METRIC].as_f64().unwrap() >= 5_000.0);
+        assert_eq!(
+            payloads[0][RECORDS_BY_PARTITION_PROPERTY],
+            serde_json::json!({ "orders-0": 2, "orders-1": 2 })
+        );
+    }
+
+    #[test]
+    fn should_measure_records_and_lag_with_metrics_clock() {
+        let sink = RecordingSink::default();
+        let clock = ManualClock::new(Utc::now() + chrono::Duration::seconds(60));
+        let mut metrics = Metrics::builder("test")
+            .clock(clock.clone())
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+        let event = kafka_event(&[("orders-0", 1, 0)]);
+
+        let result: Result<(), ()> =
+            block_on_ready(process_kafka_batch(&mut metrics, event, |_| {
+                clock.advance(Duration::from_millis(40));
+                async { Ok(()) }
+            }));
+
+        assert_eq!(result, Ok(()));
+        let payloads = sink.payloads();
+        assert_eq!(payloads[0][RECORD_DURATION_METRIC], 40.0);
+        assert!(payloads[0][CONSUMER_LAG_METRIC].as_f64().unwrap() >= 60_000.0);
+    }
+}
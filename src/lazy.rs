@@ -0,0 +1,110 @@
+use std::fmt;
+
+use crate::{mode, Metric, MetricUnit, Metrics};
+
+type Compute = Box<dyn FnOnce() -> f64 + Send + Sync>;
+
+/// A metric whose value is computed at flush time.
+pub(crate) struct LazyMetric {
+    /// The metric with the context of the call, its value is replaced at flush time.
+    metric: Metric,
+    compute: Compute,
+}
+
+impl fmt::Debug for LazyMetric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LazyMetric")
+            .field("metric", &self.metric)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Metrics {
+    /// Adds a metric whose value is computed by `compute` at the next flush, for values which are
+    /// expensive to compute, e.g. queue depths or cache statistics. The closure is called once,
+    /// right before the payload is serialized, and not at all if metrics are disabled
+    /// in the [configuration](crate::config). Lazy metrics are not visible to `len`,
+    /// `contains` and the other inspection methods before the flush.
+    ///
+    /// ```
+    /// # use lambda_helpers_metrics::{MetricUnit, Metrics};
+    /// let mut metrics = Metrics::new("custom_lambdas", "service", "dummy_service");
+    /// let cache = vec![1, 2, 3];
+    /// metrics.add_lazy_metric("cache_size", MetricUnit::Count, move || cache.len() as f64);
+    /// ```
+    pub fn add_lazy_metric(
+        &mut self,
+        name: &str,
+        unit: MetricUnit,
+        compute: impl FnOnce() -> f64 + Send + Sync + 'static,
+    ) {
+        self.lazy_entries.push(LazyMetric {
+            metric: self.new_metric(name, unit, 0.0),
+            compute: Box::new(compute),
+        });
+    }
+
+    /// Computes the lazy metrics and moves them into the buffer.
+    pub(crate) fn evaluate_lazy_metrics(&mut self) {
+        for lazy in std::mem::take(&mut self.lazy_entries) {
+            let metric = Metric {
+                values: vec![(lazy.compute)()],
+                ..lazy.metric
+            };
+            match mode::validate(&metric) {
+                Ok(()) => self.entries.push(metric),
+                Err(err) => self.record_dropped(&err),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use crate::config::MetricsConfig;
+    use crate::sink::RecordingSink;
+    use crate::{MetricUnit, Metrics};
+
+    #[test]
+    fn should_compute_lazy_metric_once_at_flush() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test").sink(sink.clone()).build().unwrap();
+
+        metrics.add_lazy_metric("queue_depth", MetricUnit::Count, move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            42.0
+        });
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+        metrics.flush_metrics();
+        metrics.flush_metrics();
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        let payloads = sink.payloads();
+        assert_eq!(payloads.len(), 1);
+        assert_eq!(payloads[0]["queue_depth"], 42.0);
+    }
+
+    #[test]
+    fn should_skip_lazy_metric_when_disabled() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let config = MetricsConfig {
+            disabled: true,
+            ..MetricsConfig::default()
+        };
+        let mut metrics = Metrics::builder("test").config(&config).build().unwrap();
+
+        metrics.add_lazy_metric("queue_depth", MetricUnit::Count, move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            42.0
+        });
+        metrics.flush_metrics();
+
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+}
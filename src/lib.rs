@@ -2,7 +2,7 @@
 //! Provides the way to put metrics to the `CloudWatch` using [EMF](https://docs.aws.amazon.com/AmazonCloudWatch/latest/monitoring/CloudWatch_Embedded_Metric_Format.html)
 //!
 //! # Examples
-//! ```
+//! ```ignore
 //! async fn function_handler(event: LambdaEvent<Request>) -> Result<Response, Error> {
 //!    let command = event.payload.command;
 //!
@@ -30,10 +30,11 @@
 //! Caller can flush metrics manually by calling `flush_metrics` method.
 //!
 //! ```
+//! # use lambda_helpers_metrics::{MetricUnit, Metrics};
 //! // ...
 //!    let mut metrics = Metrics::new("custom_lambdas", "service", "dummy_service");
 //!
-//!    metrics.try_add_dimension("application", "customer_service");
+//!    metrics.try_add_dimension("application", "customer_service").unwrap();
 //!
 //!    metrics.add_metric("test_count", MetricUnit::Count, 10.4);
 //!
@@ -42,196 +43,1677 @@
 //!    metrics.flush_metrics()
 //! // ...
 //! ```
+//!
+//! The current state of the buffer can be inspected without side effects, e.g. to check
+//! whether another dimension still fits before adding it.
+//!
+//! ```
+//! # use lambda_helpers_metrics::{MetricUnit, Metrics};
+//!    let mut metrics = Metrics::new("custom_lambdas", "service", "dummy_service");
+//!
+//!    metrics.add_metric("test_count", MetricUnit::Count, 10.4);
+//!
+//!    assert!(metrics.contains("test_count"));
+//!    assert_eq!(metrics.value_of("test_count"), Some(10.4));
+//!    assert_eq!(metrics.dimensions_remaining(), 29);
+//! ```
 use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+#[cfg(feature = "deserialize")]
+use serde::Deserialize;
+use serde::Serialize;
 
-use chrono::Utc;
-use serde::{Deserialize, Serialize};
+// lets `#[lambda_metrics]` refer to the crate by name in its own tests
+#[cfg(all(test, feature = "macros"))]
+extern crate self as lambda_helpers_metrics;
+
+mod macros;
+
+pub mod aggregator;
+#[cfg(feature = "alloc-metrics")]
+pub mod alloc_stats;
+#[cfg(feature = "events")]
+pub mod apigw;
+pub mod appconfig;
+#[cfg(feature = "archive")]
+pub mod archive;
+#[cfg(feature = "aws-sdk")]
+pub mod aws_sdk;
+#[cfg(feature = "events")]
+pub mod batch;
+pub mod borrowed;
+mod builder;
+pub mod catalog;
+pub mod clock;
+pub mod cold_start;
+pub mod collector;
+#[cfg(all(feature = "gzip", any(feature = "archive", feature = "http-push")))]
+mod compression;
+pub mod config;
+pub mod context;
+pub mod contributors;
+pub mod conventions;
+pub mod cost;
+pub mod counters;
+#[cfg(feature = "datadog")]
+pub mod datadog;
+pub mod deadline;
+pub mod defaults;
+pub mod delta;
+pub mod derived;
+pub mod destinations;
+mod dimension_set;
+pub mod dlq;
+#[cfg(feature = "dynamodb")]
+pub mod dynamodb;
+mod emf;
+mod environment;
+mod error;
+mod event;
+#[cfg(feature = "eventbridge")]
+pub mod eventbridge;
+pub mod exporters;
+pub mod fallback;
+mod filter;
+mod format;
+pub mod handle;
+#[cfg(feature = "lambda")]
+pub mod handler;
+pub mod histogram;
+#[cfg(feature = "reqwest")]
+pub mod http_client;
+#[cfg(feature = "http-push")]
+pub mod http_push;
+pub mod inspect;
+#[cfg(feature = "lambda")]
+pub mod invocation;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+mod lazy;
+#[cfg(feature = "async")]
+pub mod metric_scope;
+pub mod mode;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+pub mod outcome;
+pub mod payload_size;
+mod platform;
+mod policy;
+pub mod powertools;
+pub mod preset;
+pub mod process_stats;
+#[cfg(feature = "prometheus")]
+pub mod prometheus;
+pub mod provider;
+pub mod registry;
+pub mod rename;
+#[cfg(feature = "replay")]
+pub mod replay;
+#[cfg(feature = "async")]
+pub mod resolver;
+pub mod rollup;
+mod routing;
+pub mod runtime_info;
+pub mod schema;
+pub mod scope;
+pub mod selectors;
+pub mod self_metrics;
+pub mod shutdown;
+pub mod sink;
+pub mod slo;
+#[cfg(feature = "tracing")]
+pub mod span_fields;
+pub mod spill;
+pub mod stage;
+pub mod staleness;
+pub mod state;
+pub mod step_functions;
+pub mod storage;
+#[cfg(feature = "lambda")]
+pub mod streaming;
+pub mod template;
+pub mod tenant;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod timed;
+#[cfg(feature = "tokio-metrics")]
+pub mod tokio_stats;
+mod unit;
+pub mod value;
+pub mod warmup;
+#[cfg(feature = "fast-serialize")]
+mod writer;
+
+pub use builder::MetricsBuilder;
+pub use defaults::init;
+pub use dimension_set::DimensionSet;
+pub use environment::Environment;
+pub use error::MetricsError;
+pub use event::EVENT_FIELD;
+pub use format::{LogFields, LogLevel, OutputFormat, OutputSchema};
+#[cfg(feature = "macros")]
+pub use lambda_helpers_metrics_macros::lambda_metrics;
+pub use outcome::{MetricizedResult, OutcomeGuard};
+#[cfg(feature = "background")]
+pub use policy::ChannelOverflowPolicy;
+pub use policy::{
+    DimensionLengthPolicy, DimensionOverflowPolicy, Limits, MetricOverflowPolicy,
+    TimestampAgePolicy, MAX_DIMENSION_NAME_LEN, MAX_DIMENSION_VALUE_LEN, MAX_TIMESTAMP_AGE,
+    MAX_TIMESTAMP_SKEW, TIMESTAMP_WARNING_PROPERTY,
+};
+pub use routing::SinkRoute;
+use routing::{NamespaceRouting, SinkRoutes};
+pub use schema::MetricSchema;
+#[cfg(feature = "async")]
+pub use sink::AsyncMetricsSink;
+pub use sink::MetricsSink;
+pub use tenant::TenantContext;
+pub use unit::{MetricUnit, ParseMetricUnitError};
+pub use value::IntoMetric;
 
 const MAX_DIMENSIONS: usize = 30;
 const MAX_METRICS: usize = 100;
+const MAX_VALUES_PER_METRIC: usize = 100;
+/// The largest log event accepted by `CloudWatch` Logs.
+const MAX_EVENT_BYTES: usize = 256 * 1024;
+/// Suffix of the sample count recorded with [`Metrics::add_weighted`].
+pub const SAMPLES_SUFFIX: &str = "_samples";
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-#[serde(rename_all = "PascalCase")]
-pub(crate) struct Dimensions(HashMap<String, String>);
+/// Dimensions in insertion order, serialized as a map.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Dimensions(Vec<(String, String)>);
+
+impl Dimensions {
+    /// Returns the value of the dimension.
+    #[must_use]
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub(crate) fn contains_key(&self, key: &str) -> bool {
+        self.0.iter().any(|(name, _)| name == key)
+    }
+
+    /// Replaces the value of an existing key in place, or appends a new key.
+    pub(crate) fn insert(&mut self, key: &str, value: &str) {
+        match self.0.iter_mut().find(|(name, _)| name == key) {
+            Some((_, existing)) => *existing = value.to_string(),
+            None => self.0.push((key.to_string(), value.to_string())),
+        }
+    }
+
+    pub(crate) fn remove(&mut self, key: &str) -> Option<String> {
+        let index = self.0.iter().position(|(name, _)| name == key)?;
+        Some(self.0.remove(index).1)
+    }
+
+    pub(crate) fn remove_oldest(&mut self) -> Option<(String, String)> {
+        if self.0.is_empty() {
+            None
+        } else {
+            Some(self.0.remove(0))
+        }
+    }
+
+    /// Returns the number of dimensions.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns `true` if there are no dimensions.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Iterates over keys and values in insertion order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    pub(crate) fn keys(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().map(|(key, _)| key.as_str())
+    }
+
+    /// Returns a copy of these dimensions with `other` added on top, overriding existing keys.
+    pub(crate) fn merged(&self, other: &Dimensions) -> Dimensions {
+        let mut merged = self.clone();
+        for (key, value) in &other.0 {
+            merged.insert(key, value);
+        }
+        merged
+    }
+}
+
+impl Serialize for Dimensions {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|(key, value)| (key, value)))
+    }
+}
+
+#[cfg(feature = "deserialize")]
+impl<'de> Deserialize<'de> for Dimensions {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let map = HashMap::<String, String>::deserialize(deserializer)?;
+        Ok(Dimensions(map.into_iter().collect()))
+    }
+}
+
+/// A payload serialized by a flush, with the sink route and the metrics of its chunk.
+pub(crate) struct FlushedPayload {
+    payload: Result<String, MetricsError>,
+    route: Option<usize>,
+    /// The counts of the metrics of the library, restored for the next flush if the payload fails.
+    library: Vec<(String, f64)>,
+    /// The number of the other metrics, counted as dropped if the payload fails.
+    metrics: usize,
+}
+
+impl FlushedPayload {
+    /// Returns the size of the payload, 0 if it couldn't be serialized.
+    pub(crate) fn len(&self) -> usize {
+        self.payload.as_ref().map_or(0, String::len)
+    }
+}
+
+/// Values which are part of the payload but are not dimensions, e.g. a request id.
+#[derive(Debug, Serialize, Clone, Default)]
+#[cfg_attr(feature = "deserialize", derive(Deserialize))]
+pub(crate) struct Properties(HashMap<String, serde_json::Value>);
+
+/// The shared dimensions and properties written into the payloads: the ones of the object,
+/// or a copy with the [dimension providers](provider) applied.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PayloadFields<'a> {
+    dimensions: &'a Dimensions,
+    properties: &'a Properties,
+}
+
+impl<'a> PayloadFields<'a> {
+    pub(crate) fn new(dimensions: &'a Dimensions, properties: &'a Properties) -> Self {
+        Self {
+            dimensions,
+            properties,
+        }
+    }
+
+    /// The dimensions of a payload, the shared ones with the per-metric ones of its entries.
+    pub(crate) fn dimensions_of(&self, entries: &[&Metric]) -> Dimensions {
+        match entries.first() {
+            Some(metric) => self.dimensions.merged(&metric.dimensions),
+            None => self.dimensions.clone(),
+        }
+    }
+}
+
+/// The policies applied when a dimension is added, see [`Metrics::try_add_dimension`].
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct DimensionPolicies {
+    length: DimensionLengthPolicy,
+    overflow: DimensionOverflowPolicy,
+    max_dimensions: usize,
+}
+
+impl DimensionPolicies {
+    /// Adds a dimension to `dimensions`, or to `properties` if it is demoted.
+    pub(crate) fn add(
+        self,
+        dimensions: &mut Arc<Dimensions>,
+        properties: &mut Properties,
+        key: &str,
+        value: &str,
+    ) -> Result<(), MetricsError> {
+        let key = self
+            .length
+            .apply("dimension name", key, MAX_DIMENSION_NAME_LEN)?;
+        let value = self
+            .length
+            .apply("dimension value", value, MAX_DIMENSION_VALUE_LEN)?;
+        let (key, value) = (key.as_ref(), value.as_ref());
+        if dimensions.get(key) == Some(value) {
+            return Ok(());
+        }
+        if dimensions.len() < self.max_dimensions || dimensions.contains_key(key) {
+            // copies the dimensions if they are shared with a `DimensionSet`
+            Arc::make_mut(dimensions).insert(key, value);
+            return Ok(());
+        }
+        match self.overflow {
+            DimensionOverflowPolicy::Reject => Err(MetricsError::TooManyDimensions),
+            DimensionOverflowPolicy::DropOldest => {
+                let dimensions = Arc::make_mut(dimensions);
+                dimensions.remove_oldest();
+                dimensions.insert(key, value);
+                Ok(())
+            }
+            DimensionOverflowPolicy::DemoteToProperty => {
+                properties.0.insert(key.to_string(), value.into());
+                Ok(())
+            }
+        }
+    }
+}
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "deserialize", derive(Deserialize))]
 #[serde(rename_all = "PascalCase")]
-pub(crate) struct MetricValues(HashMap<String, f64>);
+pub(crate) struct MetricValues(HashMap<String, MetricValue>);
 
-#[derive(Debug, Serialize, Deserialize)]
+/// A single value, or an array of samples which `CloudWatch` aggregates into statistics.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+#[cfg_attr(feature = "deserialize", derive(Deserialize))]
+#[serde(untagged)]
+pub(crate) enum MetricValue {
+    Single(f64),
+    Multiple(Vec<f64>),
+}
+
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "deserialize", derive(Deserialize))]
 pub(crate) struct DimensionName(String);
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "deserialize", derive(Deserialize))]
 pub(crate) struct Namespace(String);
 
-/// `MetricDefinition` is used to serialize and publish metrics to `CloudWatch`.
-/// List of units in the [AWS Documentation](https://docs.aws.amazon.com/AmazonCloudWatch/latest/APIReference/API_MetricDatum.html)
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
-pub enum MetricUnit {
-    Seconds,
-    Microseconds,
-    Milliseconds,
-    Bytes,
-    Kilobytes,
-    Megabytes,
-    Gigabytes,
-    Terabytes,
-    Count,
-    BytesPerSecond,
-    KilobytesPerSecond,
-    MegabytesPerSecond,
-    GigabytesPerSecond,
-    TerabytesPerSecond,
-    BitsPerSecond,
-    KilobitsPerSecond,
-    MegabitsPerSecond,
-    GigabitsPerSecond,
-    TerabitsPerSecond,
-    CountPerSecond,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "deserialize", derive(Deserialize))]
 pub(crate) struct Metric {
     name: String,
     unit: MetricUnit,
-    value: f64,
+    /// Always holds at least one value, more if samples were recorded with `add_sample`.
+    values: Vec<f64>,
+    /// Dimensions which apply only to this metric, on top of the shared ones.
+    dimensions: Dimensions,
+    /// Overrides the storage resolution of the `Metrics` object, see [`MetricSchema`].
+    storage_resolution: Option<u64>,
+    /// Overrides the timestamp of the payload in milliseconds, see [`Metrics::add_metric_at`].
+    timestamp: Option<i64>,
+    /// Properties which apply only to this metric, on top of the shared ones: the fields of
+    /// the span it was recorded in with the `tracing` feature.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    properties: Vec<(String, String)>,
 }
 
 impl Metric {
-    pub(crate) fn to_metric_definition(&self) -> MetricDefinition {
+    pub(crate) fn to_metric_value(&self) -> MetricValue {
+        match self.values.as_slice() {
+            [value] => MetricValue::Single(*value),
+            values => MetricValue::Multiple(values.to_vec()),
+        }
+    }
+
+    pub(crate) fn to_metric_definition(
+        &self,
+        format: OutputFormat,
+        storage_resolution: u64,
+    ) -> MetricDefinition {
         MetricDefinition {
             name: self.name.clone(),
-            unit: self.unit.clone(),
-            storage_resolution: 60,
+            unit: self.definition_unit(format),
+            storage_resolution: definition_resolution(
+                format,
+                self.storage_resolution.unwrap_or(storage_resolution),
+            ),
         }
     }
+
+    /// The `Unit` of the metric definition, `None` if it is omitted.
+    pub(crate) fn definition_unit(&self, format: OutputFormat) -> Option<MetricUnit> {
+        (format != OutputFormat::Compact || self.unit != MetricUnit::None).then_some(self.unit)
+    }
+}
+
+/// The `StorageResolution` of metric definitions, `None` if it is omitted.
+pub(crate) fn definition_resolution(format: OutputFormat, storage_resolution: u64) -> Option<u64> {
+    match format {
+        OutputFormat::AwsEmbeddedMetrics | OutputFormat::Compact if storage_resolution == 60 => {
+            None
+        }
+        _ => Some(storage_resolution),
+    }
+}
+
+/// Whether a property is written: properties holding an empty array or object are omitted
+/// in the compact format.
+pub(crate) fn writes_property(format: OutputFormat, value: &serde_json::Value) -> bool {
+    format != OutputFormat::Compact
+        || match value {
+            serde_json::Value::Array(items) => !items.is_empty(),
+            serde_json::Value::Object(fields) => !fields.is_empty(),
+            _ => true,
+        }
 }
 
 /// `Metrics` holds the current state of metrics to be logged to the `CloudWatch`.
-/// It is eventually used to build internal `MetricDefinition` struct which is serialized and printed to the console
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(rename_all = "PascalCase")]
+/// It is eventually used to build internal `MetricDefinition` struct which is serialized and written to the sink
+#[derive(Debug)]
 pub struct Metrics {
     namespace: Namespace,
-    dimensions: Dimensions,
+    /// Shared with the `DimensionSet` the object was built from, copied on first change.
+    dimensions: Arc<Dimensions>,
+    properties: Properties,
     entries: Vec<Metric>,
+    sink: Arc<dyn MetricsSink>,
+    #[cfg(feature = "async")]
+    async_sink: Option<Arc<dyn AsyncMetricsSink>>,
+    dry_run: bool,
+    dimension_overflow: DimensionOverflowPolicy,
+    dimension_length: DimensionLengthPolicy,
+    metric_overflow: MetricOverflowPolicy,
+    timestamp_age: TimestampAgePolicy,
+    /// Set by [`MetricsBuilder::limits`].
+    limits: Limits,
+    tenant_context: TenantContext,
+    buffered_tenants: Vec<String>,
+    namespace_routing: NamespaceRouting,
+    sink_routes: SinkRoutes,
+    renames: rename::Renames,
+    metric_filter: filter::MetricFilter,
+    providers: Vec<Arc<dyn provider::DimensionProvider>>,
+    #[cfg(feature = "async")]
+    resolvers: Vec<resolver::AsyncResolver>,
+    /// Set by [`MetricsBuilder::span_field`], see [`span_fields`].
+    #[cfg(feature = "tracing")]
+    span_fields: Vec<(String, runtime_info::AttachAs)>,
+    output_format: OutputFormat,
+    output_schema: Option<OutputSchema>,
+    /// Set by [`LogFields::timestamp`].
+    log_timestamp: bool,
+    storage_resolution: u64,
+    /// Dimensions pushed with [`Metrics::push_dimension`], added to metrics recorded in the scope.
+    scoped_dimensions: Dimensions,
+    log_group_name: Option<String>,
+    log_stream_name: Option<String>,
+    library_stats: self_metrics::LibraryStats,
+    /// Set by [`MetricsBuilder::overhead_metrics`], see [`self_metrics`].
+    overhead: Option<self_metrics::Overhead>,
+    on_error: Option<error::ErrorCallback>,
+    schema: MetricSchema,
+    /// Set by [`MetricsBuilder::define_dimension_preset`], see [`preset`].
+    dimension_presets: preset::DimensionPresets,
+    /// Set by [`Metrics::enable_rollups`], see [`rollup`].
+    rollups: Vec<Vec<String>>,
+    /// Set by [`MetricsBuilder::metric_catalog`], see [`Metrics::catalog`].
+    catalog: Vec<catalog::codegen::CatalogMetric>,
+    lazy_entries: Vec<lazy::LazyMetric>,
+    derived: Vec<derived::DerivedMetric>,
+    /// Set by [`config::MetricsConfig::disabled`], nothing is serialized at flush.
+    disabled: bool,
+    metric_flags: Option<appconfig::MetricFlags>,
+    clock: Arc<dyn clock::Clock>,
+    spill: Option<spill::Spill>,
+    exporters: exporters::Exporters,
+    /// Set by [`MetricsBuilder::infer_units`], see [`Metrics::add_metric_value`].
+    infer_units: bool,
+    /// Error types recorded by [`Metrics::record_error`], up to [`outcome::MAX_ERROR_TYPES`].
+    error_types: Vec<String>,
+    /// Set by [`MetricsBuilder::container_counters`], see [`counters`].
+    container_counters: bool,
+    /// Set by [`MetricsBuilder::process_stats`] with the CPU time at the previous flush,
+    /// see [`process_stats`].
+    process_stats: Option<process_stats::CpuTimes>,
+    /// Set by [`Metrics::set_deadline`].
+    deadline: Option<chrono::DateTime<chrono::Utc>>,
+    /// Set by [`MetricsBuilder::near_timeout_threshold`], see [`deadline`].
+    near_timeout_threshold: Option<std::time::Duration>,
+    warmup_detector: Option<warmup::WarmupDetector>,
+    /// Set by `MetricsBuilder::allocation_stats` with the counts at the previous flush,
+    /// see `alloc_stats`.
+    #[cfg(feature = "alloc-metrics")]
+    allocation_stats: Option<alloc_stats::AllocationCounts>,
+    /// Set by [`MetricsBuilder::event_dimension`] and [`MetricsBuilder::event_property`],
+    /// see [`selectors`].
+    event_fields: Vec<selectors::EventField>,
+    /// Set by [`MetricsBuilder::contributor_rule`], see [`contributors`].
+    contributor_rules: Vec<contributors::ContributorRule>,
+    contributions: contributors::Contributions,
+    /// Set by [`MetricsBuilder::dimension_template`], see [`template`].
+    dimension_templates: Vec<(String, String)>,
+    /// Set by [`MetricsBuilder::staleness_watchdog`], see [`staleness`].
+    staleness: Option<staleness::Staleness>,
+    /// Set by [`MetricsBuilder::tokio_stats`], see [`tokio_stats`].
+    #[cfg(feature = "tokio-metrics")]
+    tokio_stats: Option<tokio_stats::TokioStats>,
+    /// Set by [`Metrics::detect_warmup`] until the next flush.
+    warmup: bool,
 }
 
 impl Drop for Metrics {
     fn drop(&mut self) {
-        println!("Dropping metrics, publishing metrics");
         self.flush_metrics();
     }
 }
 
 impl Metrics {
     /// Creates a new `Metrics` object with the given namespace and dimensions.
+    /// The sink is selected based on the detected [`Environment`].
     #[allow(clippy::missing_panics_doc)]
     #[must_use]
     pub fn new(namespace: &str, dimension_key: &str, dimension_value: &str) -> Self {
-        let mut metrics = Self {
-            dimensions: Dimensions(HashMap::new()),
-            namespace: Namespace(namespace.to_string()),
-            entries: Vec::new(),
-        };
         // UNWRAP: for new metrics there is no risk of reaching max number of dimensions
-        metrics
-            .try_add_dimension(dimension_key, dimension_value)
-            .unwrap();
-        metrics
+        MetricsBuilder::new(namespace)
+            .dimension(dimension_key, dimension_value)
+            .build()
+            .unwrap()
+    }
+
+    /// Creates a new `Metrics` object with the given namespace and set of dimensions,
+    /// e.g. a `HashMap` or an array of pairs.
+    /// The sink is selected based on the detected [`Environment`].
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the set doesn't fit into a payload or a dimension is invalid
+    pub fn with_dimensions<K: AsRef<str>, V: AsRef<str>>(
+        namespace: &str,
+        dimensions: impl IntoIterator<Item = (K, V)>,
+    ) -> Result<Self, MetricsError> {
+        dimensions
+            .into_iter()
+            .fold(MetricsBuilder::new(namespace), |builder, (key, value)| {
+                builder.dimension(key.as_ref(), value.as_ref())
+            })
+            .build()
+    }
+
+    /// Creates a builder for more control over the created `Metrics` object.
+    #[must_use]
+    pub fn builder(namespace: &str) -> MetricsBuilder {
+        MetricsBuilder::new(namespace)
     }
     /// Add new metric to the current `Metrics` object.
-    /// - If metric's name is already present, the current metrics will be flushed and new metric will be added.
-    /// - If the limit of `MAX_METRICS` is reached, the current metrics will be flushed automatically, and new metric will be added.
+    /// By default metrics are buffered until [`Metrics::flush_metrics`], which splits them into
+    /// as many payloads as needed: at most `MAX_METRICS` per payload, and a metric recorded
+    /// again goes into the next payload. Other behaviors can be configured with [`MetricOverflowPolicy`]:
+    /// - `FlushAndContinue`: if the metric's name is already present, or the limit of `MAX_METRICS`
+    ///   is reached, the current metrics are flushed immediately and the new metric is added.
+    /// - `Error`: at the limit, the metric is dropped and the error is printed to stderr.
     pub fn add_metric(&mut self, name: &str, unit: MetricUnit, value: f64) {
-        if self.entries.len() >= MAX_METRICS
-            || self.entries.iter().any(|metric| metric.name == name)
-        {
-            self.flush_metrics();
+        if let Err(err) = self.try_add_metric(name, unit, value) {
+            self.record_dropped(&err);
         }
-        self.entries.push(Metric {
+    }
+
+    /// Add new metric to the current `Metrics` object, see `add_metric`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if limit of `MAX_METRICS` is already reached and the policy is `Error`,
+    /// or if the metric is invalid in [strict mode](mode)
+    pub fn try_add_metric(
+        &mut self,
+        name: &str,
+        unit: MetricUnit,
+        value: f64,
+    ) -> Result<(), MetricsError> {
+        self.push_metric(self.new_metric(name, unit, value))
+    }
+
+    /// Adds a metric with its own timestamp, e.g. when replaying or backfilling data.
+    /// EMF has a single timestamp per payload, so metrics with different timestamps are
+    /// emitted in separate payloads. A metric which can't be added is counted in
+    /// `MetricsLibraryDropped` and the error is printed to stderr, like in `add_metric`.
+    ///
+    /// ```
+    /// # use lambda_helpers_metrics::{MetricUnit, Metrics};
+    /// use chrono::{TimeZone, Utc};
+    ///
+    /// let mut metrics = Metrics::new("custom_lambdas", "service", "dummy_service");
+    /// let recorded = Utc.with_ymd_and_hms(2024, 6, 1, 12, 0, 0).unwrap();
+    /// metrics.add_metric_at("orders", MetricUnit::Count, 3.0, recorded);
+    /// ```
+    pub fn add_metric_at(
+        &mut self,
+        name: &str,
+        unit: MetricUnit,
+        value: f64,
+        timestamp: chrono::DateTime<chrono::Utc>,
+    ) {
+        let result = self.push_metric(Metric {
+            timestamp: Some(timestamp.timestamp_millis()),
+            ..self.new_metric(name, unit, value)
+        });
+        if let Err(err) = result {
+            self.record_dropped(&err);
+        }
+    }
+
+    /// Returns a metric recorded now, with the dimensions and properties of the current
+    /// context: the scoped dimensions, and the fields of the current span with the `tracing`
+    /// feature.
+    pub(crate) fn new_metric(&self, name: &str, unit: MetricUnit, value: f64) -> Metric {
+        #[cfg(feature = "tracing")]
+        let (dimensions, properties) = self.span_context(&self.scoped_dimensions);
+        #[cfg(not(feature = "tracing"))]
+        let (dimensions, properties) = (self.scoped_dimensions.clone(), Vec::new());
+        Metric {
             name: name.to_string(),
             unit,
-            value,
+            values: vec![value],
+            dimensions,
+            storage_resolution: None,
+            timestamp: None,
+            properties,
+        }
+    }
+
+    /// Returns a metric recorded now with dimensions which apply only to it, see
+    /// [`Metrics::new_metric`].
+    pub(crate) fn new_metric_with_dimensions(
+        &self,
+        name: &str,
+        unit: MetricUnit,
+        value: f64,
+        dimensions: &Dimensions,
+    ) -> Metric {
+        let mut metric = self.new_metric(name, unit, value);
+        metric.dimensions = metric.dimensions.merged(dimensions);
+        metric
+    }
+
+    /// Buffers the metric, reporting it as dropped if it can't be added.
+    pub(crate) fn push_or_drop(&mut self, metric: Metric) {
+        if let Err(err) = self.push_metric(metric) {
+            self.record_dropped(&err);
+        }
+    }
+
+    /// Buffers the metric as a new entry, values are never merged into a buffered metric.
+    /// A metric with the same name, dimensions, properties and timestamp as a buffered one is
+    /// handled by the [`MetricOverflowPolicy`]; one with another timestamp goes into another
+    /// payload at flush, whatever its unit.
+    pub(crate) fn push_metric(&mut self, metric: Metric) -> Result<(), MetricsError> {
+        if !self.metric_filter.allows(&metric.name) {
+            return Ok(());
+        }
+        mode::validate(&metric)?;
+        self.check_staleness();
+        let duplicated = self.entries.iter().any(|entry| {
+            entry.name == metric.name
+                && entry.dimensions == metric.dimensions
+                && entry.timestamp == metric.timestamp
+                && entry.properties == metric.properties
         });
+        match self.metric_overflow {
+            MetricOverflowPolicy::SplitAtFlush => {}
+            MetricOverflowPolicy::FlushAndContinue => {
+                if self.entries.len() >= self.limits.max_metrics || duplicated {
+                    self.flush_metrics();
+                }
+            }
+            MetricOverflowPolicy::Error => {
+                if self.entries.len() >= self.limits.max_metrics && !duplicated {
+                    return Err(MetricsError::TooManyMetrics);
+                }
+                if duplicated {
+                    self.flush_metrics();
+                }
+            }
+        }
+        self.entries.push(metric);
+        self.mark_buffered();
+        self.spill_if_due();
+        Ok(())
+    }
+
+    /// Adds a metric with dimensions which apply only to this metric, on top of the shared ones,
+    /// without changing the shared dimensions. Metrics with different dimensions are emitted
+    /// in separate payloads. Errors are printed to stderr, like in `add_metric`.
+    ///
+    /// ```
+    /// # use lambda_helpers_metrics::{MetricUnit, Metrics};
+    /// let mut metrics = Metrics::new("custom_lambdas", "service", "dummy_service");
+    /// metrics.add_metric_with_dimensions("cache_hit", MetricUnit::Count, 1.0, &[("cache", "redis")]);
+    /// ```
+    pub fn add_metric_with_dimensions(
+        &mut self,
+        name: &str,
+        unit: MetricUnit,
+        value: f64,
+        dimensions: &[(&str, &str)],
+    ) {
+        let mut extra = Dimensions::default();
+        for (key, value) in dimensions {
+            extra.insert(key, value);
+        }
+        self.add_metric_with_extra_dimensions(name, unit, value, extra);
+    }
+
+    /// Adds a metric with dimensions which apply only to this metric.
+    /// Errors are printed to stderr, like in `add_metric`.
+    pub(crate) fn add_metric_with_extra_dimensions(
+        &mut self,
+        name: &str,
+        unit: MetricUnit,
+        value: f64,
+        dimensions: Dimensions,
+    ) {
+        if let Err(err) = self.try_add_metric_with_extra_dimensions(name, unit, value, &dimensions)
+        {
+            self.record_dropped(&err);
+        }
+    }
+
+    /// Adds a metric with dimensions which apply only to this metric.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the shared and the own dimensions of the metric exceed the limit
+    /// of dimensions, or if the metric can't be added, see [`Metrics::try_add_metric`]
+    pub(crate) fn try_add_metric_with_extra_dimensions(
+        &mut self,
+        name: &str,
+        unit: MetricUnit,
+        value: f64,
+        dimensions: &Dimensions,
+    ) -> Result<(), MetricsError> {
+        let metric = self.new_metric_with_dimensions(name, unit, value, dimensions);
+        if self.dimensions.merged(&metric.dimensions).len() > self.limits.max_dimensions {
+            return Err(MetricsError::TooManyDimensions);
+        }
+        self.push_metric(metric)
+    }
+
+    /// Increments a `Count` metric by `by`, adding it if it's not buffered yet.
+    /// Unlike `add_metric`, recording the same name again never triggers a flush.
+    /// Only a counter recorded at the current time is incremented: a metric with its own
+    /// timestamp, see [`Metrics::add_metric_at`], is left as it is and a new counter is added.
+    /// An error, e.g. because the name is buffered with another unit, is counted in
+    /// `MetricsLibraryDropped` and printed to stderr, like in `add_metric`.
+    pub fn increment(&mut self, name: &str, by: f64) {
+        self.increment_with_dimensions(name, by, Dimensions::default());
+    }
+
+    /// Increments a `Count` metric, see [`Metrics::increment`].
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the metric is buffered with another unit than `Count`, or if it
+    /// can't be added, see [`Metrics::try_add_metric`]
+    pub fn try_increment(&mut self, name: &str, by: f64) -> Result<(), MetricsError> {
+        self.try_increment_with_dimensions(name, by, &Dimensions::default())
+    }
+
+    pub(crate) fn increment_with_dimensions(
+        &mut self,
+        name: &str,
+        by: f64,
+        dimensions: Dimensions,
+    ) {
+        if let Err(err) = self.try_increment_with_dimensions(name, by, &dimensions) {
+            self.record_dropped(&err);
+        }
+    }
+
+    fn try_increment_with_dimensions(
+        &mut self,
+        name: &str,
+        by: f64,
+        dimensions: &Dimensions,
+    ) -> Result<(), MetricsError> {
+        let metric = self.new_metric_with_dimensions(name, MetricUnit::Count, by, dimensions);
+        let existing = self.entries.iter_mut().find(|entry| {
+            entry.name == name
+                && entry.dimensions == metric.dimensions
+                && entry.properties == metric.properties
+                && entry.timestamp.is_none()
+        });
+        match existing {
+            Some(entry) if entry.unit != MetricUnit::Count => Err(MetricsError::InvalidMetric(
+                format!("{name} is buffered as {}, not Count", entry.unit),
+            )),
+            Some(entry) => {
+                entry.values[0] += by;
+                Ok(())
+            }
+            None => self.push_metric(metric),
+        }
+    }
+
+    /// Records a sample of the metric. Samples of the same metric are emitted together as an
+    /// array of values, so `CloudWatch` computes statistics (e.g. percentiles) across all of them.
+    /// Unlike `add_metric`, recording the same name again doesn't trigger a flush
+    /// until the limit of 100 values per metric is reached.
+    pub fn add_sample(&mut self, name: &str, unit: MetricUnit, value: f64) {
+        self.add_sample_with_dimensions(name, unit, value, Dimensions::default());
+    }
+
+    pub(crate) fn add_sample_with_dimensions(
+        &mut self,
+        name: &str,
+        unit: MetricUnit,
+        value: f64,
+        dimensions: Dimensions,
+    ) {
+        let metric = self.new_metric_with_dimensions(name, unit, value, &dimensions);
+        let existing = self.entries.iter_mut().rev().find(|entry| {
+            entry.name == name
+                && entry.dimensions == metric.dimensions
+                && entry.properties == metric.properties
+                && entry.unit == unit
+                && entry.timestamp.is_none()
+        });
+        match existing {
+            Some(entry) if entry.values.len() < MAX_VALUES_PER_METRIC => {
+                entry.values.push(value);
+                self.spill_if_due();
+            }
+            _ => self.push_or_drop(metric),
+        }
+    }
+
+    /// Appends the value of `metric` to a buffered metric with the same definition,
+    /// dimensions and timestamp, or buffers it. A metric which can't be buffered is counted in
+    /// `MetricsLibraryDropped` and the error is printed to stderr.
+    pub(crate) fn append_value(&mut self, metric: Metric) {
+        let existing = self.entries.iter_mut().rev().find(|entry| {
+            entry.name == metric.name
+                && entry.unit == metric.unit
+                && entry.dimensions == metric.dimensions
+                && entry.storage_resolution == metric.storage_resolution
+                && entry.timestamp == metric.timestamp
+                && entry.properties == metric.properties
+        });
+        match existing {
+            Some(entry) if entry.values.len() < MAX_VALUES_PER_METRIC => {
+                entry.values.extend(metric.values);
+                self.spill_if_due();
+            }
+            _ => self.push_or_drop(metric),
+        }
+    }
+
+    /// Sets what happens when a metric is added after the limit is reached.
+    pub fn set_metric_overflow_policy(&mut self, policy: MetricOverflowPolicy) {
+        self.metric_overflow = policy;
+    }
+
+    /// Sets what happens to metrics with a timestamp `CloudWatch` would drop.
+    pub fn set_timestamp_age_policy(&mut self, policy: TimestampAgePolicy) {
+        self.timestamp_age = policy;
+    }
+
+    /// Add new metric whose unit is carried by the value's type, see [`value`].
+    /// Follows the same flushing rules as `add_metric`.
+    pub fn add(&mut self, name: &str, value: impl IntoMetric) {
+        let (unit, value) = value.into_metric();
+        self.add_metric(name, unit, value);
+    }
+
+    /// Adds a pre-aggregated value, e.g. the sum of latencies of a batch, together with the
+    /// number of samples it covers as the `<name>_samples` count, so weighted averages can be
+    /// computed in `CloudWatch` metric math as `SUM(name) / SUM(name_samples)`.
+    /// Both are recorded as samples, so repeated calls add up. If the count is not a positive
+    /// number, nothing is recorded and the error is printed to stderr.
+    ///
+    /// ```
+    /// # use lambda_helpers_metrics::{MetricUnit, Metrics};
+    /// let mut metrics = Metrics::new("custom_lambdas", "service", "dummy_service");
+    /// metrics.add_weighted("batch_latency", MetricUnit::Milliseconds, 1200.0, 10.0);
+    /// assert_eq!(metrics.value_of("batch_latency_samples"), Some(10.0));
+    /// ```
+    pub fn add_weighted(&mut self, name: &str, unit: MetricUnit, value: f64, count: f64) {
+        if !(count.is_finite() && count > 0.0) {
+            self.record_dropped(&MetricsError::InvalidMetric(format!(
+                "sample count of {name} must be positive: {count}"
+            )));
+            return;
+        }
+        self.add_sample(name, unit, value);
+        self.add_sample(&format!("{name}{SAMPLES_SUFFIX}"), MetricUnit::Count, count);
+    }
+
+    /// Adds a metric measured in one unit and recorded in another, e.g. a duration measured in
+    /// microseconds recorded as a `Milliseconds` metric, see [`MetricUnit::convert`].
+    /// Follows the same flushing rules as `add_metric`. If the units measure different things,
+    /// nothing is recorded: the metric is counted in `MetricsLibraryDropped` and the error is
+    /// printed to stderr, like in `add_metric`.
+    ///
+    /// ```
+    /// # use lambda_helpers_metrics::{MetricUnit, Metrics};
+    /// let mut metrics = Metrics::new("custom_lambdas", "service", "dummy_service");
+    /// metrics.add_metric_converted("latency", 1500.0, MetricUnit::Microseconds, MetricUnit::Milliseconds);
+    /// assert_eq!(metrics.value_of("latency"), Some(1.5));
+    /// ```
+    pub fn add_metric_converted(
+        &mut self,
+        name: &str,
+        value: f64,
+        from: MetricUnit,
+        to: MetricUnit,
+    ) {
+        match MetricUnit::convert(value, from, to) {
+            Some(value) => self.add_metric(name, to, value),
+            None => self.record_dropped(&MetricsError::InvalidMetric(format!(
+                "{name} can't be converted from {from} to {to}"
+            ))),
+        }
+    }
+
+    /// Adds a `Percent` metric: `used / capacity * 100`, e.g. for connection pool or memory
+    /// utilization. Follows the same flushing rules as `add_metric`. If the capacity is not
+    /// a positive number, nothing is recorded and the error is printed to stderr.
+    ///
+    /// ```
+    /// # use lambda_helpers_metrics::{MetricUnit, Metrics};
+    /// let mut metrics = Metrics::new("custom_lambdas", "service", "dummy_service");
+    /// metrics.add_utilization("pool_utilization", 3.0, 4.0);
+    /// assert_eq!(metrics.value_of("pool_utilization"), Some(75.0));
+    /// ```
+    pub fn add_utilization(&mut self, name: &str, used: f64, capacity: f64) {
+        if !(capacity.is_finite() && capacity > 0.0) {
+            self.record_dropped(&MetricsError::InvalidMetric(format!(
+                "capacity of {name} must be positive: {capacity}"
+            )));
+            return;
+        }
+        self.add_metric(name, MetricUnit::Percent, used / capacity * 100.0);
+    }
+
+    /// Adds a dimension, replacing the value if the key is already present.
+    /// When the limit is reached, the configured [`DimensionOverflowPolicy`] decides what happens.
+    /// Names and values longer than `CloudWatch` accepts are handled by the
+    /// [`DimensionLengthPolicy`], truncated by default.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if limit of `MAX_DIMENSION` is already reached and the policy is `Reject`
+    /// The current limit is 30
+    /// Will return `Err` if the name or value is too long and the length policy is `Error`
+    pub fn try_add_dimension(&mut self, key: &str, value: &str) -> Result<(), MetricsError> {
+        self.dimension_policies()
+            .add(&mut self.dimensions, &mut self.properties, key, value)
+    }
+
+    /// The policies of [`Metrics::try_add_dimension`], to add dimensions to a copy of the shared
+    /// dimensions too.
+    pub(crate) fn dimension_policies(&self) -> DimensionPolicies {
+        DimensionPolicies {
+            length: self.dimension_length,
+            overflow: self.dimension_overflow,
+            max_dimensions: self.limits.max_dimensions,
+        }
+    }
+
+    /// Sets what happens when a dimension is added after the limit is reached.
+    pub fn set_dimension_overflow_policy(&mut self, policy: DimensionOverflowPolicy) {
+        self.dimension_overflow = policy;
+    }
+
+    /// Sets what happens when a dimension name or value is too long.
+    pub fn set_dimension_length_policy(&mut self, policy: DimensionLengthPolicy) {
+        self.dimension_length = policy;
+    }
+
+    /// Adds a property: a value included in the payload which is not a dimension,
+    /// so it is searchable in `CloudWatch Logs Insights` without creating new metric series.
+    pub fn add_property(&mut self, key: &str, value: &str) {
+        self.add_json_property(key, value);
+    }
+
+    /// Adds a property with a structured value, e.g. an object summarising an order.
+    /// The value is written as nested JSON at the top level of the payload, so its fields
+    /// can be queried in `CloudWatch Logs Insights` (e.g. `order.total`).
+    pub fn add_json_property(&mut self, key: &str, value: impl Into<serde_json::Value>) {
+        self.properties.0.insert(key.to_string(), value.into());
+    }
+
+    /// Returns the value of the property with the given key, if present and a string.
+    #[must_use]
+    pub fn property(&self, key: &str) -> Option<&str> {
+        self.properties
+            .0
+            .get(key)
+            .and_then(serde_json::Value::as_str)
+    }
+
+    /// Returns the value of the property with the given key, if present.
+    #[must_use]
+    pub fn json_property(&self, key: &str) -> Option<&serde_json::Value> {
+        self.properties.0.get(key)
+    }
+
+    /// Returns the number of metrics currently buffered.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if no metrics are buffered.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns `true` if a metric with the given name is buffered.
+    #[must_use]
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries.iter().any(|metric| metric.name == name)
+    }
+
+    /// Returns the buffered value of the metric with the given name, if present.
+    /// For metrics holding multiple samples, the first sample is returned.
+    #[must_use]
+    pub fn value_of(&self, name: &str) -> Option<f64> {
+        self.entries
+            .iter()
+            .find(|metric| metric.name == name)
+            .map(|metric| metric.values[0])
+    }
+
+    /// Returns all buffered samples of the metric with the given name, if present.
+    #[must_use]
+    pub fn values_of(&self, name: &str) -> Option<&[f64]> {
+        self.entries
+            .iter()
+            .find(|metric| metric.name == name)
+            .map(|metric| metric.values.as_slice())
+    }
+
+    /// Returns the buffered unit of the metric with the given name, if present.
+    #[must_use]
+    pub fn unit_of(&self, name: &str) -> Option<MetricUnit> {
+        self.entries
+            .iter()
+            .find(|metric| metric.name == name)
+            .map(|metric| metric.unit)
+    }
+
+    /// Returns an iterator over the buffered metrics as `(name, unit, value)` tuples,
+    /// in the order they were added. Metrics holding multiple samples yield one tuple per sample.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, MetricUnit, f64)> {
+        self.entries.iter().flat_map(|metric| {
+            metric
+                .values
+                .iter()
+                .map(|value| (metric.name.as_str(), metric.unit, *value))
+        })
+    }
+
+    /// Returns the value of the dimension with the given key, if present.
+    #[must_use]
+    pub fn dimension(&self, key: &str) -> Option<&str> {
+        self.dimensions.get(key)
+    }
+
+    /// Returns the number of dimensions currently set.
+    #[must_use]
+    pub fn dimensions_len(&self) -> usize {
+        self.dimensions.len()
+    }
+
+    /// Returns how many more dimensions can be added before `try_add_dimension` fails.
+    #[must_use]
+    pub fn dimensions_remaining(&self) -> usize {
+        self.limits
+            .max_dimensions
+            .saturating_sub(self.dimensions.len())
+    }
+
+    /// Returns how many more metrics can be added before `add_metric` triggers a flush.
+    #[must_use]
+    pub fn metrics_remaining(&self) -> usize {
+        self.limits.max_metrics.saturating_sub(self.entries.len())
+    }
+
+    #[cfg(test)]
+    pub(crate) fn format_metrics(&self) -> CloudWatchMetricsLog {
+        self.format_entries(
+            self.payload_fields(),
+            &self.entries.iter().collect::<Vec<_>>(),
+        )
+    }
+
+    /// Splits the buffer into groups which fit into a single payload:
+    /// metrics sharing the same per-metric dimensions and properties and sink route, at most `MAX_METRICS` metrics, each name at most once.
+    pub(crate) fn payload_chunks(&self) -> Vec<Vec<&Metric>> {
+        let mut chunks: Vec<Vec<&Metric>> = Vec::new();
+        // per-metric dimensions, namespace, sink routes, timestamps and properties of the open chunks
+        type ChunkKey<'a> = (
+            &'a Dimensions,
+            Option<&'a str>,
+            Option<usize>,
+            Option<i64>,
+            &'a [(String, String)],
+        );
+        let mut open_chunks: Vec<(ChunkKey, usize)> = Vec::new();
+        for metric in &self.entries {
+            let key = (
+                &metric.dimensions,
+                self.namespace_routing.route_metric(&metric.name),
+                self.sink_route(metric),
+                metric.timestamp,
+                metric.properties.as_slice(),
+            );
+            let open = open_chunks
+                .iter_mut()
+                .find(|(open_key, _)| *open_key == key);
+            match open {
+                Some((_, index))
+                    if chunks[*index].len() < self.limits.max_metrics
+                        && !chunks[*index].iter().any(|m| m.name == metric.name) =>
+                {
+                    chunks[*index].push(metric);
+                }
+                Some((_, index)) => {
+                    chunks.push(vec![metric]);
+                    *index = chunks.len() - 1;
+                }
+                None => {
+                    chunks.push(vec![metric]);
+                    open_chunks.push((key, chunks.len() - 1));
+                }
+            }
+        }
+        chunks
+    }
+
+    /// Formats a single payload. All entries are expected to share the same per-metric dimensions
+    /// and namespace.
+    pub(crate) fn format_entries(
+        &self,
+        fields: PayloadFields<'_>,
+        entries: &[&Metric],
+    ) -> CloudWatchMetricsLog {
+        let dimensions = fields.dimensions_of(entries);
+
+        let metrics_definitions = entries
+            .iter()
+            .map(|metric| metric.to_metric_definition(self.output_format, self.storage_resolution))
+            .collect::<Vec<MetricDefinition>>();
+
+        let namespace = self.payload_namespace(entries, &dimensions);
+        let metrics_entries = vec![MetricDirective {
+            namespace,
+            dimensions: self
+                .dimension_sets(&dimensions)
+                .into_iter()
+                .map(|set| {
+                    set.into_iter()
+                        .map(|key| DimensionName(key.to_string()))
+                        .collect()
+                })
+                .collect(),
+            metrics: metrics_definitions,
+        }];
+
+        let cloudwatch_metrics = MetadataObject {
+            timestamp: self.payload_timestamp(entries),
+            cloud_watch_metrics: metrics_entries,
+            log_group_name: self.log_group_name.clone(),
+            log_stream_name: self.log_stream_name.clone(),
+        };
+
+        let metrics_values = entries
+            .iter()
+            .map(|metric| (metric.name.to_string(), metric.to_metric_value()))
+            .collect::<HashMap<_, _>>();
+
+        let mut properties = fields.properties.clone();
+        properties
+            .0
+            .retain(|_, value| writes_property(self.output_format, value));
+        for (key, value) in entries.first().map_or(&[][..], |metric| &metric.properties) {
+            properties.0.insert(key.clone(), value.as_str().into());
+        }
+        if let Some(warning) = self.timestamp_warning(entries) {
+            properties
+                .0
+                .insert(TIMESTAMP_WARNING_PROPERTY.to_string(), warning.into());
+        }
+        if let Some(timestamp) = self.log_timestamp(entries) {
+            properties
+                .0
+                .insert(format::TIMESTAMP_FIELD.to_string(), timestamp.into());
+        }
+
+        CloudWatchMetricsLog {
+            aws: cloudwatch_metrics,
+            dimensions,
+            properties,
+            metrics_values: MetricValues(metrics_values),
+        }
+    }
+
+    /// The timestamp of the payload in milliseconds: the one of its metrics if they were added
+    /// with [`Metrics::add_metric_at`], the current time otherwise.
+    pub(crate) fn payload_timestamp(&self, entries: &[&Metric]) -> i64 {
+        entries
+            .first()
+            .and_then(|metric| metric.timestamp)
+            .unwrap_or_else(|| self.clock.now().timestamp_millis())
+    }
+
+    /// Applies the [`TimestampAgePolicy`] to the buffered metrics with their own timestamp.
+    fn guard_timestamps(&mut self) {
+        let now = self.clock.now().timestamp_millis();
+        match self.timestamp_age {
+            TimestampAgePolicy::Warn => {}
+            TimestampAgePolicy::Clamp => {
+                let (oldest, newest) = TimestampAgePolicy::bounds(now);
+                for timestamp in self.entries.iter_mut().filter_map(|m| m.timestamp.as_mut()) {
+                    *timestamp = (*timestamp).clamp(oldest, newest);
+                }
+            }
+            TimestampAgePolicy::Error => {
+                let mut rejected = Vec::new();
+                self.entries.retain(|metric| {
+                    let violation = metric
+                        .timestamp
+                        .and_then(|timestamp| TimestampAgePolicy::violation(timestamp, now));
+                    if let Some(violation) = violation {
+                        rejected.push(MetricsError::InvalidMetric(format!(
+                            "{}: {violation}",
+                            metric.name
+                        )));
+                    }
+                    violation.is_none()
+                });
+                for err in rejected {
+                    self.record_dropped(&err);
+                }
+            }
+        }
+    }
+
+    /// The value of the [`TIMESTAMP_WARNING_PROPERTY`] of the payload, if `CloudWatch` would
+    /// drop it and the policy is [`TimestampAgePolicy::Warn`].
+    pub(crate) fn timestamp_warning(&self, entries: &[&Metric]) -> Option<&'static str> {
+        if self.timestamp_age != TimestampAgePolicy::Warn {
+            return None;
+        }
+        let timestamp = entries.first()?.timestamp?;
+        TimestampAgePolicy::violation(timestamp, self.clock.now().timestamp_millis())
+    }
+
+    /// Shared dimensions merged with the per-metric dimensions of the payload.
+    pub(crate) fn payload_dimensions(&self, entries: &[&Metric]) -> Dimensions {
+        self.payload_fields().dimensions_of(entries)
+    }
+
+    /// The shared dimensions and properties of the object, see [`PayloadFields`].
+    pub(crate) fn payload_fields(&self) -> PayloadFields<'_> {
+        PayloadFields::new(&self.dimensions, &self.properties)
+    }
+
+    pub(crate) fn payload_namespace(&self, entries: &[&Metric], dimensions: &Dimensions) -> String {
+        entries
+            .first()
+            .and_then(|metric| self.namespace_routing.route_metric(&metric.name))
+            .map(str::to_string)
+            .or_else(|| self.namespace_routing.route(dimensions))
+            .unwrap_or_else(|| self.namespace.0.to_string())
+    }
+
+    /// Serializes a single payload, with the hand-rolled writer if the `fast-serialize`
+    /// feature is enabled.
+    pub(crate) fn write_payload(
+        &self,
+        fields: PayloadFields<'_>,
+        entries: &[&Metric],
+        out: &mut Vec<u8>,
+    ) -> Result<(), MetricsError> {
+        let start = out.len();
+        #[cfg(feature = "fast-serialize")]
+        self.write_json(fields, entries, out);
+        #[cfg(not(feature = "fast-serialize"))]
+        serde_json::to_writer(&mut *out, &self.format_entries(fields, entries))
+            .map_err(|err| MetricsError::Serialization(err.to_string()))?;
+        // a longer line is split by CloudWatch Logs, and its metrics are not extracted
+        let written = out.len() - start;
+        if written > MAX_EVENT_BYTES {
+            out.truncate(start);
+            return Err(MetricsError::Serialization(format!(
+                "payload is {written} bytes, log events are limited to {MAX_EVENT_BYTES}"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Enables or disables dry-run mode.
+    /// In dry-run mode payloads are serialized as usual, but printed to stderr instead of being written to the sink.
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
+
+    /// Returns `true` if dry-run mode is enabled.
+    #[must_use]
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Sets when the invocation times out, e.g. from the deadline of the Lambda context,
+    /// so handlers can check [`Metrics::remaining_time`] before expensive work.
+    pub fn set_deadline(&mut self, deadline: chrono::DateTime<chrono::Utc>) {
+        self.deadline = Some(deadline);
+    }
+
+    /// Returns the time left until the deadline, zero once it has passed,
+    /// or `None` if no deadline is set.
+    #[must_use]
+    pub fn remaining_time(&self) -> Option<std::time::Duration> {
+        self.deadline
+            .map(|deadline| (deadline - self.clock.now()).to_std().unwrap_or_default())
+    }
+
+    /// Serializes the buffered metrics without flushing them, one JSON payload per line,
+    /// each line including the trailing newline. Useful with custom writers or transports.
+    /// Returns an empty vector if the buffer is empty.
+    /// The [dimension providers](crate::provider) are applied to the payloads like at a flush,
+    /// without adding their dimensions to the object.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if serialization fails
+    pub fn to_json_bytes(&self) -> Result<Vec<u8>, MetricsError> {
+        self.with_provided_fields(|fields| {
+            let mut bytes = Vec::new();
+            for chunk in self.payload_chunks() {
+                self.write_payload(fields, &chunk, &mut bytes)?;
+                bytes.push(b'\n');
+            }
+            Ok(bytes)
+        })
+    }
+
+    /// Serializes the buffered metrics without flushing them into `out`, e.g. the log buffer
+    /// of a framework, with the same output as [`Metrics::to_json_bytes`], one payload at a time.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if serialization or writing to `out` fails
+    pub fn write_emf_to(&self, out: &mut impl fmt::Write) -> Result<(), MetricsError> {
+        self.with_provided_fields(|fields| {
+            let mut payload = Vec::new();
+            for chunk in self.payload_chunks() {
+                payload.clear();
+                self.write_payload(fields, &chunk, &mut payload)?;
+                let line = std::str::from_utf8(&payload)
+                    .map_err(|err| MetricsError::Serialization(err.to_string()))?;
+                out.write_str(line)
+                    .and_then(|()| out.write_char('\n'))
+                    .map_err(|err| MetricsError::Serialization(err.to_string()))?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Merges the buffered metrics into an existing structured log record (e.g. a JSON log line
+    /// of the application), so the record is both a log event and an EMF payload.
+    /// The `_aws` metadata, dimensions, properties and metric values are added at the root
+    /// of the record, overriding existing keys with the same names.
+    ///
+    /// The embedded metrics are removed from the buffer. If they don't fit into a single payload,
+    /// the rest stays buffered for the next flush.
+    /// The [dimension providers](crate::provider) are applied like in [`Metrics::to_json_bytes`].
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if serialization fails, the record and the buffer are left untouched
+    pub fn embed_into(
+        &mut self,
+        record: &mut serde_json::Map<String, serde_json::Value>,
+    ) -> Result<(), MetricsError> {
+        let Some(chunk) = self.payload_chunks().into_iter().next() else {
+            return Ok(());
+        };
+        let payload = self
+            .with_provided_fields(|fields| {
+                serde_json::to_value(self.format_entries(fields, &chunk))
+            })
+            .map_err(|err| MetricsError::Serialization(err.to_string()))?;
+        // removes each embedded metric once, so equal metrics left out of the chunk stay
+        let mut embedded: Vec<Metric> = chunk.into_iter().cloned().collect();
+        self.entries.retain(|metric| {
+            match embedded.iter().position(|embedded| embedded == metric) {
+                Some(index) => {
+                    embedded.swap_remove(index);
+                    false
+                }
+                None => true,
+            }
+        });
+        if let serde_json::Value::Object(fields) = payload {
+            record.extend(fields);
+        }
+        Ok(())
+    }
+
+    fn emit(&self, payload: &str) -> Result<(), MetricsError> {
+        self.emit_routed(payload, None)
+    }
+
+    /// Writes a payload to the sink of the given route, see [`SinkRoute`], or the default sink.
+    fn emit_routed(&self, payload: &str, route: Option<usize>) -> Result<(), MetricsError> {
+        if self.dry_run {
+            eprintln!("Dry run, metrics not emitted: {payload}");
+            Ok(())
+        } else {
+            let emitted = match route {
+                Some(index) => self.sink_routes.sink(index).emit(payload),
+                None => self.sink.emit(payload),
+            };
+            let exported = self.exporters.emit(payload);
+            emitted.and(exported)
+        }
     }
 
+    /// Flushes the metrics to the sink.
+    /// Usually this is a single payload, unless the buffer holds more metrics than fit into one payload,
+    /// the same metric recorded more than once, or metrics with different dimensions.
+    /// Nothing is emitted if the buffer is empty.
     /// # Errors
     ///
-    /// Will return `Err` if limit of `MAX_DIMENSION` is already reached
-    /// The current limit is 30
-    pub fn try_add_dimension(&mut self, key: &str, value: &str) -> Result<(), String> {
-        if self.dimensions.0.len() >= MAX_DIMENSIONS {
-            Err("Too many dimensions".into())
-        } else {
-            self.dimensions.0.insert(key.to_string(), value.to_string());
-            Ok(())
+    /// If an error occurs during serialization or writing, it will be printed to stderr and won't be returned
+    /// The function always successes. Failures are counted and emitted with the next flush,
+    /// see [`self_metrics`]. In [strict mode](mode) errors panic in debug builds.
+    pub fn flush_metrics(&mut self) {
+        if let Err(err) = self.try_flush_metrics() {
+            mode::fail_in_strict_mode(&err);
         }
     }
 
-    pub(crate) fn format_metrics(&self) -> CloudWatchMetricsLog {
-        let metrics_definitions = self
-            .entries
-            .iter()
-            .map(Metric::to_metric_definition)
-            .collect::<Vec<MetricDefinition>>();
-
-        let metrics_entries = vec![MetricDirective {
-            namespace: self.namespace.0.to_string(),
-            dimensions: vec![self
-                .dimensions
-                .0
-                .keys()
-                .map(|key| DimensionName(key.to_string()))
-                .collect()],
-            metrics: metrics_definitions,
-        }];
+    /// Flushes the metrics to the sink, see [`Metrics::flush_metrics`].
+    /// All payloads are attempted even if one of them fails, and the buffer is cleared.
+    ///
+    /// # Errors
+    ///
+    /// Will return the first error if any payload couldn't be serialized or written
+    pub fn try_flush_metrics(&mut self) -> Result<(), MetricsError> {
+        if self.disabled {
+            self.clear_buffer();
+            return Ok(());
+        }
+        if self.skips_warmup() {
+            self.discard_warmup();
+        }
+        let (payloads, mut first_error) = self.prepare_payloads();
+        for flushed in payloads {
+            let result = flushed
+                .payload
+                .and_then(|payload| self.emit_routed(&payload, flushed.route));
+            if let Err(err) = result {
+                self.record_failed_chunk(&err, flushed.metrics, &flushed.library);
+                first_error.get_or_insert(err);
+            }
+        }
+        if let Err(err) = self.emit_contributions() {
+            first_error.get_or_insert(err);
+        }
+        self.clear_buffer();
+        first_error.map_or(Ok(()), Err)
+    }
 
-        let cloudwatch_metrics = MetadataObject {
-            timestamp: Utc::now().timestamp_millis(),
-            cloud_watch_metrics: metrics_entries,
+    /// Flushes the metrics to the async sink set with [`MetricsBuilder::async_sink`],
+    /// awaiting until all payloads are written, so the caller controls when the latency is paid.
+    /// Payloads are serialized on the current task. Without an async sink, this is the same as
+    /// [`Metrics::flush_metrics`]. Errors are printed to stderr, like in `flush_metrics`,
+    /// and panic in debug builds in [strict mode](mode). Available with the `async` feature.
+    #[cfg(feature = "async")]
+    pub async fn flush_async(&mut self) {
+        if self.skips_warmup() {
+            self.discard_warmup();
+        }
+        if !self.disabled {
+            self.refresh_resolvers().await;
+        }
+        let Some(sink) = self.async_sink.clone().filter(|_| !self.disabled) else {
+            self.flush_metrics();
+            return;
         };
+        let (payloads, mut first_error) = self.prepare_payloads();
+        for flushed in payloads {
+            let result = match flushed.payload {
+                Ok(payload) if self.dry_run || flushed.route.is_some() => {
+                    self.emit_routed(&payload, flushed.route)
+                }
+                Ok(payload) => {
+                    let exported = self.exporters.emit(&payload);
+                    sink.emit(payload).await.and(exported)
+                }
+                Err(err) => Err(err),
+            };
+            if let Err(err) = result {
+                self.record_failed_chunk(&err, flushed.metrics, &flushed.library);
+                first_error.get_or_insert(err);
+            }
+        }
+        if let Err(err) = self.emit_contributions_async(sink.as_ref()).await {
+            first_error.get_or_insert(err);
+        }
+        self.clear_buffer();
+        if let Some(err) = first_error {
+            mode::fail_in_strict_mode(&err);
+        }
+    }
+
+    /// Prepares the buffer for a flush, adding the statistics of the library, and serializes it.
+    /// Shared by [`Metrics::try_flush_metrics`] and [`Metrics::flush_async`], which only differ
+    /// in how the payloads are written. Returns the payloads and the first serialization error.
+    fn prepare_payloads(&mut self) -> (Vec<FlushedPayload>, Option<MetricsError>) {
+        self.evaluate_lazy_metrics();
+        self.evaluate_derived_metrics();
+        self.apply_renames();
+        self.guard_timestamps();
+        self.apply_providers();
+        #[cfg(feature = "async")]
+        let resolved = self.apply_resolvers();
+        self.resolve_dimension_templates();
+        self.buffer_library_metrics();
+        self.buffer_container_counters();
+        self.buffer_process_stats();
+        self.buffer_remaining_time();
+        #[cfg(feature = "alloc-metrics")]
+        self.buffer_allocation_stats();
+        #[cfg(feature = "tokio-metrics")]
+        self.buffer_tokio_stats();
+        self.buffer_overhead_metrics();
+        let started = self.clock.instant();
+        let (payloads, errors) = self.serialize_payloads();
+        let elapsed = self.clock.instant().saturating_duration_since(started);
+        #[cfg(feature = "async")]
+        self.restore_shared_fields(resolved);
+        self.record_serialization(&payloads, elapsed);
+        let first_error = self.record_serialization_errors(errors);
+        (payloads, first_error)
+    }
 
-        let metrics_values = self
-            .entries
+    /// Serializes the payloads. Payloads which can't be serialized are replaced with a
+    /// fallback payload, see [`fallback`], and their errors are returned too.
+    /// The buffer is chunked once, the payloads carry what the rest of the flush needs to know
+    /// about their chunks.
+    fn serialize_payloads(&self) -> (Vec<FlushedPayload>, Vec<MetricsError>) {
+        let mut errors = Vec::new();
+        let payloads = self
+            .payload_chunks()
             .iter()
-            .map(|metric| (metric.name.to_string(), metric.value))
-            .collect::<HashMap<_, _>>();
+            .map(|chunk| {
+                let mut payload = Vec::new();
+                let serialized = self
+                    .write_payload(self.payload_fields(), chunk, &mut payload)
+                    .and_then(|()| {
+                        String::from_utf8(payload)
+                            .map_err(|err| MetricsError::Serialization(err.to_string()))
+                    });
+                let payload = serialized.or_else(|err| {
+                    errors.push(err);
+                    Ok(self.fallback_payload(chunk))
+                });
+                let (library, others): (Vec<&Metric>, Vec<&Metric>) = chunk
+                    .iter()
+                    .partition(|metric| self_metrics::is_library_metric(metric));
+                FlushedPayload {
+                    payload,
+                    route: chunk.first().and_then(|metric| self.sink_route(metric)),
+                    library: library
+                        .iter()
+                        .map(|metric| (metric.name.clone(), metric.values[0]))
+                        .collect(),
+                    metrics: others.len(),
+                }
+            })
+            .collect();
+        (payloads, errors)
+    }
 
-        CloudWatchMetricsLog {
-            aws: cloudwatch_metrics,
-            dimensions: self.dimensions.clone(),
-            metrics_values: MetricValues(metrics_values),
+    /// Records the errors of payloads replaced with a fallback payload, returning the first.
+    fn record_serialization_errors(&mut self, errors: Vec<MetricsError>) -> Option<MetricsError> {
+        let mut first_error = None;
+        for err in errors {
+            // the metrics are emitted by the fallback payload, none is dropped
+            self.record_failed(&err, 0);
+            first_error.get_or_insert(err);
         }
+        first_error
     }
 
-    /// Flushes the metrics to stdout in a single payload.
-    /// # Errors
-    /// 
-    /// If an error occurs during serialization, it will be printed to stderr and won't be returned
-    /// The function always successes
-    pub fn flush_metrics(&mut self) {
-        let serialized_metrics: Result<String, _> = self.format_metrics().try_into();
+    /// Records the failure of a payload: its other metrics are counted as dropped, and the
+    /// counts of the library it carried are restored for the next flush, see [`FlushedPayload`].
+    fn record_failed_chunk(
+        &mut self,
+        err: &MetricsError,
+        metrics: usize,
+        library: &[(String, f64)],
+    ) {
+        self.record_failed(err, metrics);
+        self.restore_library_metrics(library);
+    }
 
-        match serialized_metrics {
-            Ok(payload) => println!("{payload}"),
-            Err(err) => eprintln!("Error when serializing metrics: {err}"),
-        }
+    fn clear_buffer(&mut self) {
+        self.remove_spill();
         self.entries = Vec::new();
+        self.lazy_entries.clear();
+        self.buffered_tenants.clear();
+        self.contributions.clear();
+        self.reset_staleness();
+        self.end_warmup();
     }
 }
 
 /// [MetricDefinition](https://docs.aws.amazon.com/AmazonCloudWatch/latest/monitoring/CloudWatch_Embedded_Metric_Format_Specification.html#CloudWatch_Embedded_Metric_Format_Specification_structure_metricdefinition)
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "deserialize", derive(Deserialize))]
 #[serde(rename_all = "PascalCase")]
 pub(crate) struct MetricDefinition {
     name: String,
-    unit: MetricUnit,
-    storage_resolution: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unit: Option<MetricUnit>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    storage_resolution: Option<u64>,
 }
 
 /// [MetricDirective](https://docs.aws.amazon.com/AmazonCloudWatch/latest/monitoring/CloudWatch_Embedded_Metric_Format_Specification.html#CloudWatch_Embedded_Metric_Format_Specification_structure_metricdirective)
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "deserialize", derive(Deserialize))]
 #[serde(rename_all = "PascalCase")]
 pub(crate) struct MetricDirective {
     namespace: String,
@@ -240,14 +1722,21 @@ pub(crate) struct MetricDirective {
 }
 
 /// [MetadataObject](https://docs.aws.amazon.com/AmazonCloudWatch/latest/monitoring/CloudWatch_Embedded_Metric_Format_Specification.html#CloudWatch_Embedded_Metric_Format_Specification_structure_metadata)
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "deserialize", derive(Deserialize))]
 #[serde(rename_all = "PascalCase")]
 pub(crate) struct MetadataObject {
     timestamp: i64,
     cloud_watch_metrics: Vec<MetricDirective>,
+    /// Used by the `CloudWatch` agent to route the payload, ignored in Lambda.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    log_group_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    log_stream_name: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "deserialize", derive(Deserialize))]
 #[serde(rename_all = "PascalCase")]
 pub(crate) struct CloudWatchMetricsLog {
     #[serde(rename = "_aws")]
@@ -255,6 +1744,8 @@ pub(crate) struct CloudWatchMetricsLog {
     #[serde(flatten)]
     dimensions: Dimensions,
     #[serde(flatten)]
+    properties: Properties,
+    #[serde(flatten)]
     metrics_values: MetricValues,
 }
 
@@ -270,6 +1761,342 @@ impl TryInto<String> for CloudWatchMetricsLog {
 mod tests {
     use super::*;
 
+    #[test]
+    fn should_add_metric_with_own_dimensions() {
+        let sink = sink::RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .dimension("service", "orders")
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+
+        metrics.add_metric_with_dimensions(
+            "cache_hit",
+            MetricUnit::Count,
+            1.0,
+            &[("cache", "redis")],
+        );
+        metrics.add_metric("requests", MetricUnit::Count, 1.0);
+        let too_many: Vec<(String, String)> = (0..30)
+            .map(|i| (format!("k{i}"), "v".to_string()))
+            .collect();
+        let too_many: Vec<(&str, &str)> = too_many
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        metrics.add_metric_with_dimensions("dropped", MetricUnit::Count, 1.0, &too_many);
+        metrics.flush_metrics();
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads.len(), 2);
+        assert_eq!(payloads[0]["cache"], "redis");
+        assert_eq!(payloads[0]["service"], "orders");
+        assert_eq!(payloads[0]["cache_hit"], 1.0);
+        assert!(payloads[1].get("cache").is_none());
+        assert_eq!(metrics.dimensions_len(), 1);
+    }
+
+    #[test]
+    fn should_serialize_to_json_lines() {
+        let mut metrics = Metrics::builder("test").dry_run(true).build().unwrap();
+        assert!(metrics.to_json_bytes().unwrap().is_empty());
+
+        metrics.add_metric("a", MetricUnit::Count, 1.0);
+        metrics.add_metric_with_extra_dimensions(
+            "b",
+            MetricUnit::Count,
+            2.0,
+            Dimensions(vec![("extra".to_string(), "x".to_string())]),
+        );
+        let bytes = metrics.to_json_bytes().unwrap();
+
+        let text = String::from_utf8(bytes).unwrap();
+        assert!(text.ends_with('\n'));
+        let lines: Vec<serde_json::Value> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["a"], 1.0);
+        assert_eq!(lines[1]["b"], 2.0);
+        assert_eq!(metrics.len(), 2);
+    }
+
+    #[test]
+    fn should_record_weighted_values_with_sample_count() {
+        let mut metrics = Metrics::new("test", "service", "dummy_service");
+
+        metrics.add_weighted("latency", MetricUnit::Milliseconds, 100.0, 4.0);
+        metrics.add_weighted("latency", MetricUnit::Milliseconds, 50.0, 1.0);
+        metrics.add_weighted("latency", MetricUnit::Milliseconds, 10.0, 0.0);
+
+        assert_eq!(metrics.values_of("latency"), Some(&[100.0, 50.0][..]));
+        assert_eq!(metrics.values_of("latency_samples"), Some(&[4.0, 1.0][..]));
+    }
+
+    #[test]
+    fn should_write_emf_into_caller_buffer() {
+        use chrono::TimeZone;
+
+        let start = chrono::Utc.timestamp_millis_opt(1_700_000_000_000).unwrap();
+        let clock = clock::ManualClock::new(start);
+        let mut metrics = Metrics::builder("test")
+            .dimension("service", "dummy_service")
+            .clock(clock)
+            .dry_run(true)
+            .build()
+            .unwrap();
+        metrics.add_metric("count", MetricUnit::Count, 1.0);
+        metrics.add_metric_with_dimensions("hits", MetricUnit::Count, 3.0, &[("cache", "redis")]);
+        let mut out = String::from("log: ");
+
+        metrics.write_emf_to(&mut out).unwrap();
+
+        let expected = String::from_utf8(metrics.to_json_bytes().unwrap()).unwrap();
+        assert_eq!(out["log: ".len()..], expected);
+        assert_eq!(expected.lines().count(), 2);
+        assert_eq!(metrics.len(), 2);
+    }
+
+    #[test]
+    fn should_embed_metrics_into_log_record() {
+        let mut metrics = Metrics::builder("test")
+            .dimension("service", "orders")
+            .dry_run(true)
+            .build()
+            .unwrap();
+        metrics.add_metric("a", MetricUnit::Count, 1.0);
+        metrics.add_metric_with_extra_dimensions(
+            "b",
+            MetricUnit::Count,
+            2.0,
+            Dimensions(vec![("extra".to_string(), "x".to_string())]),
+        );
+        let mut record = serde_json::Map::new();
+        record.insert("level".to_string(), "INFO".into());
+        record.insert("message".to_string(), "order placed".into());
+
+        metrics.embed_into(&mut record).unwrap();
+
+        assert_eq!(record["level"], "INFO");
+        assert_eq!(record["service"], "orders");
+        assert_eq!(record["a"], 1.0);
+        assert_eq!(record["_aws"]["CloudWatchMetrics"][0]["Namespace"], "test");
+        assert!(!record.contains_key("b"));
+        assert_eq!(metrics.len(), 1);
+        assert!(metrics.contains("b"));
+    }
+
+    #[test]
+    fn should_write_payload_to_sink() {
+        let sink = sink::RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .dimension("service", "dummy_service")
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+        metrics.add_metric("test", MetricUnit::Count, 2.0);
+        metrics.flush_metrics();
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads.len(), 1);
+        assert_eq!(payloads[0]["test"], 2.0);
+        assert_eq!(payloads[0]["service"], "dummy_service");
+        assert_eq!(
+            payloads[0]["_aws"]["CloudWatchMetrics"][0]["Namespace"],
+            "test"
+        );
+    }
+
+    #[test]
+    fn should_not_write_to_sink_in_dry_run() {
+        let sink = sink::RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .sink(sink.clone())
+            .dry_run(true)
+            .build()
+            .unwrap();
+        metrics.add_metric("test", MetricUnit::Count, 2.0);
+        metrics.flush_metrics();
+
+        assert!(metrics.is_empty());
+        assert!(sink.payloads().is_empty());
+    }
+
+    #[test]
+    fn should_create_metrics_with_dimension_set() {
+        let metrics = Metrics::with_dimensions(
+            "test",
+            [("service", "orders"), ("application", "customer_service")],
+        )
+        .unwrap();
+        assert_eq!(metrics.dimension("service"), Some("orders"));
+        assert_eq!(metrics.dimension("application"), Some("customer_service"));
+
+        let map: HashMap<String, String> = (0..=MAX_DIMENSIONS)
+            .map(|i| (format!("key{i}"), format!("value{i}")))
+            .collect();
+        assert!(matches!(
+            Metrics::with_dimensions("test", map),
+            Err(MetricsError::TooManyDimensions)
+        ));
+    }
+
+    fn metrics_at_dimension_limit(policy: DimensionOverflowPolicy) -> Metrics {
+        let mut metrics = Metrics::builder("test")
+            .dimension_overflow(policy)
+            .build()
+            .unwrap();
+        for i in 0..MAX_DIMENSIONS {
+            metrics
+                .try_add_dimension(&format!("key{i}"), &format!("value{i}"))
+                .unwrap();
+        }
+        metrics
+    }
+
+    #[test]
+    fn should_reject_extra_dimensions_over_the_limit() {
+        let mut metrics = metrics_at_dimension_limit(DimensionOverflowPolicy::DropOldest);
+        let extra = Dimensions(vec![("tenant".to_string(), "acme".to_string())]);
+
+        assert!(matches!(
+            metrics.try_add_metric_with_extra_dimensions("orders", MetricUnit::Count, 1.0, &extra),
+            Err(MetricsError::TooManyDimensions)
+        ));
+        metrics.add_metric_with_extra_dimensions("orders", MetricUnit::Count, 1.0, extra);
+        assert!(metrics.is_empty());
+    }
+
+    #[test]
+    fn should_drop_oldest_dimension_on_overflow() {
+        let mut metrics = metrics_at_dimension_limit(DimensionOverflowPolicy::DropOldest);
+
+        metrics.try_add_dimension("extra", "value").unwrap();
+
+        assert_eq!(metrics.dimensions_len(), MAX_DIMENSIONS);
+        assert_eq!(metrics.dimension("key0"), None);
+        assert_eq!(metrics.dimension("extra"), Some("value"));
+    }
+
+    #[test]
+    fn should_demote_dimension_to_property_on_overflow() {
+        let mut metrics = metrics_at_dimension_limit(DimensionOverflowPolicy::DemoteToProperty);
+
+        metrics.try_add_dimension("extra", "value").unwrap();
+
+        assert_eq!(metrics.dimensions_len(), MAX_DIMENSIONS);
+        assert_eq!(metrics.dimension("extra"), None);
+        assert_eq!(metrics.property("extra"), Some("value"));
+        let log = serde_json::to_value(metrics.format_metrics()).unwrap();
+        assert_eq!(log["extra"], "value");
+    }
+
+    #[test]
+    fn should_write_nested_property_values() {
+        let sink = sink::RecordingSink::default();
+        let mut metrics = Metrics::builder("test").sink(sink.clone()).build().unwrap();
+        let order = serde_json::json!({ "id": "o-1", "items": [1, 2], "total": 12.5 });
+
+        metrics.add_json_property("order", order.clone());
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+        metrics.flush_metrics();
+
+        assert_eq!(metrics.json_property("order"), Some(&order));
+        assert_eq!(metrics.property("order"), None);
+        assert_eq!(sink.payloads()[0]["order"], order);
+    }
+
+    #[test]
+    fn should_apply_length_policy_to_dimensions() {
+        let mut metrics = Metrics::new("test", "service", "dummy_service");
+        let long = "x".repeat(MAX_DIMENSION_VALUE_LEN + 1);
+
+        metrics.try_add_dimension("request", &long).unwrap();
+        let value = metrics.dimension("request").unwrap();
+        assert_eq!(value.len(), MAX_DIMENSION_VALUE_LEN);
+        assert!(value.ends_with("..."));
+
+        metrics.set_dimension_length_policy(DimensionLengthPolicy::Error);
+        assert!(matches!(
+            metrics.try_add_dimension("payload", &long),
+            Err(MetricsError::InvalidDimension(_))
+        ));
+        assert_eq!(metrics.dimension("payload"), None);
+    }
+
+    #[test]
+    fn should_split_into_payloads_at_flush() {
+        let sink = sink::RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .sink(sink.clone())
+            .metric_overflow(MetricOverflowPolicy::SplitAtFlush)
+            .build()
+            .unwrap();
+        for i in 0..250 {
+            metrics.add_metric(&format!("metric{i}"), MetricUnit::Count, 1.0);
+        }
+        metrics.add_metric("metric0", MetricUnit::Count, 2.0);
+
+        assert_eq!(metrics.len(), 251);
+        assert!(sink.payloads().is_empty());
+
+        metrics.flush_metrics();
+
+        let sizes: Vec<usize> = sink
+            .payloads()
+            .iter()
+            .map(|payload| {
+                payload["_aws"]["CloudWatchMetrics"][0]["Metrics"]
+                    .as_array()
+                    .unwrap()
+                    .len()
+            })
+            .collect();
+        assert_eq!(sizes, vec![100, 100, 51]);
+    }
+
+    #[test]
+    fn should_reject_metric_over_limit() {
+        let mut metrics = Metrics::builder("test")
+            .metric_overflow(MetricOverflowPolicy::Error)
+            .build()
+            .unwrap();
+        for i in 0..MAX_METRICS {
+            metrics
+                .try_add_metric(&format!("metric{i}"), MetricUnit::Count, 1.0)
+                .unwrap();
+        }
+
+        assert!(matches!(
+            metrics.try_add_metric("over_100", MetricUnit::Count, 1.0),
+            Err(MetricsError::TooManyMetrics)
+        ));
+        assert_eq!(metrics.len(), MAX_METRICS);
+    }
+
+    #[test]
+    fn should_not_emit_empty_payload() {
+        let sink = sink::RecordingSink::default();
+        let mut metrics = Metrics::builder("test").sink(sink.clone()).build().unwrap();
+        metrics.flush_metrics();
+
+        assert!(sink.payloads().is_empty());
+    }
+
+    #[test]
+    fn should_replace_dimension_at_limit() {
+        let mut metrics = Metrics::new("test", "service", "dummy_service");
+        for i in 0..29 {
+            metrics
+                .try_add_dimension(&format!("key{i}"), &format!("value{i}"))
+                .unwrap();
+        }
+
+        metrics.try_add_dimension("service", "other").unwrap();
+        assert_eq!(metrics.dimension("service"), Some("other"));
+    }
+
     #[test]
     fn should_create_metrics() {
         let mut metrics = Metrics::new("test_namespace", "service", "dummy_service");
@@ -285,40 +2112,121 @@ mod tests {
         );
         assert_eq!(
             log.aws.cloud_watch_metrics[0].metrics[0].unit,
-            MetricUnit::Count
+            Some(MetricUnit::Count)
         );
         assert_eq!(
             log.aws.cloud_watch_metrics[0].metrics[0].storage_resolution,
-            60
+            Some(60)
+        );
+        assert_eq!(
+            log.metrics_values.0.get("test_metric_count"),
+            Some(&MetricValue::Single(1.0))
         );
-        assert_eq!(log.metrics_values.0.get("test_metric_count"), Some(&1.0));
         assert_eq!(
             log.aws.cloud_watch_metrics[0].metrics[1].name,
             "test_metric_seconds"
         );
         assert_eq!(
             log.aws.cloud_watch_metrics[0].metrics[1].unit,
-            MetricUnit::Seconds
+            Some(MetricUnit::Seconds)
         );
         assert_eq!(
             log.aws.cloud_watch_metrics[0].metrics[1].storage_resolution,
-            60
+            Some(60)
         );
-        assert_eq!(log.dimensions.0.len(), 1);
+        assert_eq!(log.dimensions.len(), 1);
+    }
+
+    #[test]
+    fn should_include_log_group_and_stream_names() {
+        let sink = sink::RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .log_group_name("orders-metrics")
+            .log_stream_name("task-1")
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+        metrics.add_metric("test", MetricUnit::Count, 1.0);
+        metrics.flush_metrics();
+
+        let payload = &sink.payloads()[0];
+        assert_eq!(payload["_aws"]["LogGroupName"], "orders-metrics");
+        assert_eq!(payload["_aws"]["LogStreamName"], "task-1");
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn should_flush_to_async_sink() {
+        let sink = sink::RecordingSink::default();
+        let async_sink = sink::RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .sink(sink.clone())
+            .async_sink(async_sink.clone())
+            .build()
+            .unwrap();
+        metrics.add_metric("test", MetricUnit::Count, 1.0);
+
+        {
+            let mut flush = std::pin::pin!(metrics.flush_async());
+            let mut context = std::task::Context::from_waker(std::task::Waker::noop());
+            assert!(std::future::Future::poll(flush.as_mut(), &mut context).is_ready());
+        }
+
+        assert!(sink.payloads().is_empty());
+        assert_eq!(async_sink.payloads()[0]["test"], 1.0);
+        assert!(metrics.is_empty());
     }
 
     #[test]
     fn should_handle_duplicated_metric() {
-        let mut metrics = Metrics::new("test", "service", "dummy_service");
+        let sink = sink::RecordingSink::default();
+        let mut metrics = Metrics::builder("test").sink(sink.clone()).build().unwrap();
+        metrics.add_metric("test", MetricUnit::Count, 2.0);
+        metrics.add_metric("test", MetricUnit::Count, 1.0);
+
+        assert_eq!(metrics.entries.len(), 2);
+        assert!(sink.payloads().is_empty());
+        metrics.flush_metrics();
+        let payloads = sink.payloads();
+        assert_eq!(payloads.len(), 2);
+        assert_eq!(payloads[0]["test"], 2.0);
+        assert_eq!(payloads[1]["test"], 1.0);
+    }
+
+    #[test]
+    fn should_flush_duplicated_metric_with_flush_and_continue() {
+        let mut metrics = Metrics::builder("test")
+            .metric_overflow(MetricOverflowPolicy::FlushAndContinue)
+            .dry_run(true)
+            .build()
+            .unwrap();
         metrics.add_metric("test", MetricUnit::Count, 2.0);
         metrics.add_metric("test", MetricUnit::Count, 1.0);
 
         assert_eq!(metrics.entries.len(), 1);
     }
 
+    #[test]
+    fn should_buffer_over_100_metrics_until_flush() {
+        let sink = sink::RecordingSink::default();
+        let mut metrics = Metrics::builder("test").sink(sink.clone()).build().unwrap();
+        for i in 0..101 {
+            metrics.add_metric(&format!("metric{i}"), MetricUnit::Count, f64::from(i));
+        }
+
+        assert_eq!(metrics.entries.len(), 101);
+        assert!(sink.payloads().is_empty());
+        metrics.flush_metrics();
+        assert_eq!(sink.payloads().len(), 2);
+    }
+
     #[test]
     fn should_not_fail_over_100_metrics() {
-        let mut metrics = Metrics::new("test", "service", "dummy_service");
+        let mut metrics = Metrics::builder("test")
+            .metric_overflow(MetricOverflowPolicy::FlushAndContinue)
+            .dry_run(true)
+            .build()
+            .unwrap();
         for i in 0..100 {
             metrics.add_metric(&format!("metric{i}"), MetricUnit::Count, i as f64);
         }
@@ -337,9 +2245,204 @@ mod tests {
                 .unwrap();
         }
 
-        match metrics.try_add_dimension("key31", "value31") {
-            Ok(_) => assert!(false, "expected error"),
-            Err(_) => assert!(true),
-        }
+        assert!(
+            metrics.try_add_dimension("key31", "value31").is_err(),
+            "expected error"
+        );
+    }
+
+    #[test]
+    fn should_introspect_without_side_effects() {
+        let mut metrics = Metrics::new("test", "service", "dummy_service");
+        assert!(metrics.is_empty());
+        assert_eq!(metrics.dimensions_remaining(), MAX_DIMENSIONS - 1);
+        assert_eq!(metrics.dimension("service"), Some("dummy_service"));
+
+        metrics.add_metric("test", MetricUnit::Count, 2.0);
+
+        assert_eq!(metrics.len(), 1);
+        assert!(metrics.contains("test"));
+        assert!(!metrics.contains("other"));
+        assert_eq!(metrics.value_of("test"), Some(2.0));
+        assert_eq!(metrics.unit_of("test"), Some(MetricUnit::Count));
+        assert_eq!(metrics.value_of("other"), None);
+        assert_eq!(metrics.metrics_remaining(), MAX_METRICS - 1);
+        assert_eq!(metrics.len(), 1);
+    }
+
+    #[test]
+    fn should_add_unit_typed_values() {
+        let mut metrics = Metrics::new("test", "service", "dummy_service");
+        metrics.add("latency", value::Milliseconds(120));
+
+        assert_eq!(metrics.unit_of("latency"), Some(MetricUnit::Milliseconds));
+        assert_eq!(metrics.value_of("latency"), Some(120.0));
+    }
+
+    #[test]
+    fn should_add_utilization_unless_capacity_is_zero() {
+        let mut metrics = Metrics::new("test", "service", "dummy_service");
+        metrics.add_utilization("pool_utilization", 1.0, 8.0);
+        metrics.add_utilization("memory_utilization", 1.0, 0.0);
+
+        assert_eq!(metrics.value_of("pool_utilization"), Some(12.5));
+        assert_eq!(
+            metrics.unit_of("pool_utilization"),
+            Some(MetricUnit::Percent)
+        );
+        assert!(!metrics.contains("memory_utilization"));
+    }
+
+    #[test]
+    fn should_increment_without_flushing() {
+        let sink = sink::RecordingSink::default();
+        let mut metrics = Metrics::builder("test").sink(sink.clone()).build().unwrap();
+        metrics.increment("orders", 1.0);
+        metrics.increment("orders", 2.0);
+
+        assert_eq!(metrics.value_of("orders"), Some(3.0));
+        assert_eq!(metrics.unit_of("orders"), Some(MetricUnit::Count));
+        assert!(sink.payloads().is_empty());
+    }
+
+    #[test]
+    fn should_reject_increment_of_metric_with_other_unit() {
+        let mut metrics = Metrics::builder("test").dry_run(true).build().unwrap();
+        metrics.add_metric("orders", MetricUnit::Milliseconds, 5.0);
+
+        assert!(matches!(
+            metrics.try_increment("orders", 1.0),
+            Err(MetricsError::InvalidMetric(_))
+        ));
+        metrics.increment("orders", 1.0);
+        assert_eq!(metrics.values_of("orders"), Some(&[5.0][..]));
+    }
+
+    #[test]
+    fn should_not_increment_metric_with_own_timestamp() {
+        use chrono::TimeZone;
+
+        let mut metrics = Metrics::builder("test").dry_run(true).build().unwrap();
+        let recorded = chrono::Utc.timestamp_millis_opt(1_717_243_200_000).unwrap();
+        metrics.add_metric_at("orders", MetricUnit::Count, 5.0, recorded);
+        metrics.increment("orders", 1.0);
+        metrics.increment("orders", 2.0);
+
+        let values: Vec<f64> = metrics.iter().map(|(_, _, value)| value).collect();
+        assert_eq!(values, vec![5.0, 3.0]);
+    }
+
+    #[test]
+    fn should_emit_samples_as_value_array() {
+        let sink = sink::RecordingSink::default();
+        let mut metrics = Metrics::builder("test").sink(sink.clone()).build().unwrap();
+        metrics.add_sample("latency", MetricUnit::Milliseconds, 10.0);
+        metrics.add_sample("latency", MetricUnit::Milliseconds, 20.0);
+        metrics.add_sample("single", MetricUnit::Milliseconds, 5.0);
+
+        assert_eq!(metrics.values_of("latency"), Some(&[10.0, 20.0][..]));
+        assert_eq!(metrics.iter().count(), 3);
+        metrics.flush_metrics();
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads.len(), 1);
+        assert_eq!(payloads[0]["latency"], serde_json::json!([10.0, 20.0]));
+        assert_eq!(payloads[0]["single"], 5.0);
+    }
+
+    #[test]
+    fn should_iterate_over_buffered_metrics() {
+        let mut metrics = Metrics::new("test", "service", "dummy_service");
+        metrics.add_metric("first", MetricUnit::Count, 1.0);
+        metrics.add_metric("second", MetricUnit::Milliseconds, 2.5);
+
+        let collected: Vec<_> = metrics.iter().collect();
+
+        assert_eq!(
+            collected,
+            vec![
+                ("first", MetricUnit::Count, 1.0),
+                ("second", MetricUnit::Milliseconds, 2.5)
+            ]
+        );
+        let total: f64 = metrics.iter().map(|(_, _, value)| value).sum();
+        assert_eq!(total, 3.5);
+    }
+
+    #[test]
+    fn should_split_payloads_by_metric_timestamp() {
+        use chrono::TimeZone;
+
+        let sink = sink::RecordingSink::default();
+        let mut metrics = Metrics::builder("test").sink(sink.clone()).build().unwrap();
+        let earlier = chrono::Utc.timestamp_millis_opt(1_717_243_200_000).unwrap();
+        let later = chrono::Utc.timestamp_millis_opt(1_717_243_260_000).unwrap();
+
+        metrics.add_metric_at("orders", MetricUnit::Count, 1.0, earlier);
+        metrics.add_metric_at("errors", MetricUnit::Count, 2.0, earlier);
+        metrics.add_metric_at("orders", MetricUnit::Count, 3.0, later);
+        metrics.add_metric("orders", MetricUnit::Count, 4.0);
+        metrics.flush_metrics();
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads.len(), 3);
+        assert_eq!(payloads[0]["_aws"]["Timestamp"], 1_717_243_200_000_i64);
+        assert_eq!(payloads[0]["errors"], 2.0);
+        assert_eq!(payloads[1]["_aws"]["Timestamp"], 1_717_243_260_000_i64);
+        assert_eq!(payloads[1]["orders"], 3.0);
+        assert_eq!(payloads[2]["orders"], 4.0);
+    }
+
+    #[test]
+    fn should_apply_timestamp_age_policy_at_flush() {
+        use chrono::TimeZone;
+
+        let now = chrono::Utc.timestamp_millis_opt(1_717_243_200_000).unwrap();
+        let stale = now - chrono::Duration::days(20);
+        let metrics_with = |policy| {
+            let sink = sink::RecordingSink::default();
+            let metrics = Metrics::builder("test")
+                .sink(sink.clone())
+                .clock(clock::ManualClock::new(now))
+                .timestamp_age(policy)
+                .build()
+                .unwrap();
+            (metrics, sink)
+        };
+
+        let (mut metrics, sink) = metrics_with(TimestampAgePolicy::Warn);
+        metrics.add_metric_at("orders", MetricUnit::Count, 1.0, stale);
+        metrics.flush_metrics();
+        let payload = &sink.payloads()[0];
+        assert_eq!(payload["_aws"]["Timestamp"], stale.timestamp_millis());
+        assert_eq!(
+            payload[TIMESTAMP_WARNING_PROPERTY],
+            "timestamp is older than 14 days"
+        );
+
+        let (mut metrics, sink) = metrics_with(TimestampAgePolicy::Clamp);
+        metrics.add_metric_at("orders", MetricUnit::Count, 1.0, stale);
+        metrics.add_metric_at(
+            "errors",
+            MetricUnit::Count,
+            1.0,
+            now + chrono::Duration::hours(3),
+        );
+        metrics.flush_metrics();
+        let payloads = sink.payloads();
+        let oldest = (now - chrono::Duration::days(14)).timestamp_millis();
+        let newest = (now + chrono::Duration::hours(2)).timestamp_millis();
+        assert_eq!(payloads[0]["_aws"]["Timestamp"], oldest);
+        assert_eq!(payloads[1]["_aws"]["Timestamp"], newest);
+        assert!(payloads[0].get(TIMESTAMP_WARNING_PROPERTY).is_none());
+
+        let (mut metrics, sink) = metrics_with(TimestampAgePolicy::Error);
+        metrics.add_metric_at("orders", MetricUnit::Count, 1.0, stale);
+        metrics.add_metric("errors", MetricUnit::Count, 1.0);
+        metrics.flush_metrics();
+        let payloads = sink.payloads();
+        assert_eq!(payloads.len(), 1);
+        assert!(payloads[0].get("orders").is_none());
+        assert_eq!(payloads[0][self_metrics::DROPPED_METRIC], 1.0);
     }
 }
@@ -0,0 +1,166 @@
+/// Records several metrics in one statement, as `name = value unit` pairs.
+///
+/// The value is a single token: a literal, a variable or a parenthesized expression,
+/// converted with `as f64`. The unit is a shorthand (`count`, `percent`, `none`, `s`, `ms`,
+/// `us`, `bytes`, `kb`, `mb`, `gb`, `tb`, `bits`) or the name of a [`MetricUnit`](crate::MetricUnit)
+/// variant. Without a unit, the value must implement [`IntoMetric`](crate::IntoMetric),
+/// which carries the unit in its type. Names which are not identifiers can be written
+/// as string literals.
+///
+/// Each pair is recorded with `add_metric`, so the same flushing rules apply.
+///
+/// ```
+/// use lambda_helpers_metrics::value::Milliseconds;
+/// use lambda_helpers_metrics::{emit, Metrics};
+///
+/// let mut metrics = Metrics::new("custom_lambdas", "service", "dummy_service");
+/// let elapsed_ms = 120;
+/// let size = 2048;
+///
+/// emit!(metrics,
+///     orders = 3 count,
+///     latency = elapsed_ms ms,
+///     payload = size bytes,
+///     "cache.hit_ratio" = (0.5 * 100.0) percent,
+///     db_latency = (Milliseconds(15)),
+///     throughput = 42 BytesPerSecond,
+/// );
+/// assert_eq!(metrics.len(), 6);
+/// ```
+#[macro_export]
+macro_rules! emit {
+    ($metrics:expr, $($name:tt = $value:tt $($unit:ident)?),+ $(,)?) => {{
+        let metrics = &mut $metrics;
+        $(
+            $crate::__emit_metric!(metrics, $name, $value $(, $unit)?);
+        )+
+    }};
+}
+
+/// Declares metrics as [`DefinedMetric`](crate::catalog::DefinedMetric) constants,
+/// as `NAME: unit` or `NAME = "metric.name": unit` pairs, see [`crate::catalog`].
+///
+/// The unit is written like in [`emit!`](crate::emit). Constants can be preceded by
+/// attributes, e.g. doc comments, and a visibility.
+#[macro_export]
+macro_rules! define_metrics {
+    ($($(#[$attr:meta])* $vis:vis $name:ident $(= $metric:literal)? : $unit:ident),* $(,)?) => {
+        $(
+            $(#[$attr])*
+            $vis const $name: $crate::catalog::DefinedMetric = $crate::catalog::DefinedMetric::new(
+                $crate::__defined_metric_name!($name $(, $metric)?),
+                $crate::__metric_unit!($unit),
+            );
+        )*
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __defined_metric_name {
+    ($name:ident, $metric:literal) => {
+        $metric
+    };
+    ($name:ident) => {{
+        const LOWERCASE: [u8; stringify!($name).len()] =
+            $crate::catalog::__lowercase(stringify!($name));
+        match ::core::str::from_utf8(&LOWERCASE) {
+            Ok(name) => name,
+            Err(_) => panic!("metric names are identifiers"),
+        }
+    }};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __emit_metric {
+    ($metrics:ident, $name:tt, $value:tt, $unit:ident) => {
+        #[allow(clippy::cast_precision_loss, clippy::unnecessary_cast)]
+        $metrics.add_metric(
+            $crate::__metric_name!($name),
+            $crate::__metric_unit!($unit),
+            $value as f64,
+        )
+    };
+    ($metrics:ident, $name:tt, $value:tt) => {
+        $metrics.add($crate::__metric_name!($name), $value)
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __metric_name {
+    ($name:ident) => {
+        stringify!($name)
+    };
+    ($name:literal) => {
+        $name
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __metric_unit {
+    (count) => {
+        $crate::MetricUnit::Count
+    };
+    (percent) => {
+        $crate::MetricUnit::Percent
+    };
+    (none) => {
+        $crate::MetricUnit::None
+    };
+    (s) => {
+        $crate::MetricUnit::Seconds
+    };
+    (ms) => {
+        $crate::MetricUnit::Milliseconds
+    };
+    (us) => {
+        $crate::MetricUnit::Microseconds
+    };
+    (bytes) => {
+        $crate::MetricUnit::Bytes
+    };
+    (kb) => {
+        $crate::MetricUnit::Kilobytes
+    };
+    (mb) => {
+        $crate::MetricUnit::Megabytes
+    };
+    (gb) => {
+        $crate::MetricUnit::Gigabytes
+    };
+    (tb) => {
+        $crate::MetricUnit::Terabytes
+    };
+    (bits) => {
+        $crate::MetricUnit::Bits
+    };
+    ($unit:ident) => {
+        $crate::MetricUnit::$unit
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::value::Bytes;
+    use crate::{MetricUnit, Metrics};
+
+    #[test]
+    fn should_record_all_pairs() {
+        let mut metrics = Metrics::builder("test")
+            .sink(crate::sink::NullSink)
+            .build()
+            .unwrap();
+        let elapsed_ms: u64 = 120;
+
+        crate::emit!(metrics, orders = 3 count, latency = elapsed_ms ms, "payload.size" = (Bytes(10)));
+
+        assert_eq!(metrics.value_of("orders"), Some(3.0));
+        assert_eq!(metrics.unit_of("orders"), Some(MetricUnit::Count));
+        assert_eq!(metrics.value_of("latency"), Some(120.0));
+        assert_eq!(metrics.unit_of("latency"), Some(MetricUnit::Milliseconds));
+        assert_eq!(metrics.unit_of("payload.size"), Some(MetricUnit::Bytes));
+    }
+}
@@ -0,0 +1,234 @@
+//! Ergonomics of `metricScope` of the Node
+//! [`aws-embedded-metrics`](https://github.com/awslabs/aws-embedded-metrics-node) library.
+//!
+//! [`metric_scope`] creates a [`MetricsLogger`] for the duration of the closure and flushes
+//! it once the closure returns, so code ported from Node keeps its shape:
+//!
+//! ```
+//! use lambda_helpers_metrics::metric_scope::metric_scope;
+//! use lambda_helpers_metrics::MetricUnit;
+//!
+//! async fn handler(request_id: &str) -> u32 {
+//!     metric_scope(async |metrics| {
+//!         metrics
+//!             .put_dimensions([("Service", "Aggregator")])
+//!             .put_metric("ProcessingLatency", 100.0, MetricUnit::Milliseconds)
+//!             .set_property("RequestId", request_id);
+//!         42
+//!     })
+//!     .await
+//! }
+//! ```
+//!
+//! The namespace is `aws-embedded-metrics`, unless set with [`MetricsLogger::set_namespace`]
+//! or the configuration from the environment, see [`MetricsConfig::from_env`]. Unlike the
+//! Node library, all dimensions form a single dimension set.
+//!
+//! Available with the `async` feature.
+use chrono::{DateTime, Utc};
+
+use crate::config::MetricsConfig;
+use crate::powertools::MetricResolution;
+use crate::{mode, Dimensions, Metric, MetricUnit, Metrics, MetricsError, Namespace};
+
+/// Namespace of [`metric_scope`] when none is configured, the one of the Node library.
+pub const DEFAULT_NAMESPACE: &str = "aws-embedded-metrics";
+
+/// Runs the closure with a new [`MetricsLogger`] and flushes it with
+/// [`Metrics::flush_async`] once the closure returns, see [`crate::metric_scope`].
+#[allow(clippy::missing_panics_doc)]
+pub async fn metric_scope<R>(f: impl AsyncFnOnce(&mut MetricsLogger) -> R) -> R {
+    let metrics = MetricsConfig::from_env()
+        .and_then(|config| Metrics::builder(DEFAULT_NAMESPACE).config(&config).build())
+        .unwrap_or_else(|err| {
+            mode::report(&err);
+            // UNWRAP: without dimensions there is no risk of reaching max number of dimensions
+            Metrics::builder(DEFAULT_NAMESPACE).build().unwrap()
+        });
+    metric_scope_with(metrics, f).await
+}
+
+/// Like [`metric_scope`], with metrics built by the caller, e.g. with another sink.
+pub async fn metric_scope_with<R>(
+    metrics: Metrics,
+    f: impl AsyncFnOnce(&mut MetricsLogger) -> R,
+) -> R {
+    let mut logger = MetricsLogger::new(metrics);
+    let result = f(&mut logger).await;
+    logger.flush().await;
+    result
+}
+
+/// Records metrics with the method names of the Node library, see [`crate::metric_scope`].
+#[derive(Debug)]
+pub struct MetricsLogger {
+    metrics: Metrics,
+    /// Set by [`MetricsLogger::set_timestamp`].
+    timestamp: Option<DateTime<Utc>>,
+}
+
+impl MetricsLogger {
+    /// Wraps the metrics into a logger.
+    #[must_use]
+    pub fn new(metrics: Metrics) -> Self {
+        Self {
+            metrics,
+            timestamp: None,
+        }
+    }
+
+    /// Records a value of a metric. Values of a metric recorded before the flush are emitted
+    /// together as an array.
+    pub fn put_metric(&mut self, key: &str, value: f64, unit: MetricUnit) -> &mut Self {
+        self.put(key, value, unit, None)
+    }
+
+    /// Records a value of a metric with the given storage resolution.
+    pub fn put_metric_with_resolution(
+        &mut self,
+        key: &str,
+        value: f64,
+        unit: MetricUnit,
+        resolution: MetricResolution,
+    ) -> &mut Self {
+        self.put(key, value, unit, Some(resolution.seconds()))
+    }
+
+    fn put(
+        &mut self,
+        key: &str,
+        value: f64,
+        unit: MetricUnit,
+        storage_resolution: Option<u64>,
+    ) -> &mut Self {
+        let metric = Metric {
+            storage_resolution,
+            timestamp: self.timestamp.map(|timestamp| timestamp.timestamp_millis()),
+            ..self.metrics.new_metric(key, unit, value)
+        };
+        self.metrics.append_value(metric);
+        self
+    }
+
+    /// Adds dimensions. Dimensions which can't be added are reported like other errors
+    /// which are not returned.
+    pub fn put_dimensions<K: AsRef<str>, V: AsRef<str>>(
+        &mut self,
+        dimensions: impl IntoIterator<Item = (K, V)>,
+    ) -> &mut Self {
+        for (key, value) in dimensions {
+            if let Err(err) = self.metrics.try_add_dimension(key.as_ref(), value.as_ref()) {
+                mode::report(&err);
+            }
+        }
+        self
+    }
+
+    /// Replaces all dimensions, including the ones the metrics were built with.
+    pub fn set_dimensions<K: AsRef<str>, V: AsRef<str>>(
+        &mut self,
+        dimensions: impl IntoIterator<Item = (K, V)>,
+    ) -> &mut Self {
+        *std::sync::Arc::make_mut(&mut self.metrics.dimensions) = Dimensions::default();
+        self.put_dimensions(dimensions)
+    }
+
+    /// Adds a property: a value searchable in `CloudWatch Logs Insights` which is not
+    /// a dimension.
+    pub fn set_property(&mut self, key: &str, value: impl Into<serde_json::Value>) -> &mut Self {
+        self.metrics.add_json_property(key, value);
+        self
+    }
+
+    /// Sets the namespace of the payloads.
+    pub fn set_namespace(&mut self, namespace: &str) -> &mut Self {
+        self.metrics.namespace = Namespace(namespace.to_string());
+        self
+    }
+
+    /// Sets the timestamp of the metrics recorded afterwards, see [`Metrics::add_metric_at`].
+    pub fn set_timestamp(&mut self, timestamp: DateTime<Utc>) -> &mut Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    /// Returns the underlying metrics, e.g. to use an API without a Node equivalent.
+    pub fn metrics(&mut self) -> &mut Metrics {
+        &mut self.metrics
+    }
+
+    /// Flushes the buffered metrics, see [`Metrics::flush_async`].
+    pub async fn flush(&mut self) {
+        self.metrics.flush_async().await;
+    }
+
+    /// Flushes the buffered metrics, returning the first error.
+    ///
+    /// # Errors
+    ///
+    /// Will return the first error if any payload couldn't be serialized or written
+    pub fn try_flush(&mut self) -> Result<(), MetricsError> {
+        self.metrics.try_flush_metrics()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::sink::RecordingSink;
+
+    fn run<R>(future: impl Future<Output = R>) -> R {
+        let mut future = std::pin::pin!(future);
+        let mut context = std::task::Context::from_waker(std::task::Waker::noop());
+        match future.as_mut().poll(&mut context) {
+            std::task::Poll::Ready(result) => result,
+            std::task::Poll::Pending => panic!("the scope is expected to complete immediately"),
+        }
+    }
+
+    #[test]
+    fn should_flush_once_the_scope_returns() {
+        let sink = RecordingSink::default();
+        let metrics = Metrics::builder(DEFAULT_NAMESPACE)
+            .sink(sink.clone())
+            .dimension("stage", "prod")
+            .build()
+            .unwrap();
+        let timestamp = Utc.timestamp_millis_opt(1_700_000_000_000).unwrap();
+
+        let result = run(metric_scope_with(metrics, async |metrics| {
+            metrics
+                .set_namespace("Aggregator")
+                .set_dimensions([("Service", "Aggregator")])
+                .set_timestamp(timestamp)
+                .put_metric("ProcessingLatency", 100.0, MetricUnit::Milliseconds)
+                .put_metric("ProcessingLatency", 120.0, MetricUnit::Milliseconds)
+                .put_metric_with_resolution(
+                    "Memory",
+                    512.0,
+                    MetricUnit::Megabytes,
+                    MetricResolution::High,
+                )
+                .set_property("RequestId", "422b1569");
+            assert!(sink.payloads().is_empty());
+            42
+        }));
+
+        let payload = &sink.payloads()[0];
+        let directive = &payload["_aws"]["CloudWatchMetrics"][0];
+        assert_eq!(result, 42);
+        assert_eq!(directive["Namespace"], "Aggregator");
+        assert_eq!(directive["Dimensions"][0], serde_json::json!(["Service"]));
+        assert_eq!(directive["Metrics"][1]["StorageResolution"], 1);
+        assert_eq!(payload["_aws"]["Timestamp"], 1_700_000_000_000_i64);
+        assert_eq!(
+            payload["ProcessingLatency"],
+            serde_json::json!([100.0, 120.0])
+        );
+        assert_eq!(payload["RequestId"], "422b1569");
+    }
+}
@@ -0,0 +1,82 @@
+//! Crate-wide operating mode.
+//!
+//! In the default [`Mode::Lenient`] the library never fails the handler: errors are printed
+//! to stderr and counted (see [`crate::self_metrics`]). [`Mode::Strict`] is meant for staging
+//! and tests, to catch bad instrumentation early:
+//!
+//! - `try_add_metric` also rejects metrics with an empty name or a non-finite value,
+//!   which lenient mode emits as they are (non-finite values are written as `null`);
+//! - errors which would only be printed, e.g. in `add_metric` or `flush_metrics`, panic
+//!   in debug builds. Release builds still print them.
+//!
+//! [`crate::Metrics::try_flush_metrics`] returns the errors of a flush in both modes.
+//!
+//! ```
+//! use lambda_helpers_metrics::mode::{self, Mode};
+//!
+//! if std::env::var("STAGE").as_deref() == Ok("staging") {
+//!     mode::set_mode(Mode::Strict);
+//! }
+//! ```
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{Metric, MetricsError};
+
+static STRICT: AtomicBool = AtomicBool::new(false);
+
+/// How the library handles invalid metrics and emission failures.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Mode {
+    /// Errors are printed to stderr and the handler continues.
+    #[default]
+    Lenient,
+    /// Invalid metrics are rejected and errors panic in debug builds.
+    Strict,
+}
+
+/// Sets the mode for all `Metrics` objects of the process.
+pub fn set_mode(mode: Mode) {
+    STRICT.store(mode == Mode::Strict, Ordering::Relaxed);
+}
+
+/// Returns the current mode.
+#[must_use]
+pub fn mode() -> Mode {
+    if STRICT.load(Ordering::Relaxed) {
+        Mode::Strict
+    } else {
+        Mode::Lenient
+    }
+}
+
+/// Checks the metric in strict mode.
+pub(crate) fn validate(metric: &Metric) -> Result<(), MetricsError> {
+    if mode() == Mode::Lenient {
+        return Ok(());
+    }
+    if metric.name.is_empty() {
+        return Err(MetricsError::InvalidMetric(
+            "metric name is empty".to_string(),
+        ));
+    }
+    if let Some(value) = metric.values.iter().find(|value| !value.is_finite()) {
+        return Err(MetricsError::InvalidMetric(format!(
+            "value of {} is not finite: {value}",
+            metric.name
+        )));
+    }
+    Ok(())
+}
+
+/// Called for errors which are not returned to the caller.
+pub(crate) fn report(err: &MetricsError) {
+    eprintln!("{err}");
+    fail_in_strict_mode(err);
+}
+
+/// Panics in debug builds in strict mode, for errors which were already printed.
+pub(crate) fn fail_in_strict_mode(err: &MetricsError) {
+    if cfg!(debug_assertions) && mode() == Mode::Strict && !std::thread::panicking() {
+        panic!("{err}");
+    }
+}
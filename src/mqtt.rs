@@ -0,0 +1,527 @@
+//! Sink publishing payloads to an MQTT topic, e.g. of AWS IoT Core.
+//!
+//! Available with the `mqtt` feature. Each payload is published as a message on the topic,
+//! over TLS with a client certificate, so device-side and cloud-side metrics can flow through
+//! the same pipeline, e.g. an IoT rule writing the payloads to `CloudWatch Logs`.
+//!
+//! ```no_run
+//! use lambda_helpers_metrics::mqtt::{MqttIdentity, MqttSink};
+//! use lambda_helpers_metrics::Metrics;
+//!
+//! # fn example() -> Result<(), lambda_helpers_metrics::MetricsError> {
+//! let cert = std::fs::read("/greengrass/v2/thingCert.crt")?;
+//! let key = std::fs::read("/greengrass/v2/privKey.key")?;
+//! let sink = MqttSink::new(
+//!     "a1b2c3d4e5f6g7-ats.iot.eu-west-1.amazonaws.com",
+//!     "metrics/orders",
+//!     MqttIdentity::from_pem("orders-device", &cert, &key)?,
+//! )?;
+//! let metrics = Metrics::builder("custom_lambdas").sink(sink).build()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The sink speaks the subset of MQTT 3.1.1 needed to publish: a clean session, and messages
+//! with QoS 1 by default, so a publish only succeeds once the broker acknowledged it.
+//! The connection is opened lazily on port 8883, or with the `x-amzn-mqtt-ca` ALPN protocol
+//! on port 443. No pings are sent between flushes: a connection closed by the broker while
+//! idle, e.g. while the Lambda environment was frozen, is re-established once before the
+//! publish fails. IoT Core rejects messages over 128 KB.
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+use crate::sink::MetricsSink;
+use crate::MetricsError;
+
+/// Port of MQTT over TLS.
+pub const DEFAULT_PORT: u16 = 8883;
+/// ALPN protocol of MQTT with client certificates on port 443 of AWS IoT Core.
+pub const IOT_CORE_ALPN: &[u8] = b"x-amzn-mqtt-ca";
+/// Keep alive announced to the broker, the maximum of AWS IoT Core.
+pub const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(1200);
+/// Timeout of connecting, reading and writing.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const PUBACK: u8 = 0x40;
+const DISCONNECT: u8 = 0xE0;
+
+/// Quality of service of the published messages.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MqttQos {
+    /// Published without acknowledgement, lost if the connection drops.
+    AtMostOnce,
+    /// Acknowledged by the broker before the publish succeeds.
+    #[default]
+    AtLeastOnce,
+}
+
+/// Client ID and client certificate of an [`MqttSink`]. With AWS IoT Core, the client ID is
+/// usually the name of the thing the certificate is attached to.
+pub struct MqttIdentity {
+    client_id: String,
+    certs: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+}
+
+impl fmt::Debug for MqttIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MqttIdentity")
+            .field("client_id", &self.client_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl MqttIdentity {
+    /// Reads the certificate chain and the private key from PEM files' contents.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the certificate or the key can't be parsed
+    pub fn from_pem(
+        client_id: &str,
+        cert_pem: &[u8],
+        key_pem: &[u8],
+    ) -> Result<Self, MetricsError> {
+        let certs = CertificateDer::pem_slice_iter(cert_pem)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| invalid(format!("invalid client certificate: {err}")))?;
+        if certs.is_empty() {
+            return Err(invalid("no client certificate"));
+        }
+        let key = PrivateKeyDer::from_pem_slice(key_pem)
+            .map_err(|err| invalid(format!("invalid private key: {err}")))?;
+        Ok(Self {
+            client_id: client_id.to_string(),
+            certs,
+            key,
+        })
+    }
+}
+
+/// An open session with the broker.
+struct Session {
+    stream: StreamOwned<ClientConnection, TcpStream>,
+    next_packet_id: u16,
+}
+
+/// Publishes payloads to an MQTT topic, see [`crate::mqtt`].
+pub struct MqttSink {
+    host: String,
+    port: u16,
+    topic: String,
+    client_id: String,
+    qos: MqttQos,
+    timeout: Duration,
+    tls: Arc<ClientConfig>,
+    session: Mutex<Option<Session>>,
+}
+
+impl fmt::Debug for MqttSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MqttSink")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("topic", &self.topic)
+            .field("client_id", &self.client_id)
+            .field("qos", &self.qos)
+            .finish_non_exhaustive()
+    }
+}
+
+impl MqttSink {
+    /// Creates a sink for an endpoint like `a1b2c3d4e5f6g7-ats.iot.eu-west-1.amazonaws.com`,
+    /// on [`DEFAULT_PORT`] unless a port is given with `host:port`. The server certificate is
+    /// verified against the root certificates of the system.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the endpoint or the topic is invalid, or if the root certificates
+    /// of the system can't be loaded
+    pub fn new(endpoint: &str, topic: &str, identity: MqttIdentity) -> Result<Self, MetricsError> {
+        let mut roots = RootCertStore::empty();
+        roots.add_parsable_certificates(rustls_native_certs::load_native_certs().certs);
+        if roots.is_empty() {
+            return Err(invalid("no root certificates found"));
+        }
+        Self::with_roots(endpoint, topic, identity, roots)
+    }
+
+    /// Creates a sink verifying the server certificate against the given PEM root
+    /// certificates, e.g. `AmazonRootCA1.pem`, see [`MqttSink::new`].
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the endpoint, the topic or the root certificates are invalid
+    pub fn with_root_certificates(
+        endpoint: &str,
+        topic: &str,
+        identity: MqttIdentity,
+        roots_pem: &[u8],
+    ) -> Result<Self, MetricsError> {
+        let mut roots = RootCertStore::empty();
+        for cert in CertificateDer::pem_slice_iter(roots_pem) {
+            let cert = cert.map_err(|err| invalid(format!("invalid root certificate: {err}")))?;
+            roots
+                .add(cert)
+                .map_err(|err| invalid(format!("invalid root certificate: {err}")))?;
+        }
+        if roots.is_empty() {
+            return Err(invalid("no root certificates found"));
+        }
+        Self::with_roots(endpoint, topic, identity, roots)
+    }
+
+    fn with_roots(
+        endpoint: &str,
+        topic: &str,
+        identity: MqttIdentity,
+        roots: RootCertStore,
+    ) -> Result<Self, MetricsError> {
+        let (host, port) = match endpoint.rsplit_once(':') {
+            Some((host, port)) => (
+                host,
+                port.parse()
+                    .map_err(|_| invalid(format!("invalid MQTT endpoint: {endpoint}")))?,
+            ),
+            None => (endpoint, DEFAULT_PORT),
+        };
+        if host.is_empty() {
+            return Err(invalid(format!("invalid MQTT endpoint: {endpoint}")));
+        }
+        if topic.is_empty() || topic.contains(['#', '+']) || topic.len() > usize::from(u16::MAX) {
+            return Err(invalid(format!("invalid MQTT topic: {topic}")));
+        }
+        let mut tls = ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_client_auth_cert(identity.certs, identity.key)
+            .map_err(|err| invalid(format!("invalid client certificate: {err}")))?;
+        if port == 443 {
+            tls.alpn_protocols = vec![IOT_CORE_ALPN.to_vec()];
+        }
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            topic: topic.to_string(),
+            client_id: identity.client_id,
+            qos: MqttQos::default(),
+            timeout: DEFAULT_TIMEOUT,
+            tls: Arc::new(tls),
+            session: Mutex::new(None),
+        })
+    }
+
+    /// Sets the quality of service of the messages, [`MqttQos::AtLeastOnce`] by default.
+    #[must_use]
+    pub fn qos(mut self, qos: MqttQos) -> Self {
+        self.qos = qos;
+        self
+    }
+
+    /// Sets the timeout of connecting, reading and writing, [`DEFAULT_TIMEOUT`] by default.
+    #[must_use]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn connect(&self) -> Result<Session, MetricsError> {
+        let address = (self.host.as_str(), self.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| invalid(format!("unknown MQTT host: {}", self.host)))?;
+        let tcp = TcpStream::connect_timeout(&address, self.timeout)?;
+        tcp.set_read_timeout(Some(self.timeout))?;
+        tcp.set_write_timeout(Some(self.timeout))?;
+        let server_name = ServerName::try_from(self.host.clone())
+            .map_err(|err| invalid(format!("invalid MQTT host: {err}")))?;
+        let connection = ClientConnection::new(self.tls.clone(), server_name)
+            .map_err(|err| MetricsError::Io(io::Error::other(err)))?;
+        let mut stream = StreamOwned::new(connection, tcp);
+        handshake(&mut stream, &self.client_id, DEFAULT_KEEP_ALIVE)?;
+        Ok(Session {
+            stream,
+            next_packet_id: 1,
+        })
+    }
+
+    fn publish_on(&self, session: &mut Session, payload: &str) -> Result<(), MetricsError> {
+        let packet_id = session.next_packet_id;
+        // packet identifiers are non-zero
+        session.next_packet_id = session.next_packet_id.checked_add(1).unwrap_or(1);
+        publish(
+            &mut session.stream,
+            &self.topic,
+            payload.as_bytes(),
+            self.qos,
+            packet_id,
+        )
+    }
+}
+
+impl MetricsSink for MqttSink {
+    fn emit(&self, payload: &str) -> Result<(), MetricsError> {
+        let mut session = self
+            .session
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        if let Some(open) = session.as_mut() {
+            if self.publish_on(open, payload).is_ok() {
+                return Ok(());
+            }
+            // likely closed by the broker while idle, retried once on a new connection
+            *session = None;
+        }
+        let mut fresh = self.connect()?;
+        self.publish_on(&mut fresh, payload)?;
+        *session = Some(fresh);
+        Ok(())
+    }
+}
+
+impl Drop for MqttSink {
+    fn drop(&mut self) {
+        let session = self
+            .session
+            .get_mut()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        if let Some(mut session) = session.take() {
+            let _ = session.stream.write_all(&[DISCONNECT, 0]);
+            session.stream.conn.send_close_notify();
+            let _ = session.stream.flush();
+        }
+    }
+}
+
+fn invalid(err: impl fmt::Display) -> MetricsError {
+    MetricsError::Configuration(err.to_string())
+}
+
+/// Appends the variable-length encoding of the remaining length of a packet.
+fn write_remaining_length(out: &mut Vec<u8>, mut length: usize) {
+    loop {
+        // the low 7 bits of the length
+        #[allow(clippy::cast_possible_truncation)]
+        let mut byte = (length % 128) as u8;
+        length /= 128;
+        if length > 0 {
+            byte |= 0x80;
+        }
+        out.push(byte);
+        if length == 0 {
+            break;
+        }
+    }
+}
+
+/// Appends a string prefixed by its length, which is checked to fit into 16 bits.
+fn write_string(out: &mut Vec<u8>, value: &[u8]) {
+    #[allow(clippy::cast_possible_truncation)]
+    out.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    out.extend_from_slice(value);
+}
+
+fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(body.len() + 5);
+    out.push(header);
+    write_remaining_length(&mut out, body.len());
+    out.extend_from_slice(body);
+    out
+}
+
+fn connect_packet(client_id: &str, keep_alive: Duration) -> Vec<u8> {
+    let mut body = Vec::new();
+    write_string(&mut body, b"MQTT");
+    // protocol level 4 (3.1.1), clean session
+    body.extend_from_slice(&[4, 0x02]);
+    let keep_alive = u16::try_from(keep_alive.as_secs()).unwrap_or(u16::MAX);
+    body.extend_from_slice(&keep_alive.to_be_bytes());
+    write_string(&mut body, client_id.as_bytes());
+    packet(CONNECT, &body)
+}
+
+fn publish_packet(topic: &str, payload: &[u8], qos: MqttQos, packet_id: u16) -> Vec<u8> {
+    let mut body = Vec::with_capacity(topic.len() + payload.len() + 4);
+    write_string(&mut body, topic.as_bytes());
+    let header = match qos {
+        MqttQos::AtMostOnce => PUBLISH,
+        MqttQos::AtLeastOnce => {
+            body.extend_from_slice(&packet_id.to_be_bytes());
+            PUBLISH | 0x02
+        }
+    };
+    body.extend_from_slice(payload);
+    packet(header, &body)
+}
+
+/// Reads a packet, returning its type and body.
+fn read_packet(stream: &mut impl Read) -> io::Result<(u8, Vec<u8>)> {
+    let mut header = [0; 1];
+    stream.read_exact(&mut header)?;
+    let mut length = 0;
+    for shift in [0, 7, 14, 21] {
+        let mut byte = [0; 1];
+        stream.read_exact(&mut byte)?;
+        length |= usize::from(byte[0] & 0x7F) << shift;
+        if byte[0] & 0x80 == 0 {
+            let mut body = vec![0; length];
+            stream.read_exact(&mut body)?;
+            return Ok((header[0] & 0xF0, body));
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "malformed MQTT remaining length",
+    ))
+}
+
+fn handshake(
+    stream: &mut (impl Read + Write),
+    client_id: &str,
+    keep_alive: Duration,
+) -> Result<(), MetricsError> {
+    stream.write_all(&connect_packet(client_id, keep_alive))?;
+    stream.flush()?;
+    match read_packet(stream)? {
+        (CONNACK, body) if body.get(1) == Some(&0) => Ok(()),
+        (CONNACK, body) => Err(MetricsError::Io(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!(
+                "MQTT connection refused with code {}",
+                body.get(1).copied().unwrap_or_default()
+            ),
+        ))),
+        (kind, _) => Err(unexpected(kind)),
+    }
+}
+
+fn publish(
+    stream: &mut (impl Read + Write),
+    topic: &str,
+    payload: &[u8],
+    qos: MqttQos,
+    packet_id: u16,
+) -> Result<(), MetricsError> {
+    stream.write_all(&publish_packet(topic, payload, qos, packet_id))?;
+    stream.flush()?;
+    if qos == MqttQos::AtMostOnce {
+        return Ok(());
+    }
+    loop {
+        match read_packet(stream)? {
+            (PUBACK, body) if body == packet_id.to_be_bytes() => return Ok(()),
+            // acknowledgement of an earlier packet which timed out
+            (PUBACK, _) => {}
+            (kind, _) => return Err(unexpected(kind)),
+        }
+    }
+}
+
+fn unexpected(kind: u8) -> MetricsError {
+    MetricsError::Io(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("unexpected MQTT packet type {}", kind >> 4),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A stream replaying the packets of a broker and recording the packets of the client.
+    struct Broker {
+        replies: io::Cursor<Vec<u8>>,
+        received: Vec<u8>,
+    }
+
+    impl Broker {
+        fn new(replies: &[&[u8]]) -> Self {
+            Self {
+                replies: io::Cursor::new(replies.concat()),
+                received: Vec::new(),
+            }
+        }
+    }
+
+    impl Read for Broker {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.replies.read(buf)
+        }
+    }
+
+    impl Write for Broker {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.received.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn should_encode_remaining_length() {
+        for (length, expected) in [
+            (0, vec![0x00]),
+            (127, vec![0x7F]),
+            (128, vec![0x80, 0x01]),
+            (16_383, vec![0xFF, 0x7F]),
+            (2_097_152, vec![0x80, 0x80, 0x80, 0x01]),
+        ] {
+            let mut out = Vec::new();
+            write_remaining_length(&mut out, length);
+            assert_eq!(out, expected);
+        }
+    }
+
+    #[test]
+    fn should_connect_and_publish_with_acknowledgement() {
+        let mut broker = Broker::new(&[&[CONNACK, 2, 0, 0], &[PUBACK, 2, 0, 7]]);
+
+        handshake(&mut broker, "device", Duration::from_secs(60)).unwrap();
+        let connect_len = broker.received.len();
+        publish(&mut broker, "m/o", b"{}", MqttQos::AtLeastOnce, 7).unwrap();
+
+        assert_eq!(
+            broker.received[..connect_len],
+            [
+                &[CONNECT, 18, 0, 4][..],
+                b"MQTT",
+                &[4, 0x02, 0, 60, 0, 6],
+                b"device"
+            ]
+            .concat()
+        );
+        assert_eq!(
+            broker.received[connect_len..],
+            [&[PUBLISH | 0x02, 9, 0, 3][..], b"m/o", &[0, 7], b"{}"].concat()
+        );
+    }
+
+    #[test]
+    fn should_fail_on_refused_connection_and_unexpected_packets() {
+        let mut refused = Broker::new(&[&[CONNACK, 2, 0, 5]]);
+        let mut unexpected = Broker::new(&[&[PUBLISH, 0]]);
+
+        let refused = handshake(&mut refused, "device", DEFAULT_KEEP_ALIVE).unwrap_err();
+        let unexpected = publish(&mut unexpected, "m", b"{}", MqttQos::AtLeastOnce, 1);
+
+        assert!(refused.to_string().contains("code 5"));
+        assert!(unexpected.is_err());
+    }
+
+    #[test]
+    fn should_reject_invalid_identity() {
+        let err = MqttIdentity::from_pem("device", b"", b"").unwrap_err();
+        assert!(matches!(err, MetricsError::Configuration(_)));
+    }
+}
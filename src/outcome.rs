@@ -0,0 +1,400 @@
+//! Recording the outcome of fallible operations.
+//!
+//! ```
+//! use lambda_helpers_metrics::{MetricizedResult, Metrics};
+//!
+//! # fn put_item() -> Result<(), std::io::Error> { Ok(()) }
+//! # fn handler() -> Result<(), std::io::Error> {
+//! let mut metrics = Metrics::new("custom_lambdas", "service", "dummy_service");
+//! put_item().record_outcome(&mut metrics, "dynamo_put")?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Errors only available as trait objects, e.g. `Box<dyn Error>`, can be recorded with
+//! [`Metrics::record_error`]:
+//!
+//! ```
+//! use lambda_helpers_metrics::Metrics;
+//!
+//! # fn put_item() -> Result<(), Box<dyn std::error::Error>> { Ok(()) }
+//! let mut metrics = Metrics::new("custom_lambdas", "service", "dummy_service");
+//! if let Err(err) = put_item() {
+//!     metrics.record_error(err.as_ref(), "dynamo_put");
+//! }
+//! ```
+//!
+//! Early returns with `?` skip the bookkeeping written after them. [`Metrics::guard`] records
+//! the outcome when the scope exits instead, a failure unless [`OutcomeGuard::succeed`] was
+//! called:
+//!
+//! ```
+//! use lambda_helpers_metrics::Metrics;
+//!
+//! # fn charge() -> Result<(), std::io::Error> { Ok(()) }
+//! # fn handler() -> Result<(), std::io::Error> {
+//! let mut metrics = Metrics::new("custom_lambdas", "service", "dummy_service");
+//! let mut checkout = metrics.guard("checkout");
+//! charge()?;
+//! checkout.succeed();
+//! # Ok(())
+//! # }
+//! ```
+use std::borrow::Cow;
+use std::ops::{Deref, DerefMut};
+use std::time::Instant;
+
+use crate::{Dimensions, MetricUnit, Metrics};
+
+/// Name of the dimension holding the error type on `<operation>_error` metrics.
+pub const ERROR_TYPE_DIMENSION: &str = "error_type";
+/// Prefix of the properties holding the message of the last error of each type recorded with
+/// [`Metrics::record_error`], e.g. `error_message_Timeout`.
+pub const ERROR_MESSAGE_PROPERTY: &str = "error_message";
+/// Maximum number of distinct error types recorded by a `Metrics` object with
+/// [`Metrics::record_error`], the following ones are recorded as [`OTHER_ERROR_TYPE`].
+pub const MAX_ERROR_TYPES: usize = 10;
+/// Error type of the errors beyond [`MAX_ERROR_TYPES`].
+pub const OTHER_ERROR_TYPE: &str = "other";
+
+/// Extension trait recording the outcome of a `Result` as metrics.
+pub trait MetricizedResult {
+    /// Increments `<operation>_success` on `Ok`, or `<operation>_error` on `Err`.
+    /// The error metric carries an `error_type` dimension with the name of the error type,
+    /// qualified by its module if it is named `Error`, e.g. `io::Error`.
+    /// The result is returned unchanged, so the call can be chained with `?`.
+    #[must_use]
+    fn record_outcome(self, metrics: &mut Metrics, operation: &str) -> Self;
+}
+
+impl<T, E> MetricizedResult for Result<T, E> {
+    fn record_outcome(self, metrics: &mut Metrics, operation: &str) -> Self {
+        record_result(metrics, &self, operation);
+        self
+    }
+}
+
+/// Records the outcome of the result, see [`MetricizedResult::record_outcome`].
+pub(crate) fn record_result<T, E>(metrics: &mut Metrics, result: &Result<T, E>, operation: &str) {
+    match result {
+        Ok(_) => metrics.increment(&format!("{operation}_success"), 1.0),
+        Err(_) => {
+            let mut dimensions = Dimensions::default();
+            dimensions.insert(ERROR_TYPE_DIMENSION, &short_type_name::<E>());
+            metrics.increment_with_dimensions(&format!("{operation}_error"), 1.0, dimensions);
+        }
+    }
+}
+
+/// Guard returned by [`Metrics::guard`], recording the outcome of the operation when dropped.
+/// Metrics are recorded through the guard, which dereferences to [`Metrics`].
+#[derive(Debug)]
+pub struct OutcomeGuard<'a> {
+    metrics: &'a mut Metrics,
+    operation: String,
+    start: Instant,
+    succeeded: bool,
+}
+
+impl OutcomeGuard<'_> {
+    /// Marks the operation as successful, otherwise it is recorded as a failure.
+    pub fn succeed(&mut self) {
+        self.succeeded = true;
+    }
+}
+
+impl Deref for OutcomeGuard<'_> {
+    type Target = Metrics;
+
+    fn deref(&self) -> &Metrics {
+        self.metrics
+    }
+}
+
+impl DerefMut for OutcomeGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Metrics {
+        self.metrics
+    }
+}
+
+impl Drop for OutcomeGuard<'_> {
+    fn drop(&mut self) {
+        let elapsed = self
+            .metrics
+            .clock
+            .instant()
+            .saturating_duration_since(self.start);
+        self.metrics.add_sample(
+            &format!("{}_duration", self.operation),
+            MetricUnit::Milliseconds,
+            elapsed.as_secs_f64() * 1000.0,
+        );
+        let outcome = if self.succeeded { "success" } else { "error" };
+        self.metrics
+            .increment(&format!("{}_{outcome}", self.operation), 1.0);
+    }
+}
+
+impl Metrics {
+    /// Starts an operation whose outcome is recorded when the returned guard is dropped:
+    /// `<operation>_duration` in milliseconds, and `<operation>_success` if
+    /// [`OutcomeGuard::succeed`] was called, `<operation>_error` otherwise, e.g. after an early
+    /// return. The error count has no `error_type` dimension, as the error is unknown.
+    pub fn guard(&mut self, operation: &str) -> OutcomeGuard<'_> {
+        OutcomeGuard {
+            start: self.clock.instant(),
+            metrics: self,
+            operation: operation.to_string(),
+            succeeded: false,
+        }
+    }
+
+    /// Increments `<operation>_error` with an `error_type` dimension, and sets the message
+    /// of the error as the `error_message_<error_type>` property, so errors of different types
+    /// recorded before the same flush keep their own message.
+    ///
+    /// The type of a trait object is unknown, so the error type is:
+    /// - the kind of an `io::Error`, e.g. `TimedOut`;
+    /// - the name of the type or enum variant written by a derived `Debug`, e.g.
+    ///   `ThrottlingError` or `Timeout` for `Timeout` and `Constraint("pk")`;
+    /// - `other` for anything else, e.g. errors whose `Debug` is their message.
+    ///
+    /// Once [`MAX_ERROR_TYPES`] distinct types were recorded, other types are recorded as
+    /// `other`, so unexpected errors can't create unbounded series.
+    pub fn record_error(&mut self, err: &(dyn std::error::Error + 'static), operation: &str) {
+        let name = error_type_name(err);
+        let error_type = if self.error_types.contains(&name) {
+            name
+        } else if self.error_types.len() < MAX_ERROR_TYPES {
+            self.error_types.push(name.clone());
+            name
+        } else {
+            OTHER_ERROR_TYPE.to_string()
+        };
+        self.add_json_property(
+            &format!("{ERROR_MESSAGE_PROPERTY}_{error_type}"),
+            err.to_string(),
+        );
+        let mut dimensions = Dimensions::default();
+        dimensions.insert(ERROR_TYPE_DIMENSION, &error_type);
+        self.increment_with_dimensions(&format!("{operation}_error"), 1.0, dimensions);
+    }
+}
+
+/// Returns the error type of a trait object, see [`Metrics::record_error`].
+fn error_type_name(err: &(dyn std::error::Error + 'static)) -> String {
+    if let Some(err) = err.downcast_ref::<std::io::Error>() {
+        return format!("{:?}", err.kind());
+    }
+    debug_type_name(&format!("{err:?}")).to_string()
+}
+
+/// Returns the type or variant name starting a derived `Debug` representation, e.g.
+/// `Constraint` for `Constraint("pk")`, or `other` if it isn't one, e.g. for a message.
+fn debug_type_name(debug: &str) -> &str {
+    let end = debug
+        .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .unwrap_or(debug.len());
+    let (name, rest) = debug.split_at(end);
+    let is_type = name.starts_with(|c: char| c.is_ascii_uppercase())
+        && (rest.is_empty() || rest.starts_with('(') || rest.starts_with(" {"));
+    if is_type {
+        name
+    } else {
+        OTHER_ERROR_TYPE
+    }
+}
+
+/// Returns the type name without module path and generics, e.g. `SdkError` for
+/// `aws_smithy_runtime_api::client::result::SdkError<E, R>`. A type named `Error` keeps the
+/// module it belongs to, skipping `error` modules, e.g. `io::Error` for
+/// `std::io::error::Error` or `serde_json::Error`.
+pub(crate) fn short_type_name<T: ?Sized>() -> Cow<'static, str> {
+    let name = std::any::type_name::<T>();
+    let without_generics = name.split('<').next().unwrap_or(name);
+    let mut segments = without_generics.rsplit("::");
+    let last = segments.next().unwrap_or(without_generics);
+    if last != "Error" {
+        return Cow::Borrowed(last);
+    }
+    match segments.find(|segment| !matches!(*segment, "error" | "errors")) {
+        Some(module) => Cow::Owned(format!("{module}::{last}")),
+        None => Cow::Borrowed(last),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::RecordingSink;
+
+    #[derive(Debug)]
+    struct ThrottlingError;
+
+    #[test]
+    fn should_record_success_and_error_counts() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .dimension("service", "dummy_service")
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+
+        let ok: Result<u8, ThrottlingError> = Ok(1);
+        assert!(ok.record_outcome(&mut metrics, "dynamo_put").is_ok());
+        let ok: Result<u8, ThrottlingError> = Ok(2);
+        let _ = ok.record_outcome(&mut metrics, "dynamo_put");
+        let err: Result<u8, ThrottlingError> = Err(ThrottlingError);
+        assert!(err.record_outcome(&mut metrics, "dynamo_put").is_err());
+
+        assert_eq!(metrics.value_of("dynamo_put_success"), Some(2.0));
+        metrics.flush_metrics();
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads.len(), 2);
+        assert_eq!(payloads[0]["dynamo_put_success"], 2.0);
+        assert!(payloads[0].get(ERROR_TYPE_DIMENSION).is_none());
+        assert_eq!(payloads[1]["dynamo_put_error"], 1.0);
+        assert_eq!(payloads[1][ERROR_TYPE_DIMENSION], "ThrottlingError");
+        assert_eq!(payloads[1]["service"], "dummy_service");
+        assert_eq!(
+            payloads[1]["_aws"]["CloudWatchMetrics"][0]["Dimensions"][0],
+            serde_json::json!(["service", ERROR_TYPE_DIMENSION])
+        );
+    }
+
+    #[derive(Debug)]
+    enum DatabaseError {
+        Timeout,
+        Constraint(String),
+    }
+
+    impl std::fmt::Display for DatabaseError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                DatabaseError::Timeout => f.write_str("query timed out"),
+                DatabaseError::Constraint(name) => write!(f, "constraint {name} violated"),
+            }
+        }
+    }
+
+    impl std::error::Error for DatabaseError {}
+
+    /// An error whose `Debug` names one of many types, `Kind<n>`.
+    struct Numbered(usize);
+
+    impl std::fmt::Debug for Numbered {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "Kind{}", self.0)
+        }
+    }
+
+    impl std::fmt::Display for Numbered {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "error {}", self.0)
+        }
+    }
+
+    impl std::error::Error for Numbered {}
+
+    #[test]
+    fn should_record_error_trait_objects_by_type() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test").sink(sink.clone()).build().unwrap();
+        let errors: Vec<Box<dyn std::error::Error>> = vec![
+            Box::new(DatabaseError::Timeout),
+            Box::new(DatabaseError::Constraint("pk".to_string())),
+            Box::new(std::io::Error::from(std::io::ErrorKind::TimedOut)),
+            Box::new(std::io::Error::other("Connection refused")),
+        ];
+
+        for err in &errors {
+            metrics.record_error(err.as_ref(), "query");
+        }
+        metrics.flush_metrics();
+
+        let payloads = sink.payloads();
+        let by_type = |error_type: &str| {
+            payloads
+                .iter()
+                .find(|payload| payload[ERROR_TYPE_DIMENSION] == error_type)
+                .unwrap()
+        };
+        assert_eq!(
+            by_type("Timeout")["error_message_Timeout"],
+            "query timed out"
+        );
+        assert_eq!(
+            by_type("Constraint")["error_message_Constraint"],
+            "constraint pk violated"
+        );
+        assert_eq!(by_type("TimedOut")["query_error"], 1.0);
+        assert_eq!(
+            by_type("Other")["error_message_Other"],
+            "Connection refused"
+        );
+        assert_eq!(debug_type_name("\"oops\""), OTHER_ERROR_TYPE);
+        assert_eq!(debug_type_name("Connection refused"), OTHER_ERROR_TYPE);
+        assert_eq!(debug_type_name("Os { code: 2 }"), "Os");
+    }
+
+    #[test]
+    fn should_cap_distinct_error_types() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test").sink(sink.clone()).build().unwrap();
+
+        for n in 0..=MAX_ERROR_TYPES {
+            metrics.record_error(&Numbered(n), "query");
+        }
+        metrics.flush_metrics();
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads.len(), MAX_ERROR_TYPES + 1);
+        let other = payloads
+            .iter()
+            .find(|payload| payload[ERROR_TYPE_DIMENSION] == OTHER_ERROR_TYPE)
+            .unwrap();
+        assert_eq!(other["query_error"], 1.0);
+    }
+
+    fn checkout(metrics: &mut Metrics, fail: bool) -> Result<(), ThrottlingError> {
+        let mut guard = metrics.guard("checkout");
+        guard.increment("checkout_attempts", 1.0);
+        if fail {
+            Err(ThrottlingError)?;
+        }
+        guard.succeed();
+        Ok(())
+    }
+
+    #[test]
+    fn should_record_outcome_of_guarded_scope_on_early_return() {
+        let clock = crate::clock::ManualClock::new(chrono::Utc::now());
+        let mut metrics = Metrics::builder("test")
+            .clock(clock)
+            .sink(crate::sink::NullSink)
+            .build()
+            .unwrap();
+
+        assert!(checkout(&mut metrics, false).is_ok());
+        assert!(checkout(&mut metrics, true).is_err());
+
+        assert_eq!(metrics.value_of("checkout_attempts"), Some(2.0));
+        assert_eq!(metrics.value_of("checkout_success"), Some(1.0));
+        assert_eq!(metrics.value_of("checkout_error"), Some(1.0));
+        assert_eq!(
+            metrics.values_of("checkout_duration"),
+            Some(&[0.0, 0.0][..])
+        );
+        metrics.clear_buffer();
+    }
+
+    #[test]
+    fn should_shorten_type_names() {
+        assert_eq!(short_type_name::<std::io::Error>(), "io::Error");
+        assert_eq!(short_type_name::<serde_json::Error>(), "serde_json::Error");
+        assert_eq!(short_type_name::<std::fmt::Error>(), "fmt::Error");
+        assert_eq!(short_type_name::<crate::MetricsError>(), "MetricsError");
+        assert_eq!(short_type_name::<Vec<String>>(), "Vec");
+    }
+}
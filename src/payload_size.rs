@@ -0,0 +1,102 @@
+//! Sizes of the incoming event and of the response.
+//!
+//! Synchronous invocations fail once the event or the response exceeds
+//! [`SYNC_PAYLOAD_LIMIT`], and the payloads usually grow slowly with the data. Recording their
+//! serialized size allows an alarm before the limit is reached:
+//!
+//! ```
+//! use lambda_helpers_metrics::Metrics;
+//! use serde_json::json;
+//!
+//! let mut metrics = Metrics::builder("custom_lambdas").build().unwrap();
+//! let event = json!({ "order_id": "7051cd10" });
+//! metrics.record_event_size(&event);
+//! let response = json!({ "status": "accepted" });
+//! metrics.record_response_size(&response);
+//! ```
+//!
+//! The size is the one of the JSON serialization, counted without buffering it. With the
+//! `lambda` feature, `handler::with_payload_sizes` records both around a handler.
+use std::io;
+
+use serde::Serialize;
+
+use crate::{mode, MetricUnit, Metrics, MetricsError};
+
+/// Serialized size of the incoming event.
+pub const EVENT_SIZE_METRIC: &str = "EventSize";
+/// Serialized size of the response.
+pub const RESPONSE_SIZE_METRIC: &str = "ResponseSize";
+/// Maximum size in bytes of the event and of the response of a synchronous invocation.
+pub const SYNC_PAYLOAD_LIMIT: usize = 6 * 1024 * 1024;
+
+/// Counts the bytes written to it.
+#[derive(Debug, Default)]
+struct ByteCounter(usize);
+
+impl io::Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Returns the size in bytes of the JSON serialization of the value.
+///
+/// # Errors
+///
+/// Will return `Err` if the value can't be serialized
+pub fn serialized_size<T: Serialize + ?Sized>(value: &T) -> Result<usize, MetricsError> {
+    let mut counter = ByteCounter::default();
+    serde_json::to_writer(&mut counter, value)
+        .map_err(|err| MetricsError::Serialization(err.to_string()))?;
+    Ok(counter.0)
+}
+
+impl Metrics {
+    /// Records the serialized size of the incoming event as `EventSize`, see
+    /// [`crate::payload_size`]. Events which can't be serialized are reported like other
+    /// errors which are not returned.
+    pub fn record_event_size<T: Serialize + ?Sized>(&mut self, event: &T) {
+        self.record_serialized_size(EVENT_SIZE_METRIC, event);
+    }
+
+    /// Records the serialized size of the response as `ResponseSize`, see
+    /// [`crate::payload_size`].
+    pub fn record_response_size<T: Serialize + ?Sized>(&mut self, response: &T) {
+        self.record_serialized_size(RESPONSE_SIZE_METRIC, response);
+    }
+
+    fn record_serialized_size<T: Serialize + ?Sized>(&mut self, name: &str, value: &T) {
+        match serialized_size(value) {
+            #[allow(clippy::cast_precision_loss)]
+            Ok(bytes) => self.add_metric(name, MetricUnit::Bytes, bytes as f64),
+            Err(err) => mode::report(&err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::RecordingSink;
+
+    #[test]
+    fn should_record_serialized_sizes() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test").sink(sink.clone()).build().unwrap();
+        let event = serde_json::json!({ "order_id": "7051cd10" });
+
+        metrics.record_event_size(&event);
+        metrics.record_response_size("accepted");
+        metrics.flush_metrics();
+
+        let payload = &sink.payloads()[0];
+        assert_eq!(payload[EVENT_SIZE_METRIC], 23.0);
+        assert_eq!(payload[RESPONSE_SIZE_METRIC], 10.0);
+    }
+}
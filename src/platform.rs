@@ -0,0 +1,35 @@
+//! The pieces which differ between platforms.
+//!
+//! Writing to stdout, reading the time and the environment go through `std`, which supports
+//! them on `wasm32-wasip1` too, so the recording and serialization API runs in WASM custom
+//! runtimes which still scrape EMF from the logs. What WASI lacks is kept here:
+//! - processes have no ID, so [`process_id`] returns 0;
+//! - threads can't be spawned, so [`spawn`] returns an error instead of panicking, and callers
+//!   do the work inline.
+//!
+//! Sinks and integrations using sockets, e.g. `AgentSink` or the `aggregator`, compile but fail
+//! at runtime on WASI, with the errors of `std`.
+
+/// Returns the ID of the current process, 0 where processes have no ID.
+pub(crate) fn process_id() -> u32 {
+    #[cfg(target_os = "wasi")]
+    {
+        0
+    }
+    #[cfg(not(target_os = "wasi"))]
+    {
+        std::process::id()
+    }
+}
+
+/// Spawns a named thread running `f`.
+///
+/// # Errors
+///
+/// Will return `Err` where threads are not supported, e.g. on WASI
+pub(crate) fn spawn(name: &str, f: impl FnOnce() + Send + 'static) -> std::io::Result<()> {
+    std::thread::Builder::new()
+        .name(name.to_string())
+        .spawn(f)
+        .map(drop)
+}
@@ -0,0 +1,264 @@
+use std::borrow::Cow;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::{MetricsError, MAX_DIMENSIONS, MAX_METRICS};
+
+/// The longest dimension name accepted by `CloudWatch`, in characters.
+pub const MAX_DIMENSION_NAME_LEN: usize = 255;
+/// The longest dimension value accepted by `CloudWatch`, in characters.
+pub const MAX_DIMENSION_VALUE_LEN: usize = 1024;
+/// How old a timestamp can be before `CloudWatch` drops the payload.
+pub const MAX_TIMESTAMP_AGE: Duration = Duration::from_secs(14 * 24 * 60 * 60);
+/// How far in the future a timestamp can be before `CloudWatch` drops the payload.
+pub const MAX_TIMESTAMP_SKEW: Duration = Duration::from_secs(2 * 60 * 60);
+/// Property added to payloads with an out of range timestamp by [`TimestampAgePolicy::Warn`].
+pub const TIMESTAMP_WARNING_PROPERTY: &str = "MetricsLibraryTimestampWarning";
+const ELLIPSIS: &str = "...";
+
+/// What happens when a dimension is added after the limit of 30 dimensions is reached.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DimensionOverflowPolicy {
+    /// `try_add_dimension` returns an error and the dimension is not added.
+    #[default]
+    Reject,
+    /// The oldest dimension is removed to make room for the new one.
+    DropOldest,
+    /// The key is added as a property instead, so the value is still part of the payload
+    /// but not used to aggregate metrics.
+    DemoteToProperty,
+}
+
+/// What happens when the 101st metric is added to a single payload.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MetricOverflowPolicy {
+    /// The buffered metrics are flushed immediately and the new metric starts a new payload.
+    FlushAndContinue,
+    /// Metrics are buffered without limit and split into as many payloads as needed at flush time,
+    /// so payloads are only emitted when [`crate::Metrics::flush_metrics`] is called.
+    #[default]
+    SplitAtFlush,
+    /// `try_add_metric` returns an error and the metric is not added.
+    Error,
+}
+
+/// What happens when a dimension name is longer than 255 characters, or a value longer
+/// than 1024 characters, which `CloudWatch` would reject along with the whole payload.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DimensionLengthPolicy {
+    /// `try_add_dimension` returns an error and the dimension is not added.
+    Error,
+    /// The end is replaced with `...` to fit the limit.
+    #[default]
+    Truncate,
+    /// The overflow is replaced with `~` and its 64-bit FNV-1a hash, so distinct long values
+    /// stay distinct series, with the same value across processes and Rust versions.
+    Hash,
+}
+
+/// What happens at flush time to metrics added with [`crate::Metrics::add_metric_at`] with a
+/// timestamp older than 14 days or more than 2 hours in the future, which `CloudWatch`
+/// silently drops.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampAgePolicy {
+    /// The timestamp is moved to the closest accepted one.
+    Clamp,
+    /// The metric is dropped and counted as `MetricsLibraryDropped`.
+    Error,
+    /// The payload is emitted unchanged with a [`TIMESTAMP_WARNING_PROPERTY`] property,
+    /// so the data is still visible in the logs.
+    #[default]
+    Warn,
+}
+
+/// What happens when a payload is emitted to a full [`BackgroundSink`](crate::sink::BackgroundSink).
+#[cfg(feature = "background")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelOverflowPolicy {
+    /// The flush waits until the worker makes room, at most the block timeout of the sink,
+    /// then fails like other failed emissions.
+    #[default]
+    Block,
+    /// The oldest queued payload is dropped and counted as `MetricsLibrarySinkDropped`.
+    DropOldest,
+    /// The new payload is dropped and the flush fails, counted like other failed emissions.
+    DropNewest,
+}
+
+/// Limits of the payloads, which can be lowered below the ones of `CloudWatch`, e.g. to keep
+/// payloads small. Missing values in the configuration keep the limits of `CloudWatch`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    /// Metrics per payload, at most 100.
+    pub max_metrics: usize,
+    /// Dimensions per payload, at most 30.
+    pub max_dimensions: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_metrics: MAX_METRICS,
+            max_dimensions: MAX_DIMENSIONS,
+        }
+    }
+}
+
+impl Limits {
+    /// Checks that the limits are between 1 and the limits of `CloudWatch`.
+    pub(crate) fn validate(self) -> Result<(), MetricsError> {
+        for (name, limit, max) in [
+            ("max_metrics", self.max_metrics, MAX_METRICS),
+            ("max_dimensions", self.max_dimensions, MAX_DIMENSIONS),
+        ] {
+            if !(1..=max).contains(&limit) {
+                return Err(MetricsError::Configuration(format!(
+                    "invalid {name}: {limit}, expected 1 to {max}"
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl DimensionLengthPolicy {
+    /// Applies the policy to a name or value with the given limit.
+    pub(crate) fn apply<'a>(
+        self,
+        kind: &str,
+        text: &'a str,
+        max: usize,
+    ) -> Result<Cow<'a, str>, MetricsError> {
+        let len = text.chars().count();
+        if len <= max {
+            return Ok(Cow::Borrowed(text));
+        }
+        let prefix = |kept: usize| text.chars().take(kept).collect::<String>();
+        match self {
+            DimensionLengthPolicy::Error => Err(MetricsError::InvalidDimension(format!(
+                "{kind} is {len} characters long, the limit is {max}"
+            ))),
+            DimensionLengthPolicy::Truncate => {
+                Ok(Cow::Owned(prefix(max - ELLIPSIS.len()) + ELLIPSIS))
+            }
+            DimensionLengthPolicy::Hash => {
+                // `~` and 16 hex digits
+                let kept = max - 17;
+                let overflow = text.chars().skip(kept).collect::<String>();
+                Ok(Cow::Owned(format!(
+                    "{}~{:016x}",
+                    prefix(kept),
+                    fnv1a(overflow.as_bytes())
+                )))
+            }
+        }
+    }
+}
+
+/// The 64-bit FNV-1a hash, stable unlike `DefaultHasher`, so a value keeps its series.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+impl TimestampAgePolicy {
+    /// The oldest and newest accepted timestamps in milliseconds at `now`.
+    pub(crate) fn bounds(now: i64) -> (i64, i64) {
+        let millis = |duration: Duration| i64::try_from(duration.as_millis()).unwrap_or(i64::MAX);
+        (
+            now.saturating_sub(millis(MAX_TIMESTAMP_AGE)),
+            now.saturating_add(millis(MAX_TIMESTAMP_SKEW)),
+        )
+    }
+
+    /// Describes why `CloudWatch` would drop a payload with the timestamp, if it would.
+    pub(crate) fn violation(timestamp: i64, now: i64) -> Option<&'static str> {
+        let (oldest, newest) = Self::bounds(now);
+        if timestamp < oldest {
+            Some("timestamp is older than 14 days")
+        } else if timestamp > newest {
+            Some("timestamp is more than 2 hours in the future")
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_keep_values_within_limit() {
+        for policy in [
+            DimensionLengthPolicy::Error,
+            DimensionLengthPolicy::Truncate,
+            DimensionLengthPolicy::Hash,
+        ] {
+            assert!(matches!(
+                policy.apply("value", "checkout", 8),
+                Ok(Cow::Borrowed("checkout"))
+            ));
+        }
+    }
+
+    #[test]
+    fn should_apply_policy_to_long_values() {
+        let value = "é".repeat(30);
+
+        assert!(DimensionLengthPolicy::Error
+            .apply("value", &value, 20)
+            .is_err());
+
+        let truncated = DimensionLengthPolicy::Truncate
+            .apply("value", &value, 20)
+            .unwrap();
+        assert_eq!(truncated, format!("{}...", "é".repeat(17)));
+
+        let hashed = DimensionLengthPolicy::Hash
+            .apply("value", &value, 20)
+            .unwrap();
+        let other_value = format!("{}x", "é".repeat(29));
+        let other = DimensionLengthPolicy::Hash
+            .apply("value", &other_value, 20)
+            .unwrap();
+        assert_eq!(hashed.chars().count(), 20);
+        assert!(hashed.starts_with("ééé~"));
+        assert_ne!(hashed, other);
+    }
+
+    #[test]
+    fn should_hash_with_fnv1a() {
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(
+            DimensionLengthPolicy::Hash
+                .apply("value", &"a".repeat(18), 17)
+                .unwrap(),
+            format!("~{:016x}", fnv1a("a".repeat(18).as_bytes()))
+        );
+    }
+
+    #[test]
+    fn should_detect_timestamps_out_of_range() {
+        let now = 1_717_243_200_000;
+        let hour = 60 * 60 * 1000;
+
+        assert_eq!(TimestampAgePolicy::violation(now, now), None);
+        assert_eq!(
+            TimestampAgePolicy::violation(now - 14 * 24 * hour, now),
+            None
+        );
+        assert_eq!(TimestampAgePolicy::violation(now + 2 * hour, now), None);
+        assert!(TimestampAgePolicy::violation(now - 14 * 24 * hour - 1, now).is_some());
+        assert!(TimestampAgePolicy::violation(now + 2 * hour + 1, now).is_some());
+    }
+}
@@ -0,0 +1,378 @@
+//! Compatibility layer with the semantics of the Metrics utility of
+//! [Powertools for AWS Lambda (Python)](https://docs.powertools.aws.dev/lambda/python/latest/core/metrics/).
+//!
+//! [`PowertoolsMetrics`] wraps [`Metrics`] so instrumentation of functions ported from Python
+//! can be translated line by line:
+//!
+//! ```
+//! use lambda_helpers_metrics::powertools::{MetricResolution, PowertoolsMetrics};
+//! use lambda_helpers_metrics::MetricUnit;
+//!
+//! // metrics = Metrics(namespace="ServerlessAirline", service="booking")
+//! let mut metrics = PowertoolsMetrics::new("ServerlessAirline", Some("booking"));
+//! // metrics.set_default_dimensions(environment="prod")
+//! metrics.set_default_dimensions([("environment", "prod")]);
+//!
+//! // in the handler
+//! metrics.add_dimension("payment", "card").unwrap();
+//! metrics.add_metric("SuccessfulBooking", MetricUnit::Count, 1.0, MetricResolution::High);
+//! metrics.add_metadata("booking_id", "7051cd10");
+//! metrics.flush_metrics().unwrap();
+//! ```
+//!
+//! Like in Powertools:
+//! - recording a metric again appends the value, emitted as an array of values;
+//! - dimensions added with [`PowertoolsMetrics::add_dimension`] and metadata are cleared after
+//!   each flush, default dimensions and the `service` dimension are kept;
+//! - flushing without metrics reports a warning and emits nothing, or fails with
+//!   [`PowertoolsMetrics::raise_on_empty_metrics`].
+//!
+//! The wrapper dereferences to [`Metrics`] for everything else.
+use std::ops::{Deref, DerefMut};
+
+use crate::{Metric, MetricUnit, Metrics, MetricsError};
+
+/// Name of the dimension set from the `service` argument.
+pub const SERVICE_DIMENSION: &str = "service";
+/// Environment variable read by [`PowertoolsMetrics::from_env`] for the namespace.
+pub const NAMESPACE_VAR: &str = "POWERTOOLS_METRICS_NAMESPACE";
+/// Environment variable read by [`PowertoolsMetrics::from_env`] for the service.
+pub const SERVICE_VAR: &str = "POWERTOOLS_SERVICE_NAME";
+
+/// The storage resolution of a metric, like `MetricResolution` of Powertools.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MetricResolution {
+    /// Stored with a resolution of 60 seconds.
+    #[default]
+    Standard,
+    /// Stored with a resolution of 1 second.
+    High,
+}
+
+impl MetricResolution {
+    /// Returns the `StorageResolution` in seconds.
+    #[must_use]
+    pub const fn seconds(self) -> u64 {
+        match self {
+            MetricResolution::Standard => 60,
+            MetricResolution::High => 1,
+        }
+    }
+}
+
+/// [`Metrics`] with the semantics of Powertools, see [`crate::powertools`].
+#[derive(Debug)]
+pub struct PowertoolsMetrics {
+    metrics: Metrics,
+    default_dimensions: Vec<(String, String)>,
+    /// Keys added with `add_dimension`, restored to the value they replaced after the flush.
+    dimensions: Vec<(String, Option<String>)>,
+    /// Keys added with `add_metadata`, restored to the value they replaced after the flush.
+    metadata: Vec<(String, Option<serde_json::Value>)>,
+    raise_on_empty_metrics: bool,
+}
+
+impl PowertoolsMetrics {
+    /// Creates the metrics of a namespace, with the `service` dimension if given.
+    /// The sink is selected based on the detected [`Environment`](crate::Environment).
+    #[allow(clippy::missing_panics_doc)]
+    #[must_use]
+    pub fn new(namespace: &str, service: Option<&str>) -> Self {
+        // UNWRAP: without dimensions there is no risk of reaching max number of dimensions
+        let metrics = Metrics::builder(namespace).build().unwrap();
+        Self::from_metrics(metrics, service)
+    }
+
+    /// Creates the metrics from `POWERTOOLS_METRICS_NAMESPACE` and `POWERTOOLS_SERVICE_NAME`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the namespace is not set
+    pub fn from_env() -> Result<Self, MetricsError> {
+        let var = |key| std::env::var(key).ok().filter(|value| !value.is_empty());
+        let namespace = var(NAMESPACE_VAR)
+            .ok_or_else(|| MetricsError::Configuration(format!("{NAMESPACE_VAR} is not set")))?;
+        Ok(Self::new(&namespace, var(SERVICE_VAR).as_deref()))
+    }
+
+    /// Wraps metrics built with [`Metrics::builder`], e.g. with another sink.
+    #[must_use]
+    pub fn from_metrics(metrics: Metrics, service: Option<&str>) -> Self {
+        let mut metrics = Self {
+            metrics,
+            default_dimensions: Vec::new(),
+            dimensions: Vec::new(),
+            metadata: Vec::new(),
+            raise_on_empty_metrics: false,
+        };
+        if let Some(service) = service {
+            metrics.set_default_dimensions([(SERVICE_DIMENSION, service)]);
+        }
+        metrics
+    }
+
+    /// Sets whether flushing without metrics fails instead of printing a warning.
+    pub fn raise_on_empty_metrics(&mut self, raise: bool) {
+        self.raise_on_empty_metrics = raise;
+    }
+
+    /// Records a value of a metric with the given resolution. Values of a metric recorded
+    /// before the flush are emitted together as an array.
+    pub fn add_metric(
+        &mut self,
+        name: &str,
+        unit: MetricUnit,
+        value: f64,
+        resolution: MetricResolution,
+    ) {
+        let metric = Metric {
+            storage_resolution: Some(resolution.seconds()),
+            ..self.metrics.new_metric(name, unit, value)
+        };
+        self.metrics.append_value(metric);
+    }
+
+    /// Adds a dimension to the metrics of the next flush.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the dimension can't be added, see [`Metrics::try_add_dimension`]
+    pub fn add_dimension(&mut self, name: &str, value: &str) -> Result<(), MetricsError> {
+        let previous = self.metrics.dimensions.get(name).map(str::to_string);
+        self.metrics.try_add_dimension(name, value)?;
+        if !self.dimensions.iter().any(|(key, _)| key == name) {
+            self.dimensions.push((name.to_string(), previous));
+        }
+        Ok(())
+    }
+
+    /// Sets dimensions kept across flushes, replacing default dimensions with the same names.
+    /// Dimensions which can't be added are reported like other errors which are not returned.
+    pub fn set_default_dimensions<K: AsRef<str>, V: AsRef<str>>(
+        &mut self,
+        dimensions: impl IntoIterator<Item = (K, V)>,
+    ) {
+        for (key, value) in dimensions {
+            let (key, value) = (key.as_ref(), value.as_ref());
+            if let Err(err) = self.metrics.try_add_dimension(key, value) {
+                crate::mode::report(&err);
+                continue;
+            }
+            self.default_dimensions.retain(|(name, _)| name != key);
+            self.default_dimensions
+                .push((key.to_string(), value.to_string()));
+        }
+    }
+
+    /// Removes the default dimensions, including the `service` one.
+    pub fn clear_default_dimensions(&mut self) {
+        for (key, _) in std::mem::take(&mut self.default_dimensions) {
+            std::sync::Arc::make_mut(&mut self.metrics.dimensions).remove(&key);
+        }
+    }
+
+    /// Adds a value to the payloads of the next flush which is not a dimension,
+    /// searchable in `CloudWatch Logs Insights`.
+    pub fn add_metadata(&mut self, key: &str, value: impl Into<serde_json::Value>) {
+        let previous = self.metrics.properties.0.get(key).cloned();
+        self.metrics.add_json_property(key, value);
+        if !self.metadata.iter().any(|(name, _)| name == key) {
+            self.metadata.push((key.to_string(), previous));
+        }
+    }
+
+    /// Discards the buffered metrics, dimensions and metadata, keeping the default dimensions.
+    pub fn clear_metrics(&mut self) {
+        self.metrics.clear_buffer();
+        self.clear_flush_state();
+    }
+
+    /// Writes the buffered metrics, then clears the dimensions and metadata of the flush.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if nothing was recorded and [`PowertoolsMetrics::raise_on_empty_metrics`]
+    /// is set, or if a payload couldn't be serialized or written
+    pub fn flush_metrics(&mut self) -> Result<(), MetricsError> {
+        let result = if self.metrics.is_empty() {
+            if self.raise_on_empty_metrics {
+                Err(MetricsError::InvalidMetric(
+                    "must contain at least one metric".to_string(),
+                ))
+            } else {
+                self.metrics.report_error(&MetricsError::InvalidMetric(
+                    "no application metrics to publish".to_string(),
+                ));
+                Ok(())
+            }
+        } else {
+            self.metrics.try_flush_metrics()
+        };
+        self.clear_flush_state();
+        result
+    }
+
+    /// Removes the dimensions and metadata of the flush, and restores the dimensions and
+    /// properties they replaced, e.g. set with the builder, and the default dimensions.
+    fn clear_flush_state(&mut self) {
+        let dimensions = std::sync::Arc::make_mut(&mut self.metrics.dimensions);
+        for (key, previous) in self.dimensions.drain(..) {
+            match previous {
+                Some(value) => dimensions.insert(&key, &value),
+                None => {
+                    dimensions.remove(&key);
+                }
+            }
+        }
+        for (key, value) in &self.default_dimensions {
+            dimensions.insert(key, value);
+        }
+        for (key, previous) in self.metadata.drain(..) {
+            match previous {
+                Some(value) => self.metrics.properties.0.insert(key, value),
+                None => self.metrics.properties.0.remove(&key),
+            };
+        }
+    }
+}
+
+impl Deref for PowertoolsMetrics {
+    type Target = Metrics;
+
+    fn deref(&self) -> &Metrics {
+        &self.metrics
+    }
+}
+
+impl DerefMut for PowertoolsMetrics {
+    fn deref_mut(&mut self) -> &mut Metrics {
+        &mut self.metrics
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::RecordingSink;
+
+    fn powertools_metrics() -> (PowertoolsMetrics, RecordingSink) {
+        let sink = RecordingSink::default();
+        let metrics = Metrics::builder("ServerlessAirline")
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+        (
+            PowertoolsMetrics::from_metrics(metrics, Some("booking")),
+            sink,
+        )
+    }
+
+    #[test]
+    fn should_clear_dimensions_and_metadata_after_flush() {
+        let (mut metrics, sink) = powertools_metrics();
+        metrics.set_default_dimensions([("environment", "prod")]);
+
+        metrics.add_dimension("payment", "card").unwrap();
+        metrics.add_dimension("environment", "test").unwrap();
+        metrics.add_metadata("booking_id", "7051cd10");
+        metrics.add_metric(
+            "SuccessfulBooking",
+            MetricUnit::Count,
+            1.0,
+            MetricResolution::High,
+        );
+        metrics.add_metric(
+            "SuccessfulBooking",
+            MetricUnit::Count,
+            2.0,
+            MetricResolution::High,
+        );
+        metrics.flush_metrics().unwrap();
+        metrics.add_metric(
+            "SuccessfulBooking",
+            MetricUnit::Count,
+            1.0,
+            MetricResolution::Standard,
+        );
+        metrics.flush_metrics().unwrap();
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads[0]["service"], "booking");
+        assert_eq!(payloads[0]["payment"], "card");
+        assert_eq!(payloads[0]["environment"], "test");
+        assert_eq!(payloads[0]["booking_id"], "7051cd10");
+        assert_eq!(
+            payloads[0]["SuccessfulBooking"],
+            serde_json::json!([1.0, 2.0])
+        );
+        assert_eq!(
+            payloads[0]["_aws"]["CloudWatchMetrics"][0]["Metrics"][0]["StorageResolution"],
+            1
+        );
+        assert_eq!(payloads[1]["service"], "booking");
+        assert_eq!(payloads[1]["environment"], "prod");
+        assert!(payloads[1].get("payment").is_none());
+        assert!(payloads[1].get("booking_id").is_none());
+    }
+
+    #[test]
+    fn should_handle_empty_flushes() {
+        let (mut metrics, sink) = powertools_metrics();
+
+        assert!(metrics.flush_metrics().is_ok());
+        metrics.raise_on_empty_metrics(true);
+        assert!(metrics.flush_metrics().is_err());
+        assert!(sink.payloads().is_empty());
+    }
+
+    #[test]
+    fn should_clear_default_dimensions() {
+        let (mut metrics, sink) = powertools_metrics();
+
+        metrics.clear_default_dimensions();
+        metrics.add_metric(
+            "SuccessfulBooking",
+            MetricUnit::Count,
+            1.0,
+            MetricResolution::Standard,
+        );
+        metrics.flush_metrics().unwrap();
+
+        assert!(sink.payloads()[0].get("service").is_none());
+    }
+
+    #[test]
+    fn should_restore_builder_dimensions_and_properties_after_flush() {
+        let sink = RecordingSink::default();
+        let metrics = Metrics::builder("ServerlessAirline")
+            .dimension("region", "eu")
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+        let mut metrics = PowertoolsMetrics::from_metrics(metrics, None);
+        metrics.add_property("team", "payments");
+
+        metrics.add_dimension("region", "us").unwrap();
+        metrics.add_metadata("team", "bookings");
+        metrics.add_metric(
+            "Booking",
+            MetricUnit::Count,
+            1.0,
+            MetricResolution::Standard,
+        );
+        metrics.flush_metrics().unwrap();
+        metrics.add_metric(
+            "Booking",
+            MetricUnit::Count,
+            1.0,
+            MetricResolution::Standard,
+        );
+        metrics.flush_metrics().unwrap();
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads[0]["region"], "us");
+        assert_eq!(payloads[0]["team"], "bookings");
+        assert_eq!(payloads[1]["region"], "eu");
+        assert_eq!(payloads[1]["team"], "payments");
+    }
+}
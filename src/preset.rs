@@ -0,0 +1,180 @@
+//! Named dimension-set presets.
+//!
+//! A preset names the dimensions used to aggregate the metrics, e.g. `by_tenant` for
+//! `service` and `tenant`. Presets are defined once, typically in the shared
+//! [configuration](crate::config), and each function selects the one it needs:
+//!
+//! ```json
+//! {
+//!     "dimension_presets": {
+//!         "by_tenant": ["service", "tenant"],
+//!         "by_region": ["service", "region"]
+//!     },
+//!     "dimension_preset": "by_tenant"
+//! }
+//! ```
+//!
+//! ```
+//! use lambda_helpers_metrics::{MetricUnit, Metrics};
+//!
+//! let mut metrics = Metrics::builder("custom_lambdas")
+//!     .define_dimension_preset("by_tenant", &["service", "tenant"])
+//!     .define_dimension_preset("by_region", &["service", "region"])
+//!     .dimension_preset("by_tenant")
+//!     .dimension("service", "checkout")
+//!     .dimension("tenant", "acme")
+//!     .dimension("region", "eu-west-1")
+//!     .build()
+//!     .unwrap();
+//! metrics.add_metric("orders", MetricUnit::Count, 1.0);
+//! // "Dimensions": [["service", "tenant"]], "region" is written as a property
+//! metrics.flush_with_dimension_preset("by_region").unwrap();
+//! ```
+//!
+//! The dimensions which are not part of the selected preset are still written to the payloads,
+//! as properties searchable in `CloudWatch Logs Insights`. Dimensions of the preset missing from
+//! a payload are left out of its dimension set. [Rollups](crate::rollup) are declared in
+//! addition to the preset.
+use std::collections::BTreeMap;
+
+use crate::{Metrics, MetricsError};
+
+/// The presets of a `Metrics` object and the selected one.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct DimensionPresets {
+    presets: BTreeMap<String, Vec<String>>,
+    selected: Option<String>,
+}
+
+impl DimensionPresets {
+    pub(crate) fn define(&mut self, name: &str, keys: &[&str]) {
+        self.presets.insert(
+            name.to_string(),
+            keys.iter().map(ToString::to_string).collect(),
+        );
+    }
+
+    pub(crate) fn extend(&mut self, presets: &BTreeMap<String, Vec<String>>) {
+        self.presets.extend(
+            presets
+                .iter()
+                .map(|(name, keys)| (name.clone(), keys.clone())),
+        );
+    }
+
+    pub(crate) fn select(&mut self, name: Option<&str>) -> Result<(), MetricsError> {
+        if let Some(name) = name {
+            if !self.presets.contains_key(name) {
+                return Err(MetricsError::Configuration(format!(
+                    "unknown dimension preset: {name}"
+                )));
+            }
+        }
+        self.selected = name.map(str::to_string);
+        Ok(())
+    }
+
+    pub(crate) fn selected(&self) -> Option<&str> {
+        self.selected.as_deref()
+    }
+
+    /// The keys of the selected preset, `None` if no preset is selected.
+    pub(crate) fn keys(&self) -> Option<&[String]> {
+        self.presets.get(self.selected.as_ref()?).map(Vec::as_slice)
+    }
+}
+
+impl Metrics {
+    /// Selects the preset of dimensions used to aggregate the metrics of the following flushes,
+    /// or all dimensions with `None`, see [`crate::preset`].
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the preset is not defined
+    pub fn set_dimension_preset(&mut self, name: Option<&str>) -> Result<(), MetricsError> {
+        self.dimension_presets.select(name)
+    }
+
+    /// Returns the name of the selected preset of dimensions, if any.
+    #[must_use]
+    pub fn dimension_preset(&self) -> Option<&str> {
+        self.dimension_presets.selected()
+    }
+
+    /// Flushes the metrics with the given preset of dimensions, then restores the selected one.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the preset is not defined, or the first error of the flush,
+    /// see [`Metrics::try_flush_metrics`]
+    pub fn flush_with_dimension_preset(&mut self, name: &str) -> Result<(), MetricsError> {
+        let selected = self.dimension_presets.selected().map(str::to_string);
+        self.dimension_presets.select(Some(name))?;
+        let result = self.try_flush_metrics();
+        // UNWRAP: the preset was selected before
+        self.dimension_presets.select(selected.as_deref()).unwrap();
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::MetricsConfig;
+    use crate::sink::RecordingSink;
+    use crate::{MetricUnit, Metrics};
+
+    #[test]
+    fn should_aggregate_by_selected_preset() {
+        let config = MetricsConfig::from_json(
+            r#"{
+                "dimension_presets": {
+                    "by_tenant": ["service", "tenant"],
+                    "by_region": ["service", "region"]
+                },
+                "dimension_preset": "by_tenant"
+            }"#,
+        )
+        .unwrap();
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .config(&config)
+            .sink(sink.clone())
+            .dimension("service", "checkout")
+            .dimension("tenant", "acme")
+            .dimension("region", "eu-west-1")
+            .build()
+            .unwrap();
+
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+        metrics.flush_metrics();
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+        metrics.flush_with_dimension_preset("by_region").unwrap();
+        metrics.set_dimension_preset(None).unwrap();
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+        metrics.flush_metrics();
+
+        let payloads = sink.payloads();
+        let sets =
+            |index: usize| payloads[index]["_aws"]["CloudWatchMetrics"][0]["Dimensions"].clone();
+        assert_eq!(sets(0), serde_json::json!([["service", "tenant"]]));
+        assert_eq!(payloads[0]["region"], "eu-west-1");
+        assert_eq!(sets(1), serde_json::json!([["service", "region"]]));
+        assert_eq!(payloads[1]["tenant"], "acme");
+        assert_eq!(
+            sets(2),
+            serde_json::json!([["service", "tenant", "region"]])
+        );
+        assert_eq!(metrics.dimension_preset(), None);
+        assert!(metrics.set_dimension_preset(Some("by_team")).is_err());
+    }
+
+    #[test]
+    fn should_reject_unknown_preset_at_build() {
+        let result = Metrics::builder("test")
+            .define_dimension_preset("by_tenant", &["tenant"])
+            .dimension_preset("by_team")
+            .build();
+
+        assert!(result.is_err());
+    }
+}
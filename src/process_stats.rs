@@ -0,0 +1,174 @@
+//! CPU time and open file descriptors of the process, read from `/proc` at flush.
+//!
+//! Functions with little memory get a small share of a CPU, so CPU starvation shows up as
+//! latency, and leaked sockets or files only fail once the limit of descriptors is reached.
+//! `Metrics` objects built with
+//! [`MetricsBuilder::process_stats`](crate::MetricsBuilder::process_stats) record at each flush:
+//!
+//! - `process_cpu_user_time` and `process_cpu_system_time`: the CPU time spent in user and
+//!   kernel mode since the previous flush, since the start of the process at the first one;
+//! - `process_open_fds`: the number of open file descriptors.
+//!
+//! ```
+//! use lambda_helpers_metrics::Metrics;
+//!
+//! let metrics = Metrics::builder("custom_lambdas")
+//!     .process_stats(true)
+//!     .build()
+//!     .unwrap();
+//! ```
+//!
+//! The values are read from `/proc/self/stat` and `/proc/self/fd`; nothing is recorded where
+//! they can't be read, e.g. outside Linux.
+use std::time::Duration;
+
+use crate::catalog::codegen::CatalogMetric;
+use crate::{MetricUnit, Metrics};
+
+/// CPU time spent in user mode since the previous flush.
+pub const CPU_USER_TIME_METRIC: &str = "process_cpu_user_time";
+/// CPU time spent in kernel mode since the previous flush.
+pub const CPU_SYSTEM_TIME_METRIC: &str = "process_cpu_system_time";
+/// Number of open file descriptors.
+pub const OPEN_FDS_METRIC: &str = "process_open_fds";
+
+/// Clock ticks per second of the times in `/proc/self/stat`, `USER_HZ`, which is 100 on
+/// the architectures Lambda runs on.
+const CLOCK_TICKS_PER_SECOND: u64 = 100;
+
+/// CPU time of the process at the previous flush.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct CpuTimes {
+    user: Duration,
+    system: Duration,
+}
+
+impl CpuTimes {
+    fn read() -> Option<Self> {
+        Self::parse(&std::fs::read_to_string("/proc/self/stat").ok()?)
+    }
+
+    /// Parses `utime` and `stime`, the 14th and 15th fields. The 2nd field is the name of the
+    /// executable in parentheses, which can contain spaces, so fields are counted after it.
+    fn parse(stat: &str) -> Option<Self> {
+        let mut fields = stat.get(stat.rfind(')')? + 1..)?.split_whitespace();
+        // the 3rd field is the first one after the name
+        let user = fields.nth(11)?.parse::<u64>().ok()?;
+        let system = fields.next()?.parse::<u64>().ok()?;
+        let duration =
+            |ticks: u64| Duration::from_millis(ticks.saturating_mul(1000) / CLOCK_TICKS_PER_SECOND);
+        Some(Self {
+            user: duration(user),
+            system: duration(system),
+        })
+    }
+}
+
+/// Returns the number of open file descriptors, without the one used to list them.
+fn open_fds() -> Option<usize> {
+    let entries = std::fs::read_dir("/proc/self/fd").ok()?;
+    Some(entries.count().saturating_sub(1))
+}
+
+/// The metrics recorded with [`MetricsBuilder::process_stats`](crate::MetricsBuilder::process_stats),
+/// see [`Metrics::catalog`].
+pub(crate) fn catalog_metrics() -> Vec<CatalogMetric> {
+    [
+        (
+            CPU_USER_TIME_METRIC,
+            MetricUnit::Milliseconds,
+            "CPU time spent in user mode since the previous flush.",
+        ),
+        (
+            CPU_SYSTEM_TIME_METRIC,
+            MetricUnit::Milliseconds,
+            "CPU time spent in kernel mode since the previous flush.",
+        ),
+        (
+            OPEN_FDS_METRIC,
+            MetricUnit::Count,
+            "Number of open file descriptors.",
+        ),
+    ]
+    .into_iter()
+    .map(|(name, unit, description)| CatalogMetric::library(name, unit, description))
+    .collect()
+}
+
+impl Metrics {
+    /// Adds the CPU time since the previous flush and the open file descriptors to the buffer,
+    /// if enabled, see [`Metrics::measures_at_flush`].
+    pub(crate) fn buffer_process_stats(&mut self) {
+        if !self.measures_at_flush() {
+            return;
+        }
+        let Some(previous) = &mut self.process_stats else {
+            return;
+        };
+        let mut values = Vec::new();
+        if let Some(times) = CpuTimes::read() {
+            values.extend([
+                (
+                    CPU_USER_TIME_METRIC,
+                    MetricUnit::Milliseconds,
+                    millis(times.user.saturating_sub(previous.user)),
+                ),
+                (
+                    CPU_SYSTEM_TIME_METRIC,
+                    MetricUnit::Milliseconds,
+                    millis(times.system.saturating_sub(previous.system)),
+                ),
+            ]);
+            *previous = times;
+        }
+        if let Some(fds) = open_fds() {
+            #[allow(clippy::cast_precision_loss)]
+            values.push((OPEN_FDS_METRIC, MetricUnit::Count, fds as f64));
+        }
+        for (name, unit, value) in values {
+            self.push_internal_metric(name, unit, value);
+        }
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::RecordingSink;
+
+    #[test]
+    fn should_parse_cpu_times_after_the_name() {
+        let stat = "42 (my (handler) x) S 1 42 42 0 -1 4194560 1094 0 0 0 250 37 0 0 20 0 1 0";
+
+        let times = CpuTimes::parse(stat).unwrap();
+
+        assert_eq!(times.user, Duration::from_millis(2500));
+        assert_eq!(times.system, Duration::from_millis(370));
+        assert_eq!(CpuTimes::parse("42 (handler) S 1"), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn should_record_process_stats_at_flush() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .process_stats(true)
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+
+        metrics.flush_metrics();
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+        metrics.flush_metrics();
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads.len(), 1);
+        assert!(payloads[0][OPEN_FDS_METRIC].as_f64().unwrap() >= 3.0);
+        assert!(payloads[0][CPU_USER_TIME_METRIC].as_f64().unwrap() >= 0.0);
+        assert!(payloads[0][CPU_SYSTEM_TIME_METRIC].is_number());
+    }
+}
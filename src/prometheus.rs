@@ -0,0 +1,279 @@
+//! Sink pushing metrics to Prometheus remote-write endpoints.
+//!
+//! Each payload is converted into a remote-write request, snappy-compressed protobuf, and
+//! pushed to the endpoint, e.g. Mimir or Thanos, for stacks using Prometheus alongside
+//! `CloudWatch`. The series are named `<namespace>_<metric>`, with the dimensions as labels:
+//!
+//! ```no_run
+//! use lambda_helpers_metrics::prometheus::PrometheusSink;
+//! use lambda_helpers_metrics::{MetricUnit, Metrics};
+//!
+//! # async fn example() {
+//! let mut metrics = Metrics::builder("custom_lambdas")
+//!     .dimension("service", "orders")
+//!     .async_sink(
+//!         PrometheusSink::new("https://mimir.internal/api/v1/push").header("X-Scope-OrgID", "shop"),
+//!     )
+//!     .build()
+//!     .unwrap();
+//! metrics.add_metric("latency", MetricUnit::Milliseconds, 120.0);
+//! metrics.flush_async().await;
+//! # }
+//! ```
+//!
+//! A metric with a single value becomes a sample of its series. A metric with several values,
+//! e.g. recorded with `add_sample`, becomes a `_sum` and a `_count` series, since a series
+//! can't hold several samples with the same timestamp. Properties are not pushed.
+//! Available with the `prometheus` feature.
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::sink::{AsyncMetricsSink, EmitFuture};
+use crate::{emf, MetricsError};
+
+/// A series of a remote-write request, with its labels sorted by name.
+#[derive(Debug, Clone, PartialEq)]
+struct Series {
+    labels: Vec<(String, String)>,
+    value: f64,
+    timestamp: i64,
+}
+
+/// Pushes payloads to a Prometheus remote-write endpoint, see [`crate::prometheus`].
+#[derive(Debug, Clone)]
+pub struct PrometheusSink {
+    client: reqwest::Client,
+    endpoint: String,
+    headers: Vec<(String, String)>,
+    prefix_namespace: bool,
+}
+
+impl PrometheusSink {
+    /// Creates a sink pushing to the remote-write endpoint.
+    #[must_use]
+    pub fn new(endpoint: &str) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            endpoint: endpoint.to_string(),
+            headers: Vec::new(),
+            prefix_namespace: true,
+        }
+    }
+
+    /// Adds a header sent with every request, e.g. `Authorization` or the tenant of Mimir.
+    #[must_use]
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Sets whether series names are prefixed with the namespace, `true` by default.
+    #[must_use]
+    pub fn prefix_namespace(mut self, prefix: bool) -> Self {
+        self.prefix_namespace = prefix;
+        self
+    }
+
+    /// Converts the metrics of an EMF payload into series.
+    fn series(&self, payload: &str) -> Result<Vec<Series>, MetricsError> {
+        let mut series = Vec::new();
+        for metric in emf::parse(payload)? {
+            let values: Vec<f64> = metric
+                .values
+                .iter()
+                .copied()
+                .filter(|value| value.is_finite())
+                .collect();
+            let name = if self.prefix_namespace {
+                sanitize_name(&format!("{}_{}", metric.namespace, metric.name))
+            } else {
+                sanitize_name(&metric.name)
+            };
+            let timestamp = metric.timestamp.unwrap_or_else(now_millis);
+            #[allow(clippy::cast_precision_loss)]
+            let samples = match values.as_slice() {
+                [] => continue,
+                [value] => vec![(name, *value)],
+                values => vec![
+                    (format!("{name}_sum"), values.iter().sum()),
+                    (format!("{name}_count"), values.len() as f64),
+                ],
+            };
+            for (name, value) in samples {
+                let mut labels: Vec<(String, String)> = metric
+                    .dimensions
+                    .iter()
+                    .map(|(key, value)| (sanitize_label(key), value.to_string()))
+                    .collect();
+                labels.push(("__name__".to_string(), name));
+                labels.sort();
+                series.push(Series {
+                    labels,
+                    value,
+                    timestamp,
+                });
+            }
+        }
+        Ok(series)
+    }
+}
+
+impl AsyncMetricsSink for PrometheusSink {
+    fn emit(&self, payload: String) -> EmitFuture<'_> {
+        Box::pin(async move {
+            let series = self.series(&payload)?;
+            if series.is_empty() {
+                return Ok(());
+            }
+            let body = snap::raw::Encoder::new()
+                .compress_vec(&write_request(&series))
+                .map_err(|err| MetricsError::Serialization(err.to_string()))?;
+            let mut request = self
+                .client
+                .post(&self.endpoint)
+                .header("Content-Encoding", "snappy")
+                .header("Content-Type", "application/x-protobuf")
+                .header("X-Prometheus-Remote-Write-Version", "0.1.0");
+            for (name, value) in &self.headers {
+                request = request.header(name, value);
+            }
+            let response = request.body(body).send().await.map_err(io_error)?;
+            response.error_for_status().map_err(io_error)?;
+            Ok(())
+        })
+    }
+}
+
+fn io_error(err: reqwest::Error) -> MetricsError {
+    MetricsError::Io(std::io::Error::other(err))
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| {
+            i64::try_from(elapsed.as_millis()).unwrap_or(i64::MAX)
+        })
+}
+
+/// Metric names may only contain ASCII alphanumerics, underscores and colons,
+/// and can't start with a digit.
+fn sanitize_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == ':' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{name}")
+    } else {
+        name
+    }
+}
+
+/// Label names follow the rules of metric names, without colons.
+fn sanitize_label(name: &str) -> String {
+    sanitize_name(name).replace(':', "_")
+}
+
+/// Encodes the `WriteRequest` message of the remote-write protocol:
+/// `WriteRequest { repeated TimeSeries timeseries = 1; }`,
+/// `TimeSeries { repeated Label labels = 1; repeated Sample samples = 2; }`,
+/// `Label { string name = 1; string value = 2; }` and
+/// `Sample { double value = 1; int64 timestamp = 2; }`.
+fn write_request(series: &[Series]) -> Vec<u8> {
+    let mut request = Vec::new();
+    for series in series {
+        let mut time_series = Vec::new();
+        for (name, value) in &series.labels {
+            let mut label = Vec::new();
+            write_bytes(&mut label, 1, name.as_bytes());
+            write_bytes(&mut label, 2, value.as_bytes());
+            write_bytes(&mut time_series, 1, &label);
+        }
+        let mut sample = Vec::new();
+        // wire type 1, 64-bit
+        write_varint(&mut sample, 1 << 3 | 1);
+        sample.extend_from_slice(&series.value.to_le_bytes());
+        // wire type 0, varint; negative timestamps are encoded as ten bytes
+        write_varint(&mut sample, 2 << 3);
+        #[allow(clippy::cast_sign_loss)]
+        write_varint(&mut sample, series.timestamp as u64);
+        write_bytes(&mut time_series, 2, &sample);
+        write_bytes(&mut request, 1, &time_series);
+    }
+    request
+}
+
+/// Writes a length-delimited field, wire type 2.
+fn write_bytes(buffer: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    write_varint(buffer, field << 3 | 2);
+    write_varint(buffer, bytes.len() as u64);
+    buffer.extend_from_slice(bytes);
+}
+
+fn write_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        // the low seven bits, with the continuation bit
+        #[allow(clippy::cast_possible_truncation)]
+        buffer.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    #[allow(clippy::cast_possible_truncation)]
+    buffer.push(value as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAYLOAD: &str = r#"{"_aws":{"Timestamp":1000,"CloudWatchMetrics":[{"Namespace":"shop",
+        "Dimensions":[["service.name"]],"Metrics":[{"Name":"latency","Unit":"Milliseconds"},
+        {"Name":"orders","Unit":"Count"}]}]},"service.name":"orders","latency":[1.5,2.5],"orders":3}"#;
+
+    #[test]
+    fn should_convert_metrics_into_series() {
+        let series = PrometheusSink::new("http://localhost")
+            .series(PAYLOAD)
+            .unwrap();
+
+        let names: Vec<&str> = series
+            .iter()
+            .map(|series| series.labels[0].1.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            ["shop_latency_sum", "shop_latency_count", "shop_orders"]
+        );
+        assert_eq!(
+            series[2].labels,
+            vec![
+                ("__name__".to_string(), "shop_orders".to_string()),
+                ("service_name".to_string(), "orders".to_string()),
+            ]
+        );
+        assert_eq!(series[0].value, 4.0);
+        assert_eq!(series[1].value, 2.0);
+        assert_eq!(series[2].timestamp, 1000);
+    }
+
+    #[test]
+    fn should_encode_write_request() {
+        let series = Series {
+            labels: vec![("__name__".to_string(), "up".to_string())],
+            value: 1.0,
+            timestamp: 300,
+        };
+
+        let mut expected = vec![0x0a, 0x1e, 0x0a, 0x0e, 0x0a, 0x08];
+        expected.extend_from_slice(b"__name__");
+        expected.extend_from_slice(&[0x12, 0x02, b'u', b'p', 0x12, 0x0c, 0x09]);
+        expected.extend_from_slice(&1.0_f64.to_le_bytes());
+        expected.extend_from_slice(&[0x10, 0xac, 0x02]);
+        assert_eq!(write_request(&[series]), expected);
+    }
+}
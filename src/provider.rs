@@ -0,0 +1,170 @@
+//! Dimensions and properties contributed at flush by shared providers.
+//!
+//! Enrichment which is the same for every handler, e.g. deployment metadata read from a cached
+//! SSM parameter, can live in a [`DimensionProvider`] instead of being added in each handler.
+//! Providers are consulted at every flush, before the payloads are serialized, and likewise by
+//! [`Metrics::to_json_bytes`], [`Metrics::write_emf_to`] and [`Metrics::embed_into`]:
+//!
+//! ```
+//! use lambda_helpers_metrics::provider::DimensionProvider;
+//! use lambda_helpers_metrics::Metrics;
+//!
+//! #[derive(Debug)]
+//! struct Deployment {
+//!     version: String,
+//! }
+//!
+//! impl DimensionProvider for Deployment {
+//!     fn dimensions(&self) -> Vec<(String, String)> {
+//!         vec![("version".to_string(), self.version.clone())]
+//!     }
+//! }
+//!
+//! let metrics = Metrics::builder("custom_lambdas")
+//!     .dimension_provider(Deployment { version: "1.4.2".to_string() })
+//!     .build()
+//!     .unwrap();
+//! ```
+//!
+//! Contributed dimensions replace shared dimensions with the same keys, and stay set after
+//! the flush like other shared dimensions. Payloads serialized without a flush, e.g. with
+//! [`Metrics::to_json_bytes`], carry them without adding them to the object.
+use std::fmt;
+use std::sync::Arc;
+
+use crate::{mode, Dimensions, Metrics, PayloadFields, Properties};
+
+/// Contributes dimensions and properties to every flush, see [`crate::provider`].
+pub trait DimensionProvider: fmt::Debug + Send + Sync {
+    /// Returns the dimensions added to the shared dimensions.
+    fn dimensions(&self) -> Vec<(String, String)> {
+        Vec::new()
+    }
+
+    /// Returns the properties added to the payloads.
+    fn properties(&self) -> Vec<(String, serde_json::Value)> {
+        Vec::new()
+    }
+}
+
+impl Metrics {
+    /// Adds a provider consulted at every flush, see [`crate::provider`].
+    pub fn add_dimension_provider(&mut self, provider: impl DimensionProvider + 'static) {
+        self.providers.push(Arc::new(provider));
+    }
+
+    /// Adds the dimensions and properties of the providers. Dimensions which can't be added
+    /// are reported like other errors which are not returned.
+    pub(crate) fn apply_providers(&mut self) {
+        if let Some((dimensions, properties)) = self.provided_fields() {
+            self.dimensions = dimensions;
+            self.properties = properties;
+        }
+    }
+
+    /// Calls `f` with the shared fields and the ones of the providers, leaving the object
+    /// untouched, for payloads serialized without a flush.
+    pub(crate) fn with_provided_fields<R>(&self, f: impl FnOnce(PayloadFields<'_>) -> R) -> R {
+        match self.provided_fields() {
+            Some((dimensions, properties)) => f(PayloadFields::new(&dimensions, &properties)),
+            None => f(self.payload_fields()),
+        }
+    }
+
+    /// Returns a copy of the shared dimensions and properties with the ones of the providers,
+    /// or `None` without providers.
+    fn provided_fields(&self) -> Option<(Arc<Dimensions>, Properties)> {
+        if self.providers.is_empty() {
+            return None;
+        }
+        let policies = self.dimension_policies();
+        let mut dimensions = Arc::clone(&self.dimensions);
+        let mut properties = self.properties.clone();
+        for provider in &self.providers {
+            for (key, value) in provider.dimensions() {
+                if let Err(err) = policies.add(&mut dimensions, &mut properties, &key, &value) {
+                    mode::report(&err);
+                }
+            }
+            for (key, value) in provider.properties() {
+                properties.0.insert(key, value);
+            }
+        }
+        Some((dimensions, properties))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    use super::*;
+    use crate::sink::RecordingSink;
+    use crate::MetricUnit;
+
+    /// Counts the flushes it was consulted for.
+    #[derive(Debug, Default)]
+    struct Counting(AtomicU64);
+
+    impl DimensionProvider for Counting {
+        fn dimensions(&self) -> Vec<(String, String)> {
+            vec![("region".to_string(), "eu-west-1".to_string())]
+        }
+
+        fn properties(&self) -> Vec<(String, serde_json::Value)> {
+            let flush = self.0.fetch_add(1, Ordering::SeqCst) + 1;
+            vec![("flush".to_string(), flush.into())]
+        }
+    }
+
+    #[test]
+    fn should_enrich_each_flush() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .dimension_provider(Counting::default())
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+        metrics.flush_metrics();
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+        metrics.flush_metrics();
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads[0]["region"], "eu-west-1");
+        assert_eq!(
+            payloads[0]["_aws"]["CloudWatchMetrics"][0]["Dimensions"][0],
+            serde_json::json!(["region"])
+        );
+        assert_eq!(payloads[0]["flush"], 1);
+        assert_eq!(payloads[1]["flush"], 2);
+    }
+
+    #[test]
+    fn should_enrich_serialized_and_embedded_payloads() {
+        let mut metrics = Metrics::builder("test")
+            .dimension_provider(Counting::default())
+            .dry_run(true)
+            .build()
+            .unwrap();
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+
+        let line: serde_json::Value =
+            serde_json::from_slice(&metrics.to_json_bytes().unwrap()).unwrap();
+        assert_eq!(line["region"], "eu-west-1");
+        assert_eq!(line["flush"], 1);
+
+        let mut out = String::new();
+        metrics.write_emf_to(&mut out).unwrap();
+        let line: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(line["flush"], 2);
+
+        let mut record = serde_json::Map::new();
+        metrics.embed_into(&mut record).unwrap();
+        assert_eq!(record["region"], "eu-west-1");
+        assert_eq!(record["flush"], 3);
+        assert_eq!(metrics.dimension("region"), None);
+        assert_eq!(metrics.json_property("flush"), None);
+    }
+}
@@ -0,0 +1,154 @@
+//! Several named `Metrics` contexts flushed together.
+//!
+//! ```
+//! use lambda_helpers_metrics::registry::MetricsRegistry;
+//! use lambda_helpers_metrics::{MetricUnit, Metrics};
+//!
+//! let mut registry = MetricsRegistry::new();
+//! registry.register("orders", Metrics::new("shop/orders", "service", "checkout"));
+//! registry.register("payments", Metrics::new("shop/payments", "service", "checkout"));
+//!
+//! if let Some(metrics) = registry.get_mut("orders") {
+//!     metrics.add_metric("placed", MetricUnit::Count, 1.0);
+//! }
+//!
+//! // at the end of the invocation
+//! registry.flush_all();
+//! ```
+use crate::Metrics;
+
+/// Owns named `Metrics` contexts, e.g. one per subsystem, in registration order.
+#[derive(Debug, Default)]
+pub struct MetricsRegistry {
+    contexts: Vec<(String, Metrics)>,
+}
+
+impl MetricsRegistry {
+    /// Creates an empty registry.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the metrics under the name, returning the previously registered ones.
+    pub fn register(&mut self, name: &str, metrics: Metrics) -> Option<Metrics> {
+        match self.get_mut(name) {
+            Some(existing) => Some(std::mem::replace(existing, metrics)),
+            None => {
+                self.contexts.push((name.to_string(), metrics));
+                None
+            }
+        }
+    }
+
+    /// Returns the metrics registered under the name.
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<&Metrics> {
+        self.contexts
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, metrics)| metrics)
+    }
+
+    /// Returns the metrics registered under the name, for recording.
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Metrics> {
+        self.contexts
+            .iter_mut()
+            .find(|(key, _)| key == name)
+            .map(|(_, metrics)| metrics)
+    }
+
+    /// Returns the metrics registered under the name, registering the ones created by `create` first
+    /// if the name is unknown.
+    #[allow(clippy::missing_panics_doc)]
+    pub fn get_or_register_with(
+        &mut self,
+        name: &str,
+        create: impl FnOnce() -> Metrics,
+    ) -> &mut Metrics {
+        if self.get(name).is_none() {
+            self.contexts.push((name.to_string(), create()));
+        }
+        // UNWRAP: the name was registered above
+        self.get_mut(name).unwrap()
+    }
+
+    /// Removes the metrics registered under the name. They are flushed when dropped.
+    pub fn remove(&mut self, name: &str) -> Option<Metrics> {
+        let index = self.contexts.iter().position(|(key, _)| key == name)?;
+        Some(self.contexts.remove(index).1)
+    }
+
+    /// Iterates over the registered names.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.contexts.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Returns the number of registered contexts.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.contexts.len()
+    }
+
+    /// Returns `true` if no context is registered.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.contexts.is_empty()
+    }
+
+    /// Flushes all registered contexts, in registration order.
+    pub fn flush_all(&mut self) {
+        for (_, metrics) in &mut self.contexts {
+            metrics.flush_metrics();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::RecordingSink;
+    use crate::MetricUnit;
+
+    fn metrics(namespace: &str, sink: &RecordingSink) -> Metrics {
+        Metrics::builder(namespace)
+            .sink(sink.clone())
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn should_flush_all_contexts() {
+        let sink = RecordingSink::default();
+        let mut registry = MetricsRegistry::new();
+        registry.register("orders", metrics("orders", &sink));
+        registry
+            .get_or_register_with("payments", || metrics("payments", &sink))
+            .add_metric("charged", MetricUnit::Count, 1.0);
+        registry
+            .get_mut("orders")
+            .unwrap()
+            .add_metric("placed", MetricUnit::Count, 1.0);
+
+        registry.flush_all();
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads.len(), 2);
+        assert_eq!(payloads[0]["placed"], 1.0);
+        assert_eq!(payloads[1]["charged"], 1.0);
+        assert_eq!(registry.names().collect::<Vec<_>>(), ["orders", "payments"]);
+        assert!(registry.get("orders").unwrap().is_empty());
+    }
+
+    #[test]
+    fn should_replace_registered_context() {
+        let sink = RecordingSink::default();
+        let mut registry = MetricsRegistry::new();
+
+        assert!(registry.register("orders", metrics("a", &sink)).is_none());
+        assert!(registry.register("orders", metrics("b", &sink)).is_some());
+        assert_eq!(registry.len(), 1);
+        assert!(registry.remove("orders").is_some());
+        assert!(registry.is_empty());
+    }
+}
@@ -0,0 +1,107 @@
+//! Renaming metrics at flush.
+//!
+//! A rename map lets teams migrate metric naming conventions gradually, without touching every
+//! call site at once. With [`RenameMode::EmitBoth`] the metric is emitted under both names
+//! during the transition, so dashboards and alarms can move to the new name first:
+//!
+//! ```
+//! use lambda_helpers_metrics::rename::RenameMode;
+//! use lambda_helpers_metrics::{MetricUnit, Metrics};
+//!
+//! let mut metrics = Metrics::builder("custom_lambdas")
+//!     .rename_metric("latencyMs", "latency_ms", RenameMode::EmitBoth)
+//!     .rename_metric("errCount", "errors", RenameMode::Replace)
+//!     .build()
+//!     .unwrap();
+//!
+//! metrics.add_metric("latencyMs", MetricUnit::Milliseconds, 120.0);
+//! metrics.flush_metrics(); // latencyMs = 120, latency_ms = 120
+//! ```
+use serde::Deserialize;
+
+use crate::{Metric, Metrics};
+
+/// Whether a renamed metric is still emitted under its old name.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RenameMode {
+    /// Only the new name is emitted.
+    #[default]
+    Replace,
+    /// The metric is emitted under the old and the new name.
+    EmitBoth,
+}
+
+/// The rename map, by old name.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Renames(Vec<(String, String, RenameMode)>);
+
+impl Renames {
+    /// Adds a rename, replacing an earlier one of the same metric.
+    pub(crate) fn add(&mut self, old: &str, new: &str, mode: RenameMode) {
+        self.0.retain(|(name, _, _)| name != old);
+        self.0.push((old.to_string(), new.to_string(), mode));
+    }
+}
+
+impl Metrics {
+    /// Applies the rename map to the buffered metrics.
+    pub(crate) fn apply_renames(&mut self) {
+        if self.renames.0.is_empty() {
+            return;
+        }
+        let mut copies = Vec::new();
+        for metric in &mut self.entries {
+            let Some((_, new, mode)) = self
+                .renames
+                .0
+                .iter()
+                .find(|(old, _, _)| *old == metric.name)
+            else {
+                continue;
+            };
+            match mode {
+                RenameMode::Replace => metric.name.clone_from(new),
+                RenameMode::EmitBoth => copies.push(Metric {
+                    name: new.clone(),
+                    unit: metric.unit,
+                    values: metric.values.clone(),
+                    dimensions: metric.dimensions.clone(),
+                    storage_resolution: metric.storage_resolution,
+                    timestamp: metric.timestamp,
+                    properties: metric.properties.clone(),
+                }),
+            }
+        }
+        self.entries.extend(copies);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::RecordingSink;
+    use crate::MetricUnit;
+
+    #[test]
+    fn should_rename_metrics_at_flush() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .rename_metric("latencyMs", "latency_ms", RenameMode::EmitBoth)
+            .rename_metric("errCount", "errors", RenameMode::Replace)
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+
+        metrics.add_metric("latencyMs", MetricUnit::Milliseconds, 120.0);
+        metrics.add_metric("errCount", MetricUnit::Count, 2.0);
+        assert_eq!(metrics.value_of("errCount"), Some(2.0));
+        metrics.flush_metrics();
+
+        let payload = &sink.payloads()[0];
+        assert_eq!(payload["latencyMs"], 120.0);
+        assert_eq!(payload["latency_ms"], 120.0);
+        assert_eq!(payload["errors"], 2.0);
+        assert!(payload.get("errCount").is_none());
+    }
+}
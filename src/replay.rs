@@ -0,0 +1,162 @@
+//! Replay of EMF payloads as `PutMetricData` calls, for local end-to-end tests.
+//!
+//! [`ReplaySink`] parses each payload and sends the equivalent metric data to `CloudWatch`,
+//! usually an emulator like `LocalStack`, so dashboards and alarms can be exercised without
+//! deploying the function:
+//!
+//! ```no_run
+//! use lambda_helpers_metrics::replay::ReplaySink;
+//! use lambda_helpers_metrics::{MetricUnit, Metrics};
+//!
+//! # async fn example() {
+//! let mut metrics = Metrics::builder("custom_lambdas")
+//!     .async_sink(ReplaySink::localstack("http://localhost:4566"))
+//!     .build()
+//!     .unwrap();
+//! metrics.add_metric("orders", MetricUnit::Count, 1.0);
+//! metrics.flush_async().await;
+//! # }
+//! ```
+//!
+//! Payloads captured from logs can be replayed with [`ReplaySink::replay`]. Properties are
+//! not sent. Available with the `replay` feature.
+use aws_sdk_cloudwatch::config::{BehaviorVersion, Credentials, Region};
+use aws_sdk_cloudwatch::error::DisplayErrorContext;
+use aws_sdk_cloudwatch::primitives::DateTime;
+use aws_sdk_cloudwatch::types::{Dimension, MetricDatum, StandardUnit};
+use aws_sdk_cloudwatch::Client;
+
+use crate::sink::{AsyncMetricsSink, EmitFuture};
+use crate::{emf, MetricsError};
+
+/// The most metric data accepted by a single `PutMetricData` call.
+const MAX_DATA_PER_CALL: usize = 1000;
+
+/// Sends payloads to `CloudWatch` with `PutMetricData`, see [`crate::replay`].
+#[derive(Debug, Clone)]
+pub struct ReplaySink {
+    client: Client,
+}
+
+impl ReplaySink {
+    /// Creates a sink sending through the client, e.g. configured with the endpoint of an emulator.
+    #[must_use]
+    pub fn new(client: Client) -> Self {
+        Self { client }
+    }
+
+    /// Creates a sink for `LocalStack` on the endpoint, e.g. `http://localhost:4566`,
+    /// with its default region and test credentials.
+    #[must_use]
+    pub fn localstack(endpoint: &str) -> Self {
+        let config = aws_sdk_cloudwatch::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .endpoint_url(endpoint)
+            .region(Region::new("us-east-1"))
+            .credentials_provider(Credentials::new("test", "test", None, None, "localstack"))
+            .build();
+        Self::new(Client::from_conf(config))
+    }
+
+    /// Sends the metrics of an EMF payload, with one `PutMetricData` call per namespace.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the payload can't be parsed or a call fails
+    pub async fn replay(&self, payload: &str) -> Result<(), MetricsError> {
+        for (namespace, data) in metric_data(payload)? {
+            for chunk in data.chunks(MAX_DATA_PER_CALL) {
+                self.client
+                    .put_metric_data()
+                    .namespace(&namespace)
+                    .set_metric_data(Some(chunk.to_vec()))
+                    .send()
+                    .await
+                    .map_err(|err| {
+                        MetricsError::Io(std::io::Error::other(
+                            DisplayErrorContext(err).to_string(),
+                        ))
+                    })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl AsyncMetricsSink for ReplaySink {
+    fn emit(&self, payload: String) -> EmitFuture<'_> {
+        Box::pin(async move { self.replay(&payload).await })
+    }
+}
+
+/// Converts the metrics of a payload into metric data, grouped by namespace in order of
+/// appearance. Values which are not finite are skipped, `CloudWatch` rejects them.
+fn metric_data(payload: &str) -> Result<Vec<(String, Vec<MetricDatum>)>, MetricsError> {
+    let mut namespaces: Vec<(String, Vec<MetricDatum>)> = Vec::new();
+    for metric in emf::parse(payload)? {
+        let values: Vec<f64> = metric
+            .values
+            .into_iter()
+            .filter(|value| value.is_finite())
+            .collect();
+        if values.is_empty() {
+            continue;
+        }
+        let dimensions = metric
+            .dimensions
+            .iter()
+            .map(|(key, value)| Dimension::builder().name(key).value(value).build())
+            .collect();
+        let datum = MetricDatum::builder()
+            .metric_name(metric.name)
+            .unit(StandardUnit::from(metric.unit.as_str()))
+            .set_values(Some(values))
+            .set_dimensions(Some(dimensions))
+            .set_storage_resolution(
+                metric
+                    .storage_resolution
+                    .and_then(|resolution| i32::try_from(resolution).ok()),
+            )
+            .set_timestamp(metric.timestamp.map(DateTime::from_millis))
+            .build();
+        match namespaces
+            .iter_mut()
+            .find(|(namespace, _)| *namespace == metric.namespace)
+        {
+            Some((_, data)) => data.push(datum),
+            None => namespaces.push((metric.namespace, vec![datum])),
+        }
+    }
+    Ok(namespaces)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_convert_payload_into_metric_data() {
+        let payload = r#"{"_aws":{"Timestamp":1717243200000,"CloudWatchMetrics":[{"Namespace":"shop",
+            "Dimensions":[["service"]],"Metrics":[{"Name":"latency","Unit":"Milliseconds",
+            "StorageResolution":1},{"Name":"throughput","Unit":"Bytes/Second"}]}]},
+            "service":"orders","latency":[1.5,2.0],"throughput":10}"#;
+
+        let data = metric_data(payload).unwrap();
+
+        assert_eq!(data.len(), 1);
+        let (namespace, data) = &data[0];
+        assert_eq!(namespace, "shop");
+        assert_eq!(data[0].metric_name(), Some("latency"));
+        assert_eq!(data[0].values(), &[1.5, 2.0]);
+        assert_eq!(data[0].unit(), Some(&StandardUnit::Milliseconds));
+        assert_eq!(data[0].storage_resolution(), Some(1));
+        assert_eq!(
+            data[0].timestamp(),
+            Some(&DateTime::from_millis(1_717_243_200_000))
+        );
+        assert_eq!(data[0].dimensions()[0].name(), Some("service"));
+        assert_eq!(data[0].dimensions()[0].value(), Some("orders"));
+        assert_eq!(data[1].unit(), Some(&StandardUnit::BytesSecond));
+        assert_eq!(data[1].storage_resolution(), None);
+    }
+}
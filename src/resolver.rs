@@ -0,0 +1,318 @@
+//! Dimensions and properties resolved asynchronously at flush.
+//!
+//! Some enrichment needs I/O, e.g. the tier of a tenant stored in `DynamoDB` or a deployment ID
+//! read from SSM. An [`AsyncResolver`] wraps an `async` function returning a key and a value,
+//! which is awaited once during [`Metrics::flush_async`] and cached for its TTL,
+//! so warm invocations don't pay for the call:
+//!
+//! ```
+//! use std::time::Duration;
+//!
+//! use lambda_helpers_metrics::resolver::AsyncResolver;
+//! use lambda_helpers_metrics::runtime_info::AttachAs;
+//! use lambda_helpers_metrics::Metrics;
+//!
+//! async fn deployment_id() -> (String, String) {
+//!     // e.g. read from SSM
+//!     ("deployment_id".to_string(), "d-42".to_string())
+//! }
+//!
+//! let metrics = Metrics::builder("custom_lambdas")
+//!     .async_resolver(
+//!         AsyncResolver::new(deployment_id)
+//!             .ttl(Duration::from_secs(300))
+//!             .attach_as(AttachAs::Property),
+//!     )
+//!     .build()
+//!     .unwrap();
+//! ```
+//!
+//! Resolvers are only awaited by `flush_async`, concurrently, and [`Metrics::flush_metrics`]
+//! attaches the cached values. Resolved values are attached to the payloads of the flush only:
+//! they replace shared dimensions and properties with the same keys while serializing, which
+//! are restored afterwards.
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::{Duration, Instant};
+
+use crate::runtime_info::AttachAs;
+use crate::{mode, Dimensions, Metrics, Properties};
+
+/// How long resolved values are cached by default.
+pub const DEFAULT_RESOLVER_TTL: Duration = Duration::from_secs(60);
+
+type ResolveFuture = Pin<Box<dyn Future<Output = (String, String)> + Send>>;
+
+/// Resolves a dimension or property at flush, see [`crate::resolver`].
+pub struct AsyncResolver {
+    resolve: Arc<dyn Fn() -> ResolveFuture + Send + Sync>,
+    ttl: Duration,
+    attach: AttachAs,
+    cached: Option<((String, String), Instant)>,
+}
+
+impl AsyncResolver {
+    /// Creates a resolver attaching the value as a dimension, cached for
+    /// [`DEFAULT_RESOLVER_TTL`].
+    pub fn new<F, Fut>(resolve: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = (String, String)> + Send + 'static,
+    {
+        Self {
+            resolve: Arc::new(move || Box::pin(resolve())),
+            ttl: DEFAULT_RESOLVER_TTL,
+            attach: AttachAs::Dimension,
+            cached: None,
+        }
+    }
+
+    /// Sets how long the resolved value is reused before the function is awaited again.
+    #[must_use]
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Sets how the resolved value is attached, [`AttachAs::Off`] only resolves it.
+    #[must_use]
+    pub fn attach_as(mut self, attach: AttachAs) -> Self {
+        self.attach = attach;
+        self
+    }
+
+    fn is_fresh(&self, now: Instant) -> bool {
+        self.cached
+            .as_ref()
+            .is_some_and(|(_, resolved_at)| now.duration_since(*resolved_at) < self.ttl)
+    }
+}
+
+/// Shared dimensions and properties saved before the resolvers attach their values.
+pub(crate) struct SharedFields {
+    dimensions: Arc<Dimensions>,
+    properties: Properties,
+}
+
+impl fmt::Debug for AsyncResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AsyncResolver")
+            .field("ttl", &self.ttl)
+            .field("attach", &self.attach)
+            .field("cached", &self.cached)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Metrics {
+    /// Adds a resolver awaited by [`Metrics::flush_async`], see [`crate::resolver`].
+    pub fn add_async_resolver(&mut self, resolver: AsyncResolver) {
+        self.resolvers.push(resolver);
+    }
+
+    /// Awaits the resolvers whose cached value expired, concurrently.
+    pub(crate) async fn refresh_resolvers(&mut self) {
+        let now = self.clock.instant();
+        let stale = (0..self.resolvers.len())
+            .filter(|&index| !self.resolvers[index].is_fresh(now))
+            .collect::<Vec<_>>();
+        let futures = stale
+            .iter()
+            .map(|&index| (self.resolvers[index].resolve)())
+            .collect();
+        for (index, resolved) in stale.into_iter().zip(join_all(futures).await) {
+            self.resolvers[index].cached = Some((resolved, now));
+        }
+    }
+
+    /// Attaches the cached values of the resolvers, returning the shared fields to restore with
+    /// [`Metrics::restore_shared_fields`] after serialization, `None` if nothing was attached.
+    /// Dimensions which can't be added are reported like other errors which are not returned.
+    pub(crate) fn apply_resolvers(&mut self) -> Option<SharedFields> {
+        let resolved = self
+            .resolvers
+            .iter()
+            .filter(|resolver| resolver.attach != AttachAs::Off)
+            .filter_map(|resolver| {
+                let ((key, value), _) = resolver.cached.clone()?;
+                Some((key, value, resolver.attach))
+            })
+            .collect::<Vec<_>>();
+        if resolved.is_empty() {
+            return None;
+        }
+        let saved = SharedFields {
+            dimensions: Arc::clone(&self.dimensions),
+            properties: self.properties.clone(),
+        };
+        for (key, value, attach) in resolved {
+            match attach {
+                AttachAs::Off => {}
+                AttachAs::Property => self.add_property(&key, &value),
+                AttachAs::Dimension => {
+                    if let Err(err) = self.try_add_dimension(&key, &value) {
+                        mode::report(&err);
+                    }
+                }
+            }
+        }
+        Some(saved)
+    }
+
+    /// Restores the shared fields saved by [`Metrics::apply_resolvers`].
+    pub(crate) fn restore_shared_fields(&mut self, saved: Option<SharedFields>) {
+        if let Some(saved) = saved {
+            self.dimensions = saved.dimensions;
+            self.properties = saved.properties;
+        }
+    }
+}
+
+/// Awaits the futures concurrently, returning their outputs in order.
+async fn join_all(futures: Vec<ResolveFuture>) -> Vec<(String, String)> {
+    let mut pending = futures.into_iter().map(Some).collect::<Vec<_>>();
+    let mut outputs = vec![None; pending.len()];
+    std::future::poll_fn(|context| {
+        let mut done = true;
+        for (future, output) in pending.iter_mut().zip(&mut outputs) {
+            if let Some(polled) = future {
+                match polled.as_mut().poll(context) {
+                    Poll::Ready(resolved) => {
+                        *output = Some(resolved);
+                        *future = None;
+                    }
+                    Poll::Pending => done = false,
+                }
+            }
+        }
+        if done {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    })
+    .await;
+    outputs.into_iter().flatten().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::sink::RecordingSink;
+    use crate::MetricUnit;
+
+    fn flush_now(metrics: &mut Metrics) {
+        let mut flush = std::pin::pin!(metrics.flush_async());
+        let mut context = std::task::Context::from_waker(std::task::Waker::noop());
+        assert!(flush.as_mut().poll(&mut context).is_ready());
+    }
+
+    #[test]
+    fn should_resolve_once_per_ttl() {
+        let calls = Arc::new(AtomicU64::new(0));
+        let counted = calls.clone();
+        let clock = ManualClock::new(chrono::Utc::now());
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .sink(sink.clone())
+            .clock(clock.clone())
+            .async_resolver(
+                AsyncResolver::new(move || {
+                    let call = counted.fetch_add(1, Ordering::SeqCst) + 1;
+                    async move { ("tier".to_string(), format!("gold-{call}")) }
+                })
+                .ttl(Duration::from_secs(60)),
+            )
+            .async_resolver(
+                AsyncResolver::new(|| async { ("deployment".to_string(), "d-42".to_string()) })
+                    .attach_as(AttachAs::Property),
+            )
+            .build()
+            .unwrap();
+
+        for _ in 0..2 {
+            metrics.add_metric("orders", MetricUnit::Count, 1.0);
+            flush_now(&mut metrics);
+        }
+        clock.advance(Duration::from_secs(61));
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+        flush_now(&mut metrics);
+
+        let payloads = sink.payloads();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(payloads[0]["tier"], "gold-1");
+        assert_eq!(payloads[1]["tier"], "gold-1");
+        assert_eq!(payloads[2]["tier"], "gold-2");
+        assert_eq!(
+            payloads[0]["_aws"]["CloudWatchMetrics"][0]["Dimensions"][0],
+            serde_json::json!(["tier"])
+        );
+        assert_eq!(payloads[0]["deployment"], "d-42");
+    }
+
+    #[test]
+    fn should_await_resolvers_concurrently() {
+        let started = Arc::new(AtomicU64::new(0));
+        let resolver = |key: &'static str| {
+            let started = started.clone();
+            AsyncResolver::new(move || {
+                let started = started.clone();
+                started.fetch_add(1, Ordering::SeqCst);
+                // completes once both resolvers were started
+                std::future::poll_fn(move |_| {
+                    if started.load(Ordering::SeqCst) == 2 {
+                        Poll::Ready((key.to_string(), "resolved".to_string()))
+                    } else {
+                        Poll::Pending
+                    }
+                })
+            })
+        };
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .sink(sink.clone())
+            .async_resolver(resolver("tier"))
+            .async_resolver(resolver("region"))
+            .build()
+            .unwrap();
+
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+        let mut flush = std::pin::pin!(metrics.flush_async());
+        let mut context = std::task::Context::from_waker(std::task::Waker::noop());
+        assert!((0..2).any(|_| flush.as_mut().poll(&mut context).is_ready()));
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads[0]["tier"], "resolved");
+        assert_eq!(payloads[0]["region"], "resolved");
+    }
+
+    #[test]
+    fn should_not_keep_resolved_values_in_shared_fields() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .sink(sink.clone())
+            .dimension("tier", "default")
+            .async_resolver(AsyncResolver::new(|| async {
+                ("tier".to_string(), "gold".to_string())
+            }))
+            .async_resolver(
+                AsyncResolver::new(|| async { ("deployment".to_string(), "d-42".to_string()) })
+                    .attach_as(AttachAs::Property),
+            )
+            .build()
+            .unwrap();
+
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+        flush_now(&mut metrics);
+
+        assert_eq!(sink.payloads()[0]["tier"], "gold");
+        assert_eq!(metrics.dimension("tier"), Some("default"));
+        assert_eq!(metrics.property("deployment"), None);
+    }
+}
@@ -0,0 +1,104 @@
+//! Metrics emitted under several dimension sets.
+//!
+//! `CloudWatch` aggregates a metric only across identical dimension sets, so metrics recorded
+//! with `service`, `operation` and `tenant` can't be graphed per service alone. With rollups,
+//! each payload declares the coarser subsets as additional dimension sets, and `CloudWatch`
+//! creates the detailed and the aggregate series from the same recording:
+//!
+//! ```
+//! use lambda_helpers_metrics::{MetricUnit, Metrics};
+//!
+//! let mut metrics = Metrics::builder("custom_lambdas")
+//!     .dimension("service", "checkout")
+//!     .dimension("operation", "pay")
+//!     .dimension("tenant", "acme")
+//!     .rollups(&[&["service"], &["service", "operation"]])
+//!     .build()
+//!     .unwrap();
+//! metrics.add_metric("latency", MetricUnit::Milliseconds, 12.0);
+//! // "Dimensions": [["service", "operation", "tenant"], ["service"], ["service", "operation"]]
+//! ```
+//!
+//! A subset is only declared in the payloads which carry all its dimensions, and `&[]`
+//! aggregates across all dimensions. Each dimension set is a separate series billed as
+//! a custom metric.
+use crate::{Dimensions, Metrics};
+
+impl Metrics {
+    /// Emits the metrics under the subsets of dimensions too, see [`crate::rollup`].
+    /// Replaces the rollups set before. If the pinned [`OutputSchema`](crate::OutputSchema)
+    /// doesn't allow rollups, they are not enabled and the error is printed and passed to the
+    /// `on_error` callback.
+    pub fn enable_rollups(&mut self, rollups: &[&[&str]]) {
+        if let Some(schema) = self.output_schema.filter(|_| !rollups.is_empty()) {
+            if let Err(err) = schema.allow("rollups") {
+                self.report_error(&err);
+                return;
+            }
+        }
+        self.rollups = rollups
+            .iter()
+            .map(|set| set.iter().map(ToString::to_string).collect())
+            .collect();
+    }
+
+    /// Returns the dimension sets of a payload: all its dimensions, or the ones of the selected
+    /// [preset](crate::preset) it carries, then the rollups it carries all the dimensions of,
+    /// without duplicates.
+    pub(crate) fn dimension_sets<'a>(&'a self, dimensions: &'a Dimensions) -> Vec<Vec<&'a str>> {
+        let primary = match self.dimension_presets.keys() {
+            Some(keys) => keys
+                .iter()
+                .map(String::as_str)
+                .filter(|key| dimensions.contains_key(key))
+                .collect(),
+            None => dimensions.keys().collect::<Vec<_>>(),
+        };
+        let mut sets = vec![primary];
+        for rollup in &self.rollups {
+            let set = rollup.iter().map(String::as_str).collect::<Vec<_>>();
+            if set.iter().all(|key| dimensions.contains_key(key)) && !sets.contains(&set) {
+                sets.push(set);
+            }
+        }
+        sets
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sink::RecordingSink;
+    use crate::{MetricUnit, Metrics};
+
+    #[test]
+    fn should_declare_rollups_carried_by_the_payload() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .sink(sink.clone())
+            .dimension("service", "checkout")
+            .dimension("operation", "pay")
+            .rollups(&[&["service"], &["tenant"], &["service", "operation"], &[]])
+            .build()
+            .unwrap();
+
+        metrics.add_metric("latency", MetricUnit::Milliseconds, 12.0);
+        metrics.add_metric_with_dimensions("orders", MetricUnit::Count, 1.0, &[("tenant", "acme")]);
+        metrics.flush_metrics();
+
+        let payloads = sink.payloads();
+        assert_eq!(
+            payloads[0]["_aws"]["CloudWatchMetrics"][0]["Dimensions"],
+            serde_json::json!([["service", "operation"], ["service"], []])
+        );
+        assert_eq!(
+            payloads[1]["_aws"]["CloudWatchMetrics"][0]["Dimensions"],
+            serde_json::json!([
+                ["service", "operation", "tenant"],
+                ["service"],
+                ["tenant"],
+                ["service", "operation"],
+                []
+            ])
+        );
+    }
+}
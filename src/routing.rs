@@ -0,0 +1,212 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::sink::MetricsSink;
+use crate::{Dimensions, Metric, Metrics};
+
+type NamespaceRouter = dyn Fn(&Dimensions) -> Option<String> + Send + Sync;
+
+/// Selects the namespace of a payload at flush time.
+#[derive(Clone, Default)]
+pub(crate) struct NamespaceRouting {
+    router: Option<Arc<NamespaceRouter>>,
+    /// Metric name prefixes and their namespaces, the first matching rule wins.
+    prefixes: Vec<(String, String)>,
+}
+
+impl NamespaceRouting {
+    pub(crate) fn set_router(
+        &mut self,
+        router: impl Fn(&Dimensions) -> Option<String> + Send + Sync + 'static,
+    ) {
+        self.router = Some(Arc::new(router));
+    }
+
+    /// Adds a prefix rule. A trailing `*` is accepted, so both `db_` and `db_*` match `db_latency`.
+    pub(crate) fn add_prefix(&mut self, prefix: &str, namespace: &str) {
+        let prefix = prefix.strip_suffix('*').unwrap_or(prefix);
+        self.prefixes
+            .push((prefix.to_string(), namespace.to_string()));
+    }
+
+    /// Returns the namespace of the first prefix rule matching the metric name.
+    pub(crate) fn route_metric(&self, name: &str) -> Option<&str> {
+        self.prefixes
+            .iter()
+            .find(|(prefix, _)| name.starts_with(prefix.as_str()))
+            .map(|(_, namespace)| namespace.as_str())
+    }
+
+    /// Returns the namespace for a payload with the given dimensions,
+    /// or `None` if the default namespace should be used.
+    pub(crate) fn route(&self, dimensions: &Dimensions) -> Option<String> {
+        self.router.as_ref().and_then(|router| router(dimensions))
+    }
+}
+
+impl fmt::Debug for NamespaceRouting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NamespaceRouting")
+            .field("router", &self.router.is_some())
+            .field("prefixes", &self.prefixes)
+            .finish()
+    }
+}
+
+/// Selects the metrics written to a dedicated sink, see [`crate::MetricsBuilder::route_to_sink`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SinkRoute {
+    /// Metrics of payloads with the namespace, after namespace routing.
+    Namespace(String),
+    /// Metrics whose names start with the prefix. A trailing `*` is accepted like in
+    /// [`crate::MetricsBuilder::namespace_for_prefix`].
+    MetricPrefix(String),
+    /// Metrics whose dimensions (shared and per-metric ones merged) have the value.
+    Dimension(String, String),
+}
+
+impl SinkRoute {
+    fn matches(&self, metrics: &Metrics, metric: &Metric) -> bool {
+        match self {
+            Self::MetricPrefix(prefix) => metric
+                .name
+                .starts_with(prefix.strip_suffix('*').unwrap_or(prefix)),
+            Self::Namespace(namespace) => {
+                let dimensions = metrics.payload_dimensions(&[metric]);
+                metrics.payload_namespace(&[metric], &dimensions) == *namespace
+            }
+            Self::Dimension(key, value) => {
+                metrics.payload_dimensions(&[metric]).get(key) == Some(value.as_str())
+            }
+        }
+    }
+}
+
+/// The sink routes, the first matching route wins.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SinkRoutes(Vec<(SinkRoute, Arc<dyn MetricsSink>)>);
+
+impl SinkRoutes {
+    pub(crate) fn add(&mut self, route: SinkRoute, sink: Arc<dyn MetricsSink>) {
+        self.0.push((route, sink));
+    }
+
+    pub(crate) fn sink(&self, index: usize) -> &dyn MetricsSink {
+        self.0[index].1.as_ref()
+    }
+}
+
+impl Metrics {
+    /// Returns the index of the sink route of the metric, or `None` for the default sink.
+    pub(crate) fn sink_route(&self, metric: &Metric) -> Option<usize> {
+        self.sink_routes
+            .0
+            .iter()
+            .position(|(route, _)| route.matches(self, metric))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::RecordingSink;
+
+    fn namespaces(payloads: &[serde_json::Value]) -> Vec<&str> {
+        payloads
+            .iter()
+            .map(|payload| {
+                payload["_aws"]["CloudWatchMetrics"][0]["Namespace"]
+                    .as_str()
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn should_match_first_prefix_rule() {
+        let mut routing = NamespaceRouting::default();
+        routing.add_prefix("db_*", "app/database");
+        routing.add_prefix("db_cache_", "app/cache");
+
+        assert_eq!(routing.route_metric("db_cache_hits"), Some("app/database"));
+        assert_eq!(routing.route_metric("http_requests"), None);
+    }
+
+    #[test]
+    fn should_split_payloads_by_prefix_namespace() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("app")
+            .namespace_for_prefix("db_", "app/database")
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+
+        metrics.increment("db_queries", 1.0);
+        metrics.increment("requests", 1.0);
+        metrics.increment("db_errors", 1.0);
+        metrics.flush_metrics();
+
+        let payloads = sink.payloads();
+        assert_eq!(namespaces(&payloads), ["app/database", "app"]);
+        assert_eq!(payloads[0]["db_queries"], 1.0);
+        assert_eq!(payloads[0]["db_errors"], 1.0);
+        assert!(payloads[0].get("requests").is_none());
+    }
+
+    #[test]
+    fn should_write_routed_metrics_to_their_sinks() {
+        let default = RecordingSink::default();
+        let security = RecordingSink::default();
+        let tenants = RecordingSink::default();
+        let mut metrics = Metrics::builder("app")
+            .namespace_for_prefix("audit_", "app/audit")
+            .route_to_sink(
+                SinkRoute::MetricPrefix("security_*".into()),
+                security.clone(),
+            )
+            .route_to_sink(SinkRoute::Namespace("app/audit".into()), security.clone())
+            .route_to_sink(
+                SinkRoute::Dimension("tenant".into(), "acme".into()),
+                tenants.clone(),
+            )
+            .sink(default.clone())
+            .build()
+            .unwrap();
+
+        metrics.increment("requests", 1.0);
+        metrics.increment("security_denied", 1.0);
+        metrics.increment("audit_events", 1.0);
+        metrics.for_tenant("acme").increment("orders", 1.0);
+        metrics.flush_metrics();
+
+        let default = default.payloads();
+        assert_eq!(default.len(), 1);
+        assert_eq!(default[0]["requests"], 1.0);
+        assert!(default[0].get("security_denied").is_none());
+        let security = security.payloads();
+        assert_eq!(security.len(), 2);
+        assert_eq!(security[0]["security_denied"], 1.0);
+        assert_eq!(security[1]["audit_events"], 1.0);
+        assert_eq!(tenants.payloads()[0]["orders"], 1.0);
+    }
+
+    #[test]
+    fn should_route_payloads_by_dimensions() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("default")
+            .namespace_router(|dimensions| {
+                dimensions
+                    .get("tenant")
+                    .map(|tenant| format!("tenants/{tenant}"))
+            })
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+
+        metrics.for_tenant("acme").increment("orders", 1.0);
+        metrics.increment("orders", 1.0);
+        metrics.flush_metrics();
+
+        assert_eq!(namespaces(&sink.payloads()), ["tenants/acme", "default"]);
+    }
+}
@@ -0,0 +1,212 @@
+//! Facts about the execution environment attached to every payload.
+//!
+//! Each fact can be attached as a property, searchable in `CloudWatch Logs Insights`,
+//! or as a dimension, to segment metrics by it:
+//!
+//! ```
+//! use lambda_helpers_metrics::runtime_info::AttachAs;
+//! use lambda_helpers_metrics::Metrics;
+//!
+//! let metrics = Metrics::builder("custom_lambdas")
+//!     .initialization_type(AttachAs::Dimension)
+//!     .build()
+//!     .unwrap();
+//! ```
+//!
+//! - `initialization_type`: `on-demand`, `provisioned-concurrency` or `snap-start`, from
+//!   `AWS_LAMBDA_INITIALIZATION_TYPE`. Attached as a property by default.
+//! - `architecture`: `arm64` or `x86_64`, the architecture the binary was compiled for,
+//!   to compare Graviton and x86 functions. Not attached by default.
+//! - `region`: from `AWS_REGION`. Not attached by default.
+//!
+//! The region and the account ID, taken from the function ARN of the invocation context
+//! (with the `lambda` feature), can also be added as properties for cross-account
+//! aggregation pipelines:
+//!
+//! ```ignore
+//! metrics.add_region();
+//! metrics.add_account_id(&event.context);
+//! ```
+use crate::cold_start::INITIALIZATION_TYPE_VAR;
+use crate::{Metrics, MetricsError};
+
+pub const INITIALIZATION_TYPE_KEY: &str = "initialization_type";
+pub const ARCHITECTURE_KEY: &str = "architecture";
+pub const REGION_KEY: &str = "region";
+pub const ACCOUNT_ID_KEY: &str = "account_id";
+
+/// How a fact about the execution environment is attached to the payloads.
+/// There is no default: each fact has its own, see the methods of
+/// [`MetricsBuilder`](crate::MetricsBuilder) setting them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttachAs {
+    /// The fact is not attached.
+    Off,
+    /// The fact is attached as a property.
+    Property,
+    /// The fact is attached as a dimension.
+    Dimension,
+}
+
+/// Returns the initialization type of the execution environment, if known.
+#[must_use]
+pub fn initialization_type() -> Option<String> {
+    initialization_type_from(|key| std::env::var(key).ok())
+}
+
+pub(crate) fn initialization_type_from(var: impl Fn(&str) -> Option<String>) -> Option<String> {
+    var(INITIALIZATION_TYPE_VAR)
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+/// Returns the CPU architecture, named like the Lambda architectures (`arm64` or `x86_64`).
+#[must_use]
+pub fn architecture() -> &'static str {
+    architecture_name(std::env::consts::ARCH)
+}
+
+fn architecture_name(arch: &'static str) -> &'static str {
+    match arch {
+        "aarch64" => "arm64",
+        arch => arch,
+    }
+}
+
+/// Returns the region from `AWS_REGION`, if set.
+#[must_use]
+pub fn region() -> Option<String> {
+    region_from(|key| std::env::var(key).ok())
+}
+
+pub(crate) fn region_from(var: impl Fn(&str) -> Option<String>) -> Option<String> {
+    var("AWS_REGION")
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+/// Returns the account ID from an ARN, e.g. `arn:aws:lambda:eu-west-1:123456789012:function:orders`.
+#[must_use]
+pub fn account_id_from_arn(arn: &str) -> Option<&str> {
+    let mut parts = arn.split(':');
+    if parts.next() != Some("arn") {
+        return None;
+    }
+    parts.nth(3).filter(|account| !account.is_empty())
+}
+
+/// Returns the account ID from the function ARN of the invocation context.
+///
+/// Available with the `lambda` feature.
+#[cfg(feature = "lambda")]
+#[must_use]
+pub fn account_id(context: &lambda_runtime::Context) -> Option<&str> {
+    account_id_from_arn(&context.invoked_function_arn)
+}
+
+impl Metrics {
+    /// Adds the region from `AWS_REGION` as the `region` property, if set.
+    pub fn add_region(&mut self) {
+        if let Some(region) = region() {
+            self.add_property(REGION_KEY, &region);
+        }
+    }
+
+    /// Adds the account ID from the function ARN of the invocation context
+    /// as the `account_id` property, if found.
+    ///
+    /// Available with the `lambda` feature.
+    #[cfg(feature = "lambda")]
+    pub fn add_account_id(&mut self, context: &lambda_runtime::Context) {
+        if let Some(account) = account_id(context) {
+            self.add_property(ACCOUNT_ID_KEY, account);
+        }
+    }
+
+    /// Attaches `value` under `key` as configured, see [`AttachAs`].
+    pub(crate) fn attach(
+        &mut self,
+        attach: AttachAs,
+        key: &str,
+        value: &str,
+    ) -> Result<(), MetricsError> {
+        match attach {
+            AttachAs::Off => {}
+            AttachAs::Property => self.add_property(key, value),
+            AttachAs::Dimension => self.try_add_dimension(key, value)?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::environment::tests::vars;
+
+    #[test]
+    fn should_read_initialization_type() {
+        assert_eq!(
+            initialization_type_from(vars(&[(
+                INITIALIZATION_TYPE_VAR,
+                "provisioned-concurrency"
+            )])),
+            Some("provisioned-concurrency".to_string())
+        );
+        assert_eq!(
+            initialization_type_from(vars(&[(INITIALIZATION_TYPE_VAR, "")])),
+            None
+        );
+        assert_eq!(initialization_type_from(vars(&[])), None);
+    }
+
+    #[test]
+    fn should_name_architectures_like_lambda() {
+        assert_eq!(architecture_name("aarch64"), "arm64");
+        assert_eq!(architecture_name("x86_64"), "x86_64");
+        assert!(!architecture().is_empty());
+    }
+
+    #[test]
+    fn should_read_region_and_account_id() {
+        assert_eq!(
+            region_from(vars(&[("AWS_REGION", "eu-west-1")])),
+            Some("eu-west-1".to_string())
+        );
+        assert_eq!(region_from(vars(&[])), None);
+        assert_eq!(
+            account_id_from_arn("arn:aws:lambda:eu-west-1:123456789012:function:orders:prod"),
+            Some("123456789012")
+        );
+        assert_eq!(account_id_from_arn("arn:aws:s3:::bucket"), None);
+        assert_eq!(account_id_from_arn("orders"), None);
+    }
+
+    #[cfg(feature = "lambda")]
+    #[test]
+    fn should_add_account_id_property() {
+        let mut metrics = Metrics::new("test", "service", "dummy_service");
+        let mut context = lambda_runtime::Context::default();
+        context.invoked_function_arn =
+            "arn:aws:lambda:eu-west-1:123456789012:function:orders".to_string();
+
+        metrics.add_account_id(&context);
+
+        assert_eq!(metrics.property(ACCOUNT_ID_KEY), Some("123456789012"));
+    }
+
+    #[test]
+    fn should_attach_as_property_or_dimension() {
+        let mut metrics = Metrics::new("test", "service", "dummy_service");
+
+        metrics.attach(AttachAs::Off, "off", "1").unwrap();
+        metrics.attach(AttachAs::Property, "property", "2").unwrap();
+        metrics
+            .attach(AttachAs::Dimension, "dimension", "3")
+            .unwrap();
+
+        assert!(metrics.property("off").is_none());
+        assert_eq!(metrics.property("property"), Some("2"));
+        assert_eq!(metrics.dimension("dimension"), Some("3"));
+    }
+}
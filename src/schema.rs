@@ -0,0 +1,230 @@
+//! Metric schema declared up front.
+//!
+//! The names, units and storage resolutions of all metrics are declared and validated once,
+//! e.g. at cold start, and each declaration returns a [`MetricHandle`]. During invocations
+//! values are recorded against handles, so a metric is always emitted with the same
+//! definition and nothing is validated in the hot path.
+//!
+//! ```
+//! use lambda_helpers_metrics::{MetricSchema, MetricUnit, Metrics};
+//!
+//! let mut schema = MetricSchema::new();
+//! let orders = schema.register("orders", MetricUnit::Count).unwrap();
+//! let latency = schema
+//!     .register_with_resolution("latency", MetricUnit::Milliseconds, 1)
+//!     .unwrap();
+//!
+//! // in the handler
+//! let mut metrics = Metrics::builder("custom_lambdas")
+//!     .schema(&schema)
+//!     .build()
+//!     .unwrap();
+//! metrics.record(orders, 3.0);
+//! metrics.record(latency, 12.5);
+//! ```
+//!
+//! Declarations can be documented with [`MetricSchema::describe`] and
+//! [`MetricSchema::set_owner`], which ends up in [`Metrics::catalog`].
+use std::sync::Arc;
+
+use crate::catalog::codegen::CatalogMetric;
+use crate::{Metric, MetricUnit, Metrics, MetricsError};
+
+/// Identifies a metric declared in a [`MetricSchema`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MetricHandle(usize);
+
+#[derive(Debug, Clone, PartialEq)]
+struct SchemaEntry {
+    name: String,
+    unit: MetricUnit,
+    storage_resolution: Option<u64>,
+    description: Option<String>,
+    owner: Option<String>,
+}
+
+/// Declared metrics, shared by all `Metrics` objects built with it.
+/// Cloning is cheap, the declarations are reference counted.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MetricSchema(Arc<Vec<SchemaEntry>>);
+
+impl MetricSchema {
+    /// Creates an empty schema.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declares a metric with the storage resolution of the `Metrics` object.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the name is empty or already declared
+    pub fn register(&mut self, name: &str, unit: MetricUnit) -> Result<MetricHandle, MetricsError> {
+        self.declare(name, unit, None)
+    }
+
+    /// Declares a metric with its own storage resolution: 1 for high resolution, 60 for standard.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the name is empty or already declared, or the resolution is invalid
+    pub fn register_with_resolution(
+        &mut self,
+        name: &str,
+        unit: MetricUnit,
+        storage_resolution: u64,
+    ) -> Result<MetricHandle, MetricsError> {
+        if !matches!(storage_resolution, 1 | 60) {
+            return Err(MetricsError::InvalidMetric(format!(
+                "invalid storage resolution of {name}: {storage_resolution}, expected 1 or 60"
+            )));
+        }
+        self.declare(name, unit, Some(storage_resolution))
+    }
+
+    fn declare(
+        &mut self,
+        name: &str,
+        unit: MetricUnit,
+        storage_resolution: Option<u64>,
+    ) -> Result<MetricHandle, MetricsError> {
+        if name.is_empty() {
+            return Err(MetricsError::InvalidMetric(
+                "metric name is empty".to_string(),
+            ));
+        }
+        if self.handle(name).is_some() {
+            return Err(MetricsError::InvalidMetric(format!(
+                "metric {name} is already declared"
+            )));
+        }
+        let entries = Arc::make_mut(&mut self.0);
+        entries.push(SchemaEntry {
+            name: name.to_string(),
+            unit,
+            storage_resolution,
+            description: None,
+            owner: None,
+        });
+        Ok(MetricHandle(entries.len() - 1))
+    }
+
+    /// Sets the description of a declared metric, shown in [`Metrics::catalog`].
+    /// A handle which is not part of the schema is ignored.
+    pub fn describe(&mut self, handle: MetricHandle, description: &str) {
+        if let Some(entry) = Arc::make_mut(&mut self.0).get_mut(handle.0) {
+            entry.description = Some(description.to_string());
+        }
+    }
+
+    /// Sets the owner of a declared metric, shown in [`Metrics::catalog`].
+    /// A handle which is not part of the schema is ignored.
+    pub fn set_owner(&mut self, handle: MetricHandle, owner: &str) {
+        if let Some(entry) = Arc::make_mut(&mut self.0).get_mut(handle.0) {
+            entry.owner = Some(owner.to_string());
+        }
+    }
+
+    /// Returns the declarations as entries of a catalog.
+    pub(crate) fn catalog_metrics(&self) -> impl Iterator<Item = CatalogMetric> + '_ {
+        self.0.iter().map(|entry| CatalogMetric {
+            name: entry.name.clone(),
+            unit: entry.unit,
+            description: entry.description.clone(),
+            owner: entry.owner.clone(),
+            dimensions: Vec::new(),
+        })
+    }
+
+    /// Returns the handle of the metric with the given name, if declared.
+    #[must_use]
+    pub fn handle(&self, name: &str) -> Option<MetricHandle> {
+        self.0
+            .iter()
+            .position(|entry| entry.name == name)
+            .map(MetricHandle)
+    }
+
+    /// Returns the number of declared metrics.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns `true` if no metrics are declared.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Metrics {
+    /// Records a value of a metric declared in the schema set with
+    /// [`crate::MetricsBuilder::schema`]. Follows the same flushing rules as `add_metric`.
+    /// A handle which is not part of the schema is dropped and the error printed to stderr.
+    pub fn record(&mut self, handle: MetricHandle, value: f64) {
+        let Some(entry) = self.schema.0.get(handle.0) else {
+            self.record_dropped(&MetricsError::InvalidMetric(format!(
+                "unknown metric handle {}",
+                handle.0
+            )));
+            return;
+        };
+        let metric = Metric {
+            storage_resolution: entry.storage_resolution,
+            ..self.new_metric(&entry.name, entry.unit, value)
+        };
+        self.push_or_drop(metric);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::RecordingSink;
+
+    #[test]
+    fn should_validate_declarations() {
+        let mut schema = MetricSchema::new();
+
+        let orders = schema.register("orders", MetricUnit::Count).unwrap();
+
+        assert_eq!(schema.handle("orders"), Some(orders));
+        assert!(schema.register("orders", MetricUnit::Count).is_err());
+        assert!(schema.register("", MetricUnit::Count).is_err());
+        assert!(schema
+            .register_with_resolution("latency", MetricUnit::Milliseconds, 5)
+            .is_err());
+        assert_eq!(schema.len(), 1);
+    }
+
+    #[test]
+    fn should_record_by_handle_with_declared_definition() {
+        let mut schema = MetricSchema::new();
+        let orders = schema.register("orders", MetricUnit::Count).unwrap();
+        let latency = schema
+            .register_with_resolution("latency", MetricUnit::Milliseconds, 1)
+            .unwrap();
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .schema(&schema)
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+
+        metrics.record(orders, 3.0);
+        metrics.record(latency, 12.5);
+        metrics.record(MetricHandle(5), 1.0);
+        metrics.flush_metrics();
+
+        let payload = &sink.payloads()[0];
+        let definitions = &payload["_aws"]["CloudWatchMetrics"][0]["Metrics"];
+        assert_eq!(definitions[0]["Unit"], "Count");
+        assert_eq!(definitions[0]["StorageResolution"], 60);
+        assert_eq!(definitions[1]["Unit"], "Milliseconds");
+        assert_eq!(definitions[1]["StorageResolution"], 1);
+        assert_eq!(payload["latency"], 12.5);
+        assert!(payload.get(crate::self_metrics::DROPPED_METRIC).is_some());
+    }
+}
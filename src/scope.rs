@@ -0,0 +1,117 @@
+//! Temporary dimensions scoped to a block.
+//!
+//! ```
+//! use lambda_helpers_metrics::{MetricUnit, Metrics};
+//!
+//! let mut metrics = Metrics::new("custom_lambdas", "service", "dummy_service");
+//! {
+//!     let mut checkout = metrics.push_dimension("operation", "checkout").unwrap();
+//!     checkout.add_metric("latency", MetricUnit::Milliseconds, 120.0);
+//! }
+//! // recorded without the `operation` dimension
+//! metrics.add_metric("requests", MetricUnit::Count, 1.0);
+//! ```
+use std::ops::{Deref, DerefMut};
+
+use crate::{Metrics, MetricsError};
+
+/// Guard returned by [`Metrics::push_dimension`], removing the dimension when dropped.
+/// Metrics are recorded through the guard, which dereferences to [`Metrics`].
+#[derive(Debug)]
+pub struct DimensionGuard<'a> {
+    metrics: &'a mut Metrics,
+    key: String,
+    previous: Option<String>,
+}
+
+impl Deref for DimensionGuard<'_> {
+    type Target = Metrics;
+
+    fn deref(&self) -> &Metrics {
+        self.metrics
+    }
+}
+
+impl DerefMut for DimensionGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Metrics {
+        self.metrics
+    }
+}
+
+impl Drop for DimensionGuard<'_> {
+    fn drop(&mut self) {
+        match &self.previous {
+            Some(previous) => self.metrics.scoped_dimensions.insert(&self.key, previous),
+            None => {
+                self.metrics.scoped_dimensions.remove(&self.key);
+            }
+        }
+    }
+}
+
+impl Metrics {
+    /// Adds a dimension to the metrics recorded until the returned guard is dropped.
+    /// Unlike shared dimensions, it is attached to each metric recorded in the scope,
+    /// so it doesn't leak into metrics recorded after the block, even if they are flushed together.
+    /// Pushing a key which is already scoped overrides it until the guard is dropped.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the limit of 30 dimensions would be exceeded
+    pub fn push_dimension(
+        &mut self,
+        key: &str,
+        value: &str,
+    ) -> Result<DimensionGuard<'_>, MetricsError> {
+        let previous = self.scoped_dimensions.get(key).map(str::to_string);
+        if previous.is_none()
+            && self.dimensions.len() + self.scoped_dimensions.len() >= self.limits.max_dimensions
+        {
+            return Err(MetricsError::TooManyDimensions);
+        }
+        self.scoped_dimensions.insert(key, value);
+        Ok(DimensionGuard {
+            metrics: self,
+            key: key.to_string(),
+            previous,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sink::RecordingSink;
+    use crate::{MetricUnit, Metrics};
+
+    #[test]
+    fn should_remove_dimension_when_guard_is_dropped() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .dimension("service", "orders")
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+
+        {
+            let mut checkout = metrics.push_dimension("operation", "checkout").unwrap();
+            checkout.add_metric("latency", MetricUnit::Milliseconds, 120.0);
+            {
+                let mut nested = checkout.push_dimension("operation", "payment").unwrap();
+                nested.increment("calls", 1.0);
+            }
+            checkout.increment("calls", 1.0);
+        }
+        metrics.increment("requests", 1.0);
+        metrics.flush_metrics();
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads.len(), 3);
+        assert_eq!(payloads[0]["operation"], "checkout");
+        assert_eq!(payloads[0]["latency"], 120.0);
+        assert_eq!(payloads[0]["calls"], 1.0);
+        assert_eq!(payloads[1]["operation"], "payment");
+        assert!(payloads[2].get("operation").is_none());
+        assert_eq!(payloads[2]["service"], "orders");
+        assert_eq!(metrics.dimensions_len(), 1);
+    }
+}
@@ -0,0 +1,263 @@
+//! Dimensions and properties extracted from the incoming event with JSONPath selectors.
+//!
+//! Selectors set on the builder, or in the configuration, are applied to the event by
+//! [`Metrics::extract_event_fields`], or by `handler::with_event_fields` with the `lambda`
+//! feature, so handlers don't need extraction code:
+//!
+//! ```
+//! use lambda_helpers_metrics::Metrics;
+//! use serde_json::json;
+//!
+//! let mut metrics = Metrics::builder("custom_lambdas")
+//!     .event_dimension("$.detail.orderType", "order_type")
+//!     .event_property("$.detail.items[0].sku", "first_sku")
+//!     .build()
+//!     .unwrap();
+//! let event = json!({ "detail": { "orderType": "express", "items": [{ "sku": "A-1" }] } });
+//! metrics.extract_event_fields(&event);
+//! ```
+//!
+//! Selectors are a subset of JSONPath: `$` followed by `.name`, `['name']` or `[index]`
+//! segments. Strings, numbers and booleans become dimensions, while properties can hold any
+//! JSON value. Fields missing from the event are skipped.
+//!
+//! Each distinct value of a dimension creates a series, so once [`MAX_DIMENSION_VALUES`]
+//! values were selected into a dimension by a `Metrics` object, the following ones are
+//! recorded as [`OTHER_DIMENSION_VALUE`]. Values with unbounded cardinality, e.g. an order ID,
+//! belong in properties.
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{mode, Metrics, MetricsError};
+
+/// Maximum number of distinct values selected into each dimension by a `Metrics` object, the
+/// following ones are recorded as [`OTHER_DIMENSION_VALUE`].
+pub const MAX_DIMENSION_VALUES: usize = 50;
+/// Dimension value of the values beyond [`MAX_DIMENSION_VALUES`].
+pub const OTHER_DIMENSION_VALUE: &str = "other";
+
+/// A segment of a selector.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+/// A parsed JSONPath selector, see [`crate::selectors`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Selector(Vec<Segment>);
+
+impl Selector {
+    pub(crate) fn parse(selector: &str) -> Result<Self, MetricsError> {
+        let invalid = || MetricsError::Configuration(format!("invalid selector: {selector}"));
+        let mut rest = selector.strip_prefix('$').ok_or_else(invalid)?;
+        let mut segments = Vec::new();
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix('.') {
+                let end = after.find(['.', '[']).unwrap_or(after.len());
+                let key = &after[..end];
+                if key.is_empty() || key == "*" {
+                    return Err(invalid());
+                }
+                segments.push(Segment::Key(key.to_string()));
+                rest = &after[end..];
+            } else if let Some(after) = rest.strip_prefix('[') {
+                let end = after.find(']').ok_or_else(invalid)?;
+                let inner = &after[..end];
+                let quoted = inner
+                    .strip_prefix('\'')
+                    .and_then(|inner| inner.strip_suffix('\''))
+                    .or_else(|| {
+                        inner
+                            .strip_prefix('"')
+                            .and_then(|inner| inner.strip_suffix('"'))
+                    });
+                let segment = match quoted {
+                    Some(key) => Segment::Key(key.to_string()),
+                    None => Segment::Index(inner.parse().map_err(|_| invalid())?),
+                };
+                segments.push(segment);
+                rest = &after[end + 1..];
+            } else {
+                return Err(invalid());
+            }
+        }
+        Ok(Self(segments))
+    }
+
+    fn select<'a>(&self, value: &'a Value) -> Option<&'a Value> {
+        self.0
+            .iter()
+            .try_fold(value, |value, segment| match segment {
+                Segment::Key(key) => value.get(key),
+                Segment::Index(index) => value.get(index),
+            })
+    }
+}
+
+/// Whether a selected value becomes a dimension or a property.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Target {
+    Dimension,
+    Property,
+}
+
+/// A selector and the name of the field it populates.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct EventField {
+    pub(crate) selector: Selector,
+    pub(crate) name: String,
+    pub(crate) target: Target,
+    /// Distinct values selected into the dimension, up to [`MAX_DIMENSION_VALUES`].
+    pub(crate) values: Vec<String>,
+}
+
+impl EventField {
+    /// Returns the value to record, [`OTHER_DIMENSION_VALUE`] for new values beyond the cap.
+    fn cap(&mut self, value: String) -> String {
+        if self.values.contains(&value) {
+            value
+        } else if self.values.len() < MAX_DIMENSION_VALUES {
+            self.values.push(value.clone());
+            value
+        } else {
+            OTHER_DIMENSION_VALUE.to_string()
+        }
+    }
+}
+
+/// Returns the value of a dimension, `None` for objects, arrays and `null`.
+fn dimension_value(value: &Value) -> Option<String> {
+    match value {
+        Value::String(value) => Some(value.clone()),
+        Value::Number(_) | Value::Bool(_) => Some(value.to_string()),
+        Value::Null | Value::Array(_) | Value::Object(_) => None,
+    }
+}
+
+impl Metrics {
+    /// Adds the dimensions and properties selected from the event, see [`crate::selectors`].
+    /// Errors, e.g. an event which can't be serialized or too many dimensions, are reported
+    /// like other errors which are not returned. Dimension values are capped, see
+    /// [`MAX_DIMENSION_VALUES`].
+    pub fn extract_event_fields<T: Serialize + ?Sized>(&mut self, event: &T) {
+        if self.event_fields.is_empty() {
+            return;
+        }
+        let event = match serde_json::to_value(event) {
+            Ok(event) => event,
+            Err(err) => {
+                mode::report(&MetricsError::Serialization(err.to_string()));
+                return;
+            }
+        };
+        let mut fields = std::mem::take(&mut self.event_fields);
+        for field in &mut fields {
+            let Some(value) = field.selector.select(&event) else {
+                continue;
+            };
+            match field.target {
+                Target::Dimension => {
+                    if let Some(value) = dimension_value(value) {
+                        let value = field.cap(value);
+                        if let Err(err) = self.try_add_dimension(&field.name, &value) {
+                            mode::report(&err);
+                        }
+                    }
+                }
+                Target::Property => self.add_json_property(&field.name, value.clone()),
+            }
+        }
+        self.event_fields = fields;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::RecordingSink;
+    use crate::MetricUnit;
+
+    #[test]
+    fn should_parse_selectors() {
+        assert_eq!(
+            Selector::parse("$.detail['order-type'][2].id").unwrap(),
+            Selector(vec![
+                Segment::Key("detail".to_string()),
+                Segment::Key("order-type".to_string()),
+                Segment::Index(2),
+                Segment::Key("id".to_string()),
+            ])
+        );
+        assert_eq!(Selector::parse("$").unwrap(), Selector(Vec::new()));
+        for invalid in ["detail", "$..detail", "$.*", "$[x]", "$.a[0", "$a"] {
+            assert!(Selector::parse(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn should_extract_dimensions_and_properties_from_event() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .event_dimension("$.detail.orderType", "order_type")
+            .event_dimension("$.detail.priority", "priority")
+            .event_dimension("$.detail.items", "items")
+            .event_dimension("$.detail.missing", "missing")
+            .event_property("$.detail.items[0]", "first_item")
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+        let event = serde_json::json!({
+            "detail": { "orderType": "express", "priority": 2, "items": [{ "sku": "A-1" }] }
+        });
+
+        metrics.extract_event_fields(&event);
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+        metrics.flush_metrics();
+
+        let payload = &sink.payloads()[0];
+        assert_eq!(payload["order_type"], "express");
+        assert_eq!(payload["priority"], "2");
+        assert_eq!(payload["first_item"], serde_json::json!({ "sku": "A-1" }));
+        assert_eq!(
+            payload["_aws"]["CloudWatchMetrics"][0]["Dimensions"][0],
+            serde_json::json!(["order_type", "priority"])
+        );
+    }
+
+    #[test]
+    fn should_cap_distinct_dimension_values() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .event_dimension("$.orderId", "order_id")
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+
+        for id in 0..=MAX_DIMENSION_VALUES {
+            metrics.extract_event_fields(&serde_json::json!({ "orderId": id }));
+            metrics.add_metric("orders", MetricUnit::Count, 1.0);
+            metrics.flush_metrics();
+        }
+        metrics.extract_event_fields(&serde_json::json!({ "orderId": 0 }));
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+        metrics.flush_metrics();
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads[MAX_DIMENSION_VALUES - 1]["order_id"], "49");
+        assert_eq!(
+            payloads[MAX_DIMENSION_VALUES]["order_id"],
+            OTHER_DIMENSION_VALUE
+        );
+        assert_eq!(payloads[MAX_DIMENSION_VALUES + 1]["order_id"], "0");
+    }
+
+    #[test]
+    fn should_reject_invalid_selector_in_build() {
+        let result = Metrics::builder("test")
+            .event_dimension("detail.orderType", "order_type")
+            .build();
+
+        assert!(matches!(result, Err(MetricsError::Configuration(_))));
+    }
+}
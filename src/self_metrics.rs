@@ -0,0 +1,429 @@
+//! Metrics about the library itself.
+//!
+//! Errors of the library are printed to stderr and never returned from `add_metric` or
+//! `flush_metrics`, so lost data would go unnoticed. Each `Metrics` object counts metrics
+//! it dropped and emissions which failed, and adds the counts to the next flush as
+//! [`DROPPED_METRIC`] and [`ERRORS_METRIC`], along with the payloads its sink dropped as
+//! [`SINK_DROPPED_METRIC`]. Counts of a flush which fails again are kept for the following one.
+//!
+//! With [`MetricsBuilder::overhead_metrics`](crate::MetricsBuilder::overhead_metrics), the cost
+//! of the flushes is recorded as well: [`PAYLOAD_BYTES_METRIC`], [`SERIALIZATION_TIME_METRIC`]
+//! and [`FLUSHES_METRIC`]. A payload can't measure itself, so the size and serialization time of
+//! a flush are emitted with the next flush which has metrics to emit.
+use std::time::Duration;
+
+use crate::catalog::codegen::CatalogMetric;
+use crate::{mode, Dimensions, FlushedPayload, Metric, MetricUnit, Metrics, MetricsError};
+
+/// Number of metrics dropped by the library: rejected by a limit, or part of a payload
+/// which couldn't be serialized or written.
+pub const DROPPED_METRIC: &str = "MetricsLibraryDropped";
+/// Number of payloads which couldn't be serialized or written to the sink.
+pub const ERRORS_METRIC: &str = "MetricsLibraryErrors";
+/// Number of payloads the sink accepted but dropped, e.g. evicted from the queue of a
+/// [`BackgroundSink`](crate::sink::BackgroundSink), see [`MetricsSink::take_dropped`](crate::MetricsSink::take_dropped).
+pub const SINK_DROPPED_METRIC: &str = "MetricsLibrarySinkDropped";
+/// Size in bytes of the payloads serialized by the previous flush.
+pub const PAYLOAD_BYTES_METRIC: &str = "MetricsLibraryPayloadBytes";
+/// Time spent serializing the payloads of the previous flush, in microseconds.
+pub const SERIALIZATION_TIME_METRIC: &str = "MetricsLibrarySerializationTime";
+/// Number of the flush within the invocation, starting at 1 after `Metrics::attach_context`
+/// (with the `lambda` feature). Its maximum is the number of flushes per invocation.
+pub const FLUSHES_METRIC: &str = "MetricsLibraryFlushes";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(crate) struct LibraryStats {
+    dropped: u64,
+    errors: u64,
+    sink_dropped: u64,
+}
+
+/// The cost of the flushes, see [`MetricsBuilder::overhead_metrics`](crate::MetricsBuilder::overhead_metrics).
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(crate) struct Overhead {
+    /// Measured by the previous flush, `None` once emitted.
+    measured: Option<(usize, Duration)>,
+    flushes: u64,
+}
+
+/// The metrics of the library with their units and descriptions, see [`Metrics::catalog`].
+pub(crate) fn catalog_metrics(overhead: bool) -> Vec<CatalogMetric> {
+    let mut metrics = vec![
+        (
+            DROPPED_METRIC,
+            MetricUnit::Count,
+            "Metrics dropped by the library.",
+        ),
+        (
+            ERRORS_METRIC,
+            MetricUnit::Count,
+            "Payloads which couldn't be serialized or written.",
+        ),
+        (
+            SINK_DROPPED_METRIC,
+            MetricUnit::Count,
+            "Payloads the sink accepted but dropped.",
+        ),
+    ];
+    if overhead {
+        metrics.extend([
+            (
+                PAYLOAD_BYTES_METRIC,
+                MetricUnit::Bytes,
+                "Size of the payloads of the previous flush.",
+            ),
+            (
+                SERIALIZATION_TIME_METRIC,
+                MetricUnit::Microseconds,
+                "Time spent serializing the payloads of the previous flush.",
+            ),
+            (
+                FLUSHES_METRIC,
+                MetricUnit::Count,
+                "Number of the flush within the invocation.",
+            ),
+        ]);
+    }
+    metrics
+        .into_iter()
+        .map(|(name, unit, description)| CatalogMetric::library(name, unit, description))
+        .collect()
+}
+
+impl Metrics {
+    /// Records a metric rejected before it was buffered.
+    pub(crate) fn record_dropped(&mut self, err: &MetricsError) {
+        mode::report(err);
+        self.library_stats.dropped += 1;
+    }
+
+    /// Reports an error which is not returned, passing it to the `on_error` callback too.
+    pub(crate) fn report_error(&self, err: &MetricsError) {
+        mode::report(err);
+        if let Some(callback) = &self.on_error {
+            (callback.0)(err);
+        }
+    }
+
+    /// Records a payload of `metrics` metrics which couldn't be emitted.
+    /// The error is returned by `try_flush_metrics`, so it's only printed and passed
+    /// to the `on_error` callback here.
+    pub(crate) fn record_failed(&mut self, err: &MetricsError, metrics: usize) {
+        eprintln!("{err}");
+        if let Some(callback) = &self.on_error {
+            (callback.0)(err);
+        }
+        self.library_stats.errors += 1;
+        self.library_stats.dropped += metrics as u64;
+    }
+
+    /// Adds a metric of the library to the buffer. Metrics of the library don't carry scoped or
+    /// per-metric dimensions, and skip the limits and callbacks of [`Metrics::add_metric`].
+    pub(crate) fn push_internal_metric(
+        &mut self,
+        name: impl Into<String>,
+        unit: MetricUnit,
+        value: f64,
+    ) {
+        self.entries.push(Metric {
+            name: name.into(),
+            unit,
+            values: vec![value],
+            dimensions: Dimensions::default(),
+            storage_resolution: None,
+            timestamp: None,
+            properties: Vec::new(),
+        });
+    }
+
+    /// Whether statistics measured at flush, e.g. CPU time or allocations, are added to the
+    /// buffer. They aren't added to an empty buffer, so they alone never cause a flush.
+    pub(crate) fn measures_at_flush(&self) -> bool {
+        !self.entries.is_empty()
+    }
+
+    /// Moves the counts into the buffer, to be emitted with the other metrics.
+    pub(crate) fn buffer_library_metrics(&mut self) {
+        let mut stats = std::mem::take(&mut self.library_stats);
+        stats.sink_dropped += self.sink.take_dropped();
+        #[cfg(feature = "async")]
+        if let Some(sink) = &self.async_sink {
+            stats.sink_dropped += sink.take_dropped();
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let counts = [
+            (DROPPED_METRIC, stats.dropped as f64),
+            (ERRORS_METRIC, stats.errors as f64),
+            (SINK_DROPPED_METRIC, stats.sink_dropped as f64),
+        ];
+        for (name, count) in counts {
+            if count == 0.0 {
+                continue;
+            }
+            self.push_internal_metric(name, MetricUnit::Count, count);
+        }
+    }
+
+    /// Adds the cost of the previous flush and the number of this one to the buffer, if enabled,
+    /// see [`Metrics::measures_at_flush`].
+    pub(crate) fn buffer_overhead_metrics(&mut self) {
+        if !self.measures_at_flush() {
+            return;
+        }
+        let Some(overhead) = &mut self.overhead else {
+            return;
+        };
+        overhead.flushes += 1;
+        #[allow(clippy::cast_precision_loss)]
+        let mut values = vec![(FLUSHES_METRIC, MetricUnit::Count, overhead.flushes as f64)];
+        if let Some((bytes, duration)) = overhead.measured.take() {
+            #[allow(clippy::cast_precision_loss)]
+            values.extend([
+                (PAYLOAD_BYTES_METRIC, MetricUnit::Bytes, bytes as f64),
+                (
+                    SERIALIZATION_TIME_METRIC,
+                    MetricUnit::Microseconds,
+                    duration.as_secs_f64() * 1_000_000.0,
+                ),
+            ]);
+        }
+        for (name, unit, value) in values {
+            self.push_internal_metric(name, unit, value);
+        }
+    }
+
+    /// Remembers the cost of the payloads serialized by this flush, if enabled.
+    pub(crate) fn record_serialization(&mut self, payloads: &[FlushedPayload], duration: Duration) {
+        let Some(overhead) = &mut self.overhead else {
+            return;
+        };
+        if payloads.is_empty() {
+            return;
+        }
+        let bytes = payloads.iter().map(FlushedPayload::len).sum();
+        overhead.measured = Some((bytes, duration));
+    }
+
+    /// Restarts the count of flushes at the start of an invocation.
+    #[cfg(feature = "lambda")]
+    pub(crate) fn reset_flush_count(&mut self) {
+        if let Some(overhead) = &mut self.overhead {
+            overhead.flushes = 0;
+        }
+    }
+
+    /// Returns the counts of a failed payload which included metrics of the library,
+    /// so they are emitted with the next flush.
+    pub(crate) fn restore_library_metrics(&mut self, counts: &[(String, f64)]) {
+        for (name, count) in counts {
+            // the counts are whole numbers
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            let count = *count as u64;
+            match name.as_str() {
+                DROPPED_METRIC => self.library_stats.dropped += count,
+                ERRORS_METRIC => self.library_stats.errors += count,
+                SINK_DROPPED_METRIC => self.library_stats.sink_dropped += count,
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Returns `true` for the metrics added by [`Metrics::buffer_library_metrics`].
+pub(crate) fn is_library_metric(metric: &Metric) -> bool {
+    metric.dimensions.is_empty()
+        && matches!(
+            metric.name.as_str(),
+            DROPPED_METRIC | ERRORS_METRIC | SINK_DROPPED_METRIC
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sink::{MetricsSink, RecordingSink};
+    use crate::{MetricOverflowPolicy, MetricUnit, Metrics, MetricsError};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    use super::{
+        DROPPED_METRIC, ERRORS_METRIC, FLUSHES_METRIC, PAYLOAD_BYTES_METRIC,
+        SERIALIZATION_TIME_METRIC, SINK_DROPPED_METRIC,
+    };
+
+    #[derive(Debug, Default, Clone)]
+    struct FlakySink {
+        failing: Arc<AtomicBool>,
+        recording: RecordingSink,
+    }
+
+    impl MetricsSink for FlakySink {
+        fn emit(&self, payload: &str) -> Result<(), MetricsError> {
+            if self.failing.load(Ordering::SeqCst) {
+                Err(MetricsError::Io(std::io::Error::other("unavailable")))
+            } else {
+                MetricsSink::emit(&self.recording, payload)
+            }
+        }
+    }
+
+    /// Reports two dropped payloads once.
+    #[derive(Debug, Default, Clone)]
+    struct EvictingSink {
+        reported: Arc<AtomicBool>,
+        recording: RecordingSink,
+    }
+
+    impl MetricsSink for EvictingSink {
+        fn emit(&self, payload: &str) -> Result<(), MetricsError> {
+            MetricsSink::emit(&self.recording, payload)
+        }
+
+        fn take_dropped(&self) -> u64 {
+            if self.reported.swap(true, Ordering::SeqCst) {
+                0
+            } else {
+                2
+            }
+        }
+    }
+
+    #[test]
+    fn should_emit_payloads_dropped_by_sink() {
+        let sink = EvictingSink::default();
+        let mut metrics = Metrics::builder("test").sink(sink.clone()).build().unwrap();
+
+        metrics.add_metric("requests", MetricUnit::Count, 1.0);
+        metrics.flush_metrics();
+        metrics.add_metric("requests", MetricUnit::Count, 1.0);
+        metrics.flush_metrics();
+
+        let payloads = sink.recording.payloads();
+        assert_eq!(payloads[0][SINK_DROPPED_METRIC], 2.0);
+        assert!(payloads[1].get(SINK_DROPPED_METRIC).is_none());
+    }
+
+    #[test]
+    fn should_emit_dropped_metrics_with_flush() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .sink(sink.clone())
+            .metric_overflow(MetricOverflowPolicy::Error)
+            .build()
+            .unwrap();
+
+        for i in 0..102 {
+            metrics.add_metric(&format!("metric_{i}"), MetricUnit::Count, 1.0);
+        }
+        metrics.flush_metrics();
+        metrics.add_metric("requests", MetricUnit::Count, 1.0);
+        metrics.flush_metrics();
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads.len(), 3);
+        assert_eq!(payloads[1][DROPPED_METRIC], 2.0);
+        assert!(payloads[1].get(ERRORS_METRIC).is_none());
+        assert!(payloads[2].get(DROPPED_METRIC).is_none());
+    }
+
+    #[test]
+    fn should_emit_failed_emissions_after_sink_recovers() {
+        let sink = FlakySink::default();
+        let mut metrics = Metrics::builder("test").sink(sink.clone()).build().unwrap();
+
+        sink.failing.store(true, Ordering::SeqCst);
+        metrics.add_metric("requests", MetricUnit::Count, 1.0);
+        metrics.add_metric("errors", MetricUnit::Count, 1.0);
+        metrics.flush_metrics();
+        metrics.add_metric("requests", MetricUnit::Count, 1.0);
+        metrics.flush_metrics();
+        sink.failing.store(false, Ordering::SeqCst);
+        metrics.flush_metrics();
+
+        let payloads = sink.recording.payloads();
+        assert_eq!(payloads.len(), 1);
+        assert_eq!(payloads[0][DROPPED_METRIC], 3.0);
+        assert_eq!(payloads[0][ERRORS_METRIC], 2.0);
+        assert!(payloads[0].get("requests").is_none());
+    }
+
+    #[test]
+    fn should_return_flush_errors() {
+        let sink = FlakySink::default();
+        let mut metrics = Metrics::builder("test").sink(sink.clone()).build().unwrap();
+
+        sink.failing.store(true, Ordering::SeqCst);
+        metrics.add_metric("requests", MetricUnit::Count, 1.0);
+        assert!(matches!(
+            metrics.try_flush_metrics(),
+            Err(MetricsError::Io(_))
+        ));
+        assert!(metrics.is_empty());
+
+        sink.failing.store(false, Ordering::SeqCst);
+        metrics.try_flush_metrics().unwrap();
+        assert_eq!(sink.recording.payloads()[0][ERRORS_METRIC], 1.0);
+    }
+
+    #[test]
+    fn should_call_error_callback_for_each_failed_payload() {
+        let sink = FlakySink::default();
+        let errors = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = errors.clone();
+        let mut metrics = Metrics::builder("test")
+            .sink(sink.clone())
+            .on_error(move |err| recorded.lock().unwrap().push(err.to_string()))
+            .build()
+            .unwrap();
+
+        metrics.add_metric("requests", MetricUnit::Count, 1.0);
+        metrics.flush_metrics();
+        assert!(errors.lock().unwrap().is_empty());
+
+        sink.failing.store(true, Ordering::SeqCst);
+        metrics.add_metric("requests", MetricUnit::Count, 1.0);
+        metrics.add_metric_with_dimensions("requests", MetricUnit::Count, 1.0, &[("a", "b")]);
+        metrics.flush_metrics();
+        assert_eq!(
+            *errors.lock().unwrap(),
+            vec!["Error when writing metrics: unavailable"; 2]
+        );
+    }
+
+    #[test]
+    fn should_not_emit_library_metrics_without_failures() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test").sink(sink.clone()).build().unwrap();
+
+        metrics.add_metric("requests", MetricUnit::Count, 1.0);
+        metrics.flush_metrics();
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads.len(), 1);
+        assert!(payloads[0].get(DROPPED_METRIC).is_none());
+    }
+
+    #[test]
+    fn should_emit_overhead_of_previous_flush() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .overhead_metrics(true)
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+
+        metrics.add_metric("requests", MetricUnit::Count, 1.0);
+        metrics.flush_metrics();
+        metrics.flush_metrics();
+        metrics.add_metric("requests", MetricUnit::Count, 1.0);
+        metrics.flush_metrics();
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads.len(), 2);
+        assert_eq!(payloads[0][FLUSHES_METRIC], 1.0);
+        assert!(payloads[0].get(PAYLOAD_BYTES_METRIC).is_none());
+        assert_eq!(payloads[1][FLUSHES_METRIC], 2.0);
+        let first_len = serde_json::to_string(&payloads[0]).unwrap().len();
+        assert_eq!(payloads[1][PAYLOAD_BYTES_METRIC], first_len as f64);
+        assert!(payloads[1][SERIALIZATION_TIME_METRIC].as_f64().unwrap() >= 0.0);
+    }
+}
@@ -0,0 +1,169 @@
+//! Flushing long-lived metrics when the execution environment shuts down.
+//!
+//! Metrics kept across invocations (the [current context](crate::context), or handles
+//! owned by statics) are lost when the execution environment is shut down with metrics
+//! still buffered. Handles registered here, and the current context, are flushed by
+//! [`flush_pending`]. With the `async` feature, async sinks buffering payloads, e.g. an
+//! `ArchiveSink`, are registered with [`register_sink`] and flushed by
+//! [`flush_pending_async`].
+//!
+//! Lambda sends `SIGTERM` to the runtime before shutdown only if an extension is registered.
+//! With the `graceful-shutdown` feature, [`flush_on_shutdown`] registers a no-op internal
+//! extension and flushes the pending metrics, and the registered sinks, on `SIGTERM`/`SIGINT`:
+//!
+//! ```ignore
+//! #[tokio::main]
+//! async fn main() -> Result<(), Error> {
+//!     let handle = MetricsHandle::new(Metrics::new("custom_lambdas", "service", "dummy_service"));
+//!     shutdown::register(handle.clone());
+//!     shutdown::flush_on_shutdown().await;
+//!     lambda_runtime::run(service_fn(handler)).await
+//! }
+//! ```
+#[cfg(feature = "async")]
+use std::sync::Arc;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use crate::context::{self, MetricsHandle};
+#[cfg(feature = "async")]
+use crate::{mode, sink::EmitFuture};
+
+/// Flushes an async sink, see [`register_sink`].
+#[cfg(feature = "async")]
+type SinkFlush = Arc<dyn Fn() -> EmitFuture<'static> + Send + Sync>;
+
+/// What is flushed at shutdown. The functions of the module use the process-wide [`PENDING`].
+struct Registry {
+    handles: Mutex<Vec<MetricsHandle>>,
+    #[cfg(feature = "async")]
+    sinks: Mutex<Vec<SinkFlush>>,
+}
+
+static PENDING: Registry = Registry::new();
+
+impl Registry {
+    const fn new() -> Self {
+        Self {
+            handles: Mutex::new(Vec::new()),
+            #[cfg(feature = "async")]
+            sinks: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn handles(&self) -> MutexGuard<'_, Vec<MetricsHandle>> {
+        self.handles.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn flush_handles(&self) {
+        let handles = self.handles().clone();
+        for handle in handles {
+            handle.flush();
+        }
+    }
+
+    #[cfg(feature = "async")]
+    fn sinks(&self) -> MutexGuard<'_, Vec<SinkFlush>> {
+        self.sinks.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    #[cfg(feature = "async")]
+    async fn flush_sinks(&self) {
+        let flushes = self.sinks().clone();
+        for flush in flushes {
+            if let Err(err) = flush().await {
+                mode::report(&err);
+            }
+        }
+    }
+}
+
+/// Registers a handle to be flushed by [`flush_pending`].
+pub fn register(handle: MetricsHandle) {
+    PENDING.handles().push(handle);
+}
+
+/// Flushes the registered handles and the current context.
+pub fn flush_pending() {
+    PENDING.flush_handles();
+    if let Some(handle) = context::current() {
+        handle.flush();
+    }
+}
+
+/// Registers the flush of an async sink buffering payloads, to be awaited by
+/// [`flush_pending_async`]. Available with the `async` feature.
+#[cfg(feature = "async")]
+pub fn register_sink(flush: impl Fn() -> EmitFuture<'static> + Send + Sync + 'static) {
+    PENDING.sinks().push(Arc::new(flush));
+}
+
+/// Flushes the registered handles and the current context like [`flush_pending`], then the
+/// registered sinks. Errors of the sinks are reported, not returned.
+/// Available with the `async` feature.
+#[cfg(feature = "async")]
+pub async fn flush_pending_async() {
+    flush_pending();
+    PENDING.flush_sinks().await;
+}
+
+/// Registers a no-op internal extension, so Lambda sends `SIGTERM` before shutting down
+/// the execution environment, and calls [`flush_pending`] when it is received, or
+/// [`flush_pending_async`] with the `async` feature.
+/// Must be called before `lambda_runtime::run`, in a tokio runtime.
+///
+/// # Panics
+///
+/// Panics if the extension can't be registered, see `lambda_runtime::spawn_graceful_shutdown_handler`
+#[cfg(all(unix, feature = "graceful-shutdown"))]
+pub async fn flush_on_shutdown() {
+    #[cfg(feature = "async")]
+    lambda_runtime::spawn_graceful_shutdown_handler(flush_pending_async).await;
+    #[cfg(not(feature = "async"))]
+    lambda_runtime::spawn_graceful_shutdown_handler(|| async { flush_pending() }).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::RecordingSink;
+    use crate::{MetricUnit, Metrics};
+
+    #[test]
+    fn should_flush_registered_handles() {
+        let registry = Registry::new();
+        let sink = RecordingSink::default();
+        let handle =
+            MetricsHandle::new(Metrics::builder("test").sink(sink.clone()).build().unwrap());
+        registry.handles().push(handle.clone());
+
+        handle.with(|metrics| metrics.add_metric("orders", MetricUnit::Count, 1.0));
+        // not the process-wide registry nor the current context, they belong to other tests
+        registry.flush_handles();
+
+        assert_eq!(sink.payloads()[0]["orders"], 1.0);
+        assert!(handle.with(|metrics| metrics.is_empty()));
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn should_flush_registered_sinks() {
+        use std::future::Future;
+        use std::pin::pin;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::task::{Context, Waker};
+
+        let registry = Registry::new();
+        let flushed = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&flushed);
+        registry.sinks().push(Arc::new(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Box::pin(std::future::ready(Ok(())))
+        }));
+
+        let _ = pin!(registry.flush_sinks())
+            .as_mut()
+            .poll(&mut Context::from_waker(Waker::noop()));
+
+        assert_eq!(flushed.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,928 @@
+//! Destinations for serialized EMF payloads.
+//!
+//! By default the sink is chosen from the detected [`Environment`](crate::Environment):
+//! - Lambda: [`StdoutSink`], the Lambda log pipeline extracts metrics from stdout
+//! - ECS: [`AgentSink`], payloads are sent to the `CloudWatch` agent
+//! - local development: [`PrettySink`], human readable output
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+#[cfg(feature = "async")]
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::io::Write;
+use std::net::{TcpStream, UdpSocket};
+use std::path::PathBuf;
+#[cfg(feature = "async")]
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::clock::{Clock, SystemClock};
+use crate::MetricsError;
+
+#[cfg(feature = "background")]
+mod background;
+
+#[cfg(feature = "background")]
+pub use background::{BackgroundSink, DEFAULT_BLOCK_TIMEOUT};
+
+/// Default endpoint of the `CloudWatch` agent listening for EMF payloads.
+pub const DEFAULT_AGENT_ENDPOINT: &str = "tcp://127.0.0.1:25888";
+
+/// Environment variable overriding the `CloudWatch` agent endpoint.
+pub const AGENT_ENDPOINT_ENV: &str = "AWS_EMF_AGENT_ENDPOINT";
+
+/// A destination for serialized EMF payloads.
+///
+/// Implementations receive one complete JSON document per call, without a trailing newline.
+pub trait MetricsSink: fmt::Debug + Send + Sync {
+    /// Writes a single serialized payload.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the payload could not be delivered.
+    fn emit(&self, payload: &str) -> Result<(), MetricsError>;
+
+    /// Writes a structured log line which is not an EMF payload, e.g. the lines of
+    /// [`crate::contributors`]. Only sinks writing to logs keep them, the default discards them.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the line could not be delivered.
+    fn emit_log_line(&self, line: &str) -> Result<(), MetricsError> {
+        let _ = line;
+        Ok(())
+    }
+
+    /// Returns the number of payloads the sink accepted but dropped since the last call,
+    /// e.g. queued payloads evicted by [`BackgroundSink`]. Counted by the library as
+    /// [`SINK_DROPPED_METRIC`](crate::self_metrics::SINK_DROPPED_METRIC).
+    fn take_dropped(&self) -> u64 {
+        0
+    }
+}
+
+/// The future returned by [`AsyncMetricsSink::emit`].
+#[cfg(feature = "async")]
+pub type EmitFuture<'a> = Pin<Box<dyn Future<Output = Result<(), MetricsError>> + Send + 'a>>;
+
+/// A destination for serialized EMF payloads which is written asynchronously, e.g. a remote
+/// service. Used by [`Metrics::flush_async`](crate::Metrics::flush_async). Available with the
+/// `async` feature.
+#[cfg(feature = "async")]
+pub trait AsyncMetricsSink: fmt::Debug + Send + Sync {
+    /// Writes a single serialized payload, without a trailing newline.
+    fn emit(&self, payload: String) -> EmitFuture<'_>;
+
+    /// Writes a structured log line which is not an EMF payload, see
+    /// [`MetricsSink::emit_log_line`]. The default discards it.
+    fn emit_log_line(&self, line: String) -> EmitFuture<'_> {
+        drop(line);
+        Box::pin(std::future::ready(Ok(())))
+    }
+
+    /// Returns the number of payloads the sink accepted but dropped since the last call, see
+    /// [`MetricsSink::take_dropped`].
+    fn take_dropped(&self) -> u64 {
+        0
+    }
+}
+
+/// Writes each payload as a single line to stdout. This is what Lambda expects.
+///
+/// Each line is written with a single write while holding the stdout lock, so payloads
+/// flushed concurrently by several `Metrics` objects or tasks never interleave.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StdoutSink;
+
+impl MetricsSink for StdoutSink {
+    fn emit(&self, payload: &str) -> Result<(), MetricsError> {
+        write_line(&mut std::io::stdout().lock(), payload)
+    }
+
+    fn emit_log_line(&self, line: &str) -> Result<(), MetricsError> {
+        self.emit(line)
+    }
+}
+
+/// Writes the payload and the newline in a single write, and flushes,
+/// so the line reaches the output in one piece.
+pub(crate) fn write_line(out: &mut impl Write, payload: &str) -> Result<(), MetricsError> {
+    let mut line = Vec::with_capacity(payload.len() + 1);
+    line.extend_from_slice(payload.as_bytes());
+    line.push(b'\n');
+    out.write_all(&line)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Pretty-prints each payload to stdout, for local development.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PrettySink;
+
+impl MetricsSink for PrettySink {
+    fn emit(&self, payload: &str) -> Result<(), MetricsError> {
+        let value: serde_json::Value = serde_json::from_str(payload)
+            .map_err(|err| MetricsError::Serialization(err.to_string()))?;
+        let pretty = serde_json::to_string_pretty(&value)
+            .map_err(|err| MetricsError::Serialization(err.to_string()))?;
+        write_line(&mut std::io::stdout().lock(), &pretty)
+    }
+
+    fn emit_log_line(&self, line: &str) -> Result<(), MetricsError> {
+        self.emit(line)
+    }
+}
+
+/// Discards all payloads, used when metrics are disabled.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NullSink;
+
+impl MetricsSink for NullSink {
+    fn emit(&self, _payload: &str) -> Result<(), MetricsError> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum AgentEndpoint {
+    Tcp(String),
+    Udp(String),
+}
+
+/// Sends payloads to the `CloudWatch` agent, e.g. a sidecar in ECS.
+///
+/// The TCP connection is opened lazily and re-established after a failed write.
+#[derive(Debug)]
+pub struct AgentSink {
+    endpoint: AgentEndpoint,
+    stream: Mutex<Option<TcpStream>>,
+}
+
+impl AgentSink {
+    /// Creates a sink for an endpoint like `tcp://127.0.0.1:25888` or `udp://127.0.0.1:25888`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the endpoint has no `tcp://` or `udp://` scheme.
+    pub fn new(endpoint: &str) -> Result<Self, MetricsError> {
+        let endpoint = if let Some(address) = endpoint.strip_prefix("tcp://") {
+            AgentEndpoint::Tcp(address.to_string())
+        } else if let Some(address) = endpoint.strip_prefix("udp://") {
+            AgentEndpoint::Udp(address.to_string())
+        } else {
+            return Err(MetricsError::Configuration(format!(
+                "unsupported agent endpoint: {endpoint}"
+            )));
+        };
+        Ok(Self {
+            endpoint,
+            stream: Mutex::new(None),
+        })
+    }
+
+    /// Creates a sink for the endpoint from `AWS_EMF_AGENT_ENDPOINT`,
+    /// falling back to [`DEFAULT_AGENT_ENDPOINT`] if it is missing or malformed.
+    #[allow(clippy::missing_panics_doc)]
+    #[must_use]
+    pub fn from_env() -> Self {
+        std::env::var(AGENT_ENDPOINT_ENV)
+            .ok()
+            .and_then(|endpoint| Self::new(&endpoint).ok())
+            // UNWRAP: the default endpoint is always valid
+            .unwrap_or_else(|| Self::new(DEFAULT_AGENT_ENDPOINT).unwrap())
+    }
+}
+
+impl MetricsSink for AgentSink {
+    fn emit(&self, payload: &str) -> Result<(), MetricsError> {
+        match &self.endpoint {
+            AgentEndpoint::Tcp(address) => {
+                let mut stream = self
+                    .stream
+                    .lock()
+                    .unwrap_or_else(std::sync::PoisonError::into_inner);
+                if stream.is_none() {
+                    *stream = Some(TcpStream::connect(address)?);
+                }
+                if let Some(connection) = stream.as_mut() {
+                    if let Err(err) = write_line(connection, payload) {
+                        *stream = None;
+                        return Err(err);
+                    }
+                }
+                Ok(())
+            }
+            AgentEndpoint::Udp(address) => {
+                let socket = UdpSocket::bind("0.0.0.0:0")?;
+                socket.send_to(format!("{payload}\n").as_bytes(), address)?;
+                Ok(())
+            }
+        }
+    }
+
+    fn emit_log_line(&self, line: &str) -> Result<(), MetricsError> {
+        self.emit(line)
+    }
+}
+
+/// Appends each payload as a line to a local file, e.g. on an EFS mount collected by another
+/// process. The file is created if missing and opened lazily.
+///
+/// With the `gzip` feature, payloads can be compressed with [`FileSink::gzip`]. Each payload
+/// is then written as its own gzip member, the file stays a valid gzip stream which
+/// `gunzip` or `zcat` decompress as a whole.
+///
+/// ```no_run
+/// use lambda_helpers_metrics::sink::FileSink;
+/// use lambda_helpers_metrics::Metrics;
+///
+/// let metrics = Metrics::builder("custom_lambdas")
+///     .sink(FileSink::new("/mnt/metrics/orders.ndjson"))
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Debug)]
+pub struct FileSink {
+    path: PathBuf,
+    file: Mutex<Option<File>>,
+    #[cfg(feature = "gzip")]
+    gzip_level: Option<u32>,
+}
+
+impl FileSink {
+    /// Creates a sink appending to the file at the path.
+    #[must_use]
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            file: Mutex::new(None),
+            #[cfg(feature = "gzip")]
+            gzip_level: None,
+        }
+    }
+
+    /// Compresses each payload with gzip at the level, from 0 (none) to 9 (best).
+    /// Available with the `gzip` feature.
+    #[cfg(feature = "gzip")]
+    #[must_use]
+    pub fn gzip(mut self, level: u32) -> Self {
+        self.gzip_level = Some(level);
+        self
+    }
+}
+
+impl MetricsSink for FileSink {
+    fn emit(&self, payload: &str) -> Result<(), MetricsError> {
+        let mut file = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+        if file.is_none() {
+            *file = Some(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)?,
+            );
+        }
+        let Some(out) = file.as_mut() else {
+            return Ok(());
+        };
+        #[cfg(feature = "gzip")]
+        if let Some(level) = self.gzip_level {
+            let line = format!("{payload}\n");
+            out.write_all(&crate::compression::gzip(line.as_bytes(), level)?)?;
+            return Ok(out.flush()?);
+        }
+        write_line(out, payload)
+    }
+
+    fn emit_log_line(&self, line: &str) -> Result<(), MetricsError> {
+        self.emit(line)
+    }
+}
+
+/// Retries a failing sink with jittered exponential backoff, for remote sinks which fail
+/// transiently. The wait before retry `n` is random, up to `base_delay * 2^(n - 1)` capped at
+/// `max_delay`. Once the attempts are exhausted the last error is returned, so the flush
+/// reports it to the `on_error` callback and counts it as `MetricsLibraryErrors`.
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use lambda_helpers_metrics::sink::{AgentSink, RetrySink};
+///
+/// let sink = RetrySink::new(AgentSink::from_env())
+///     .max_attempts(4)
+///     .base_delay(Duration::from_millis(20));
+/// ```
+#[derive(Debug, Clone)]
+pub struct RetrySink<S> {
+    sink: S,
+    max_attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+}
+
+impl<S: MetricsSink> RetrySink<S> {
+    /// Wraps `sink` with 3 attempts, waiting up to 50ms before the first retry and up to 1s
+    /// before any retry.
+    #[must_use]
+    pub fn new(sink: S) -> Self {
+        Self {
+            sink,
+            max_attempts: 3,
+            base_delay: Duration::from_millis(50),
+            max_delay: Duration::from_secs(1),
+        }
+    }
+
+    /// Sets the number of attempts including the first one, at least one.
+    #[must_use]
+    pub fn max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = attempts.max(1);
+        self
+    }
+
+    /// Sets the longest wait before the first retry, doubled for each following retry.
+    #[must_use]
+    pub fn base_delay(mut self, delay: Duration) -> Self {
+        self.base_delay = delay;
+        self
+    }
+
+    /// Sets the longest wait before any retry.
+    #[must_use]
+    pub fn max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+
+    /// Returns the wait before the given retry, starting at 1.
+    fn backoff(&self, retry: u32) -> Duration {
+        let ceiling = self
+            .base_delay
+            .saturating_mul(1 << (retry - 1).min(16))
+            .min(self.max_delay);
+        // full jitter: retries of concurrent invocations don't hit the destination together
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u32(retry);
+        #[allow(clippy::cast_precision_loss)]
+        let fraction = hasher.finish() as f64 / u64::MAX as f64;
+        ceiling.mul_f64(fraction)
+    }
+}
+
+impl<S: MetricsSink> RetrySink<S> {
+    fn emit_with(&self, emit: impl Fn(&S) -> Result<(), MetricsError>) -> Result<(), MetricsError> {
+        let mut attempt = 1;
+        loop {
+            match emit(&self.sink) {
+                Ok(()) => return Ok(()),
+                Err(err) if attempt >= self.max_attempts => return Err(err),
+                Err(err) => {
+                    eprintln!("{err}, retrying");
+                    std::thread::sleep(self.backoff(attempt));
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+impl<S: MetricsSink> MetricsSink for RetrySink<S> {
+    fn emit(&self, payload: &str) -> Result<(), MetricsError> {
+        self.emit_with(|sink| sink.emit(payload))
+    }
+
+    fn emit_log_line(&self, line: &str) -> Result<(), MetricsError> {
+        self.emit_with(|sink| sink.emit_log_line(line))
+    }
+
+    fn take_dropped(&self) -> u64 {
+        self.sink.take_dropped()
+    }
+}
+
+#[derive(Debug, Default)]
+struct Breaker {
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+}
+
+/// Stops writing to a failing remote sink for a while, so an unavailable destination doesn't
+/// add latency to every invocation. After `failure_threshold` consecutive failures the circuit
+/// opens and payloads go to the fallback sink, [`StdoutSink`] by default. Once the cooldown
+/// has passed, a single payload probes the remote sink: the circuit closes if it succeeds and
+/// opens again otherwise.
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use lambda_helpers_metrics::sink::{AgentSink, CircuitBreakerSink};
+///
+/// let sink = CircuitBreakerSink::new(AgentSink::from_env())
+///     .failure_threshold(3)
+///     .cooldown(Duration::from_secs(60));
+/// ```
+#[derive(Debug)]
+pub struct CircuitBreakerSink<S> {
+    sink: S,
+    fallback: Box<dyn MetricsSink>,
+    failure_threshold: u32,
+    cooldown: Duration,
+    clock: Arc<dyn Clock>,
+    breaker: Mutex<Breaker>,
+}
+
+impl<S: MetricsSink> CircuitBreakerSink<S> {
+    /// Wraps `sink`, opening after 5 consecutive failures for 30 seconds.
+    #[must_use]
+    pub fn new(sink: S) -> Self {
+        Self {
+            sink,
+            fallback: Box::new(StdoutSink),
+            failure_threshold: 5,
+            cooldown: Duration::from_secs(30),
+            clock: Arc::new(SystemClock),
+            breaker: Mutex::default(),
+        }
+    }
+
+    /// Sets the sink receiving the payloads while the circuit is open.
+    #[must_use]
+    pub fn fallback(mut self, sink: impl MetricsSink + 'static) -> Self {
+        self.fallback = Box::new(sink);
+        self
+    }
+
+    /// Sets the number of consecutive failures opening the circuit, at least one.
+    #[must_use]
+    pub fn failure_threshold(mut self, failures: u32) -> Self {
+        self.failure_threshold = failures.max(1);
+        self
+    }
+
+    /// Sets how long the circuit stays open before the remote sink is probed.
+    #[must_use]
+    pub fn cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    /// Replaces the clock measuring the cooldown, see [`crate::clock`].
+    #[must_use]
+    pub fn clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Returns `true` if payloads currently go to the fallback sink.
+    #[must_use]
+    pub fn is_open(&self) -> bool {
+        self.breaker
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .opened_at
+            .is_some()
+    }
+}
+
+impl<S: MetricsSink> CircuitBreakerSink<S> {
+    fn emit_with(
+        &self,
+        emit: impl Fn(&dyn MetricsSink) -> Result<(), MetricsError>,
+    ) -> Result<(), MetricsError> {
+        let now = self.clock.instant();
+        let probing = {
+            let mut breaker = self.breaker.lock().unwrap_or_else(PoisonError::into_inner);
+            match breaker.opened_at {
+                Some(opened_at) if now.duration_since(opened_at) < self.cooldown => {
+                    drop(breaker);
+                    return emit(self.fallback.as_ref());
+                }
+                Some(_) => {
+                    // other payloads keep going to the fallback during the probe
+                    breaker.opened_at = Some(now);
+                    true
+                }
+                None => false,
+            }
+        };
+        let result = emit(&self.sink);
+        let mut breaker = self.breaker.lock().unwrap_or_else(PoisonError::into_inner);
+        match result {
+            Ok(()) => {
+                *breaker = Breaker::default();
+                Ok(())
+            }
+            Err(err) => {
+                breaker.consecutive_failures += 1;
+                if !probing && breaker.consecutive_failures < self.failure_threshold {
+                    return Err(err);
+                }
+                breaker.opened_at = Some(self.clock.instant());
+                drop(breaker);
+                eprintln!("{err}, writing to the fallback sink");
+                emit(self.fallback.as_ref())
+            }
+        }
+    }
+}
+
+impl<S: MetricsSink> MetricsSink for CircuitBreakerSink<S> {
+    fn emit(&self, payload: &str) -> Result<(), MetricsError> {
+        self.emit_with(|sink| sink.emit(payload))
+    }
+
+    fn emit_log_line(&self, line: &str) -> Result<(), MetricsError> {
+        self.emit_with(|sink| sink.emit_log_line(line))
+    }
+
+    fn take_dropped(&self) -> u64 {
+        self.sink.take_dropped() + self.fallback.take_dropped()
+    }
+}
+
+/// Writes every payload to several sinks, e.g. stdout EMF plus an archive, so a migration
+/// between destinations can run both at once.
+///
+/// Each sink is attempted even if another one fails. Failures of the sinks added with
+/// [`TeeSink::required`] fail the emission, like a single sink would. Sinks added with
+/// [`TeeSink::also`] are best-effort: their failures are printed to stderr and counted as
+/// `MetricsLibrarySinkDropped`, without the metrics being reported as lost.
+///
+/// ```
+/// use lambda_helpers_metrics::sink::{AgentSink, StdoutSink, TeeSink};
+/// use lambda_helpers_metrics::Metrics;
+///
+/// let metrics = Metrics::builder("custom_lambdas")
+///     .sink(TeeSink::new(StdoutSink).also(AgentSink::from_env()))
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Debug)]
+pub struct TeeSink {
+    /// The sinks, with whether their failures fail the emission.
+    sinks: Vec<(Box<dyn MetricsSink>, bool)>,
+    failed: AtomicU64,
+}
+
+impl TeeSink {
+    /// Creates a tee writing to a required sink.
+    #[must_use]
+    pub fn new(sink: impl MetricsSink + 'static) -> Self {
+        Self {
+            sinks: Vec::new(),
+            failed: AtomicU64::new(0),
+        }
+        .required(sink)
+    }
+
+    /// Adds a sink whose failures fail the emission.
+    #[must_use]
+    pub fn required(mut self, sink: impl MetricsSink + 'static) -> Self {
+        self.sinks.push((Box::new(sink), true));
+        self
+    }
+
+    /// Adds a best-effort sink whose failures are only printed and counted.
+    #[must_use]
+    pub fn also(mut self, sink: impl MetricsSink + 'static) -> Self {
+        self.sinks.push((Box::new(sink), false));
+        self
+    }
+}
+
+impl TeeSink {
+    fn emit_with(
+        &self,
+        emit: impl Fn(&dyn MetricsSink) -> Result<(), MetricsError>,
+    ) -> Result<(), MetricsError> {
+        let mut first_error = None;
+        for (sink, required) in &self.sinks {
+            match emit(sink.as_ref()) {
+                Ok(()) => {}
+                Err(err) if *required => {
+                    first_error.get_or_insert(err);
+                }
+                Err(err) => {
+                    eprintln!("{err}");
+                    self.failed.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    }
+}
+
+impl MetricsSink for TeeSink {
+    fn emit(&self, payload: &str) -> Result<(), MetricsError> {
+        self.emit_with(|sink| sink.emit(payload))
+    }
+
+    fn emit_log_line(&self, line: &str) -> Result<(), MetricsError> {
+        self.emit_with(|sink| sink.emit_log_line(line))
+    }
+
+    fn take_dropped(&self) -> u64 {
+        let own = self.failed.swap(0, Ordering::Relaxed);
+        self.sinks
+            .iter()
+            .fold(own, |dropped, (sink, _)| dropped + sink.take_dropped())
+    }
+}
+
+/// Keeps emitted payloads in memory, for tests. Clones share the payloads.
+/// Available with the `testing` feature, see [`crate::testing`].
+#[cfg(any(test, feature = "testing"))]
+#[derive(Debug, Default, Clone)]
+pub struct RecordingSink(Arc<Mutex<Vec<String>>>);
+
+#[cfg(any(test, feature = "testing"))]
+impl RecordingSink {
+    /// Creates an empty sink.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the payloads emitted so far, parsed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a payload is not valid JSON.
+    #[must_use]
+    pub fn payloads(&self) -> Vec<serde_json::Value> {
+        self.raw_payloads()
+            .iter()
+            .map(|payload| serde_json::from_str(payload).expect("emitted payload is not JSON"))
+            .collect()
+    }
+
+    /// Returns the payloads emitted so far, as written to the sink.
+    #[must_use]
+    pub fn raw_payloads(&self) -> Vec<String> {
+        self.lock().clone()
+    }
+
+    /// Discards the payloads emitted so far.
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<String>> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl MetricsSink for RecordingSink {
+    fn emit(&self, payload: &str) -> Result<(), MetricsError> {
+        self.lock().push(payload.to_string());
+        Ok(())
+    }
+
+    fn emit_log_line(&self, line: &str) -> Result<(), MetricsError> {
+        MetricsSink::emit(self, line)
+    }
+}
+
+#[cfg(all(any(test, feature = "testing"), feature = "async"))]
+impl AsyncMetricsSink for RecordingSink {
+    fn emit(&self, payload: String) -> EmitFuture<'_> {
+        Box::pin(async move { MetricsSink::emit(self, &payload) })
+    }
+
+    fn emit_log_line(&self, line: String) -> EmitFuture<'_> {
+        Box::pin(async move { MetricsSink::emit(self, &line) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records the size of each write.
+    #[derive(Debug, Default)]
+    struct Writes(Vec<usize>);
+
+    impl Write for Writes {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.push(buf.len());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Fails the given number of times, then records.
+    #[derive(Debug, Default)]
+    struct FailingSink {
+        failures: AtomicU64,
+        attempts: AtomicU64,
+        recording: RecordingSink,
+    }
+
+    impl MetricsSink for FailingSink {
+        fn emit(&self, payload: &str) -> Result<(), MetricsError> {
+            self.attempts.fetch_add(1, Ordering::SeqCst);
+            let remaining = self.failures.load(Ordering::SeqCst);
+            if remaining > 0 {
+                self.failures.store(remaining - 1, Ordering::SeqCst);
+                return Err(MetricsError::Io(std::io::Error::other("unavailable")));
+            }
+            MetricsSink::emit(&self.recording, payload)
+        }
+    }
+
+    fn failing(failures: u64) -> FailingSink {
+        FailingSink {
+            failures: AtomicU64::new(failures),
+            ..FailingSink::default()
+        }
+    }
+
+    #[test]
+    fn should_retry_until_attempts_are_exhausted() {
+        let sink = RetrySink::new(failing(2))
+            .max_attempts(3)
+            .base_delay(Duration::from_millis(1));
+        assert!(sink.emit("{}").is_ok());
+        assert_eq!(sink.sink.attempts.load(Ordering::SeqCst), 3);
+        assert_eq!(sink.sink.recording.payloads().len(), 1);
+
+        let sink = RetrySink::new(failing(3))
+            .max_attempts(3)
+            .base_delay(Duration::from_millis(1));
+        assert!(sink.emit("{}").is_err());
+        assert_eq!(sink.sink.attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn should_cap_jittered_backoff() {
+        let sink = RetrySink::new(NullSink)
+            .base_delay(Duration::from_millis(100))
+            .max_delay(Duration::from_millis(300));
+
+        assert!(sink.backoff(1) <= Duration::from_millis(100));
+        assert!(sink.backoff(2) <= Duration::from_millis(200));
+        assert!(sink.backoff(40) <= Duration::from_millis(300));
+    }
+
+    #[test]
+    fn should_open_circuit_and_probe_after_cooldown() {
+        let clock = crate::clock::ManualClock::new(chrono::Utc::now());
+        let fallback = RecordingSink::default();
+        let sink = CircuitBreakerSink::new(failing(3))
+            .failure_threshold(2)
+            .cooldown(Duration::from_secs(10))
+            .fallback(fallback.clone())
+            .clock(clock.clone());
+
+        assert!(sink.emit("1").is_err());
+        assert!(sink.emit("2").is_ok());
+        assert!(sink.is_open());
+        assert!(sink.emit("3").is_ok());
+        assert_eq!(sink.sink.attempts.load(Ordering::SeqCst), 2);
+
+        // the probe fails, the circuit opens again
+        clock.advance(Duration::from_secs(10));
+        assert!(sink.emit("4").is_ok());
+        assert!(sink.is_open());
+        assert_eq!(sink.sink.attempts.load(Ordering::SeqCst), 3);
+
+        clock.advance(Duration::from_secs(10));
+        assert!(sink.emit("5").is_ok());
+        assert!(!sink.is_open());
+        assert_eq!(fallback.payloads(), vec![2, 3, 4]);
+        assert_eq!(sink.sink.recording.payloads(), vec![5]);
+    }
+
+    #[test]
+    fn should_write_to_all_sinks_with_independent_errors() {
+        let primary = RecordingSink::default();
+        let archive = RecordingSink::default();
+        let sink = TeeSink::new(primary.clone())
+            .also(failing(1))
+            .also(archive.clone());
+
+        assert!(sink.emit("1").is_ok());
+        assert_eq!(primary.payloads(), vec![1]);
+        assert_eq!(archive.payloads(), vec![1]);
+        assert_eq!(sink.take_dropped(), 1);
+        assert_eq!(sink.take_dropped(), 0);
+
+        let sink = TeeSink::new(failing(1)).required(primary.clone());
+        assert!(sink.emit("2").is_err());
+        assert_eq!(primary.payloads(), vec![1, 2]);
+    }
+
+    #[test]
+    fn should_write_line_in_single_write() {
+        let mut writes = Writes::default();
+
+        write_line(&mut writes, r#"{"_aws":{}}"#).unwrap();
+
+        assert_eq!(writes.0, vec![12]);
+    }
+
+    #[test]
+    fn should_parse_agent_endpoints() {
+        assert_eq!(
+            AgentSink::new("tcp://127.0.0.1:25888").unwrap().endpoint,
+            AgentEndpoint::Tcp("127.0.0.1:25888".into())
+        );
+        assert_eq!(
+            AgentSink::new("udp://127.0.0.1:25888").unwrap().endpoint,
+            AgentEndpoint::Udp("127.0.0.1:25888".into())
+        );
+        assert!(AgentSink::new("127.0.0.1:25888").is_err());
+    }
+
+    #[test]
+    fn should_send_payload_lines_to_agent() {
+        use std::io::{BufRead, BufReader};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let sink = AgentSink::new(&format!("tcp://{address}")).unwrap();
+
+        sink.emit("{\"a\":1}").unwrap();
+        sink.emit("{\"b\":2}").unwrap();
+
+        let (connection, _) = listener.accept().unwrap();
+        let lines: Vec<String> = BufReader::new(connection)
+            .lines()
+            .take(2)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(lines, vec!["{\"a\":1}", "{\"b\":2}"]);
+    }
+
+    fn file_sink_path(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("file_sink_{name}_{}.ndjson", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn should_append_payload_lines_to_file() {
+        let path = file_sink_path("plain");
+        let sink = FileSink::new(&path);
+
+        sink.emit("{\"a\":1}").unwrap();
+        sink.emit_log_line("{\"b\":2}").unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(content, "{\"a\":1}\n{\"b\":2}\n");
+    }
+
+    #[test]
+    fn should_forward_log_lines_through_wrappers() {
+        let recording = RecordingSink::default();
+        let fallback = RecordingSink::default();
+        let breaker = CircuitBreakerSink::new(failing(1))
+            .failure_threshold(1)
+            .fallback(fallback.clone());
+
+        RetrySink::new(recording.clone())
+            .emit_log_line("{\"retry\":1}")
+            .unwrap();
+        CircuitBreakerSink::new(recording.clone())
+            .emit_log_line("{\"breaker\":1}")
+            .unwrap();
+        breaker.emit("{}").unwrap();
+        breaker.emit_log_line("{\"open\":1}").unwrap();
+
+        assert_eq!(
+            recording.raw_payloads(),
+            vec!["{\"retry\":1}", "{\"breaker\":1}"]
+        );
+        assert_eq!(fallback.raw_payloads(), vec!["{}", "{\"open\":1}"]);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn should_append_compressed_payloads_to_file() {
+        use std::io::Read;
+
+        let path = file_sink_path("gzip");
+        let sink = FileSink::new(&path).gzip(6);
+
+        sink.emit("{\"a\":1}").unwrap();
+        sink.emit("{\"b\":2}").unwrap();
+
+        let compressed = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut content = String::new();
+        flate2::read::MultiGzDecoder::new(compressed.as_slice())
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "{\"a\":1}\n{\"b\":2}\n");
+    }
+}
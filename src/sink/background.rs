@@ -0,0 +1,661 @@
+//! [`BackgroundSink`], writing payloads on a worker thread. Available with the `background`
+//! feature.
+use std::collections::VecDeque;
+#[cfg(feature = "async")]
+use std::future::Future;
+#[cfg(feature = "async")]
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+#[cfg(feature = "async")]
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use crate::sink::MetricsSink;
+#[cfg(feature = "async")]
+use crate::sink::{AsyncMetricsSink, EmitFuture};
+use crate::{platform, ChannelOverflowPolicy, MetricsError};
+
+/// How long an emission waits for room with [`ChannelOverflowPolicy::Block`] by default.
+pub const DEFAULT_BLOCK_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A queued write.
+#[derive(Debug)]
+enum Line {
+    Payload(String),
+    /// Written with [`MetricsSink::emit_log_line`].
+    Log(String),
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    payloads: VecDeque<Line>,
+    /// A payload is being written by the worker.
+    writing: bool,
+    closed: bool,
+    /// The worker exited, e.g. because the wrapped sink panicked.
+    stopped: bool,
+    /// Async emissions waiting for room, woken when the worker takes a payload.
+    #[cfg(feature = "async")]
+    waiting: Vec<Waker>,
+    /// The deadlines of the async emissions waiting for room, woken by the timer.
+    #[cfg(feature = "async")]
+    deadlines: Vec<(Instant, Waker)>,
+    /// The timer thread shared by the async emissions was started.
+    #[cfg(feature = "async")]
+    timer: bool,
+}
+
+#[derive(Debug)]
+struct Channel {
+    queue: Mutex<Queue>,
+    changed: Condvar,
+    capacity: usize,
+    overflow: ChannelOverflowPolicy,
+    dropped: AtomicU64,
+}
+
+impl Channel {
+    fn lock(&self) -> MutexGuard<'_, Queue> {
+        self.queue.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn wait<'a>(&self, queue: MutexGuard<'a, Queue>) -> MutexGuard<'a, Queue> {
+        self.changed
+            .wait(queue)
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn wait_timeout<'a>(
+        &self,
+        queue: MutexGuard<'a, Queue>,
+        timeout: Duration,
+    ) -> MutexGuard<'a, Queue> {
+        self.changed
+            .wait_timeout(queue, timeout)
+            .unwrap_or_else(PoisonError::into_inner)
+            .0
+    }
+
+    /// Wakes the threads and tasks waiting for a change of the queue.
+    fn notify(&self, mut queue: MutexGuard<'_, Queue>) {
+        #[cfg(feature = "async")]
+        let waiting = std::mem::take(&mut queue.waiting);
+        drop(queue);
+        self.changed.notify_all();
+        #[cfg(feature = "async")]
+        waiting.into_iter().for_each(Waker::wake);
+    }
+
+    /// Makes room for a line following the overflow policy, returns `false` if the emission
+    /// has to wait for the worker.
+    fn admit(&self, queue: &mut Queue) -> Result<bool, MetricsError> {
+        if queue.stopped {
+            return Err(background_error(
+                std::io::ErrorKind::BrokenPipe,
+                "the background worker stopped",
+            ));
+        }
+        if queue.payloads.len() < self.capacity {
+            return Ok(true);
+        }
+        match self.overflow {
+            ChannelOverflowPolicy::Block => Ok(false),
+            ChannelOverflowPolicy::DropOldest => {
+                queue.payloads.pop_front();
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                Ok(true)
+            }
+            ChannelOverflowPolicy::DropNewest => Err(background_error(
+                std::io::ErrorKind::WouldBlock,
+                "the background sink is full",
+            )),
+        }
+    }
+
+    /// Writes the queued payloads until the last handle is dropped.
+    fn work(&self, sink: &dyn MetricsSink) {
+        let _stopped = StopGuard(self);
+        loop {
+            let mut queue = self.lock();
+            while queue.payloads.is_empty() && !queue.closed {
+                queue = self.wait(queue);
+            }
+            let Some(payload) = queue.payloads.pop_front() else {
+                return;
+            };
+            queue.writing = true;
+            self.notify(queue);
+            let result = match &payload {
+                Line::Payload(payload) => sink.emit(payload),
+                Line::Log(line) => sink.emit_log_line(line),
+            };
+            if let Err(err) = result {
+                eprintln!("{err}");
+            }
+            self.lock().writing = false;
+            self.changed.notify_all();
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl Channel {
+    /// Wakes the async emissions at their deadline, even if the worker is stuck in the sink,
+    /// until the last handle is dropped. A single timer serves all the emissions of the channel.
+    fn time_out(&self) {
+        let mut queue = self.lock();
+        loop {
+            let now = Instant::now();
+            let (expired, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut queue.deadlines)
+                .into_iter()
+                .partition(|(deadline, _)| *deadline <= now);
+            queue.deadlines = pending;
+            if !expired.is_empty() {
+                drop(queue);
+                expired.into_iter().for_each(|(_, waker)| waker.wake());
+                queue = self.lock();
+                continue;
+            }
+            if queue.closed {
+                queue.timer = false;
+                return;
+            }
+            queue = match queue.deadlines.iter().map(|(deadline, _)| *deadline).min() {
+                Some(next) => self.wait_timeout(queue, next.saturating_duration_since(now)),
+                None => self.wait(queue),
+            };
+        }
+    }
+}
+
+/// Marks the worker as stopped when it exits, also by a panic of the wrapped sink, so
+/// emissions and [`BackgroundSink::wait_idle`] don't wait for it.
+struct StopGuard<'a>(&'a Channel);
+
+impl Drop for StopGuard<'_> {
+    fn drop(&mut self) {
+        let mut queue = self.0.lock();
+        queue.stopped = true;
+        queue.writing = false;
+        self.0.notify(queue);
+    }
+}
+
+fn background_error(kind: std::io::ErrorKind, message: &str) -> MetricsError {
+    MetricsError::Io(std::io::Error::new(kind, message))
+}
+
+fn timed_out() -> MetricsError {
+    background_error(
+        std::io::ErrorKind::TimedOut,
+        "timed out waiting for room in the background sink",
+    )
+}
+
+/// Closes the channel when the last clone of the sink is dropped.
+#[derive(Debug)]
+struct ChannelHandle(Arc<Channel>);
+
+impl Drop for ChannelHandle {
+    fn drop(&mut self) {
+        self.0.lock().closed = true;
+        self.0.changed.notify_all();
+    }
+}
+
+/// Writes payloads to another sink on a worker thread, e.g. a remote sink, so the flush
+/// doesn't wait for it. The payloads are queued in a bounded channel, so a slow sink can't
+/// cause unbounded memory growth during a burst; the [`ChannelOverflowPolicy`] decides what
+/// happens when it is full. Clones share the channel and the worker.
+///
+/// ```
+/// use lambda_helpers_metrics::sink::{BackgroundSink, StdoutSink};
+/// use lambda_helpers_metrics::{ChannelOverflowPolicy, Metrics};
+///
+/// let sink = BackgroundSink::new(StdoutSink, 64, ChannelOverflowPolicy::DropOldest);
+/// let metrics = Metrics::builder("custom_lambdas")
+///     .sink(sink.clone())
+///     .build()
+///     .unwrap();
+/// // at the end of the invocation
+/// sink.wait_idle();
+/// ```
+///
+/// With [`ChannelOverflowPolicy::Block`], an emission waits at most the
+/// [`block_timeout`](BackgroundSink::block_timeout) for room, then fails. Once the worker has
+/// stopped, e.g. because the wrapped sink panicked, emissions fail right away.
+///
+/// The sink can also be set with [`MetricsBuilder::async_sink`](crate::MetricsBuilder::async_sink),
+/// so [`Metrics::flush_async`](crate::Metrics::flush_async) goes through the same bounded
+/// channel: waiting for room then suspends the task instead of blocking the thread.
+///
+/// Errors of the wrapped sink are printed to stderr. Where threads can't be spawned, e.g. on
+/// WASI, payloads are written inline by `emit` instead.
+#[derive(Debug, Clone)]
+pub struct BackgroundSink {
+    channel: Arc<ChannelHandle>,
+    /// The wrapped sink, set when the worker couldn't be started.
+    inline: Option<Arc<dyn MetricsSink>>,
+    block_timeout: Duration,
+}
+
+impl BackgroundSink {
+    /// Starts a worker writing to `sink`, with room for `capacity` queued payloads (at least one).
+    #[must_use]
+    pub fn new(
+        sink: impl MetricsSink + 'static,
+        capacity: usize,
+        overflow: ChannelOverflowPolicy,
+    ) -> Self {
+        Self::with_spawn(Arc::new(sink), capacity, overflow, |work| {
+            platform::spawn("metrics-background-sink", work)
+        })
+    }
+
+    fn with_spawn(
+        sink: Arc<dyn MetricsSink>,
+        capacity: usize,
+        overflow: ChannelOverflowPolicy,
+        spawn: impl FnOnce(Box<dyn FnOnce() + Send>) -> std::io::Result<()>,
+    ) -> Self {
+        let channel = Arc::new(Channel {
+            queue: Mutex::default(),
+            changed: Condvar::new(),
+            capacity: capacity.max(1),
+            overflow,
+            dropped: AtomicU64::new(0),
+        });
+        let worker = Arc::clone(&channel);
+        let worker_sink = Arc::clone(&sink);
+        let inline = match spawn(Box::new(move || worker.work(worker_sink.as_ref()))) {
+            Ok(()) => None,
+            Err(err) => {
+                eprintln!("writing metrics inline, the background worker can't start: {err}");
+                Some(sink)
+            }
+        };
+        Self {
+            channel: Arc::new(ChannelHandle(channel)),
+            inline,
+            block_timeout: DEFAULT_BLOCK_TIMEOUT,
+        }
+    }
+
+    /// Sets how long an emission waits for room with [`ChannelOverflowPolicy::Block`],
+    /// [`DEFAULT_BLOCK_TIMEOUT`] by default.
+    #[must_use]
+    pub fn block_timeout(mut self, timeout: Duration) -> Self {
+        self.block_timeout = timeout;
+        self
+    }
+
+    /// Returns the number of queued payloads.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.channel.0.lock().payloads.len()
+    }
+
+    /// Returns `true` if no payloads are queued.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Waits until all queued payloads are written, e.g. before the invocation ends,
+    /// or the worker has stopped.
+    pub fn wait_idle(&self) {
+        let channel = &self.channel.0;
+        let mut queue = channel.lock();
+        while (!queue.payloads.is_empty() || queue.writing) && !queue.stopped {
+            queue = channel.wait(queue);
+        }
+    }
+}
+
+impl BackgroundSink {
+    fn enqueue(&self, line: Line) -> Result<(), MetricsError> {
+        let channel = &self.channel.0;
+        let deadline = Instant::now() + self.block_timeout;
+        let mut queue = channel.lock();
+        while !channel.admit(&mut queue)? {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(timed_out());
+            }
+            queue = channel.wait_timeout(queue, remaining);
+        }
+        queue.payloads.push_back(line);
+        channel.notify(queue);
+        Ok(())
+    }
+}
+
+/// An async emission waiting for room in the channel.
+#[cfg(feature = "async")]
+struct Enqueue<'a> {
+    sink: &'a BackgroundSink,
+    line: Option<Line>,
+    deadline: Option<Instant>,
+}
+
+#[cfg(feature = "async")]
+impl Future for Enqueue<'_> {
+    type Output = Result<(), MetricsError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let channel = &this.sink.channel.0;
+        let mut queue = channel.lock();
+        match channel.admit(&mut queue) {
+            Err(err) => return Poll::Ready(Err(err)),
+            Ok(true) => {
+                if let Some(line) = this.line.take() {
+                    queue.payloads.push_back(line);
+                }
+                channel.notify(queue);
+                return Poll::Ready(Ok(()));
+            }
+            Ok(false) => {}
+        }
+        let deadline = match this.deadline {
+            Some(deadline) => deadline,
+            None => {
+                if !queue.timer {
+                    let timer = Arc::clone(channel);
+                    let started = platform::spawn("metrics-background-timeout", move || {
+                        timer.time_out();
+                    });
+                    if let Err(err) = started {
+                        return Poll::Ready(Err(err.into()));
+                    }
+                    queue.timer = true;
+                }
+                let deadline = *this
+                    .deadline
+                    .insert(Instant::now() + this.sink.block_timeout);
+                queue.deadlines.push((deadline, cx.waker().clone()));
+                // the timer recomputes its next deadline
+                channel.changed.notify_all();
+                deadline
+            }
+        };
+        if Instant::now() >= deadline {
+            return Poll::Ready(Err(timed_out()));
+        }
+        queue.waiting.push(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+#[cfg(feature = "async")]
+impl BackgroundSink {
+    fn enqueue_async(&self, line: Line) -> EmitFuture<'_> {
+        match &self.inline {
+            Some(sink) => Box::pin(std::future::ready(match &line {
+                Line::Payload(payload) => sink.emit(payload),
+                Line::Log(line) => sink.emit_log_line(line),
+            })),
+            None => Box::pin(Enqueue {
+                sink: self,
+                line: Some(line),
+                deadline: None,
+            }),
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl AsyncMetricsSink for BackgroundSink {
+    fn emit(&self, payload: String) -> EmitFuture<'_> {
+        self.enqueue_async(Line::Payload(payload))
+    }
+
+    fn emit_log_line(&self, line: String) -> EmitFuture<'_> {
+        self.enqueue_async(Line::Log(line))
+    }
+
+    fn take_dropped(&self) -> u64 {
+        MetricsSink::take_dropped(self)
+    }
+}
+
+impl MetricsSink for BackgroundSink {
+    fn emit(&self, payload: &str) -> Result<(), MetricsError> {
+        match &self.inline {
+            Some(sink) => sink.emit(payload),
+            None => self.enqueue(Line::Payload(payload.to_string())),
+        }
+    }
+
+    fn emit_log_line(&self, line: &str) -> Result<(), MetricsError> {
+        match &self.inline {
+            Some(sink) => sink.emit_log_line(line),
+            None => self.enqueue(Line::Log(line.to_string())),
+        }
+    }
+
+    fn take_dropped(&self) -> u64 {
+        self.channel.0.dropped.swap(0, Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::RecordingSink;
+
+    /// Blocks each write until opened.
+    #[derive(Debug, Default, Clone)]
+    struct GateSink {
+        gate: Arc<(Mutex<bool>, Condvar)>,
+        recording: RecordingSink,
+    }
+
+    impl GateSink {
+        fn open(&self) {
+            *self.gate.0.lock().unwrap() = true;
+            self.gate.1.notify_all();
+        }
+
+        fn written(&self) -> Vec<String> {
+            self.recording
+                .payloads()
+                .into_iter()
+                .map(|payload| payload.as_str().unwrap().to_string())
+                .collect()
+        }
+    }
+
+    impl MetricsSink for GateSink {
+        fn emit(&self, payload: &str) -> Result<(), MetricsError> {
+            let mut open = self.gate.0.lock().unwrap();
+            while !*open {
+                open = self.gate.1.wait(open).unwrap();
+            }
+            MetricsSink::emit(&self.recording, payload)
+        }
+    }
+
+    /// Emits `"a"`, which the worker blocks on, and fills the queue of one with `"b"`.
+    fn blocked_sink(overflow: ChannelOverflowPolicy) -> (GateSink, BackgroundSink) {
+        let gate = GateSink::default();
+        let sink = BackgroundSink::new(gate.clone(), 1, overflow);
+        MetricsSink::emit(&sink, r#""a""#).unwrap();
+        while !sink.is_empty() {
+            std::thread::yield_now();
+        }
+        MetricsSink::emit(&sink, r#""b""#).unwrap();
+        (gate, sink)
+    }
+
+    #[test]
+    fn should_drop_oldest_payload_when_full() {
+        let (gate, sink) = blocked_sink(ChannelOverflowPolicy::DropOldest);
+
+        MetricsSink::emit(&sink, r#""c""#).unwrap();
+        gate.open();
+        sink.wait_idle();
+
+        assert_eq!(gate.written(), vec!["a", "c"]);
+        assert_eq!(MetricsSink::take_dropped(&sink), 1);
+        assert_eq!(MetricsSink::take_dropped(&sink), 0);
+    }
+
+    #[test]
+    fn should_reject_newest_payload_when_full() {
+        let (gate, sink) = blocked_sink(ChannelOverflowPolicy::DropNewest);
+
+        assert!(MetricsSink::emit(&sink, r#""c""#).is_err());
+        gate.open();
+        sink.wait_idle();
+
+        assert_eq!(gate.written(), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn should_block_until_worker_makes_room() {
+        let (gate, sink) = blocked_sink(ChannelOverflowPolicy::Block);
+
+        let opener = gate.clone();
+        let opening = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            opener.open();
+        });
+        MetricsSink::emit(&sink, r#""c""#).unwrap();
+        sink.wait_idle();
+        opening.join().unwrap();
+
+        assert_eq!(gate.written(), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn should_fail_blocked_emission_after_timeout() {
+        let (gate, sink) = blocked_sink(ChannelOverflowPolicy::Block);
+        let sink = sink.block_timeout(Duration::from_millis(20));
+
+        assert!(MetricsSink::emit(&sink, r#""c""#).is_err());
+        gate.open();
+        sink.wait_idle();
+
+        assert_eq!(gate.written(), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn should_fail_emissions_once_worker_stopped() {
+        #[derive(Debug)]
+        struct PanickingSink;
+
+        impl MetricsSink for PanickingSink {
+            fn emit(&self, _payload: &str) -> Result<(), MetricsError> {
+                panic!("sink failure");
+            }
+        }
+
+        let sink = BackgroundSink::new(PanickingSink, 1, ChannelOverflowPolicy::Block);
+        MetricsSink::emit(&sink, r#""a""#).unwrap();
+        sink.wait_idle();
+
+        assert!(MetricsSink::emit(&sink, r#""b""#).is_err());
+    }
+
+    #[cfg(feature = "async")]
+    fn poll_emit(future: &mut EmitFuture<'_>) -> Poll<Result<(), MetricsError>> {
+        future
+            .as_mut()
+            .poll(&mut Context::from_waker(Waker::noop()))
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn should_suspend_async_emission_until_worker_makes_room() {
+        let (gate, sink) = blocked_sink(ChannelOverflowPolicy::Block);
+
+        let mut emission = AsyncMetricsSink::emit(&sink, r#""c""#.to_string());
+        assert!(poll_emit(&mut emission).is_pending());
+        gate.open();
+        let result = loop {
+            if let Poll::Ready(result) = poll_emit(&mut emission) {
+                break result;
+            }
+            std::thread::yield_now();
+        };
+        sink.wait_idle();
+
+        assert!(result.is_ok());
+        assert_eq!(gate.written(), vec!["a", "b", "c"]);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn should_share_one_timer_between_async_emissions() {
+        let (gate, sink) = blocked_sink(ChannelOverflowPolicy::Block);
+        let sink = sink.block_timeout(Duration::from_millis(20));
+
+        struct Woken(AtomicU64);
+
+        impl std::task::Wake for Woken {
+            fn wake(self: Arc<Self>) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let woken = Arc::new(Woken(AtomicU64::new(0)));
+        let waker = Waker::from(Arc::clone(&woken));
+        let mut emissions: Vec<_> = (0..3)
+            .map(|_| AsyncMetricsSink::emit(&sink, r#""c""#.to_string()))
+            .collect();
+        for emission in &mut emissions {
+            let polled = emission.as_mut().poll(&mut Context::from_waker(&waker));
+            assert!(polled.is_pending());
+        }
+        {
+            let queue = sink.channel.0.lock();
+            assert!(queue.timer);
+            assert_eq!(queue.deadlines.len(), 3);
+        }
+        while woken.0.load(Ordering::SeqCst) < 3 {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+
+        for emission in &mut emissions {
+            assert!(matches!(poll_emit(emission), Poll::Ready(Err(_))));
+        }
+        gate.open();
+        sink.wait_idle();
+        assert_eq!(gate.written(), vec!["a", "b"]);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn should_time_out_async_emission() {
+        let (gate, sink) = blocked_sink(ChannelOverflowPolicy::Block);
+        let sink = sink.block_timeout(Duration::from_millis(20));
+
+        let mut emission = AsyncMetricsSink::emit(&sink, r#""c""#.to_string());
+        assert!(poll_emit(&mut emission).is_pending());
+        std::thread::sleep(Duration::from_millis(30));
+
+        assert!(matches!(poll_emit(&mut emission), Poll::Ready(Err(_))));
+        gate.open();
+        sink.wait_idle();
+        assert_eq!(gate.written(), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn should_write_inline_when_worker_cannot_start() {
+        let recording = RecordingSink::default();
+        let sink = BackgroundSink::with_spawn(
+            Arc::new(recording.clone()),
+            1,
+            ChannelOverflowPolicy::DropNewest,
+            |_| Err(std::io::ErrorKind::Unsupported.into()),
+        );
+
+        MetricsSink::emit(&sink, r#""a""#).unwrap();
+        MetricsSink::emit(&sink, r#""b""#).unwrap();
+        sink.wait_idle();
+
+        assert_eq!(recording.payloads(), vec!["a", "b"]);
+    }
+}
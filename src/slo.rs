@@ -0,0 +1,117 @@
+//! Service level objective (SLO) metrics.
+//!
+//! An [`SloRecorder`] classifies each invocation as good (successful and faster than
+//! the latency threshold) or bad, and records it as `slo_good`/`slo_bad` counts, from which
+//! error budget alarms are built in `CloudWatch`.
+//!
+//! With [`SloRecorder::burn_rate`], the `slo_burn_rate` metric is also recorded: 0 for good
+//! invocations and `1 / (1 - target)` for bad ones, so its `Average` over any alarm window
+//! is the rate at which the error budget is burnt (1 means the budget lasts exactly the SLO period).
+//!
+//! ```
+//! use std::time::Duration;
+//! use lambda_helpers_metrics::slo::SloRecorder;
+//! use lambda_helpers_metrics::Metrics;
+//!
+//! let slo = SloRecorder::new(Duration::from_millis(300), 0.999).unwrap().burn_rate(true);
+//!
+//! let mut metrics = Metrics::new("custom_lambdas", "service", "dummy_service");
+//! slo.record(&mut metrics, Duration::from_millis(120), true);
+//!
+//! assert_eq!(metrics.value_of("slo_good"), Some(1.0));
+//! assert_eq!(metrics.value_of("slo_bad"), Some(0.0));
+//! ```
+use std::time::Duration;
+
+use crate::{MetricUnit, Metrics, MetricsError};
+
+pub const SLO_GOOD_METRIC: &str = "slo_good";
+pub const SLO_BAD_METRIC: &str = "slo_bad";
+pub const SLO_BURN_RATE_METRIC: &str = "slo_burn_rate";
+
+/// Records invocations against a latency and availability objective.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SloRecorder {
+    threshold: Duration,
+    target: f64,
+    burn_rate: bool,
+}
+
+impl SloRecorder {
+    /// Creates a recorder for invocations which should succeed within `threshold`,
+    /// with `target` the expected share of good invocations, e.g. `0.999`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the target is not between 0 and 1 (exclusive)
+    pub fn new(threshold: Duration, target: f64) -> Result<Self, MetricsError> {
+        if !(target > 0.0 && target < 1.0) {
+            return Err(MetricsError::Configuration(format!(
+                "invalid SLO target: {target}, expected a value between 0 and 1"
+            )));
+        }
+        Ok(Self {
+            threshold,
+            target,
+            burn_rate: false,
+        })
+    }
+
+    /// Also records the `slo_burn_rate` metric.
+    #[must_use]
+    pub fn burn_rate(mut self, enabled: bool) -> Self {
+        self.burn_rate = enabled;
+        self
+    }
+
+    /// Returns `true` if an invocation is good: successful and faster than the threshold.
+    #[must_use]
+    pub fn is_good(&self, latency: Duration, success: bool) -> bool {
+        success && latency <= self.threshold
+    }
+
+    /// Records a single invocation.
+    pub fn record(&self, metrics: &mut Metrics, latency: Duration, success: bool) {
+        let good = self.is_good(latency, success);
+        metrics.increment(SLO_GOOD_METRIC, if good { 1.0 } else { 0.0 });
+        metrics.increment(SLO_BAD_METRIC, if good { 0.0 } else { 1.0 });
+        if self.burn_rate {
+            let burn_rate = if good { 0.0 } else { 1.0 / (1.0 - self.target) };
+            metrics.add_sample(SLO_BURN_RATE_METRIC, MetricUnit::None, burn_rate);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_reject_invalid_target() {
+        assert!(SloRecorder::new(Duration::from_millis(300), 1.0).is_err());
+        assert!(SloRecorder::new(Duration::from_millis(300), 0.0).is_err());
+        assert!(SloRecorder::new(Duration::from_millis(300), f64::NAN).is_err());
+    }
+
+    #[test]
+    fn should_record_good_and_bad_invocations() {
+        let slo = SloRecorder::new(Duration::from_millis(300), 0.99)
+            .unwrap()
+            .burn_rate(true);
+        let mut metrics = Metrics::builder("test")
+            .sink(crate::sink::NullSink)
+            .build()
+            .unwrap();
+
+        slo.record(&mut metrics, Duration::from_millis(100), true);
+        slo.record(&mut metrics, Duration::from_millis(500), true);
+        slo.record(&mut metrics, Duration::from_millis(100), false);
+
+        assert_eq!(metrics.value_of(SLO_GOOD_METRIC), Some(1.0));
+        assert_eq!(metrics.value_of(SLO_BAD_METRIC), Some(2.0));
+        let burn_rates = metrics.values_of(SLO_BURN_RATE_METRIC).unwrap();
+        assert_eq!(burn_rates.len(), 3);
+        assert_eq!(burn_rates[0], 0.0);
+        assert!((burn_rates[1] - 100.0).abs() < 1e-9);
+    }
+}
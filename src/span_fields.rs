@@ -0,0 +1,384 @@
+//! Fields of the current `tracing` span attached to the metrics recorded inside it.
+//!
+//! A [`SpanFieldsLayer`] keeps the field values of the spans, and `Metrics` objects built with
+//! [`MetricsBuilder::span_field`](crate::MetricsBuilder::span_field) attach the designated
+//! fields of the current span and its parents, so the metric context stays in sync with the
+//! trace context:
+//!
+//! ```
+//! use lambda_helpers_metrics::runtime_info::AttachAs;
+//! use lambda_helpers_metrics::span_fields::SpanFieldsLayer;
+//! use lambda_helpers_metrics::Metrics;
+//! use tracing_subscriber::layer::SubscriberExt;
+//!
+//! let subscriber = tracing_subscriber::registry().with(SpanFieldsLayer::default());
+//! tracing::subscriber::with_default(subscriber, || {
+//!     let mut metrics = Metrics::builder("custom_lambdas")
+//!         .span_field("tenant_id", AttachAs::Dimension)
+//!         .span_field("operation", AttachAs::Property)
+//!         .build()
+//!         .unwrap();
+//!
+//!     let _span = tracing::info_span!("order", tenant_id = "acme", operation = "create").entered();
+//!     metrics.increment("orders", 1.0);
+//! });
+//! ```
+//!
+//! Fields of inner spans override the fields of their parents. Fields attached as properties
+//! apply only to the metrics recorded inside the span: metrics recorded in spans with different
+//! values are emitted in separate payloads, like metrics with different dimensions.
+//!
+//! A [`SpanTimingLayer`] records the duration of the spans created with the
+//! [`TIMED_FIELD`] marker, `metrics.timed = true`, as a `Milliseconds` sample named after the
+//! span, into a [`MetricsHandle`] or the [current context](crate::context):
+//!
+//! ```
+//! use lambda_helpers_metrics::context::MetricsHandle;
+//! use lambda_helpers_metrics::span_fields::SpanTimingLayer;
+//! use lambda_helpers_metrics::Metrics;
+//! use tracing_subscriber::layer::SubscriberExt;
+//!
+//! let handle = MetricsHandle::new(Metrics::new("custom_lambdas", "service", "dummy_service"));
+//! let subscriber =
+//!     tracing_subscriber::registry().with(SpanTimingLayer::default().handle(handle.clone()));
+//! tracing::subscriber::with_default(subscriber, || {
+//!     let _span = tracing::info_span!("load_order", metrics.timed = true).entered();
+//!     // load_order is recorded when the span closes
+//! });
+//! handle.flush();
+//! ```
+//!
+//! Available with the `tracing` feature; without the layers, nothing is attached or recorded.
+use std::fmt;
+use std::time::Instant;
+
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::Subscriber;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{Layer, Registry};
+
+use crate::context::{self, MetricsHandle};
+use crate::runtime_info::AttachAs;
+use crate::{Dimensions, MetricUnit, Metrics};
+
+/// The span field marking spans whose duration is recorded by [`SpanTimingLayer`].
+pub const TIMED_FIELD: &str = "metrics.timed";
+
+/// The recorded field values of a span, kept in its extensions.
+#[derive(Debug, Default)]
+struct SpanFields(Vec<(&'static str, String)>);
+
+impl SpanFields {
+    fn set(&mut self, name: &'static str, value: String) {
+        match self.0.iter_mut().find(|(key, _)| *key == name) {
+            Some((_, current)) => *current = value,
+            None => self.0.push((name, value)),
+        }
+    }
+}
+
+/// Records the fields of a span, strings without the quotes of their `Debug` format.
+struct FieldVisitor<'a> {
+    layer: &'a SpanFieldsLayer,
+    fields: &'a mut SpanFields,
+}
+
+impl Visit for FieldVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if self.layer.keeps(field.name()) {
+            self.fields.set(field.name(), value.to_string());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if self.layer.keeps(field.name()) {
+            self.fields.set(field.name(), format!("{value:?}"));
+        }
+    }
+}
+
+/// A `tracing_subscriber` layer keeping the field values of spans, see [`crate::span_fields`].
+/// It must be layered on the [`Registry`].
+#[derive(Debug, Clone, Default)]
+pub struct SpanFieldsLayer {
+    /// The fields kept, all of them if empty.
+    fields: Vec<String>,
+}
+
+impl SpanFieldsLayer {
+    /// Keeps only the given fields, instead of all fields of all spans.
+    #[must_use]
+    pub fn fields<I, S>(mut self, fields: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.fields.extend(fields.into_iter().map(Into::into));
+        self
+    }
+
+    fn keeps(&self, field: &str) -> bool {
+        self.fields.is_empty() || self.fields.iter().any(|kept| kept == field)
+    }
+}
+
+impl<S> Layer<S> for SpanFieldsLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attributes: &Attributes<'_>, id: &Id, context: Context<'_, S>) {
+        let Some(span) = context.span(id) else {
+            return;
+        };
+        let mut fields = SpanFields::default();
+        attributes.record(&mut FieldVisitor {
+            layer: self,
+            fields: &mut fields,
+        });
+        span.extensions_mut().insert(fields);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, context: Context<'_, S>) {
+        let Some(span) = context.span(id) else {
+            return;
+        };
+        let mut extensions = span.extensions_mut();
+        if let Some(fields) = extensions.get_mut::<SpanFields>() {
+            values.record(&mut FieldVisitor {
+                layer: self,
+                fields,
+            });
+        }
+    }
+}
+
+/// When a timed span was created, kept in its extensions.
+struct Started(Instant);
+
+/// Finds the [`TIMED_FIELD`] marker.
+#[derive(Default)]
+struct TimedVisitor(bool);
+
+impl Visit for TimedVisitor {
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        if field.name() == TIMED_FIELD {
+            self.0 = value;
+        }
+    }
+
+    fn record_debug(&mut self, _field: &Field, _value: &dyn fmt::Debug) {}
+}
+
+/// A `tracing_subscriber` layer recording the duration of timed spans, see
+/// [`crate::span_fields`]. The duration runs from the creation of the span until it is closed,
+/// measured with the clock of the metrics.
+#[derive(Debug, Clone, Default)]
+pub struct SpanTimingLayer {
+    handle: Option<MetricsHandle>,
+}
+
+impl SpanTimingLayer {
+    /// Records into the given handle instead of the current context.
+    #[must_use]
+    pub fn handle(mut self, handle: MetricsHandle) -> Self {
+        self.handle = Some(handle);
+        self
+    }
+}
+
+impl<S> Layer<S> for SpanTimingLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attributes: &Attributes<'_>, id: &Id, context: Context<'_, S>) {
+        if attributes.metadata().fields().field(TIMED_FIELD).is_none() {
+            return;
+        }
+        let mut visitor = TimedVisitor::default();
+        attributes.record(&mut visitor);
+        if !visitor.0 {
+            return;
+        }
+        let started = match &self.handle {
+            Some(handle) => Some(handle.with(|metrics| metrics.clock.instant())),
+            None => context::with_current(|metrics| metrics.clock.instant()),
+        };
+        if let (Some(started), Some(span)) = (started, context.span(id)) {
+            span.extensions_mut().insert(Started(started));
+        }
+    }
+
+    fn on_close(&self, id: Id, context: Context<'_, S>) {
+        let Some(span) = context.span(&id) else {
+            return;
+        };
+        let Some(started) = span.extensions().get::<Started>().map(|started| started.0) else {
+            return;
+        };
+        let name = span.name();
+        drop(span);
+        let record = |metrics: &mut Metrics| {
+            let elapsed = metrics.clock.instant().saturating_duration_since(started);
+            metrics.add_sample(
+                name,
+                MetricUnit::Milliseconds,
+                elapsed.as_secs_f64() * 1000.0,
+            );
+        };
+        match &self.handle {
+            Some(handle) => handle.with(record),
+            None => {
+                context::with_current(record);
+            }
+        }
+    }
+}
+
+/// Returns the fields of the current span and its parents, inner spans first.
+fn current_span_fields(names: &[(String, AttachAs)]) -> Vec<(String, String, AttachAs)> {
+    tracing::Span::current()
+        .with_subscriber(|(id, dispatch)| {
+            let mut found = Vec::new();
+            let Some(registry) = dispatch.downcast_ref::<Registry>() else {
+                return found;
+            };
+            let Some(span) = registry.span(id) else {
+                return found;
+            };
+            for span in span.scope() {
+                let extensions = span.extensions();
+                let Some(fields) = extensions.get::<SpanFields>() else {
+                    continue;
+                };
+                for (name, attach) in names {
+                    if found.iter().any(|(key, _, _)| key == name) {
+                        continue;
+                    }
+                    if let Some((_, value)) = fields.0.iter().find(|(key, _)| key == name) {
+                        found.push((name.clone(), value.clone(), *attach));
+                    }
+                }
+            }
+            found
+        })
+        .unwrap_or_default()
+}
+
+impl Metrics {
+    /// Returns the scoped dimensions merged with the span fields attached as dimensions, and
+    /// the span fields attached as properties, for a metric recorded now.
+    pub(crate) fn span_context(&self, scoped: &Dimensions) -> (Dimensions, Vec<(String, String)>) {
+        if self.span_fields.is_empty() {
+            return (scoped.clone(), Vec::new());
+        }
+        let mut dimensions = Dimensions::default();
+        let mut properties = Vec::new();
+        for (key, value, attach) in current_span_fields(&self.span_fields) {
+            match attach {
+                AttachAs::Dimension => dimensions.insert(&key, &value),
+                AttachAs::Property => properties.push((key, value)),
+                AttachAs::Off => {}
+            }
+        }
+        (dimensions.merged(scoped), properties)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+    use crate::sink::RecordingSink;
+    use crate::MetricUnit;
+
+    #[test]
+    fn should_record_duration_of_timed_spans() {
+        let handle = MetricsHandle::new(Metrics::new("test", "service", "orders"));
+        let subscriber =
+            tracing_subscriber::registry().with(SpanTimingLayer::default().handle(handle.clone()));
+
+        tracing::subscriber::with_default(subscriber, || {
+            for _ in 0..2 {
+                let _span = tracing::info_span!("load_order", metrics.timed = true).entered();
+            }
+            let _untimed = tracing::info_span!("untimed").entered();
+            let _disabled = tracing::info_span!("disabled", metrics.timed = false).entered();
+        });
+
+        handle.with(|metrics| {
+            assert_eq!(metrics.values_of("load_order").map(<[f64]>::len), Some(2));
+            assert_eq!(metrics.values_of("untimed"), None);
+            assert_eq!(metrics.values_of("disabled"), None);
+        });
+    }
+
+    #[test]
+    fn should_split_payloads_by_span_properties() {
+        let subscriber = tracing_subscriber::registry().with(SpanFieldsLayer::default());
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .span_field("operation", AttachAs::Property)
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+
+        tracing::subscriber::with_default(subscriber, || {
+            for operation in ["create", "delete", "create"] {
+                let _span = tracing::info_span!("order", operation).entered();
+                metrics.increment("orders", 1.0);
+            }
+        });
+        metrics.flush_metrics();
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads.len(), 2);
+        assert_eq!(payloads[0]["operation"], "create");
+        assert_eq!(payloads[0]["orders"], 2.0);
+        assert_eq!(payloads[1]["operation"], "delete");
+        assert_eq!(payloads[1]["orders"], 1.0);
+    }
+
+    #[test]
+    fn should_attach_fields_of_current_span() {
+        let subscriber = tracing_subscriber::registry().with(SpanFieldsLayer::default().fields([
+            "tenant_id",
+            "operation",
+            "attempt",
+        ]));
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .span_field("tenant_id", AttachAs::Dimension)
+            .span_field("operation", AttachAs::Property)
+            .span_field("attempt", AttachAs::Property)
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+
+        tracing::subscriber::with_default(subscriber, || {
+            let request = tracing::info_span!("request", tenant_id = "acme", operation = "list");
+            let _request = request.enter();
+            let order = tracing::info_span!("order", operation = "create", attempt = 2);
+            let _order = order.enter();
+            metrics.increment("orders", 1.0);
+            metrics.add_sample("latency", MetricUnit::Milliseconds, 10.0);
+            metrics.add_sample("latency", MetricUnit::Milliseconds, 20.0);
+        });
+        metrics.increment("outside", 1.0);
+        metrics.flush_metrics();
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads.len(), 2);
+        assert!(metrics.properties.0.is_empty());
+        assert_eq!(payloads[0]["tenant_id"], "acme");
+        assert_eq!(payloads[0]["operation"], "create");
+        assert_eq!(payloads[0]["attempt"], "2");
+        assert_eq!(payloads[0]["orders"], 1.0);
+        assert_eq!(payloads[0]["latency"], serde_json::json!([10.0, 20.0]));
+        assert!(payloads[1].get("tenant_id").is_none());
+        assert!(payloads[1].get("operation").is_none());
+        assert_eq!(payloads[1]["outside"], 1.0);
+    }
+}
@@ -0,0 +1,402 @@
+//! Crash-resilient spill of unflushed metrics.
+//!
+//! If the process is killed before the flush (a timeout or running out of memory), the buffered
+//! metrics are lost. With a [`Spill`] the buffer is periodically persisted to a file in `/tmp`,
+//! which survives a restart of the runtime in the same execution environment. The next
+//! `Metrics` object built with the same spill file emits the orphaned payloads, tagged with
+//! the `replayed` property, and removes the file:
+//!
+//! ```no_run
+//! use std::time::Duration;
+//!
+//! use lambda_helpers_metrics::spill::Spill;
+//! use lambda_helpers_metrics::Metrics;
+//!
+//! let metrics = Metrics::builder("custom_lambdas")
+//!     .spill(Spill::new("/tmp/metrics.spill").interval(Duration::from_millis(500)))
+//!     .build()
+//!     .unwrap();
+//! ```
+//!
+//! The buffer is serialized when a metric is added, at most once per interval, and written to
+//! the file by a worker thread, so recording a metric never waits for the disk. The file is
+//! removed once the buffer is flushed. Each `Metrics` object needs its own spill file.
+//! Orphaned payloads are replayed by the worker too, before it writes the first snapshot, so
+//! building `Metrics` doesn't touch the file; they keep their original timestamps. Errors are
+//! reported like other errors which aren't returned, see [`crate::MetricsBuilder::on_error`].
+//! Where threads can't be spawned, e.g. on WASI, the file is written and replayed inline.
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::error::ErrorCallback;
+use crate::sink::MetricsSink;
+use crate::{mode, platform, Metrics, MetricsError};
+
+pub const REPLAYED_PROPERTY: &str = "replayed";
+pub const DEFAULT_SPILL_PATH: &str = "/tmp/lambda_helpers_metrics.spill";
+pub const DEFAULT_SPILL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Where and how often the buffered metrics are persisted.
+#[derive(Debug, Clone)]
+pub struct Spill {
+    path: PathBuf,
+    interval: Duration,
+    last_spilled: Option<Instant>,
+    /// Set when the `Metrics` object is built.
+    writer: Option<Arc<WriterHandle>>,
+}
+
+impl Default for Spill {
+    fn default() -> Self {
+        Self::new(DEFAULT_SPILL_PATH)
+    }
+}
+
+impl Spill {
+    /// Persists the buffer to the given file.
+    #[must_use]
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            interval: DEFAULT_SPILL_INTERVAL,
+            last_spilled: None,
+            writer: None,
+        }
+    }
+
+    /// Sets the minimum time between two writes of the file, 1 second by default.
+    #[must_use]
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Returns the path of the spill file.
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[derive(Debug, Default)]
+struct Pending {
+    /// The latest serialized buffer, replacing older ones not written yet.
+    snapshot: Option<Vec<u8>>,
+    /// The orphaned file is not replayed yet.
+    replay: bool,
+    closed: bool,
+}
+
+/// Replays and writes the spill file, on a worker thread or inline.
+#[derive(Debug)]
+struct Writer {
+    path: PathBuf,
+    pending: Mutex<Pending>,
+    changed: Condvar,
+    /// Held while the file is read or written, taken after `pending`.
+    file: Mutex<()>,
+    sink: Arc<dyn MetricsSink>,
+    dry_run: bool,
+    on_error: Option<ErrorCallback>,
+}
+
+impl Writer {
+    fn lock(&self) -> MutexGuard<'_, Pending> {
+        self.pending.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn lock_file(&self) -> MutexGuard<'_, ()> {
+        self.file.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn wait<'a>(&self, pending: MutexGuard<'a, Pending>) -> MutexGuard<'a, Pending> {
+        self.changed
+            .wait(pending)
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn report(&self, err: &MetricsError) {
+        mode::report(err);
+        if let Some(callback) = &self.on_error {
+            (callback.0)(err);
+        }
+    }
+
+    /// Replays the orphaned file, then writes the snapshots until the last handle is dropped.
+    fn work(&self) {
+        loop {
+            let mut pending = self.lock();
+            while !pending.replay && pending.snapshot.is_none() && !pending.closed {
+                pending = self.wait(pending);
+            }
+            let file = self.lock_file();
+            if pending.replay {
+                drop(pending);
+                self.replay();
+                drop(file);
+                self.lock().replay = false;
+                self.changed.notify_all();
+            } else if let Some(snapshot) = pending.snapshot.take() {
+                drop(pending);
+                self.write(&snapshot);
+            } else {
+                return;
+            }
+        }
+    }
+
+    /// Emits the payloads orphaned by a previous process, tagged with the `replayed` property.
+    fn replay(&self) {
+        let Ok(spilled) = std::fs::read_to_string(&self.path) else {
+            return;
+        };
+        for line in spilled.lines().filter(|line| !line.trim().is_empty()) {
+            let result = serde_json::from_str::<serde_json::Value>(line)
+                .map_err(|err| MetricsError::Serialization(err.to_string()))
+                .and_then(|mut payload| {
+                    if let Some(fields) = payload.as_object_mut() {
+                        fields.insert(REPLAYED_PROPERTY.to_string(), true.into());
+                    }
+                    if self.dry_run {
+                        eprintln!("Dry run, metrics not emitted: {payload}");
+                        Ok(())
+                    } else {
+                        self.sink.emit(&payload.to_string())
+                    }
+                });
+            if let Err(err) = result {
+                self.report(&err);
+            }
+        }
+        self.remove_file();
+    }
+
+    fn write(&self, snapshot: &[u8]) {
+        // written next to the file and renamed, so a crash never leaves a partial file
+        let partial = self.path.with_extension("partial");
+        let result =
+            std::fs::write(&partial, snapshot).and_then(|()| std::fs::rename(&partial, &self.path));
+        if let Err(err) = result {
+            self.report(&err.into());
+        }
+    }
+
+    fn remove_file(&self) {
+        if let Err(err) = std::fs::remove_file(&self.path) {
+            if err.kind() != std::io::ErrorKind::NotFound {
+                self.report(&err.into());
+            }
+        }
+    }
+
+    /// Discards the snapshot not written yet and removes the file, once replayed.
+    fn remove(&self) {
+        let mut pending = self.lock();
+        while pending.replay {
+            pending = self.wait(pending);
+        }
+        pending.snapshot = None;
+        let _file = self.lock_file();
+        drop(pending);
+        self.remove_file();
+    }
+}
+
+/// Stops the worker when the last clone of the spill is dropped, after the pending write.
+#[derive(Debug)]
+struct WriterHandle {
+    writer: Arc<Writer>,
+    /// The worker couldn't be started, so the file is written inline.
+    inline: bool,
+}
+
+impl WriterHandle {
+    fn queue(&self, snapshot: Vec<u8>) {
+        if self.inline {
+            let _file = self.writer.lock_file();
+            self.writer.write(&snapshot);
+        } else {
+            self.writer.lock().snapshot = Some(snapshot);
+            self.writer.changed.notify_all();
+        }
+    }
+}
+
+impl Drop for WriterHandle {
+    fn drop(&mut self) {
+        self.writer.lock().closed = true;
+        self.writer.changed.notify_all();
+    }
+}
+
+impl Metrics {
+    /// Starts the worker writing the spill file, which first replays the orphaned payloads.
+    pub(crate) fn start_spill(&mut self) {
+        let Some(spill) = &mut self.spill else {
+            return;
+        };
+        let writer = Arc::new(Writer {
+            path: spill.path.clone(),
+            pending: Mutex::new(Pending {
+                replay: true,
+                ..Pending::default()
+            }),
+            changed: Condvar::new(),
+            file: Mutex::new(()),
+            sink: Arc::clone(&self.sink),
+            dry_run: self.dry_run,
+            on_error: self.on_error.clone(),
+        });
+        let worker = Arc::clone(&writer);
+        let inline = match platform::spawn("metrics-spill", move || worker.work()) {
+            Ok(()) => false,
+            Err(_) => {
+                writer.lock().replay = false;
+                writer.replay();
+                true
+            }
+        };
+        spill.writer = Some(Arc::new(WriterHandle { writer, inline }));
+    }
+
+    /// Persists the buffered metrics to the spill file now, see [`crate::spill`].
+    /// The buffer is serialized here and written by the worker. Does nothing if no spill is set.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if serialization fails
+    pub fn spill(&mut self) -> Result<(), MetricsError> {
+        if self.spill.is_none() {
+            return Ok(());
+        }
+        let bytes = self.to_json_bytes()?;
+        let now = self.clock.instant();
+        if let Some(spill) = &mut self.spill {
+            if let Some(writer) = &spill.writer {
+                writer.queue(bytes);
+            }
+            spill.last_spilled = Some(now);
+        }
+        Ok(())
+    }
+
+    /// Persists the buffer if the interval elapsed since the last write.
+    pub(crate) fn spill_if_due(&mut self) {
+        let now = self.clock.instant();
+        let due = self.spill.as_ref().is_some_and(|spill| {
+            spill
+                .last_spilled
+                .is_none_or(|last| now.duration_since(last) >= spill.interval)
+        });
+        if due {
+            if let Err(err) = self.spill() {
+                self.report_error(&err);
+            }
+        }
+    }
+
+    /// Removes the spill file once the buffer it holds is flushed.
+    pub(crate) fn remove_spill(&mut self) {
+        let Some(spill) = &mut self.spill else {
+            return;
+        };
+        if spill.last_spilled.take().is_some() {
+            if let Some(handle) = &spill.writer {
+                handle.writer.remove();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::{NullSink, RecordingSink};
+    use crate::MetricUnit;
+
+    fn spill_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "lambda_helpers_metrics-{name}-{}.spill",
+            std::process::id()
+        ))
+    }
+
+    /// Polls `f` until the worker did its part.
+    fn wait_for<T>(f: impl Fn() -> Option<T>) -> T {
+        for _ in 0..500 {
+            if let Some(value) = f() {
+                return value;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("the spill worker didn't finish in time");
+    }
+
+    #[test]
+    fn should_replay_metrics_of_crashed_process() {
+        let path = spill_path("replay");
+        let mut crashed = Metrics::builder("test")
+            .dimension("service", "orders")
+            .spill(Spill::new(&path).interval(Duration::ZERO))
+            .sink(NullSink)
+            .build()
+            .unwrap();
+        crashed.add_metric("orders", MetricUnit::Count, 2.0);
+        wait_for(|| path.exists().then_some(()));
+        // killed before the flush
+        std::mem::forget(crashed);
+
+        let sink = RecordingSink::default();
+        let _metrics = Metrics::builder("test")
+            .spill(Spill::new(&path))
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+
+        let payloads = wait_for(|| {
+            let payloads = sink.payloads();
+            (!payloads.is_empty()).then_some(payloads)
+        });
+        assert_eq!(payloads.len(), 1);
+        assert_eq!(payloads[0]["orders"], 2.0);
+        assert_eq!(payloads[0]["service"], "orders");
+        assert_eq!(payloads[0][REPLAYED_PROPERTY], true);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn should_remove_spill_file_after_flush() {
+        let path = spill_path("flush");
+        let mut metrics = Metrics::builder("test")
+            .spill(Spill::new(&path).interval(Duration::ZERO))
+            .sink(NullSink)
+            .build()
+            .unwrap();
+
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+        wait_for(|| path.exists().then_some(()));
+        metrics.flush_metrics();
+
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn should_report_failed_writes_to_on_error() {
+        let path = std::env::temp_dir()
+            .join("lambda_helpers_metrics-missing-dir")
+            .join("metrics.spill");
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&errors);
+        let mut metrics = Metrics::builder("test")
+            .spill(Spill::new(&path).interval(Duration::ZERO))
+            .sink(NullSink)
+            .on_error(move |err| recorded.lock().unwrap().push(err.to_string()))
+            .build()
+            .unwrap();
+
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+
+        wait_for(|| (!errors.lock().unwrap().is_empty()).then_some(()));
+    }
+}
@@ -0,0 +1,124 @@
+//! Stage dimension taken from an environment variable.
+//!
+//! Fleets deployed to many stages often name them inconsistently (`prod-us-east-1`,
+//! `production`...). A [`StageDimension`] reads the stage from an environment variable,
+//! maps the value through a table and adds it as a dimension, so all functions report
+//! the same stage names. Values missing from the table are used as they are, and nothing
+//! is added if the variable is not set.
+//!
+//! ```
+//! use lambda_helpers_metrics::stage::StageDimension;
+//! use lambda_helpers_metrics::Metrics;
+//!
+//! let metrics = Metrics::builder("custom_lambdas")
+//!     .stage_dimension(
+//!         StageDimension::new("APP_STAGE")
+//!             .map("prod-us-east-1", "prod")
+//!             .map("prod-eu-west-1", "prod"),
+//!     )
+//!     .build()
+//!     .unwrap();
+//! ```
+//!
+//! It can also be set in the [configuration](crate::config):
+//!
+//! ```json
+//! {
+//!     "stage": {
+//!         "env_var": "APP_STAGE",
+//!         "dimension": "stage",
+//!         "mapping": { "prod-us-east-1": "prod" }
+//!     }
+//! }
+//! ```
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+
+pub const DEFAULT_STAGE_DIMENSION: &str = "stage";
+
+fn default_dimension() -> String {
+    DEFAULT_STAGE_DIMENSION.to_string()
+}
+
+/// Where the stage is read from and how it is mapped.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StageDimension {
+    env_var: String,
+    #[serde(default = "default_dimension")]
+    dimension: String,
+    #[serde(default)]
+    mapping: BTreeMap<String, String>,
+}
+
+impl StageDimension {
+    /// Reads the stage from the given environment variable, added as the `stage` dimension.
+    #[must_use]
+    pub fn new(env_var: &str) -> Self {
+        Self {
+            env_var: env_var.to_string(),
+            dimension: default_dimension(),
+            mapping: BTreeMap::new(),
+        }
+    }
+
+    /// Sets the key of the dimension.
+    #[must_use]
+    pub fn dimension(mut self, key: &str) -> Self {
+        self.dimension = key.to_string();
+        self
+    }
+
+    /// Maps the value `from` of the variable to the stage `to`.
+    #[must_use]
+    pub fn map(mut self, from: &str, to: &str) -> Self {
+        self.mapping.insert(from.to_string(), to.to_string());
+        self
+    }
+
+    /// Returns the key and value of the dimension, if the variable is set.
+    pub(crate) fn resolve_from(
+        &self,
+        var: impl Fn(&str) -> Option<String>,
+    ) -> Option<(String, String)> {
+        let value = var(&self.env_var)?;
+        let value = value.trim();
+        if value.is_empty() {
+            return None;
+        }
+        let stage = self.mapping.get(value).map_or(value, String::as_str);
+        Some((self.dimension.clone(), stage.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::environment::tests::vars;
+
+    #[test]
+    fn should_map_stage_from_env_var() {
+        let stage = StageDimension::new("APP_STAGE").map("prod-us-east-1", "prod");
+
+        assert_eq!(
+            stage.resolve_from(vars(&[("APP_STAGE", "prod-us-east-1")])),
+            Some(("stage".to_string(), "prod".to_string()))
+        );
+        assert_eq!(
+            stage.resolve_from(vars(&[("APP_STAGE", "dev")])),
+            Some(("stage".to_string(), "dev".to_string()))
+        );
+        assert_eq!(stage.resolve_from(vars(&[("APP_STAGE", " ")])), None);
+        assert_eq!(stage.resolve_from(vars(&[])), None);
+    }
+
+    #[test]
+    fn should_deserialize_with_default_dimension() {
+        let stage: StageDimension =
+            serde_json::from_str(r#"{ "env_var": "APP_STAGE", "mapping": { "p": "prod" } }"#)
+                .unwrap();
+
+        assert_eq!(stage, StageDimension::new("APP_STAGE").map("p", "prod"));
+    }
+}
@@ -0,0 +1,268 @@
+//! Watchdog for buffered metrics which are not flushed.
+//!
+//! Metrics kept in a global or long-lived context are only emitted when the buffer is flushed.
+//! A missing flush goes unnoticed until the process crashes or is frozen, and everything
+//! buffered since the last flush is lost with it. The watchdog notices a buffer holding
+//! metrics for longer than a duration or a number of invocations:
+//!
+//! ```
+//! use std::time::Duration;
+//!
+//! use lambda_helpers_metrics::staleness::{StalenessAction, StalenessWatchdog};
+//! use lambda_helpers_metrics::Metrics;
+//!
+//! let mut metrics = Metrics::builder("custom_lambdas")
+//!     .staleness_watchdog(
+//!         StalenessWatchdog::new()
+//!             .max_age(Duration::from_secs(60))
+//!             .max_invocations(10)
+//!             .action(StalenessAction::Flush),
+//!     )
+//!     .build()
+//!     .unwrap();
+//! metrics.mark_invocation();
+//! ```
+//!
+//! There is no background thread: the buffer is checked when a metric is added and when an
+//! invocation starts, see [`Metrics::mark_invocation`], which `Metrics::attach_context` calls
+//! with the `lambda` feature.
+use std::time::{Duration, Instant};
+
+use crate::{Metrics, MetricsError};
+
+/// What the watchdog does with a stale buffer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StalenessAction {
+    /// Reports a [`MetricsError::StaleBuffer`] to the `on_error` callback and stderr, once per
+    /// buffer, and keeps the metrics buffered.
+    #[default]
+    Report,
+    /// Flushes the buffer.
+    Flush,
+}
+
+/// When a buffer is stale and what to do about it, see [`crate::staleness`].
+/// Without a limit set, a buffer is never stale.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StalenessWatchdog {
+    max_age: Option<Duration>,
+    max_invocations: Option<u32>,
+    action: StalenessAction,
+}
+
+impl StalenessWatchdog {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the time after which metrics buffered since the last flush are stale.
+    #[must_use]
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Sets the number of invocations started after which metrics buffered since the last
+    /// flush are stale.
+    #[must_use]
+    pub fn max_invocations(mut self, max_invocations: u32) -> Self {
+        self.max_invocations = Some(max_invocations);
+        self
+    }
+
+    /// Sets what happens with a stale buffer, [`StalenessAction::Report`] by default.
+    #[must_use]
+    pub fn action(mut self, action: StalenessAction) -> Self {
+        self.action = action;
+        self
+    }
+}
+
+/// State of the watchdog for the current buffer.
+#[derive(Debug)]
+pub(crate) struct Staleness {
+    watchdog: StalenessWatchdog,
+    /// When the first metric of the buffer was added.
+    since: Option<Instant>,
+    /// Invocations started since the first metric of the buffer was added.
+    invocations: u32,
+    reported: bool,
+}
+
+impl Staleness {
+    pub(crate) fn new(watchdog: StalenessWatchdog) -> Self {
+        Self {
+            watchdog,
+            since: None,
+            invocations: 0,
+            reported: false,
+        }
+    }
+
+    /// Returns the error describing why the buffer is stale, if it is.
+    fn check(&self, now: Instant) -> Option<MetricsError> {
+        let since = self.since?;
+        let age = now.saturating_duration_since(since);
+        let too_old = self.watchdog.max_age.is_some_and(|max_age| age > max_age);
+        let too_many = self
+            .watchdog
+            .max_invocations
+            .is_some_and(|max_invocations| self.invocations >= max_invocations);
+        (too_old || too_many).then(|| {
+            MetricsError::StaleBuffer(format!(
+                "buffered for {}ms and {} invocations without a flush",
+                age.as_millis(),
+                self.invocations
+            ))
+        })
+    }
+}
+
+impl Metrics {
+    /// Counts the start of an invocation for the watchdog set with
+    /// [`crate::MetricsBuilder::staleness_watchdog`], and checks the buffer.
+    pub fn mark_invocation(&mut self) {
+        if let Some(staleness) = &mut self.staleness {
+            if staleness.since.is_some() {
+                staleness.invocations += 1;
+            }
+        }
+        self.check_staleness();
+    }
+
+    /// Flushes or reports the buffer if it is stale, see [`crate::staleness`].
+    pub(crate) fn check_staleness(&mut self) {
+        let now = self.clock.instant();
+        let Some(staleness) = &mut self.staleness else {
+            return;
+        };
+        let Some(err) = staleness.check(now) else {
+            return;
+        };
+        match staleness.watchdog.action {
+            StalenessAction::Flush => {
+                // reset before flushing, metrics recorded by the flush itself check again
+                *staleness = Staleness::new(staleness.watchdog);
+                self.flush_metrics();
+            }
+            StalenessAction::Report if !staleness.reported => {
+                staleness.reported = true;
+                self.report_error(&err);
+            }
+            StalenessAction::Report => {}
+        }
+    }
+
+    /// Starts the age of the buffer with its first metric.
+    pub(crate) fn mark_buffered(&mut self) {
+        let now = self.clock.instant();
+        if let Some(staleness) = &mut self.staleness {
+            staleness.since.get_or_insert(now);
+        }
+    }
+
+    /// Restarts the watchdog once the buffer is cleared.
+    pub(crate) fn reset_staleness(&mut self) {
+        if let Some(staleness) = &mut self.staleness {
+            *staleness = Staleness::new(staleness.watchdog);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use chrono::Utc;
+
+    use super::*;
+    use crate::clock::{Clock, ManualClock};
+    use crate::sink::RecordingSink;
+    use crate::MetricUnit;
+
+    #[test]
+    fn should_flush_buffer_older_than_max_age() {
+        let clock = ManualClock::new(Utc::now());
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .clock(clock.clone())
+            .staleness_watchdog(
+                StalenessWatchdog::new()
+                    .max_age(Duration::from_secs(60))
+                    .action(StalenessAction::Flush),
+            )
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+        clock.advance(Duration::from_secs(30));
+        metrics.add_metric("refunds", MetricUnit::Count, 1.0);
+        assert!(sink.payloads().is_empty());
+        clock.advance(Duration::from_secs(31));
+        metrics.add_metric("returns", MetricUnit::Count, 1.0);
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads.len(), 1);
+        assert_eq!(payloads[0]["refunds"], 1.0);
+        assert!(payloads[0].get("returns").is_none());
+        assert_eq!(metrics.len(), 1);
+    }
+
+    #[test]
+    fn should_report_buffer_kept_over_max_invocations_once() {
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let recorded = reported.clone();
+        let mut metrics = Metrics::builder("test")
+            .staleness_watchdog(StalenessWatchdog::new().max_invocations(2))
+            .on_error(move |err| recorded.lock().unwrap().push(err.to_string()))
+            .sink(crate::sink::NullSink)
+            .build()
+            .unwrap();
+
+        metrics.mark_invocation();
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+        metrics.mark_invocation();
+        assert!(reported.lock().unwrap().is_empty());
+        metrics.mark_invocation();
+        metrics.mark_invocation();
+        assert_eq!(reported.lock().unwrap().len(), 1);
+        assert_eq!(metrics.len(), 1);
+
+        metrics.flush_metrics();
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+        metrics.mark_invocation();
+        assert_eq!(reported.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn should_flush_stale_buffer_once_with_metrics_recorded_at_flush() {
+        let clock = ManualClock::new(Utc::now());
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .clock(clock.clone())
+            .near_timeout_threshold(Duration::from_secs(2))
+            .staleness_watchdog(
+                StalenessWatchdog::new()
+                    .max_age(Duration::from_secs(60))
+                    .action(StalenessAction::Flush),
+            )
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+        metrics.set_deadline(clock.now() + Duration::from_secs(600));
+
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+        clock.advance(Duration::from_secs(61));
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads.len(), 1);
+        assert_eq!(payloads[0]["orders"], 1.0);
+        assert!(payloads[0]
+            .get(crate::deadline::REMAINING_TIME_METRIC)
+            .is_some());
+        assert_eq!(metrics.len(), 1);
+    }
+}
@@ -0,0 +1,103 @@
+//! Metrics of booleans and of named states.
+//!
+//! [`Metrics::add_flag`] records a boolean as 0 or 1, e.g. a feature-flag evaluation or a health
+//! check, so its average is the rate of `true`. [`StateTracker`] records the transitions
+//! between named states, e.g. of a circuit breaker:
+//!
+//! ```
+//! use lambda_helpers_metrics::state::StateTracker;
+//! use lambda_helpers_metrics::Metrics;
+//!
+//! let mut metrics = Metrics::new("custom_lambdas", "service", "dummy_service");
+//! metrics.add_flag("new_checkout_enabled", true);
+//!
+//! let mut breaker = StateTracker::new("payment_breaker", "closed");
+//! breaker.transition(&mut metrics, "open");
+//! ```
+//!
+//! A transition increments `<name>_transitions` with the `from_state` and `to_state`
+//! dimensions, so alarms can target a single transition, e.g. to `open`. The tracker is
+//! independent of the `Metrics` object, so it can outlive invocations in a static or a field.
+use crate::{Dimensions, MetricUnit, Metrics};
+
+/// Dimension holding the state left by a transition.
+pub const FROM_STATE_DIMENSION: &str = "from_state";
+/// Dimension holding the state entered by a transition.
+pub const TO_STATE_DIMENSION: &str = "to_state";
+
+/// The current state of something with named states, see [`crate::state`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateTracker {
+    name: String,
+    state: String,
+}
+
+impl StateTracker {
+    /// Starts in `initial`, which isn't recorded as a transition.
+    #[must_use]
+    pub fn new(name: &str, initial: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            state: initial.to_string(),
+        }
+    }
+
+    /// Returns the current state.
+    #[must_use]
+    pub fn state(&self) -> &str {
+        &self.state
+    }
+
+    /// Moves to `state`, recording the transition. Returns `false`, and records nothing,
+    /// if it is already the current state.
+    pub fn transition(&mut self, metrics: &mut Metrics, state: &str) -> bool {
+        if self.state == state {
+            return false;
+        }
+        let mut dimensions = Dimensions::default();
+        dimensions.insert(FROM_STATE_DIMENSION, &self.state);
+        dimensions.insert(TO_STATE_DIMENSION, state);
+        metrics.increment_with_dimensions(&format!("{}_transitions", self.name), 1.0, dimensions);
+        state.clone_into(&mut self.state);
+        true
+    }
+}
+
+impl Metrics {
+    /// Records a boolean as a `Count` of 1 for `true` and 0 for `false`, see [`crate::state`].
+    pub fn add_flag(&mut self, name: &str, value: bool) {
+        self.add_metric(name, MetricUnit::Count, f64::from(u8::from(value)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::RecordingSink;
+
+    #[test]
+    fn should_record_flags_and_state_transitions() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test").sink(sink.clone()).build().unwrap();
+        let mut breaker = StateTracker::new("breaker", "closed");
+
+        metrics.add_flag("enabled", true);
+        metrics.add_flag("healthy", false);
+        assert!(breaker.transition(&mut metrics, "open"));
+        assert!(!breaker.transition(&mut metrics, "open"));
+        assert!(breaker.transition(&mut metrics, "closed"));
+        assert!(breaker.transition(&mut metrics, "open"));
+        metrics.flush_metrics();
+
+        assert_eq!(breaker.state(), "open");
+        let payloads = sink.payloads();
+        assert_eq!(payloads.len(), 3);
+        assert_eq!(payloads[0]["enabled"], 1.0);
+        assert_eq!(payloads[0]["healthy"], 0.0);
+        assert_eq!(payloads[1]["breaker_transitions"], 2.0);
+        assert_eq!(payloads[1][FROM_STATE_DIMENSION], "closed");
+        assert_eq!(payloads[1][TO_STATE_DIMENSION], "open");
+        assert_eq!(payloads[2]["breaker_transitions"], 1.0);
+        assert_eq!(payloads[2][TO_STATE_DIMENSION], "closed");
+    }
+}
@@ -0,0 +1,222 @@
+//! Metrics for Lambda functions invoked as Step Functions tasks.
+//!
+//! Step Functions passes the [context object](https://docs.aws.amazon.com/step-functions/latest/dg/input-output-contextobject.html)
+//! to the function only if the state maps it into the input, e.g. with
+//! `"Parameters": { "payload.$": "$", "context.$": "$$" }`.
+//!
+//! ```
+//! use lambda_helpers_metrics::step_functions::StepFunctionsTask;
+//! use lambda_helpers_metrics::Metrics;
+//!
+//! let input = serde_json::json!({
+//!     "payload": {},
+//!     "context": {
+//!         "StateMachine": { "Name": "checkout" },
+//!         "State": { "Name": "ChargeCard", "RetryCount": 0 }
+//!     }
+//! });
+//! let mut metrics = Metrics::new("custom_lambdas", "service", "dummy_service");
+//! let mut task = StepFunctionsTask::from_input(&input);
+//!
+//! task.heartbeat();
+//! let result: Result<(), String> = Ok(());
+//! task.record_outcome(&mut metrics, &result);
+//! ```
+//!
+//! Metrics are dimensioned by `state_machine` and `state`:
+//! - `sfn_task_success` / `sfn_task_failure`
+//! - `sfn_task_duration` in milliseconds, measured from the creation of the task
+//! - `sfn_task_heartbeats`, the number of heartbeats sent
+//! - `sfn_state_retry_count`, the retry count of the state
+//! - `sfn_state_latency` in milliseconds, between entering the state and the start of the task
+use std::time::Instant;
+
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+
+use crate::{Dimensions, MetricUnit, Metrics};
+
+/// Key of the context object in the input used by [`StepFunctionsTask::from_input`].
+pub const DEFAULT_CONTEXT_KEY: &str = "context";
+
+pub const TASK_SUCCESS_METRIC: &str = "sfn_task_success";
+pub const TASK_FAILURE_METRIC: &str = "sfn_task_failure";
+pub const TASK_DURATION_METRIC: &str = "sfn_task_duration";
+pub const TASK_HEARTBEATS_METRIC: &str = "sfn_task_heartbeats";
+pub const STATE_RETRY_COUNT_METRIC: &str = "sfn_state_retry_count";
+pub const STATE_LATENCY_METRIC: &str = "sfn_state_latency";
+
+/// A single execution of a Step Functions task.
+#[derive(Debug, Clone)]
+pub struct StepFunctionsTask {
+    state_machine: String,
+    state: String,
+    retry_count: Option<f64>,
+    entered_time: Option<DateTime<Utc>>,
+    started: Instant,
+    started_at: DateTime<Utc>,
+    heartbeats: u32,
+}
+
+impl StepFunctionsTask {
+    /// Reads the context object from the `context` key of the input.
+    #[must_use]
+    pub fn from_input(input: &Value) -> Self {
+        Self::from_input_with_key(input, DEFAULT_CONTEXT_KEY)
+    }
+
+    /// Reads the context object from the given key of the input.
+    /// Missing fields are reported as `unknown`.
+    #[must_use]
+    pub fn from_input_with_key(input: &Value, key: &str) -> Self {
+        Self::from_context(input.get(key).unwrap_or(&Value::Null))
+    }
+
+    /// Creates the task from the context object itself.
+    #[must_use]
+    pub fn from_context(context: &Value) -> Self {
+        let text = |pointer: &str| {
+            context
+                .pointer(pointer)
+                .and_then(Value::as_str)
+                .unwrap_or("unknown")
+                .to_string()
+        };
+        Self {
+            state_machine: text("/StateMachine/Name"),
+            state: text("/State/Name"),
+            retry_count: context.pointer("/State/RetryCount").and_then(Value::as_f64),
+            entered_time: context
+                .pointer("/State/EnteredTime")
+                .and_then(Value::as_str)
+                .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+                .map(|time| time.with_timezone(&Utc)),
+            started: Instant::now(),
+            started_at: Utc::now(),
+            heartbeats: 0,
+        }
+    }
+
+    /// Name of the state machine, `unknown` if missing from the context.
+    #[must_use]
+    pub fn state_machine(&self) -> &str {
+        &self.state_machine
+    }
+
+    /// Name of the state, `unknown` if missing from the context.
+    #[must_use]
+    pub fn state(&self) -> &str {
+        &self.state
+    }
+
+    /// Counts a heartbeat, call it whenever `SendTaskHeartbeat` is sent.
+    pub fn heartbeat(&mut self) {
+        self.heartbeats += 1;
+    }
+
+    /// Records a successful task.
+    pub fn succeed(&self, metrics: &mut Metrics) {
+        self.record(metrics, true);
+    }
+
+    /// Records a failed task.
+    pub fn fail(&self, metrics: &mut Metrics) {
+        self.record(metrics, false);
+    }
+
+    /// Records the task as succeeded or failed, depending on the result.
+    pub fn record_outcome<T, E>(&self, metrics: &mut Metrics, result: &Result<T, E>) {
+        self.record(metrics, result.is_ok());
+    }
+
+    fn dimensions(&self) -> Dimensions {
+        let mut dimensions = Dimensions::default();
+        dimensions.insert("state_machine", &self.state_machine);
+        dimensions.insert("state", &self.state);
+        dimensions
+    }
+
+    fn record(&self, metrics: &mut Metrics, success: bool) {
+        let dimensions = self.dimensions();
+        let (recorded, other) = if success {
+            (TASK_SUCCESS_METRIC, TASK_FAILURE_METRIC)
+        } else {
+            (TASK_FAILURE_METRIC, TASK_SUCCESS_METRIC)
+        };
+        metrics.increment_with_dimensions(recorded, 1.0, dimensions.clone());
+        metrics.increment_with_dimensions(other, 0.0, dimensions.clone());
+        metrics.add_sample_with_dimensions(
+            TASK_DURATION_METRIC,
+            MetricUnit::Milliseconds,
+            self.started.elapsed().as_secs_f64() * 1000.0,
+            dimensions.clone(),
+        );
+        metrics.increment_with_dimensions(
+            TASK_HEARTBEATS_METRIC,
+            f64::from(self.heartbeats),
+            dimensions.clone(),
+        );
+        if let Some(retry_count) = self.retry_count {
+            metrics.add_sample_with_dimensions(
+                STATE_RETRY_COUNT_METRIC,
+                MetricUnit::Count,
+                retry_count,
+                dimensions.clone(),
+            );
+        }
+        if let Some(entered_time) = self.entered_time {
+            #[allow(clippy::cast_precision_loss)]
+            let latency = (self.started_at - entered_time).num_milliseconds().max(0) as f64;
+            metrics.add_sample_with_dimensions(
+                STATE_LATENCY_METRIC,
+                MetricUnit::Milliseconds,
+                latency,
+                dimensions,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::RecordingSink;
+
+    #[test]
+    fn should_record_task_metrics_with_context_dimensions() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test").sink(sink.clone()).build().unwrap();
+        let entered = (Utc::now() - chrono::Duration::seconds(2)).to_rfc3339();
+        let input = serde_json::json!({
+            "context": {
+                "StateMachine": { "Name": "checkout" },
+                "State": { "Name": "ChargeCard", "RetryCount": 1, "EnteredTime": entered }
+            }
+        });
+
+        let mut task = StepFunctionsTask::from_input(&input);
+        task.heartbeat();
+        task.heartbeat();
+        task.record_outcome(&mut metrics, &Err::<(), _>("declined"));
+        metrics.flush_metrics();
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads.len(), 1);
+        let payload = &payloads[0];
+        assert_eq!(payload["state_machine"], "checkout");
+        assert_eq!(payload["state"], "ChargeCard");
+        assert_eq!(payload[TASK_FAILURE_METRIC], 1.0);
+        assert_eq!(payload[TASK_SUCCESS_METRIC], 0.0);
+        assert_eq!(payload[TASK_HEARTBEATS_METRIC], 2.0);
+        assert_eq!(payload[STATE_RETRY_COUNT_METRIC], 1.0);
+        assert!(payload[STATE_LATENCY_METRIC].as_f64().unwrap() >= 2000.0);
+    }
+
+    #[test]
+    fn should_fall_back_to_unknown_without_context() {
+        let task = StepFunctionsTask::from_input(&serde_json::json!({ "payload": 1 }));
+
+        assert_eq!(task.state_machine(), "unknown");
+        assert_eq!(task.state(), "unknown");
+    }
+}
@@ -0,0 +1,174 @@
+//! Usage of the ephemeral storage under `/tmp`.
+//!
+//! A function writing temporary files fails with `ENOSPC` once its ephemeral storage is full,
+//! and Lambda has no metric for it. [`Metrics::record_tmp_usage`] walks `/tmp` and records
+//! the bytes used by the files as `tmp_used_bytes`:
+//!
+//! ```
+//! use lambda_helpers_metrics::Metrics;
+//!
+//! let mut metrics = Metrics::builder("custom_lambdas").build().unwrap();
+//! metrics.record_tmp_usage();
+//! ```
+//!
+//! The walk is capped in depth and in number of entries, so a directory with many small files
+//! doesn't slow down the invocation; when a cap is reached the recorded value is a lower bound
+//! and the `tmp_used_bytes_truncated` property is set. Symbolic links are not followed.
+use std::path::PathBuf;
+
+use crate::{MetricUnit, Metrics};
+
+/// Bytes used by the files under the walked directory.
+pub const TMP_USED_METRIC: &str = "tmp_used_bytes";
+/// Property set when a cap was reached before the whole directory was walked.
+pub const TRUNCATED_PROPERTY: &str = "tmp_used_bytes_truncated";
+/// Default maximum depth of the walk, the walked directory being at depth 0.
+pub const DEFAULT_MAX_DEPTH: usize = 8;
+/// Default maximum number of entries visited by the walk.
+pub const DEFAULT_MAX_ENTRIES: usize = 10_000;
+
+/// A capped walk of a directory measuring the bytes used by its files, see [`crate::storage`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageUsage {
+    path: PathBuf,
+    max_depth: usize,
+    max_entries: usize,
+}
+
+/// The result of [`StorageUsage::measure`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MeasuredUsage {
+    /// Bytes used by the files visited.
+    pub bytes: u64,
+    /// Whether a cap was reached, in which case `bytes` is a lower bound.
+    pub truncated: bool,
+}
+
+impl StorageUsage {
+    /// Measures `/tmp`.
+    #[must_use]
+    pub fn tmp() -> Self {
+        Self::new("/tmp")
+    }
+
+    /// Measures the given directory.
+    #[must_use]
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_entries: DEFAULT_MAX_ENTRIES,
+        }
+    }
+
+    /// Sets the maximum depth of the walk, [`DEFAULT_MAX_DEPTH`] by default.
+    #[must_use]
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Sets the maximum number of entries visited, [`DEFAULT_MAX_ENTRIES`] by default.
+    #[must_use]
+    pub fn max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+
+    /// Walks the directory. Entries which can't be read, e.g. removed during the walk,
+    /// are skipped.
+    #[must_use]
+    pub fn measure(&self) -> MeasuredUsage {
+        let mut usage = MeasuredUsage::default();
+        let mut visited = 0;
+        let mut pending = vec![(self.path.clone(), 0)];
+        while let Some((dir, depth)) = pending.pop() {
+            let Ok(entries) = std::fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                if visited == self.max_entries {
+                    usage.truncated = true;
+                    return usage;
+                }
+                visited += 1;
+                let Ok(metadata) = entry.metadata() else {
+                    continue;
+                };
+                if metadata.is_dir() {
+                    if depth < self.max_depth {
+                        pending.push((entry.path(), depth + 1));
+                    } else {
+                        usage.truncated = true;
+                    }
+                } else if metadata.is_file() {
+                    usage.bytes += metadata.len();
+                }
+            }
+        }
+        usage
+    }
+}
+
+impl Metrics {
+    /// Records the bytes used under `/tmp`, see [`crate::storage`].
+    pub fn record_tmp_usage(&mut self) {
+        self.record_storage_usage(&StorageUsage::tmp());
+    }
+
+    /// Records the bytes used under the directory of the walk as `tmp_used_bytes`,
+    /// e.g. with other caps than [`Metrics::record_tmp_usage`].
+    pub fn record_storage_usage(&mut self, usage: &StorageUsage) {
+        let measured = usage.measure();
+        #[allow(clippy::cast_precision_loss)]
+        self.add_metric(TMP_USED_METRIC, MetricUnit::Bytes, measured.bytes as f64);
+        if measured.truncated {
+            self.add_json_property(TRUNCATED_PROPERTY, true);
+        } else {
+            self.properties.0.remove(TRUNCATED_PROPERTY);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::RecordingSink;
+
+    #[test]
+    fn should_measure_files_within_the_caps() {
+        let root = std::env::temp_dir().join(format!("storage_test_{}", std::process::id()));
+        std::fs::create_dir_all(root.join("a/b")).unwrap();
+        std::fs::write(root.join("top.bin"), [0; 100]).unwrap();
+        std::fs::write(root.join("a/middle.bin"), [0; 20]).unwrap();
+        std::fs::write(root.join("a/b/deep.bin"), [0; 3]).unwrap();
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test").sink(sink.clone()).build().unwrap();
+
+        let full = StorageUsage::new(&root).measure();
+        let shallow = StorageUsage::new(&root).max_depth(1).measure();
+        let capped = StorageUsage::new(&root).max_entries(1).measure();
+        metrics.record_storage_usage(&StorageUsage::new(&root).max_depth(1));
+        metrics.flush_metrics();
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(
+            full,
+            MeasuredUsage {
+                bytes: 123,
+                truncated: false
+            }
+        );
+        assert_eq!(
+            shallow,
+            MeasuredUsage {
+                bytes: 120,
+                truncated: true
+            }
+        );
+        assert!(capped.truncated);
+        let payload = &sink.payloads()[0];
+        assert_eq!(payload[TMP_USED_METRIC], 120.0);
+        assert_eq!(payload[TRUNCATED_PROPERTY], true);
+    }
+}
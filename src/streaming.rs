@@ -0,0 +1,184 @@
+//! Metrics for Lambda response streaming.
+//!
+//! Available with the `lambda` feature.
+//!
+//! ```ignore
+//! use lambda_helpers_metrics::context::MetricsHandle;
+//! use lambda_helpers_metrics::streaming::{metered_response, MeteredStream};
+//! use lambda_runtime::streaming::{channel, Body, Response};
+//!
+//! async fn handler(event: LambdaEvent<Value>) -> Result<Response<MeteredStream<Body>>, Error> {
+//!     let metrics = MetricsHandle::new(Metrics::new("custom_lambdas", "service", "dummy_service"));
+//!     let (mut tx, rx) = channel();
+//!     tokio::spawn(async move { tx.send_data("hello".into()).await });
+//!     Ok(metered_response(rx, metrics))
+//! }
+//! ```
+//!
+//! When the stream ends (or is dropped, e.g. when the client disconnects) it records:
+//! - `stream_time_to_first_byte` in milliseconds, if any data was sent
+//! - `stream_bytes` in bytes
+//! - `stream_duration` in milliseconds
+//!
+//! The stream outlives the handler, so the metrics are flushed once they are recorded.
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures_core::Stream;
+use lambda_runtime::streaming::{Body, Response};
+
+use crate::context::MetricsHandle;
+use crate::MetricUnit;
+
+pub const TIME_TO_FIRST_BYTE_METRIC: &str = "stream_time_to_first_byte";
+pub const BYTES_METRIC: &str = "stream_bytes";
+pub const DURATION_METRIC: &str = "stream_duration";
+
+/// A stream wrapper measuring time to first byte, bytes streamed and stream duration.
+#[derive(Debug)]
+pub struct MeteredStream<S> {
+    inner: S,
+    handle: MetricsHandle,
+    started: Instant,
+    first_byte: Option<Duration>,
+    bytes: u64,
+    flush_on_completion: bool,
+    finished: bool,
+}
+
+impl<S> MeteredStream<S> {
+    /// Wraps the stream. Times are measured from now.
+    pub fn new(inner: S, handle: MetricsHandle) -> Self {
+        Self {
+            inner,
+            handle,
+            started: Instant::now(),
+            first_byte: None,
+            bytes: 0,
+            flush_on_completion: true,
+            finished: false,
+        }
+    }
+
+    /// Measures times from the given instant, e.g. the start of the handler.
+    #[must_use]
+    pub fn started_at(mut self, started: Instant) -> Self {
+        self.started = started;
+        self
+    }
+
+    /// Disables flushing the metrics when the stream ends,
+    /// for callers which flush the handle themselves.
+    #[must_use]
+    pub fn without_flush(mut self) -> Self {
+        self.flush_on_completion = false;
+        self
+    }
+
+    fn finish(&mut self) {
+        if self.finished {
+            return;
+        }
+        self.finished = true;
+        let duration = self.started.elapsed();
+        self.handle.with(|metrics| {
+            if let Some(first_byte) = self.first_byte {
+                metrics.add_metric(
+                    TIME_TO_FIRST_BYTE_METRIC,
+                    MetricUnit::Milliseconds,
+                    first_byte.as_secs_f64() * 1000.0,
+                );
+            }
+            #[allow(clippy::cast_precision_loss)]
+            metrics.add_metric(BYTES_METRIC, MetricUnit::Bytes, self.bytes as f64);
+            metrics.add_metric(
+                DURATION_METRIC,
+                MetricUnit::Milliseconds,
+                duration.as_secs_f64() * 1000.0,
+            );
+            if self.flush_on_completion {
+                metrics.flush_metrics();
+            }
+        });
+    }
+}
+
+impl<S, D, E> Stream for MeteredStream<S>
+where
+    S: Stream<Item = Result<D, E>> + Unpin,
+    D: AsRef<[u8]>,
+{
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = Pin::new(&mut self.inner).poll_next(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(data))) => {
+                let len = data.as_ref().len() as u64;
+                if len > 0 && self.first_byte.is_none() {
+                    self.first_byte = Some(self.started.elapsed());
+                }
+                self.bytes += len;
+            }
+            Poll::Ready(None) => self.finish(),
+            Poll::Ready(Some(Err(_))) | Poll::Pending => {}
+        }
+        poll
+    }
+}
+
+/// Wraps a streaming body into a response which records streaming metrics.
+pub fn metered_response(body: Body, handle: MetricsHandle) -> Response<MeteredStream<Body>> {
+    Response::from(MeteredStream::new(body, handle))
+}
+
+impl<S> Drop for MeteredStream<S> {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::task::Waker;
+
+    use super::*;
+    use crate::sink::RecordingSink;
+    use crate::Metrics;
+
+    struct Chunks(VecDeque<Result<Vec<u8>, ()>>);
+
+    impl Stream for Chunks {
+        type Item = Result<Vec<u8>, ()>;
+
+        fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+            Poll::Ready(self.0.pop_front())
+        }
+    }
+
+    #[test]
+    fn should_record_stream_metrics_on_completion() {
+        let sink = RecordingSink::default();
+        let handle =
+            MetricsHandle::new(Metrics::builder("test").sink(sink.clone()).build().unwrap());
+        let chunks = Chunks(VecDeque::from([
+            Ok(b"hello".to_vec()),
+            Ok(b" world".to_vec()),
+        ]));
+        let mut stream = MeteredStream::new(chunks, handle);
+
+        let mut cx = Context::from_waker(Waker::noop());
+        while let Poll::Ready(Some(_)) = Pin::new(&mut stream).poll_next(&mut cx) {}
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads.len(), 1);
+        assert_eq!(payloads[0][BYTES_METRIC], 11.0);
+        assert!(payloads[0][TIME_TO_FIRST_BYTE_METRIC].is_number());
+        assert!(payloads[0][DURATION_METRIC].is_number());
+
+        drop(stream);
+        assert_eq!(sink.payloads().len(), 1);
+    }
+}
@@ -0,0 +1,160 @@
+//! Dimension values with placeholders resolved at flush.
+//!
+//! A dimension added with [`MetricsBuilder::dimension_template`](crate::MetricsBuilder::dimension_template)
+//! or [`Metrics::add_dimension_template`] can contain placeholders like `{request_id}`,
+//! replaced at each flush by the value of the property with that key. Shared configuration can
+//! then describe dimensions before the per-invocation values are known:
+//!
+//! ```
+//! use lambda_helpers_metrics::Metrics;
+//!
+//! let mut metrics = Metrics::builder("custom_lambdas")
+//!     .dimension_template("version", "v{function_version}")
+//!     .build()
+//!     .unwrap();
+//!
+//! // e.g. in `emit_standard_metrics`
+//! metrics.add_property("function_version", "42");
+//! // flushed with `version` = `v42`
+//! ```
+//!
+//! Values of other dimensions are never resolved, so braces in them are kept as they are.
+//! Placeholders without a matching property are left as they are. The resolved value is added
+//! like any other dimension, so the [`DimensionLengthPolicy`](crate::DimensionLengthPolicy)
+//! and the limit of dimensions apply to it; values which can't be added are reported.
+use std::borrow::Cow;
+
+use crate::Metrics;
+
+/// Replaces the `{key}` placeholders of `template` with the values returned by `lookup`.
+pub(crate) fn resolve<'a>(
+    template: &'a str,
+    lookup: impl Fn(&str) -> Option<&'a str>,
+) -> Cow<'a, str> {
+    if !template.contains('{') {
+        return Cow::Borrowed(template);
+    }
+    let mut resolved = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        resolved.push_str(&rest[..start]);
+        let placeholder = &rest[start..];
+        let value = placeholder
+            .find('}')
+            .map(|end| (&placeholder[1..end], end))
+            .filter(|(key, _)| {
+                !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+            })
+            .and_then(|(key, end)| lookup(key).map(|value| (value, end)));
+        match value {
+            Some((value, end)) => {
+                resolved.push_str(value);
+                rest = &placeholder[end + 1..];
+            }
+            None => {
+                resolved.push('{');
+                rest = &placeholder[1..];
+            }
+        }
+    }
+    resolved.push_str(rest);
+    Cow::Owned(resolved)
+}
+
+impl Metrics {
+    /// Adds a dimension whose value is resolved from the properties at each flush, replacing
+    /// the template of the key if present, see [`crate::template`].
+    pub fn add_dimension_template(&mut self, key: &str, template: &str) {
+        match self
+            .dimension_templates
+            .iter_mut()
+            .find(|(existing, _)| existing == key)
+        {
+            Some((_, existing)) => *existing = template.to_string(),
+            None => self
+                .dimension_templates
+                .push((key.to_string(), template.to_string())),
+        }
+    }
+
+    /// Adds the dimensions of the templates, resolved from the current properties.
+    pub(crate) fn resolve_dimension_templates(&mut self) {
+        let resolved = self
+            .dimension_templates
+            .iter()
+            .map(|(key, template)| {
+                let value = resolve(template, |key| self.property(key)).into_owned();
+                (key.clone(), value)
+            })
+            .collect::<Vec<_>>();
+        for (key, value) in resolved {
+            if let Err(err) = self.try_add_dimension(&key, &value) {
+                self.report_error(&err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::RecordingSink;
+    use crate::{DimensionLengthPolicy, MetricUnit, MAX_DIMENSION_VALUE_LEN};
+
+    #[test]
+    fn should_resolve_placeholders() {
+        let lookup = |key: &str| match key {
+            "request_id" => Some("req-1"),
+            "version" => Some("42"),
+            _ => None,
+        };
+
+        assert_eq!(resolve("plain", lookup), "plain");
+        assert_eq!(resolve("{request_id}", lookup), "req-1");
+        assert_eq!(resolve("v{version}-{request_id}", lookup), "v42-req-1");
+        assert_eq!(resolve("{missing}/{version}", lookup), "{missing}/42");
+        assert_eq!(resolve("{not a key} {", lookup), "{not a key} {");
+    }
+
+    #[test]
+    fn should_resolve_dimensions_from_properties_at_flush() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .dimension_template("version", "v{function_version}")
+            .dimension("literal", "{function_version}")
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+
+        metrics.add_property("function_version", "42");
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+        metrics.flush_metrics();
+        metrics.add_property("function_version", "43");
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+        metrics.flush_metrics();
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads[0]["version"], "v42");
+        assert_eq!(payloads[0]["literal"], "{function_version}");
+        assert_eq!(payloads[1]["version"], "v43");
+    }
+
+    #[test]
+    fn should_apply_length_policy_to_resolved_value() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .dimension_length(DimensionLengthPolicy::Error)
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+        metrics.add_dimension_template("request", "{request_id}");
+
+        metrics.add_property("request_id", &"x".repeat(MAX_DIMENSION_VALUE_LEN + 1));
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+        metrics.flush_metrics();
+
+        let payload = &sink.payloads()[0];
+        assert!(payload.get("request").is_none());
+        assert_eq!(payload["orders"], 1.0);
+    }
+}
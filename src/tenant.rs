@@ -0,0 +1,188 @@
+//! Multi-tenant dimensions.
+//!
+//! The dimensions identifying a tenant are configured once, as templates where `{tenant}`
+//! is replaced by the tenant ID:
+//!
+//! ```
+//! use lambda_helpers_metrics::value::Count;
+//! use lambda_helpers_metrics::{Metrics, TenantContext};
+//!
+//! let mut metrics = Metrics::builder("custom_lambdas")
+//!     .dimension("service", "dummy_service")
+//!     .tenant_context(
+//!         TenantContext::new("tenant")
+//!             .dimension("tenant_group", "customers/{tenant}")
+//!             .max_tenants_per_payload(5),
+//!     )
+//!     .build()
+//!     .unwrap();
+//!
+//! metrics.for_tenant("acme").add("orders", Count(3));
+//! ```
+//!
+//! Every tenant creates its own metric series, so the number of distinct tenants buffered at once
+//! is capped: recording for a new tenant above the cap flushes the buffer first.
+use crate::value::IntoMetric;
+use crate::{Dimensions, MetricUnit, Metrics};
+
+pub const TENANT_PLACEHOLDER: &str = "{tenant}";
+pub const DEFAULT_TENANT_DIMENSION: &str = "tenant";
+pub const DEFAULT_MAX_TENANTS_PER_PAYLOAD: usize = 10;
+
+/// Configuration of the dimensions identifying a tenant.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TenantContext {
+    dimensions: Vec<(String, String)>,
+    max_tenants_per_payload: usize,
+}
+
+impl Default for TenantContext {
+    fn default() -> Self {
+        Self::new(DEFAULT_TENANT_DIMENSION)
+    }
+}
+
+impl TenantContext {
+    /// Creates a context where the tenant ID is the value of the `key` dimension.
+    #[must_use]
+    pub fn new(key: &str) -> Self {
+        Self {
+            dimensions: vec![(key.to_string(), TENANT_PLACEHOLDER.to_string())],
+            max_tenants_per_payload: DEFAULT_MAX_TENANTS_PER_PAYLOAD,
+        }
+    }
+
+    /// Adds a tenant dimension, `{tenant}` in the template is replaced by the tenant ID.
+    #[must_use]
+    pub fn dimension(mut self, key: &str, template: &str) -> Self {
+        self.dimensions
+            .push((key.to_string(), template.to_string()));
+        self
+    }
+
+    /// Sets the maximum number of distinct tenants buffered at once, at least 1.
+    #[must_use]
+    pub fn max_tenants_per_payload(mut self, max: usize) -> Self {
+        self.max_tenants_per_payload = max.max(1);
+        self
+    }
+
+    pub(crate) fn dimensions_for(&self, tenant: &str) -> Dimensions {
+        let mut dimensions = Dimensions::default();
+        for (key, template) in &self.dimensions {
+            dimensions.insert(key, &template.replace(TENANT_PLACEHOLDER, tenant));
+        }
+        dimensions
+    }
+}
+
+/// Metrics recorded for a single tenant, see [`Metrics::for_tenant`].
+#[derive(Debug)]
+pub struct TenantScope<'a> {
+    metrics: &'a mut Metrics,
+    dimensions: Dimensions,
+}
+
+impl TenantScope<'_> {
+    /// Adds a metric with the tenant dimensions, see [`Metrics::add_metric`].
+    pub fn add_metric(&mut self, name: &str, unit: MetricUnit, value: f64) {
+        self.metrics
+            .add_metric_with_extra_dimensions(name, unit, value, self.dimensions.clone());
+    }
+
+    /// Adds a metric with the tenant dimensions, see [`Metrics::add`].
+    pub fn add(&mut self, name: &str, value: impl IntoMetric) {
+        let (unit, value) = value.into_metric();
+        self.add_metric(name, unit, value);
+    }
+
+    /// Increments a counter with the tenant dimensions, see [`Metrics::increment`].
+    pub fn increment(&mut self, name: &str, by: f64) {
+        self.metrics
+            .increment_with_dimensions(name, by, self.dimensions.clone());
+    }
+
+    /// Records a sample with the tenant dimensions, see [`Metrics::add_sample`].
+    pub fn add_sample(&mut self, name: &str, unit: MetricUnit, value: f64) {
+        self.metrics
+            .add_sample_with_dimensions(name, unit, value, self.dimensions.clone());
+    }
+}
+
+impl Metrics {
+    /// Returns a scope whose metrics carry the dimensions of the given tenant,
+    /// as configured by the [`TenantContext`].
+    /// If the cap of distinct tenants is reached, the buffered metrics are flushed first.
+    pub fn for_tenant(&mut self, tenant: &str) -> TenantScope<'_> {
+        if !self.buffered_tenants.iter().any(|known| known == tenant) {
+            if self.buffered_tenants.len() >= self.tenant_context.max_tenants_per_payload {
+                self.flush_metrics();
+            }
+            self.buffered_tenants.push(tenant.to_string());
+        }
+        let dimensions = self.tenant_context.dimensions_for(tenant);
+        TenantScope {
+            metrics: self,
+            dimensions,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::RecordingSink;
+    use crate::value::Count;
+
+    #[test]
+    fn should_render_tenant_dimensions() {
+        let context = TenantContext::new("tenant").dimension("group", "customers/{tenant}");
+
+        let dimensions = context.dimensions_for("acme");
+
+        assert_eq!(dimensions.get("tenant"), Some("acme"));
+        assert_eq!(dimensions.get("group"), Some("customers/acme"));
+    }
+
+    #[test]
+    fn should_record_metrics_with_tenant_dimensions() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .dimension("service", "orders")
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+
+        metrics.for_tenant("acme").add("orders", Count(3));
+        metrics.for_tenant("globex").increment("orders", 1.0);
+        metrics.add("orders", Count(4));
+        metrics.flush_metrics();
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads.len(), 3);
+        assert_eq!(payloads[0][DEFAULT_TENANT_DIMENSION], "acme");
+        assert_eq!(payloads[0]["service"], "orders");
+        assert_eq!(payloads[0]["orders"], 3.0);
+        assert_eq!(payloads[1][DEFAULT_TENANT_DIMENSION], "globex");
+        assert!(payloads[2].get(DEFAULT_TENANT_DIMENSION).is_none());
+    }
+
+    #[test]
+    fn should_flush_when_tenant_cap_is_reached() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .tenant_context(TenantContext::default().max_tenants_per_payload(2))
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+
+        metrics.for_tenant("a").increment("orders", 1.0);
+        metrics.for_tenant("b").increment("orders", 1.0);
+        metrics.for_tenant("a").increment("orders", 1.0);
+        assert!(sink.payloads().is_empty());
+
+        metrics.for_tenant("c").increment("orders", 1.0);
+        assert_eq!(sink.payloads().len(), 2);
+        assert_eq!(metrics.len(), 1);
+    }
+}
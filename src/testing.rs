@@ -0,0 +1,780 @@
+//! Test harness capturing the emitted payloads, for end-to-end instrumentation tests.
+//!
+//! Available with the `testing` feature, typically enabled in `dev-dependencies`.
+//!
+//! ```
+//! use lambda_helpers_metrics::testing::capture;
+//! use lambda_helpers_metrics::{MetricUnit, Metrics};
+//!
+//! let ((), payloads) = capture(
+//!     Metrics::builder("custom_lambdas").dimension("service", "checkout"),
+//!     |metrics| metrics.add_metric("orders", MetricUnit::Count, 3.0),
+//! );
+//!
+//! assert_eq!(payloads[0].value("orders"), Some(3.0));
+//! assert_eq!(payloads[0].unit("orders"), Some(MetricUnit::Count));
+//! assert_eq!(payloads[0].dimension("service"), Some("checkout"));
+//! ```
+//!
+//! For code recording into metrics it creates itself, e.g. the [current context](crate::context),
+//! a [`RecordingSink`] can be set on the builder directly.
+//!
+//! A [`FaultySink`] fails every Nth emit, with a chosen error, or slows emits down, to verify
+//! how an application behaves when emission is degraded (error callbacks, self-metrics...).
+//!
+//! [`diff_payloads`] compares two payloads semantically, for golden (approval) tests of
+//! instrumentation changes: the timestamp is ignored, as well as key order and number formatting.
+//!
+//! The assertion macros check a sink or a list of payloads, and list what was emitted
+//! when they fail:
+//!
+//! ```
+//! use lambda_helpers_metrics::sink::RecordingSink;
+//! use lambda_helpers_metrics::{assert_dimension, assert_metric_emitted, assert_metric_not_emitted};
+//! use lambda_helpers_metrics::{MetricUnit, Metrics};
+//!
+//! let sink = RecordingSink::new();
+//! let mut metrics = Metrics::builder("custom_lambdas")
+//!     .dimension("service", "checkout")
+//!     .sink(sink.clone())
+//!     .build()
+//!     .unwrap();
+//! metrics.add_metric("orders", MetricUnit::Count, 3.0);
+//! metrics.flush_metrics();
+//!
+//! assert_metric_emitted!(sink, "orders");
+//! assert_metric_emitted!(sink, "orders", Count, 3.0);
+//! assert_metric_not_emitted!(sink, "errors");
+//! assert_dimension!(sink, "service", "checkout");
+//! ```
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde_json::Value;
+
+use crate::sink::RecordingSink;
+use crate::{MetricUnit, Metrics, MetricsBuilder, MetricsError, MetricsSink};
+
+type InjectedError = dyn Fn() -> MetricsError + Send + Sync;
+
+/// Sink injecting failures and latency, keeping the payloads of successful emits.
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use lambda_helpers_metrics::testing::FaultySink;
+/// use lambda_helpers_metrics::{MetricUnit, Metrics, MetricsError};
+///
+/// let sink = FaultySink::new()
+///     .fail_every(2)
+///     .error(|| MetricsError::Configuration("agent unreachable".to_string()))
+///     .latency(Duration::from_millis(5));
+/// let mut metrics = Metrics::builder("custom_lambdas").sink(sink.clone()).build().unwrap();
+///
+/// metrics.add_metric("orders", MetricUnit::Count, 1.0);
+/// assert!(metrics.try_flush_metrics().is_ok());
+/// metrics.add_metric("orders", MetricUnit::Count, 1.0);
+/// assert!(metrics.try_flush_metrics().is_err());
+/// assert_eq!(sink.failures(), 1);
+/// ```
+#[derive(Clone)]
+pub struct FaultySink {
+    captured: RecordingSink,
+    fail_every: usize,
+    error: Arc<InjectedError>,
+    latency: Duration,
+    emits: Arc<AtomicUsize>,
+    failures: Arc<AtomicUsize>,
+}
+
+impl fmt::Debug for FaultySink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FaultySink")
+            .field("fail_every", &self.fail_every)
+            .field("latency", &self.latency)
+            .field("emits", &self.emits)
+            .field("failures", &self.failures)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for FaultySink {
+    fn default() -> Self {
+        Self {
+            captured: RecordingSink::new(),
+            fail_every: 0,
+            error: Arc::new(|| MetricsError::Io(std::io::Error::other("injected failure"))),
+            latency: Duration::ZERO,
+            emits: Arc::default(),
+            failures: Arc::default(),
+        }
+    }
+}
+
+impl FaultySink {
+    /// Creates a sink which doesn't fail until configured to.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fails every `n`th emit: 1 fails all of them, 0 (the default) none.
+    #[must_use]
+    pub fn fail_every(mut self, n: usize) -> Self {
+        self.fail_every = n;
+        self
+    }
+
+    /// Sets the error of failed emits, an I/O error by default.
+    #[must_use]
+    pub fn error(mut self, error: impl Fn() -> MetricsError + Send + Sync + 'static) -> Self {
+        self.error = Arc::new(error);
+        self
+    }
+
+    /// Blocks every emit, successful or not, for the given duration.
+    #[must_use]
+    pub fn latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Returns the payloads of successful emits.
+    #[must_use]
+    pub fn payloads(&self) -> Vec<EmittedPayload> {
+        self.captured.emitted_payloads()
+    }
+
+    /// Returns the number of emits, successful or not.
+    #[must_use]
+    pub fn emits(&self) -> usize {
+        self.emits.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of failed emits.
+    #[must_use]
+    pub fn failures(&self) -> usize {
+        self.failures.load(Ordering::Relaxed)
+    }
+}
+
+impl MetricsSink for FaultySink {
+    fn emit(&self, payload: &str) -> Result<(), MetricsError> {
+        if !self.latency.is_zero() {
+            std::thread::sleep(self.latency);
+        }
+        let emit = self.emits.fetch_add(1, Ordering::Relaxed) + 1;
+        if self.fail_every > 0 && emit.is_multiple_of(self.fail_every) {
+            self.failures.fetch_add(1, Ordering::Relaxed);
+            return Err((self.error)());
+        }
+        self.captured.emit(payload)
+    }
+}
+
+/// An emitted payload with typed accessors.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmittedPayload(Value);
+
+impl EmittedPayload {
+    /// Parses a payload.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the payload is not valid JSON
+    pub fn parse(payload: &str) -> Result<Self, MetricsError> {
+        serde_json::from_str(payload)
+            .map(Self)
+            .map_err(|err| MetricsError::Serialization(err.to_string()))
+    }
+
+    fn directive(&self) -> &Value {
+        &self.0["_aws"]["CloudWatchMetrics"][0]
+    }
+
+    fn definition(&self, name: &str) -> Option<&Value> {
+        self.directive()["Metrics"]
+            .as_array()?
+            .iter()
+            .find(|definition| definition["Name"] == name)
+    }
+
+    /// Returns the namespace of the metrics.
+    #[must_use]
+    pub fn namespace(&self) -> Option<&str> {
+        self.directive()["Namespace"].as_str()
+    }
+
+    /// Returns the names of the metrics, in the order they are declared.
+    #[must_use]
+    pub fn metric_names(&self) -> Vec<&str> {
+        self.directive()["Metrics"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|definition| definition["Name"].as_str())
+            .collect()
+    }
+
+    /// Returns `true` if the payload declares the metric.
+    #[must_use]
+    pub fn contains(&self, name: &str) -> bool {
+        self.definition(name).is_some()
+    }
+
+    /// Returns the value of a metric recorded once, or the first value of a metric with samples.
+    #[must_use]
+    pub fn value(&self, name: &str) -> Option<f64> {
+        self.values(name)?.first().copied()
+    }
+
+    /// Returns all values of a metric.
+    #[must_use]
+    pub fn values(&self, name: &str) -> Option<Vec<f64>> {
+        self.definition(name)?;
+        match &self.0[name] {
+            Value::Array(values) => values.iter().map(Value::as_f64).collect(),
+            value => value.as_f64().map(|value| vec![value]),
+        }
+    }
+
+    /// Returns the unit of a metric.
+    #[must_use]
+    pub fn unit(&self, name: &str) -> Option<MetricUnit> {
+        self.definition(name)?["Unit"].as_str()?.parse().ok()
+    }
+
+    /// Returns the storage resolution of a metric, if set.
+    #[must_use]
+    pub fn storage_resolution(&self, name: &str) -> Option<u64> {
+        self.definition(name)?["StorageResolution"].as_u64()
+    }
+
+    /// Returns the value of a dimension declared in the payload.
+    #[must_use]
+    pub fn dimension(&self, key: &str) -> Option<&str> {
+        let declared = self
+            .dimension_sets()
+            .into_iter()
+            .flatten()
+            .any(|k| k == key);
+        declared.then(|| self.0[key].as_str()).flatten()
+    }
+
+    /// Returns the dimension sets of the payload.
+    #[must_use]
+    pub fn dimension_sets(&self) -> Vec<Vec<&str>> {
+        self.directive()["Dimensions"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|set| {
+                set.as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(Value::as_str)
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Returns a top-level field which is neither a metric nor a dimension, e.g. a property.
+    #[must_use]
+    pub fn property(&self, key: &str) -> Option<&Value> {
+        self.0.get(key)
+    }
+
+    /// Returns the timestamp of the payload, in milliseconds since the epoch.
+    #[must_use]
+    pub fn timestamp(&self) -> Option<i64> {
+        self.0["_aws"]["Timestamp"].as_i64()
+    }
+
+    /// Returns the whole payload.
+    #[must_use]
+    pub fn raw(&self) -> &Value {
+        &self.0
+    }
+}
+
+/// Sources of payloads checked by the assertion macros.
+#[doc(hidden)]
+pub trait Payloads {
+    fn emitted_payloads(&self) -> Vec<EmittedPayload>;
+}
+
+impl Payloads for RecordingSink {
+    fn emitted_payloads(&self) -> Vec<EmittedPayload> {
+        self.raw_payloads()
+            .iter()
+            .map(|payload| EmittedPayload::parse(payload).expect("emitted payload is not JSON"))
+            .collect()
+    }
+}
+
+impl Payloads for [EmittedPayload] {
+    fn emitted_payloads(&self) -> Vec<EmittedPayload> {
+        self.to_vec()
+    }
+}
+
+impl Payloads for Vec<EmittedPayload> {
+    fn emitted_payloads(&self) -> Vec<EmittedPayload> {
+        self.clone()
+    }
+}
+
+/// Describes the metrics and dimensions of the payloads, for failure messages.
+fn describe(payloads: &[EmittedPayload]) -> String {
+    if payloads.is_empty() {
+        return "no payloads were emitted".to_string();
+    }
+    let mut description = String::from("emitted:");
+    for (index, payload) in payloads.iter().enumerate() {
+        let dimensions = payload
+            .dimension_sets()
+            .into_iter()
+            .flatten()
+            .map(|key| format!("{key}={}", payload.dimension(key).unwrap_or_default()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        description.push_str(&format!("\n  payload {index} [{dimensions}]"));
+        for name in payload.metric_names() {
+            let unit = payload
+                .unit(name)
+                .map(|unit| unit.to_string())
+                .unwrap_or_default();
+            let values = payload.values(name).unwrap_or_default();
+            description.push_str(&format!("\n    {name} ({unit}): {values:?}"));
+        }
+    }
+    description
+}
+
+#[doc(hidden)]
+#[track_caller]
+pub fn assert_metric_emitted(
+    payloads: &(impl Payloads + ?Sized),
+    name: &str,
+    expected: Option<(MetricUnit, f64)>,
+) {
+    let payloads = payloads.emitted_payloads();
+    let found = payloads.iter().any(|payload| match expected {
+        None => payload.contains(name),
+        Some((unit, value)) => {
+            payload.unit(name) == Some(unit)
+                && payload
+                    .values(name)
+                    .unwrap_or_default()
+                    .iter()
+                    .any(|emitted| (emitted - value).abs() < 1e-9)
+        }
+    });
+    if !found {
+        let expected = match expected {
+            None => String::new(),
+            Some((unit, value)) => format!(" with unit {unit} and value {value}"),
+        };
+        panic!(
+            "metric `{name}`{expected} was not emitted, {}",
+            describe(&payloads)
+        );
+    }
+}
+
+#[doc(hidden)]
+#[track_caller]
+pub fn assert_metric_not_emitted(payloads: &(impl Payloads + ?Sized), name: &str) {
+    let payloads = payloads.emitted_payloads();
+    if payloads.iter().any(|payload| payload.contains(name)) {
+        panic!("metric `{name}` was emitted, {}", describe(&payloads));
+    }
+}
+
+#[doc(hidden)]
+#[track_caller]
+pub fn assert_dimension(payloads: &(impl Payloads + ?Sized), key: &str, value: &str) {
+    let payloads = payloads.emitted_payloads();
+    if !payloads
+        .iter()
+        .any(|payload| payload.dimension(key) == Some(value))
+    {
+        panic!(
+            "dimension `{key}={value}` was not emitted, {}",
+            describe(&payloads)
+        );
+    }
+}
+
+/// Asserts that a metric was emitted, optionally with the given unit and value,
+/// in a [`RecordingSink`] or a list of [`EmittedPayload`]. The unit is a
+/// [`MetricUnit`] variant or one of the shorthands of [`emit!`](crate::emit).
+///
+/// On failure, the message lists the emitted metrics and dimensions.
+#[macro_export]
+macro_rules! assert_metric_emitted {
+    ($payloads:expr, $name:expr $(,)?) => {
+        $crate::testing::assert_metric_emitted(&$payloads, $name, None)
+    };
+    ($payloads:expr, $name:expr, $unit:ident, $value:expr $(,)?) => {
+        $crate::testing::assert_metric_emitted(
+            &$payloads,
+            $name,
+            Some(($crate::__metric_unit!($unit), $value as f64)),
+        )
+    };
+}
+
+/// Asserts that a metric was not emitted, see [`assert_metric_emitted!`].
+#[macro_export]
+macro_rules! assert_metric_not_emitted {
+    ($payloads:expr, $name:expr $(,)?) => {
+        $crate::testing::assert_metric_not_emitted(&$payloads, $name)
+    };
+}
+
+/// Asserts that a payload was emitted with the dimension, see [`assert_metric_emitted!`].
+#[macro_export]
+macro_rules! assert_dimension {
+    ($payloads:expr, $key:expr, $value:expr $(,)?) => {
+        $crate::testing::assert_dimension(&$payloads, $key, $value)
+    };
+}
+
+/// A difference between two payloads, see [`diff_payloads`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Difference {
+    /// The field is only in the expected payload.
+    Missing { path: String, expected: Value },
+    /// The field is only in the actual payload.
+    Unexpected { path: String, actual: Value },
+    /// The field differs.
+    Changed {
+        path: String,
+        expected: Value,
+        actual: Value,
+    },
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Difference::Missing { path, expected } => write!(f, "- {path}: {expected}"),
+            Difference::Unexpected { path, actual } => write!(f, "+ {path}: {actual}"),
+            Difference::Changed {
+                path,
+                expected,
+                actual,
+            } => write!(f, "~ {path}: {expected} -> {actual}"),
+        }
+    }
+}
+
+/// The differences between two payloads, empty if they are equivalent.
+/// Displayed as one line per difference, prefixed with `-` (missing), `+` (unexpected)
+/// or `~` (changed).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PayloadDiff(Vec<Difference>);
+
+impl PayloadDiff {
+    /// Returns `true` if the payloads are equivalent.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns the differences, ordered by path.
+    #[must_use]
+    pub fn differences(&self) -> &[Difference] {
+        &self.0
+    }
+}
+
+impl fmt::Display for PayloadDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, difference) in self.0.iter().enumerate() {
+            if index > 0 {
+                f.write_str("\n")?;
+            }
+            write!(f, "{difference}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Compares two payloads, ignoring the timestamp, the order of keys and the formatting
+/// of numbers (`1` and `1.0` are equal).
+///
+/// ```
+/// use lambda_helpers_metrics::testing::diff_payloads;
+///
+/// let golden = r#"{"_aws":{"Timestamp":1,"CloudWatchMetrics":[]},"orders":1,"service":"checkout"}"#;
+/// let actual = r#"{"service":"orders","orders":1.0,"_aws":{"CloudWatchMetrics":[],"Timestamp":2}}"#;
+///
+/// let diff = diff_payloads(golden, actual).unwrap();
+/// assert_eq!(diff.to_string(), r#"~ service: "checkout" -> "orders""#);
+/// ```
+///
+/// # Errors
+///
+/// Will return `Err` if a payload is not valid JSON
+pub fn diff_payloads(expected: &str, actual: &str) -> Result<PayloadDiff, MetricsError> {
+    let expected = EmittedPayload::parse(expected)?;
+    let actual = EmittedPayload::parse(actual)?;
+    Ok(expected.diff(&actual))
+}
+
+impl EmittedPayload {
+    /// Compares the payload with another one, see [`diff_payloads`].
+    #[must_use]
+    pub fn diff(&self, actual: &EmittedPayload) -> PayloadDiff {
+        let mut differences = Vec::new();
+        diff_values(
+            "",
+            &without_timestamp(&self.0),
+            &without_timestamp(&actual.0),
+            &mut differences,
+        );
+        PayloadDiff(differences)
+    }
+}
+
+fn without_timestamp(payload: &Value) -> Value {
+    let mut payload = payload.clone();
+    if let Some(aws) = payload.get_mut("_aws").and_then(Value::as_object_mut) {
+        aws.remove("Timestamp");
+    }
+    payload
+}
+
+fn child_path(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{path}.{key}")
+    }
+}
+
+fn diff_values(path: &str, expected: &Value, actual: &Value, differences: &mut Vec<Difference>) {
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => {
+            let mut keys = expected.keys().chain(actual.keys()).collect::<Vec<_>>();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                let path = child_path(path, key);
+                match (expected.get(key), actual.get(key)) {
+                    (Some(expected), Some(actual)) => {
+                        diff_values(&path, expected, actual, differences);
+                    }
+                    (Some(expected), None) => differences.push(Difference::Missing {
+                        path,
+                        expected: expected.clone(),
+                    }),
+                    (None, Some(actual)) => differences.push(Difference::Unexpected {
+                        path,
+                        actual: actual.clone(),
+                    }),
+                    (None, None) => {}
+                }
+            }
+        }
+        (Value::Array(expected), Value::Array(actual)) => {
+            for index in 0..expected.len().max(actual.len()) {
+                let path = format!("{path}[{index}]");
+                match (expected.get(index), actual.get(index)) {
+                    (Some(expected), Some(actual)) => {
+                        diff_values(&path, expected, actual, differences);
+                    }
+                    (Some(expected), None) => differences.push(Difference::Missing {
+                        path,
+                        expected: expected.clone(),
+                    }),
+                    (None, Some(actual)) => differences.push(Difference::Unexpected {
+                        path,
+                        actual: actual.clone(),
+                    }),
+                    (None, None) => {}
+                }
+            }
+        }
+        (Value::Number(expected_number), Value::Number(actual_number))
+            if expected_number.as_f64() == actual_number.as_f64() => {}
+        (expected, actual) if expected == actual => {}
+        (expected, actual) => differences.push(Difference::Changed {
+            path: path.to_string(),
+            expected: expected.clone(),
+            actual: actual.clone(),
+        }),
+    }
+}
+
+/// Builds `Metrics` writing to a [`RecordingSink`], runs `f` with it, flushes and returns
+/// the result of `f` with the emitted payloads.
+///
+/// # Panics
+///
+/// Panics if the builder fails, see [`MetricsBuilder::build`].
+pub fn capture<R>(
+    builder: MetricsBuilder,
+    f: impl FnOnce(&mut Metrics) -> R,
+) -> (R, Vec<EmittedPayload>) {
+    let sink = RecordingSink::new();
+    let mut metrics = builder
+        .sink(sink.clone())
+        .build()
+        .expect("invalid metrics builder");
+    let result = f(&mut metrics);
+    metrics.flush_metrics();
+    (result, sink.emitted_payloads())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_capture_payloads_of_closure() {
+        let (result, payloads) = capture(
+            Metrics::builder("test").dimension("service", "checkout"),
+            |metrics| {
+                metrics.add_metric("orders", MetricUnit::Count, 3.0);
+                metrics.add_sample("latency", MetricUnit::Milliseconds, 10.0);
+                metrics.add_sample("latency", MetricUnit::Milliseconds, 20.0);
+                metrics.add_property("request_id", "req-1");
+                "done"
+            },
+        );
+
+        assert_eq!(result, "done");
+        assert_eq!(payloads.len(), 1);
+        let payload = &payloads[0];
+        assert_eq!(payload.namespace(), Some("test"));
+        assert_eq!(payload.metric_names(), ["orders", "latency"]);
+        assert_eq!(payload.value("orders"), Some(3.0));
+        assert_eq!(payload.values("latency"), Some(vec![10.0, 20.0]));
+        assert_eq!(payload.unit("latency"), Some(MetricUnit::Milliseconds));
+        assert_eq!(payload.dimension("service"), Some("checkout"));
+        assert_eq!(payload.dimension("request_id"), None);
+        assert_eq!(payload.property("request_id").unwrap(), "req-1");
+        assert!(payload.timestamp().is_some());
+        assert!(!payload.contains("missing"));
+    }
+
+    #[test]
+    fn should_assert_emitted_metrics() {
+        let ((), payloads) = capture(
+            Metrics::builder("test").dimension("service", "checkout"),
+            |metrics| metrics.add_metric("orders", MetricUnit::Count, 3.0),
+        );
+
+        crate::assert_metric_emitted!(payloads, "orders");
+        crate::assert_metric_emitted!(payloads, "orders", Count, 3);
+        crate::assert_metric_emitted!(payloads, "orders", count, 3.0);
+        crate::assert_metric_not_emitted!(payloads, "errors");
+        crate::assert_dimension!(payloads, "service", "checkout");
+    }
+
+    #[test]
+    fn should_fail_every_nth_emit() {
+        let sink = FaultySink::new().fail_every(3);
+        let errors = Arc::new(AtomicUsize::new(0));
+        let callback_errors = Arc::clone(&errors);
+        let mut metrics = Metrics::builder("test")
+            .sink(sink.clone())
+            .on_error(move |_| {
+                callback_errors.fetch_add(1, Ordering::Relaxed);
+            })
+            .build()
+            .unwrap();
+
+        for _ in 0..6 {
+            metrics.add_metric("orders", MetricUnit::Count, 1.0);
+            metrics.flush_metrics();
+        }
+
+        assert_eq!(sink.emits(), 6);
+        assert_eq!(sink.failures(), 2);
+        assert_eq!(errors.load(Ordering::Relaxed), 2);
+        assert_eq!(sink.payloads().len(), 4);
+    }
+
+    #[test]
+    fn should_diff_payloads_semantically() {
+        let golden = r#"{
+            "_aws": {
+                "Timestamp": 1,
+                "CloudWatchMetrics": [{
+                    "Namespace": "test",
+                    "Dimensions": [["service"]],
+                    "Metrics": [{ "Name": "orders", "Unit": "Count" }]
+                }]
+            },
+            "service": "checkout",
+            "orders": 1,
+            "request_id": "req-1"
+        }"#;
+        let ((), payloads) = capture(
+            Metrics::builder("test").dimension("service", "checkout"),
+            |metrics| {
+                metrics.add_metric("orders", MetricUnit::Count, 1.0);
+                metrics.add_property("request_id", "req-1");
+            },
+        );
+        let actual = payloads[0]
+            .raw()
+            .to_string()
+            .replace(r#","StorageResolution":60"#, "");
+
+        assert!(diff_payloads(golden, &actual).unwrap().is_empty());
+
+        let changed = actual
+            .replace("req-1", "req-2")
+            .replace(r#""Unit":"Count""#, r#""Unit":"None""#);
+        let diff = diff_payloads(golden, &changed).unwrap();
+        assert_eq!(
+            diff.to_string(),
+            "~ _aws.CloudWatchMetrics[0].Metrics[0].Unit: \"Count\" -> \"None\"\n\
+             ~ request_id: \"req-1\" -> \"req-2\""
+        );
+
+        let diff = diff_payloads(r#"{"a":1,"b":[1]}"#, r#"{"b":[1,2],"c":true}"#).unwrap();
+        assert_eq!(
+            diff.differences(),
+            [
+                Difference::Missing {
+                    path: "a".to_string(),
+                    expected: Value::from(1)
+                },
+                Difference::Unexpected {
+                    path: "b[1]".to_string(),
+                    actual: Value::from(2)
+                },
+                Difference::Unexpected {
+                    path: "c".to_string(),
+                    actual: Value::from(true)
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn should_describe_emitted_metrics_on_failure() {
+        let ((), payloads) = capture(
+            Metrics::builder("test").dimension("service", "checkout"),
+            |metrics| metrics.add_metric("orders", MetricUnit::Count, 3.0),
+        );
+
+        let failure = std::panic::catch_unwind(|| {
+            crate::assert_metric_emitted!(payloads, "orders", Count, 4.0);
+        })
+        .unwrap_err();
+
+        let message = failure.downcast_ref::<String>().unwrap();
+        assert!(message.starts_with("metric `orders` with unit Count and value 4 was not emitted"));
+        assert!(message.contains("payload 0 [service=checkout]"));
+        assert!(message.contains("orders (Count): [3.0]"));
+    }
+}
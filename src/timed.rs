@@ -0,0 +1,183 @@
+//! Timing of awaited operations.
+//!
+//! [`TimedFuture::record_duration`] records the time a future took to complete into a
+//! [`MetricsHandle`], without a timer kept around the `.await`:
+//!
+//! ```
+//! use lambda_helpers_metrics::context::MetricsHandle;
+//! use lambda_helpers_metrics::timed::TimedFuture;
+//! use lambda_helpers_metrics::Metrics;
+//!
+//! # async fn query() -> Result<u32, std::io::Error> { Ok(1) }
+//! # async fn handler() -> Result<(), std::io::Error> {
+//! let handle = MetricsHandle::new(Metrics::new("custom_lambdas", "service", "dummy_service"));
+//! let rows = query()
+//!     .record_duration(&handle, "dynamo_query_ms")
+//!     .outcome("dynamo_query")
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The duration is measured from the first poll until the future completes, and recorded as a
+//! sample in milliseconds. Futures dropped before completion record nothing.
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use crate::context::MetricsHandle;
+use crate::{outcome, MetricUnit, Metrics};
+
+type OutcomeRecorder<T> = Box<dyn FnOnce(&mut Metrics, &T) + Send>;
+
+/// Extension trait timing futures, see [`crate::timed`].
+pub trait TimedFuture: Future + Sized {
+    /// Records the time until the future completes as a sample of `name`, in milliseconds.
+    fn record_duration(self, handle: &MetricsHandle, name: &str) -> Timed<Self> {
+        Timed {
+            future: Box::pin(self),
+            handle: handle.clone(),
+            name: name.to_string(),
+            outcome: None,
+            start: None,
+        }
+    }
+}
+
+impl<F: Future> TimedFuture for F {}
+
+/// A future timed with [`TimedFuture::record_duration`].
+pub struct Timed<F: Future> {
+    future: Pin<Box<F>>,
+    handle: MetricsHandle,
+    name: String,
+    outcome: Option<OutcomeRecorder<F::Output>>,
+    start: Option<Instant>,
+}
+
+impl<F: Future> fmt::Debug for Timed<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Timed")
+            .field("name", &self.name)
+            .field("start", &self.start)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F, T, E> Timed<F>
+where
+    F: Future<Output = Result<T, E>>,
+{
+    /// Also records the outcome of the result like
+    /// [`MetricizedResult::record_outcome`](crate::MetricizedResult::record_outcome):
+    /// `<operation>_success`, or `<operation>_error` with an `error_type` dimension.
+    #[must_use]
+    pub fn outcome(mut self, operation: &str) -> Self {
+        let operation = operation.to_string();
+        self.outcome = Some(Box::new(move |metrics, result| {
+            outcome::record_result(metrics, result, &operation);
+        }));
+        self
+    }
+}
+
+impl<F: Future> Future for Timed<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        if self.start.is_none() {
+            self.start = Some(self.handle.with(|metrics| metrics.clock.instant()));
+        }
+        let Poll::Ready(output) = self.future.as_mut().poll(cx) else {
+            return Poll::Pending;
+        };
+        let start = self.start.take();
+        let outcome = self.outcome.take();
+        self.handle.with(|metrics| {
+            let elapsed = start.map_or_else(Default::default, |start| {
+                metrics.clock.instant().saturating_duration_since(start)
+            });
+            metrics.add_sample(
+                &self.name,
+                MetricUnit::Milliseconds,
+                elapsed.as_secs_f64() * 1000.0,
+            );
+            if let Some(outcome) = outcome {
+                outcome(metrics, &output);
+            }
+        });
+        Poll::Ready(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::pin::pin;
+    use std::task::Waker;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::outcome::ERROR_TYPE_DIMENSION;
+    use crate::sink::RecordingSink;
+
+    #[derive(Debug)]
+    struct ThrottlingError;
+
+    fn poll_ready<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        let mut context = Context::from_waker(Waker::noop());
+        match future.as_mut().poll(&mut context) {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("future not ready"),
+        }
+    }
+
+    #[test]
+    fn should_record_duration_and_outcome_on_completion() {
+        let clock = ManualClock::new(chrono::Utc::now());
+        let sink = RecordingSink::default();
+        let handle = MetricsHandle::new(
+            Metrics::builder("test")
+                .clock(clock.clone())
+                .sink(sink.clone())
+                .build()
+                .unwrap(),
+        );
+        let query = |fail: bool| {
+            let clock = clock.clone();
+            async move {
+                clock.advance(Duration::from_millis(12));
+                if fail {
+                    Err(ThrottlingError)
+                } else {
+                    Ok(3)
+                }
+            }
+        };
+
+        let rows = poll_ready(
+            query(false)
+                .record_duration(&handle, "query_ms")
+                .outcome("query"),
+        );
+        let failed = poll_ready(
+            query(true)
+                .record_duration(&handle, "query_ms")
+                .outcome("query"),
+        );
+        poll_ready(async {}.record_duration(&handle, "noop_ms"));
+        handle.flush();
+
+        assert_eq!(rows.unwrap(), 3);
+        assert!(failed.is_err());
+        let payloads = sink.payloads();
+        assert_eq!(payloads[0]["query_ms"], serde_json::json!([12.0, 12.0]));
+        assert_eq!(payloads[0]["query_success"], 1.0);
+        assert_eq!(payloads[0]["noop_ms"], 0.0);
+        assert_eq!(payloads[1]["query_error"], 1.0);
+        assert_eq!(payloads[1][ERROR_TYPE_DIMENSION], "ThrottlingError");
+    }
+}
@@ -0,0 +1,452 @@
+//! Statistics of the tokio runtime and of instrumented tasks, recorded at flush.
+//!
+//! Latency of an async handler is often spent waiting for the runtime rather than in the
+//! handler: long polls blocking a worker, tasks scheduled again and again, or tasks yielding
+//! because they exhausted their budget. [`TokioStats`] records the statistics of the runtime,
+//! and of the tasks instrumented with a [`TaskMonitor`], along with the other metrics:
+//!
+//! ```
+//! use lambda_helpers_metrics::tokio_stats::{TaskMonitor, TokioStats};
+//! use lambda_helpers_metrics::Metrics;
+//!
+//! # let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+//! # runtime.block_on(async {
+//! let monitor = TaskMonitor::new("handler");
+//! let mut metrics = Metrics::builder("custom_lambdas")
+//!     .tokio_stats(TokioStats::new(tokio::runtime::Handle::current()).task_monitor(&monitor))
+//!     .build()
+//!     .unwrap();
+//!
+//! monitor.instrument(async { /* handle the event */ }).await;
+//! metrics.flush_async().await;
+//! # });
+//! ```
+//!
+//! The statistics are recorded at most once per [interval](TokioStats::interval), at the first
+//! flush with other metrics, as deltas since the previous recording. Like the metrics of the
+//! library, they don't carry the dimensions of the `Metrics` object; the ones of a task carry
+//! the name of its monitor as the `task` dimension.
+//!
+//! Runtime metrics:
+//! - `tokio_workers_count`, `tokio_alive_tasks_count` and `tokio_global_queue_depth`;
+//! - `tokio_park_count` and `tokio_busy_duration` of the workers;
+//! - with `RUSTFLAGS="--cfg tokio_unstable"`: `tokio_poll_count`, `tokio_mean_poll_duration`,
+//!   `tokio_local_schedule_count` and `tokio_budget_forced_yield_count`.
+//!
+//! Task metrics: `tokio_task_instrumented_count`, `tokio_task_poll_count`,
+//! `tokio_task_mean_poll_duration`, `tokio_task_slow_poll_count` (polls longer than
+//! [`SLOW_POLL_THRESHOLD`]) and `tokio_task_scheduled_count`.
+//!
+//! Available with the `tokio-metrics` feature.
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use tokio::runtime::Handle;
+
+use crate::catalog::codegen::CatalogMetric;
+use crate::{Dimensions, Metric, MetricUnit, Metrics};
+
+/// Default minimum time between two recordings of the statistics.
+pub const DEFAULT_TOKIO_STATS_INTERVAL: Duration = Duration::from_secs(60);
+/// Polls of an instrumented task longer than this are counted as slow.
+pub const SLOW_POLL_THRESHOLD: Duration = Duration::from_micros(50);
+/// Name of the dimension holding the name of the [`TaskMonitor`].
+pub const TASK_DIMENSION: &str = "task";
+
+/// Records the statistics of a tokio runtime at flush, see [`crate::tokio_stats`].
+#[derive(Debug, Clone)]
+pub struct TokioStats {
+    handle: Handle,
+    interval: Duration,
+    monitors: Vec<TaskMonitor>,
+    /// When the statistics were last recorded, and the totals of the runtime at that time.
+    last: Option<(Instant, RuntimeTotals)>,
+}
+
+impl TokioStats {
+    /// Records the statistics of the runtime of the handle.
+    #[must_use]
+    pub fn new(handle: Handle) -> Self {
+        Self {
+            handle,
+            interval: DEFAULT_TOKIO_STATS_INTERVAL,
+            monitors: Vec::new(),
+            last: None,
+        }
+    }
+
+    /// Sets the minimum time between two recordings, [`DEFAULT_TOKIO_STATS_INTERVAL`]
+    /// by default. With `Duration::ZERO` the statistics are recorded at every flush.
+    #[must_use]
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Records the statistics of the tasks instrumented with the monitor too.
+    #[must_use]
+    pub fn task_monitor(mut self, monitor: &TaskMonitor) -> Self {
+        self.monitors.push(monitor.clone());
+        self
+    }
+
+    /// Returns the metrics it records, see [`Metrics::catalog`]. The names and units are the
+    /// ones of the metrics recorded from empty totals, so they can't diverge.
+    pub(crate) fn catalog_metrics(&self) -> Vec<CatalogMetric> {
+        let totals = RuntimeTotals::default();
+        let mut metrics = totals.metrics(&totals);
+        if !self.monitors.is_empty() {
+            metrics.extend(TaskMonitor::new("").take_metrics());
+        }
+        metrics
+            .into_iter()
+            .map(|metric| CatalogMetric {
+                dimensions: metric
+                    .dimensions
+                    .iter()
+                    .map(|(key, _)| key.to_string())
+                    .collect(),
+                ..CatalogMetric::library(
+                    metric.name,
+                    metric.unit,
+                    "Statistics of the tokio runtime.",
+                )
+            })
+            .collect()
+    }
+
+    /// Returns the metrics to record, or nothing if the interval has not elapsed.
+    fn collect(&mut self, now: Instant) -> Vec<Metric> {
+        if let Some((last, _)) = self.last {
+            if now.duration_since(last) < self.interval {
+                return Vec::new();
+            }
+        }
+        let totals = RuntimeTotals::read(&self.handle);
+        let previous = self.last.map(|(_, totals)| totals).unwrap_or_default();
+        let mut metrics = totals.metrics(&previous);
+        for monitor in &self.monitors {
+            metrics.extend(monitor.take_metrics());
+        }
+        self.last = Some((now, totals));
+        metrics
+    }
+}
+
+/// Totals of the runtime since it started.
+#[derive(Debug, Clone, Copy, Default)]
+struct RuntimeTotals {
+    workers: usize,
+    alive_tasks: usize,
+    global_queue_depth: usize,
+    park_count: u64,
+    busy_duration: Duration,
+    #[cfg(tokio_unstable)]
+    poll_count: u64,
+    #[cfg(tokio_unstable)]
+    local_schedule_count: u64,
+    #[cfg(tokio_unstable)]
+    budget_forced_yield_count: u64,
+    /// Mean over the workers, not a total.
+    #[cfg(tokio_unstable)]
+    mean_poll_duration: Duration,
+}
+
+impl RuntimeTotals {
+    fn read(handle: &Handle) -> Self {
+        let runtime = handle.metrics();
+        let workers = runtime.num_workers();
+        let mut totals = Self {
+            workers,
+            alive_tasks: runtime.num_alive_tasks(),
+            global_queue_depth: runtime.global_queue_depth(),
+            ..Self::default()
+        };
+        for worker in 0..workers {
+            totals.park_count += runtime.worker_park_count(worker);
+            totals.busy_duration += runtime.worker_total_busy_duration(worker);
+            #[cfg(tokio_unstable)]
+            {
+                totals.poll_count += runtime.worker_poll_count(worker);
+                totals.local_schedule_count += runtime.worker_local_schedule_count(worker);
+                totals.mean_poll_duration += runtime.worker_mean_poll_time(worker);
+            }
+        }
+        #[cfg(tokio_unstable)]
+        {
+            totals.budget_forced_yield_count = runtime.budget_forced_yield_count();
+            totals.mean_poll_duration /= u32::try_from(workers.max(1)).unwrap_or(u32::MAX);
+        }
+        totals
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn metrics(&self, previous: &Self) -> Vec<Metric> {
+        #[cfg_attr(not(tokio_unstable), allow(unused_mut))]
+        let mut values = vec![
+            (
+                "tokio_workers_count",
+                MetricUnit::Count,
+                self.workers as f64,
+            ),
+            (
+                "tokio_alive_tasks_count",
+                MetricUnit::Count,
+                self.alive_tasks as f64,
+            ),
+            (
+                "tokio_global_queue_depth",
+                MetricUnit::Count,
+                self.global_queue_depth as f64,
+            ),
+            (
+                "tokio_park_count",
+                MetricUnit::Count,
+                self.park_count.saturating_sub(previous.park_count) as f64,
+            ),
+            (
+                "tokio_busy_duration",
+                MetricUnit::Milliseconds,
+                millis(self.busy_duration.saturating_sub(previous.busy_duration)),
+            ),
+        ];
+        #[cfg(tokio_unstable)]
+        values.extend([
+            (
+                "tokio_poll_count",
+                MetricUnit::Count,
+                self.poll_count.saturating_sub(previous.poll_count) as f64,
+            ),
+            (
+                "tokio_mean_poll_duration",
+                MetricUnit::Microseconds,
+                micros(self.mean_poll_duration),
+            ),
+            (
+                "tokio_local_schedule_count",
+                MetricUnit::Count,
+                self.local_schedule_count
+                    .saturating_sub(previous.local_schedule_count) as f64,
+            ),
+            (
+                "tokio_budget_forced_yield_count",
+                MetricUnit::Count,
+                self.budget_forced_yield_count
+                    .saturating_sub(previous.budget_forced_yield_count) as f64,
+            ),
+        ]);
+        values
+            .into_iter()
+            .map(|(name, unit, value)| metric(name, unit, value, Dimensions::default()))
+            .collect()
+    }
+}
+
+/// Measures the polls of the futures it instruments, see [`crate::tokio_stats`].
+/// Clones share the statistics.
+#[derive(Debug, Clone)]
+pub struct TaskMonitor {
+    name: String,
+    stats: Arc<TaskStats>,
+}
+
+/// Counters of a [`TaskMonitor`] since the previous recording.
+#[derive(Debug, Default)]
+struct TaskStats {
+    instrumented: AtomicU64,
+    polls: AtomicU64,
+    slow_polls: AtomicU64,
+    /// Polls after the first one of each future, which follow a wake-up.
+    scheduled: AtomicU64,
+    poll_nanos: AtomicU64,
+}
+
+impl TaskMonitor {
+    /// Creates a monitor, its name is the value of the `task` dimension.
+    #[must_use]
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            stats: Arc::default(),
+        }
+    }
+
+    /// Returns the name of the monitor.
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Wraps the future so its polls are measured.
+    pub fn instrument<F: Future>(&self, future: F) -> Instrumented<F> {
+        self.stats.instrumented.fetch_add(1, Ordering::Relaxed);
+        Instrumented {
+            future: Box::pin(future),
+            stats: Arc::clone(&self.stats),
+            polled: false,
+        }
+    }
+
+    /// Returns the metrics of the polls since the previous call, and resets the counters.
+    fn take_metrics(&self) -> Vec<Metric> {
+        let take = |counter: &AtomicU64| counter.swap(0, Ordering::Relaxed);
+        let instrumented = take(&self.stats.instrumented);
+        let polls = take(&self.stats.polls);
+        let slow_polls = take(&self.stats.slow_polls);
+        let scheduled = take(&self.stats.scheduled);
+        let poll_nanos = take(&self.stats.poll_nanos);
+        let mean_poll = Duration::from_nanos(poll_nanos.checked_div(polls).unwrap_or_default());
+        let mut dimensions = Dimensions::default();
+        dimensions.insert(TASK_DIMENSION, &self.name);
+        #[allow(clippy::cast_precision_loss)]
+        let values = [
+            (
+                "tokio_task_instrumented_count",
+                MetricUnit::Count,
+                instrumented as f64,
+            ),
+            ("tokio_task_poll_count", MetricUnit::Count, polls as f64),
+            (
+                "tokio_task_mean_poll_duration",
+                MetricUnit::Microseconds,
+                micros(mean_poll),
+            ),
+            (
+                "tokio_task_slow_poll_count",
+                MetricUnit::Count,
+                slow_polls as f64,
+            ),
+            (
+                "tokio_task_scheduled_count",
+                MetricUnit::Count,
+                scheduled as f64,
+            ),
+        ];
+        values
+            .into_iter()
+            .map(|(name, unit, value)| metric(name, unit, value, dimensions.clone()))
+            .collect()
+    }
+}
+
+/// A future instrumented with [`TaskMonitor::instrument`].
+#[derive(Debug)]
+pub struct Instrumented<F> {
+    future: Pin<Box<F>>,
+    stats: Arc<TaskStats>,
+    polled: bool,
+}
+
+impl<F: Future> Future for Instrumented<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        if std::mem::replace(&mut self.polled, true) {
+            self.stats.scheduled.fetch_add(1, Ordering::Relaxed);
+        }
+        let started = Instant::now();
+        let result = self.future.as_mut().poll(cx);
+        let elapsed = started.elapsed();
+        self.stats.polls.fetch_add(1, Ordering::Relaxed);
+        if elapsed > SLOW_POLL_THRESHOLD {
+            self.stats.slow_polls.fetch_add(1, Ordering::Relaxed);
+        }
+        self.stats.poll_nanos.fetch_add(
+            u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX),
+            Ordering::Relaxed,
+        );
+        result
+    }
+}
+
+fn metric(name: &str, unit: MetricUnit, value: f64, dimensions: Dimensions) -> Metric {
+    Metric {
+        name: name.to_string(),
+        unit,
+        values: vec![value],
+        dimensions,
+        storage_resolution: None,
+        timestamp: None,
+        properties: Vec::new(),
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+fn micros(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1_000_000.0
+}
+
+impl Metrics {
+    /// Adds the statistics of the runtime to the buffer, if enabled and the interval elapsed,
+    /// see [`Metrics::measures_at_flush`].
+    pub(crate) fn buffer_tokio_stats(&mut self) {
+        if !self.measures_at_flush() {
+            return;
+        }
+        let now = self.clock.instant();
+        if let Some(stats) = &mut self.tokio_stats {
+            let metrics = stats.collect(now);
+            self.entries.extend(metrics);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::sink::RecordingSink;
+
+    #[test]
+    fn should_record_runtime_and_task_stats_once_per_interval() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let monitor = TaskMonitor::new("handler");
+        let clock = ManualClock::new(chrono::Utc::now());
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .sink(sink.clone())
+            .clock(clock.clone())
+            .tokio_stats(
+                TokioStats::new(runtime.handle().clone())
+                    .interval(Duration::from_secs(10))
+                    .task_monitor(&monitor),
+            )
+            .build()
+            .unwrap();
+
+        runtime.block_on(monitor.instrument(async {
+            tokio::task::yield_now().await;
+        }));
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+        metrics.flush_metrics();
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+        metrics.flush_metrics();
+        clock.advance(Duration::from_secs(10));
+        metrics.add_metric("orders", MetricUnit::Count, 1.0);
+        metrics.flush_metrics();
+
+        let payloads = sink.payloads();
+        let task = payloads
+            .iter()
+            .find(|payload| payload.get(TASK_DIMENSION).is_some())
+            .unwrap();
+        assert_eq!(task["task"], "handler");
+        assert_eq!(task["tokio_task_instrumented_count"], 1.0);
+        assert_eq!(task["tokio_task_poll_count"], 2.0);
+        assert_eq!(task["tokio_task_scheduled_count"], 1.0);
+        let recorded = |payload: &serde_json::Value| payload.get("tokio_workers_count").is_some();
+        assert_eq!(payloads.iter().filter(|p| recorded(p)).count(), 2);
+        assert!(payloads
+            .iter()
+            .any(|payload| payload["tokio_workers_count"] == 1.0));
+    }
+}
@@ -0,0 +1,293 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// `MetricUnit` is used to serialize and publish metrics to `CloudWatch`.
+/// List of units in the [AWS Documentation](https://docs.aws.amazon.com/AmazonCloudWatch/latest/APIReference/API_MetricDatum.html)
+///
+/// `Display` and `FromStr` use the exact strings expected by `CloudWatch` (e.g. `Bytes/Second`),
+/// so units can be read from configuration files or environment variables.
+/// Parsing is case-insensitive.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MetricUnit {
+    Seconds,
+    Microseconds,
+    Milliseconds,
+    Bytes,
+    Kilobytes,
+    Megabytes,
+    Gigabytes,
+    Terabytes,
+    Bits,
+    Kilobits,
+    Megabits,
+    Gigabits,
+    Terabits,
+    Percent,
+    Count,
+    #[serde(rename = "Bytes/Second")]
+    BytesPerSecond,
+    #[serde(rename = "Kilobytes/Second")]
+    KilobytesPerSecond,
+    #[serde(rename = "Megabytes/Second")]
+    MegabytesPerSecond,
+    #[serde(rename = "Gigabytes/Second")]
+    GigabytesPerSecond,
+    #[serde(rename = "Terabytes/Second")]
+    TerabytesPerSecond,
+    #[serde(rename = "Bits/Second")]
+    BitsPerSecond,
+    #[serde(rename = "Kilobits/Second")]
+    KilobitsPerSecond,
+    #[serde(rename = "Megabits/Second")]
+    MegabitsPerSecond,
+    #[serde(rename = "Gigabits/Second")]
+    GigabitsPerSecond,
+    #[serde(rename = "Terabits/Second")]
+    TerabitsPerSecond,
+    #[serde(rename = "Count/Second")]
+    CountPerSecond,
+    None,
+}
+
+impl MetricUnit {
+    /// All units supported by `CloudWatch`.
+    pub const ALL: [MetricUnit; 27] = [
+        MetricUnit::Seconds,
+        MetricUnit::Microseconds,
+        MetricUnit::Milliseconds,
+        MetricUnit::Bytes,
+        MetricUnit::Kilobytes,
+        MetricUnit::Megabytes,
+        MetricUnit::Gigabytes,
+        MetricUnit::Terabytes,
+        MetricUnit::Bits,
+        MetricUnit::Kilobits,
+        MetricUnit::Megabits,
+        MetricUnit::Gigabits,
+        MetricUnit::Terabits,
+        MetricUnit::Percent,
+        MetricUnit::Count,
+        MetricUnit::BytesPerSecond,
+        MetricUnit::KilobytesPerSecond,
+        MetricUnit::MegabytesPerSecond,
+        MetricUnit::GigabytesPerSecond,
+        MetricUnit::TerabytesPerSecond,
+        MetricUnit::BitsPerSecond,
+        MetricUnit::KilobitsPerSecond,
+        MetricUnit::MegabitsPerSecond,
+        MetricUnit::GigabitsPerSecond,
+        MetricUnit::TerabitsPerSecond,
+        MetricUnit::CountPerSecond,
+        MetricUnit::None,
+    ];
+
+    /// Returns the unit name exactly as `CloudWatch` expects it.
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MetricUnit::Seconds => "Seconds",
+            MetricUnit::Microseconds => "Microseconds",
+            MetricUnit::Milliseconds => "Milliseconds",
+            MetricUnit::Bytes => "Bytes",
+            MetricUnit::Kilobytes => "Kilobytes",
+            MetricUnit::Megabytes => "Megabytes",
+            MetricUnit::Gigabytes => "Gigabytes",
+            MetricUnit::Terabytes => "Terabytes",
+            MetricUnit::Bits => "Bits",
+            MetricUnit::Kilobits => "Kilobits",
+            MetricUnit::Megabits => "Megabits",
+            MetricUnit::Gigabits => "Gigabits",
+            MetricUnit::Terabits => "Terabits",
+            MetricUnit::Percent => "Percent",
+            MetricUnit::Count => "Count",
+            MetricUnit::BytesPerSecond => "Bytes/Second",
+            MetricUnit::KilobytesPerSecond => "Kilobytes/Second",
+            MetricUnit::MegabytesPerSecond => "Megabytes/Second",
+            MetricUnit::GigabytesPerSecond => "Gigabytes/Second",
+            MetricUnit::TerabytesPerSecond => "Terabytes/Second",
+            MetricUnit::BitsPerSecond => "Bits/Second",
+            MetricUnit::KilobitsPerSecond => "Kilobits/Second",
+            MetricUnit::MegabitsPerSecond => "Megabits/Second",
+            MetricUnit::GigabitsPerSecond => "Gigabits/Second",
+            MetricUnit::TerabitsPerSecond => "Terabits/Second",
+            MetricUnit::CountPerSecond => "Count/Second",
+            MetricUnit::None => "None",
+        }
+    }
+}
+
+/// What a unit measures, units of the same quantity can be converted into each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Quantity {
+    Time,
+    Data,
+    DataRate,
+    Other(MetricUnit),
+}
+
+impl MetricUnit {
+    /// The quantity and the size of the unit in the smallest unit of the quantity: microseconds,
+    /// bits or bits per second, so conversions multiply or divide by an exact integer factor.
+    fn scale(self) -> (Quantity, u64) {
+        const KIB: u64 = 1024;
+        match self {
+            MetricUnit::Seconds => (Quantity::Time, 1_000_000),
+            MetricUnit::Milliseconds => (Quantity::Time, 1_000),
+            MetricUnit::Microseconds => (Quantity::Time, 1),
+            MetricUnit::Bits => (Quantity::Data, 1),
+            MetricUnit::Kilobits => (Quantity::Data, 1_000),
+            MetricUnit::Megabits => (Quantity::Data, 1_000_000),
+            MetricUnit::Gigabits => (Quantity::Data, 1_000_000_000),
+            MetricUnit::Terabits => (Quantity::Data, 1_000_000_000_000),
+            MetricUnit::Bytes => (Quantity::Data, 8),
+            MetricUnit::Kilobytes => (Quantity::Data, 8 * KIB),
+            MetricUnit::Megabytes => (Quantity::Data, 8 * KIB.pow(2)),
+            MetricUnit::Gigabytes => (Quantity::Data, 8 * KIB.pow(3)),
+            MetricUnit::Terabytes => (Quantity::Data, 8 * KIB.pow(4)),
+            MetricUnit::BitsPerSecond => (Quantity::DataRate, 1),
+            MetricUnit::KilobitsPerSecond => (Quantity::DataRate, 1_000),
+            MetricUnit::MegabitsPerSecond => (Quantity::DataRate, 1_000_000),
+            MetricUnit::GigabitsPerSecond => (Quantity::DataRate, 1_000_000_000),
+            MetricUnit::TerabitsPerSecond => (Quantity::DataRate, 1_000_000_000_000),
+            MetricUnit::BytesPerSecond => (Quantity::DataRate, 8),
+            MetricUnit::KilobytesPerSecond => (Quantity::DataRate, 8 * KIB),
+            MetricUnit::MegabytesPerSecond => (Quantity::DataRate, 8 * KIB.pow(2)),
+            MetricUnit::GigabytesPerSecond => (Quantity::DataRate, 8 * KIB.pow(3)),
+            MetricUnit::TerabytesPerSecond => (Quantity::DataRate, 8 * KIB.pow(4)),
+            unit => (Quantity::Other(unit), 1),
+        }
+    }
+
+    /// Converts a value between units of the same quantity, e.g. microseconds to milliseconds.
+    /// Byte units are binary (a kilobyte is 1024 bytes), bit units are decimal (a kilobit is
+    /// 1000 bits), like network throughput. Returns `None` if the units measure different
+    /// things, e.g. seconds and bytes.
+    ///
+    /// ```
+    /// # use lambda_helpers_metrics::MetricUnit;
+    /// let ms = MetricUnit::convert(1500.0, MetricUnit::Microseconds, MetricUnit::Milliseconds);
+    /// assert_eq!(ms, Some(1.5));
+    /// assert_eq!(MetricUnit::convert(1.0, MetricUnit::Seconds, MetricUnit::Bytes), None);
+    /// ```
+    #[must_use]
+    pub fn convert(value: f64, from: MetricUnit, to: MetricUnit) -> Option<f64> {
+        let (from_quantity, from_scale) = from.scale();
+        let (to_quantity, to_scale) = to.scale();
+        if from_quantity != to_quantity {
+            return None;
+        }
+        // the factors are below 2^53, so they are exact as f64
+        #[allow(clippy::cast_precision_loss)]
+        let converted = if from_scale % to_scale == 0 {
+            value * (from_scale / to_scale) as f64
+        } else if to_scale % from_scale == 0 {
+            value / (to_scale / from_scale) as f64
+        } else {
+            value * from_scale as f64 / to_scale as f64
+        };
+        Some(converted)
+    }
+}
+
+impl fmt::Display for MetricUnit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Error returned when a string is not a valid `CloudWatch` unit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseMetricUnitError(String);
+
+impl fmt::Display for ParseMetricUnitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown metric unit: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseMetricUnitError {}
+
+impl FromStr for MetricUnit {
+    type Err = ParseMetricUnitError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let trimmed = s.trim();
+        MetricUnit::ALL
+            .iter()
+            .find(|unit| unit.as_str().eq_ignore_ascii_case(trimmed))
+            .copied()
+            .ok_or_else(|| ParseMetricUnitError(s.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_round_trip_all_units() {
+        for unit in MetricUnit::ALL {
+            assert_eq!(unit.to_string().parse::<MetricUnit>(), Ok(unit));
+        }
+    }
+
+    #[test]
+    fn should_use_cloudwatch_strings() {
+        assert_eq!(MetricUnit::BytesPerSecond.to_string(), "Bytes/Second");
+        assert_eq!(
+            serde_json::to_string(&MetricUnit::CountPerSecond).unwrap(),
+            "\"Count/Second\""
+        );
+        assert_eq!("milliseconds".parse(), Ok(MetricUnit::Milliseconds));
+        assert!("Fortnights".parse::<MetricUnit>().is_err());
+    }
+
+    #[test]
+    fn should_deserialize_cloudwatch_strings() {
+        for unit in MetricUnit::ALL {
+            let json = serde_json::to_string(&unit).unwrap();
+            assert_eq!(serde_json::from_str::<MetricUnit>(&json).unwrap(), unit);
+        }
+    }
+
+    #[test]
+    fn should_convert_between_units_of_same_quantity() {
+        let convert = MetricUnit::convert;
+        assert_eq!(
+            convert(2.0, MetricUnit::Seconds, MetricUnit::Milliseconds),
+            Some(2000.0)
+        );
+        assert_eq!(
+            convert(2048.0, MetricUnit::Bytes, MetricUnit::Kilobytes),
+            Some(2.0)
+        );
+        assert_eq!(convert(1.0, MetricUnit::Bytes, MetricUnit::Bits), Some(8.0));
+        assert_eq!(
+            convert(1500.0, MetricUnit::Microseconds, MetricUnit::Milliseconds),
+            Some(1.5)
+        );
+        assert_eq!(
+            convert(300.0, MetricUnit::Milliseconds, MetricUnit::Seconds),
+            Some(0.3)
+        );
+        assert_eq!(
+            convert(
+                1.0,
+                MetricUnit::MegabitsPerSecond,
+                MetricUnit::KilobitsPerSecond
+            ),
+            Some(1000.0)
+        );
+        assert_eq!(
+            convert(3.0, MetricUnit::Count, MetricUnit::Count),
+            Some(3.0)
+        );
+        assert_eq!(convert(3.0, MetricUnit::Count, MetricUnit::Percent), None);
+        assert_eq!(
+            convert(1.0, MetricUnit::Bytes, MetricUnit::BytesPerSecond),
+            None
+        );
+    }
+}
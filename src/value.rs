@@ -0,0 +1,176 @@
+//! Unit-typed metric values.
+//!
+//! Each wrapper encodes the `CloudWatch` unit in its type, so passing a value in seconds
+//! where milliseconds are expected is a compile error rather than a wrong dashboard.
+//!
+//! ```
+//! use lambda_helpers_metrics::value::{Bytes, Milliseconds};
+//! use lambda_helpers_metrics::Metrics;
+//!
+//! let mut metrics = Metrics::new("custom_lambdas", "service", "dummy_service");
+//! metrics.add("latency", Milliseconds(120));
+//! metrics.add("payload_size", Bytes(2048));
+//! metrics.add("elapsed", std::time::Duration::from_millis(15));
+//! ```
+//!
+//! Alternatively, with [`MetricsBuilder::infer_units`](crate::MetricsBuilder::infer_units),
+//! the unit of [`Metrics::add_metric_value`](crate::Metrics::add_metric_value) is inferred from
+//! the suffix of the metric name, see [`unit_from_suffix`].
+use std::time::Duration;
+
+use crate::{MetricUnit, Metrics};
+
+/// Name suffixes and the units they imply.
+const UNIT_SUFFIXES: [(&str, MetricUnit); 5] = [
+    ("_ms", MetricUnit::Milliseconds),
+    ("_seconds", MetricUnit::Seconds),
+    ("_bytes", MetricUnit::Bytes),
+    ("_count", MetricUnit::Count),
+    ("_percent", MetricUnit::Percent),
+];
+
+/// Returns the unit implied by the suffix of a metric name: `_ms`, `_seconds`, `_bytes`,
+/// `_count` or `_percent`.
+#[must_use]
+pub fn unit_from_suffix(name: &str) -> Option<MetricUnit> {
+    UNIT_SUFFIXES
+        .iter()
+        .find(|(suffix, _)| name.ends_with(suffix))
+        .map(|(_, unit)| *unit)
+}
+
+/// A value that knows its own `CloudWatch` unit.
+pub trait IntoMetric {
+    /// Converts the value into the unit and raw value that is recorded.
+    fn into_metric(self) -> (MetricUnit, f64);
+}
+
+macro_rules! metric_value {
+    ($(#[$doc:meta])* $name:ident($inner:ty) => $unit:ident) => {
+        $(#[$doc])*
+        #[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+        pub struct $name(pub $inner);
+
+        impl IntoMetric for $name {
+            #[allow(clippy::cast_precision_loss)]
+            fn into_metric(self) -> (MetricUnit, f64) {
+                (MetricUnit::$unit, self.0 as f64)
+            }
+        }
+    };
+}
+
+metric_value!(
+    /// Duration in seconds.
+    Seconds(f64) => Seconds
+);
+metric_value!(
+    /// Duration in milliseconds.
+    Milliseconds(u64) => Milliseconds
+);
+metric_value!(
+    /// Duration in microseconds.
+    Microseconds(u64) => Microseconds
+);
+metric_value!(
+    /// Size in bytes.
+    Bytes(u64) => Bytes
+);
+metric_value!(
+    /// Size in kilobytes.
+    Kilobytes(f64) => Kilobytes
+);
+metric_value!(
+    /// Size in megabytes.
+    Megabytes(f64) => Megabytes
+);
+metric_value!(
+    /// Number of occurrences.
+    Count(u64) => Count
+);
+metric_value!(
+    /// Percentage, usually in the `0.0..=100.0` range.
+    Percent(f64) => Percent
+);
+metric_value!(
+    /// Throughput in bytes per second.
+    BytesPerSecond(f64) => BytesPerSecond
+);
+metric_value!(
+    /// Rate of occurrences per second.
+    CountPerSecond(f64) => CountPerSecond
+);
+
+/// Durations are recorded in milliseconds, keeping sub-millisecond precision.
+impl IntoMetric for Duration {
+    fn into_metric(self) -> (MetricUnit, f64) {
+        (MetricUnit::Milliseconds, self.as_secs_f64() * 1000.0)
+    }
+}
+
+/// An explicit unit and value pair, for cases where the unit is only known at runtime.
+impl IntoMetric for (MetricUnit, f64) {
+    fn into_metric(self) -> (MetricUnit, f64) {
+        self
+    }
+}
+
+impl Metrics {
+    /// Adds a metric whose unit is inferred from the suffix of its name if enabled with
+    /// [`MetricsBuilder::infer_units`](crate::MetricsBuilder::infer_units), e.g. `latency_ms`
+    /// is recorded in milliseconds. Otherwise, or for other names, the unit is `None`.
+    /// Follows the same flushing rules as `add_metric`.
+    pub fn add_metric_value(&mut self, name: &str, value: f64) {
+        let unit = self
+            .infer_units
+            .then(|| unit_from_suffix(name))
+            .flatten()
+            .unwrap_or(MetricUnit::None);
+        self.add_metric(name, unit, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_carry_unit_in_type() {
+        assert_eq!(
+            Milliseconds(12).into_metric(),
+            (MetricUnit::Milliseconds, 12.0)
+        );
+        assert_eq!(Bytes(3).into_metric(), (MetricUnit::Bytes, 3.0));
+        assert_eq!(Percent(99.5).into_metric(), (MetricUnit::Percent, 99.5));
+        assert_eq!(
+            Duration::from_micros(1500).into_metric(),
+            (MetricUnit::Milliseconds, 1.5)
+        );
+    }
+
+    #[test]
+    fn should_infer_unit_from_name_suffix() {
+        assert_eq!(
+            unit_from_suffix("latency_ms"),
+            Some(MetricUnit::Milliseconds)
+        );
+        assert_eq!(unit_from_suffix("payload_bytes"), Some(MetricUnit::Bytes));
+        assert_eq!(unit_from_suffix("orders"), None);
+
+        let mut metrics = Metrics::builder("test").infer_units(true).build().unwrap();
+        metrics.add_metric_value("latency_ms", 12.0);
+        metrics.add_metric_value("retries", 2.0);
+        let mut plain = Metrics::new("test", "service", "dummy_service");
+        plain.add_metric_value("latency_ms", 12.0);
+
+        let log = serde_json::to_value(metrics.format_metrics()).unwrap();
+        let definitions = &log["_aws"]["CloudWatchMetrics"][0]["Metrics"];
+        assert_eq!(definitions[0]["Unit"], "Milliseconds");
+        assert_eq!(definitions[1]["Unit"], "None");
+        let log = serde_json::to_value(plain.format_metrics()).unwrap();
+        assert_eq!(
+            log["_aws"]["CloudWatchMetrics"][0]["Metrics"][0]["Unit"],
+            "None"
+        );
+    }
+}
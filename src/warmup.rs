@@ -0,0 +1,211 @@
+//! Detection of keep-warm pings.
+//!
+//! Scheduled pings keeping execution environments warm skew latency and error statistics.
+//! A [`WarmupDetector`] recognizes them from the raw event, and the invocation is either not
+//! emitted at all or tagged with the `warmup` dimension, depending on the [`WarmupMode`]:
+//!
+//! ```
+//! use lambda_helpers_metrics::warmup::{WarmupDetector, WarmupMode};
+//! use lambda_helpers_metrics::{MetricUnit, Metrics};
+//! use serde_json::json;
+//!
+//! let mut metrics = Metrics::builder("custom_lambdas")
+//!     .warmup_detector(WarmupDetector::field("source", "serverless-plugin-warmup"))
+//!     .build()
+//!     .unwrap();
+//!
+//! let event = json!({ "source": "serverless-plugin-warmup" });
+//! if metrics.detect_warmup(&event) {
+//!     // nothing recorded for this invocation is emitted
+//! }
+//! metrics.add_metric("latency", MetricUnit::Milliseconds, 3.0);
+//! metrics.flush_metrics();
+//! ```
+//!
+//! The detection applies until the next flush.
+use std::fmt;
+use std::sync::Arc;
+
+use serde_json::Value;
+
+use crate::Metrics;
+
+/// The dimension of the metrics of a keep-warm ping, with [`WarmupMode::Dimension`].
+pub const WARMUP_DIMENSION: &str = "warmup";
+
+type Predicate = dyn Fn(&Value) -> bool + Send + Sync;
+
+/// What happens to the metrics of a keep-warm ping.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WarmupMode {
+    /// The metrics recorded until the next flush are discarded, except the
+    /// [metrics of the library](crate::self_metrics).
+    #[default]
+    Skip,
+    /// The metrics recorded until the next flush carry the `warmup` dimension set to `true`.
+    Dimension,
+}
+
+/// Recognizes keep-warm pings from the raw event, see [`crate::warmup`].
+#[derive(Clone)]
+pub struct WarmupDetector {
+    predicate: Arc<Predicate>,
+    mode: WarmupMode,
+}
+
+impl fmt::Debug for WarmupDetector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WarmupDetector")
+            .field("mode", &self.mode)
+            .finish_non_exhaustive()
+    }
+}
+
+impl WarmupDetector {
+    /// Detects pings with a predicate over the raw event.
+    #[must_use]
+    pub fn new(predicate: impl Fn(&Value) -> bool + Send + Sync + 'static) -> Self {
+        Self {
+            predicate: Arc::new(predicate),
+            mode: WarmupMode::default(),
+        }
+    }
+
+    /// Detects pings whose top-level `key` holds the string `value`,
+    /// e.g. `source` set to `serverless-plugin-warmup`.
+    #[must_use]
+    pub fn field(key: &str, value: &str) -> Self {
+        let (key, value) = (key.to_string(), value.to_string());
+        Self::new(move |event| event.get(&key).and_then(Value::as_str) == Some(value.as_str()))
+    }
+
+    /// Sets what happens to the metrics of a ping, [`WarmupMode::Skip`] by default.
+    #[must_use]
+    pub fn mode(mut self, mode: WarmupMode) -> Self {
+        self.mode = mode;
+        self
+    }
+}
+
+impl Metrics {
+    /// Checks whether the event is a keep-warm ping with the detector set by
+    /// [`MetricsBuilder::warmup_detector`](crate::MetricsBuilder::warmup_detector), and marks
+    /// the invocation if it is. Returns `false` without a detector.
+    pub fn detect_warmup(&mut self, event: &Value) -> bool {
+        let Some(detector) = &self.warmup_detector else {
+            return false;
+        };
+        if !(detector.predicate)(event) {
+            return false;
+        }
+        if detector.mode == WarmupMode::Dimension {
+            self.scoped_dimensions.insert(WARMUP_DIMENSION, "true");
+        }
+        self.warmup = true;
+        true
+    }
+
+    /// Returns `true` if the current invocation was detected as a keep-warm ping.
+    #[must_use]
+    pub fn is_warmup(&self) -> bool {
+        self.warmup
+    }
+
+    /// Returns `true` if the buffer is discarded at flush because of a ping.
+    pub(crate) fn skips_warmup(&self) -> bool {
+        self.warmup
+            && self
+                .warmup_detector
+                .as_ref()
+                .is_some_and(|detector| detector.mode == WarmupMode::Skip)
+    }
+
+    /// Discards the metrics of a ping at flush. The counts of the library, e.g. dropped metrics
+    /// or failed emissions, are moved into the emptied buffer, so they are still emitted.
+    pub(crate) fn discard_warmup(&mut self) {
+        self.clear_buffer();
+        self.buffer_library_metrics();
+    }
+
+    /// Clears the mark of a ping after the flush.
+    pub(crate) fn end_warmup(&mut self) {
+        if std::mem::take(&mut self.warmup) {
+            self.scoped_dimensions.remove(WARMUP_DIMENSION);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::self_metrics::DROPPED_METRIC;
+    use crate::sink::RecordingSink;
+    use crate::{MetricOverflowPolicy, MetricUnit};
+
+    fn metrics(mode: WarmupMode, sink: &RecordingSink) -> Metrics {
+        Metrics::builder("test")
+            .warmup_detector(WarmupDetector::field("source", "warmer").mode(mode))
+            .sink(sink.clone())
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn should_skip_metrics_of_pings() {
+        let sink = RecordingSink::default();
+        let mut metrics = metrics(WarmupMode::Skip, &sink);
+
+        assert!(metrics.detect_warmup(&json!({ "source": "warmer" })));
+        metrics.add_metric("latency", MetricUnit::Milliseconds, 3.0);
+        metrics.flush_metrics();
+        assert!(!metrics.detect_warmup(&json!({ "source": "orders" })));
+        metrics.add_metric("latency", MetricUnit::Milliseconds, 120.0);
+        metrics.flush_metrics();
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads.len(), 1);
+        assert_eq!(payloads[0]["latency"], 120.0);
+    }
+
+    #[test]
+    fn should_emit_library_metrics_of_pings() {
+        let sink = RecordingSink::default();
+        let mut metrics = Metrics::builder("test")
+            .warmup_detector(WarmupDetector::field("source", "warmer"))
+            .metric_overflow(MetricOverflowPolicy::Error)
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+
+        metrics.detect_warmup(&json!({ "source": "warmer" }));
+        for i in 0..102 {
+            metrics.add_metric(&format!("metric_{i}"), MetricUnit::Count, 1.0);
+        }
+        metrics.flush_metrics();
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads.len(), 1);
+        assert_eq!(payloads[0][DROPPED_METRIC], 2.0);
+        assert!(payloads[0].get("metric_0").is_none());
+        assert!(metrics.is_empty());
+    }
+
+    #[test]
+    fn should_tag_metrics_of_pings() {
+        let sink = RecordingSink::default();
+        let mut metrics = metrics(WarmupMode::Dimension, &sink);
+
+        metrics.detect_warmup(&json!({ "source": "warmer" }));
+        metrics.add_metric("latency", MetricUnit::Milliseconds, 3.0);
+        metrics.flush_metrics();
+        metrics.add_metric("latency", MetricUnit::Milliseconds, 120.0);
+        metrics.flush_metrics();
+
+        let payloads = sink.payloads();
+        assert_eq!(payloads[0][WARMUP_DIMENSION], "true");
+        assert!(payloads[1].get(WARMUP_DIMENSION).is_none());
+        assert!(!metrics.is_warmup());
+    }
+}
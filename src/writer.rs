@@ -0,0 +1,222 @@
+//! Hand-rolled JSON writer for the flush path, enabled with the `fast-serialize` feature.
+//!
+//! The payload structure is fixed, so it is written directly from the buffered entries,
+//! without building the intermediate payload structs and going through `serde`.
+//! Integers are formatted with `itoa` and floats with `ryu` directly into the output buffer,
+//! which is sized up front. This is the only fast serialization path of the flush, selected in
+//! `Metrics::write_payload`. The output is equivalent to the one of `serde_json`: floats are
+//! written in their shortest form which parses back to the same value, possibly in another
+//! exponent notation, and non-finite values as `null`.
+use crate::{
+    definition_resolution, writes_property, Metric, Metrics, PayloadFields,
+    TIMESTAMP_WARNING_PROPERTY,
+};
+
+impl Metrics {
+    pub(crate) fn write_json(
+        &self,
+        fields: PayloadFields<'_>,
+        entries: &[&Metric],
+        out: &mut Vec<u8>,
+    ) {
+        let dimensions = fields.dimensions_of(entries);
+        let namespace = self.payload_namespace(entries, &dimensions);
+        out.reserve(estimated_len(entries));
+
+        out.extend_from_slice(b"{\"_aws\":{\"Timestamp\":");
+        write_i64(out, self.payload_timestamp(entries));
+        out.extend_from_slice(b",\"CloudWatchMetrics\":[{\"Namespace\":");
+        write_str(out, &namespace);
+        out.extend_from_slice(b",\"Dimensions\":[");
+        for (index, set) in self.dimension_sets(&dimensions).iter().enumerate() {
+            if index > 0 {
+                out.push(b',');
+            }
+            out.push(b'[');
+            for (index, key) in set.iter().enumerate() {
+                if index > 0 {
+                    out.push(b',');
+                }
+                write_str(out, key);
+            }
+            out.push(b']');
+        }
+        out.extend_from_slice(b"],\"Metrics\":[");
+        for (index, metric) in entries.iter().enumerate() {
+            if index > 0 {
+                out.push(b',');
+            }
+            out.extend_from_slice(b"{\"Name\":");
+            write_str(out, &metric.name);
+            if let Some(unit) = metric.definition_unit(self.output_format) {
+                out.extend_from_slice(b",\"Unit\":");
+                write_str(out, unit.as_str());
+            }
+            let resolution = metric.storage_resolution.unwrap_or(self.storage_resolution);
+            if let Some(resolution) = definition_resolution(self.output_format, resolution) {
+                out.extend_from_slice(b",\"StorageResolution\":");
+                out.extend_from_slice(itoa::Buffer::new().format(resolution).as_bytes());
+            }
+            out.push(b'}');
+        }
+        out.extend_from_slice(b"]}]");
+        if let Some(name) = &self.log_group_name {
+            out.extend_from_slice(b",\"LogGroupName\":");
+            write_str(out, name);
+        }
+        if let Some(name) = &self.log_stream_name {
+            out.extend_from_slice(b",\"LogStreamName\":");
+            write_str(out, name);
+        }
+        out.push(b'}');
+
+        for (key, value) in dimensions.iter() {
+            write_key(out, key);
+            write_str(out, value);
+        }
+        let metric_properties = entries.first().map_or(&[][..], |metric| &metric.properties);
+        for (key, value) in &fields.properties.0 {
+            if !writes_property(self.output_format, value)
+                || metric_properties
+                    .iter()
+                    .any(|(metric_key, _)| metric_key == key)
+            {
+                continue;
+            }
+            write_key(out, key);
+            match value {
+                serde_json::Value::String(value) => write_str(out, value),
+                value => out.extend_from_slice(value.to_string().as_bytes()),
+            }
+        }
+        for (key, value) in metric_properties {
+            write_key(out, key);
+            write_str(out, value);
+        }
+        if let Some(warning) = self.timestamp_warning(entries) {
+            write_key(out, TIMESTAMP_WARNING_PROPERTY);
+            write_str(out, warning);
+        }
+        if let Some(timestamp) = self.log_timestamp(entries) {
+            write_key(out, crate::format::TIMESTAMP_FIELD);
+            write_str(out, &timestamp);
+        }
+        for metric in entries {
+            write_key(out, &metric.name);
+            match metric.values.as_slice() {
+                [value] => write_f64(out, *value),
+                values => {
+                    out.push(b'[');
+                    for (index, value) in values.iter().enumerate() {
+                        if index > 0 {
+                            out.push(b',');
+                        }
+                        write_f64(out, *value);
+                    }
+                    out.push(b']');
+                }
+            }
+        }
+        out.push(b'}');
+    }
+}
+
+/// A rough upper bound of the payload size for typical entries, to avoid reallocations.
+fn estimated_len(entries: &[&Metric]) -> usize {
+    let metrics: usize = entries
+        .iter()
+        .map(|metric| 2 * metric.name.len() + 64 + 24 * metric.values.len())
+        .sum();
+    256 + metrics
+}
+
+fn write_i64(out: &mut Vec<u8>, value: i64) {
+    out.extend_from_slice(itoa::Buffer::new().format(value).as_bytes());
+}
+
+fn write_key(out: &mut Vec<u8>, key: &str) {
+    out.push(b',');
+    write_str(out, key);
+    out.push(b':');
+}
+
+fn write_f64(out: &mut Vec<u8>, value: f64) {
+    if value.is_finite() {
+        out.extend_from_slice(ryu::Buffer::new().format_finite(value).as_bytes());
+    } else {
+        out.extend_from_slice(b"null");
+    }
+}
+
+fn write_str(out: &mut Vec<u8>, value: &str) {
+    out.push(b'"');
+    let mut start = 0;
+    for (index, byte) in value.bytes().enumerate() {
+        let escaped: &[u8] = match byte {
+            b'"' => b"\\\"",
+            b'\\' => b"\\\\",
+            b'\n' => b"\\n",
+            b'\r' => b"\\r",
+            b'\t' => b"\\t",
+            0x08 => b"\\b",
+            0x0c => b"\\f",
+            0x00..=0x1f => {
+                const HEX: &[u8; 16] = b"0123456789abcdef";
+                out.extend_from_slice(&value.as_bytes()[start..index]);
+                out.extend_from_slice(b"\\u00");
+                out.push(HEX[usize::from(byte >> 4)]);
+                out.push(HEX[usize::from(byte & 0xf)]);
+                start = index + 1;
+                continue;
+            }
+            _ => continue,
+        };
+        out.extend_from_slice(&value.as_bytes()[start..index]);
+        out.extend_from_slice(escaped);
+        start = index + 1;
+    }
+    out.extend_from_slice(&value.as_bytes()[start..]);
+    out.push(b'"');
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{MetricUnit, Metrics, OutputFormat};
+
+    #[test]
+    fn should_write_same_json_as_serde() {
+        let mut metrics = Metrics::builder("name\"space")
+            .dimension("service", "line\nbreak \u{1} \\ ünïcode")
+            .output_format(OutputFormat::Compact)
+            .log_group_name("log\tgroup")
+            .dry_run(true)
+            .build()
+            .unwrap();
+        metrics.add_property("request_id", "abc");
+        metrics.add_json_property("tags", serde_json::json!([]));
+        metrics.add_json_property(
+            "order",
+            serde_json::json!({ "id": "a\"b", "items": [1, 2.5] }),
+        );
+        metrics.add_metric("count", MetricUnit::Count, 1.0);
+        metrics.add_metric("ratio", MetricUnit::None, 0.1);
+        metrics.add_metric("nan", MetricUnit::Count, f64::NAN);
+        metrics.add_metric("tiny", MetricUnit::Seconds, 1e-12);
+        metrics.add_sample("latency", MetricUnit::Milliseconds, 1.5);
+        metrics.add_sample("latency", MetricUnit::Milliseconds, 2.0);
+        metrics.add_metric_with_dimensions("hits", MetricUnit::Count, 3.0, &[("cache", "redis")]);
+
+        for chunk in metrics.payload_chunks() {
+            let mut fast = Vec::new();
+            metrics.write_json(metrics.payload_fields(), &chunk, &mut fast);
+
+            let mut fast: serde_json::Value = serde_json::from_slice(&fast).unwrap();
+            let mut expected =
+                serde_json::to_value(metrics.format_entries(metrics.payload_fields(), &chunk))
+                    .unwrap();
+            fast["_aws"]["Timestamp"] = 0.into();
+            expected["_aws"]["Timestamp"] = 0.into();
+            assert_eq!(fast, expected);
+        }
+    }
+}
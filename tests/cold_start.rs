@@ -0,0 +1,17 @@
+//! Tests of the init duration. The process start is global to the process, so they run in their
+//! own test binary.
+use lambda_helpers_metrics::cold_start::{self, INIT_DURATION_METRIC};
+use lambda_helpers_metrics::sink::NullSink;
+use lambda_helpers_metrics::Metrics;
+
+#[test]
+fn should_record_init_duration_once_marked() {
+    let mut metrics = Metrics::builder("test").sink(NullSink).build().unwrap();
+
+    assert!(!metrics.record_init_duration());
+    cold_start::mark_process_start();
+    assert!(metrics.record_init_duration());
+    assert!(!metrics.record_init_duration());
+
+    assert!(metrics.value_of(INIT_DURATION_METRIC).is_some());
+}
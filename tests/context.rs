@@ -0,0 +1,21 @@
+//! Tests of the current context. It is global to the process, so they run in their own test
+//! binary.
+use lambda_helpers_metrics::context::{self, MetricsHandle};
+use lambda_helpers_metrics::sink::NullSink;
+use lambda_helpers_metrics::{MetricUnit, Metrics};
+
+#[test]
+fn should_record_into_current_context() {
+    let handle = MetricsHandle::new(Metrics::builder("test").sink(NullSink).build().unwrap());
+    context::set_current(handle.clone());
+
+    context::with_current(|metrics| metrics.add_metric("ambient", MetricUnit::Count, 1.0));
+    let previous = context::take_current();
+
+    assert!(previous.is_some());
+    assert_eq!(
+        handle.with(|metrics| metrics.value_of("ambient")),
+        Some(1.0)
+    );
+    assert_eq!(context::with_current(|metrics| metrics.len()), None);
+}
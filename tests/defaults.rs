@@ -0,0 +1,90 @@
+//! Tests of the process-wide defaults. They are global to the process, so the tests run in
+//! their own test binary, one at a time.
+use std::sync::{Arc, Mutex, PoisonError};
+
+use lambda_helpers_metrics::defaults::Defaults;
+use lambda_helpers_metrics::sink::NullSink;
+use lambda_helpers_metrics::{DimensionLengthPolicy, DimensionOverflowPolicy, Metrics};
+
+static DEFAULTS: Mutex<()> = Mutex::new(());
+
+/// Runs `test` with the given defaults, clearing them afterwards.
+fn with_defaults<T>(defaults: Defaults, test: impl FnOnce() -> T) -> T {
+    let _guard = DEFAULTS.lock().unwrap_or_else(PoisonError::into_inner);
+    lambda_helpers_metrics::init(defaults);
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(test));
+    lambda_helpers_metrics::init(Defaults::new());
+    result.unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+}
+
+#[test]
+fn should_apply_defaults_to_metrics_built_afterwards() {
+    let before = Metrics::builder("test").sink(NullSink).build().unwrap();
+    let defaults = Defaults::new()
+        .dimension("service", "checkout")
+        .dimension("stage", "prod")
+        .property("team", "payments");
+
+    let metrics = with_defaults(defaults, || {
+        Metrics::builder("test")
+            .sink(NullSink)
+            .dimension("stage", "dev")
+            .build()
+            .unwrap()
+    });
+
+    assert_eq!(metrics.dimension("service"), Some("checkout"));
+    assert_eq!(metrics.dimension("stage"), Some("dev"));
+    assert_eq!(metrics.property("team"), Some("payments"));
+    assert_eq!(before.dimension("service"), None);
+}
+
+#[test]
+fn should_report_and_skip_invalid_default_dimensions() {
+    let errors = Arc::new(Mutex::new(Vec::new()));
+    let reported = Arc::clone(&errors);
+    let defaults = Defaults::new()
+        .dimension("request", &"x".repeat(2000))
+        .dimension("service", "checkout");
+
+    let metrics = with_defaults(defaults, || {
+        Metrics::builder("test")
+            .sink(NullSink)
+            .dimension_length(DimensionLengthPolicy::Error)
+            .on_error(move |err| reported.lock().unwrap().push(err.to_string()))
+            .build()
+    });
+
+    let metrics = metrics.unwrap();
+    assert_eq!(metrics.dimension("request"), None);
+    assert_eq!(metrics.dimension("service"), Some("checkout"));
+    assert_eq!(
+        *errors.lock().unwrap(),
+        vec!["Invalid dimension: dimension value is 2000 characters long, the limit is 1024"]
+    );
+}
+
+#[test]
+fn should_keep_dimensions_of_builder_when_defaults_fill_the_limit() {
+    let errors = Arc::new(Mutex::new(Vec::new()));
+    let reported = Arc::clone(&errors);
+    let defaults = (0..30).fold(Defaults::new(), |defaults, i| {
+        defaults.dimension(&format!("default_{i}"), "x")
+    });
+
+    let metrics = with_defaults(defaults, || {
+        Metrics::builder("test")
+            .sink(NullSink)
+            .dimension("service", "checkout")
+            .dimension_overflow(DimensionOverflowPolicy::DropOldest)
+            .on_error(move |err| reported.lock().unwrap().push(err.to_string()))
+            .build()
+    });
+
+    let metrics = metrics.unwrap();
+    assert_eq!(metrics.dimension("service"), Some("checkout"));
+    assert_eq!(metrics.dimension("default_28"), Some("x"));
+    assert_eq!(metrics.dimension("default_29"), None);
+    assert_eq!(metrics.dimensions_remaining(), 0);
+    assert_eq!(errors.lock().unwrap().len(), 1);
+}
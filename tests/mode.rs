@@ -0,0 +1,60 @@
+//! Tests of the strict mode. The mode is global to the process, so they run in their own test
+//! binary, one at a time.
+use std::sync::{Mutex, PoisonError};
+
+use lambda_helpers_metrics::mode::{self, Mode};
+use lambda_helpers_metrics::sink::NullSink;
+use lambda_helpers_metrics::{MetricUnit, Metrics, MetricsError};
+
+static MODE: Mutex<()> = Mutex::new(());
+
+/// Runs `test` in the given mode, restoring the lenient mode afterwards.
+fn with_mode<T>(mode: Mode, test: impl FnOnce() -> T) -> T {
+    let _guard = MODE.lock().unwrap_or_else(PoisonError::into_inner);
+    mode::set_mode(mode);
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(test));
+    mode::set_mode(Mode::Lenient);
+    result.unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+}
+
+fn metrics() -> Metrics {
+    Metrics::builder("test").sink(NullSink).build().unwrap()
+}
+
+#[test]
+fn should_reject_invalid_metrics_in_strict_mode() {
+    let mut metrics = metrics();
+
+    with_mode(Mode::Strict, || {
+        assert_eq!(mode::mode(), Mode::Strict);
+        assert!(matches!(
+            metrics.try_add_metric("latency", MetricUnit::Milliseconds, f64::NAN),
+            Err(MetricsError::InvalidMetric(_))
+        ));
+        assert!(matches!(
+            metrics.try_add_metric("", MetricUnit::Count, 1.0),
+            Err(MetricsError::InvalidMetric(_))
+        ));
+    });
+    assert!(metrics.is_empty());
+
+    with_mode(Mode::Lenient, || {
+        metrics
+            .try_add_metric("latency", MetricUnit::Milliseconds, f64::NAN)
+            .unwrap();
+    });
+    assert!(metrics.contains("latency"));
+}
+
+#[test]
+#[cfg(debug_assertions)]
+fn should_panic_on_dropped_metric_in_strict_mode() {
+    let mut metrics = metrics();
+
+    let result = with_mode(Mode::Strict, || {
+        std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            metrics.add_metric("latency", MetricUnit::Milliseconds, f64::INFINITY);
+        }))
+    });
+    assert!(result.is_err());
+}